@@ -1,5 +1,6 @@
 use std::fs;
 use std::io::Error;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::str::FromStr;
 
@@ -10,7 +11,7 @@ use tokio::signal;
 
 use g1_cli::{param::ParametersConfig, tracing::TracingConfig};
 
-use bittorrent_actor::{Actors, Mode, StorageOpen};
+use bittorrent_actor::{Actors, Discovery, Mode, PeerSource, StorageOpen};
 use bittorrent_base::{InfoHash, MagnetUri};
 use bittorrent_metainfo::{InfoOwner, MetainfoOwner};
 
@@ -26,6 +27,17 @@ struct Program {
     torrent_source: TorrentSource,
     #[command(flatten)]
     output: Output,
+
+    /// Connects to these peers immediately (e.g., a seedbox).
+    #[arg(long)]
+    peer: Vec<SocketAddr>,
+
+    /// Overrides the process-wide DHT setting for this torrent.
+    #[arg(long)]
+    dht: Option<bool>,
+    /// Overrides the process-wide PEX setting for this torrent (disabling only).
+    #[arg(long)]
+    pex: Option<bool>,
 }
 
 #[derive(Args, Debug)]
@@ -52,36 +64,48 @@ struct Output {
 
 impl Program {
     async fn execute(self) -> Result<(), Error> {
-        let (mode, info_hash) = self.torrent_source.into_mode()?;
-        let mut actors = Actors::spawn(mode, info_hash, self.output.into_open()).await?;
+        let (mode, info_hash, peers) = self.torrent_source.into_mode()?;
+        let discovery = Discovery {
+            dht: self.dht,
+            peer_exchange: self.pex,
+        };
+        let mut actors =
+            Actors::spawn(mode, info_hash, self.output.into_open(), peers, discovery).await?;
+        for peer_endpoint in self.peer {
+            actors.add_peer(peer_endpoint, PeerSource::User);
+        }
         tokio::select! {
             () = signal::ctrl_c().map(Result::unwrap) => eprintln!("ctrl-c received!"),
             () = actors.join_any() => {}
         }
-        actors.shutdown_all().await
+        let result = actors.shutdown_all().await;
+        eprintln!("total: {:?}", actors.txrx.torrent.total());
+        eprintln!("overhead: {:?}", bittorrent_socket::overhead());
+        result
     }
 }
 
 impl TorrentSource {
-    fn into_mode(self) -> Result<(Mode, InfoHash), Error> {
+    fn into_mode(self) -> Result<(Mode, InfoHash, Vec<String>), Error> {
         if let Some(metainfo_path) = self.metainfo {
             let metainfo = MetainfoOwner::try_from(Bytes::from(fs::read(&metainfo_path)?))
                 .map_err(Error::other)?;
-            let info_hash = InfoHash::new(metainfo.deref().info.compute_info_hash());
-            Ok((Mode::Tracker(metainfo), info_hash))
+            let info_hash = metainfo.deref().info.compute_info_hash();
+            Ok((Mode::Tracker(metainfo), info_hash, Vec::new()))
         } else if let Some(mut magnet_uri) = self.magnet_uri {
             // TODO: Support multiple downloads.
             Ok((
                 Mode::Trackerless(None),
                 magnet_uri.info_hashes.pop().unwrap(),
+                magnet_uri.peers,
             ))
         } else if let Some(info_path) = self.info {
             let info =
                 InfoOwner::try_from(Bytes::from(fs::read(&info_path)?)).map_err(Error::other)?;
-            let info_hash = InfoHash::new(info.deref().compute_info_hash());
-            Ok((Mode::Trackerless(Some(info)), info_hash))
+            let info_hash = info.deref().compute_info_hash();
+            Ok((Mode::Trackerless(Some(info)), info_hash, Vec::new()))
         } else {
-            Ok((Mode::Trackerless(None), self.info_hash.unwrap()))
+            Ok((Mode::Trackerless(None), self.info_hash.unwrap(), Vec::new()))
         }
     }
 }
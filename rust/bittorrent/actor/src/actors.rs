@@ -1,4 +1,5 @@
 use std::io::Error;
+use std::net::SocketAddr;
 
 use futures::future::{FutureExt, OptionFuture};
 
@@ -7,10 +8,13 @@ use g1_tokio::task::JoinQueue;
 use bittorrent_base::InfoHash;
 use bittorrent_dht::{Dht, DhtGuard};
 use bittorrent_manager::{Manager, ManagerGuard};
+use bittorrent_portmap::{PortMap, PortMapGuard};
 use bittorrent_tracker::{Tracker, TrackerGuard};
 use bittorrent_transceiver::{Transceiver, TransceiverGuard};
 use bittorrent_utp::UtpSocket;
 
+use crate::discovery::Discovery;
+use crate::external::ExternalAddr;
 use crate::init::{Guards, Init};
 use crate::storage::StorageOpen;
 use crate::Mode;
@@ -31,19 +35,44 @@ pub struct Actors {
     pub tracker: Option<Tracker>,
     tracker_guard: Option<TrackerGuard>,
 
+    pub portmap: Option<PortMap>,
+    portmap_guard: Option<PortMapGuard>,
+
+    pub external_addr: ExternalAddr,
+
     utp_socket_ipv4: Option<UtpSocket>,
     utp_socket_ipv6: Option<UtpSocket>,
 
     tasks: JoinQueue<Result<(), Error>>,
 }
 
+/// Where a manually added peer comes from.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PeerSource {
+    Magnet, // BEP 9 "x.pe" parameter.
+    User,
+}
+
 impl Actors {
-    pub async fn spawn(mode: Mode, info_hash: InfoHash, open: StorageOpen) -> Result<Self, Error> {
-        let mut init = Init::new(mode, info_hash, open);
+    /// Spawns the actors, where `peers` are the `x.pe` peers of the magnet URI, if any, and
+    /// `discovery` overrides the process-wide peer discovery settings for this torrent.
+    ///
+    /// The `x.pe` peers are connected to as soon as the peer manager starts, which is before the
+    /// info is fetched.
+    pub async fn spawn(
+        mode: Mode,
+        info_hash: InfoHash,
+        open: StorageOpen,
+        peers: Vec<String>,
+        discovery: Discovery,
+    ) -> Result<Self, Error> {
+        let mut init = Init::new(mode, info_hash, open, peers, discovery);
         let manager = init.init_manager().await?;
         let dht_ipv4 = init.init_dht_ipv4().await?;
         let dht_ipv6 = init.init_dht_ipv6().await?;
         let tracker = init.init_tracker().await?;
+        let portmap = init.init_portmap().await?;
+        let external_addr = init.external_addr();
 
         // Spawn txrx at last.
         let txrx = init.init_txrx().await?;
@@ -54,6 +83,7 @@ impl Actors {
             dht_guard_ipv4,
             dht_guard_ipv6,
             tracker_guard,
+            portmap_guard,
             utp_socket_ipv4,
             utp_socket_ipv6,
             tasks,
@@ -74,6 +104,11 @@ impl Actors {
             tracker,
             tracker_guard,
 
+            portmap,
+            portmap_guard,
+
+            external_addr,
+
             utp_socket_ipv4,
             utp_socket_ipv6,
 
@@ -81,6 +116,12 @@ impl Actors {
         })
     }
 
+    /// Connects to the peer immediately, regardless of what the tracker and DHT return.
+    pub fn add_peer(&self, peer_endpoint: SocketAddr, source: PeerSource) {
+        tracing::info!(?peer_endpoint, ?source, "add peer");
+        self.manager.connect(peer_endpoint, None);
+    }
+
     pub async fn join_any(&mut self) {
         macro_rules! call {
             ($guard:ident, $func:ident $(,)?) => {
@@ -93,6 +134,7 @@ impl Actors {
             Some(()) = call!(dht_guard_ipv4, joinable) => {}
             Some(()) = call!(dht_guard_ipv6, joinable) => {}
             Some(()) = call!(tracker_guard, join) => {}
+            Some(()) = call!(portmap_guard, join) => {}
             Some(()) = call!(utp_socket_ipv4, join) => {}
             Some(()) = call!(utp_socket_ipv6, join) => {}
             () = self.tasks.joinable() => {}
@@ -106,12 +148,13 @@ impl Actors {
                     .map(|result| result.map($mapper).unwrap_or(Ok(())))
             };
         }
-        let results = <[_; 8]>::from(tokio::join!(
+        let results = <[_; 9]>::from(tokio::join!(
             self.txrx_guard.shutdown().map(|r| r?),
             self.manager_guard.shutdown().map(|r| r?),
             shutdown!(dht_guard_ipv4, |r| r?),
             shutdown!(dht_guard_ipv6, |r| r?),
             shutdown!(tracker_guard, |r| r?.map_err(Error::other)),
+            shutdown!(portmap_guard, |r| r?),
             shutdown!(utp_socket_ipv4, |r| r),
             shutdown!(utp_socket_ipv6, |r| r),
             self.tasks.shutdown().map(|r| r?),
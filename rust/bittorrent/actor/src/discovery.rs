@@ -0,0 +1,99 @@
+//! Per-Torrent Peer Discovery Settings
+//!
+//! The process-wide settings (`bittorrent_base::dht_enable` and `bittorrent_extension::pex_enable`)
+//! are the defaults, which each torrent may override.  DHT may be enabled or disabled per torrent,
+//! but PEX may only be disabled, since the extension ids are negotiated process-wide.
+//!
+//! NOTE: We do not implement Local Service Discovery (BEP 14) yet.
+
+use bittorrent_base::Features;
+use bittorrent_extension::Enabled;
+
+/// Per-torrent overrides of the process-wide peer discovery settings, where `None` inherits the
+/// process-wide setting.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Discovery {
+    pub dht: Option<bool>,
+    pub peer_exchange: Option<bool>,
+}
+
+/// Peer discovery mechanisms that are in effect for a torrent.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct Resolved {
+    pub(crate) dht: bool,
+    pub(crate) peer_exchange: bool,
+}
+
+impl Discovery {
+    pub(crate) fn resolve(&self, private: bool) -> Resolved {
+        self.resolve_with(private, Features::load().dht, Enabled::load().peer_exchange)
+    }
+
+    fn resolve_with(&self, private: bool, dht: bool, peer_exchange: bool) -> Resolved {
+        // BEP 27 specifies that a private torrent obtains peers only from its trackers, which no
+        // override can change.
+        if private {
+            if self.dht == Some(true) || self.peer_exchange == Some(true) {
+                tracing::warn!(discovery = ?self, "ignore overrides of private torrent");
+            }
+            return Resolved {
+                dht: false,
+                peer_exchange: false,
+            };
+        }
+        if self.peer_exchange == Some(true) && !peer_exchange {
+            tracing::warn!("ignore pex override because pex is disabled process-wide");
+        }
+        Resolved {
+            dht: self.dht.unwrap_or(dht),
+            peer_exchange: self.peer_exchange.unwrap_or(true) && peer_exchange,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve() {
+        fn test(discovery: Discovery, private: bool, global: (bool, bool), expect: (bool, bool)) {
+            assert_eq!(
+                discovery.resolve_with(private, global.0, global.1),
+                Resolved {
+                    dht: expect.0,
+                    peer_exchange: expect.1,
+                },
+            );
+        }
+
+        let inherit = Discovery::default();
+        let disable = Discovery {
+            dht: Some(false),
+            peer_exchange: Some(false),
+        };
+        let enable = Discovery {
+            dht: Some(true),
+            peer_exchange: Some(true),
+        };
+
+        for global in [(false, false), (true, false), (false, true), (true, true)] {
+            test(inherit, false, global, global);
+            test(disable, false, global, (false, false));
+            test(enable, false, global, (true, global.1));
+
+            test(inherit, true, global, (false, false));
+            test(enable, true, global, (false, false));
+        }
+
+        test(
+            Discovery {
+                dht: Some(false),
+                peer_exchange: None,
+            },
+            false,
+            (true, true),
+            (false, true),
+        );
+    }
+}
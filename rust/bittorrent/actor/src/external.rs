@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::watch;
+
+use g1_base::sync::MutexExt;
+
+/// Where an external address hint comes from.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Source {
+    PortMap,
+    Tracker,
+    Dht,  // BEP 42 "ip" field.
+    Peer, // BEP 10 "yourip" field.
+}
+
+/// Aggregates external address hints from multiple sources.
+///
+/// Each voter (identified by its source and, optionally, its address) has at most one vote, and
+/// votes expire after a while.  The external address only changes when another address collects
+/// more weighted votes than the current one, so that a single peer cannot flip it.
+///
+/// IPv4 and IPv6 addresses are elected separately because a dual-stack host has one of each.
+#[derive(Clone, Debug)]
+pub struct ExternalAddr {
+    v4: Election,
+    v6: Election,
+    /// The external TCP port that the gateway maps, which is IPv4-only.
+    // Wrap it in an `Arc` so that `Clone` can be derived for `ExternalAddr`.
+    port_v4_send: Arc<watch::Sender<Option<u16>>>,
+}
+
+#[derive(Clone, Debug)]
+struct Election {
+    votes: Arc<Mutex<Votes>>,
+    // Wrap it in an `Arc` so that `Clone` can be derived for `Election`.
+    addr_send: Arc<watch::Sender<Option<IpAddr>>>,
+}
+
+#[derive(Debug)]
+struct Votes {
+    quorum: u32,
+    lifetime: Duration,
+    votes: HashMap<(Source, Option<IpAddr>), (IpAddr, Instant)>,
+    current: Option<IpAddr>,
+}
+
+impl Source {
+    fn weight(self, quorum: u32) -> u32 {
+        match self {
+            // The gateway is authoritative, and it should reach the quorum on its own.
+            Self::PortMap => quorum,
+            Self::Tracker => 2,
+            Self::Dht | Self::Peer => 1,
+        }
+    }
+}
+
+impl Default for ExternalAddr {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ExternalAddr {
+    pub fn new() -> Self {
+        let quorum = *crate::external_addr_quorum();
+        let lifetime = *crate::external_addr_vote_lifetime();
+        Self {
+            v4: Election::new(quorum, lifetime),
+            v6: Election::new(quorum, lifetime),
+            port_v4_send: Arc::new(watch::channel(None).0),
+        }
+    }
+
+    pub fn external_addr_v4(&self) -> Option<IpAddr> {
+        self.v4.external_addr(Instant::now())
+    }
+
+    pub fn external_addr_v6(&self) -> Option<IpAddr> {
+        self.v6.external_addr(Instant::now())
+    }
+
+    pub fn subscribe_v4(&self) -> watch::Receiver<Option<IpAddr>> {
+        self.v4.addr_send.subscribe()
+    }
+
+    pub fn subscribe_v6(&self) -> watch::Receiver<Option<IpAddr>> {
+        self.v6.addr_send.subscribe()
+    }
+
+    pub fn external_port_v4(&self) -> Option<u16> {
+        *self.port_v4_send.borrow()
+    }
+
+    pub fn subscribe_port_v4(&self) -> watch::Receiver<Option<u16>> {
+        self.port_v4_send.subscribe()
+    }
+
+    /// Sets the external port that the gateway maps, or `None` if there is no mapping.
+    pub fn set_external_port_v4(&self, new_port: Option<u16>) {
+        self.port_v4_send.send_if_modified(|port| {
+            if *port == new_port {
+                return false;
+            }
+            tracing::info!(external_port = ?new_port, "external port");
+            *port = new_port;
+            true
+        });
+    }
+
+    /// Records (or replaces) the vote of a voter.
+    ///
+    /// `voter` is the address of the remote that told us our address, or `None` if the source
+    /// has only one voter.
+    pub fn submit(&self, source: Source, voter: Option<IpAddr>, addr: IpAddr) {
+        let addr = addr.to_canonical();
+        if addr.is_unspecified() || addr.is_loopback() || addr.is_multicast() {
+            tracing::debug!(?source, ?voter, %addr, "ignore invalid external address");
+            return;
+        }
+        let election = if addr.is_ipv4() { &self.v4 } else { &self.v6 };
+        election.submit(source, voter, addr, Instant::now());
+    }
+
+    pub fn retract(&self, source: Source, voter: Option<IpAddr>) {
+        let now = Instant::now();
+        self.v4.retract(source, voter, now);
+        self.v6.retract(source, voter, now);
+    }
+
+    /// Removes expired votes, so that subscribers are notified when the consensus expires.
+    pub fn expire(&self) {
+        let now = Instant::now();
+        self.v4.external_addr(now);
+        self.v6.external_addr(now);
+    }
+}
+
+impl Election {
+    fn new(quorum: u32, lifetime: Duration) -> Self {
+        Self {
+            votes: Arc::new(Mutex::new(Votes::new(quorum, lifetime))),
+            addr_send: Arc::new(watch::channel(None).0),
+        }
+    }
+
+    fn external_addr(&self, now: Instant) -> Option<IpAddr> {
+        let mut votes = self.votes.must_lock();
+        votes.elect(now);
+        self.update(&votes);
+        votes.current
+    }
+
+    fn submit(&self, source: Source, voter: Option<IpAddr>, addr: IpAddr, now: Instant) {
+        let mut votes = self.votes.must_lock();
+        votes.submit(source, voter, addr, now);
+        self.update(&votes);
+    }
+
+    fn retract(&self, source: Source, voter: Option<IpAddr>, now: Instant) {
+        let mut votes = self.votes.must_lock();
+        votes.retract(source, voter, now);
+        self.update(&votes);
+    }
+
+    fn update(&self, votes: &Votes) {
+        let external_addr = votes.current;
+        self.addr_send.send_if_modified(|addr| {
+            if *addr == external_addr {
+                return false;
+            }
+            tracing::info!(?external_addr, "external address");
+            *addr = external_addr;
+            true
+        });
+    }
+}
+
+impl Votes {
+    fn new(quorum: u32, lifetime: Duration) -> Self {
+        Self {
+            quorum,
+            lifetime,
+            votes: HashMap::new(),
+            current: None,
+        }
+    }
+
+    fn submit(&mut self, source: Source, voter: Option<IpAddr>, addr: IpAddr, now: Instant) {
+        self.votes.insert((source, voter), (addr, now));
+        self.elect(now);
+    }
+
+    fn retract(&mut self, source: Source, voter: Option<IpAddr>, now: Instant) {
+        self.votes.remove(&(source, voter));
+        self.elect(now);
+    }
+
+    fn elect(&mut self, now: Instant) {
+        self.votes
+            .retain(|_, (_, voted_at)| now.saturating_duration_since(*voted_at) < self.lifetime);
+
+        let mut tally = HashMap::<IpAddr, u32>::new();
+        for ((source, _), (addr, _)) in &self.votes {
+            *tally.entry(*addr).or_default() += source.weight(self.quorum);
+        }
+
+        let current_score = self
+            .current
+            .and_then(|current| tally.get(&current).copied())
+            .unwrap_or(0);
+        if current_score == 0 {
+            // All votes for the current address have expired or been retracted.
+            self.current = None;
+        }
+
+        // Break ties deterministically so that the result does not depend on hash order.
+        let Some((addr, score)) = tally
+            .into_iter()
+            .max_by_key(|(addr, score)| (*score, *addr))
+        else {
+            return;
+        };
+        if score >= self.quorum && score > current_score {
+            self.current = Some(addr);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+
+    #[test]
+    fn elect() {
+        fn ip(x: u8) -> IpAddr {
+            Ipv4Addr::new(203, 0, 113, x).into()
+        }
+
+        let lifetime = Duration::from_secs(10);
+        let t0 = Instant::now();
+        let mut votes = Votes::new(4, lifetime);
+
+        // A single peer cannot decide our address.
+        votes.submit(Source::Peer, Some(ip(100)), ip(1), t0);
+        assert_eq!(votes.current, None);
+        // Nor can it by voting repeatedly.
+        votes.submit(Source::Peer, Some(ip(100)), ip(1), t0);
+        votes.submit(Source::Peer, Some(ip(100)), ip(1), t0);
+        assert_eq!(votes.current, None);
+
+        votes.submit(Source::Dht, Some(ip(101)), ip(1), t0);
+        votes.submit(Source::Tracker, Some(ip(102)), ip(1), t0);
+        assert_eq!(votes.current, Some(ip(1)));
+
+        // The current address is sticky on ties.
+        votes.submit(Source::Peer, Some(ip(103)), ip(2), t0);
+        votes.submit(Source::Peer, Some(ip(104)), ip(2), t0);
+        votes.submit(Source::Peer, Some(ip(105)), ip(2), t0);
+        votes.submit(Source::Peer, Some(ip(106)), ip(2), t0);
+        assert_eq!(votes.current, Some(ip(1)));
+        votes.submit(Source::Peer, Some(ip(107)), ip(2), t0);
+        assert_eq!(votes.current, Some(ip(2)));
+
+        votes.retract(Source::Peer, Some(ip(107)), t0);
+        assert_eq!(votes.current, Some(ip(2)));
+
+        // Votes expire.
+        votes.submit(Source::Dht, Some(ip(108)), ip(3), t0 + lifetime);
+        assert_eq!(votes.current, None);
+        assert_eq!(votes.votes.len(), 1);
+    }
+
+    #[test]
+    fn dual_stack() {
+        let v4 = IpAddr::from(Ipv4Addr::new(203, 0, 113, 1));
+        let v6 = IpAddr::from(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1));
+        let peer = |x: u8| Some(IpAddr::from(Ipv4Addr::new(198, 51, 100, x)));
+
+        let external_addr = ExternalAddr::new();
+        external_addr.submit(Source::PortMap, None, v4);
+        for x in 0..4 {
+            external_addr.submit(Source::Peer, peer(x), v6);
+        }
+        assert_eq!(external_addr.external_addr_v4(), Some(v4));
+        assert_eq!(external_addr.external_addr_v6(), Some(v6));
+
+        // IPv4-mapped addresses are IPv4 addresses.
+        let mapped = IpAddr::from(Ipv4Addr::new(203, 0, 113, 2).to_ipv6_mapped());
+        for x in 0..4 {
+            external_addr.submit(Source::Dht, peer(x + 10), mapped);
+        }
+        assert_eq!(external_addr.external_addr_v4(), Some(v4));
+        assert_eq!(external_addr.external_addr_v6(), Some(v6));
+
+        external_addr.retract(Source::PortMap, None);
+        assert_eq!(
+            external_addr.external_addr_v4(),
+            Some(mapped.to_canonical())
+        );
+        assert_eq!(external_addr.external_addr_v6(), Some(v6));
+    }
+
+    #[test]
+    fn expire_on_read() {
+        let v4 = IpAddr::from(Ipv4Addr::new(203, 0, 113, 1));
+        let lifetime = Duration::from_secs(10);
+        let t0 = Instant::now();
+        let election = Election::new(1, lifetime);
+        let addr_recv = election.addr_send.subscribe();
+
+        election.submit(Source::PortMap, None, v4, t0);
+        assert_eq!(election.external_addr(t0), Some(v4));
+        assert_eq!(*addr_recv.borrow(), Some(v4));
+
+        assert_eq!(election.external_addr(t0 + lifetime), None);
+        assert_eq!(*addr_recv.borrow(), None);
+    }
+}
@@ -1,30 +1,42 @@
 use std::io::Error;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
 use std::pin::Pin;
 use std::sync::Arc;
 
 use bytes::Bytes;
-use futures::{future::OptionFuture, sink::Sink, stream::Stream};
+use futures::{
+    future::{self, OptionFuture},
+    sink::{Sink, SinkExt},
+    stream::{Stream, TryStreamExt},
+};
 use tokio::net::{TcpListener, TcpSocket, UdpSocket};
-use tokio::sync::broadcast::Receiver;
+use tokio::sync::{broadcast::Receiver, mpsc};
+use tokio::time;
 
 use g1_base::fmt::{DebugExt, InsertPlaceholder};
 use g1_futures::sink;
-use g1_tokio::net::udp::{self, OwnedUdpSink, OwnedUdpStream};
+use g1_tokio::net::udp::{self, Ecn, OwnedUdpStream};
 use g1_tokio::task::{JoinGuard, JoinQueue};
 
-use bittorrent_base::{Dimension, Features, InfoHash};
+use bittorrent_base::{Dimension, Features, InfoHash, PeerId};
 use bittorrent_dht::{Dht, DhtGuard};
 use bittorrent_manager::{Manager, ManagerGuard};
 use bittorrent_metainfo::Info;
 use bittorrent_peer::Recvs;
+use bittorrent_portmap::{Mapping, PortMap, PortMapGuard, Protocol};
 use bittorrent_tracker::{Tracker, TrackerGuard};
 use bittorrent_transceiver::{
-    DynStorage, Torrent, Transceiver, TransceiverGuard, TransceiverSpawn, Update,
+    Counters, DynStorage, SelfAddr, Torrent, Transceiver, TransceiverGuard, TransceiverSpawn,
+    Update,
 };
-use bittorrent_utp::UtpSocket;
+use bittorrent_utp::{UtpConfig, UtpSocket};
 
+use crate::discovery::Discovery;
+use crate::external::ExternalAddr;
 use crate::integrate;
+use crate::resume;
+use crate::schedule;
 use crate::storage::StorageOpen;
 use crate::Mode;
 
@@ -32,14 +44,22 @@ use crate::Mode;
 type DynStream = Pin<Box<dyn Stream<Item = Result<(SocketAddr, Bytes), Error>> + Send + 'static>>;
 type DynSink = Pin<Box<dyn Sink<(SocketAddr, Bytes), Error = Error> + Send + 'static>>;
 
-pub(crate) type Fork = bittorrent_udp::Fork<OwnedUdpStream>;
-type Fanin = sink::Fanin<OwnedUdpSink>;
+type EcnDatagram = (SocketAddr, Bytes, Ecn);
+type DynEcnStream = Pin<Box<dyn Stream<Item = Result<EcnDatagram, Error>> + Send + 'static>>;
+type DynEcnSink = Pin<Box<dyn Sink<EcnDatagram, Error = Error> + Send + 'static>>;
+
+pub(crate) type Fork = bittorrent_udp::Fork<OwnedUdpStream<EcnDatagram>, EcnDatagram>;
 
 #[derive(DebugExt)]
 pub(crate) struct Init {
     mode: Mode,
     info_hash: InfoHash,
     open: StorageOpen,
+    // BEP 9 "x.pe" peers, which are either `hostname:port`, `ipv4:port`, or `[ipv6]:port`.
+    peers: Vec<String>,
+    peer_exchange: bool,
+
+    self_id: Option<PeerId>,
 
     txrx: Option<Transceiver>,
     txrx_guard: Option<TransceiverGuard>,
@@ -55,6 +75,12 @@ pub(crate) struct Init {
     tracker: Option<Tracker>,
     tracker_guard: Option<Option<TrackerGuard>>,
 
+    portmap: Option<PortMap>,
+    portmap_guard: Option<Option<PortMapGuard>>,
+    mapped_addr: Option<Option<IpAddr>>,
+
+    external_addr: ExternalAddr,
+
     net_ipv4: Option<NetInit>,
     net_ipv6: Option<NetInit>,
 
@@ -72,6 +98,8 @@ pub(crate) struct Guards {
 
     pub(crate) tracker_guard: Option<TrackerGuard>,
 
+    pub(crate) portmap_guard: Option<PortMapGuard>,
+
     pub(crate) utp_socket_ipv4: Option<UtpSocket>,
     pub(crate) utp_socket_ipv6: Option<UtpSocket>,
 
@@ -89,14 +117,16 @@ struct NetInit {
 
     dht: Option<Dht>,
     dht_guard: Option<DhtGuard>,
-    dht_stream: Option<Fork>,
-    dht_sink: Option<Fanin>,
+    #[debug(with = InsertPlaceholder)]
+    dht_stream: Option<DynStream>,
+    #[debug(with = InsertPlaceholder)]
+    dht_sink: Option<DynSink>,
 
     utp_socket: Option<UtpSocket>,
     #[debug(with = InsertPlaceholder)]
-    utp_stream: Option<DynStream>,
+    utp_stream: Option<DynEcnStream>,
     #[debug(with = InsertPlaceholder)]
-    utp_sink: Option<DynSink>,
+    utp_sink: Option<DynEcnSink>,
 
     udp_socket: Option<Arc<UdpSocket>>,
     udp_stream_and_sink_init: bool,
@@ -113,24 +143,47 @@ macro_rules! subinit {
 }
 
 impl Init {
-    pub(crate) fn new(mode: Mode, info_hash: InfoHash, open: StorageOpen) -> Self {
+    pub(crate) fn new(
+        mode: Mode,
+        info_hash: InfoHash,
+        open: StorageOpen,
+        peers: Vec<String>,
+        discovery: Discovery,
+    ) -> Self {
+        let private = match &mode {
+            Mode::Tracker(metainfo) => metainfo.deref().info.private,
+            Mode::Trackerless(Some(info)) => info.deref().private,
+            // The info is unknown until it is fetched, but a magnet link is not supposed to refer
+            // to a private torrent in the first place.
+            Mode::Trackerless(None) => None,
+        } == Some(true);
+        let discovery = discovery.resolve(private);
+        tracing::info!(?discovery, "discovery");
         Self::with_params(
             mode,
             info_hash,
             open,
+            peers,
             *crate::self_endpoint_ipv4(),
             *crate::self_endpoint_ipv6(),
-            Features::load(),
+            Features {
+                dht: discovery.dht,
+                ..Features::load()
+            },
+            discovery.peer_exchange,
         )
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn with_params(
         mode: Mode,
         info_hash: InfoHash,
         open: StorageOpen,
+        peers: Vec<String>,
         self_endpoint_ipv4: Option<SocketAddr>,
         self_endpoint_ipv6: Option<SocketAddr>,
         self_features: Features,
+        peer_exchange: bool,
     ) -> Self {
         let tasks = Arc::new(JoinQueue::new());
         let net_init_new = |self_endpoint| {
@@ -147,6 +200,8 @@ impl Init {
             mode,
             info_hash,
             open,
+            peers,
+            peer_exchange,
 
             txrx: None,
             txrx_guard: None,
@@ -154,6 +209,8 @@ impl Init {
             torrent: None,
             update_recv: None,
 
+            self_id: None,
+
             manager: None,
             recvs: None,
             manager_guard: None,
@@ -161,6 +218,12 @@ impl Init {
             tracker: None,
             tracker_guard: None,
 
+            portmap: None,
+            portmap_guard: None,
+            mapped_addr: None,
+
+            external_addr: ExternalAddr::new(),
+
             net_ipv4,
             net_ipv6,
 
@@ -169,11 +232,12 @@ impl Init {
     }
 
     pub(crate) async fn into_guards(mut self) -> Result<Guards, Error> {
+        self.init_bandwidth_schedule();
         self.init_txrx_guard().await?;
-        let manager = self.init_manager().await?;
-        subinit!(self.net_ipv4, init_dht_guard(manager.clone()));
-        subinit!(self.net_ipv6, init_dht_guard(manager));
+        self.init_dht_ipv4().await?;
+        self.init_dht_ipv6().await?;
         self.init_tracker_guard().await?;
+        self.init_portmap_guard().await?;
         subinit!(self.net_ipv4, init_utp_socket());
         subinit!(self.net_ipv6, init_utp_socket());
 
@@ -181,6 +245,7 @@ impl Init {
             txrx_guard,
             manager_guard,
             tracker_guard,
+            portmap_guard,
             mut net_ipv4,
             mut net_ipv6,
             tasks,
@@ -204,6 +269,8 @@ impl Init {
 
             tracker_guard: tracker_guard.unwrap(),
 
+            portmap_guard: portmap_guard.unwrap(),
+
             utp_socket_ipv4,
             utp_socket_ipv6,
 
@@ -211,6 +278,23 @@ impl Init {
         })
     }
 
+    //
+    // Bandwidth Schedule
+    //
+
+    fn init_bandwidth_schedule(&self) {
+        if schedule::init() {
+            return;
+        }
+        let _ = self.tasks.push(JoinGuard::spawn(move |cancel| async move {
+            tokio::select! {
+                () = cancel.wait() => {}
+                () = schedule::run() => {}
+            }
+            Ok(())
+        }));
+    }
+
     //
     // Transceiver
     //
@@ -255,25 +339,60 @@ impl Init {
         let mut recvs = self.init_once_recvs().await?;
         let dht_ipv4 = self.init_dht_ipv4().await?;
         let dht_ipv6 = self.init_dht_ipv6().await?;
+        let (your_ip_send, your_ip_recv) = mpsc::channel(*crate::your_ip_queue_size());
+        let self_addr = SelfAddr {
+            port_ipv4: subinit!(self.net_ipv4, init_self_endpoint())
+                .map(|endpoint| endpoint.port()),
+            port_ipv6: subinit!(self.net_ipv6, init_self_endpoint())
+                .map(|endpoint| endpoint.port()),
+            external_addr_v4_recv: self.external_addr.subscribe_v4(),
+            external_addr_v6_recv: self.external_addr.subscribe_v6(),
+            your_ip_send,
+        };
+        {
+            let external_addr = self.external_addr.clone();
+            let _ = self.tasks.push(JoinGuard::spawn(move |cancel| async move {
+                tokio::select! {
+                    () = cancel.wait() => {}
+                    () = integrate::update_external_addr_from_peers(
+                        your_ip_recv,
+                        external_addr,
+                    ) => {}
+                }
+                Ok(())
+            }));
+        }
+        {
+            let external_addr = self.external_addr.clone();
+            let _ = self.tasks.push(JoinGuard::spawn(move |cancel| async move {
+                tokio::select! {
+                    () = cancel.wait() => {}
+                    () = integrate::expire_external_addr(external_addr) => {}
+                }
+                Ok(())
+            }));
+        }
 
         async fn open(
             open: &StorageOpen,
             info: &Info<'_>,
-        ) -> Result<(Bytes, Dimension, DynStorage), Error> {
+        ) -> Result<(Bytes, Dimension, DynStorage, PathBuf, Counters), Error> {
             // `MetainfoOwner` and `InfoOwner` do not guarantee that their buffers exactly match
             // the raw info blob.  Therefore, we cannot rely on the `into_buffer` method and must
             // explicitly copy the blob.
             let raw_info = Bytes::copy_from_slice(info.raw_info);
             let dim = info.new_dimension(*bittorrent_base::block_size());
             let storage = open.open(info, dim.clone()).await?;
-            Ok((raw_info, dim, storage))
+            let resume_path = resume::new_path(open.torrent_dir(), info.name);
+            let base = resume::load(&resume_path).await?;
+            Ok((raw_info, dim, storage, resume_path, base))
         }
-        let (raw_info, dim, storage) = match &self.mode {
+        let (raw_info, dim, storage, resume_path, base) = match &self.mode {
             Mode::Tracker(metainfo) => open(&self.open, &metainfo.deref().info).await?,
             Mode::Trackerless(Some(info)) => open(&self.open, info.deref()).await?,
             Mode::Trackerless(None) => {
-                if dht_ipv4.is_none() && dht_ipv6.is_none() {
-                    return Err(Error::other("fetch_info requires dht"));
+                if dht_ipv4.is_none() && dht_ipv6.is_none() && self.peers.is_empty() {
+                    return Err(Error::other("fetch_info requires dht or magnet peers"));
                 }
                 open(
                     &self.open,
@@ -290,13 +409,28 @@ impl Init {
             raw_info,
             dim,
             manager.clone(),
+            self_addr,
             recvs,
             storage,
             dht_ipv4,
             dht_ipv6,
+            self.peer_exchange,
+            base,
         )
         .await?;
 
+        {
+            let torrent = torrent.clone();
+            let _ = self.tasks.push(JoinGuard::spawn(move |cancel| async move {
+                tokio::select! {
+                    () = cancel.wait() => {}
+                    () = integrate::save_resume(resume_path.clone(), torrent.clone()) => {}
+                }
+                // Save the final counters on shutdown.
+                resume::save(&resume_path, torrent.total()).await
+            }));
+        }
+
         {
             let update_recv = update_recv.resubscribe();
             let _ = self.tasks.push(JoinGuard::spawn(move |cancel| async move {
@@ -314,6 +448,31 @@ impl Init {
         Ok(())
     }
 
+    //
+    // Peer Id
+    //
+
+    async fn init_self_id(&mut self) -> Result<PeerId, Error> {
+        if let Some(self_id) = self.self_id.clone() {
+            return Ok(self_id);
+        }
+
+        // We use a per-torrent peer id for private torrents, in both announces and handshakes, so
+        // that it does not link them to our other torrents.
+        let self_id = match &self.mode {
+            Mode::Tracker(metainfo) if metainfo.deref().info.private == Some(true) => {
+                let name = metainfo.deref().info.name;
+                resume::load_peer_id(&resume::new_peer_id_path(self.open.torrent_dir(), name))
+                    .await?
+            }
+            _ => bittorrent_base::self_id().clone(),
+        };
+        tracing::info!(?self_id, "init self id");
+
+        self.self_id = Some(self_id.clone());
+        Ok(self_id)
+    }
+
     //
     // Manager
     //
@@ -333,9 +492,11 @@ impl Init {
             return Ok(());
         }
 
+        let self_id = self.init_self_id().await?;
         tracing::info!("init peer manager");
         let (manager, recvs, manager_guard) = Manager::spawn(
             self.info_hash.clone(),
+            self_id,
             subinit!(self.net_ipv4, init_once_tcp_listener()),
             subinit!(self.net_ipv6, init_once_tcp_listener()),
             subinit!(self.net_ipv4, init_utp_socket()),
@@ -346,6 +507,18 @@ impl Init {
             manager.connect(peer_endpoint, None);
         }
 
+        if !self.peers.is_empty() {
+            let peers = self.peers.clone();
+            let manager = manager.clone();
+            let _ = self.tasks.push(JoinGuard::spawn(move |cancel| async move {
+                tokio::select! {
+                    () = cancel.wait() => {}
+                    () = integrate::add_magnet_peers(peers, manager) => {}
+                }
+                Ok(())
+            }));
+        }
+
         self.manager = Some(manager);
         self.recvs = Some(recvs);
         self.manager_guard = Some(manager_guard);
@@ -358,12 +531,20 @@ impl Init {
 
     pub(crate) async fn init_dht_ipv4(&mut self) -> Result<Option<Dht>, Error> {
         let manager = self.init_manager().await?;
-        Ok(subinit!(self.net_ipv4, init_dht(manager)).flatten())
+        // Fall back to the elected address, which is unlikely to be available this early.
+        let external_ip = self
+            .init_mapped_addr()
+            .await?
+            .or_else(|| self.external_addr.external_addr_v4());
+        let external_addr = self.external_addr.clone();
+        Ok(subinit!(self.net_ipv4, init_dht(manager, external_ip, external_addr)).flatten())
     }
 
     pub(crate) async fn init_dht_ipv6(&mut self) -> Result<Option<Dht>, Error> {
         let manager = self.init_manager().await?;
-        Ok(subinit!(self.net_ipv6, init_dht(manager)).flatten())
+        let external_addr = self.external_addr.clone();
+        // NAT-PMP and UPnP IGD are IPv4-only.
+        Ok(subinit!(self.net_ipv6, init_dht(manager, None, external_addr)).flatten())
     }
 
     //
@@ -387,18 +568,22 @@ impl Init {
         let torrent = self.init_torrent().await?;
         let update_recv = self.init_once_update_recv().await?;
         let manager = self.init_manager().await?;
+        let self_id = self.init_self_id().await?;
         let Mode::Tracker(metainfo) = &self.mode else {
             std::unreachable!()
         };
 
         // TODO: Support IPv6.
-        let port_ipv4 = subinit!(self.net_ipv4, init_self_endpoint())
-            .unwrap()
-            .port();
+        let self_endpoint_ipv4 = subinit!(self.net_ipv4, init_self_endpoint()).unwrap();
 
         tracing::info!("init tracker");
-        let (tracker, tracker_guard) =
-            Tracker::spawn(metainfo.deref(), self.info_hash.clone(), port_ipv4, torrent);
+        let (tracker, tracker_guard) = Tracker::spawn(
+            metainfo.deref(),
+            self.info_hash.clone(),
+            self_id,
+            self_endpoint_ipv4,
+            torrent,
+        );
 
         {
             let tracker = tracker.clone();
@@ -422,10 +607,129 @@ impl Init {
             }));
         }
 
+        {
+            let port_recv = self.external_addr.subscribe_port_v4();
+            let tracker = tracker.clone();
+            let self_port = self_endpoint_ipv4.port();
+            let _ = self.tasks.push(JoinGuard::spawn(move |cancel| async move {
+                tokio::select! {
+                    () = cancel.wait() => {}
+                    () = integrate::update_tracker_port(port_recv, tracker, self_port) => {}
+                }
+                Ok(())
+            }));
+        }
+
+        {
+            let status_recv = tracker.subscribe();
+            let external_addr = self.external_addr.clone();
+            let _ = self.tasks.push(JoinGuard::spawn(move |cancel| async move {
+                tokio::select! {
+                    () = cancel.wait() => {}
+                    () = integrate::update_external_addr_from_tracker(
+                        status_recv,
+                        external_addr,
+                    ) => {}
+                }
+                Ok(())
+            }));
+        }
+
         self.tracker = Some(tracker);
         self.tracker_guard = Some(Some(tracker_guard));
         Ok(())
     }
+
+    //
+    // PortMap
+    //
+
+    pub(crate) fn external_addr(&self) -> ExternalAddr {
+        self.external_addr.clone()
+    }
+
+    /// Returns the address mapped by the gateway, for which our DHT node id is generated.
+    ///
+    /// Since the node id cannot be changed afterwards, we wait for the gateway, but only briefly.
+    async fn init_mapped_addr(&mut self) -> Result<Option<IpAddr>, Error> {
+        if let Some(mapped_addr) = self.mapped_addr {
+            return Ok(mapped_addr);
+        }
+        // Do not wait for the gateway when it is of no use.
+        if !self
+            .net_ipv4
+            .as_ref()
+            .is_some_and(|net| net.self_features.dht)
+        {
+            return Ok(None);
+        }
+
+        let mapped_addr = match self.init_portmap().await? {
+            Some(portmap) => {
+                let mut external_addr_recv = portmap.subscribe();
+                let mapped_addr = time::timeout(
+                    *crate::port_mapping_wait(),
+                    external_addr_recv.wait_for(Option::is_some),
+                )
+                .await
+                .ok()
+                .and_then(Result::ok)
+                .and_then(|addr| *addr);
+                if mapped_addr.is_none() {
+                    tracing::info!("port mapping is not ready for the dht node id");
+                }
+                mapped_addr
+            }
+            None => None,
+        };
+
+        self.mapped_addr = Some(mapped_addr);
+        Ok(mapped_addr)
+    }
+
+    pub(crate) async fn init_portmap(&mut self) -> Result<Option<PortMap>, Error> {
+        self.init_portmap_guard().await?;
+        Ok(self.portmap.clone())
+    }
+
+    async fn init_portmap_guard(&mut self) -> Result<(), Error> {
+        if self.portmap_guard.is_some() {
+            return Ok(());
+        }
+        // NAT-PMP and UPnP IGD are IPv4-only.
+        let Some(self_endpoint) = subinit!(self.net_ipv4, init_self_endpoint()) else {
+            self.portmap_guard = Some(None);
+            return Ok(());
+        };
+        if !*crate::port_mapping_enable() || self_endpoint.ip().is_loopback() {
+            self.portmap_guard = Some(None);
+            return Ok(());
+        }
+
+        // We map the UDP port, too, because both uTP and DHT share it with the TCP listener.
+        let port = self_endpoint.port();
+        tracing::info!(port, "init port mapping");
+        let (portmap, portmap_guard) = PortMap::spawn(vec![
+            Mapping::new(Protocol::Tcp, port),
+            Mapping::new(Protocol::Udp, port),
+        ]);
+
+        {
+            let portmap = portmap.clone();
+            let external_addr = self.external_addr.clone();
+            let _ = self.tasks.push(JoinGuard::spawn(move |cancel| async move {
+                tokio::select! {
+                    () = cancel.wait() => {}
+                    () = integrate::update_external_addr(portmap, external_addr) => {}
+                }
+                Ok(())
+            }));
+        }
+
+        self.portmap = Some(portmap);
+        self.portmap_guard = Some(Some(portmap_guard));
+        Ok(())
+    }
 }
 
 impl NetInit {
@@ -485,6 +789,9 @@ impl NetInit {
             TcpSocket::new_v6()
         }?;
         socket.set_reuseport(true)?;
+        if let Some(interface) = bittorrent_base::bind_interface() {
+            g1_tokio::net::bind_device(&socket, interface)?;
+        }
         socket.bind(self.self_endpoint_to_bind)?;
         let tcp_listener = socket.listen(*crate::tcp_listen_backlog())?;
         let self_endpoint = tcp_listener.local_addr()?;
@@ -501,21 +808,33 @@ impl NetInit {
     // DHT
     //
 
-    async fn init_dht(&mut self, manager: Manager) -> Result<Option<Dht>, Error> {
-        self.init_dht_guard(manager).await?;
+    async fn init_dht(
+        &mut self,
+        manager: Manager,
+        external_ip: Option<IpAddr>,
+        external_addr: ExternalAddr,
+    ) -> Result<Option<Dht>, Error> {
+        self.init_dht_guard(manager, external_ip, external_addr)
+            .await?;
         Ok(self.dht.clone())
     }
 
-    async fn init_dht_guard(&mut self, manager: Manager) -> Result<(), Error> {
+    async fn init_dht_guard(
+        &mut self,
+        manager: Manager,
+        external_ip: Option<IpAddr>,
+        external_addr: ExternalAddr,
+    ) -> Result<(), Error> {
         if !self.self_features.dht || self.dht.is_some() {
             return Ok(());
         }
 
         let self_endpoint = self.init_self_endpoint().await?;
 
-        tracing::info!(?self_endpoint, "init dht");
+        tracing::info!(?self_endpoint, ?external_ip, "init dht");
         let (dht, dht_guard) = Dht::spawn(
             self_endpoint,
+            external_ip,
             self.init_once_dht_stream().await?,
             self.init_once_dht_sink().await?,
         );
@@ -532,17 +851,31 @@ impl NetInit {
             }));
         }
 
+        {
+            let requester_recv = dht.subscribe_requester();
+            let _ = self.tasks.push(JoinGuard::spawn(move |cancel| async move {
+                tokio::select! {
+                    () = cancel.wait() => {}
+                    () = integrate::update_external_addr_from_dht(
+                        requester_recv,
+                        external_addr,
+                    ) => {}
+                }
+                Ok(())
+            }));
+        }
+
         self.dht = Some(dht);
         self.dht_guard = Some(dht_guard);
         Ok(())
     }
 
-    async fn init_once_dht_stream(&mut self) -> Result<Fork, Error> {
+    async fn init_once_dht_stream(&mut self) -> Result<DynStream, Error> {
         self.init_udp_stream_and_sink().await?;
         Ok(self.dht_stream.take().unwrap())
     }
 
-    async fn init_once_dht_sink(&mut self) -> Result<Fanin, Error> {
+    async fn init_once_dht_sink(&mut self) -> Result<DynSink, Error> {
         self.init_udp_stream_and_sink().await?;
         Ok(self.dht_sink.take().unwrap())
     }
@@ -553,21 +886,22 @@ impl NetInit {
 
     async fn init_utp_socket(&mut self) -> Result<&UtpSocket, Error> {
         if self.utp_socket.is_none() {
-            self.utp_socket = Some(UtpSocket::new(
+            self.utp_socket = Some(UtpSocket::with_ecn(
                 self.init_udp_socket().await?,
                 self.init_once_utp_stream().await?,
                 self.init_once_utp_sink().await?,
+                UtpConfig::default(),
             ));
         }
         Ok(self.utp_socket.as_ref().unwrap())
     }
 
-    async fn init_once_utp_stream(&mut self) -> Result<DynStream, Error> {
+    async fn init_once_utp_stream(&mut self) -> Result<DynEcnStream, Error> {
         self.init_udp_stream_and_sink().await?;
         Ok(self.utp_stream.take().unwrap())
     }
 
-    async fn init_once_utp_sink(&mut self) -> Result<DynSink, Error> {
+    async fn init_once_utp_sink(&mut self) -> Result<DynEcnSink, Error> {
         self.init_udp_stream_and_sink().await?;
         Ok(self.utp_sink.take().unwrap())
     }
@@ -580,7 +914,12 @@ impl NetInit {
         if self.udp_socket.is_none() {
             let self_endpoint = self.init_self_endpoint().await?;
             // TODO: Do we need to call `set_reuseport(true)` on UDP sockets?
-            self.udp_socket = Some(Arc::new(UdpSocket::bind(self_endpoint).await?));
+            let udp_socket = UdpSocket::bind(self_endpoint).await?;
+            // uTP and DHT share this socket.
+            if let Some(interface) = bittorrent_base::bind_interface() {
+                g1_tokio::net::bind_device(&udp_socket, interface)?;
+            }
+            self.udp_socket = Some(Arc::new(udp_socket));
         }
         Ok(self.udp_socket.clone().unwrap())
     }
@@ -590,7 +929,7 @@ impl NetInit {
             return Ok(());
         }
 
-        let (stream, sink) = udp::UdpSocket::new(self.init_udp_socket().await?).into_split();
+        let (stream, sink) = udp::UdpSocket::with_ecn(self.init_udp_socket().await?)?.into_split();
 
         if self.self_features.dht {
             let (dht_stream, utp_stream, udp_error_stream) = bittorrent_udp::fork(stream);
@@ -603,10 +942,17 @@ impl NetInit {
                 }
             }));
 
-            self.dht_stream = Some(dht_stream);
+            // DHT does not take part in ECN.
+            self.dht_stream = Some(Box::pin(
+                dht_stream.map_ok(|(peer_endpoint, payload, _)| (peer_endpoint, payload)),
+            ));
             self.utp_stream = Some(Box::pin(utp_stream));
 
-            self.dht_sink = Some(dht_sink);
+            self.dht_sink = Some(Box::pin(dht_sink.with(
+                |(peer_endpoint, payload): (SocketAddr, Bytes)| {
+                    future::ready(Ok((peer_endpoint, payload, Ecn::NotEct)))
+                },
+            )));
             self.utp_sink = Some(Box::pin(utp_sink));
         } else {
             self.utp_stream = Some(Box::pin(stream));
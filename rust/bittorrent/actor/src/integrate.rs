@@ -1,22 +1,32 @@
 use bytes::Bytes;
 use futures::stream::TryStreamExt;
 use std::io::Error;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
 use tokio::{
-    sync::broadcast::{error::RecvError, Receiver},
+    sync::{
+        broadcast::{error::RecvError, Receiver},
+        mpsc, watch,
+    },
     time,
 };
 
-use g1_tokio::net::{self, udp::OwnedUdpStream};
+use g1_tokio::net::dns;
 
 use bittorrent_base::InfoHash;
 use bittorrent_dht::Dht;
 use bittorrent_manager::Manager;
 use bittorrent_metainfo::InfoOwner;
 use bittorrent_peer::Recvs;
-use bittorrent_tracker::{Endpoint as TrackerEndpoint, PeerContactInfo, Tracker};
+use bittorrent_portmap::{PortMap, Protocol};
+use bittorrent_tracker::{Endpoint as TrackerEndpoint, PeerContactInfo, Status, Tracker};
 use bittorrent_trackerless::Trackerless;
-use bittorrent_transceiver::Update;
-use bittorrent_udp::Fork;
+use bittorrent_transceiver::{Torrent, Update};
+
+use crate::actors::PeerSource;
+use crate::external::{ExternalAddr, Source};
+use crate::init::Fork;
+use crate::resume;
 
 pub(crate) async fn fetch_info(
     info_hash: InfoHash,
@@ -51,6 +61,18 @@ pub(crate) async fn make_warm_calls(mut update_recv: Receiver<Update>, manager:
     }
 }
 
+// NOTE: This never exits.  You have to abort it.
+pub(crate) async fn save_resume(resume_path: PathBuf, torrent: Torrent) {
+    let mut interval = time::interval(*crate::resume_save_period());
+    interval.tick().await; // Skip the first tick, which completes immediately.
+    loop {
+        interval.tick().await;
+        if let Err(error) = resume::save(&resume_path, torrent.total()).await {
+            tracing::warn!(?resume_path, %error, "save resume data error");
+        }
+    }
+}
+
 // NOTE: This never exits.  You have to abort it.
 pub(crate) async fn recruit_from_dht(dht: Dht, info_hash: InfoHash, manager: Manager) {
     let mut interval = time::interval(*crate::dht_lookup_peers_period());
@@ -63,6 +85,18 @@ pub(crate) async fn recruit_from_dht(dht: Dht, info_hash: InfoHash, manager: Man
     }
 }
 
+pub(crate) async fn add_magnet_peers(peers: Vec<String>, manager: Manager) {
+    for peer in peers {
+        match dns::resolver().lookup_endpoint(&peer).await {
+            Ok(peer_endpoint) => {
+                tracing::info!(?peer_endpoint, source = ?PeerSource::Magnet, "add peer");
+                manager.connect(peer_endpoint, None);
+            }
+            Err(error) => tracing::warn!(peer, %error, "resolve magnet peer error"),
+        }
+    }
+}
+
 pub(crate) async fn update_tracker(mut update_recv: Receiver<Update>, tracker: Tracker) {
     loop {
         match update_recv.recv().await {
@@ -91,7 +125,7 @@ pub(crate) async fn recruit_from_tracker(tracker: Tracker, manager: Manager) {
         let endpoint = match endpoint {
             TrackerEndpoint::SocketAddr(endpoint) => endpoint,
             TrackerEndpoint::DomainName(ref domain_name, port) => {
-                match net::lookup_host_first((domain_name.as_str(), port)).await {
+                match dns::resolver().lookup_host_first(domain_name, port).await {
                     Ok(endpoint) => endpoint,
                     Err(error) => {
                         tracing::warn!(?id, ?endpoint, %error, "peer endpoint resolution error");
@@ -104,11 +138,104 @@ pub(crate) async fn recruit_from_tracker(tracker: Tracker, manager: Manager) {
     }
 }
 
-pub(crate) async fn handle_udp_error(
-    mut udp_error_stream: Fork<OwnedUdpStream>,
-) -> Result<(), Error> {
-    while let Some((peer_endpoint, payload)) = udp_error_stream.try_next().await? {
+pub(crate) async fn update_external_addr_from_tracker(
+    mut status_recv: watch::Receiver<Status>,
+    external_addr: ExternalAddr,
+) {
+    loop {
+        let addr = status_recv.borrow_and_update().external_ip;
+        match addr {
+            Some(addr) => external_addr.submit(Source::Tracker, None, addr),
+            None => external_addr.retract(Source::Tracker, None),
+        }
+        if status_recv.changed().await.is_err() {
+            break;
+        }
+    }
+}
+
+pub(crate) async fn update_external_addr_from_dht(
+    mut requester_recv: Receiver<(SocketAddr, SocketAddr)>,
+    external_addr: ExternalAddr,
+) {
+    loop {
+        match requester_recv.recv().await {
+            Ok((node_endpoint, self_endpoint)) => {
+                external_addr.submit(Source::Dht, Some(node_endpoint.ip()), self_endpoint.ip());
+            }
+            Err(RecvError::Lagged(num_skipped)) => {
+                tracing::debug!(num_skipped, "lag behind on dht requester updates");
+            }
+            Err(RecvError::Closed) => break,
+        }
+    }
+}
+
+pub(crate) async fn update_external_addr_from_peers(
+    mut your_ip_recv: mpsc::Receiver<(IpAddr, IpAddr)>,
+    external_addr: ExternalAddr,
+) {
+    while let Some((peer_ip, your_ip)) = your_ip_recv.recv().await {
+        external_addr.submit(Source::Peer, Some(peer_ip), your_ip);
+    }
+}
+
+pub(crate) async fn expire_external_addr(external_addr: ExternalAddr) {
+    let mut interval = time::interval(*crate::external_addr_expire_period());
+    loop {
+        interval.tick().await;
+        external_addr.expire();
+    }
+}
+
+pub(crate) async fn handle_udp_error(mut udp_error_stream: Fork) -> Result<(), Error> {
+    while let Some((peer_endpoint, payload, _)) = udp_error_stream.try_next().await? {
         tracing::warn!(?peer_endpoint, ?payload, "receive unrecognizable payload");
     }
     Ok(())
 }
+
+pub(crate) async fn update_external_addr(portmap: PortMap, external_addr: ExternalAddr) {
+    let mut external_addr_recv = portmap.subscribe();
+    let mut mappings_recv = portmap.subscribe_mappings();
+    loop {
+        let addr = *external_addr_recv.borrow_and_update();
+        match addr {
+            Some(addr) => external_addr.submit(Source::PortMap, None, addr),
+            None => external_addr.retract(Source::PortMap, None),
+        }
+        let port = mappings_recv
+            .borrow_and_update()
+            .iter()
+            .find(|mapping| mapping.protocol == Protocol::Tcp)
+            .map(|mapping| mapping.external_port);
+        external_addr.set_external_port_v4(port);
+        tokio::select! {
+            result = external_addr_recv.changed() => {
+                if result.is_err() {
+                    break;
+                }
+            }
+            result = mappings_recv.changed() => {
+                if result.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Announces the external port that the gateway maps, or `self_port` if there is no mapping.
+pub(crate) async fn update_tracker_port(
+    mut port_recv: watch::Receiver<Option<u16>>,
+    tracker: Tracker,
+    self_port: u16,
+) {
+    loop {
+        let port = *port_recv.borrow_and_update();
+        tracker.set_port(port.unwrap_or(self_port));
+        if port_recv.changed().await.is_err() {
+            break;
+        }
+    }
+}
@@ -1,8 +1,12 @@
 #![feature(result_flattening)]
 
 mod actors;
+mod discovery;
+mod external;
 mod init;
 mod integrate;
+mod resume;
+mod schedule;
 mod storage;
 
 use std::net::SocketAddr;
@@ -12,7 +16,9 @@ use bytes::Bytes;
 
 use bittorrent_metainfo::{InfoOwner, MetainfoOwner};
 
-pub use crate::actors::Actors;
+pub use crate::actors::{Actors, PeerSource};
+pub use crate::discovery::Discovery;
+pub use crate::external::{ExternalAddr, Source as ExternalAddrSource};
 pub use crate::storage::StorageOpen;
 
 g1_param::define!(self_endpoint_ipv4: Option<SocketAddr> = Some("0.0.0.0:6881".parse().unwrap()));
@@ -20,6 +26,26 @@ g1_param::define!(self_endpoint_ipv6: Option<SocketAddr> = None); // TODO: Enabl
 
 g1_param::define!(tcp_listen_backlog: u32 = 256);
 
+// Port mapping is on by default, which means that we contact the gateway (via NAT-PMP or UPnP IGD)
+// without being asked to.  Turn it off if that is undesirable.
+g1_param::define!(port_mapping_enable: bool = true);
+// How long we wait for the gateway at startup so that our DHT node id conforms to BEP 42.
+g1_param::define!(
+    port_mapping_wait: Duration = Duration::from_secs(3);
+    parse = g1_param::parse::duration;
+);
+
+g1_param::define!(external_addr_quorum: u32 = 4);
+g1_param::define!(your_ip_queue_size: usize = 64);
+g1_param::define!(
+    external_addr_vote_lifetime: Duration = Duration::from_secs(3600);
+    parse = g1_param::parse::duration;
+);
+g1_param::define!(
+    external_addr_expire_period: Duration = Duration::from_secs(60);
+    parse = g1_param::parse::duration;
+);
+
 g1_param::define!(
     fetch_info_timeout: Duration = Duration::from_secs(60);
     parse = g1_param::parse::duration;
@@ -30,6 +56,21 @@ g1_param::define!(
     parse = g1_param::parse::duration;
 );
 
+g1_param::define!(
+    resume_save_period: Duration = Duration::from_secs(60);
+    parse = g1_param::parse::duration;
+);
+
+// Rate limits of piece payload in bytes per second (unlimited if unset).
+g1_param::define!(upload_rate_limit: Option<u64> = None);
+g1_param::define!(download_rate_limit: Option<u64> = None);
+// Weekly profiles that override the rate limits above; the first matching profile wins.
+g1_param::define!(bandwidth_schedule: Vec<schedule::BandwidthProfile> = Vec::new());
+g1_param::define!(
+    bandwidth_schedule_period: Duration = Duration::from_secs(60);
+    parse = g1_param::parse::duration;
+);
+
 // Useful for testing.
 g1_param::define!(peer_endpoints: Vec<SocketAddr> = Vec::new());
 
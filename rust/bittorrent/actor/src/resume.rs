@@ -0,0 +1,129 @@
+//! Resume Data
+//!
+//! At the moment, resume data only carries the byte counters of a torrent, so that they are
+//! accumulated across sessions.  It is stored as a Bencode dictionary next to the torrent data.
+//!
+//! For private torrents, we also store the peer id that we announce to the tracker next to the
+//! torrent data, because private trackers tend to track users by their peer id across sessions.
+
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use bittorrent_base::PeerId;
+use bittorrent_bencode::serde as serde_bencode;
+use bittorrent_transceiver::Counters;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+struct ResumeData {
+    uploaded: u64,
+    downloaded: u64,
+    wasted: u64,
+}
+
+pub(crate) fn new_path(torrent_dir: &Path, name: &str) -> PathBuf {
+    torrent_dir.join(format!("{name}.resume"))
+}
+
+/// Loads the counters from resume data, or returns zeros if resume data does not exist.
+pub(crate) async fn load(path: &Path) -> Result<Counters, Error> {
+    let buffer = match fs::read(path).await {
+        Ok(buffer) => buffer,
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(Counters::default()),
+        Err(error) => return Err(error),
+    };
+    let data: ResumeData = serde_bencode::from_bytes(&buffer).map_err(Error::other)?;
+    Ok(data.into())
+}
+
+pub(crate) async fn save(path: &Path, counters: Counters) -> Result<(), Error> {
+    let buffer = serde_bencode::to_bytes(&ResumeData::from(counters)).map_err(Error::other)?;
+    // Write to a temporary file first so that a crash does not leave behind a truncated file.
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, &buffer).await?;
+    fs::rename(&tmp_path, path).await
+}
+
+pub(crate) fn new_peer_id_path(torrent_dir: &Path, name: &str) -> PathBuf {
+    torrent_dir.join(format!("{name}.peer_id"))
+}
+
+/// Loads the peer id, or generates and saves a new one if it does not exist.
+pub(crate) async fn load_peer_id(path: &Path) -> Result<PeerId, Error> {
+    match fs::read(path).await {
+        Ok(buffer) => PeerId::try_from(buffer.as_slice()).map_err(Error::other),
+        Err(error) if error.kind() == ErrorKind::NotFound => {
+            let peer_id = PeerId::generate();
+            let tmp_path = path.with_extension("tmp");
+            fs::write(&tmp_path, peer_id.as_array()).await?;
+            fs::rename(&tmp_path, path).await?;
+            Ok(peer_id)
+        }
+        Err(error) => Err(error),
+    }
+}
+
+impl From<ResumeData> for Counters {
+    fn from(data: ResumeData) -> Self {
+        Self {
+            send: data.uploaded,
+            recv: data.downloaded,
+            wasted: data.wasted,
+        }
+    }
+}
+
+impl From<Counters> for ResumeData {
+    fn from(counters: Counters) -> Self {
+        Self {
+            uploaded: counters.send,
+            downloaded: counters.recv,
+            wasted: counters.wasted,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn load_and_save() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = new_path(tempdir.path(), "foo");
+        assert_eq!(path, tempdir.path().join("foo.resume"));
+
+        assert_eq!(load(&path).await.unwrap(), Counters::default());
+
+        let counters = Counters {
+            send: 1,
+            recv: 2,
+            wasted: 3,
+        };
+        save(&path, counters).await.unwrap();
+        assert_eq!(
+            fs::read(&path).await.unwrap(),
+            b"d10:downloadedi2e8:uploadedi1e6:wastedi3ee",
+        );
+        assert_eq!(load(&path).await.unwrap(), counters);
+
+        fs::write(&path, b"spam").await.unwrap();
+        assert!(load(&path).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn peer_id() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = new_peer_id_path(tempdir.path(), "foo");
+        assert_eq!(path, tempdir.path().join("foo.peer_id"));
+
+        let peer_id = load_peer_id(&path).await.unwrap();
+        assert_eq!(fs::read(&path).await.unwrap(), peer_id.as_array());
+        assert_eq!(load_peer_id(&path).await.unwrap(), peer_id);
+
+        fs::write(&path, b"spam").await.unwrap();
+        assert!(load_peer_id(&path).await.is_err());
+    }
+}
@@ -0,0 +1,215 @@
+//! Bandwidth Schedule
+//!
+//! An operator may switch the rate limits by the time of the week, e.g., unlimited at night and
+//! capped during work hours.  Times are in the local time zone.
+
+use chrono::{Datelike, Local, NaiveDateTime, NaiveTime, Weekday};
+use serde::de::{Deserializer, Error as _};
+use serde::Deserialize;
+use tokio::time;
+
+use bittorrent_socket::RateLimits;
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct BandwidthProfile {
+    /// Days of the week on which the profile starts; empty means every day.
+    #[serde(default, deserialize_with = "de_weekdays")]
+    days: Vec<Weekday>,
+    /// `HH:MM`.  If `end` is not after `start`, the profile spans midnight.
+    #[serde(deserialize_with = "de_time")]
+    start: NaiveTime,
+    #[serde(deserialize_with = "de_time")]
+    end: NaiveTime,
+    /// Bytes per second; `None` means unlimited.
+    #[serde(default)]
+    upload: Option<u64>,
+    #[serde(default)]
+    download: Option<u64>,
+}
+
+impl BandwidthProfile {
+    fn matches(&self, now: NaiveDateTime) -> bool {
+        let starts_on = |day| self.days.is_empty() || self.days.contains(&day);
+        let day = now.weekday();
+        let time = now.time();
+        if self.start < self.end {
+            starts_on(day) && self.start <= time && time < self.end
+        } else {
+            (starts_on(day) && self.start <= time) || (starts_on(day.pred()) && time < self.end)
+        }
+    }
+}
+
+fn default_rate_limits() -> RateLimits {
+    RateLimits {
+        upload: *crate::upload_rate_limit(),
+        download: *crate::download_rate_limit(),
+    }
+}
+
+/// Returns the rate limits of the first matching profile, or the default rate limits.
+fn rate_limits(
+    schedule: &[BandwidthProfile],
+    default: RateLimits,
+    now: NaiveDateTime,
+) -> RateLimits {
+    schedule
+        .iter()
+        .find(|profile| profile.matches(now))
+        .map_or(default, |profile| RateLimits {
+            upload: profile.upload,
+            download: profile.download,
+        })
+}
+
+/// Applies the default rate limits and returns true if there is no schedule to run.
+pub(crate) fn init() -> bool {
+    if crate::bandwidth_schedule().is_empty() {
+        let limits = default_rate_limits();
+        tracing::info!(?limits, "apply rate limits");
+        bittorrent_socket::set_rate_limits(limits);
+        true
+    } else {
+        false
+    }
+}
+
+pub(crate) async fn run() {
+    let schedule = crate::bandwidth_schedule();
+    let default = default_rate_limits();
+    let mut limits = None;
+    let mut interval = time::interval(*crate::bandwidth_schedule_period());
+    loop {
+        interval.tick().await;
+        let new_limits = rate_limits(schedule, default, Local::now().naive_local());
+        if limits != Some(new_limits) {
+            tracing::info!(limits = ?new_limits, "apply rate limits");
+            bittorrent_socket::set_rate_limits(new_limits);
+            limits = Some(new_limits);
+        }
+    }
+}
+
+fn de_weekdays<'de, D>(deserializer: D) -> Result<Vec<Weekday>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|day| {
+            day.parse()
+                .map_err(|_| D::Error::custom(format!("invalid weekday: {day}")))
+        })
+        .collect()
+}
+
+fn de_time<'de, D>(deserializer: D) -> Result<NaiveTime, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let time = String::deserialize(deserializer)?;
+    NaiveTime::parse_from_str(&time, "%H:%M")
+        .map_err(|_| D::Error::custom(format!("invalid time of day: {time}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use serde::de::value::{Error, StrDeserializer};
+
+    use super::*;
+
+    fn t(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    // 2024-01-01 is a Monday.
+    fn dt(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, day)
+            .unwrap()
+            .and_time(t(hour, minute))
+    }
+
+    fn profile(
+        days: &[Weekday],
+        start: NaiveTime,
+        end: NaiveTime,
+        upload: u64,
+    ) -> BandwidthProfile {
+        BandwidthProfile {
+            days: days.to_vec(),
+            start,
+            end,
+            upload: Some(upload),
+            download: None,
+        }
+    }
+
+    #[test]
+    fn matches() {
+        let work_hours = profile(&[Weekday::Mon, Weekday::Fri], t(9, 0), t(17, 0), 1);
+        assert_eq!(work_hours.matches(dt(1, 8, 59)), false);
+        assert_eq!(work_hours.matches(dt(1, 9, 0)), true);
+        assert_eq!(work_hours.matches(dt(1, 16, 59)), true);
+        assert_eq!(work_hours.matches(dt(1, 17, 0)), false);
+        assert_eq!(work_hours.matches(dt(2, 12, 0)), false);
+        assert_eq!(work_hours.matches(dt(5, 12, 0)), true);
+
+        let night = profile(&[Weekday::Sun], t(22, 0), t(6, 0), 1);
+        assert_eq!(night.matches(dt(7, 21, 59)), false);
+        assert_eq!(night.matches(dt(7, 22, 0)), true);
+        assert_eq!(night.matches(dt(8, 5, 59)), true);
+        assert_eq!(night.matches(dt(8, 6, 0)), false);
+        assert_eq!(night.matches(dt(1, 5, 59)), true);
+        assert_eq!(night.matches(dt(1, 22, 0)), false);
+
+        let every_day = profile(&[], t(0, 0), t(0, 0), 1);
+        for day in 1..=7 {
+            assert_eq!(every_day.matches(dt(day, 0, 0)), true);
+            assert_eq!(every_day.matches(dt(day, 12, 0)), true);
+        }
+    }
+
+    #[test]
+    fn test_rate_limits() {
+        let schedule = [
+            profile(&[], t(9, 0), t(17, 0), 1),
+            profile(&[], t(12, 0), t(13, 0), 2),
+            profile(&[], t(0, 0), t(6, 0), 3),
+        ];
+        let default = RateLimits {
+            upload: None,
+            download: Some(100),
+        };
+        let limits = |upload| RateLimits {
+            upload,
+            download: None,
+        };
+        assert_eq!(rate_limits(&schedule, default, dt(1, 8, 0)), default);
+        assert_eq!(
+            rate_limits(&schedule, default, dt(1, 9, 0)),
+            limits(Some(1))
+        );
+        // The first matching profile wins.
+        assert_eq!(
+            rate_limits(&schedule, default, dt(1, 12, 0)),
+            limits(Some(1))
+        );
+        assert_eq!(
+            rate_limits(&schedule, default, dt(1, 0, 0)),
+            limits(Some(3))
+        );
+        assert_eq!(rate_limits(&[], default, dt(1, 0, 0)), default);
+    }
+
+    #[test]
+    fn deserialize() {
+        assert_eq!(
+            de_time(StrDeserializer::<Error>::new("22:30")),
+            Ok(t(22, 30))
+        );
+        assert!(de_time(StrDeserializer::<Error>::new("22:30:00")).is_err());
+        assert!(de_time(StrDeserializer::<Error>::new("24:00")).is_err());
+    }
+}
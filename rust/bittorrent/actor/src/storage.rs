@@ -1,5 +1,5 @@
 use std::io::Error;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use bittorrent_base::Dimension;
 use bittorrent_metainfo::Info;
@@ -21,4 +21,10 @@ impl StorageOpen {
             }
         })
     }
+
+    pub(crate) fn torrent_dir(&self) -> &Path {
+        match self {
+            Self::File(torrent_dir) | Self::Single(torrent_dir) => torrent_dir,
+        }
+    }
 }
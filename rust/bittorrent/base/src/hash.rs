@@ -0,0 +1,63 @@
+//! Info Hash Computation
+//!
+//! An info hash is computed from the info dictionary exactly as it appears in the metainfo, not
+//! from a re-encoding of it.  A torrent in the wild may not be canonically encoded (e.g., its
+//! dictionary keys are not sorted), and re-encoding it would produce a different info hash.
+
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+
+use crate::{AnyInfoHash, InfoHash, INFO_HASH_V2_SIZE};
+
+impl InfoHash {
+    /// Computes the v1 info hash, which is the SHA-1 of the raw info dictionary.
+    pub fn v1(raw_info: &[u8]) -> Self {
+        Self::new(Sha1::digest(raw_info).into())
+    }
+
+    /// Computes the v2 info hash, which is the SHA-256 of the raw info dictionary (BEP 52).
+    pub fn v2(raw_info: &[u8]) -> [u8; INFO_HASH_V2_SIZE] {
+        Sha256::digest(raw_info).into()
+    }
+
+    /// Computes the truncated v2 info hash; see `InfoHash::from_v2`.
+    pub fn v2_truncated(raw_info: &[u8]) -> Self {
+        Self::from_v2(&Self::v2(raw_info))
+    }
+}
+
+impl AnyInfoHash {
+    pub fn v1(raw_info: &[u8]) -> Self {
+        Self::V1(InfoHash::v1(raw_info))
+    }
+
+    pub fn v2(raw_info: &[u8]) -> Self {
+        Self::V2(InfoHash::v2(raw_info))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use super::*;
+
+    #[test]
+    fn info_hash() {
+        assert_eq!(
+            InfoHash::v1(b""),
+            InfoHash::new(hex!("da39a3ee5e6b4b0d3255bfef95601890afd80709")),
+        );
+        assert_eq!(
+            InfoHash::v2(b""),
+            hex!("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"),
+        );
+        assert_eq!(
+            InfoHash::v2_truncated(b""),
+            InfoHash::new(hex!("e3b0c44298fc1c149afbf4c8996fb92427ae41e4")),
+        );
+
+        assert_eq!(AnyInfoHash::v1(b""), AnyInfoHash::V1(InfoHash::v1(b"")));
+        assert_eq!(AnyInfoHash::v2(b""), AnyInfoHash::V2(InfoHash::v2(b"")));
+    }
+}
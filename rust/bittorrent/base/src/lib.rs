@@ -5,8 +5,11 @@ pub mod compact;
 #[cfg(feature = "parse")]
 pub mod parse;
 
+#[cfg(feature = "hash")]
+mod hash;
 #[cfg(feature = "param")]
 mod param;
+mod peer_id;
 
 use std::array::TryFromSliceError;
 use std::borrow::Borrow;
@@ -23,10 +26,13 @@ use g1_base::fmt::{DebugExt, EscapeAscii, Hex};
 pub const PROTOCOL_ID: &[u8] = b"BitTorrent protocol";
 
 pub const INFO_HASH_SIZE: usize = 20;
+pub const INFO_HASH_V2_SIZE: usize = 32; // BEP 52.
 pub const PIECE_HASH_SIZE: usize = 20;
 
 pub const PEER_ID_SIZE: usize = 20;
 
+pub use crate::peer_id::{Client, CLIENT_CODE};
+
 pub const NODE_ID_SIZE: usize = 20; // BEP 5.
 
 // These parameters are not declared as `pub` because they should only be accessed via
@@ -52,6 +58,16 @@ g1_param::define!(pub send_buffer_capacity: usize = 65536);
 #[cfg(feature = "param")]
 g1_param::define!(pub payload_size_limit: usize = 65536);
 
+// Network interface that peer and DHT traffic is bound to (Linux only).  To bind all traffic,
+// including tracker traffic, to a local address instead, set the self endpoints to that address.
+#[cfg(feature = "param")]
+g1_param::define!(pub bind_interface: Option<String> = None);
+
+// SOCKS5 proxy that outgoing TCP peer connections and UDP tracker announces go through.  Since
+// uTP cannot go through the proxy, we do not connect to peers over uTP when it is set.
+#[cfg(feature = "param")]
+g1_param::define!(pub socks5_proxy: Option<std::net::SocketAddr> = None);
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub struct Features {
     pub dht: bool,
@@ -74,10 +90,17 @@ impl Features {
     }
 }
 
-// TODO: Add other Magnet URI parameters.
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
 pub struct MagnetUri {
     pub info_hashes: Vec<InfoHash>,
+    // BEP 52 SHA-256 info hashes, which are encoded as multihashes (`btmh`).
+    pub info_hashes_v2: Vec<[u8; INFO_HASH_V2_SIZE]>,
+    pub display_name: Option<String>,
+    pub trackers: Vec<String>,
+    // BEP 9 Peer addresses, which are either `hostname:port`, `ipv4:port`, or `[ipv6]:port`.
+    pub peers: Vec<String>,
+    // BEP 19 WebSeed - HTTP/FTP Seeding (GetRight style)
+    pub web_seeds: Vec<String>,
 }
 
 #[derive(Clone, DebugExt, Eq, Hash, PartialEq)]
@@ -95,6 +118,12 @@ impl InfoHash {
     pub fn new(info_hash: [u8; INFO_HASH_SIZE]) -> Self {
         Self(Arc::new(info_hash))
     }
+
+    /// Truncates a v2 info hash to 20 bytes, which is what a v2 swarm uses where a v1 info hash is
+    /// expected, e.g., in the peer handshake, DHT, and tracker requests (BEP 52).
+    pub fn from_v2(info_hash_v2: &[u8; INFO_HASH_V2_SIZE]) -> Self {
+        Self::new(info_hash_v2[..INFO_HASH_SIZE].try_into().unwrap())
+    }
 }
 
 impl AsRef<[u8]> for InfoHash {
@@ -109,6 +138,59 @@ impl Borrow<[u8]> for InfoHash {
     }
 }
 
+/// Info hash of either version.
+///
+/// A hybrid torrent has one of each, both computed from the same info dictionary.
+#[derive(Clone, Eq, Hash, PartialEq)]
+pub enum AnyInfoHash {
+    V1(InfoHash),
+    V2([u8; INFO_HASH_V2_SIZE]),
+}
+
+impl AnyInfoHash {
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Self::V1(info_hash) => info_hash.as_ref(),
+            Self::V2(info_hash) => info_hash.as_slice(),
+        }
+    }
+
+    /// Returns the 20-byte form of the info hash, truncating a v2 info hash.
+    pub fn to_info_hash(&self) -> InfoHash {
+        match self {
+            Self::V1(info_hash) => info_hash.clone(),
+            Self::V2(info_hash) => InfoHash::from_v2(info_hash),
+        }
+    }
+}
+
+impl fmt::Debug for AnyInfoHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::V1(info_hash) => f.debug_tuple("V1").field(info_hash).finish(),
+            Self::V2(info_hash) => f.debug_tuple("V2").field(&Hex(info_hash)).finish(),
+        }
+    }
+}
+
+impl From<InfoHash> for AnyInfoHash {
+    fn from(info_hash: InfoHash) -> Self {
+        Self::V1(info_hash)
+    }
+}
+
+impl From<[u8; INFO_HASH_V2_SIZE]> for AnyInfoHash {
+    fn from(info_hash: [u8; INFO_HASH_V2_SIZE]) -> Self {
+        Self::V2(info_hash)
+    }
+}
+
+impl AsRef<[u8]> for AnyInfoHash {
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
 #[derive(Clone, DebugExt, Eq, Hash, PartialEq)]
 #[cfg_attr(feature = "param", derive(Deserialize))]
 pub struct PeerId(
@@ -317,8 +399,32 @@ impl Dimension {
 
 #[cfg(test)]
 mod tests {
+    use hex_literal::hex;
+
     use super::*;
 
+    #[test]
+    fn any_info_hash() {
+        let v1 = InfoHash::new(hex!("0123456789abcdef0123456789abcdef01234567"));
+        let v2 = hex!("00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff");
+
+        let info_hash = AnyInfoHash::from(v1.clone());
+        assert_eq!(info_hash.as_bytes(), v1.as_ref());
+        assert_eq!(info_hash.to_info_hash(), v1);
+
+        let info_hash = AnyInfoHash::from(v2);
+        assert_eq!(info_hash.as_bytes(), v2.as_slice());
+        assert_eq!(
+            info_hash.to_info_hash(),
+            InfoHash::new(hex!("00112233445566778899aabbccddeeff00112233")),
+        );
+        assert_eq!(info_hash.to_info_hash(), InfoHash::from_v2(&v2));
+        assert_eq!(
+            format!("{:?}", info_hash),
+            "V2(00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff)",
+        );
+    }
+
     #[test]
     fn piece_index_to_scalar() {
         assert_eq!(PieceIndex::from(0).to_scalar(1), 0);
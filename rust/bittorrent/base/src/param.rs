@@ -3,7 +3,7 @@ use std::sync::Arc;
 use rand::prelude::*;
 use serde::{de, Deserialize, Deserializer};
 
-use crate::{PeerId, PEER_ID_SIZE};
+use crate::{peer_id, PeerId, PEER_ID_SIZE};
 
 impl PeerId {
     pub(crate) fn deserialize<'de, D>(deserializer: D) -> Result<Arc<[u8; PEER_ID_SIZE]>, D::Error>
@@ -16,16 +16,18 @@ impl PeerId {
         })?))
     }
 
-    pub(crate) fn generate() -> Self {
+    /// Generates an Azureus-style (BEP 20) peer id with our client code and version.
+    pub fn generate() -> Self {
         Self::new(Self::random())
     }
 
-    // TODO: Comply with BEP 20.
     fn random() -> [u8; PEER_ID_SIZE] {
         const CHARSET: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz.-";
         let mut peer_id = [0u8; PEER_ID_SIZE];
+        let prefix = peer_id::prefix();
+        peer_id[..prefix.len()].copy_from_slice(&prefix);
         let mut rng = thread_rng();
-        peer_id.fill_with(|| *CHARSET.choose(&mut rng).unwrap());
+        peer_id[prefix.len()..].fill_with(|| *CHARSET.choose(&mut rng).unwrap());
         peer_id
     }
 }
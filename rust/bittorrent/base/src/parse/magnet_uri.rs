@@ -1,7 +1,12 @@
+use std::borrow::Cow;
+
 use lazy_regex::regex;
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC};
 use snafu::prelude::*;
 
-use crate::InfoHash;
+use g1_base::str::Hex;
+
+use crate::{InfoHash, INFO_HASH_V2_SIZE};
 
 #[derive(Clone, Debug, Eq, PartialEq, Snafu)]
 pub enum Error {
@@ -11,10 +16,31 @@ pub enum Error {
     InvalidMagnetUri,
     #[snafu(display("invalid urn: {urn:?}"))]
     InvalidUrn { urn: String },
+    #[snafu(display("invalid utf8 value: {value:?}"))]
+    InvalidUtf8Value { value: String },
     #[snafu(display("unsupported protocol: {protocol:?}"))]
     UnsupportedProtocol { protocol: String },
 }
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(super) enum Protocol {
+    Btih,
+    // BEP 52
+    Btmh,
+}
+
+/// Characters that we percent-encode in parameter values.
+pub(super) const VALUE_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~')
+    .remove(b':')
+    .remove(b'/');
+
+// Multihash prefix of SHA-256: function code 0x12 and digest size 0x20.
+const SHA256_MULTIHASH_PREFIX: &str = "1220";
+
 pub(super) fn parse(uri: &str) -> Result<impl Iterator<Item = (&str, &str)>, Error> {
     let query_regex = regex!(
         r"(?ix-u)
@@ -72,12 +98,14 @@ pub(super) fn parse_urn(urn: &str) -> Result<(&str, &str), Error> {
     ))
 }
 
-pub(super) fn parse_protocol(protocol: &str) -> Result<(), Error> {
+pub(super) fn parse_protocol(protocol: &str) -> Result<Protocol, Error> {
     if ["btih", "sha1"]
         .into_iter()
         .any(|supported| supported.eq_ignore_ascii_case(protocol))
     {
-        Ok(())
+        Ok(Protocol::Btih)
+    } else if "btmh".eq_ignore_ascii_case(protocol) {
+        Ok(Protocol::Btmh)
     } else {
         Err(Error::UnsupportedProtocol {
             protocol: protocol.to_string(),
@@ -98,6 +126,34 @@ pub(super) fn parse_info_hash(info_hash: &str) -> Result<InfoHash, Error> {
     }
 }
 
+pub(super) fn parse_info_hash_v2(multihash: &str) -> Result<[u8; INFO_HASH_V2_SIZE], Error> {
+    multihash
+        .get(..SHA256_MULTIHASH_PREFIX.len())
+        .filter(|prefix| *prefix == SHA256_MULTIHASH_PREFIX)
+        .and_then(|_| Hex::try_from(&multihash[SHA256_MULTIHASH_PREFIX.len()..]).ok())
+        .map(Hex::into_inner)
+        .context(InvalidInfoHashSnafu {
+            info_hash: multihash,
+        })
+}
+
+pub(super) fn encode_info_hash_v2(info_hash: &[u8; INFO_HASH_V2_SIZE]) -> String {
+    let mut multihash = SHA256_MULTIHASH_PREFIX.to_string();
+    info_hash
+        .iter()
+        .for_each(|x| multihash.push_str(&format!("{x:02x}")));
+    multihash
+}
+
+pub(super) fn decode_value(value: &str) -> Result<String, Error> {
+    percent_encoding::percent_decode_str(value)
+        .decode_utf8()
+        .map(Cow::into_owned)
+        .map_err(|_| Error::InvalidUtf8Value {
+            value: value.to_string(),
+        })
+}
+
 fn decode_base32<const N: usize>(input: &str) -> [u8; N] {
     assert!(input.len() * 5 / 8 <= N);
     let mut output = [0; N];
@@ -211,18 +267,17 @@ mod tests {
             );
         }
 
-        assert_eq!(parse_protocol("btih"), Ok(()));
-        assert_eq!(parse_protocol("bTiH"), Ok(()));
-        assert_eq!(parse_protocol("sha1"), Ok(()));
-        assert_eq!(parse_protocol("ShA1"), Ok(()));
+        assert_eq!(parse_protocol("btih"), Ok(Protocol::Btih));
+        assert_eq!(parse_protocol("bTiH"), Ok(Protocol::Btih));
+        assert_eq!(parse_protocol("sha1"), Ok(Protocol::Btih));
+        assert_eq!(parse_protocol("ShA1"), Ok(Protocol::Btih));
+        assert_eq!(parse_protocol("btmh"), Ok(Protocol::Btmh));
+        assert_eq!(parse_protocol("BTMH"), Ok(Protocol::Btmh));
 
         test_err("");
         test_err(" btih");
         test_err("sha1 ");
         test_err("foo");
-
-        // TODO: We do not support BitTorrent v2 (btmh) at the moment.
-        test_err("btmh");
     }
 
     #[test]
@@ -263,6 +318,42 @@ mod tests {
         test_err("ABCDEFGHIJKLMNOPQRSTUVWXYZ23456");
     }
 
+    #[test]
+    fn test_parse_info_hash_v2() {
+        fn test_err(multihash: &str) {
+            assert_eq!(
+                parse_info_hash_v2(multihash),
+                Err(Error::InvalidInfoHash {
+                    info_hash: multihash.to_string(),
+                }),
+            );
+        }
+
+        let multihash = format!("1220{}", "0123456789abcdef".repeat(4));
+        let info_hash = hex!("0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef");
+        assert_eq!(parse_info_hash_v2(&multihash), Ok(info_hash));
+        assert_eq!(encode_info_hash_v2(&info_hash), multihash);
+
+        test_err("");
+        test_err("1220");
+        test_err(&format!("1114{}", "00".repeat(20)));
+        test_err(&format!("1220{}", "00".repeat(31)));
+        test_err(&format!("1220{}", "zz".repeat(32)));
+    }
+
+    #[test]
+    fn test_decode_value() {
+        assert_eq!(decode_value(""), Ok("".to_string()));
+        assert_eq!(decode_value("foo+bar"), Ok("foo+bar".to_string()));
+        assert_eq!(decode_value("foo%20bar%2F"), Ok("foo bar/".to_string()));
+        assert_eq!(
+            decode_value("%ff"),
+            Err(Error::InvalidUtf8Value {
+                value: "%ff".to_string(),
+            }),
+        );
+    }
+
     #[test]
     fn test_decode_base32() {
         fn to_array<const N: usize>(string: &str) -> [u8; N] {
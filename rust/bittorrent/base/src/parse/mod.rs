@@ -1,7 +1,9 @@
 pub mod magnet_uri;
 
+use std::fmt;
 use std::str::FromStr;
 
+use percent_encoding::utf8_percent_encode;
 use snafu::prelude::*;
 
 use g1_base::str::Hex;
@@ -20,21 +22,79 @@ impl FromStr for MagnetUri {
     type Err = ParseMagnetUriError;
 
     fn from_str(uri: &str) -> Result<Self, Self::Err> {
-        let mut info_hashes = Vec::new();
+        fn push_new<T: PartialEq>(vec: &mut Vec<T>, x: T) {
+            if !vec.contains(&x) {
+                vec.push(x);
+            }
+        }
+
+        let mut this = Self::default();
         let result: Result<_, _> = try {
             for (name, value) in magnet_uri::parse(uri)? {
                 if magnet_uri::is_exact_topic(name).is_some() {
                     let (protocol, info_hash) = magnet_uri::parse_urn(value)?;
-                    magnet_uri::parse_protocol(protocol)?;
-                    let info_hash = magnet_uri::parse_info_hash(info_hash)?;
-                    if !info_hashes.contains(&info_hash) {
-                        info_hashes.push(info_hash);
+                    match magnet_uri::parse_protocol(protocol)? {
+                        magnet_uri::Protocol::Btih => push_new(
+                            &mut this.info_hashes,
+                            magnet_uri::parse_info_hash(info_hash)?,
+                        ),
+                        magnet_uri::Protocol::Btmh => push_new(
+                            &mut this.info_hashes_v2,
+                            magnet_uri::parse_info_hash_v2(info_hash)?,
+                        ),
                     }
+                } else if name.eq_ignore_ascii_case("dn") {
+                    this.display_name = Some(magnet_uri::decode_value(value)?);
+                } else if name.eq_ignore_ascii_case("tr") {
+                    push_new(&mut this.trackers, magnet_uri::decode_value(value)?);
+                } else if name.eq_ignore_ascii_case("x.pe") {
+                    push_new(&mut this.peers, magnet_uri::decode_value(value)?);
+                } else if name.eq_ignore_ascii_case("ws") {
+                    push_new(&mut this.web_seeds, magnet_uri::decode_value(value)?);
                 }
             }
         };
         result.context(ParseMagnetUriSnafu { uri })?;
-        Ok(Self { info_hashes })
+        Ok(this)
+    }
+}
+
+impl fmt::Display for MagnetUri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut separator = '?';
+        let mut write_param = |f: &mut fmt::Formatter<'_>, name: &str, value: &str| {
+            let value = utf8_percent_encode(value, magnet_uri::VALUE_ENCODE_SET);
+            let result = write!(f, "{separator}{name}={value}");
+            separator = '&';
+            result
+        };
+
+        f.write_str("magnet:")?;
+        for info_hash in &self.info_hashes {
+            let info_hash: String = info_hash
+                .as_ref()
+                .iter()
+                .map(|x| format!("{x:02x}"))
+                .collect();
+            write_param(f, "xt", &format!("urn:btih:{info_hash}"))?;
+        }
+        for info_hash in &self.info_hashes_v2 {
+            let multihash = magnet_uri::encode_info_hash_v2(info_hash);
+            write_param(f, "xt", &format!("urn:btmh:{multihash}"))?;
+        }
+        if let Some(display_name) = &self.display_name {
+            write_param(f, "dn", display_name)?;
+        }
+        for tracker in &self.trackers {
+            write_param(f, "tr", tracker)?;
+        }
+        for peer in &self.peers {
+            write_param(f, "x.pe", peer)?;
+        }
+        for web_seed in &self.web_seeds {
+            write_param(f, "ws", web_seed)?;
+        }
+        Ok(())
     }
 }
 
@@ -77,6 +137,7 @@ mod tests {
             MagnetUri::from_str("magnet:"),
             Ok(MagnetUri {
                 info_hashes: vec![],
+                ..Default::default()
             }),
         );
         assert_eq!(
@@ -87,6 +148,7 @@ mod tests {
                 info_hashes: vec![InfoHash::new(hex!(
                     "0123456789012345678901234567890123456789"
                 ))],
+                ..Default::default()
             }),
         );
         assert_eq!(
@@ -98,6 +160,7 @@ mod tests {
                     InfoHash::new(hex!("0123456789012345678901234567890123456789")),
                     InfoHash::new(hex!("00 44 32 14 c7 42 54 b6 35 cf 84 65 3a 56 d7 c6 75 be 77 df")),
                 ],
+                ..Default::default()
             }),
         );
 
@@ -109,9 +172,21 @@ mod tests {
             },
         );
         test_err(
-            "magnet:?xt=urn:btmh:0123456789012345678901234567890123456789",
+            "magnet:?xt=urn:ed2k:0123456789012345678901234567890123456789",
             magnet_uri::Error::UnsupportedProtocol {
-                protocol: "btmh".to_string(),
+                protocol: "ed2k".to_string(),
+            },
+        );
+        test_err(
+            "magnet:?xt=urn:btmh:0123456789012345678901234567890123456789",
+            magnet_uri::Error::InvalidInfoHash {
+                info_hash: "0123456789012345678901234567890123456789".to_string(),
+            },
+        );
+        test_err(
+            "magnet:?dn=%ff",
+            magnet_uri::Error::InvalidUtf8Value {
+                value: "%ff".to_string(),
             },
         );
         test_err(
@@ -121,4 +196,35 @@ mod tests {
             },
         );
     }
+
+    #[test]
+    fn magnet_uri_full() {
+        let uri = format!(
+            concat!(
+                "magnet:?xt=urn:btih:{}&xt=urn:btmh:1220{}&dn=foo%20bar",
+                "&tr=udp://tracker:8000&tr={}",
+                "&x.pe=127.0.0.1:6881&x.pe=%5B::1%5D:6881&ws=http://seed/foo",
+            ),
+            "01".repeat(20),
+            "02".repeat(32),
+            "http://tracker/announce%3Fkey%3D1",
+        );
+        let expect = MagnetUri {
+            info_hashes: vec![InfoHash::new([0x01; 20])],
+            info_hashes_v2: vec![[0x02; 32]],
+            display_name: Some("foo bar".to_string()),
+            trackers: vec![
+                "udp://tracker:8000".to_string(),
+                "http://tracker/announce?key=1".to_string(),
+            ],
+            peers: vec!["127.0.0.1:6881".to_string(), "[::1]:6881".to_string()],
+            web_seeds: vec!["http://seed/foo".to_string()],
+        };
+        let magnet_uri = MagnetUri::from_str(&uri).unwrap();
+        assert_eq!(magnet_uri, expect);
+        assert_eq!(magnet_uri.to_string(), uri);
+        assert_eq!(MagnetUri::from_str(&magnet_uri.to_string()), Ok(expect));
+
+        assert_eq!(MagnetUri::default().to_string(), "magnet:");
+    }
 }
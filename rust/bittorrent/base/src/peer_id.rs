@@ -0,0 +1,149 @@
+//! BEP 20 Peer Id Conventions
+//!
+//! Parsing peer ids is best-effort; BEP 20 lists several styles, and clients do not always follow
+//! them.  We support the two most common styles:
+//!
+//! * Azureus style: `-XXVVVV-`, followed by random bytes.
+//! * Mainline style: `XM-m-p--` (or `XM-mm-p-`), followed by random bytes.
+
+use crate::{PeerId, PEER_ID_SIZE};
+
+/// Our Azureus-style client code.
+pub const CLIENT_CODE: &[u8; 2] = b"G1";
+
+/// Client name and version of a peer.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Client {
+    pub name: String,
+    pub version: String,
+}
+
+// TODO: Add more clients as we encounter them.
+const AZUREUS_CLIENTS: &[(&[u8; 2], &str)] = &[
+    (CLIENT_CODE, "g1"),
+    (b"AZ", "Vuze"),
+    (b"BC", "BitComet"),
+    (b"BI", "BiglyBT"),
+    (b"DE", "Deluge"),
+    (b"KT", "KTorrent"),
+    (b"LT", "libtorrent (Rasterbar)"),
+    (b"TR", "Transmission"),
+    (b"UT", "uTorrent"),
+    (b"UM", "uTorrent for Mac"),
+    (b"lt", "libtorrent (Rakshasa)"),
+    (b"qB", "qBittorrent"),
+];
+
+const MAINLINE_CLIENTS: &[(u8, &str)] = &[(b'M', "Mainline"), (b'Q', "Queen Bee")];
+
+impl PeerId {
+    /// Parses the client name and version from the peer id, on a best-effort basis.
+    pub fn client(&self) -> Option<Client> {
+        parse_azureus(self.as_array()).or_else(|| parse_mainline(self.as_array()))
+    }
+}
+
+/// Returns the Azureus-style peer id prefix of our client.
+pub(crate) fn prefix() -> [u8; 8] {
+    let mut prefix = *b"-XX0000-";
+    prefix[1..3].copy_from_slice(CLIENT_CODE);
+    // The version occupies four characters: major, minor, patch, and a reserved zero.
+    for (c, n) in prefix[3..6]
+        .iter_mut()
+        .zip(env!("CARGO_PKG_VERSION").split('.'))
+    {
+        *c = n
+            .parse::<u32>()
+            .ok()
+            .and_then(|n| char::from_digit(n, 36))
+            .map_or(b'0', |c| c.to_ascii_uppercase() as u8);
+    }
+    prefix
+}
+
+fn parse_azureus(peer_id: &[u8; PEER_ID_SIZE]) -> Option<Client> {
+    let [b'-', c0, c1, v0, v1, v2, v3, b'-', ..] = *peer_id else {
+        return None;
+    };
+    if !c0.is_ascii_alphanumeric() || !c1.is_ascii_alphanumeric() {
+        return None;
+    }
+    let version = [v0, v1, v2, v3]
+        .into_iter()
+        .map(|v| char::from(v).to_digit(36).map(|v| v.to_string()))
+        .collect::<Option<Vec<_>>>()?
+        .join(".");
+    let code = [c0, c1];
+    let name = AZUREUS_CLIENTS
+        .iter()
+        .find_map(|(c, name)| (**c == code).then(|| name.to_string()))
+        .unwrap_or_else(|| String::from_utf8_lossy(&code).into_owned());
+    Some(Client { name, version })
+}
+
+fn parse_mainline(peer_id: &[u8; PEER_ID_SIZE]) -> Option<Client> {
+    let (&c, rest) = peer_id.split_first().unwrap();
+    let name = MAINLINE_CLIENTS
+        .iter()
+        .find_map(|(code, name)| (*code == c).then_some(*name))?;
+    // The version occupies seven characters and is padded with `-`.
+    let version = std::str::from_utf8(&rest[..7]).ok()?.trim_end_matches('-');
+    let parts: Vec<_> = version.split('-').collect();
+    if parts.len() != 3
+        || !parts
+            .iter()
+            .all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()))
+    {
+        return None;
+    }
+    Some(Client {
+        name: name.to_string(),
+        version: parts.join("."),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_peer_id(prefix: &[u8]) -> PeerId {
+        let mut peer_id = [b'x'; PEER_ID_SIZE];
+        peer_id[..prefix.len()].copy_from_slice(prefix);
+        PeerId::new(peer_id)
+    }
+
+    fn new_client(name: &str, version: &str) -> Option<Client> {
+        Some(Client {
+            name: name.to_string(),
+            version: version.to_string(),
+        })
+    }
+
+    #[test]
+    fn client() {
+        for (prefix, expect) in [
+            (b"-qB4520-".as_slice(), new_client("qBittorrent", "4.5.2.0")),
+            (b"-TR300Z-", new_client("Transmission", "3.0.0.35")),
+            (b"-ZZ1234-", new_client("ZZ", "1.2.3.4")),
+            (b"M4-3-6--", new_client("Mainline", "4.3.6")),
+            (b"M7-10-3-", new_client("Mainline", "7.10.3")),
+            (b"Q1-2-3--", new_client("Queen Bee", "1.2.3")),
+            (b"-qB45.0-", None),
+            (b"-q-4520-", None),
+            (b"X4-3-6--", None),
+            (b"M4-3--", None),
+            (b"M4-3-6-7", None),
+            (b"", None),
+        ] {
+            assert_eq!(new_peer_id(prefix).client(), expect, "{prefix:?}");
+        }
+    }
+
+    #[test]
+    fn self_prefix() {
+        let prefix = prefix();
+        assert_eq!(&prefix[..3], b"-G1");
+        assert_eq!(prefix[7], b'-');
+        assert_eq!(new_peer_id(&prefix).client().unwrap().name, "g1");
+    }
+}
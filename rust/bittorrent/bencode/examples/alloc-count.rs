@@ -0,0 +1,95 @@
+//! Compares the heap allocations of decoding into `borrow::Value` and into an arena.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::error;
+use std::fs;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use clap::Parser;
+
+use g1_base::arena::Arena;
+
+use bittorrent_bencode::{arena, borrow};
+
+struct CountingAllocator;
+
+static NUM_ALLOCS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        NUM_ALLOCS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        NUM_ALLOCS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+#[derive(Debug, Parser)]
+struct AllocCount {
+    #[arg(long, default_value_t = 10000)]
+    repeat: usize,
+    /// Bencode input file; read from stdin if not provided
+    input: Option<PathBuf>,
+}
+
+fn main() -> Result<(), Box<dyn error::Error>> {
+    let alloc_count = AllocCount::parse();
+
+    let input = match &alloc_count.input {
+        Some(path) => fs::read(path)?,
+        None => {
+            let mut input = Vec::new();
+            io::stdin().read_to_end(&mut input)?;
+            input
+        }
+    };
+
+    measure("borrow", alloc_count.repeat, || {
+        for _ in 0..alloc_count.repeat {
+            borrow::Value::<true>::try_from(input.as_slice())?;
+        }
+        Ok(())
+    })?;
+
+    let mut arena = Arena::new();
+    measure("arena", alloc_count.repeat, || {
+        for _ in 0..alloc_count.repeat {
+            arena.scope(|arena| {
+                arena::Value::<true>::decode_in(arena, &mut input.as_slice()).map(drop)
+            })?;
+        }
+        Ok(())
+    })?;
+
+    Ok(())
+}
+
+fn measure<F>(name: &str, repeat: usize, f: F) -> Result<(), Box<dyn error::Error>>
+where
+    F: FnOnce() -> Result<(), bittorrent_bencode::Error>,
+{
+    let num_allocs = NUM_ALLOCS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    f()?;
+    let elapsed = start.elapsed();
+    let num_allocs = NUM_ALLOCS.load(Ordering::Relaxed) - num_allocs;
+    println!(
+        "{name}: allocs={num_allocs} allocs/decode={:.2} time/decode={:?}",
+        num_allocs as f64 / repeat.max(1) as f64,
+        elapsed / u32::try_from(repeat.max(1))?,
+    );
+    Ok(())
+}
@@ -0,0 +1,243 @@
+//! Arena-allocated Values
+//!
+//! Decoding a `borrow::Value` allocates a `Vec` or a `BTreeMap` for every list and dictionary.
+//! When decoding many short-lived messages, you may decode them into an arena instead and reset
+//! the arena after each message.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::mem;
+
+use bytes::Buf;
+use snafu::prelude::*;
+
+use g1_base::arena::Arena;
+use g1_base::fmt::EscapeAscii;
+use g1_base::{cmp::PartialEqExt, ops::Deref};
+use g1_bytes::{BufPeekExt, BufSliceExt};
+
+use crate::{
+    decode_byte_string, decode_integer, own, Error, NotStrictlyIncreasingDictionaryKeySnafu,
+};
+
+/// Arena-allocated Value
+///
+/// It is similar to `borrow::Value`, except that lists and dictionaries are slices allocated in
+/// an arena.  Dictionary entries are sorted by key.
+pub type Value<'a, const STRICT: bool = true> =
+    super::Value<ByteString<'a>, List<'a, STRICT>, Dictionary<'a, STRICT>, STRICT>;
+
+pub type ByteString<'a> = &'a [u8];
+
+#[derive(Clone, Copy, Deref, Eq, PartialEqExt)]
+pub struct List<'a, const STRICT: bool> {
+    #[deref(target)]
+    list: &'a [Value<'a, STRICT>],
+    #[partial_eq(skip)]
+    raw_value: &'a [u8],
+}
+
+#[derive(Clone, Copy, Deref, Eq, PartialEqExt)]
+pub struct Dictionary<'a, const STRICT: bool> {
+    #[deref(target)]
+    dict: &'a [(ByteString<'a>, Value<'a, STRICT>)],
+    #[partial_eq(skip)]
+    raw_value: &'a [u8],
+}
+
+impl<const STRICT: bool> fmt::Debug for List<'_, STRICT> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.list.fmt(f)
+    }
+}
+
+impl<const STRICT: bool> fmt::Debug for Dictionary<'_, STRICT> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.dict.iter().map(|(k, v)| (EscapeAscii(k), v)))
+            .finish()
+    }
+}
+
+impl<'a, const STRICT: bool> Dictionary<'a, STRICT> {
+    pub fn get(&self, key: &[u8]) -> Option<&'a Value<'a, STRICT>> {
+        self.dict
+            .binary_search_by_key(&key, |(k, _)| *k)
+            .ok()
+            .map(|i| &self.dict[i].1)
+    }
+}
+
+impl<'a, const STRICT: bool> Value<'a, STRICT> {
+    /// Decodes a value, allocating its lists and dictionaries in `arena`.
+    pub fn decode_in<Buffer>(arena: &'a Arena, buffer: &mut Buffer) -> Result<Self, Error>
+    where
+        Buffer: BufPeekExt + BufSliceExt + 'a,
+    {
+        match buffer.peek_u8().ok_or(Error::Incomplete)? {
+            b'0'..=b'9' => Ok(Self::ByteString(decode_byte_string(buffer)?)),
+            b'i' => {
+                buffer.advance(1);
+                let int = buffer
+                    .get_slice_until_strip(|x| *x == b'e')
+                    .ok_or(Error::Incomplete)?;
+                Ok(Self::Integer(decode_integer(int).ok_or_else(|| {
+                    Error::InvalidInteger {
+                        integer: int.escape_ascii().to_string(),
+                    }
+                })?))
+            }
+            b'l' => {
+                let mut list = Vec::new_in(arena);
+                let mut buf = buffer.dup();
+                buf.advance(1);
+                while buf.peek_u8().ok_or(Error::Incomplete)? != b'e' {
+                    list.push(Self::decode_in(arena, &mut buf)?);
+                }
+                buf.advance(1);
+                Ok(Self::List(List {
+                    list: Vec::leak(list),
+                    raw_value: buffer.get_slice(buffer.remaining() - buf.remaining()),
+                }))
+            }
+            b'd' => {
+                let mut dict = Vec::<(ByteString<'a>, Self), _>::new_in(arena);
+                let mut sorted = true;
+                let mut buf = buffer.dup();
+                buf.advance(1);
+                while buf.peek_u8().ok_or(Error::Incomplete)? != b'e' {
+                    let key = decode_byte_string(&mut buf)?;
+                    let value = Self::decode_in(arena, &mut buf)?;
+                    if let Some((last_key, _)) = dict.last() {
+                        ensure!(
+                            !STRICT || *last_key < key,
+                            NotStrictlyIncreasingDictionaryKeySnafu {
+                                last_key: last_key.escape_ascii().to_string(),
+                                new_key: key.escape_ascii().to_string(),
+                            },
+                        );
+                        sorted &= *last_key < key;
+                    }
+                    dict.push((key, value));
+                }
+                buf.advance(1);
+                if !sorted {
+                    // Match `borrow::Value`, where the last duplicated key wins.
+                    dict.sort_by_key(|(key, _)| *key);
+                    dict.dedup_by(|(key, value), (prev_key, prev_value)| {
+                        let duplicated = key == prev_key;
+                        if duplicated {
+                            mem::swap(value, prev_value);
+                        }
+                        duplicated
+                    });
+                }
+                Ok(Self::Dictionary(Dictionary {
+                    dict: Vec::leak(dict),
+                    raw_value: buffer.get_slice(buffer.remaining() - buf.remaining()),
+                }))
+            }
+            value_type => Err(Error::InvalidValueType { value_type }),
+        }
+    }
+
+    pub fn raw_value(&self) -> &'a [u8] {
+        match self {
+            Self::List(list) => list.raw_value,
+            Self::Dictionary(dict) => dict.raw_value,
+            Self::ByteString(_) | Self::Integer(_) => {
+                panic!("we do not store raw value for these types: {:?}", self)
+            }
+        }
+    }
+
+    /// Converts from `arena::Value` to `own::Value`.
+    pub fn to_owned(&self) -> own::Value {
+        match self {
+            Self::ByteString(bytes) => own::ByteString::from(*bytes).into(),
+            Self::Integer(int) => (*int).into(),
+            Self::List(List { list, .. }) => {
+                list.iter().map(Self::to_owned).collect::<Vec<_>>().into()
+            }
+            Self::Dictionary(Dictionary { dict, .. }) => dict
+                .iter()
+                .map(|(key, value)| ((*key).into(), value.to_owned()))
+                .collect::<BTreeMap<_, _>>()
+                .into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::assert_matches::assert_matches;
+
+    use crate::borrow;
+
+    use super::*;
+
+    #[test]
+    fn decode_in() {
+        fn test(data: &[u8]) {
+            let arena = Arena::new();
+            let mut buffer = data;
+            let value = Value::<true>::decode_in(&arena, &mut buffer).unwrap();
+            assert_eq!(buffer, b"");
+            assert_eq!(
+                value.to_owned(),
+                borrow::Value::<true>::try_from(data).unwrap().to_owned(),
+            );
+            if matches!(value, Value::List(_) | Value::Dictionary(_)) {
+                assert_eq!(value.raw_value(), data);
+            }
+            assert!(arena.num_chunks() <= 1);
+        }
+
+        test(b"0:");
+        test(b"3:foo");
+        test(b"i-42e");
+        test(b"le");
+        test(b"li1e3:fooli2eee");
+        test(b"de");
+        test(b"d1:ai1e1:bl1:xd1:yi2eeee");
+
+        let arena = Arena::new();
+        let Value::Dictionary(dict) =
+            Value::<true>::decode_in(&arena, &mut b"d1:ai1e1:bli2eee".as_slice()).unwrap()
+        else {
+            panic!()
+        };
+        assert_eq!(dict.get(b"a"), Some(&Value::Integer(1)));
+        assert_matches!(dict.get(b"b"), Some(Value::List(list)) if list.len() == 1);
+        assert_eq!(dict.get(b"c"), None);
+    }
+
+    #[test]
+    fn decode_in_lenient() {
+        let arena = Arena::new();
+        assert_eq!(
+            Value::<true>::decode_in(&arena, &mut b"d1:bi1e1:ai2ee".as_slice()),
+            Err(Error::NotStrictlyIncreasingDictionaryKey {
+                last_key: "b".to_string(),
+                new_key: "a".to_string(),
+            }),
+        );
+
+        let data = b"d1:bi1e1:ai2e1:bi3ee";
+        let value = Value::<false>::decode_in(&arena, &mut data.as_slice()).unwrap();
+        assert_eq!(
+            value.to_owned(),
+            borrow::Value::<false>::try_from(data.as_slice())
+                .unwrap()
+                .to_owned(),
+        );
+        let Value::Dictionary(dict) = value else {
+            panic!()
+        };
+        assert_eq!(
+            dict.iter().map(|(key, _)| *key).collect::<Vec<_>>(),
+            [b"a", b"b"],
+        );
+        assert_eq!(dict.get(b"b"), Some(&Value::Integer(3)));
+    }
+}
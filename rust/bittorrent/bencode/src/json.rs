@@ -0,0 +1,246 @@
+//! Conversion between Bencode and JSON
+//!
+//! Integers, lists, and dictionaries are mapped to JSON numbers, arrays, and objects.  Because
+//! JSON strings must be valid Unicode, byte strings (including dictionary keys) are mapped to JSON
+//! strings according to `Encoding`.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use base64::prelude::*;
+use serde_json::Value as JsonValue;
+use snafu::prelude::*;
+
+use crate::own;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Encoding {
+    /// Encodes byte strings in base64.  It is lossless.
+    Base64,
+    /// Decodes byte strings as UTF-8 and replaces invalid sequences with U+FFFD.  It is lossy.
+    Lossy,
+    /// Decodes byte strings as UTF-8 and escapes invalid bytes as `\xNN` (and `\` as `\\`).  It is
+    /// lossless, and it keeps the byte strings that are valid UTF-8 readable.
+    Escape,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Snafu)]
+pub enum Error {
+    #[snafu(display("invalid base64 string: \"{string}\""))]
+    InvalidBase64 { string: String },
+    #[snafu(display("invalid escape sequence: \"{string}\""))]
+    InvalidEscape { string: String },
+    #[snafu(display("unsupported json value: {value}"))]
+    UnsupportedJsonValue { value: JsonValue },
+}
+
+pub fn to_json(value: &own::Value, encoding: Encoding) -> JsonValue {
+    match value {
+        own::Value::ByteString(bytes) => JsonValue::String(encode(bytes, encoding)),
+        own::Value::Integer(int) => JsonValue::from(*int),
+        own::Value::List(list) => list
+            .iter()
+            .map(|element| to_json(element, encoding))
+            .collect(),
+        own::Value::Dictionary(dict) => JsonValue::Object(
+            dict.iter()
+                .map(|(key, value)| (encode(key, encoding), to_json(value, encoding)))
+                .collect(),
+        ),
+    }
+}
+
+/// Converts a JSON value to Bencode.
+///
+/// Since Bencode has no null, boolean, or floating point, these JSON values are rejected.
+pub fn from_json(value: &JsonValue, encoding: Encoding) -> Result<own::Value, Error> {
+    Ok(match value {
+        JsonValue::String(string) => decode(string, encoding)?.into(),
+        JsonValue::Number(number) => number
+            .as_i64()
+            .context(UnsupportedJsonValueSnafu {
+                value: value.clone(),
+            })?
+            .into(),
+        JsonValue::Array(array) => array
+            .iter()
+            .map(|element| from_json(element, encoding))
+            .try_collect::<Vec<_>>()?
+            .into(),
+        JsonValue::Object(object) => {
+            let mut dict = BTreeMap::new();
+            for (key, value) in object {
+                dict.insert(decode(key, encoding)?, from_json(value, encoding)?);
+            }
+            dict.into()
+        }
+        JsonValue::Null | JsonValue::Bool(_) => {
+            return Err(Error::UnsupportedJsonValue {
+                value: value.clone(),
+            });
+        }
+    })
+}
+
+fn encode(bytes: &[u8], encoding: Encoding) -> String {
+    match encoding {
+        Encoding::Base64 => BASE64_STANDARD.encode(bytes),
+        Encoding::Lossy => String::from_utf8_lossy(bytes).into_owned(),
+        Encoding::Escape => {
+            let mut string = String::with_capacity(bytes.len());
+            for chunk in bytes.utf8_chunks() {
+                for c in chunk.valid().chars() {
+                    if c == '\\' {
+                        string.push_str("\\\\");
+                    } else {
+                        string.push(c);
+                    }
+                }
+                for byte in chunk.invalid() {
+                    write!(string, "\\x{byte:02x}").unwrap();
+                }
+            }
+            string
+        }
+    }
+}
+
+fn decode(string: &str, encoding: Encoding) -> Result<own::ByteString, Error> {
+    match encoding {
+        Encoding::Base64 => BASE64_STANDARD
+            .decode(string)
+            .map(|bytes| bytes.as_slice().into())
+            .map_err(|_| Error::InvalidBase64 {
+                string: string.to_string(),
+            }),
+        Encoding::Lossy => Ok(string.as_bytes().into()),
+        Encoding::Escape => unescape(string).context(InvalidEscapeSnafu { string }),
+    }
+}
+
+fn unescape(string: &str) -> Option<own::ByteString> {
+    let mut bytes = own::ByteString::with_capacity(string.len());
+    let mut rest = string.as_bytes();
+    while let Some((&x, r)) = rest.split_first() {
+        rest = r;
+        if x != b'\\' {
+            bytes.extend_from_slice(&[x]);
+            continue;
+        }
+        match rest {
+            [b'\\', r @ ..] => {
+                bytes.extend_from_slice(b"\\");
+                rest = r;
+            }
+            [b'x', hi, lo, r @ ..] => {
+                let hi = char::from(*hi).to_digit(16)?;
+                let lo = char::from(*lo).to_digit(16)?;
+                bytes.extend_from_slice(&[u8::try_from(hi * 16 + lo).unwrap()]);
+                rest = r;
+            }
+            _ => return None,
+        }
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn new_bytes(bytes: &[u8]) -> own::Value {
+        own::ByteString::from(bytes).into()
+    }
+
+    #[test]
+    fn round_trip() {
+        let value: own::Value = BTreeMap::from([
+            (b"int".as_slice().into(), (-1).into()),
+            (
+                b"list".as_slice().into(),
+                vec![new_bytes(b"spam"), new_bytes(b"\xff\\x"), 42.into()].into(),
+            ),
+            (b"\xfe".as_slice().into(), BTreeMap::new().into()),
+        ])
+        .into();
+
+        for (encoding, expect) in [
+            (
+                Encoding::Base64,
+                json!({"aW50": -1, "bGlzdA==": ["c3BhbQ==", "/1x4", 42], "/g==": {}}),
+            ),
+            (
+                Encoding::Escape,
+                json!({"int": -1, "list": ["spam", "\\xff\\\\x", 42], "\\xfe": {}}),
+            ),
+        ] {
+            let json = to_json(&value, encoding);
+            assert_eq!(json, expect, "{encoding:?}");
+            assert_eq!(
+                from_json(&json, encoding),
+                Ok(value.clone()),
+                "{encoding:?}"
+            );
+        }
+
+        let json = to_json(&value, Encoding::Lossy);
+        assert_eq!(
+            json,
+            json!({"int": -1, "list": ["spam", "\u{fffd}\\x", 42], "\u{fffd}": {}}),
+        );
+        assert_eq!(
+            from_json(&json!(["spam", "\u{fffd}"]), Encoding::Lossy),
+            Ok(vec![new_bytes(b"spam"), new_bytes("\u{fffd}".as_bytes())].into()),
+        );
+    }
+
+    #[test]
+    fn escape() {
+        for (bytes, string) in [
+            (b"".as_slice(), ""),
+            (b"\\", "\\\\"),
+            (b"\\x00", "\\\\x00"),
+            (b"\x00\x7f", "\x00\x7f"),
+            ("\u{00e9}".as_bytes(), "\u{00e9}"),
+            (b"\xc3", "\\xc3"),
+            (b"\xc3\x28", "\\xc3("),
+        ] {
+            assert_eq!(encode(bytes, Encoding::Escape), string);
+            assert_eq!(unescape(string).as_deref(), Some(bytes));
+        }
+
+        for string in ["\\", "\\y", "\\x", "\\x0", "\\xgg", "\\x+f"] {
+            assert_eq!(unescape(string), None, "{string:?}");
+        }
+    }
+
+    #[test]
+    fn from_json_error() {
+        for value in [json!(null), json!(true), json!(0.5), json!(u64::MAX)] {
+            assert_eq!(
+                from_json(&value, Encoding::Lossy),
+                Err(Error::UnsupportedJsonValue {
+                    value: value.clone(),
+                }),
+            );
+        }
+        assert_eq!(
+            from_json(&json!([{"a": null}]), Encoding::Lossy),
+            Err(Error::UnsupportedJsonValue { value: json!(null) }),
+        );
+        assert_eq!(
+            from_json(&json!("!"), Encoding::Base64),
+            Err(Error::InvalidBase64 {
+                string: "!".to_string(),
+            }),
+        );
+        assert_eq!(
+            from_json(&json!({"\\": 1}), Encoding::Escape),
+            Err(Error::InvalidEscape {
+                string: "\\".to_string(),
+            }),
+        );
+    }
+}
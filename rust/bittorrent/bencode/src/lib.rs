@@ -1,12 +1,18 @@
 //! Implementation of Bencode Format as Specified in BEP 3
 
+#![feature(allocator_api)]
 #![feature(iterator_try_collect)]
 #![cfg_attr(test, feature(assert_matches))]
 
+pub mod arena;
 pub mod convert;
 pub mod dict;
+#[cfg(feature = "json")]
+pub mod json;
+pub mod pretty;
 #[cfg(feature = "serde")]
 pub mod serde;
+pub mod stream;
 
 use std::collections::BTreeMap;
 use std::fmt;
@@ -468,6 +474,27 @@ impl<'a, const STRICT: bool> borrow::Value<'a, STRICT> {
         }
     }
 
+    /// Returns the raw Bencode data of a dictionary entry's value.
+    ///
+    /// Unlike `raw_value`, this also covers byte strings and integers, whose raw data might differ
+    /// from their re-encoding (e.g., `i-0e`).  It returns `None` when the value is not a
+    /// dictionary, the key is absent, or the raw data is unavailable.
+    pub fn raw_value_of(&self, key: &[u8]) -> Option<&'a [u8]> {
+        let Self::Dictionary(dict) = self else {
+            return None;
+        };
+        let mut buffer = dict.raw_value.strip_prefix(b"d")?;
+        while *buffer.first()? != b'e' {
+            let k = decode_byte_string(&mut buffer).ok()?;
+            let raw_value = buffer;
+            Self::decode(&mut buffer).ok()?;
+            if k == key {
+                return Some(&raw_value[..raw_value.len() - buffer.len()]);
+            }
+        }
+        None
+    }
+
     /// Converts from `borrow::Value` to `own::Value`.
     ///
     /// This method that is similar to `std::borrow::ToOwned`.  However we cannot implement
@@ -896,6 +923,25 @@ mod tests {
         assert_eq!(dict.raw_value(), b"de");
     }
 
+    #[test]
+    fn raw_value_of() {
+        let value =
+            borrow::Value::<true>::try_from(b"d1:ai-0e1:b03:xyz1:cl1:xee".as_slice()).unwrap();
+        assert_eq!(value.raw_value_of(b"a"), Some(b"i-0e".as_slice()));
+        assert_eq!(value.raw_value_of(b"b"), Some(b"03:xyz".as_slice()));
+        assert_eq!(value.raw_value_of(b"c"), Some(b"l1:xe".as_slice()));
+        assert_eq!(value.raw_value_of(b"d"), None);
+
+        let value = borrow::Value::<true>::try_from(b"l1:ae".as_slice()).unwrap();
+        assert_eq!(value.raw_value_of(b"a"), None);
+
+        let value = borrow::Value::new_dictionary_without_raw_value(BTreeMap::from([(
+            b"a".as_slice(),
+            borrow::Value::Integer(0),
+        )]));
+        assert_eq!(value.raw_value_of(b"a"), None);
+    }
+
     #[test]
     fn value_as() {
         let value = own::Value::from(new_owned_bytes(b"foo"));
@@ -0,0 +1,306 @@
+//! Human-Readable Rendering and Structural Diff
+//!
+//! These are debugging aids, e.g., for finding out why a peer rejects our extension handshake, or
+//! why the info hash of a torrent mismatches.
+
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::ops::Deref;
+
+use crate::Value;
+
+/// Renders a value in an indented, human-readable form.
+///
+/// Byte strings of printable ASCII are rendered as quoted strings; others, such as the `pieces`
+/// of an info dictionary, are rendered in (truncated) hex.
+pub struct Pretty<'a, T>(&'a T);
+
+/// Path to a value, e.g., `.info.files[2].length`.
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Path(Vec<Segment>);
+
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Segment {
+    Key(Vec<u8>),
+    Index(usize),
+}
+
+const INDENT: &str = "  ";
+const MAX_HEX_SIZE: usize = 32;
+
+impl<ByteString, List, Dictionary, const STRICT: bool> Value<ByteString, List, Dictionary, STRICT>
+where
+    ByteString: AsRef<[u8]>,
+    List: Deref<Target = Vec<Self>>,
+    Dictionary: Deref<Target = BTreeMap<ByteString, Self>>,
+{
+    pub fn pretty(&self) -> Pretty<'_, Self> {
+        Pretty(self)
+    }
+}
+
+impl<ByteString, List, Dictionary, const STRICT: bool> fmt::Display
+    for Pretty<'_, Value<ByteString, List, Dictionary, STRICT>>
+where
+    ByteString: AsRef<[u8]>,
+    List: Deref<Target = Vec<Value<ByteString, List, Dictionary, STRICT>>>,
+    Dictionary: Deref<Target = BTreeMap<ByteString, Value<ByteString, List, Dictionary, STRICT>>>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_value(f, self.0, 0)
+    }
+}
+
+fn write_value<ByteString, List, Dictionary, const STRICT: bool>(
+    f: &mut fmt::Formatter<'_>,
+    value: &Value<ByteString, List, Dictionary, STRICT>,
+    depth: usize,
+) -> fmt::Result
+where
+    ByteString: AsRef<[u8]>,
+    List: Deref<Target = Vec<Value<ByteString, List, Dictionary, STRICT>>>,
+    Dictionary: Deref<Target = BTreeMap<ByteString, Value<ByteString, List, Dictionary, STRICT>>>,
+{
+    match value {
+        Value::ByteString(bytes) => write_byte_string(f, bytes.as_ref()),
+        Value::Integer(int) => write!(f, "{int}"),
+        Value::List(list) => {
+            if list.is_empty() {
+                return f.write_str("[]");
+            }
+            f.write_str("[\n")?;
+            for element in list.iter() {
+                write_indent(f, depth + 1)?;
+                write_value(f, element, depth + 1)?;
+                f.write_str(",\n")?;
+            }
+            write_indent(f, depth)?;
+            f.write_str("]")
+        }
+        Value::Dictionary(dict) => {
+            if dict.is_empty() {
+                return f.write_str("{}");
+            }
+            f.write_str("{\n")?;
+            for (key, value) in dict.iter() {
+                write_indent(f, depth + 1)?;
+                write_byte_string(f, key.as_ref())?;
+                f.write_str(": ")?;
+                write_value(f, value, depth + 1)?;
+                f.write_str(",\n")?;
+            }
+            write_indent(f, depth)?;
+            f.write_str("}")
+        }
+    }
+}
+
+fn write_byte_string(f: &mut fmt::Formatter<'_>, bytes: &[u8]) -> fmt::Result {
+    if bytes.iter().all(|&x| x == b' ' || x.is_ascii_graphic()) {
+        return write!(f, "\"{}\"", bytes.escape_ascii());
+    }
+    write!(f, "<{} bytes: ", bytes.len())?;
+    for x in &bytes[..cmp::min(bytes.len(), MAX_HEX_SIZE)] {
+        write!(f, "{x:02x}")?;
+    }
+    if bytes.len() > MAX_HEX_SIZE {
+        f.write_str("...")?;
+    }
+    f.write_str(">")
+}
+
+fn write_indent(f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+    for _ in 0..depth {
+        f.write_str(INDENT)?;
+    }
+    Ok(())
+}
+
+impl Path {
+    pub fn segments(&self) -> &[Segment] {
+        &self.0
+    }
+
+    fn push(&self, segment: Segment) -> Self {
+        let mut path = self.clone();
+        path.0.push(segment);
+        path
+    }
+}
+
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return f.write_str(".");
+        }
+        for segment in &self.0 {
+            match segment {
+                Segment::Key(key) => write!(f, ".{}", key.escape_ascii())?,
+                Segment::Index(index) => write!(f, "[{index}]")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the paths at which the two values differ, in sorted order.
+///
+/// It descends into dictionaries and lists, and reports a dictionary key that is present in only
+/// one of the values, a list element that is present in only one of the values, and a value that
+/// differs in type or content.
+pub fn diff<ByteString, List, Dictionary, const STRICT: bool>(
+    a: &Value<ByteString, List, Dictionary, STRICT>,
+    b: &Value<ByteString, List, Dictionary, STRICT>,
+) -> Vec<Path>
+where
+    ByteString: AsRef<[u8]> + Ord,
+    List: Deref<Target = Vec<Value<ByteString, List, Dictionary, STRICT>>>,
+    Dictionary: Deref<Target = BTreeMap<ByteString, Value<ByteString, List, Dictionary, STRICT>>>,
+{
+    let mut paths = Vec::new();
+    diff_impl(a, b, &Path::default(), &mut paths);
+    paths
+}
+
+fn diff_impl<ByteString, List, Dictionary, const STRICT: bool>(
+    a: &Value<ByteString, List, Dictionary, STRICT>,
+    b: &Value<ByteString, List, Dictionary, STRICT>,
+    path: &Path,
+    paths: &mut Vec<Path>,
+) where
+    ByteString: AsRef<[u8]> + Ord,
+    List: Deref<Target = Vec<Value<ByteString, List, Dictionary, STRICT>>>,
+    Dictionary: Deref<Target = BTreeMap<ByteString, Value<ByteString, List, Dictionary, STRICT>>>,
+{
+    match (a, b) {
+        (Value::ByteString(a), Value::ByteString(b)) => {
+            if a.as_ref() != b.as_ref() {
+                paths.push(path.clone());
+            }
+        }
+        (Value::Integer(a), Value::Integer(b)) => {
+            if a != b {
+                paths.push(path.clone());
+            }
+        }
+        (Value::List(a), Value::List(b)) => {
+            for i in 0..cmp::max(a.len(), b.len()) {
+                let path = path.push(Segment::Index(i));
+                match (a.get(i), b.get(i)) {
+                    (Some(a), Some(b)) => diff_impl(a, b, &path, paths),
+                    _ => paths.push(path),
+                }
+            }
+        }
+        (Value::Dictionary(a), Value::Dictionary(b)) => {
+            for key in a.keys().chain(b.keys()).collect::<BTreeSet<_>>() {
+                let path = path.push(Segment::Key(key.as_ref().to_vec()));
+                match (a.get(key), b.get(key)) {
+                    (Some(a), Some(b)) => diff_impl(a, b, &path, paths),
+                    _ => paths.push(path),
+                }
+            }
+        }
+        _ => paths.push(path.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::own;
+
+    use super::*;
+
+    fn b(bytes: &[u8]) -> own::ByteString {
+        bytes.into()
+    }
+
+    fn new_value(length: i64, pieces: &[u8], files: Vec<own::Value>) -> own::Value {
+        BTreeMap::from([
+            (b(b"announce"), b(b"http://tracker/announce").into()),
+            (
+                b(b"info"),
+                BTreeMap::from([
+                    (b(b"files"), files.into()),
+                    (b(b"length"), length.into()),
+                    (b(b"pieces"), b(pieces).into()),
+                ])
+                .into(),
+            ),
+        ])
+        .into()
+    }
+
+    #[test]
+    fn pretty() {
+        let value = new_value(42, b"\x00\x01\xff", vec![1.into(), BTreeMap::new().into()]);
+        assert_eq!(
+            value.pretty().to_string(),
+            r#"{
+  "announce": "http://tracker/announce",
+  "info": {
+    "files": [
+      1,
+      {},
+    ],
+    "length": 42,
+    "pieces": <3 bytes: 0001ff>,
+  },
+}"#,
+        );
+
+        let value: own::Value = vec![b(&[0u8; 33]).into(), b(b"a\"b").into()].into();
+        assert_eq!(
+            value.pretty().to_string(),
+            format!(
+                "[\n  <33 bytes: {}...>,\n  \"a\\\"b\",\n]",
+                "00".repeat(MAX_HEX_SIZE),
+            ),
+        );
+
+        let value: own::Value = Vec::new().into();
+        assert_eq!(value.pretty().to_string(), "[]");
+    }
+
+    #[test]
+    fn diff() {
+        fn test(a: &own::Value, b: &own::Value, expect: &[&str]) {
+            let paths = super::diff(a, b);
+            assert_eq!(
+                paths
+                    .iter()
+                    .map(|path| path.to_string())
+                    .collect::<Vec<_>>(),
+                expect,
+            );
+            // `diff` is symmetric.
+            assert_eq!(super::diff(b, a), paths);
+        }
+
+        let a = new_value(42, b"x", vec![1.into(), 2.into()]);
+        test(&a, &a, &[]);
+
+        let other = new_value(43, b"y", vec![1.into(), 3.into(), 4.into()]);
+        test(
+            &a,
+            &other,
+            &[
+                ".info.files[1]",
+                ".info.files[2]",
+                ".info.length",
+                ".info.pieces",
+            ],
+        );
+
+        let mut other = a.clone();
+        if let own::Value::Dictionary(dict) = &mut other {
+            dict.remove(b"announce".as_slice());
+            dict.insert(b(b"comment"), b(b"").into());
+        }
+        test(&a, &other, &[".announce", ".comment"]);
+
+        test(&a, &1.into(), &["."]);
+        test(&1.into(), &b(b"1").into(), &["."]);
+    }
+}
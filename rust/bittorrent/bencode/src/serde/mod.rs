@@ -4,7 +4,7 @@ mod ser;
 
 pub use de::{from_bytes, from_bytes_lenient, from_bytes_lenient_two_pass, Deserializer};
 pub use error::{Error, Result};
-pub use ser::{to_bytes, Serializer};
+pub use ser::{to_bytes, to_writer, Serializer};
 
 /// Converts from one integer type to another.
 ///
@@ -20,7 +20,7 @@ where
 #[cfg(test)]
 mod tests {
     use std::assert_matches::assert_matches;
-    use std::collections::BTreeMap;
+    use std::collections::{BTreeMap, HashMap};
     use std::fmt;
 
     use bytes::BytesMut;
@@ -56,6 +56,12 @@ mod tests {
         },
     }
 
+    // Its fields are not declared in the sorted order.
+    #[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
+    enum Enum2 {
+        Struct { z: u32, a: u32 },
+    }
+
     #[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
     struct ValueField<'a> {
         #[serde(borrow)]
@@ -163,6 +169,43 @@ mod tests {
         test(value, b"d1:ad1:bd1:cdeeee");
     }
 
+    #[test]
+    fn round_trip() {
+        fn test<'a, T>(value: T, expect: &'a [u8])
+        where
+            T: fmt::Debug + PartialEq + Deserialize<'a> + Serialize,
+        {
+            let mut buffer = BytesMut::from(b"prefix".as_slice());
+            to_writer(&mut buffer, &value).unwrap();
+            assert_eq!(&buffer[..6], b"prefix");
+            assert_eq!(&buffer[6..], expect);
+
+            // The output is accepted by the strict decoders.
+            let mut data = Vec::new();
+            borrow::Value::try_from(expect).unwrap().encode(&mut data);
+            assert_eq!(data, expect);
+            assert_eq!(from_bytes::<T>(expect), Ok(value));
+        }
+
+        // Keys are sorted regardless of the iteration order of the map.
+        test(
+            HashMap::from([("zz", 1), ("b", 2), ("a", 3), ("aa", 4)]),
+            b"d1:ai3e2:aai4e1:bi2e2:zzi1ee",
+        );
+        test(
+            BTreeMap::from([("b".to_string(), vec![HashMap::from([("y", 1), ("x", 2)])])]),
+            b"d1:bld1:xi2e1:yi1eeee",
+        );
+        test(Enum2::Struct { z: 1, a: 2 }, b"d6:Structd1:ai2e1:zi1eee");
+
+        let mut buffer = BytesMut::from(b"prefix".as_slice());
+        assert_eq!(
+            to_writer(&mut buffer, &u64::MAX),
+            Err(Error::IntegerValueOutOfRange),
+        );
+        assert_eq!(buffer, b"prefix".as_slice());
+    }
+
     #[test]
     fn test_err() {
         assert_eq!(
@@ -1,7 +1,7 @@
 use std::collections::BTreeMap;
 use std::ops::Deref;
 
-use bytes::BytesMut;
+use bytes::{BufMut, BytesMut};
 use serde::{
     ser::{
         self, SerializeMap, SerializeSeq, SerializeStruct, SerializeStructVariant, SerializeTuple,
@@ -57,10 +57,23 @@ where
     T: Serialize,
 {
     let mut buffer = BytesMut::new();
-    value.serialize(Serializer)?.encode(&mut buffer);
+    to_writer(&mut buffer, value)?;
     Ok(buffer)
 }
 
+/// Serializes `value` and appends it to `buffer`.
+///
+/// The output is canonical (dictionary keys are sorted), and thus it is accepted by the strict
+/// decoder.  Nothing is written if serialization fails.
+pub fn to_writer<B, T>(buffer: &mut B, value: &T) -> Result<(), Error>
+where
+    B: BufMut,
+    T: Serialize,
+{
+    value.serialize(Serializer)?.encode(buffer);
+    Ok(())
+}
+
 /// Constructs a `BTreeMap<own::ByteString, own::Value>` from an array of `(&str, own::Value)`.
 macro_rules! btree_from {
     ($(($key:expr, $value:expr $(,)?)),* $(,)?) => {
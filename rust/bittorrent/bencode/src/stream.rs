@@ -0,0 +1,246 @@
+//! Incremental Decoder
+//!
+//! `Decoder` is fed chunks of data (e.g., from a `tokio` stream) and yields top-level values as
+//! they complete.  It scans each byte only once to find the end of the current value, and then it
+//! splits the value off without copying, so that a large value is not buffered twice.
+//!
+//! ```ignore
+//! let mut decoder = Decoder::new();
+//! while let Some(chunk) = stream.try_next().await? {
+//!     decoder.feed(&chunk);
+//!     while let Some(value) = decoder.try_next()? {
+//!         // ...
+//!     }
+//! }
+//! decoder.finish()?;
+//! ```
+
+use bytes::{Bytes, BytesMut};
+
+use crate::{borrow, Error};
+
+#[derive(Debug, Default)]
+pub struct Decoder {
+    buffer: BytesMut,
+    // Number of bytes of `buffer` that have been scanned.
+    scanned: usize,
+    // Nesting depth of lists and dictionaries of the current value.
+    depth: usize,
+    state: State,
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+enum State {
+    #[default]
+    Value,
+    Integer,
+    Length(usize),
+    ByteString(usize),
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn feed(&mut self, chunk: &[u8]) {
+        self.buffer.extend_from_slice(chunk);
+    }
+
+    /// Returns the next top-level value, or `None` if it has not been completely fed.
+    ///
+    /// NOTE: The decoder should not be used after it returns an error.
+    pub fn try_next(&mut self) -> Result<Option<borrow::ValueOwner<Bytes>>, Error> {
+        if !self.scan()? {
+            return Ok(None);
+        }
+        let value = self.buffer.split_to(self.scanned).freeze();
+        self.scanned = 0;
+        borrow::ValueOwner::try_from(value).map(Some)
+    }
+
+    /// Checks that there is no partially-fed value left.
+    pub fn finish(&self) -> Result<(), Error> {
+        if self.buffer.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Incomplete)
+        }
+    }
+
+    /// Scans the buffer and returns true if a top-level value is completed.
+    fn scan(&mut self) -> Result<bool, Error> {
+        loop {
+            if let State::ByteString(remaining) = self.state {
+                let size = remaining.min(self.buffer.len() - self.scanned);
+                self.scanned += size;
+                if size < remaining {
+                    self.state = State::ByteString(remaining - size);
+                    return Ok(false);
+                }
+                self.state = State::Value;
+                if self.depth == 0 {
+                    return Ok(true);
+                }
+            }
+
+            let Some(&x) = self.buffer.get(self.scanned) else {
+                return Ok(false);
+            };
+            self.scanned += 1;
+            let end_of_value = match (self.state, x) {
+                (State::Value, b'i') => {
+                    self.state = State::Integer;
+                    false
+                }
+                (State::Value, b'0'..=b'9') => {
+                    self.state = State::Length(usize::from(x - b'0'));
+                    false
+                }
+                (State::Value, b'l' | b'd') => {
+                    self.depth += 1;
+                    false
+                }
+                (State::Value, b'e') if self.depth > 0 => {
+                    self.depth -= 1;
+                    true
+                }
+                (State::Value, value_type) => return Err(Error::InvalidValueType { value_type }),
+
+                // We leave the validation of integers to `Value::decode`.
+                (State::Integer, b'e') => {
+                    self.state = State::Value;
+                    true
+                }
+                (State::Integer, _) => false,
+
+                (State::Length(length), b'0'..=b'9') => {
+                    self.state = State::Length(
+                        length
+                            .checked_mul(10)
+                            .and_then(|length| length.checked_add(usize::from(x - b'0')))
+                            .ok_or_else(|| self.invalid_length())?,
+                    );
+                    false
+                }
+                (State::Length(length), b':') => {
+                    self.state = State::ByteString(length);
+                    false
+                }
+                (State::Length(_), _) => return Err(self.invalid_length()),
+
+                (State::ByteString(_), _) => unreachable!(),
+            };
+            if end_of_value && self.depth == 0 {
+                return Ok(true);
+            }
+        }
+    }
+
+    /// Returns the error of the byte string length being scanned.
+    ///
+    /// The length includes the last scanned byte only if it is a digit (i.e., on overflow).
+    fn invalid_length(&self) -> Error {
+        let mut length = &self.buffer[..self.scanned];
+        if let Some((last, rest)) = length.split_last() {
+            if !last.is_ascii_digit() {
+                length = rest;
+            }
+        }
+        let start = length
+            .iter()
+            .rposition(|x| !x.is_ascii_digit())
+            .map_or(0, |i| i + 1);
+        Error::InvalidByteStringLength {
+            length: length[start..].escape_ascii().to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_values(decoder: &mut Decoder, expect: &[&[u8]]) {
+        for expect in expect {
+            let value = decoder.try_next().unwrap().unwrap();
+            assert_eq!(borrow::ValueOwner::as_slice(&value), *expect);
+        }
+        assert_eq!(decoder.try_next().map(|value| value.map(|_| ())), Ok(None));
+    }
+
+    #[test]
+    fn decoder() {
+        let testdata = b"i42e0:4:spamli1e3:fooed3:bari1e3:fooli2eee";
+        let expect: &[&[u8]] = &[
+            b"i42e",
+            b"0:",
+            b"4:spam",
+            b"li1e3:fooe",
+            b"d3:bari1e3:fooli2eee",
+        ];
+
+        let mut decoder = Decoder::new();
+        decoder.feed(testdata);
+        assert_values(&mut decoder, expect);
+        assert_eq!(decoder.finish(), Ok(()));
+
+        // Feed one byte at a time.
+        let mut decoder = Decoder::new();
+        let mut values = Vec::new();
+        for x in testdata {
+            decoder.feed(&[*x]);
+            while let Some(value) = decoder.try_next().unwrap() {
+                values.push(borrow::ValueOwner::as_slice(&value).to_vec());
+            }
+        }
+        assert_eq!(values, expect);
+        assert_eq!(decoder.finish(), Ok(()));
+
+        let mut decoder = Decoder::new();
+        decoder.feed(b"li1e4:sp");
+        assert_values(&mut decoder, &[]);
+        assert_eq!(decoder.finish(), Err(Error::Incomplete));
+        decoder.feed(b"ame");
+        assert_values(&mut decoder, &[b"li1e4:spame"]);
+        assert_eq!(decoder.finish(), Ok(()));
+    }
+
+    #[test]
+    fn decoder_error() {
+        fn test(testdata: &[u8], expect: Error) {
+            let mut decoder = Decoder::new();
+            decoder.feed(testdata);
+            assert_eq!(decoder.try_next().map(|_| ()), Err(expect));
+        }
+
+        test(b"e", Error::InvalidValueType { value_type: b'e' });
+        test(b"lx", Error::InvalidValueType { value_type: b'x' });
+        test(
+            b"12x",
+            Error::InvalidByteStringLength {
+                length: "12".to_string(),
+            },
+        );
+        test(
+            b"99999999999999999999:",
+            Error::InvalidByteStringLength {
+                length: "99999999999999999999".to_string(),
+            },
+        );
+        // Errors detected by `Value::decode`.
+        test(
+            b"i1xe",
+            Error::InvalidInteger {
+                integer: "1x".to_string(),
+            },
+        );
+        test(
+            b"d1:bi1e1:ai2ee",
+            Error::NotStrictlyIncreasingDictionaryKey {
+                last_key: "b".to_string(),
+                new_key: "a".to_string(),
+            },
+        );
+    }
+}
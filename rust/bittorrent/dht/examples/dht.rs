@@ -1,5 +1,6 @@
 use std::io::Error;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::Arc;
 
@@ -25,6 +26,9 @@ struct Program {
 
     #[arg(long, global = true, default_value = "0.0.0.0:6881")]
     self_endpoint: SocketAddr,
+    /// Restores the routing table from and saves it to this file.
+    #[arg(long, global = true)]
+    nodes_path: Option<PathBuf>,
     #[command(subcommand)]
     command: Command,
 }
@@ -37,6 +41,7 @@ enum Command {
     AnnouncePeer(AnnouncePeer),
     LookupNodes(LookupNodes),
     LookupPeers(LookupPeers),
+    Scrape(Scrape),
     Serve,
 }
 
@@ -45,14 +50,21 @@ impl Program {
         let socket = UdpSocket::new(net::UdpSocket::bind(self.self_endpoint).await?);
         let self_endpoint = socket.socket().local_addr()?;
         let (stream, sink) = socket.into_split();
-        let (dht, mut dht_guard) = Dht::spawn(self_endpoint, stream, sink);
+        let (dht, mut dht_guard) = Dht::spawn(self_endpoint, None, stream, sink);
+        if let Some(nodes_path) = &self.nodes_path {
+            let nodes = bittorrent_dht::load_nodes(nodes_path).await?;
+            let num_nodes = nodes.len();
+            let num_inserted = dht.bootstrap_from(nodes).await;
+            eprintln!("restore routing table: {num_inserted}/{num_nodes} nodes");
+        }
         match &self.command {
-            Command::Ping(this) => this.execute(dht).await?,
-            Command::FindNode(this) => this.execute(dht).await?,
-            Command::GetPeers(this) => this.execute(dht).await?,
-            Command::AnnouncePeer(this) => this.execute(dht).await?,
-            Command::LookupNodes(this) => this.execute(dht).await?,
-            Command::LookupPeers(this) => this.execute(dht).await?,
+            Command::Ping(this) => this.execute(dht.clone()).await?,
+            Command::FindNode(this) => this.execute(dht.clone()).await?,
+            Command::GetPeers(this) => this.execute(dht.clone()).await?,
+            Command::AnnouncePeer(this) => this.execute(dht.clone()).await?,
+            Command::LookupNodes(this) => this.execute(dht.clone()).await?,
+            Command::LookupPeers(this) => this.execute(dht.clone()).await?,
+            Command::Scrape(this) => this.execute(dht.clone()).await?,
             Command::Serve => {
                 tokio::select! {
                     () = signal::ctrl_c().map(Result::unwrap) => eprintln!("ctrl-c received!"),
@@ -60,6 +72,9 @@ impl Program {
                 }
             }
         }
+        if let Some(nodes_path) = &self.nodes_path {
+            bittorrent_dht::save_nodes(nodes_path, &dht.export_nodes()).await?;
+        }
         dht_guard.shutdown().await?
     }
 }
@@ -142,6 +157,8 @@ struct AnnouncePeer {
     implied_port: Option<bool>,
     #[arg(value_parser = parse_token)]
     token: Arc<[u8]>,
+    #[arg(long)]
+    seed: bool,
 }
 
 impl AnnouncePeer {
@@ -152,6 +169,7 @@ impl AnnouncePeer {
             self.port,
             self.implied_port,
             &self.token,
+            self.seed,
         )
         .await
     }
@@ -185,14 +203,29 @@ impl LookupPeers {
             (
                 peers,
                 closest
-                    .as_ref()
-                    .map(|(node, token)| (node, Hex(token.as_ref()))),
+                    .iter()
+                    .map(|(node, token)| (node, Hex(token.as_ref())))
+                    .collect::<Vec<_>>(),
             ),
         );
         Ok(())
     }
 }
 
+#[derive(Args, Debug)]
+struct Scrape {
+    #[arg(value_parser = InfoHash::from_str)]
+    info_hash: InfoHash,
+}
+
+impl Scrape {
+    async fn execute(&self, dht: Dht) -> Result<(), Error> {
+        let (num_seeds, num_downloaders) = dht.scrape(self.info_hash.clone()).await;
+        println!("seeds={num_seeds} downloaders={num_downloaders}");
+        Ok(())
+    }
+}
+
 fn parse_node_id(hex: &str) -> Result<NodeId, Error> {
     Ok(NodeId::new(
         Hex::try_from(hex)
@@ -1,4 +1,4 @@
-use std::collections::{BTreeMap, BTreeSet};
+use std::collections::BTreeMap;
 use std::io::Error;
 use std::sync::Arc;
 
@@ -8,14 +8,18 @@ use tokio::sync::mpsc;
 use g1_base::{fmt::Hex, sync::MutexExt};
 use g1_tokio::task::Cancel;
 
+use bittorrent_base::InfoHash;
 use bittorrent_bencode::{borrow, serde as serde_bencode, FormatDictionary};
 
 use crate::{
+    item::{self, Item},
     kbucket::KBucketItem,
     message::{query, response, Message, MessageOwner, Payload},
     reqrep::{Endpoint, Sender},
     routing::KBucketFull,
+    security, stat,
     token::{Token, TokenSource},
+    NodeId, NodeIdBitSlice,
 };
 
 use super::NodeState;
@@ -87,14 +91,9 @@ impl Handler {
             tracing::trace!(query.extra = ?FormatDictionary(extra));
         }
 
-        {
-            let mut routing = self.state.routing.must_lock();
-            let item = KBucketItem::new((query.id().try_into().unwrap(), self.endpoint.0).into());
-            if let Err(full) = routing.insert(item) {
-                tracing::info!("kbucket full");
-                // We make our best effort to notify the server that a `KBucket` is full.
-                let _ = self.kbucket_full_send.try_send(full);
-            }
+        // BEP 43 specifies that read-only nodes should not be added to the routing table.
+        if self.request.deref().read_only != Some(true) {
+            self.insert_requester(query.id().try_into().unwrap());
         }
 
         match query {
@@ -102,6 +101,23 @@ impl Handler {
             query::Query::FindNode(find_node) => self.handle_find_node(find_node),
             query::Query::GetPeers(get_peers) => self.handle_get_peers(get_peers),
             query::Query::AnnouncePeer(announce_peer) => self.handle_announce_peer(announce_peer),
+            query::Query::Get(get) => self.handle_get(get),
+            query::Query::Put(put) => self.handle_put(put),
+        }
+    }
+
+    fn insert_requester(&self, id: NodeId) {
+        let ip = self.endpoint.0.ip();
+        if *crate::enforce_node_id() && !security::is_conformant(&id, ip) {
+            tracing::debug!(?id, "node id violates bep 42");
+            stat::add_nonconformant_node_id();
+            return;
+        }
+        let mut routing = self.state.routing(self.endpoint.0).must_lock();
+        if let Err(full) = routing.insert(KBucketItem::new((id, self.endpoint.0).into())) {
+            tracing::info!("kbucket full");
+            // We make our best effort to notify the server that a `KBucket` is full.
+            let _ = self.kbucket_full_send.try_send(full);
         }
     }
 
@@ -110,66 +126,180 @@ impl Handler {
     }
 
     fn handle_find_node(&self, find_node: &query::FindNode) -> Result<Bytes, Error> {
-        let nodes = self
-            .state
-            .routing
-            .must_lock()
-            .get_closest(find_node.target_bits());
-        let nodes = response::FindNode::encode_nodes_v4(nodes.iter()).freeze();
-        self.encode_response(response::FindNode::new(self.id(), &nodes))
+        let (nodes, nodes6) = self.get_closest(find_node.target_bits(), find_node.want.as_deref());
+        self.encode_response(response::FindNode::new(
+            self.id(),
+            nodes.as_deref(),
+            nodes6.as_deref(),
+        ))
     }
 
     fn handle_get_peers(&self, get_peers: &query::GetPeers) -> Result<Bytes, Error> {
         let token = self.generate_token();
-        let (values, nodes) = {
-            // You must maintain the locking order.
-            let routing = self.state.routing.must_lock();
-            let peers = self.state.peers.must_lock();
-            match peers.get(get_peers.info_hash) {
-                Some(peers) => (
-                    Some(response::GetPeers::encode_peers_v4(peers.iter().copied())),
-                    None,
-                ),
-                None => {
-                    let nodes = routing.get_closest(get_peers.info_hash_bits());
-                    let nodes = response::GetPeers::encode_nodes_v4(nodes.iter()).freeze();
-                    (None, Some(nodes))
-                }
-            }
+        // We return peers of the requester's address family only, which is what a BEP 5 node
+        // expects, and a dual-stack node announces itself on both families anyway.
+        let is_ipv6 = self.endpoint.0.is_ipv6();
+        let noseed = get_peers.noseed.unwrap_or(false);
+        let (values, filters) = match self.state.peers.must_lock().get(get_peers.info_hash) {
+            Some(swarm) => (
+                Some(response::GetPeers::encode_peers(
+                    swarm
+                        .peers
+                        .iter()
+                        .filter(|(peer, is_seed)| {
+                            peer.is_ipv6() == is_ipv6 && !(noseed && **is_seed)
+                        })
+                        .map(|(peer, _)| *peer),
+                ))
+                .filter(|values| !values.is_empty()),
+                get_peers
+                    .scrape
+                    .unwrap_or(false)
+                    .then(|| (swarm.seeds.clone(), swarm.downloaders.clone())),
+            ),
+            None => (None, None),
         };
-        self.encode_response(response::GetPeers::new(
+        let (nodes, nodes6) = match values {
+            Some(_) => (None, None),
+            None => self.get_closest(get_peers.info_hash_bits(), get_peers.want.as_deref()),
+        };
+        let mut response = response::GetPeers::new(
             self.id(),
             Some(&token),
             values
                 .as_ref()
                 .map(|values| values.iter().map(Bytes::as_ref).collect()),
             nodes.as_deref(),
-        ))
+            nodes6.as_deref(),
+        );
+        if let Some((seeds, downloaders)) = &filters {
+            response.seeds = Some(seeds.as_ref());
+            response.peers = Some(downloaders.as_ref());
+        }
+        self.encode_response(response)
     }
 
     fn handle_announce_peer(&self, announce_peer: &query::AnnouncePeer) -> Result<Bytes, Error> {
         if !self.validate_token(announce_peer.token) {
             tracing::warn!(announce_peer.token = ?Hex(announce_peer.token), "invalid token");
+            stat::add_invalid_token();
             return self.to_bytes(Payload::Error(response::Error::ProtocolError {
                 message: "invalid token",
             }));
         }
 
-        let info_hash = announce_peer.info_hash.try_into().unwrap();
+        let info_hash: InfoHash = announce_peer.info_hash.try_into().unwrap();
         let mut peer = self.endpoint.0;
         if !announce_peer.implied_port.unwrap_or(false) {
             peer.set_port(announce_peer.port);
         }
-        tracing::info!(?info_hash, ?peer, "accept announce_peer");
-        self.state
-            .peers
-            .must_lock()
-            .entry(info_hash)
-            .or_insert_with(BTreeSet::new)
-            .insert(peer);
+        let is_seed = announce_peer.seed.unwrap_or(false);
+        {
+            let mut peers = self.state.peers.must_lock();
+            let swarm = peers.entry(info_hash.clone()).or_default();
+            // We respond as usual so that a flooder cannot tell whether the swarm is full.
+            if swarm.peers.len() >= *crate::max_peers_per_info_hash()
+                && !swarm.peers.contains_key(&peer)
+            {
+                tracing::debug!(?info_hash, ?peer, "swarm full; reject announce_peer");
+                stat::add_rejected_peer();
+                return self.encode_response(response::AnnouncePeer::new(self.id()));
+            }
+            tracing::info!(?info_hash, ?peer, is_seed, "accept announce_peer");
+            swarm.peers.insert(peer, is_seed);
+            if is_seed {
+                swarm.seeds.insert(peer.ip());
+            } else {
+                swarm.downloaders.insert(peer.ip());
+            }
+        }
         self.encode_response(response::AnnouncePeer::new(self.id()))
     }
 
+    fn handle_get(&self, get: &query::Get) -> Result<Bytes, Error> {
+        let token = self.generate_token();
+        let (nodes, nodes6) = self.get_closest(get.target_bits(), get.want.as_deref());
+        let item = self.state.items.must_lock().get(get.target).cloned();
+        let (value, key, signature, seq) = match &item {
+            None => (None, None, None, None),
+            Some(item) => match &item.mutable {
+                None => (Some(&item.value), None, None, None),
+                // The requester already has the item; we send only the sequence number.
+                Some(mutable) if get.seq.is_some_and(|seq| seq >= mutable.seq) => {
+                    (None, None, None, Some(mutable.seq))
+                }
+                Some(mutable) => (
+                    Some(&item.value),
+                    Some(mutable.public_key.as_slice()),
+                    Some(mutable.signature.as_slice()),
+                    Some(mutable.seq),
+                ),
+            },
+        };
+        self.encode_response(response::Get::new(
+            self.id(),
+            Some(&token),
+            nodes.as_deref(),
+            nodes6.as_deref(),
+            // We can call `unwrap` because the item was verified when it was stored.
+            value.map(|value| borrow::Value::try_from(value.as_ref()).unwrap()),
+            key,
+            signature,
+            seq,
+        ))
+    }
+
+    fn handle_put(&self, put: &query::Put) -> Result<Bytes, Error> {
+        if !self.validate_token(put.token) {
+            tracing::warn!(put.token = ?Hex(put.token), "invalid token");
+            stat::add_invalid_token();
+            return self.to_bytes(Payload::Error(response::Error::ProtocolError {
+                message: "invalid token",
+            }));
+        }
+
+        let result = Item::from_parts(
+            put.value_bytes(),
+            put.key,
+            put.signature,
+            put.seq,
+            Bytes::copy_from_slice(put.salt.unwrap_or_default()),
+        )
+        .and_then(|item| {
+            item.verify()?;
+            self.state.items.must_lock().put(item, put.cas)
+        });
+        match result {
+            Ok(()) => {
+                tracing::info!("accept put");
+                self.encode_response(response::Put::new(self.id()))
+            }
+            Err(error) => {
+                tracing::warn!(%error, "reject put");
+                self.to_bytes(Payload::Error(to_error_response(&error)))
+            }
+        }
+    }
+
+    /// Returns the closest nodes in the compact form, per the address families that the
+    /// requester wants.
+    fn get_closest(
+        &self,
+        target: &NodeIdBitSlice,
+        want: Option<&[&[u8]]>,
+    ) -> (Option<Bytes>, Option<Bytes>) {
+        let (want_v4, want_v6) = query::want(want, self.endpoint.0);
+        let nodes = want_v4.then(|| {
+            let nodes = self.state.routing.must_lock().get_closest(target);
+            response::FindNode::encode_nodes_v4(nodes.iter()).freeze()
+        });
+        let nodes6 = want_v6.then(|| {
+            let nodes = self.state.routing_v6.must_lock().get_closest(target);
+            response::FindNode::encode_nodes_v6(nodes.iter()).freeze()
+        });
+        (nodes, nodes6)
+    }
+
     fn id(&self) -> &[u8] {
         self.state.self_id.as_ref()
     }
@@ -198,6 +328,35 @@ impl Handler {
     }
 }
 
+fn to_error_response(error: &item::Error) -> response::Error<'static> {
+    match error {
+        item::Error::ValueTooBig { .. } => response::Error::MessageTooBig {
+            message: "message (v field) too big",
+        },
+        item::Error::SaltTooBig { .. } => response::Error::SaltTooBig {
+            message: "salt (salt field) too big",
+        },
+        item::Error::InvalidPublicKey | item::Error::InvalidSignature => {
+            response::Error::InvalidSignature {
+                message: "invalid signature",
+            }
+        }
+        item::Error::CasMismatch { .. } => response::Error::CasMismatch {
+            message: "cas mismatch",
+        },
+        item::Error::SequenceNumberLessThanCurrent { .. } => {
+            response::Error::SequenceNumberLessThanCurrent {
+                message: "sequence number less than current",
+            }
+        }
+        item::Error::InvalidValue { .. }
+        | item::Error::MissingField { .. }
+        | item::Error::TargetMismatch => response::Error::ProtocolError {
+            message: "invalid item",
+        },
+    }
+}
+
 fn new_expect_query_error() -> response::Error<'static> {
     response::Error::ProtocolError {
         message: "expect query",
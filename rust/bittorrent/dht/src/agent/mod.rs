@@ -6,7 +6,7 @@
 mod handle;
 mod refresh;
 
-use std::collections::{BTreeSet, HashMap};
+use std::collections::{BTreeMap, HashMap};
 use std::io::Error;
 use std::net::SocketAddr;
 use std::panic;
@@ -14,7 +14,10 @@ use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
 use bitvec::prelude::*;
-use tokio::{sync::mpsc, time};
+use tokio::{
+    sync::{broadcast, mpsc},
+    time,
+};
 
 use g1_base::sync::MutexExt;
 use g1_tokio::task::{Cancel, JoinGuard, JoinQueue};
@@ -22,8 +25,13 @@ use g1_tokio::task::{Cancel, JoinGuard, JoinQueue};
 use bittorrent_base::InfoHash;
 
 use crate::{
-    reqrep::{Client, Incoming, ReqRep, Sender},
+    bloom::BloomFilter,
+    item::ItemStore,
+    limit::QueryLimiter,
+    reqrep::{Client, Incoming, ReqRep, RequesterSender, Sender},
     routing::{KBucketFull, KBucketPrefix, RoutingTable},
+    rtt::RttEstimator,
+    stat,
     token::TokenSource,
     NodeId, NODE_ID_SIZE,
 };
@@ -37,13 +45,29 @@ use self::{
 #[derive(Debug)]
 pub(crate) struct Agent {
     pub(crate) self_id: NodeId,
-    // NOTE: There is no deadlock because `Handler::handle_query` always acquires `routing` before
-    // `peers`.
+    // Whether we are listening on an IPv6 socket, which we assume to be dual-stack.
+    pub(crate) dual_stack: bool,
+    // NOTE: There is no deadlock because `Handler::handle_query` always acquires `routing` and
+    // `routing_v6` before `peers` and `items`.
     // TODO: Relying on this locking convention feels fragile.  What should we do instead?
     pub(crate) routing: Mutex<RoutingTable>,
-    // Use `BTreeSet` because it seems nicer to return an ordered peer set.
-    pub(crate) peers: Mutex<HashMap<InfoHash, BTreeSet<SocketAddr>>>,
+    // BEP 32 specifies that IPv6 nodes are kept in a separate routing table.
+    pub(crate) routing_v6: Mutex<RoutingTable>,
+    pub(crate) peers: Mutex<HashMap<InfoHash, Swarm>>,
+    pub(crate) items: Mutex<ItemStore>,
+    pub(crate) rtt: Mutex<RttEstimator>,
     pub(crate) reqrep: ReqRep,
+    pub(crate) requester_send: RequesterSender,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Swarm {
+    // Use `BTreeMap` because it seems nicer to return an ordered peer set.  The value is whether
+    // the peer is a seed.
+    pub(crate) peers: BTreeMap<SocketAddr, bool>,
+    // BEP 33 bloom filters of seeds and downloaders.
+    pub(crate) seeds: BloomFilter,
+    pub(crate) downloaders: BloomFilter,
 }
 
 pub(crate) type AgentGuard = JoinGuard<Result<(), Error>>;
@@ -56,33 +80,58 @@ struct Actor {
     cancel: Cancel,
     state: NodeState,
     token_src: Arc<TokenSource>,
+    query_limiter: QueryLimiter,
     // For now, we are spawning handlers and refreshers onto the same queue.
     tasks: JoinQueue<Result<(), Error>>,
     kbucket_full_recv: mpsc::Receiver<KBucketFull>,
     kbucket_full_send: mpsc::Sender<KBucketFull>,
     kbucket_refresh_period: Duration,
+    read_only: bool,
 }
 
 impl Agent {
-    pub(crate) fn spawn(self_id: NodeId, reqrep: ReqRep) -> (Arc<Self>, AgentGuard) {
-        tracing::info!(?self_id);
-        let this = Arc::new(Self::new(self_id, reqrep));
+    pub(crate) fn spawn(
+        self_id: NodeId,
+        dual_stack: bool,
+        reqrep: ReqRep,
+    ) -> (Arc<Self>, AgentGuard) {
+        tracing::info!(?self_id, dual_stack);
+        let this = Arc::new(Self::new(self_id, dual_stack, reqrep));
         let state = this.clone();
         let guard = JoinGuard::spawn(move |cancel| Actor::new(cancel, state).run());
         (this, guard)
     }
 
-    fn new(self_id: NodeId, reqrep: ReqRep) -> Self {
+    fn new(self_id: NodeId, dual_stack: bool, reqrep: ReqRep) -> Self {
         Self {
             self_id: self_id.clone(),
-            routing: Mutex::new(RoutingTable::new(self_id)),
+            dual_stack,
+            routing: Mutex::new(RoutingTable::new(self_id.clone())),
+            routing_v6: Mutex::new(RoutingTable::new(self_id)),
             peers: Mutex::new(HashMap::new()),
+            items: Mutex::new(ItemStore::new()),
+            rtt: Mutex::new(RttEstimator::new()),
             reqrep,
+            requester_send: broadcast::channel(*crate::requester_queue_size()).0,
         }
     }
 
     pub(crate) fn connect(&self, peer_endpoint: SocketAddr) -> Client {
-        Client::new(self.reqrep.clone(), self.self_id.clone(), peer_endpoint)
+        Client::new(
+            self.reqrep.clone(),
+            self.self_id.clone(),
+            self.dual_stack,
+            peer_endpoint,
+            self.requester_send.clone(),
+        )
+    }
+
+    /// Returns the routing table of the node's address family.
+    pub(crate) fn routing(&self, endpoint: SocketAddr) -> &Mutex<RoutingTable> {
+        match endpoint {
+            SocketAddr::V4(_) => &self.routing,
+            SocketAddr::V6(_) => &self.routing_v6,
+        }
     }
 }
 
@@ -94,10 +143,12 @@ impl Actor {
             cancel: cancel.clone(),
             state,
             token_src: Arc::new(TokenSource::new()),
+            query_limiter: QueryLimiter::new(),
             tasks: JoinQueue::with_cancel(cancel),
             kbucket_full_recv,
             kbucket_full_send,
             kbucket_refresh_period: *crate::refresh_period(),
+            read_only: *crate::read_only(),
         }
     }
 
@@ -110,7 +161,9 @@ impl Actor {
 
                 request = self.state.reqrep.accept() => {
                     let Some(request) = request else { break };
-                    self.spawn_handler(request);
+                    if self.admit(&request) {
+                        self.spawn_handler(request);
+                    }
                 }
                 guard = self.tasks.join_next() => {
                     let Some(guard) = guard else { break };
@@ -133,7 +186,21 @@ impl Actor {
         Ok(())
     }
 
+    fn admit(&mut self, ((endpoint, _), _): &(Incoming, Sender)) -> bool {
+        let admitted = self.query_limiter.admit(endpoint.0.ip(), Instant::now());
+        if !admitted {
+            tracing::debug!(?endpoint, "rate limit exceeded; drop request");
+            stat::add_rate_limited_query();
+        }
+        admitted
+    }
+
     fn spawn_handler(&self, ((endpoint, request), response_send): (Incoming, Sender)) {
+        // BEP 43 specifies that a read-only node does not respond to queries.
+        if self.read_only {
+            tracing::trace!(?endpoint, "read-only mode; ignore request");
+            return;
+        }
         self.push_task(JoinGuard::spawn(move |cancel| {
             Handler::new(
                 cancel,
@@ -157,10 +224,12 @@ impl Actor {
     fn spawn_kbucket_refresher(&self, now: Instant) {
         let mut ids = Vec::new();
         let should_refresh = now - self.kbucket_refresh_period;
-        for (kbucket, prefix) in self.state.routing.must_lock().iter() {
-            if let Some(recently_seen) = kbucket.recently_seen() {
-                if recently_seen <= should_refresh {
-                    ids.push(random_id(prefix));
+        for routing in [&self.state.routing, &self.state.routing_v6] {
+            for (kbucket, prefix) in routing.must_lock().iter() {
+                if let Some(recently_seen) = kbucket.recently_seen() {
+                    if recently_seen <= should_refresh {
+                        ids.push(random_id(prefix));
+                    }
                 }
             }
         }
@@ -183,7 +252,7 @@ impl Actor {
 }
 
 fn random_id(prefix: KBucketPrefix) -> NodeId {
-    let mut id: [u8; NODE_ID_SIZE] = rand::random();
+    let mut id: [u8; NODE_ID_SIZE] = g1_base::rand::random();
     id.view_bits_mut()[0..prefix.len()].copy_from_bitslice(&prefix);
     NodeId::new(id)
 }
@@ -90,14 +90,20 @@ impl NodeRefresher {
                     %error,
                     "ping node error; remove from routing table",
                 );
-                if state.routing.must_lock().remove(&incumbent).is_some() {
+                if state
+                    .routing(incumbent.endpoint)
+                    .must_lock()
+                    .remove(&incumbent)
+                    .is_some()
+                {
                     have_removed_nodes = true;
                 }
             }
         }
 
         if have_removed_nodes {
-            if let Err((_, candidate)) = state.routing.must_lock().insert(candidate) {
+            let routing = state.routing(candidate.contact_info.endpoint);
+            if let Err((_, candidate)) = routing.must_lock().insert(candidate) {
                 tracing::warn!(
                     candidate = ?candidate.contact_info,
                     "discard candidate because kbucket is still full after removing nodes",
@@ -136,11 +142,11 @@ impl KBucketRefresher {
                 }
                 nodes = lookup_nodes => nodes,
             };
-            {
-                let mut routing = state.routing.must_lock();
-                for node in nodes {
-                    routing.must_insert(KBucketItem::new(node));
-                }
+            for node in nodes {
+                state
+                    .routing(node.endpoint)
+                    .must_lock()
+                    .must_insert(KBucketItem::new(node));
             }
         }
         Ok(())
@@ -0,0 +1,116 @@
+//! BEP 33 Bloom Filter of Peer IP Addresses
+
+use std::net::IpAddr;
+
+use sha1::{Digest, Sha1};
+
+use g1_base::fmt::{DebugExt, Hex};
+
+pub(crate) const BLOOM_FILTER_SIZE: usize = 256;
+
+// BEP 33 specifies `m = 2048` and `k = 2`.
+const M: usize = BLOOM_FILTER_SIZE * 8;
+const K: f64 = 2.0;
+
+#[derive(Clone, DebugExt, Eq, PartialEq)]
+pub(crate) struct BloomFilter(#[debug(with = Hex)] [u8; BLOOM_FILTER_SIZE]);
+
+impl Default for BloomFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for BloomFilter {
+    type Error = &'a [u8];
+
+    fn try_from(bits: &'a [u8]) -> Result<Self, Self::Error> {
+        Ok(Self(bits.try_into().map_err(|_| bits)?))
+    }
+}
+
+impl AsRef<[u8]> for BloomFilter {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl BloomFilter {
+    pub(crate) fn new() -> Self {
+        Self([0; BLOOM_FILTER_SIZE])
+    }
+
+    pub(crate) fn insert(&mut self, ip: IpAddr) {
+        let hash = match ip {
+            IpAddr::V4(ip) => Sha1::digest(ip.octets()),
+            IpAddr::V6(ip) => Sha1::digest(ip.octets()),
+        };
+        for index in [
+            usize::from(hash[0]) | (usize::from(hash[1]) << 8),
+            usize::from(hash[2]) | (usize::from(hash[3]) << 8),
+        ] {
+            let index = index % M;
+            self.0[index / 8] |= 1 << (index % 8);
+        }
+    }
+
+    pub(crate) fn union(&mut self, other: &Self) {
+        for (x, y) in self.0.iter_mut().zip(other.0.iter()) {
+            *x |= y;
+        }
+    }
+
+    /// Estimates the number of distinct IP addresses that have been inserted.
+    pub(crate) fn estimate(&self) -> f64 {
+        let num_zeros: u32 = self.0.iter().map(|x| x.count_zeros()).sum();
+        // Clamp it so that a saturated filter does not yield an infinite estimate.
+        let c = f64::from(num_zeros.max(1));
+        let m = M as f64;
+        (c / m).ln() / (K * (1.0 - 1.0 / m).ln())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+
+    #[test]
+    fn estimate() {
+        let mut filter = BloomFilter::new();
+        assert_eq!(filter.estimate(), 0.0);
+
+        // Test vector of BEP 33.
+        for i in 0..=255 {
+            filter.insert(Ipv4Addr::new(192, 0, 2, i).into());
+        }
+        for i in 0..=0x3e7 {
+            filter.insert(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, i).into());
+        }
+        assert!(
+            (filter.estimate() - 1224.9308).abs() < 0.0001,
+            "{}",
+            filter.estimate(),
+        );
+
+        let mut other = BloomFilter::new();
+        other.union(&filter);
+        assert_eq!(other, filter);
+
+        let filter = BloomFilter([0xff; BLOOM_FILTER_SIZE]);
+        assert!(filter.estimate().is_finite());
+    }
+
+    #[test]
+    fn try_from() {
+        assert_eq!(
+            BloomFilter::try_from([0u8; BLOOM_FILTER_SIZE].as_slice()),
+            Ok(BloomFilter::new()),
+        );
+        assert_eq!(
+            BloomFilter::try_from([0u8; 3].as_slice()),
+            Err([0u8; 3].as_slice()),
+        );
+    }
+}
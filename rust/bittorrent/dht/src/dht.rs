@@ -1,19 +1,25 @@
 use std::io::Error;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 
 use bytes::Bytes;
 use futures::{sink::Sink, stream::Stream};
+use tokio::sync::broadcast;
 
-use g1_tokio::task::JoinArray;
+use ed25519_dalek::PUBLIC_KEY_LENGTH;
+
+use g1_base::sync::MutexExt;
+use g1_tokio::task::{JoinArray, Joiner};
 
 use bittorrent_base::InfoHash;
 
 use crate::{
     agent::Agent,
-    lookup::{Lookup, LookupPeers},
-    reqrep::{self, GetPeers, Nodes},
-    NodeId,
+    item::{self, Item},
+    kbucket::KBucketItem,
+    lookup::{Lookup, LookupItem, LookupPeers, LookupScrape},
+    reqrep::{self, GetItem, GetPeers, Nodes},
+    security, NodeContactInfo, NodeId,
 };
 
 #[derive(Clone, Debug)]
@@ -25,8 +31,13 @@ pub struct Dht {
 pub type DhtGuard = JoinArray<Result<(), Error>, 2>;
 
 impl Dht {
+    /// Spawns a DHT node.
+    ///
+    /// When our external IP address is known, e.g., from port mapping, the node id is made to
+    /// conform to BEP 42 for it; otherwise, the node id is used as is.
     pub fn spawn<Incoming, Outgoing>(
         self_endpoint: SocketAddr,
+        external_ip: Option<IpAddr>,
         incoming: Incoming,
         outgoing: Outgoing,
     ) -> (Self, DhtGuard)
@@ -34,8 +45,14 @@ impl Dht {
         Incoming: Stream<Item = Result<(SocketAddr, Bytes), Error>> + Send + Unpin + 'static,
         Outgoing: Sink<(SocketAddr, Bytes), Error = Error> + Send + Unpin + 'static,
     {
-        let (reqrep, reqrep_guard) = reqrep::spawn(incoming, outgoing);
-        let (agent, agent_guard) = Agent::spawn(crate::self_id().clone(), reqrep);
+        // We assume that an IPv6 socket is dual-stack, i.e., `IPV6_V6ONLY` is not set.
+        let dual_stack = self_endpoint.is_ipv6();
+        let (reqrep, reqrep_guard) = reqrep::spawn(incoming, outgoing, dual_stack);
+        let self_id = match external_ip {
+            Some(external_ip) => security::make_conformant(crate::self_id(), external_ip),
+            None => crate::self_id().clone(),
+        };
+        let (agent, agent_guard) = Agent::spawn(self_id, dual_stack, reqrep);
         (
             Self {
                 self_endpoint,
@@ -53,6 +70,59 @@ impl Dht {
         self.self_endpoint
     }
 
+    /// Subscribes to `(node_endpoint, self_endpoint)`, where `self_endpoint` is our endpoint as
+    /// the node sees it (the BEP 42 `ip` field of responses).
+    pub fn subscribe_requester(&self) -> broadcast::Receiver<(SocketAddr, SocketAddr)> {
+        self.agent.requester_send.subscribe()
+    }
+
+    /// Returns the nodes of the routing tables, which may be persisted with `save_nodes`.
+    pub fn export_nodes(&self) -> Vec<NodeContactInfo> {
+        let mut nodes = Vec::new();
+        for routing in [&self.agent.routing, &self.agent.routing_v6] {
+            for (kbucket, _) in routing.must_lock().iter() {
+                nodes.extend(kbucket.iter().cloned());
+            }
+        }
+        nodes
+    }
+
+    /// Pings the nodes, which are typically restored by `load_nodes`, and inserts the responsive
+    /// ones into the routing tables, so that lookups do not have to bootstrap from the routers.
+    ///
+    /// It returns the number of nodes inserted.
+    pub async fn bootstrap_from(&self, nodes: Vec<NodeContactInfo>) -> usize {
+        let pings: Vec<_> = nodes
+            .into_iter()
+            // We cannot reach IPv6 nodes from an IPv4 socket.
+            .filter(|node| self.self_endpoint.is_ipv6() || node.endpoint.is_ipv4())
+            .map(|node| {
+                let agent = self.agent.clone();
+                async move {
+                    let result = agent.connect(node.endpoint).ping().await;
+                    if let Err(error) = &result {
+                        tracing::debug!(?node, %error, "bootstrap_from ping error");
+                    }
+                    result.ok().map(|()| node)
+                }
+            })
+            .collect();
+
+        let mut num_inserted = 0;
+        let mut tasks = Joiner::new(pings, *crate::alpha());
+        while let Some(join_result) = tasks.join_next().await {
+            // We can call `unwrap` because we do not expect tasks to crash.
+            if let Some(node) = join_result.unwrap() {
+                self.agent
+                    .routing(node.endpoint)
+                    .must_lock()
+                    .must_insert(KBucketItem::new(node));
+                num_inserted += 1;
+            }
+        }
+        num_inserted
+    }
+
     pub async fn ping(&self, peer_endpoint: SocketAddr) -> Result<(), Error> {
         self.agent.connect(peer_endpoint).ping().await
     }
@@ -80,10 +150,40 @@ impl Dht {
         port: u16,
         implied_port: Option<bool>,
         token: &[u8],
+        seed: bool,
     ) -> Result<(), Error> {
         self.agent
             .connect(peer_endpoint)
-            .announce_peer(info_hash, port, implied_port, token)
+            .announce_peer(info_hash, port, implied_port, token, seed)
+            .await
+    }
+
+    /// Sends a `get` query, where `salt` is used to verify the mutable item in the response.
+    pub async fn get(
+        &self,
+        peer_endpoint: SocketAddr,
+        target: &[u8],
+        salt: Bytes,
+        seq: Option<i64>,
+    ) -> Result<GetItem, Error> {
+        let (token, item, nodes) = self.agent.connect(peer_endpoint).get(target, seq).await?;
+        let item = item
+            .map(|item| item.verify_get(target, salt))
+            .transpose()
+            .map_err(Error::other)?;
+        Ok((token, item, nodes))
+    }
+
+    pub async fn put(
+        &self,
+        peer_endpoint: SocketAddr,
+        token: &[u8],
+        item: &Item,
+        cas: Option<i64>,
+    ) -> Result<(), Error> {
+        self.agent
+            .connect(peer_endpoint)
+            .put(token, item, cas)
             .await
     }
 
@@ -96,4 +196,60 @@ impl Dht {
             .lookup_peers(info_hash)
             .await
     }
+
+    /// Estimates the numbers of seeds and downloaders of the swarm (BEP 33).
+    pub async fn scrape(&self, info_hash: InfoHash) -> LookupScrape {
+        Lookup::new(self.agent.clone()).scrape(info_hash).await
+    }
+
+    pub async fn lookup_immutable_item(&self, target: NodeId) -> LookupItem {
+        Lookup::new(self.agent.clone())
+            .lookup_item(target, None)
+            .await
+    }
+
+    pub async fn lookup_mutable_item(
+        &self,
+        public_key: &[u8; PUBLIC_KEY_LENGTH],
+        salt: Bytes,
+    ) -> LookupItem {
+        let target = NodeId::new(item::mutable_target(public_key, &salt));
+        Lookup::new(self.agent.clone())
+            .lookup_item(target, Some(salt))
+            .await
+    }
+
+    /// Stores the item on the closest nodes and returns the number of nodes that accept it.
+    pub async fn put_item(&self, item: Item, cas: Option<i64>) -> usize {
+        let salt = item.mutable().map(|mutable| mutable.salt().clone());
+        let (_, nodes) = Lookup::new(self.agent.clone())
+            .lookup_item(NodeId::new(item.target()), salt)
+            .await;
+
+        let item = Arc::new(item);
+        let puts: Vec<_> = nodes
+            .into_iter()
+            .map(|(node, token)| {
+                let agent = self.agent.clone();
+                let item = item.clone();
+                async move {
+                    let result = agent.connect(node.endpoint).put(&token, &item, cas).await;
+                    if let Err(error) = &result {
+                        tracing::warn!(?node, %error, "put error");
+                    }
+                    result.is_ok()
+                }
+            })
+            .collect();
+
+        let mut num_stored = 0;
+        let mut tasks = Joiner::new(puts, *crate::alpha());
+        while let Some(join_result) = tasks.join_next().await {
+            // We can call `unwrap` because we do not expect tasks to crash.
+            if join_result.unwrap() {
+                num_stored += 1;
+            }
+        }
+        num_stored
+    }
 }
@@ -0,0 +1,468 @@
+//! BEP 44 Storing Arbitrary Data in the DHT
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use ed25519_dalek::{
+    Signature, Signer, SigningKey, VerifyingKey, PUBLIC_KEY_LENGTH, SIGNATURE_LENGTH,
+};
+use sha1::{Digest, Sha1};
+use snafu::prelude::*;
+
+use g1_base::fmt::{DebugExt, Hex};
+
+use bittorrent_bencode::borrow;
+
+use crate::NODE_ID_SIZE;
+
+const VALUE_SIZE_LIMIT: usize = 1000;
+const SALT_SIZE_LIMIT: usize = 64;
+
+/// Item
+///
+/// An immutable item is keyed by the SHA-1 hash of its value, and a mutable item is keyed by the
+/// SHA-1 hash of its public key and salt.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Item {
+    // The Bencode-encoded value.
+    pub(crate) value: Bytes,
+    pub(crate) mutable: Option<MutableItem>,
+}
+
+#[derive(Clone, DebugExt, Eq, PartialEq)]
+pub struct MutableItem {
+    #[debug(with = Hex)]
+    pub(crate) public_key: [u8; PUBLIC_KEY_LENGTH],
+    pub(crate) salt: Bytes,
+    pub(crate) seq: i64,
+    #[debug(with = Hex)]
+    pub(crate) signature: [u8; SIGNATURE_LENGTH],
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Snafu)]
+pub enum Error {
+    #[snafu(display("expect value size <= {VALUE_SIZE_LIMIT}: {size}"))]
+    ValueTooBig { size: usize },
+    #[snafu(display("invalid bencode value: {source}"))]
+    InvalidValue { source: bittorrent_bencode::Error },
+    #[snafu(display("expect salt size <= {SALT_SIZE_LIMIT}: {size}"))]
+    SaltTooBig { size: usize },
+    #[snafu(display("missing mutable item field: \"{field}\""))]
+    MissingField { field: &'static str },
+    #[snafu(display("invalid public key"))]
+    InvalidPublicKey,
+    #[snafu(display("invalid signature"))]
+    InvalidSignature,
+    #[snafu(display("target mismatch"))]
+    TargetMismatch,
+    #[snafu(display("cas mismatch: cas={cas} seq={seq}"))]
+    CasMismatch { cas: i64, seq: i64 },
+    #[snafu(display("expect seq > {current}: {seq}"))]
+    SequenceNumberLessThanCurrent { seq: i64, current: i64 },
+}
+
+impl Item {
+    /// Creates an immutable item from a Bencode-encoded value.
+    pub fn new_immutable(value: Bytes) -> Result<Self, Error> {
+        let this = Self {
+            value,
+            mutable: None,
+        };
+        this.verify()?;
+        Ok(this)
+    }
+
+    /// Creates a mutable item from a Bencode-encoded value and signs it.
+    pub fn new_mutable(
+        signing_key: &SigningKey,
+        salt: Bytes,
+        seq: i64,
+        value: Bytes,
+    ) -> Result<Self, Error> {
+        check_value(&value)?;
+        check_salt(&salt)?;
+        let signature = signing_key.sign(&signature_message(&salt, seq, &value));
+        Ok(Self {
+            value,
+            mutable: Some(MutableItem {
+                public_key: signing_key.verifying_key().to_bytes(),
+                salt,
+                seq,
+                signature: signature.to_bytes(),
+            }),
+        })
+    }
+
+    /// Assembles an item from the fields of a `get` response or a `put` query.
+    pub(crate) fn from_parts(
+        value: Bytes,
+        public_key: Option<&[u8]>,
+        signature: Option<&[u8]>,
+        seq: Option<i64>,
+        salt: Bytes,
+    ) -> Result<Self, Error> {
+        let Some(public_key) = public_key else {
+            return Ok(Self {
+                value,
+                mutable: None,
+            });
+        };
+        // The lengths of `public_key` and `signature` are checked by the message decoder.
+        Ok(Self {
+            value,
+            mutable: Some(MutableItem {
+                public_key: public_key.try_into().unwrap(),
+                salt,
+                seq: seq.context(MissingFieldSnafu { field: "seq" })?,
+                signature: signature
+                    .context(MissingFieldSnafu { field: "sig" })?
+                    .try_into()
+                    .unwrap(),
+            }),
+        })
+    }
+
+    pub fn value(&self) -> &Bytes {
+        &self.value
+    }
+
+    pub fn mutable(&self) -> Option<&MutableItem> {
+        self.mutable.as_ref()
+    }
+
+    pub fn target(&self) -> [u8; NODE_ID_SIZE] {
+        match &self.mutable {
+            Some(mutable) => mutable_target(&mutable.public_key, &mutable.salt),
+            None => Sha1::digest(&self.value).into(),
+        }
+    }
+
+    pub fn verify(&self) -> Result<(), Error> {
+        check_value(&self.value)?;
+        if let Some(mutable) = &self.mutable {
+            check_salt(&mutable.salt)?;
+            VerifyingKey::from_bytes(&mutable.public_key)
+                .map_err(|_| Error::InvalidPublicKey)?
+                .verify_strict(
+                    &signature_message(&mutable.salt, mutable.seq, &self.value),
+                    &Signature::from_bytes(&mutable.signature),
+                )
+                .map_err(|_| Error::InvalidSignature)?;
+        }
+        Ok(())
+    }
+
+    /// Verifies an item received from a `get` response.
+    ///
+    /// The response does not carry the salt, which the requester has to supply.
+    pub(crate) fn verify_get(mut self, target: &[u8], salt: Bytes) -> Result<Self, Error> {
+        if let Some(mutable) = &mut self.mutable {
+            mutable.salt = salt;
+        }
+        self.verify()?;
+        ensure!(self.target() == target, TargetMismatchSnafu);
+        Ok(self)
+    }
+}
+
+impl MutableItem {
+    pub fn public_key(&self) -> &[u8; PUBLIC_KEY_LENGTH] {
+        &self.public_key
+    }
+
+    pub fn salt(&self) -> &Bytes {
+        &self.salt
+    }
+
+    pub fn seq(&self) -> i64 {
+        self.seq
+    }
+
+    pub fn signature(&self) -> &[u8; SIGNATURE_LENGTH] {
+        &self.signature
+    }
+}
+
+pub(crate) fn mutable_target(public_key: &[u8], salt: &[u8]) -> [u8; NODE_ID_SIZE] {
+    let mut hasher = Sha1::new();
+    hasher.update(public_key);
+    hasher.update(salt);
+    hasher.finalize().into()
+}
+
+fn check_value(value: &[u8]) -> Result<(), Error> {
+    ensure!(
+        value.len() <= VALUE_SIZE_LIMIT,
+        ValueTooBigSnafu { size: value.len() },
+    );
+    borrow::Value::<true>::try_from(value).context(InvalidValueSnafu)?;
+    Ok(())
+}
+
+fn check_salt(salt: &[u8]) -> Result<(), Error> {
+    ensure!(
+        salt.len() <= SALT_SIZE_LIMIT,
+        SaltTooBigSnafu { size: salt.len() },
+    );
+    Ok(())
+}
+
+/// Returns the data to be signed, which is the Bencode-encoded dictionary of `salt` (if not
+/// empty), `seq`, and `v`, without the enclosing "d" and "e".
+fn signature_message(salt: &[u8], seq: i64, value: &[u8]) -> BytesMut {
+    let mut buffer = BytesMut::new();
+    if !salt.is_empty() {
+        buffer.put_slice(format!("4:salt{}:", salt.len()).as_bytes());
+        buffer.put_slice(salt);
+    }
+    buffer.put_slice(format!("3:seqi{seq}e1:v").as_bytes());
+    buffer.put_slice(value);
+    buffer
+}
+
+/// Items that peers store on our node.
+#[derive(Debug)]
+pub(crate) struct ItemStore {
+    items: HashMap<[u8; NODE_ID_SIZE], (Item, Instant)>,
+    capacity: usize,
+    expiration: Duration,
+}
+
+impl ItemStore {
+    pub(crate) fn new() -> Self {
+        Self::with_config(*crate::item_store_capacity(), *crate::item_expiration())
+    }
+
+    fn with_config(capacity: usize, expiration: Duration) -> Self {
+        Self {
+            items: HashMap::new(),
+            capacity,
+            expiration,
+        }
+    }
+
+    pub(crate) fn get(&self, target: &[u8]) -> Option<&Item> {
+        let (item, put_at) = self.items.get(target)?;
+        (put_at.elapsed() < self.expiration).then_some(item)
+    }
+
+    /// Stores a verified item.
+    ///
+    /// For a mutable item, `cas` is the sequence number that the putter expects the stored item to
+    /// have.
+    pub(crate) fn put(&mut self, item: Item, cas: Option<i64>) -> Result<(), Error> {
+        let target = item.target();
+        if let (Some(new), Some(current)) = (&item.mutable, self.get(&target)) {
+            let current_seq = current
+                .mutable
+                .as_ref()
+                .map_or(i64::MIN, |current| current.seq);
+            if let Some(cas) = cas {
+                ensure!(
+                    cas == current_seq,
+                    CasMismatchSnafu {
+                        cas,
+                        seq: current_seq,
+                    },
+                );
+            }
+            // An item of the same sequence number is accepted only when its value is unchanged,
+            // which merely refreshes its expiration.
+            ensure!(
+                new.seq > current_seq || (new.seq == current_seq && item.value == current.value),
+                SequenceNumberLessThanCurrentSnafu {
+                    seq: new.seq,
+                    current: current_seq,
+                },
+            );
+        }
+
+        if !self.items.contains_key(&target) && self.items.len() >= self.capacity {
+            self.evict();
+        }
+        self.items.insert(target, (item, Instant::now()));
+        Ok(())
+    }
+
+    /// Removes expired items, or the oldest item if none has expired.
+    fn evict(&mut self) {
+        let expiration = self.expiration;
+        self.items
+            .retain(|_, (_, put_at)| put_at.elapsed() < expiration);
+        if self.items.len() < self.capacity {
+            return;
+        }
+        tracing::debug!("item store full");
+        if let Some(target) = self
+            .items
+            .iter()
+            .min_by_key(|(_, (_, put_at))| *put_at)
+            .map(|(target, _)| *target)
+        {
+            self.items.remove(&target);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use super::*;
+
+    // Test vectors from BEP 44.
+    const PUBLIC_KEY: [u8; PUBLIC_KEY_LENGTH] =
+        hex!("77ff84905a91936367c01360803104f92432fcd904a43511876df5cdf3e7e548");
+    const VALUE: &[u8] = b"12:Hello World!";
+
+    fn new_mutable(salt: &'static [u8], signature: [u8; SIGNATURE_LENGTH]) -> Item {
+        Item {
+            value: Bytes::from_static(VALUE),
+            mutable: Some(MutableItem {
+                public_key: PUBLIC_KEY,
+                salt: Bytes::from_static(salt),
+                seq: 1,
+                signature,
+            }),
+        }
+    }
+
+    #[test]
+    fn immutable() {
+        let item = Item::new_immutable(Bytes::from_static(VALUE)).unwrap();
+        assert_eq!(
+            item.target(),
+            hex!("e5f96f6f38320f0f33959cb4d3d656452117aadb"),
+        );
+        assert_eq!(item.verify(), Ok(()));
+
+        assert_eq!(
+            Item::new_immutable(Bytes::from_static(b"12:Hello")),
+            Err(Error::InvalidValue {
+                source: bittorrent_bencode::Error::Incomplete,
+            }),
+        );
+        assert_eq!(
+            Item::new_immutable(Bytes::from(format!("1001:{}", "x".repeat(1001)))),
+            Err(Error::ValueTooBig { size: 1006 }),
+        );
+    }
+
+    #[test]
+    fn mutable() {
+        let item = new_mutable(
+            b"",
+            hex!(
+                "305ac8aeb6c9c151fa120f120ea2cfb923564e11552d06a5d856091e5e853cff"
+                "1260d3f39e4999684aa92eb73ffd136e6f4f3ecbfda0ce53a1608ecd7ae21f01"
+            ),
+        );
+        assert_eq!(
+            item.target(),
+            hex!("4a533d47ec9c7d95b1ad75f576cffc641853b750"),
+        );
+        assert_eq!(item.verify(), Ok(()));
+
+        let item = new_mutable(
+            b"foobar",
+            hex!(
+                "6834284b6b24c3204eb2fea824d82f88883a3d95e8b4a21b8c0ded553d17d17d"
+                "df9a8a7104b1258f30bed3787e6cb896fca78c58f8e03b5f18f14951a87d9a08"
+            ),
+        );
+        assert_eq!(
+            item.target(),
+            hex!("411eba73b6f087ca51a3795d9c8c938d365e32c1"),
+        );
+        assert_eq!(item.verify(), Ok(()));
+
+        let mut item = item;
+        item.mutable.as_mut().unwrap().seq = 2;
+        assert_eq!(item.verify(), Err(Error::InvalidSignature));
+    }
+
+    #[test]
+    fn sign() {
+        let signing_key = SigningKey::from_bytes(&[1; 32]);
+        let item = Item::new_mutable(
+            &signing_key,
+            Bytes::from_static(b"foobar"),
+            3,
+            Bytes::from_static(VALUE),
+        )
+        .unwrap();
+        assert_eq!(item.verify(), Ok(()));
+        let target = item.target();
+
+        let mut other = item.clone();
+        other.mutable.as_mut().unwrap().salt = Bytes::new();
+        assert_eq!(
+            other
+                .clone()
+                .verify_get(&target, Bytes::from_static(b"foobar")),
+            Ok(item.clone()),
+        );
+        assert_eq!(
+            other.verify_get(&target, Bytes::new()),
+            Err(Error::InvalidSignature),
+        );
+
+        assert_eq!(
+            Item::new_mutable(
+                &signing_key,
+                Bytes::from(vec![0; 65]),
+                3,
+                Bytes::from_static(VALUE),
+            ),
+            Err(Error::SaltTooBig { size: 65 }),
+        );
+    }
+
+    #[test]
+    fn item_store() {
+        let signing_key = SigningKey::from_bytes(&[1; 32]);
+        let new_item = |seq, value| {
+            Item::new_mutable(&signing_key, Bytes::new(), seq, Bytes::from_static(value)).unwrap()
+        };
+        let item_1 = new_item(1, b"1:a");
+        let target = item_1.target();
+
+        let mut store = ItemStore::with_config(2, Duration::from_secs(60));
+        assert_eq!(store.get(&target), None);
+        assert_eq!(store.put(item_1.clone(), None), Ok(()));
+        assert_eq!(store.get(&target), Some(&item_1));
+
+        assert_eq!(store.put(item_1.clone(), None), Ok(()));
+        assert_eq!(
+            store.put(new_item(1, b"1:b"), None),
+            Err(Error::SequenceNumberLessThanCurrent { seq: 1, current: 1 }),
+        );
+        assert_eq!(
+            store.put(new_item(0, b"1:b"), None),
+            Err(Error::SequenceNumberLessThanCurrent { seq: 0, current: 1 }),
+        );
+        assert_eq!(
+            store.put(new_item(2, b"1:b"), Some(0)),
+            Err(Error::CasMismatch { cas: 0, seq: 1 }),
+        );
+        let item_2 = new_item(2, b"1:b");
+        assert_eq!(store.put(item_2.clone(), Some(1)), Ok(()));
+        assert_eq!(store.get(&target), Some(&item_2));
+
+        let immutable_1 = Item::new_immutable(Bytes::from_static(b"1:x")).unwrap();
+        let immutable_2 = Item::new_immutable(Bytes::from_static(b"1:y")).unwrap();
+        assert_eq!(store.put(immutable_1.clone(), None), Ok(()));
+        assert_eq!(store.items.len(), 2);
+        // The store evicts an item when it is full.
+        assert_eq!(store.put(immutable_2.clone(), None), Ok(()));
+        assert_eq!(store.items.len(), 2);
+        assert_eq!(store.get(&immutable_2.target()), Some(&immutable_2));
+
+        let mut store = ItemStore::with_config(2, Duration::ZERO);
+        assert_eq!(store.put(item_1.clone(), None), Ok(()));
+        assert_eq!(store.get(&target), None);
+        // An expired item does not constrain the sequence number.
+        assert_eq!(store.put(new_item(0, b"1:b"), None), Ok(()));
+    }
+}
@@ -1,5 +1,4 @@
 //! Distributed Hash Table
-// TODO: Implement BEP 32 IPv6 extension for DHT.
 
 #![feature(iterator_try_collect)]
 #![feature(result_flattening)]
@@ -8,12 +7,19 @@
 #![cfg_attr(test, feature(generic_arg_infer))]
 
 mod agent;
+mod bloom;
 mod dht;
+mod item;
 mod kbucket;
+mod limit;
 mod lookup;
 mod message;
+mod persist;
 mod reqrep;
 mod routing;
+mod rtt;
+mod security;
+mod stat;
 mod token;
 
 use std::array::TryFromSliceError;
@@ -29,6 +35,9 @@ use g1_base::fmt::{DebugExt, Hex};
 use bittorrent_base::{INFO_HASH_SIZE, NODE_ID_SIZE};
 
 pub use self::dht::{Dht, DhtGuard};
+pub use self::item::{Error as ItemError, Item, MutableItem};
+pub use self::persist::{load_nodes, save_nodes};
+pub use self::stat::{abuse, Abuse};
 
 // Our code is written under this assumption.
 #[allow(clippy::assertions_on_constants)]
@@ -36,6 +45,25 @@ const _: () = assert!(INFO_HASH_SIZE == NODE_ID_SIZE);
 
 g1_param::define!(k: usize = 20);
 g1_param::define!(alpha: usize = 16);
+
+// Lookup tuning.  Setting a per-node timeout shorter than `g1_msg`'s `request_timeout` makes a
+// lookup give up on slow nodes sooner.
+g1_param::define!(
+    lookup_timeout: Duration = Duration::from_secs(2);
+    parse = g1_param::parse::duration;
+);
+// A lookup terminates after this many consecutive rounds that do not change the closest nodes.
+g1_param::define!(lookup_stall_limit: usize = 1);
+// In the adaptive mode, the per-node timeout is derived from the observed RTT distribution:
+// `clamp(quantile * multiplier, min_timeout, lookup_timeout)`.
+g1_param::define!(lookup_adaptive: bool = false);
+g1_param::define!(lookup_adaptive_quantile: f64 = 0.9);
+g1_param::define!(lookup_adaptive_multiplier: f64 = 1.5);
+g1_param::define!(
+    lookup_adaptive_min_timeout: Duration = Duration::from_millis(200);
+    parse = g1_param::parse::duration;
+);
+g1_param::define!(rtt_num_samples: usize = 256);
 g1_param::define!(bootstrap: Vec<String> = vec![
     "router.bittorrent.com:6881".to_string(),
     "router.utorrent.com:6881".to_string(),
@@ -44,6 +72,28 @@ g1_param::define!(bootstrap: Vec<String> = vec![
 
 g1_param::define!(self_id: NodeId = NodeId::new(rand::random()));
 
+// In the BEP 43 read-only mode, we query the DHT without serving it, i.e., we set the `ro` flag in
+// our queries and do not respond to queries.
+g1_param::define!(read_only: bool = false);
+
+// Per-IP rate limit of incoming queries, in queries per second.
+g1_param::define!(
+    query_rate: f64 = 5.0;
+    validate = |rate: &f64| *rate > 0.0;
+);
+g1_param::define!(
+    query_burst: f64 = 20.0;
+    validate = |burst: &f64| *burst >= 1.0;
+);
+// Maximum number of IP addresses that the rate limiter tracks.
+g1_param::define!(query_limiter_capacity: usize = 4096);
+
+// Maximum number of announced peers that we store per info hash.
+g1_param::define!(max_peers_per_info_hash: usize = 500);
+
+// Do not add nodes whose ids violate BEP 42 to the routing table.
+g1_param::define!(enforce_node_id: bool = true);
+
 g1_param::define!(
     token_period: Duration = Duration::from_secs(5 * 60);
     parse = g1_param::parse::duration;
@@ -52,9 +102,18 @@ g1_param::define!(
     token_valid_since: Duration = Duration::from_secs(10 * 60);
     parse = g1_param::parse::duration;
 );
-g1_param::define!(token_secret: u64 = rand::random());
+// If unset, a random secret is generated for each token period.
+g1_param::define!(token_secret: Option<u64> = None);
+
+// BEP 44 recommends storing items for at least two hours.
+g1_param::define!(item_store_capacity: usize = 4096);
+g1_param::define!(
+    item_expiration: Duration = Duration::from_secs(2 * 60 * 60);
+    parse = g1_param::parse::duration;
+);
 
 g1_param::define!(kbucket_full_queue_size: usize = 64);
+g1_param::define!(requester_queue_size: usize = 64);
 g1_param::define!(
     refresh_period: Duration = Duration::from_secs(15 * 60);
     parse = g1_param::parse::duration;
@@ -0,0 +1,105 @@
+//! Per-IP Query Rate Limit
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::Instant;
+
+#[derive(Debug)]
+pub(crate) struct QueryLimiter {
+    rate: f64,
+    burst: f64,
+    capacity: usize,
+    buckets: HashMap<IpAddr, Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    n: f64,
+    last_fill: Instant,
+}
+
+impl QueryLimiter {
+    pub(crate) fn new() -> Self {
+        Self::with_params(
+            *crate::query_rate(),
+            *crate::query_burst(),
+            *crate::query_limiter_capacity(),
+        )
+    }
+
+    fn with_params(rate: f64, burst: f64, capacity: usize) -> Self {
+        Self {
+            rate,
+            burst,
+            capacity,
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Returns true if a query from the IP address is within the rate limit.
+    pub(crate) fn admit(&mut self, ip: IpAddr, now: Instant) -> bool {
+        if self.buckets.len() >= self.capacity && !self.buckets.contains_key(&ip) {
+            // Evict full buckets, which are indistinguishable from new ones.
+            let (rate, burst) = (self.rate, self.burst);
+            self.buckets
+                .retain(|_, bucket| bucket.fill(now, rate, burst) < burst);
+            // We fail closed; otherwise, an attacker could bypass the limit with spoofed addresses.
+            if self.buckets.len() >= self.capacity {
+                return false;
+            }
+        }
+
+        let bucket = self.buckets.entry(ip).or_insert(Bucket {
+            n: self.burst,
+            last_fill: now,
+        });
+        if bucket.fill(now, self.rate, self.burst) < 1.0 {
+            return false;
+        }
+        bucket.n -= 1.0;
+        true
+    }
+}
+
+impl Bucket {
+    fn fill(&mut self, now: Instant, rate: f64, burst: f64) -> f64 {
+        let t = now.saturating_duration_since(self.last_fill).as_secs_f64();
+        self.n = (self.n + rate * t).min(burst);
+        self.last_fill = now;
+        self.n
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn admit() {
+        let t0 = Instant::now();
+        let t1 = t0 + Duration::from_millis(500);
+        let t2 = t0 + Duration::from_secs(10);
+        let ip1 = "127.0.0.1".parse().unwrap();
+        let ip2 = "127.0.0.2".parse().unwrap();
+        let ip3 = "::1".parse().unwrap();
+
+        let mut limiter = QueryLimiter::with_params(2.0, 3.0, 2);
+        for _ in 0..3 {
+            assert_eq!(limiter.admit(ip1, t0), true);
+        }
+        assert_eq!(limiter.admit(ip1, t0), false);
+        assert_eq!(limiter.admit(ip2, t0), true);
+
+        // The limiter is at capacity.
+        assert_eq!(limiter.admit(ip3, t0), false);
+
+        assert_eq!(limiter.admit(ip1, t1), true);
+        assert_eq!(limiter.admit(ip1, t1), false);
+
+        // Both buckets are full again, and thus are evicted.
+        assert_eq!(limiter.admit(ip3, t2), true);
+        assert_eq!(limiter.buckets.len(), 1);
+    }
+}
@@ -3,21 +3,27 @@
 // wording is a bit ambiguous to me.  How can we ensure compliance with BEP 5?
 
 use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::future::Future;
 use std::io::{Error, ErrorKind};
 use std::mem;
 use std::net::SocketAddr;
+use std::time::Duration;
 
 use async_trait::async_trait;
 use bitvec::prelude::*;
+use bytes::Bytes;
+use tokio::time::{self, Instant};
 
 use g1_base::sync::MutexExt;
-use g1_tokio::{net, task::Joiner};
+use g1_tokio::{net::dns, task::Joiner};
 
 use bittorrent_base::InfoHash;
 
 use crate::{
     agent::NodeState,
-    reqrep::{Client, GetPeers, Nodes, Token},
+    bloom::BloomFilter,
+    item::Item,
+    reqrep::{Client, GetItem, GetPeers, Nodes, Scrape, Token},
     Distance, NodeContactInfo, NodeId, NodeIdBitSlice,
 };
 
@@ -26,29 +32,82 @@ pub(crate) struct Lookup {
     state: NodeState,
     limit: usize,
     concurrency: usize,
+    timeout: Duration,
+    stall_limit: usize,
+    adaptive: Option<Adaptive>,
+}
+
+#[derive(Debug)]
+struct Adaptive {
+    quantile: f64,
+    multiplier: f64,
+    min_timeout: Duration,
 }
 
 pub(crate) type LookupPeers = (
     Peers,
-    // Closest node to which we may send `announce_peer`.
-    Option<(NodeContactInfo, Token)>,
+    // Closest node of each address family to which we may send `announce_peer`.
+    Vec<(NodeContactInfo, Token)>,
 );
 
 type Peers = BTreeSet<SocketAddr>;
 
+pub(crate) type LookupScrape = (
+    // Estimated number of seeds.
+    usize,
+    // Estimated number of downloaders.
+    usize,
+);
+
+pub(crate) type LookupItem = (
+    // The item of the highest sequence number.
+    Option<Item>,
+    // Closest nodes to which we may send `put`.
+    Vec<(NodeContactInfo, Token)>,
+);
+
 impl Lookup {
     pub(crate) fn new(state: NodeState) -> Self {
-        Self::with_limit(state, *crate::k(), *crate::alpha())
-    }
-
-    fn with_limit(state: NodeState, limit: usize, concurrency: usize) -> Self {
         Self {
             state,
-            limit,
-            concurrency,
+            limit: *crate::k(),
+            concurrency: *crate::alpha(),
+            timeout: *crate::lookup_timeout(),
+            stall_limit: *crate::lookup_stall_limit(),
+            adaptive: crate::lookup_adaptive().then(|| Adaptive {
+                quantile: *crate::lookup_adaptive_quantile(),
+                multiplier: *crate::lookup_adaptive_multiplier(),
+                min_timeout: *crate::lookup_adaptive_min_timeout(),
+            }),
+        }
+    }
+
+    /// Returns the per-node timeout.
+    ///
+    /// In the adaptive mode, it shrinks the timeout to fit the observed RTT distribution.
+    fn node_timeout(&self) -> Duration {
+        let Some(adaptive) = &self.adaptive else {
+            return self.timeout;
+        };
+        match self.state.rtt.must_lock().quantile(adaptive.quantile) {
+            Some(rtt) => adaptive.timeout(rtt, self.timeout),
+            None => self.timeout,
         }
     }
 
+    /// Sends a request to the node and records its RTT.
+    async fn request<F, T>(state: NodeState, timeout: Duration, request: F) -> Result<T, Error>
+    where
+        F: Future<Output = Result<T, Error>>,
+    {
+        let start = Instant::now();
+        let response = time::timeout(timeout, request)
+            .await
+            .map_err(|_| Error::new(ErrorKind::TimedOut, "lookup timeout"))??;
+        state.rtt.must_lock().record(start.elapsed());
+        Ok(response)
+    }
+
     pub(crate) async fn lookup_nodes(&self, id: NodeId) -> Nodes {
         self.lookup(NodeLookuper, id).await
     }
@@ -57,6 +116,16 @@ impl Lookup {
         self.lookup(PeerLookuper::new(), info_hash).await
     }
 
+    pub(crate) async fn scrape(&self, info_hash: InfoHash) -> LookupScrape {
+        self.lookup(ScrapeLookuper::default(), info_hash).await
+    }
+
+    /// Looks up an item, where `salt` is `None` for immutable items.
+    pub(crate) async fn lookup_item(&self, target: NodeId, salt: Option<Bytes>) -> LookupItem {
+        let lookuper = ItemLookuper::new(target.clone(), salt, self.limit);
+        self.lookup(lookuper, target).await
+    }
+
     async fn lookup<L, I>(&self, mut lookuper: L, id: I) -> L::Output
     where
         L: Lookuper,
@@ -64,14 +133,17 @@ impl Lookup {
     {
         // Create a map from `Distance` to `NodeContactInfo`.  We may use `Distance` as the map key
         // because the XOR metric is unidirectional, which means that `p == q` if and only if
-        // `d(id, p) == d(id, q)`.
-        let mut candidates = BTreeMap::from_iter(into_entries(
-            self.state
-                .routing
-                .must_lock()
-                .get_closest_with_limit(id.bits(), self.limit),
-            id.bits(),
-        ));
+        // `d(id, p) == d(id, q)`.  (A node that is in both routing tables is queried over only
+        // one of the address families.)
+        let mut candidates = BTreeMap::new();
+        for routing in [&self.state.routing, &self.state.routing_v6] {
+            candidates.extend(into_entries(
+                routing
+                    .must_lock()
+                    .get_closest_with_limit(id.bits(), self.limit),
+                id.bits(),
+            ));
+        }
         if candidates.is_empty() {
             candidates = self.bootstrap(id.clone()).await;
         }
@@ -79,6 +151,7 @@ impl Lookup {
         let mut good_nodes = BTreeMap::new();
         let mut closest_nodes = Nodes::new();
         let mut have_been_queried = HashSet::new();
+        let mut num_stalls = 0;
         while !candidates.is_empty() {
             let closest_candidates: Vec<_> = mem::take(&mut candidates)
                 .values()
@@ -88,6 +161,7 @@ impl Lookup {
                 .collect();
 
             have_been_queried.extend(closest_candidates.iter().cloned());
+            let timeout = self.node_timeout();
             let queries: Vec<_> = closest_candidates
                 .into_iter()
                 .map(|candidate| {
@@ -95,7 +169,9 @@ impl Lookup {
                     let id = id.clone();
                     async move {
                         let client = state.connect(candidate.endpoint);
-                        (candidate, L::request(client, id.as_ref()).await)
+                        let response =
+                            Self::request(state, timeout, L::request(client, id.as_ref())).await;
+                        (candidate, response)
                     }
                 })
                 .collect();
@@ -119,15 +195,23 @@ impl Lookup {
                         } else {
                             tracing::warn!(?candidate, %error, "{} error", L::KRPC_METHOD_NAME);
                         }
-                        let _ = self.state.routing.must_lock().remove(&candidate);
+                        let _ = self
+                            .state
+                            .routing(candidate.endpoint)
+                            .must_lock()
+                            .remove(&candidate);
                     }
                 }
             }
 
             let next_closest_nodes = good_nodes.values().take(self.limit).cloned().collect();
             if closest_nodes == next_closest_nodes {
-                break;
+                num_stalls += 1;
+                if num_stalls >= self.stall_limit {
+                    break;
+                }
             } else {
+                num_stalls = 0;
                 drop(mem::replace(&mut closest_nodes, next_closest_nodes));
             }
         }
@@ -147,7 +231,7 @@ impl Lookup {
                 let id = id.clone();
                 async move {
                     let nodes: Result<Nodes, Error> = try {
-                        let endpoint = net::lookup_host_first(&bootstrap).await?;
+                        let endpoint = dns::resolver().lookup_endpoint(&bootstrap).await?;
                         state.connect(endpoint).find_node(id.as_ref()).await?
                     };
                     match nodes {
@@ -175,6 +259,13 @@ impl Lookup {
     }
 }
 
+impl Adaptive {
+    fn timeout(&self, rtt: Duration, max_timeout: Duration) -> Duration {
+        rtt.mul_f64(self.multiplier)
+            .clamp(self.min_timeout.min(max_timeout), max_timeout)
+    }
+}
+
 fn into_entries<'a, I>(
     nodes: I,
     id: &'a NodeIdBitSlice,
@@ -233,6 +324,22 @@ struct NodeLookuper;
 struct PeerLookuper {
     peers: Peers,
     closest: Option<(NodeContactInfo, Distance, Token)>,
+    closest_v6: Option<(NodeContactInfo, Distance, Token)>,
+}
+
+#[derive(Debug, Default)]
+struct ScrapeLookuper {
+    seeds: BloomFilter,
+    downloaders: BloomFilter,
+}
+
+#[derive(Debug)]
+struct ItemLookuper {
+    target: NodeId,
+    salt: Option<Bytes>,
+    limit: usize,
+    item: Option<Item>,
+    closest: BTreeMap<Distance, (NodeContactInfo, Token)>,
 }
 
 #[async_trait]
@@ -265,13 +372,17 @@ impl PeerLookuper {
         Self {
             peers: BTreeSet::new(),
             closest: None,
+            closest_v6: None,
         }
     }
 
-    fn other_is_closer(&self, node_distance: &Distance) -> bool {
-        match &self.closest {
-            Some((_, distance, _)) => node_distance < distance,
-            None => true,
+    fn closest_mut(
+        &mut self,
+        endpoint: SocketAddr,
+    ) -> &mut Option<(NodeContactInfo, Distance, Token)> {
+        match endpoint {
+            SocketAddr::V4(_) => &mut self.closest,
+            SocketAddr::V6(_) => &mut self.closest_v6,
         }
     }
 }
@@ -296,9 +407,14 @@ impl Lookuper for PeerLookuper {
         let (token, peers, nodes) = response;
         if let Some(peers) = peers {
             self.peers.extend(peers);
-            if self.other_is_closer(node_distance) {
+            let closest = self.closest_mut(node.endpoint);
+            let is_closer = match closest {
+                Some((_, distance, _)) => node_distance < distance,
+                None => true,
+            };
+            if is_closer {
                 tracing::debug!(?node, "find closer node");
-                self.closest = Some((node.clone(), node_distance.clone(), token.unwrap()));
+                *closest = Some((node.clone(), node_distance.clone(), token.unwrap()));
             }
         }
         nodes.unwrap_or_default()
@@ -307,7 +423,154 @@ impl Lookuper for PeerLookuper {
     fn finish(self, _nodes: Nodes) -> Self::Output {
         (
             self.peers,
-            self.closest.map(|(closest, _, token)| (closest, token)),
+            [self.closest, self.closest_v6]
+                .into_iter()
+                .flatten()
+                .map(|(closest, _, token)| (closest, token))
+                .collect(),
         )
     }
 }
+
+#[async_trait]
+impl Lookuper for ScrapeLookuper {
+    type Response = Scrape;
+    type Output = LookupScrape;
+
+    const KRPC_METHOD_NAME: &'static str = "get_peers";
+
+    async fn request(client: Client, id: &[u8]) -> Result<Self::Response, Error> {
+        client.scrape(id).await
+    }
+
+    fn process_response(
+        &mut self,
+        _node: &NodeContactInfo,
+        _node_distance: &Distance,
+        response: Self::Response,
+    ) -> Nodes {
+        let (_, filters, nodes) = response;
+        if let Some((seeds, downloaders)) = filters {
+            self.seeds.union(&seeds);
+            self.downloaders.union(&downloaders);
+        }
+        nodes.unwrap_or_default()
+    }
+
+    fn finish(self, _nodes: Nodes) -> Self::Output {
+        (
+            self.seeds.estimate().round() as usize,
+            self.downloaders.estimate().round() as usize,
+        )
+    }
+}
+
+impl ItemLookuper {
+    fn new(target: NodeId, salt: Option<Bytes>, limit: usize) -> Self {
+        Self {
+            target,
+            salt,
+            limit,
+            item: None,
+            closest: BTreeMap::new(),
+        }
+    }
+
+    fn accept_item(&mut self, node: &NodeContactInfo, item: Item) {
+        if item.mutable.is_some() != self.salt.is_some() {
+            tracing::warn!(?node, ?item, "unexpected item type");
+            return;
+        }
+        let salt = self.salt.clone().unwrap_or_default();
+        let item = match item.verify_get(self.target.as_ref(), salt) {
+            Ok(item) => item,
+            Err(error) => {
+                tracing::warn!(?node, %error, "invalid item");
+                return;
+            }
+        };
+        let is_newer = match (&self.item, &item.mutable) {
+            (
+                Some(Item {
+                    mutable: Some(current),
+                    ..
+                }),
+                Some(new),
+            ) => new.seq > current.seq,
+            (Some(_), _) => false,
+            (None, _) => true,
+        };
+        if is_newer {
+            tracing::debug!(?node, ?item, "find item");
+            self.item = Some(item);
+        }
+    }
+}
+
+#[async_trait]
+impl Lookuper for ItemLookuper {
+    type Response = GetItem;
+    type Output = LookupItem;
+
+    const KRPC_METHOD_NAME: &'static str = "get";
+
+    async fn request(client: Client, id: &[u8]) -> Result<Self::Response, Error> {
+        client.get(id, None).await
+    }
+
+    fn process_response(
+        &mut self,
+        node: &NodeContactInfo,
+        node_distance: &Distance,
+        response: Self::Response,
+    ) -> Nodes {
+        let (token, item, nodes) = response;
+        if let Some(item) = item {
+            self.accept_item(node, item);
+        }
+        if let Some(token) = token {
+            self.closest
+                .insert(node_distance.clone(), (node.clone(), token));
+            if self.closest.len() > self.limit {
+                self.closest.pop_last();
+            }
+        }
+        nodes.unwrap_or_default()
+    }
+
+    fn finish(self, _nodes: Nodes) -> Self::Output {
+        (self.item, self.closest.into_values().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adaptive_timeout() {
+        let adaptive = Adaptive {
+            quantile: 0.9,
+            multiplier: 1.5,
+            min_timeout: Duration::from_millis(200),
+        };
+        let max_timeout = Duration::from_secs(2);
+        for (rtt, expect) in [
+            (10, 200),
+            (100, 200),
+            (200, 300),
+            (1000, 1500),
+            (5000, 2000),
+        ] {
+            assert_eq!(
+                adaptive.timeout(Duration::from_millis(rtt), max_timeout),
+                Duration::from_millis(expect),
+            );
+        }
+        // `min_timeout` does not exceed `max_timeout`.
+        assert_eq!(
+            adaptive.timeout(Duration::from_millis(10), Duration::from_millis(100)),
+            Duration::from_millis(100),
+        );
+    }
+}
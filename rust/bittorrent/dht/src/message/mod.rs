@@ -6,6 +6,7 @@ mod serde_impl;
 
 use std::collections::BTreeMap;
 
+use ed25519_dalek::{PUBLIC_KEY_LENGTH, SIGNATURE_LENGTH};
 use serde::{Deserialize, Serialize};
 use serde_bytes::Bytes;
 use snafu::prelude::*;
@@ -56,6 +57,10 @@ pub(crate) enum Error {
     ExpectInfoHashSize { info_hash: Vec<u8> },
     #[snafu(display("invalid node port: {port}"))]
     InvalidNodePort { port: i64 },
+    #[snafu(display("expect public key size == {PUBLIC_KEY_LENGTH}: {key:?}"))]
+    ExpectPublicKeySize { key: Vec<u8> },
+    #[snafu(display("expect signature size == {SIGNATURE_LENGTH}: {signature:?}"))]
+    ExpectSignatureSize { signature: Vec<u8> },
     #[snafu(display("unknown method name: {method_name:?}"))]
     UnknownMethodName { method_name: Vec<u8> },
 
@@ -91,6 +96,8 @@ pub(crate) struct Message<'a> {
     pub(crate) payload: Payload<'a>,
     #[debug(with = Hex)]
     pub(crate) version: Option<&'a [u8]>,
+    // BEP 43 read-only flag of queries.
+    pub(crate) read_only: Option<bool>,
 
     #[debug(with = FormatDictionary)]
     pub(crate) extra: BTreeMap<&'a [u8], borrow::Value<'a>>,
@@ -107,7 +114,7 @@ const TXID_SIZE: usize = 2;
 
 impl<'a> Message<'a> {
     pub(crate) fn new_txid() -> [u8; TXID_SIZE] {
-        rand::random::<[u8; TXID_SIZE]>()
+        g1_base::rand::random::<[u8; TXID_SIZE]>()
     }
 
     pub(crate) fn new(txid: &'a [u8], payload: Payload<'a>) -> Self {
@@ -115,6 +122,7 @@ impl<'a> Message<'a> {
             txid,
             payload,
             version: None, // TODO: Supply the peer version, as specified in BEP 20.
+            read_only: None,
             extra: BTreeMap::new(),
         }
     }
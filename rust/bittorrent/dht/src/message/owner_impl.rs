@@ -58,6 +58,24 @@ impl<'a> TryFrom<&'a [u8]> for response::AnnouncePeer<'a> {
     }
 }
 
+// Ditto.
+impl<'a> TryFrom<&'a [u8]> for response::Get<'a> {
+    type Error = ();
+
+    fn try_from(_: &'a [u8]) -> Result<Self, Self::Error> {
+        std::unreachable!()
+    }
+}
+
+// Ditto.
+impl<'a> TryFrom<&'a [u8]> for response::Put<'a> {
+    type Error = ();
+
+    fn try_from(_: &'a [u8]) -> Result<Self, Self::Error> {
+        std::unreachable!()
+    }
+}
+
 impl<'a> TryFrom<Message<'a>> for response::Response<'a> {
     type Error = Error;
 
@@ -102,3 +120,19 @@ impl<'a> TryFrom<Message<'a>> for response::AnnouncePeer<'a> {
         response::Response::try_from(message).and_then(Self::try_from)
     }
 }
+
+impl<'a> TryFrom<Message<'a>> for response::Get<'a> {
+    type Error = Error;
+
+    fn try_from(message: Message<'a>) -> Result<Self, Self::Error> {
+        response::Response::try_from(message).and_then(Self::try_from)
+    }
+}
+
+impl<'a> TryFrom<Message<'a>> for response::Put<'a> {
+    type Error = Error;
+
+    fn try_from(message: Message<'a>) -> Result<Self, Self::Error> {
+        response::Response::try_from(message).and_then(Self::try_from)
+    }
+}
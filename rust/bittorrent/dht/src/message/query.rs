@@ -1,6 +1,8 @@
 use std::collections::BTreeMap;
+use std::net::SocketAddr;
 
 use bitvec::prelude::*;
+use bytes::{Bytes, BytesMut};
 
 use g1_base::fmt::{DebugExt, Hex};
 
@@ -8,12 +10,19 @@ use bittorrent_bencode::{borrow, FormatDictionary};
 
 use crate::NodeIdBitSlice;
 
+// BEP 32 IPv6 extension
+pub(crate) const WANT_NODES_V4: &[u8] = b"n4";
+pub(crate) const WANT_NODES_V6: &[u8] = b"n6";
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub(crate) enum Query<'a> {
     Ping(Ping<'a>),
     FindNode(FindNode<'a>),
     GetPeers(GetPeers<'a>),
     AnnouncePeer(AnnouncePeer<'a>),
+    // BEP 44 Storing Arbitrary Data in the DHT
+    Get(Get<'a>),
+    Put(Put<'a>),
 }
 
 #[derive(Clone, DebugExt, Eq, PartialEq)]
@@ -31,6 +40,8 @@ pub(crate) struct FindNode<'a> {
     pub(crate) id: &'a [u8],
     #[debug(with = Hex)]
     pub(crate) target: &'a [u8],
+    #[debug(with = Hex)]
+    pub(crate) want: Option<Vec<&'a [u8]>>,
 
     #[debug(with = FormatDictionary)]
     pub(crate) extra: BTreeMap<&'a [u8], borrow::Value<'a>>,
@@ -42,6 +53,11 @@ pub(crate) struct GetPeers<'a> {
     pub(crate) id: &'a [u8],
     #[debug(with = Hex)]
     pub(crate) info_hash: &'a [u8],
+    #[debug(with = Hex)]
+    pub(crate) want: Option<Vec<&'a [u8]>>,
+    // BEP 33 DHT Scrapes
+    pub(crate) scrape: Option<bool>,
+    pub(crate) noseed: Option<bool>,
 
     #[debug(with = FormatDictionary)]
     pub(crate) extra: BTreeMap<&'a [u8], borrow::Value<'a>>,
@@ -59,6 +75,48 @@ pub(crate) struct AnnouncePeer<'a> {
     pub(crate) implied_port: Option<bool>,
     #[debug(with = Hex)]
     pub(crate) token: &'a [u8],
+    // BEP 33 DHT Scrapes
+    pub(crate) seed: Option<bool>,
+
+    #[debug(with = FormatDictionary)]
+    pub(crate) extra: BTreeMap<&'a [u8], borrow::Value<'a>>,
+}
+
+#[derive(Clone, DebugExt, Eq, PartialEq)]
+pub(crate) struct Get<'a> {
+    #[debug(with = Hex)]
+    pub(crate) id: &'a [u8],
+    #[debug(with = Hex)]
+    pub(crate) target: &'a [u8],
+    // The requester already has the mutable item of this sequence number.
+    pub(crate) seq: Option<i64>,
+    #[debug(with = Hex)]
+    pub(crate) want: Option<Vec<&'a [u8]>>,
+
+    #[debug(with = FormatDictionary)]
+    pub(crate) extra: BTreeMap<&'a [u8], borrow::Value<'a>>,
+}
+
+#[derive(Clone, DebugExt, Eq, PartialEq)]
+pub(crate) struct Put<'a> {
+    #[debug(with = Hex)]
+    pub(crate) id: &'a [u8],
+    #[debug(with = Hex)]
+    pub(crate) token: &'a [u8],
+    pub(crate) value: borrow::Value<'a>,
+    // BEP 44 signatures and hashes cover `v` as the requester encoded it, which might differ from
+    // our re-encoding of `value`.
+    #[debug(with = Hex)]
+    pub(crate) raw_value: Option<&'a [u8]>,
+    // The fields below are present only for mutable items.
+    #[debug(with = Hex)]
+    pub(crate) key: Option<&'a [u8]>,
+    #[debug(with = Hex)]
+    pub(crate) signature: Option<&'a [u8]>,
+    pub(crate) seq: Option<i64>,
+    pub(crate) cas: Option<i64>,
+    #[debug(with = Hex)]
+    pub(crate) salt: Option<&'a [u8]>,
 
     #[debug(with = FormatDictionary)]
     pub(crate) extra: BTreeMap<&'a [u8], borrow::Value<'a>>,
@@ -71,6 +129,8 @@ impl<'a> Query<'a> {
             Self::FindNode(find_node) => find_node.id,
             Self::GetPeers(get_peers) => get_peers.id,
             Self::AnnouncePeer(announce_peer) => announce_peer.id,
+            Self::Get(get) => get.id,
+            Self::Put(put) => put.id,
         }
     }
 
@@ -80,10 +140,23 @@ impl<'a> Query<'a> {
             Self::FindNode(find_node) => &find_node.extra,
             Self::GetPeers(get_peers) => &get_peers.extra,
             Self::AnnouncePeer(announce_peer) => &announce_peer.extra,
+            Self::Get(get) => &get.extra,
+            Self::Put(put) => &put.extra,
         }
     }
 }
 
+/// Returns whether the requester wants IPv4 and IPv6 nodes, respectively.
+///
+/// When `want` is absent, BEP 32 specifies that the requester wants nodes of the address family
+/// that the query is sent over.
+pub(crate) fn want(want: Option<&[&[u8]]>, requester: SocketAddr) -> (bool, bool) {
+    match want {
+        Some(want) => (want.contains(&WANT_NODES_V4), want.contains(&WANT_NODES_V6)),
+        None => (requester.is_ipv4(), requester.is_ipv6()),
+    }
+}
+
 impl<'a> Ping<'a> {
     pub(crate) fn new(id: &'a [u8]) -> Self {
         Self {
@@ -98,6 +171,7 @@ impl<'a> FindNode<'a> {
         Self {
             id,
             target,
+            want: None,
             extra: BTreeMap::new(),
         }
     }
@@ -112,6 +186,9 @@ impl<'a> GetPeers<'a> {
         Self {
             id,
             info_hash,
+            want: None,
+            scrape: None,
+            noseed: None,
             extra: BTreeMap::new(),
         }
     }
@@ -135,7 +212,53 @@ impl<'a> AnnouncePeer<'a> {
             port,
             implied_port,
             token,
+            seed: None,
+            extra: BTreeMap::new(),
+        }
+    }
+}
+
+impl<'a> Get<'a> {
+    pub(crate) fn new(id: &'a [u8], target: &'a [u8], seq: Option<i64>) -> Self {
+        Self {
+            id,
+            target,
+            seq,
+            want: None,
             extra: BTreeMap::new(),
         }
     }
+
+    pub(crate) fn target_bits(&self) -> &NodeIdBitSlice {
+        self.target.view_bits()
+    }
+}
+
+impl<'a> Put<'a> {
+    pub(crate) fn new(id: &'a [u8], token: &'a [u8], value: borrow::Value<'a>) -> Self {
+        Self {
+            id,
+            token,
+            value,
+            raw_value: None,
+            key: None,
+            signature: None,
+            seq: None,
+            cas: None,
+            salt: None,
+            extra: BTreeMap::new(),
+        }
+    }
+
+    /// Returns `v` as the requester encoded it, or our encoding of `value` if unavailable.
+    pub(crate) fn value_bytes(&self) -> Bytes {
+        match self.raw_value {
+            Some(raw_value) => Bytes::copy_from_slice(raw_value),
+            None => {
+                let mut buffer = BytesMut::new();
+                self.value.encode(&mut buffer);
+                buffer.freeze()
+            }
+        }
+    }
 }
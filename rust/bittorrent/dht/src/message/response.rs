@@ -1,5 +1,5 @@
 use std::collections::BTreeMap;
-use std::net::{SocketAddr, SocketAddrV4};
+use std::net::{SocketAddr, SocketAddrV4, SocketAddrV6};
 
 use bytes::{Bytes, BytesMut};
 
@@ -25,12 +25,21 @@ g1_base::impl_owner_try_from!(message::MessageOwner for GetPeersOwner);
 g1_base::define_owner!(#[derive(Debug)] pub(crate) AnnouncePeerOwner for AnnouncePeer);
 g1_base::impl_owner_try_from!(message::MessageOwner for AnnouncePeerOwner);
 
+g1_base::define_owner!(#[derive(Debug)] pub(crate) GetOwner for Get);
+g1_base::impl_owner_try_from!(message::MessageOwner for GetOwner);
+
+g1_base::define_owner!(#[derive(Debug)] pub(crate) PutOwner for Put);
+g1_base::impl_owner_try_from!(message::MessageOwner for PutOwner);
+
 #[derive(Clone, DebugExt, Eq, PartialEq)]
 pub(crate) struct Response<'a> {
     #[debug(with = FormatDictionary)]
     pub(super) response: BTreeMap<&'a [u8], borrow::Value<'a>>,
     #[debug(with = Hex)]
     pub(crate) requester: Option<&'a [u8]>,
+    // BEP 44 `v` as the responder encoded it.
+    #[debug(with = Hex)]
+    pub(super) raw_value: Option<&'a [u8]>,
 }
 
 #[derive(Clone, DebugExt, Eq, PartialEq)]
@@ -46,8 +55,11 @@ pub(crate) struct Ping<'a> {
 pub(crate) struct FindNode<'a> {
     #[debug(with = Hex)]
     pub(crate) id: &'a [u8],
+    // BEP 32 specifies that a response carries `nodes` and/or `nodes6`.
+    #[debug(with = Hex)]
+    pub(super) nodes: Option<&'a [u8]>,
     #[debug(with = Hex)]
-    pub(super) nodes: &'a [u8],
+    pub(super) nodes6: Option<&'a [u8]>,
 
     #[debug(with = FormatDictionary)]
     pub(crate) extra: BTreeMap<&'a [u8], borrow::Value<'a>>,
@@ -62,11 +74,18 @@ pub(crate) struct GetPeers<'a> {
     #[debug(with = Hex)]
     pub(crate) token: Option<&'a [u8]>,
     // While BEP 5 appears to specify that `values` and `nodes` should be "either or", some
-    // implementations still return both.
+    // implementations still return both.  BEP 32 lets `values` mix IPv4 and IPv6 peers.
     #[debug(with = Hex)]
     pub(super) values: Option<Vec<&'a [u8]>>,
     #[debug(with = Hex)]
     pub(super) nodes: Option<&'a [u8]>,
+    #[debug(with = Hex)]
+    pub(super) nodes6: Option<&'a [u8]>,
+    // BEP 33 DHT Scrapes
+    #[debug(with = Hex)]
+    pub(crate) seeds: Option<&'a [u8]>,
+    #[debug(with = Hex)]
+    pub(crate) peers: Option<&'a [u8]>,
 
     #[debug(with = FormatDictionary)]
     pub(crate) extra: BTreeMap<&'a [u8], borrow::Value<'a>>,
@@ -81,6 +100,41 @@ pub(crate) struct AnnouncePeer<'a> {
     pub(crate) extra: BTreeMap<&'a [u8], borrow::Value<'a>>,
 }
 
+#[derive(Clone, DebugExt, Eq, PartialEq)]
+pub(crate) struct Get<'a> {
+    #[debug(with = Hex)]
+    pub(crate) id: &'a [u8],
+    #[debug(with = Hex)]
+    pub(crate) token: Option<&'a [u8]>,
+    #[debug(with = Hex)]
+    pub(super) nodes: Option<&'a [u8]>,
+    #[debug(with = Hex)]
+    pub(super) nodes6: Option<&'a [u8]>,
+    pub(crate) value: Option<borrow::Value<'a>>,
+    // BEP 44 signatures and hashes cover `v` as the responder encoded it, which might differ from
+    // our re-encoding of `value`.
+    #[debug(with = Hex)]
+    pub(crate) raw_value: Option<&'a [u8]>,
+    // The fields below are present only for mutable items.
+    #[debug(with = Hex)]
+    pub(crate) key: Option<&'a [u8]>,
+    #[debug(with = Hex)]
+    pub(crate) signature: Option<&'a [u8]>,
+    pub(crate) seq: Option<i64>,
+
+    #[debug(with = FormatDictionary)]
+    pub(crate) extra: BTreeMap<&'a [u8], borrow::Value<'a>>,
+}
+
+#[derive(Clone, DebugExt, Eq, PartialEq)]
+pub(crate) struct Put<'a> {
+    #[debug(with = Hex)]
+    pub(crate) id: &'a [u8],
+
+    #[debug(with = FormatDictionary)]
+    pub(crate) extra: BTreeMap<&'a [u8], borrow::Value<'a>>,
+}
+
 // Do NOT `derive(Snafu)` since this is not a typical error type.
 #[derive(Clone, Debug, Eq, PartialEq)]
 // Keep the "Error" suffix to be consistent with BEP 5.
@@ -90,6 +144,12 @@ pub(crate) enum Error<'a> {
     ServerError { message: &'a str },
     ProtocolError { message: &'a str },
     MethodUnknown { message: &'a str },
+    // BEP 44 Storing Arbitrary Data in the DHT
+    MessageTooBig { message: &'a str },
+    InvalidSignature { message: &'a str },
+    SaltTooBig { message: &'a str },
+    CasMismatch { message: &'a str },
+    SequenceNumberLessThanCurrent { message: &'a str },
 }
 
 impl<'a> Response<'a> {
@@ -97,8 +157,14 @@ impl<'a> Response<'a> {
         Self {
             response,
             requester: None, // TODO: Supply self endpoint, as specified in BEP 42.
+            raw_value: None,
         }
     }
+
+    /// Decodes our endpoint as the responder sees it (BEP 42).
+    pub(crate) fn decode_requester(&self) -> Option<Result<SocketAddr, message::Error>> {
+        self.requester.map(decode_endpoint)
+    }
 }
 
 impl<'a> Ping<'a> {
@@ -111,25 +177,35 @@ impl<'a> Ping<'a> {
 }
 
 impl<'a> FindNode<'a> {
-    pub(crate) fn new(id: &'a [u8], nodes: &'a [u8]) -> Self {
+    pub(crate) fn new(id: &'a [u8], nodes: Option<&'a [u8]>, nodes6: Option<&'a [u8]>) -> Self {
         Self {
             id,
             nodes,
+            nodes6,
             extra: BTreeMap::new(),
         }
     }
 
-    // TODO: Add `decode_nodes_v6`.
-    pub(crate) fn decode_nodes_v4(&self) -> Result<Vec<NodeContactInfo>, message::Error> {
-        decode_nodes::<SocketAddrV4>(self.nodes)
+    pub(crate) fn decode_nodes_v4(&self) -> Option<Result<Vec<NodeContactInfo>, message::Error>> {
+        Some(decode_nodes::<SocketAddrV4>(self.nodes?))
     }
 
-    // TODO: Add `encode_nodes_v6`.
+    pub(crate) fn decode_nodes_v6(&self) -> Option<Result<Vec<NodeContactInfo>, message::Error>> {
+        Some(decode_nodes::<SocketAddrV6>(self.nodes6?))
+    }
+
+    // `get_peers` and `get` responses share the same compact node format.
     pub(crate) fn encode_nodes_v4<'b>(
         nodes: impl Iterator<Item = &'b NodeContactInfo>,
     ) -> BytesMut {
         encode_nodes(nodes, to_v4)
     }
+
+    pub(crate) fn encode_nodes_v6<'b>(
+        nodes: impl Iterator<Item = &'b NodeContactInfo>,
+    ) -> BytesMut {
+        encode_nodes(nodes, to_v6)
+    }
 }
 
 impl<'a> GetPeers<'a> {
@@ -138,36 +214,34 @@ impl<'a> GetPeers<'a> {
         token: Option<&'a [u8]>,
         values: Option<Vec<&'a [u8]>>,
         nodes: Option<&'a [u8]>,
+        nodes6: Option<&'a [u8]>,
     ) -> Self {
         Self {
             id,
             token,
             values,
             nodes,
+            nodes6,
+            seeds: None,
+            peers: None,
             extra: BTreeMap::new(),
         }
     }
 
-    // TODO: Add `decode_peers_v6`.
-    pub(crate) fn decode_peers_v4(&self) -> Option<Result<Vec<SocketAddr>, message::Error>> {
-        Some(decode_peers::<SocketAddrV4>(self.values.as_ref()?))
+    pub(crate) fn decode_peers(&self) -> Option<Result<Vec<SocketAddr>, message::Error>> {
+        Some(decode_peers(self.values.as_ref()?))
     }
 
-    // TODO: Add `encode_peers_v6`.
-    pub(crate) fn encode_peers_v4(peers: impl Iterator<Item = SocketAddr>) -> Vec<Bytes> {
-        encode_peers(peers, to_v4)
+    pub(crate) fn encode_peers(peers: impl Iterator<Item = SocketAddr>) -> Vec<Bytes> {
+        encode_peers(peers)
     }
 
-    // TODO: Add `decode_nodes_v6`.
     pub(crate) fn decode_nodes_v4(&self) -> Option<Result<Vec<NodeContactInfo>, message::Error>> {
         Some(decode_nodes::<SocketAddrV4>(self.nodes?))
     }
 
-    // TODO: Add `encode_nodes_v6`.
-    pub(crate) fn encode_nodes_v4<'b>(
-        nodes: impl Iterator<Item = &'b NodeContactInfo>,
-    ) -> BytesMut {
-        encode_nodes(nodes, to_v4)
+    pub(crate) fn decode_nodes_v6(&self) -> Option<Result<Vec<NodeContactInfo>, message::Error>> {
+        Some(decode_nodes::<SocketAddrV6>(self.nodes6?))
     }
 }
 
@@ -180,6 +254,63 @@ impl<'a> AnnouncePeer<'a> {
     }
 }
 
+impl<'a> Get<'a> {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        id: &'a [u8],
+        token: Option<&'a [u8]>,
+        nodes: Option<&'a [u8]>,
+        nodes6: Option<&'a [u8]>,
+        value: Option<borrow::Value<'a>>,
+        key: Option<&'a [u8]>,
+        signature: Option<&'a [u8]>,
+        seq: Option<i64>,
+    ) -> Self {
+        Self {
+            id,
+            token,
+            nodes,
+            nodes6,
+            value,
+            raw_value: None,
+            key,
+            signature,
+            seq,
+            extra: BTreeMap::new(),
+        }
+    }
+
+    /// Returns `v` as the responder encoded it, or our encoding of `value` if unavailable.
+    pub(crate) fn value_bytes(&self) -> Option<Bytes> {
+        match (self.raw_value, &self.value) {
+            (Some(raw_value), _) => Some(Bytes::copy_from_slice(raw_value)),
+            (None, Some(value)) => {
+                let mut buffer = BytesMut::new();
+                value.encode(&mut buffer);
+                Some(buffer.freeze())
+            }
+            (None, None) => None,
+        }
+    }
+
+    pub(crate) fn decode_nodes_v4(&self) -> Option<Result<Vec<NodeContactInfo>, message::Error>> {
+        Some(decode_nodes::<SocketAddrV4>(self.nodes?))
+    }
+
+    pub(crate) fn decode_nodes_v6(&self) -> Option<Result<Vec<NodeContactInfo>, message::Error>> {
+        Some(decode_nodes::<SocketAddrV6>(self.nodes6?))
+    }
+}
+
+impl<'a> Put<'a> {
+    pub(crate) fn new(id: &'a [u8]) -> Self {
+        Self {
+            id,
+            extra: BTreeMap::new(),
+        }
+    }
+}
+
 impl From<compact::Error> for message::Error {
     fn from(error: compact::Error) -> Self {
         match error {
@@ -193,31 +324,34 @@ impl From<compact::Error> for message::Error {
     }
 }
 
-fn decode_peers<T>(peers: &[&[u8]]) -> Result<Vec<SocketAddr>, message::Error>
-where
-    T: Compact,
-    SocketAddr: From<T>,
-{
-    peers
-        .iter()
-        .copied()
-        .map(T::decode)
-        .map(|result| result.map(SocketAddr::from))
-        .try_collect()
-        .map_err(message::Error::from)
+fn decode_peers(peers: &[&[u8]]) -> Result<Vec<SocketAddr>, message::Error> {
+    peers.iter().copied().map(decode_endpoint).try_collect()
 }
 
-fn encode_peers<T, F>(peers: impl Iterator<Item = SocketAddr>, to_endpoint: F) -> Vec<Bytes>
-where
-    T: Compact,
-    F: Fn(SocketAddr) -> T,
-{
-    let mut buffer = BytesMut::new();
-    T::encode_many(peers.map(to_endpoint), &mut buffer);
-    T::split_buffer(buffer.freeze()).unwrap().collect()
+// BEP 32 tells IPv4 and IPv6 endpoints apart by their compact size.
+fn decode_endpoint(endpoint: &[u8]) -> Result<SocketAddr, message::Error> {
+    let endpoint = if endpoint.len() == SocketAddrV6::SIZE {
+        SocketAddrV6::decode(endpoint).map(SocketAddr::from)
+    } else {
+        SocketAddrV4::decode(endpoint).map(SocketAddr::from)
+    };
+    endpoint.map_err(message::Error::from)
+}
+
+fn encode_peers(peers: impl Iterator<Item = SocketAddr>) -> Vec<Bytes> {
+    peers
+        .map(|peer| {
+            let mut buffer = BytesMut::new();
+            match peer {
+                SocketAddr::V4(peer) => peer.encode(&mut buffer),
+                SocketAddr::V6(peer) => peer.encode(&mut buffer),
+            }
+            buffer.freeze()
+        })
+        .collect()
 }
 
-fn decode_nodes<T>(nodes: &[u8]) -> Result<Vec<NodeContactInfo>, message::Error>
+pub(crate) fn decode_nodes<T>(nodes: &[u8]) -> Result<Vec<NodeContactInfo>, message::Error>
 where
     T: Compact,
     SocketAddr: From<T>,
@@ -254,6 +388,13 @@ fn to_v4(endpoint: SocketAddr) -> SocketAddrV4 {
     }
 }
 
+fn to_v6(endpoint: SocketAddr) -> SocketAddrV6 {
+    match endpoint {
+        SocketAddr::V4(_) => std::unreachable!(),
+        SocketAddr::V6(endpoint) => endpoint,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use hex_literal::hex;
@@ -269,34 +410,102 @@ mod tests {
         let compact_nodes =
             hex!("0123456789abcdef 0123456789abcdef 01234567 7f000001 1f40").as_slice();
 
-        let find_node = FindNode::new(&[], &[]);
-        assert_eq!(find_node.decode_nodes_v4(), Ok(Vec::new()));
+        let find_node = FindNode::new(&[], Some(&[]), None);
+        assert_eq!(find_node.decode_nodes_v4(), Some(Ok(Vec::new())));
+        assert_eq!(find_node.decode_nodes_v6(), None);
         assert_eq!(FindNode::encode_nodes_v4([].iter()), b"".as_slice());
 
-        let find_node = FindNode::new(&[], compact_nodes);
-        assert_eq!(find_node.decode_nodes_v4(), Ok(nodes.clone()));
+        let find_node = FindNode::new(&[], Some(compact_nodes), None);
+        assert_eq!(find_node.decode_nodes_v4(), Some(Ok(nodes.clone())));
         assert_eq!(FindNode::encode_nodes_v4(nodes.iter()), compact_nodes);
 
-        let get_peers = GetPeers::new(&[], None, None, None);
-        assert_eq!(get_peers.decode_peers_v4(), None);
+        let get_peers = GetPeers::new(&[], None, None, None, None);
+        assert_eq!(get_peers.decode_peers(), None);
         assert_eq!(get_peers.decode_nodes_v4(), None);
+        assert_eq!(get_peers.decode_nodes_v6(), None);
 
-        let get_peers = GetPeers::new(&[], None, Some(Vec::new()), Some(&[]));
-        assert_eq!(get_peers.decode_peers_v4(), Some(Ok(Vec::new())));
+        let get_peers = GetPeers::new(&[], None, Some(Vec::new()), Some(&[]), None);
+        assert_eq!(get_peers.decode_peers(), Some(Ok(Vec::new())));
         assert_eq!(get_peers.decode_nodes_v4(), Some(Ok(Vec::new())));
-        assert_eq!(
-            GetPeers::encode_peers_v4([].into_iter()),
-            Vec::<Bytes>::new(),
+        assert_eq!(GetPeers::encode_peers([].into_iter()), Vec::<Bytes>::new());
+        assert_eq!(FindNode::encode_nodes_v4([].into_iter()), b"".as_slice());
+
+        let get_peers = GetPeers::new(
+            &[],
+            None,
+            Some(vec![compact_endpoint]),
+            Some(compact_nodes),
+            None,
         );
-        assert_eq!(GetPeers::encode_nodes_v4([].into_iter()), b"".as_slice());
-
-        let get_peers = GetPeers::new(&[], None, Some(vec![compact_endpoint]), Some(compact_nodes));
-        assert_eq!(get_peers.decode_peers_v4(), Some(Ok(vec![endpoint])));
+        assert_eq!(get_peers.decode_peers(), Some(Ok(vec![endpoint])));
         assert_eq!(get_peers.decode_nodes_v4(), Some(Ok(nodes.clone())));
         assert_eq!(
-            GetPeers::encode_peers_v4([endpoint].into_iter()),
+            GetPeers::encode_peers([endpoint].into_iter()),
             vec![Bytes::from_static(compact_endpoint)],
         );
-        assert_eq!(GetPeers::encode_nodes_v4(nodes.iter()), compact_nodes);
+        assert_eq!(FindNode::encode_nodes_v4(nodes.iter()), compact_nodes);
+    }
+
+    #[test]
+    fn compact_v6() {
+        let node_id = NodeId::new(hex!("0123456789abcdef 0123456789abcdef 01234567"));
+        let endpoint_v4 = "127.0.0.1:8000".parse().unwrap();
+        let endpoint_v6 = "[::1]:8000".parse().unwrap();
+        let nodes = vec![(node_id, endpoint_v6).into()];
+        let compact_endpoint_v4 = hex!("7f000001 1f40").as_slice();
+        let compact_endpoint_v6 = hex!("00000000000000000000000000000001 1f40").as_slice();
+        let compact_nodes = hex!(
+            "0123456789abcdef 0123456789abcdef 01234567"
+            "00000000000000000000000000000001 1f40"
+        )
+        .as_slice();
+
+        let find_node = FindNode::new(&[], None, Some(compact_nodes));
+        assert_eq!(find_node.decode_nodes_v4(), None);
+        assert_eq!(find_node.decode_nodes_v6(), Some(Ok(nodes.clone())));
+        assert_eq!(FindNode::encode_nodes_v6(nodes.iter()), compact_nodes);
+
+        let get_peers = GetPeers::new(
+            &[],
+            None,
+            Some(vec![compact_endpoint_v4, compact_endpoint_v6]),
+            None,
+            Some(compact_nodes),
+        );
+        assert_eq!(
+            get_peers.decode_peers(),
+            Some(Ok(vec![endpoint_v4, endpoint_v6])),
+        );
+        assert_eq!(get_peers.decode_nodes_v6(), Some(Ok(nodes.clone())));
+
+        let mut response = Response::new(BTreeMap::new());
+        assert_eq!(response.decode_requester(), None);
+        response.requester = Some(compact_endpoint_v4);
+        assert_eq!(response.decode_requester(), Some(Ok(endpoint_v4)));
+        response.requester = Some(compact_endpoint_v6);
+        assert_eq!(response.decode_requester(), Some(Ok(endpoint_v6)));
+        response.requester = Some(b"x");
+        assert_eq!(
+            response.decode_requester(),
+            Some(Err(message::Error::ExpectCompactSize {
+                size: 1,
+                expect: 6
+            })),
+        );
+
+        assert_eq!(
+            GetPeers::encode_peers([endpoint_v4, endpoint_v6].into_iter()),
+            vec![
+                Bytes::from_static(compact_endpoint_v4),
+                Bytes::from_static(compact_endpoint_v6),
+            ],
+        );
+        assert_eq!(
+            GetPeers::new(&[], None, Some(vec![&[0; 7]]), None, None).decode_peers(),
+            Some(Err(message::Error::ExpectCompactSize {
+                size: 7,
+                expect: 6
+            })),
+        );
     }
 }
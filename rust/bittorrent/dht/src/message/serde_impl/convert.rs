@@ -1,3 +1,4 @@
+use ed25519_dalek::{PUBLIC_KEY_LENGTH, SIGNATURE_LENGTH};
 use snafu::prelude::*;
 
 use bittorrent_base::{INFO_HASH_SIZE, NODE_ID_SIZE};
@@ -7,7 +8,10 @@ use bittorrent_bencode::{
     dict,
 };
 
-use crate::message::{Error, ExpectIdSizeSnafu, ExpectInfoHashSizeSnafu};
+use crate::message::{
+    Error, ExpectIdSizeSnafu, ExpectInfoHashSizeSnafu, ExpectPublicKeySizeSnafu,
+    ExpectSignatureSizeSnafu,
+};
 
 impl From<convert::Error> for Error {
     fn from(error: convert::Error) -> Self {
@@ -51,6 +55,28 @@ pub(super) fn to_info_hash(value: borrow::Value) -> Result<&'_ [u8], Error> {
     })
 }
 
+pub(super) fn to_public_key(value: borrow::Value<'_>) -> Result<&'_ [u8], Error> {
+    to_bytes(value).and_then(|key| {
+        ensure!(
+            key.len() == PUBLIC_KEY_LENGTH,
+            ExpectPublicKeySizeSnafu { key: key.to_vec() },
+        );
+        Ok(key)
+    })
+}
+
+pub(super) fn to_signature(value: borrow::Value<'_>) -> Result<&'_ [u8], Error> {
+    to_bytes(value).and_then(|signature| {
+        ensure!(
+            signature.len() == SIGNATURE_LENGTH,
+            ExpectSignatureSizeSnafu {
+                signature: signature.to_vec(),
+            },
+        );
+        Ok(signature)
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::{super::test_harness::*, *};
@@ -22,6 +22,7 @@ use crate::message::{
 const TXID: &[u8] = b"t";
 const MESSAGE_TYPE: &[u8] = b"y";
 const VERSION: &[u8] = b"v";
+const READ_ONLY: &[u8] = b"ro"; // BEP 43 Read-only DHT Nodes
 
 const QUERY: &[u8] = b"q";
 const RESPONSE: &[u8] = b"r";
@@ -35,6 +36,9 @@ impl<'a> TryFrom<BTreeMap<&'a [u8], borrow::Value<'a>>> for Message<'a> {
             txid: dict.must_remove(TXID).and_then(to_bytes::<Error>)?,
             payload: Payload::try_from(&mut dict)?,
             version: dict.remove(VERSION).map(to_bytes::<Error>).transpose()?,
+            read_only: dict
+                .remove_int::<Error>(READ_ONLY)?
+                .map(|read_only| read_only != 0),
             extra: dict,
         })
     }
@@ -45,6 +49,9 @@ impl<'a> From<Message<'a>> for BTreeMap<&'a Bytes, own::Value> {
         let mut dict = Self::from(message.payload);
         dict.insert_from(TXID, Some(message.txid), from_bytes);
         dict.insert_from(VERSION, message.version, from_bytes);
+        dict.insert_from(READ_ONLY, message.read_only, |read_only| {
+            i64::from(read_only).into()
+        });
         dict.append(&mut from_dict(message.extra, Bytes::new));
         dict
     }
@@ -154,6 +161,7 @@ mod tests {
                     new_btree_map([(b"id", new_bytes(TEST_ID)), (b"spam egg", 1.into())]).into(),
                 ),
                 (b"v", new_bytes(b"some version")),
+                (b"ro", 1.into()),
                 (b"foo bar", 0.into()),
             ],
             Message {
@@ -163,6 +171,7 @@ mod tests {
                     extra: new_btree_map([(b"spam egg", 1.into())]),
                 })),
                 version: Some(b"some version"),
+                read_only: Some(true),
                 extra: new_btree_map([(b"foo bar", 0.into())]),
             },
         );
@@ -182,6 +191,7 @@ mod tests {
             Payload::Response(response::Response {
                 response: new_btree_map([(b"id", new_bytes(TEST_ID)), (b"spam egg", 1.into())]),
                 requester: Some(b"some ip"),
+                raw_value: None,
             }),
         );
         test_ok(
@@ -4,18 +4,18 @@ use serde_bytes::Bytes;
 
 use bittorrent_bencode::{
     borrow,
-    convert::{from_bytes, from_dict, to_bytes, to_dict, to_int},
+    convert::{from_bytes, from_dict, from_vec, to_bytes, to_dict, to_int, to_vec},
     dict::{DictionaryInsert, DictionaryRemove},
     own,
 };
 
 use crate::message::{
-    query::{AnnouncePeer, FindNode, GetPeers, Ping, Query},
+    query::{AnnouncePeer, FindNode, Get, GetPeers, Ping, Put, Query},
     Error,
 };
 
 use super::{
-    convert::{to_id, to_info_hash},
+    convert::{to_id, to_info_hash, to_public_key, to_signature},
     QUERY,
 };
 
@@ -23,27 +23,46 @@ const PING: &[u8] = b"ping";
 const FIND_NODE: &[u8] = b"find_node";
 const GET_PEERS: &[u8] = b"get_peers";
 const ANNOUNCE_PEER: &[u8] = b"announce_peer";
+const GET: &[u8] = b"get";
+const PUT: &[u8] = b"put";
 
 const ARGUMENTS: &[u8] = b"a";
 
+const CAS: &[u8] = b"cas";
 const ID: &[u8] = b"id";
 const IMPLIED_PORT: &[u8] = b"implied_port";
 const INFO_HASH: &[u8] = b"info_hash";
+const KEY: &[u8] = b"k";
+const NOSEED: &[u8] = b"noseed";
 const PORT: &[u8] = b"port";
+const SALT: &[u8] = b"salt";
+const SCRAPE: &[u8] = b"scrape";
+const SEED: &[u8] = b"seed";
+const SEQ: &[u8] = b"seq";
+const SIGNATURE: &[u8] = b"sig";
 const TARGET: &[u8] = b"target";
 const TOKEN: &[u8] = b"token";
+const VALUE: &[u8] = b"v";
+const WANT: &[u8] = b"want";
 
 impl<'a> TryFrom<&mut BTreeMap<&'a [u8], borrow::Value<'a>>> for Query<'a> {
     type Error = Error;
 
     fn try_from(dict: &mut BTreeMap<&'a [u8], borrow::Value<'a>>) -> Result<Self, Self::Error> {
         let method_name = dict.must_remove::<Error>(QUERY).and_then(to_bytes)?;
-        let (arguments, _) = dict.must_remove::<Error>(ARGUMENTS).and_then(to_dict)?;
+        let arguments = dict.must_remove::<Error>(ARGUMENTS)?;
+        let raw_value = arguments.raw_value_of(VALUE);
+        let (arguments, _) = to_dict::<Error>(arguments)?;
         match method_name {
             PING => Ok(Self::Ping(Ping::try_from(arguments)?)),
             FIND_NODE => Ok(Self::FindNode(FindNode::try_from(arguments)?)),
             GET_PEERS => Ok(Self::GetPeers(GetPeers::try_from(arguments)?)),
             ANNOUNCE_PEER => Ok(Self::AnnouncePeer(AnnouncePeer::try_from(arguments)?)),
+            GET => Ok(Self::Get(Get::try_from(arguments)?)),
+            PUT => Ok(Self::Put(Put {
+                raw_value,
+                ..Put::try_from(arguments)?
+            })),
             _ => Err(Error::UnknownMethodName {
                 method_name: Vec::from(method_name),
             }),
@@ -58,6 +77,8 @@ impl<'a> From<Query<'a>> for BTreeMap<&'a Bytes, own::Value> {
             Query::FindNode(find_node) => (FIND_NODE, find_node.into()),
             Query::GetPeers(get_peers) => (GET_PEERS, get_peers.into()),
             Query::AnnouncePeer(announce_peer) => (ANNOUNCE_PEER, announce_peer.into()),
+            Query::Get(get) => (GET, get.into()),
+            Query::Put(put) => (PUT, put.into()),
         };
         Self::from([
             (Bytes::new(QUERY), from_bytes(method_name)),
@@ -92,6 +113,10 @@ impl<'a> TryFrom<BTreeMap<&'a [u8], borrow::Value<'a>>> for FindNode<'a> {
         Ok(Self {
             id: dict.must_remove(ID).and_then(to_id)?,
             target: dict.must_remove(TARGET).and_then(to_id)?,
+            want: dict
+                .remove(WANT)
+                .map(|want| to_vec(want, to_bytes::<Error>))
+                .transpose()?,
             extra: dict,
         })
     }
@@ -103,6 +128,7 @@ impl<'a> From<FindNode<'a>> for BTreeMap<own::ByteString, own::Value> {
             (own::ByteString::from(ID), from_bytes(find_node.id)),
             (own::ByteString::from(TARGET), from_bytes(find_node.target)),
         ]);
+        dict.insert_from(WANT, find_node.want, |want| from_vec(want, from_bytes));
         dict.append(&mut from_dict(find_node.extra, own::ByteString::from));
         dict
     }
@@ -115,6 +141,12 @@ impl<'a> TryFrom<BTreeMap<&'a [u8], borrow::Value<'a>>> for GetPeers<'a> {
         Ok(Self {
             id: dict.must_remove(ID).and_then(to_id)?,
             info_hash: dict.must_remove(INFO_HASH).and_then(to_info_hash)?,
+            want: dict
+                .remove(WANT)
+                .map(|want| to_vec(want, to_bytes::<Error>))
+                .transpose()?,
+            scrape: dict.remove_int::<Error>(SCRAPE)?.map(|scrape| scrape != 0),
+            noseed: dict.remove_int::<Error>(NOSEED)?.map(|noseed| noseed != 0),
             extra: dict,
         })
     }
@@ -129,6 +161,9 @@ impl<'a> From<GetPeers<'a>> for BTreeMap<own::ByteString, own::Value> {
                 from_bytes(get_peers.info_hash),
             ),
         ]);
+        dict.insert_from(WANT, get_peers.want, |want| from_vec(want, from_bytes));
+        dict.insert_from(SCRAPE, get_peers.scrape, |scrape| i64::from(scrape).into());
+        dict.insert_from(NOSEED, get_peers.noseed, |noseed| i64::from(noseed).into());
         dict.append(&mut from_dict(get_peers.extra, own::ByteString::from));
         dict
     }
@@ -151,6 +186,7 @@ impl<'a> TryFrom<BTreeMap<&'a [u8], borrow::Value<'a>>> for AnnouncePeer<'a> {
                 .transpose()?
                 .map(|implied_port| implied_port != 0),
             token: dict.must_remove::<Error>(TOKEN).and_then(to_bytes)?,
+            seed: dict.remove_int::<Error>(SEED)?.map(|seed| seed != 0),
             extra: dict,
         })
     }
@@ -176,11 +212,78 @@ impl<'a> From<AnnouncePeer<'a>> for BTreeMap<own::ByteString, own::Value> {
         dict.insert_from(IMPLIED_PORT, announce_peer.implied_port, |implied_port| {
             i64::from(implied_port).into()
         });
+        dict.insert_from(SEED, announce_peer.seed, |seed| i64::from(seed).into());
         dict.append(&mut from_dict(announce_peer.extra, own::ByteString::from));
         dict
     }
 }
 
+impl<'a> TryFrom<BTreeMap<&'a [u8], borrow::Value<'a>>> for Get<'a> {
+    type Error = Error;
+
+    fn try_from(mut dict: BTreeMap<&'a [u8], borrow::Value<'a>>) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: dict.must_remove(ID).and_then(to_id)?,
+            target: dict.must_remove(TARGET).and_then(to_id)?,
+            seq: dict.remove_int::<Error>(SEQ)?,
+            want: dict
+                .remove(WANT)
+                .map(|want| to_vec(want, to_bytes::<Error>))
+                .transpose()?,
+            extra: dict,
+        })
+    }
+}
+
+impl<'a> From<Get<'a>> for BTreeMap<own::ByteString, own::Value> {
+    fn from(get: Get<'a>) -> Self {
+        let mut dict = Self::from([
+            (own::ByteString::from(ID), from_bytes(get.id)),
+            (own::ByteString::from(TARGET), from_bytes(get.target)),
+        ]);
+        dict.insert_from(SEQ, get.seq, own::Value::from);
+        dict.insert_from(WANT, get.want, |want| from_vec(want, from_bytes));
+        dict.append(&mut from_dict(get.extra, own::ByteString::from));
+        dict
+    }
+}
+
+impl<'a> TryFrom<BTreeMap<&'a [u8], borrow::Value<'a>>> for Put<'a> {
+    type Error = Error;
+
+    fn try_from(mut dict: BTreeMap<&'a [u8], borrow::Value<'a>>) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: dict.must_remove(ID).and_then(to_id)?,
+            token: dict.must_remove::<Error>(TOKEN).and_then(to_bytes)?,
+            value: dict.must_remove::<Error>(VALUE)?,
+            raw_value: None,
+            key: dict.remove(KEY).map(to_public_key).transpose()?,
+            signature: dict.remove(SIGNATURE).map(to_signature).transpose()?,
+            seq: dict.remove_int::<Error>(SEQ)?,
+            cas: dict.remove_int::<Error>(CAS)?,
+            salt: dict.remove(SALT).map(to_bytes::<Error>).transpose()?,
+            extra: dict,
+        })
+    }
+}
+
+impl<'a> From<Put<'a>> for BTreeMap<own::ByteString, own::Value> {
+    fn from(put: Put<'a>) -> Self {
+        let mut dict = Self::from([
+            (own::ByteString::from(ID), from_bytes(put.id)),
+            (own::ByteString::from(TOKEN), from_bytes(put.token)),
+            (own::ByteString::from(VALUE), put.value.to_owned()),
+        ]);
+        dict.insert_from(KEY, put.key, from_bytes);
+        dict.insert_from(SIGNATURE, put.signature, from_bytes);
+        dict.insert_from(SEQ, put.seq, own::Value::from);
+        dict.insert_from(CAS, put.cas, own::Value::from);
+        dict.insert_from(SALT, put.salt, from_bytes);
+        dict.append(&mut from_dict(put.extra, own::ByteString::from));
+        dict
+    }
+}
+
 #[cfg(test)]
 mod test_harness {
     use super::*;
@@ -216,6 +319,7 @@ mod tests {
             Query::FindNode(FindNode {
                 id: TEST_ID,
                 target: TEST_ID,
+                want: None,
                 extra: new_btree_map([(b"foo bar", 0.into())]),
             }),
         );
@@ -235,9 +339,36 @@ mod tests {
             Query::GetPeers(GetPeers {
                 id: TEST_ID,
                 info_hash: TEST_ID,
+                want: None,
+                scrape: None,
+                noseed: None,
                 extra: new_btree_map([(b"foo bar", 0.into())]),
             }),
         );
+        test_ok(
+            [
+                (b"q", new_bytes(b"get_peers")),
+                (
+                    b"a",
+                    new_btree_map([
+                        (b"id", new_bytes(TEST_ID)),
+                        (b"info_hash", new_bytes(TEST_ID)),
+                        (b"want", vec![new_bytes(b"n4"), new_bytes(b"n6")].into()),
+                        (b"scrape", 1.into()),
+                        (b"noseed", 0.into()),
+                    ])
+                    .into(),
+                ),
+            ],
+            Query::GetPeers(GetPeers {
+                id: TEST_ID,
+                info_hash: TEST_ID,
+                want: Some(vec![b"n4", b"n6"]),
+                scrape: Some(true),
+                noseed: Some(false),
+                extra: new_btree_map([]),
+            }),
+        );
         test_ok(
             [
                 (b"q", new_bytes(b"announce_peer")),
@@ -249,6 +380,7 @@ mod tests {
                         (b"port", 8000.into()),
                         (b"implied_port", 1.into()),
                         (b"token", new_bytes(b"some token")),
+                        (b"seed", 1.into()),
                         (b"foo bar", 0.into()),
                     ])
                     .into(),
@@ -260,9 +392,82 @@ mod tests {
                 port: 8000,
                 implied_port: Some(true),
                 token: b"some token",
+                seed: Some(true),
+                extra: new_btree_map([(b"foo bar", 0.into())]),
+            }),
+        );
+        test_ok(
+            [
+                (b"q", new_bytes(b"get")),
+                (
+                    b"a",
+                    new_btree_map([
+                        (b"id", new_bytes(TEST_ID)),
+                        (b"target", new_bytes(TEST_ID)),
+                        (b"seq", 1.into()),
+                        (b"foo bar", 0.into()),
+                    ])
+                    .into(),
+                ),
+            ],
+            Query::Get(Get {
+                id: TEST_ID,
+                target: TEST_ID,
+                seq: Some(1),
+                want: None,
+                extra: new_btree_map([(b"foo bar", 0.into())]),
+            }),
+        );
+        test_ok(
+            [
+                (b"q", new_bytes(b"put")),
+                (
+                    b"a",
+                    new_btree_map([
+                        (b"id", new_bytes(TEST_ID)),
+                        (b"token", new_bytes(b"some token")),
+                        (b"v", new_bytes(b"some value")),
+                        (b"k", new_bytes(&[1; 32])),
+                        (b"sig", new_bytes(&[2; 64])),
+                        (b"seq", 3.into()),
+                        (b"cas", 2.into()),
+                        (b"salt", new_bytes(b"some salt")),
+                        (b"foo bar", 0.into()),
+                    ])
+                    .into(),
+                ),
+            ],
+            Query::Put(Put {
+                id: TEST_ID,
+                token: b"some token",
+                value: new_bytes(b"some value"),
+                raw_value: None,
+                key: Some(&[1; 32]),
+                signature: Some(&[2; 64]),
+                seq: Some(3),
+                cas: Some(2),
+                salt: Some(b"some salt"),
                 extra: new_btree_map([(b"foo bar", 0.into())]),
             }),
         );
+        test_err::<Query, _>(
+            [
+                (b"q", new_bytes(b"put")),
+                (
+                    b"a",
+                    new_btree_map([
+                        (b"id", new_bytes(TEST_ID)),
+                        (b"token", new_bytes(b"some token")),
+                        (b"v", new_bytes(b"some value")),
+                        (b"k", new_bytes(b"some key")),
+                    ])
+                    .into(),
+                ),
+            ],
+            Error::ExpectPublicKeySize {
+                key: b"some key".to_vec(),
+            },
+        );
         test_err::<Query, _>(
             [
                 (b"q", new_bytes(b"no-such-method")),
@@ -291,4 +496,19 @@ mod tests {
             Error::InvalidNodePort { port: 65536 },
         );
     }
+
+    #[test]
+    fn put_raw_value() {
+        let value = borrow::Value::try_from(
+            b"d1:ad2:id20:0123456789abcdef01235:token1:x1:vi-0ee1:q3:pute".as_slice(),
+        )
+        .unwrap();
+        let (mut dict, _) = to_dict::<Error>(value).unwrap();
+        let Query::Put(put) = Query::try_from(&mut dict).unwrap() else {
+            std::unreachable!()
+        };
+        assert_eq!(put.value, borrow::Value::Integer(0));
+        assert_eq!(put.raw_value, Some(b"i-0e".as_slice()));
+        assert_eq!(put.value_bytes(), b"i-0e".as_slice());
+    }
 }
@@ -11,22 +11,41 @@ use bittorrent_bencode::{
 };
 
 use crate::message::{
-    response::{AnnouncePeer, Error as ErrorResponse, FindNode, GetPeers, Ping, Response},
+    response::{
+        AnnouncePeer, Error as ErrorResponse, FindNode, Get, GetPeers, Ping, Put, Response,
+    },
     Error, ExpectErrorListSizeSnafu, MissingDictionaryKeySnafu,
 };
 
-use super::{convert::to_id, ERROR, RESPONSE};
+use super::{
+    convert::{to_id, to_public_key, to_signature},
+    ERROR, RESPONSE,
+};
 
 const ID: &[u8] = b"id";
 const TOKEN: &[u8] = b"token";
 const NODES: &[u8] = b"nodes";
+const NODES6: &[u8] = b"nodes6";
+const PEERS: &[u8] = b"BFpe";
+const SEEDS: &[u8] = b"BFsd";
 const VALUES: &[u8] = b"values";
 const REQUESTER: &[u8] = b"ip"; // BEP 42 DHT Security Extension
 
+// BEP 44 Storing Arbitrary Data in the DHT
+const VALUE: &[u8] = b"v";
+const KEY: &[u8] = b"k";
+const SIGNATURE: &[u8] = b"sig";
+const SEQ: &[u8] = b"seq";
+
 const GENERIC_ERROR: i64 = 201;
 const SERVER_ERROR: i64 = 202;
 const PROTOCOL_ERROR: i64 = 203;
 const METHOD_UNKNOWN: i64 = 204;
+const MESSAGE_TOO_BIG: i64 = 205;
+const INVALID_SIGNATURE: i64 = 206;
+const SALT_TOO_BIG: i64 = 207;
+const CAS_MISMATCH: i64 = 301;
+const SEQUENCE_NUMBER_LESS_THAN_CURRENT: i64 = 302;
 
 impl<'a> TryFrom<&mut BTreeMap<&'a [u8], borrow::Value<'a>>> for Response<'a> {
     type Error = Error;
@@ -35,10 +54,13 @@ impl<'a> TryFrom<&mut BTreeMap<&'a [u8], borrow::Value<'a>>> for Response<'a> {
         // The implementation of BEP 42 in libtorrent also appears to send back our external IP
         // address and port in "ip" and "p" of the response dictionary.  However, BEP 42 does not
         // specify this behavior.  For now, we ignore them.
-        let (response, _) = dict.must_remove::<Error>(RESPONSE).and_then(to_dict)?;
+        let response = dict.must_remove::<Error>(RESPONSE)?;
+        let raw_value = response.raw_value_of(VALUE);
+        let (response, _) = to_dict::<Error>(response)?;
         Ok(Self {
             response,
             requester: dict.remove(REQUESTER).map(to_bytes::<Error>).transpose()?,
+            raw_value,
         })
     }
 }
@@ -93,20 +115,30 @@ impl<'a> TryFrom<BTreeMap<&'a [u8], borrow::Value<'a>>> for FindNode<'a> {
     type Error = Error;
 
     fn try_from(mut dict: BTreeMap<&'a [u8], borrow::Value<'a>>) -> Result<Self, Self::Error> {
-        Ok(Self {
+        let this = Self {
             id: dict.must_remove(ID).and_then(to_id)?,
-            nodes: dict.must_remove::<Error>(NODES).and_then(to_bytes)?,
+            nodes: dict.remove(NODES).map(to_bytes::<Error>).transpose()?,
+            nodes6: dict.remove(NODES6).map(to_bytes::<Error>).transpose()?,
             extra: dict,
-        })
+        };
+        ensure!(
+            this.nodes.is_some() || this.nodes6.is_some(),
+            MissingDictionaryKeySnafu {
+                key: "nodes or nodes6",
+            },
+        );
+        Ok(this)
     }
 }
 
 impl<'a> From<FindNode<'a>> for BTreeMap<&'a [u8], borrow::Value<'a>> {
     fn from(mut find_node: FindNode<'a>) -> Self {
-        let mut dict = Self::from([
-            (ID, borrow::Value::ByteString(find_node.id)),
-            (NODES, borrow::Value::ByteString(find_node.nodes)),
-        ]);
+        let mut dict = Self::from([(ID, borrow::Value::ByteString(find_node.id))]);
+        for (key, nodes) in [(NODES, find_node.nodes), (NODES6, find_node.nodes6)] {
+            if let Some(nodes) = nodes {
+                dict.insert(key, borrow::Value::ByteString(nodes));
+            }
+        }
         dict.append(&mut find_node.extra);
         dict
     }
@@ -132,12 +164,15 @@ impl<'a> TryFrom<BTreeMap<&'a [u8], borrow::Value<'a>>> for GetPeers<'a> {
                 .map(|values| to_vec(values, to_bytes::<Error>))
                 .transpose()?,
             nodes: dict.remove(NODES).map(to_bytes::<Error>).transpose()?,
+            nodes6: dict.remove(NODES6).map(to_bytes::<Error>).transpose()?,
+            seeds: dict.remove(SEEDS).map(to_bytes::<Error>).transpose()?,
+            peers: dict.remove(PEERS).map(to_bytes::<Error>).transpose()?,
             extra: dict,
         };
         ensure!(
-            this.values.is_some() || this.nodes.is_some(),
+            this.values.is_some() || this.nodes.is_some() || this.nodes6.is_some(),
             MissingDictionaryKeySnafu {
-                key: "values or nodes",
+                key: "values or nodes or nodes6",
             },
         );
         if this.values.is_some() {
@@ -164,8 +199,15 @@ impl<'a> From<GetPeers<'a>> for BTreeMap<&'a [u8], borrow::Value<'a>> {
                 ),
             );
         }
-        if let Some(nodes) = get_peers.nodes {
-            dict.insert(NODES, borrow::Value::ByteString(nodes));
+        for (key, value) in [
+            (NODES, get_peers.nodes),
+            (NODES6, get_peers.nodes6),
+            (SEEDS, get_peers.seeds),
+            (PEERS, get_peers.peers),
+        ] {
+            if let Some(value) = value {
+                dict.insert(key, borrow::Value::ByteString(value));
+            }
         }
         dict.append(&mut get_peers.extra);
         dict
@@ -199,6 +241,88 @@ impl<'a> From<AnnouncePeer<'a>> for BTreeMap<&'a [u8], borrow::Value<'a>> {
     }
 }
 
+impl<'a> TryFrom<Response<'a>> for Get<'a> {
+    type Error = Error;
+
+    fn try_from(response: Response<'a>) -> Result<Self, Self::Error> {
+        Ok(Self {
+            raw_value: response.raw_value,
+            ..response.response.try_into()?
+        })
+    }
+}
+
+impl<'a> TryFrom<BTreeMap<&'a [u8], borrow::Value<'a>>> for Get<'a> {
+    type Error = Error;
+
+    fn try_from(mut dict: BTreeMap<&'a [u8], borrow::Value<'a>>) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: dict.must_remove(ID).and_then(to_id)?,
+            token: dict.remove(TOKEN).map(to_bytes::<Error>).transpose()?,
+            nodes: dict.remove(NODES).map(to_bytes::<Error>).transpose()?,
+            nodes6: dict.remove(NODES6).map(to_bytes::<Error>).transpose()?,
+            value: dict.remove(VALUE),
+            raw_value: None,
+            key: dict.remove(KEY).map(to_public_key).transpose()?,
+            signature: dict.remove(SIGNATURE).map(to_signature).transpose()?,
+            seq: dict.remove_int::<Error>(SEQ)?,
+            extra: dict,
+        })
+    }
+}
+
+impl<'a> From<Get<'a>> for BTreeMap<&'a [u8], borrow::Value<'a>> {
+    fn from(mut get: Get<'a>) -> Self {
+        let mut dict = Self::from([(ID, borrow::Value::ByteString(get.id))]);
+        for (key, value) in [
+            (TOKEN, get.token),
+            (NODES, get.nodes),
+            (NODES6, get.nodes6),
+            (KEY, get.key),
+            (SIGNATURE, get.signature),
+        ] {
+            if let Some(value) = value {
+                dict.insert(key, borrow::Value::ByteString(value));
+            }
+        }
+        if let Some(value) = get.value {
+            dict.insert(VALUE, value);
+        }
+        if let Some(seq) = get.seq {
+            dict.insert(SEQ, borrow::Value::Integer(seq));
+        }
+        dict.append(&mut get.extra);
+        dict
+    }
+}
+
+impl<'a> TryFrom<Response<'a>> for Put<'a> {
+    type Error = Error;
+
+    fn try_from(response: Response<'a>) -> Result<Self, Self::Error> {
+        response.response.try_into()
+    }
+}
+
+impl<'a> TryFrom<BTreeMap<&'a [u8], borrow::Value<'a>>> for Put<'a> {
+    type Error = Error;
+
+    fn try_from(mut dict: BTreeMap<&'a [u8], borrow::Value<'a>>) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: dict.must_remove(ID).and_then(to_id)?,
+            extra: dict,
+        })
+    }
+}
+
+impl<'a> From<Put<'a>> for BTreeMap<&'a [u8], borrow::Value<'a>> {
+    fn from(mut put: Put<'a>) -> Self {
+        let mut dict = Self::from([(ID, borrow::Value::ByteString(put.id))]);
+        dict.append(&mut put.extra);
+        dict
+    }
+}
+
 impl<'a> TryFrom<&mut BTreeMap<&'a [u8], borrow::Value<'a>>> for ErrorResponse<'a> {
     type Error = Error;
 
@@ -220,6 +344,13 @@ impl<'a> TryFrom<&mut BTreeMap<&'a [u8], borrow::Value<'a>>> for ErrorResponse<'
             SERVER_ERROR => Ok(Self::ServerError { message }),
             PROTOCOL_ERROR => Ok(Self::ProtocolError { message }),
             METHOD_UNKNOWN => Ok(Self::MethodUnknown { message }),
+            MESSAGE_TOO_BIG => Ok(Self::MessageTooBig { message }),
+            INVALID_SIGNATURE => Ok(Self::InvalidSignature { message }),
+            SALT_TOO_BIG => Ok(Self::SaltTooBig { message }),
+            CAS_MISMATCH => Ok(Self::CasMismatch { message }),
+            SEQUENCE_NUMBER_LESS_THAN_CURRENT => {
+                Ok(Self::SequenceNumberLessThanCurrent { message })
+            }
             _ => Err(Error::UnknownErrorCode { error_code }),
         }
     }
@@ -232,6 +363,13 @@ impl<'a> From<ErrorResponse<'a>> for BTreeMap<&'a Bytes, own::Value> {
             ErrorResponse::ServerError { message } => (SERVER_ERROR, message),
             ErrorResponse::ProtocolError { message } => (PROTOCOL_ERROR, message),
             ErrorResponse::MethodUnknown { message } => (METHOD_UNKNOWN, message),
+            ErrorResponse::MessageTooBig { message } => (MESSAGE_TOO_BIG, message),
+            ErrorResponse::InvalidSignature { message } => (INVALID_SIGNATURE, message),
+            ErrorResponse::SaltTooBig { message } => (SALT_TOO_BIG, message),
+            ErrorResponse::CasMismatch { message } => (CAS_MISMATCH, message),
+            ErrorResponse::SequenceNumberLessThanCurrent { message } => {
+                (SEQUENCE_NUMBER_LESS_THAN_CURRENT, message)
+            }
         };
         Self::from([(
             Bytes::new(ERROR),
@@ -274,6 +412,7 @@ mod tests {
             Response {
                 response: new_btree_map([]),
                 requester: None,
+                raw_value: None,
             },
         );
     }
@@ -291,6 +430,7 @@ mod tests {
             let response = Response {
                 response: dict.clone(),
                 requester: None,
+                raw_value: None,
             };
             assert_eq!(T::try_from(response), Ok(expect.clone()));
             assert_eq!(T::try_from(dict.clone()), Ok(expect.clone()));
@@ -313,10 +453,30 @@ mod tests {
             ],
             FindNode {
                 id: TEST_ID,
-                nodes: b"some nodes",
+                nodes: Some(b"some nodes"),
+                nodes6: None,
                 extra: new_btree_map([(b"foo bar", 0.into())]),
             },
         );
+        test_ok(
+            [
+                (b"id", new_bytes(TEST_ID)),
+                (b"nodes", new_bytes(b"some nodes")),
+                (b"nodes6", new_bytes(b"some nodes6")),
+            ],
+            FindNode {
+                id: TEST_ID,
+                nodes: Some(b"some nodes"),
+                nodes6: Some(b"some nodes6"),
+                extra: new_btree_map([]),
+            },
+        );
+        test_err::<FindNode, _>(
+            [(b"id", new_bytes(TEST_ID)), (b"foo bar", 0.into())],
+            Error::MissingDictionaryKey {
+                key: "nodes or nodes6".to_string(),
+            },
+        );
 
         test_ok(
             [
@@ -331,9 +491,30 @@ mod tests {
                 token: Some(b"some token"),
                 values: Some(vec![b"v0", b"v1"]),
                 nodes: Some(b"some nodes"),
+                nodes6: None,
+                seeds: None,
+                peers: None,
                 extra: new_btree_map([(b"foo bar", 0.into())]),
             },
         );
+        test_ok(
+            [
+                (b"id", new_bytes(TEST_ID)),
+                (b"nodes6", new_bytes(b"some nodes6")),
+                (b"BFsd", new_bytes(b"some seeds")),
+                (b"BFpe", new_bytes(b"some peers")),
+            ],
+            GetPeers {
+                id: TEST_ID,
+                token: None,
+                values: None,
+                nodes: None,
+                nodes6: Some(b"some nodes6"),
+                seeds: Some(b"some seeds"),
+                peers: Some(b"some peers"),
+                extra: new_btree_map([]),
+            },
+        );
         test_err::<GetPeers, _>(
             [
                 (b"id", new_bytes(TEST_ID)),
@@ -341,7 +522,7 @@ mod tests {
                 (b"foo bar", 0.into()),
             ],
             Error::MissingDictionaryKey {
-                key: "values or nodes".to_string(),
+                key: "values or nodes or nodes6".to_string(),
             },
         );
         test_err::<GetPeers, _>(
@@ -362,6 +543,67 @@ mod tests {
                 extra: new_btree_map([(b"foo bar", 0.into())]),
             },
         );
+
+        test_ok(
+            [
+                (b"id", new_bytes(TEST_ID)),
+                (b"token", new_bytes(b"some token")),
+                (b"nodes", new_bytes(b"some nodes")),
+                (b"nodes6", new_bytes(b"some nodes6")),
+                (b"v", new_bytes(b"some value")),
+                (b"k", new_bytes(&[1; 32])),
+                (b"sig", new_bytes(&[2; 64])),
+                (b"seq", 3.into()),
+                (b"foo bar", 0.into()),
+            ],
+            Get {
+                id: TEST_ID,
+                token: Some(b"some token"),
+                nodes: Some(b"some nodes"),
+                nodes6: Some(b"some nodes6"),
+                value: Some(new_bytes(b"some value")),
+                raw_value: None,
+                key: Some(&[1; 32]),
+                signature: Some(&[2; 64]),
+                seq: Some(3),
+                extra: new_btree_map([(b"foo bar", 0.into())]),
+            },
+        );
+        test_ok(
+            [
+                (b"id", new_bytes(TEST_ID)),
+                (b"token", new_bytes(b"some token")),
+            ],
+            Get {
+                id: TEST_ID,
+                token: Some(b"some token"),
+                nodes: None,
+                nodes6: None,
+                value: None,
+                raw_value: None,
+                key: None,
+                signature: None,
+                seq: None,
+                extra: new_btree_map([]),
+            },
+        );
+        test_err::<Get, _>(
+            [
+                (b"id", new_bytes(TEST_ID)),
+                (b"sig", new_bytes(b"some signature")),
+            ],
+            Error::ExpectSignatureSize {
+                signature: b"some signature".to_vec(),
+            },
+        );
+
+        test_ok(
+            [(b"id", new_bytes(TEST_ID)), (b"foo bar", 0.into())],
+            Put {
+                id: TEST_ID,
+                extra: new_btree_map([(b"foo bar", 0.into())]),
+            },
+        );
     }
 
     #[test]
@@ -386,9 +628,30 @@ mod tests {
             [(b"e", vec![200.into(), new_bytes(b"foo bar")].into())],
             Error::UnknownErrorCode { error_code: 200 },
         );
-        test_err::<ErrorResponse, _>(
+        test_ok(
             [(b"e", vec![205.into(), new_bytes(b"foo bar")].into())],
-            Error::UnknownErrorCode { error_code: 205 },
+            ErrorResponse::MessageTooBig { message: "foo bar" },
+        );
+        test_ok(
+            [(b"e", vec![302.into(), new_bytes(b"foo bar")].into())],
+            ErrorResponse::SequenceNumberLessThanCurrent { message: "foo bar" },
+        );
+        test_err::<ErrorResponse, _>(
+            [(b"e", vec![208.into(), new_bytes(b"foo bar")].into())],
+            Error::UnknownErrorCode { error_code: 208 },
         );
     }
+
+    #[test]
+    fn get_raw_value() {
+        let value = borrow::Value::try_from(
+            b"d1:rd2:id20:0123456789abcdef01231:v03:xyze1:y1:re".as_slice(),
+        )
+        .unwrap();
+        let (mut dict, _) = to_dict::<Error>(value).unwrap();
+        let get = Get::try_from(Response::try_from(&mut dict).unwrap()).unwrap();
+        assert_eq!(get.value, Some(new_bytes(b"xyz")));
+        assert_eq!(get.raw_value, Some(b"03:xyz".as_slice()));
+        assert_eq!(get.value_bytes().unwrap(), b"03:xyz".as_slice());
+    }
 }
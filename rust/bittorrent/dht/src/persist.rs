@@ -0,0 +1,100 @@
+//! Routing Table Persistence
+//!
+//! The nodes of the routing tables are stored as a Bencode dictionary of the compact node info
+//! strings `nodes` and `nodes6`, which is the same format as the body of a `find_node` response.
+
+use std::io::{Error, ErrorKind};
+use std::net::{SocketAddrV4, SocketAddrV6};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
+use tokio::fs;
+
+use bittorrent_bencode::serde as serde_bencode;
+
+use crate::{
+    message::response::{self, FindNode},
+    NodeContactInfo,
+};
+
+#[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
+struct SavedNodes {
+    nodes: ByteBuf,
+    nodes6: ByteBuf,
+}
+
+/// Loads the nodes, or returns an empty list if the file does not exist.
+pub async fn load_nodes(path: &Path) -> Result<Vec<NodeContactInfo>, Error> {
+    let buffer = match fs::read(path).await {
+        Ok(buffer) => buffer,
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => return Err(error),
+    };
+    decode(&buffer)
+}
+
+pub async fn save_nodes(path: &Path, nodes: &[NodeContactInfo]) -> Result<(), Error> {
+    let buffer = encode(nodes)?;
+    // Write to a temporary file first so that a crash does not leave behind a truncated file.
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, &buffer).await?;
+    fs::rename(&tmp_path, path).await
+}
+
+fn decode(buffer: &[u8]) -> Result<Vec<NodeContactInfo>, Error> {
+    let saved: SavedNodes = serde_bencode::from_bytes(buffer).map_err(Error::other)?;
+    let mut nodes = response::decode_nodes::<SocketAddrV4>(&saved.nodes).map_err(Error::other)?;
+    nodes.extend(response::decode_nodes::<SocketAddrV6>(&saved.nodes6).map_err(Error::other)?);
+    Ok(nodes)
+}
+
+fn encode(nodes: &[NodeContactInfo]) -> Result<Vec<u8>, Error> {
+    let saved = SavedNodes {
+        nodes: ByteBuf::from(FindNode::encode_nodes_v4(
+            nodes.iter().filter(|node| node.endpoint.is_ipv4()),
+        )),
+        nodes6: ByteBuf::from(FindNode::encode_nodes_v6(
+            nodes.iter().filter(|node| node.endpoint.is_ipv6()),
+        )),
+    };
+    Ok(serde_bencode::to_bytes(&saved)
+        .map_err(Error::other)?
+        .to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use crate::NodeId;
+
+    use super::*;
+
+    #[test]
+    fn encode_and_decode() {
+        let nodes = vec![
+            NodeContactInfo::new_mock(1),
+            NodeContactInfo::new_mock(2),
+            (NodeId::max(), SocketAddr::new("::1".parse().unwrap(), 3)).into(),
+        ];
+        let buffer = encode(&nodes).unwrap();
+        assert_eq!(&buffer[..11], b"d5:nodes52:");
+        assert_eq!(decode(&buffer).unwrap(), nodes);
+
+        assert!(decode(b"d5:nodes0:6:nodes60:e").unwrap().is_empty());
+        assert!(decode(b"d5:nodes3:xyz6:nodes60:e").is_err());
+        assert!(decode(b"spam").is_err());
+    }
+
+    #[tokio::test]
+    async fn load_and_save() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("nodes");
+        assert!(load_nodes(&path).await.unwrap().is_empty());
+
+        let nodes = vec![NodeContactInfo::new_mock(1)];
+        save_nodes(&path, &nodes).await.unwrap();
+        assert_eq!(load_nodes(&path).await.unwrap(), nodes);
+    }
+}
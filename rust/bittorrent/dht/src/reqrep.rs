@@ -1,6 +1,6 @@
 use std::collections::BTreeMap;
 use std::io::Error;
-use std::net::SocketAddr;
+use std::net::{SocketAddr, SocketAddrV6};
 use std::sync::Arc;
 
 use bytes::Bytes;
@@ -9,6 +9,7 @@ use futures::{
     sink::{Sink, SinkExt},
     stream::{Stream, StreamExt},
 };
+use tokio::sync::broadcast;
 
 use g1_base::fmt::{DebugExt, Hex};
 use g1_msg::reqrep;
@@ -16,6 +17,8 @@ use g1_msg::reqrep;
 use bittorrent_bencode::{borrow, serde as serde_bencode, FormatDictionary};
 
 use crate::{
+    bloom::BloomFilter,
+    item::Item,
     message::{self, query, response, Message, MessageOwner, Payload},
     NodeContactInfo, NodeId,
 };
@@ -33,7 +36,11 @@ pub(crate) struct Endpoint(
     #[debug(with = Hex)] pub(crate) Arc<[u8]>,
 );
 
-pub(crate) fn spawn<I, O>(incoming: I, outgoing: O) -> (ReqRep, ReqRepGuard)
+/// Spawns the request-response actor.
+///
+/// When `dual_stack` is true, IPv4 endpoints are sent as IPv4-mapped IPv6 endpoints.  Incoming
+/// IPv4-mapped endpoints are always converted back so that we never see them elsewhere.
+pub(crate) fn spawn<I, O>(incoming: I, outgoing: O, dual_stack: bool) -> (ReqRep, ReqRepGuard)
 where
     I: Stream<Item = Result<(SocketAddr, Bytes), Error>> + Send + Unpin + 'static,
     O: Sink<(SocketAddr, Bytes), Error = Error> + Send + Unpin + 'static,
@@ -42,43 +49,118 @@ where
         incoming.map(|raw_message| {
             raw_message.and_then(|(raw_endpoint, raw_payload)| {
                 let payload = MessageOwner::try_from(raw_payload).map_err(Error::other)?;
-                Ok((Endpoint(raw_endpoint, payload.deref().txid.into()), payload))
+                Ok((
+                    Endpoint(to_canonical(raw_endpoint), payload.deref().txid.into()),
+                    payload,
+                ))
             })
         }),
-        outgoing.with(|(Endpoint(raw_endpoint, _), raw_payload)| {
+        outgoing.with(move |(Endpoint(raw_endpoint, _), raw_payload)| {
+            let raw_endpoint = match raw_endpoint {
+                SocketAddr::V4(endpoint) if dual_stack => SocketAddr::V6(SocketAddrV6::new(
+                    endpoint.ip().to_ipv6_mapped(),
+                    endpoint.port(),
+                    0,
+                    0,
+                )),
+                _ => raw_endpoint,
+            };
             future::ok((raw_endpoint, raw_payload))
         }),
     )
 }
 
+fn to_canonical(endpoint: SocketAddr) -> SocketAddr {
+    match endpoint {
+        SocketAddr::V6(v6) => match v6.ip().to_ipv4_mapped() {
+            Some(ip) => SocketAddr::new(ip.into(), v6.port()),
+            None => endpoint,
+        },
+        SocketAddr::V4(_) => endpoint,
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct Client {
     reqrep: ReqRep,
     self_id: NodeId,
+    dual_stack: bool,
     peer_endpoint: SocketAddr,
+    requester_send: RequesterSender,
 }
 
+/// Sends `(peer_endpoint, self_endpoint)`, where `self_endpoint` is our endpoint as the peer sees
+/// it (the BEP 42 `ip` field).
+pub(crate) type RequesterSender = broadcast::Sender<(SocketAddr, SocketAddr)>;
+
 pub(crate) type Nodes = Vec<NodeContactInfo>;
 
 pub(crate) type GetPeers = (Option<Token>, Option<Peers>, Option<Nodes>);
 pub(crate) type Token = Bytes;
 pub(crate) type Peers = Vec<SocketAddr>;
 
+// BEP 33 bloom filters of seeds and downloaders.
+pub(crate) type Scrape = (
+    Option<Token>,
+    Option<(BloomFilter, BloomFilter)>,
+    Option<Nodes>,
+);
+
+// The mutable item is not verified yet, and its salt is empty, which the requester has to supply.
+pub(crate) type GetItem = (Option<Token>, Option<Item>, Option<Nodes>);
+
 impl Client {
-    pub(crate) fn new(reqrep: ReqRep, self_id: NodeId, peer_endpoint: SocketAddr) -> Self {
+    pub(crate) fn new(
+        reqrep: ReqRep,
+        self_id: NodeId,
+        dual_stack: bool,
+        peer_endpoint: SocketAddr,
+        requester_send: RequesterSender,
+    ) -> Self {
         Self {
             reqrep,
             self_id,
+            dual_stack,
             peer_endpoint,
+            requester_send,
         }
     }
 
+    /// Returns the `want` argument of BEP 32, which is omitted unless we are dual-stack.
+    fn want(&self) -> Option<Vec<&'static [u8]>> {
+        self.dual_stack
+            .then(|| vec![query::WANT_NODES_V4, query::WANT_NODES_V6])
+    }
+
+    /// Merges the IPv4 and IPv6 nodes, where the latter are dropped unless we are dual-stack.
+    fn merge_nodes(
+        &self,
+        nodes: Option<Result<Nodes, message::Error>>,
+        nodes6: Option<Result<Nodes, message::Error>>,
+    ) -> Result<Option<Nodes>, Error> {
+        let nodes = nodes.transpose().map_err(Error::other)?;
+        let nodes6 = nodes6
+            .filter(|_| self.dual_stack)
+            .transpose()
+            .map_err(Error::other)?;
+        Ok(match (nodes, nodes6) {
+            (Some(mut nodes), Some(nodes6)) => {
+                nodes.extend(nodes6);
+                Some(nodes)
+            }
+            (nodes, nodes6) => nodes.or(nodes6),
+        })
+    }
+
     async fn transact<T>(&self, query: query::Query<'_>) -> Result<T, Error>
     where
         T: TryFrom<MessageOwner<Bytes>, Error = message::Error>,
     {
         let peer_endpoint = Endpoint(self.peer_endpoint, Arc::from(Message::new_txid()));
-        let request = Message::new(&peer_endpoint.1, Payload::Query(query));
+        let mut request = Message::new(&peer_endpoint.1, Payload::Query(query));
+        if *crate::read_only() {
+            request.read_only = Some(true);
+        }
         tracing::trace!(?request, "->peer");
 
         let request = serde_bencode::to_bytes(&request)
@@ -94,10 +176,26 @@ impl Client {
         if let Payload::Error(error) = &response.payload {
             return Err(Error::other(format!("peer returns error: {:?}", error)));
         }
+        if let Payload::Response(response) = &response.payload {
+            self.report_requester(response);
+        }
 
         response_owner.try_into().map_err(Error::other)
     }
 
+    fn report_requester(&self, response: &response::Response) {
+        match response.decode_requester() {
+            Some(Ok(self_endpoint)) => {
+                // It is fine that no one subscribes to it.
+                let _ = self
+                    .requester_send
+                    .send((self.peer_endpoint, self_endpoint));
+            }
+            Some(Err(error)) => tracing::debug!(%error, "invalid requester endpoint"),
+            None => {}
+        }
+    }
+
     pub(crate) async fn ping(&self) -> Result<(), Error> {
         let response_owner: response::PingOwner<Bytes> = self
             .transact(query::Query::Ping(query::Ping::new(self.self_id.as_ref())))
@@ -108,36 +206,47 @@ impl Client {
     }
 
     pub(crate) async fn find_node(&self, target: &[u8]) -> Result<Nodes, Error> {
-        let response_owner: response::FindNodeOwner<Bytes> = self
-            .transact(query::Query::FindNode(query::FindNode::new(
-                self.self_id.as_ref(),
-                target,
-            )))
-            .await?;
+        let mut find_node = query::FindNode::new(self.self_id.as_ref(), target);
+        find_node.want = self.want();
+        let response_owner: response::FindNodeOwner<Bytes> =
+            self.transact(query::Query::FindNode(find_node)).await?;
         let response = response_owner.deref();
         log_body_extra(&response.extra);
-        response.decode_nodes_v4().map_err(Error::other)
+        Ok(self
+            .merge_nodes(response.decode_nodes_v4(), response.decode_nodes_v6())?
+            .unwrap_or_default())
     }
 
     pub(crate) async fn get_peers(&self, info_hash: &[u8]) -> Result<GetPeers, Error> {
-        let response_owner: response::GetPeersOwner<Bytes> = self
-            .transact(query::Query::GetPeers(query::GetPeers::new(
-                self.self_id.as_ref(),
-                info_hash,
-            )))
-            .await?;
+        let mut get_peers = query::GetPeers::new(self.self_id.as_ref(), info_hash);
+        get_peers.want = self.want();
+        let response_owner: response::GetPeersOwner<Bytes> =
+            self.transact(query::Query::GetPeers(get_peers)).await?;
         let response = response_owner.deref();
         log_body_extra(&response.extra);
         Ok((
             response.token.map(Token::copy_from_slice),
-            response
-                .decode_peers_v4()
-                .transpose()
-                .map_err(Error::other)?,
-            response
-                .decode_nodes_v4()
-                .transpose()
-                .map_err(Error::other)?,
+            response.decode_peers().transpose().map_err(Error::other)?,
+            self.merge_nodes(response.decode_nodes_v4(), response.decode_nodes_v6())?,
+        ))
+    }
+
+    pub(crate) async fn scrape(&self, info_hash: &[u8]) -> Result<Scrape, Error> {
+        let mut get_peers = query::GetPeers::new(self.self_id.as_ref(), info_hash);
+        get_peers.want = self.want();
+        get_peers.scrape = Some(true);
+        let response_owner: response::GetPeersOwner<Bytes> =
+            self.transact(query::Query::GetPeers(get_peers)).await?;
+        let response = response_owner.deref();
+        log_body_extra(&response.extra);
+        let filters = match (response.seeds, response.peers) {
+            (Some(seeds), Some(peers)) => Some((to_bloom_filter(seeds)?, to_bloom_filter(peers)?)),
+            _ => None,
+        };
+        Ok((
+            response.token.map(Token::copy_from_slice),
+            filters,
+            self.merge_nodes(response.decode_nodes_v4(), response.decode_nodes_v6())?,
         ))
     }
 
@@ -147,20 +256,74 @@ impl Client {
         port: u16,
         implied_port: Option<bool>,
         token: &[u8],
+        seed: bool,
     ) -> Result<(), Error> {
+        let mut announce_peer =
+            query::AnnouncePeer::new(self.self_id.as_ref(), info_hash, port, implied_port, token);
+        announce_peer.seed = seed.then_some(true);
         let response_owner: response::AnnouncePeerOwner<Bytes> = self
-            .transact(query::Query::AnnouncePeer(query::AnnouncePeer::new(
-                self.self_id.as_ref(),
-                info_hash,
-                port,
-                implied_port,
-                token,
-            )))
+            .transact(query::Query::AnnouncePeer(announce_peer))
             .await?;
         let response = response_owner.deref();
         log_body_extra(&response.extra);
         Ok(())
     }
+
+    pub(crate) async fn get(&self, target: &[u8], seq: Option<i64>) -> Result<GetItem, Error> {
+        let mut get = query::Get::new(self.self_id.as_ref(), target, seq);
+        get.want = self.want();
+        let response_owner: response::GetOwner<Bytes> =
+            self.transact(query::Query::Get(get)).await?;
+        let response = response_owner.deref();
+        log_body_extra(&response.extra);
+        Ok((
+            response.token.map(Token::copy_from_slice),
+            response
+                .value_bytes()
+                .map(|value| {
+                    Item::from_parts(
+                        value,
+                        response.key,
+                        response.signature,
+                        response.seq,
+                        Bytes::new(),
+                    )
+                })
+                .transpose()
+                .map_err(Error::other)?,
+            self.merge_nodes(response.decode_nodes_v4(), response.decode_nodes_v6())?,
+        ))
+    }
+
+    pub(crate) async fn put(
+        &self,
+        token: &[u8],
+        item: &Item,
+        cas: Option<i64>,
+    ) -> Result<(), Error> {
+        let mut put = query::Put::new(
+            self.self_id.as_ref(),
+            token,
+            borrow::Value::try_from(item.value.as_ref()).map_err(Error::other)?,
+        );
+        if let Some(mutable) = &item.mutable {
+            put.key = Some(mutable.public_key.as_slice());
+            put.signature = Some(mutable.signature.as_slice());
+            put.seq = Some(mutable.seq);
+            put.cas = cas;
+            put.salt = (!mutable.salt.is_empty()).then_some(mutable.salt.as_ref());
+        }
+        let response_owner: response::PutOwner<Bytes> =
+            self.transact(query::Query::Put(put)).await?;
+        let response = response_owner.deref();
+        log_body_extra(&response.extra);
+        Ok(())
+    }
+}
+
+fn to_bloom_filter(bits: &[u8]) -> Result<BloomFilter, Error> {
+    BloomFilter::try_from(bits)
+        .map_err(|bits| Error::other(format!("invalid bloom filter size: {}", bits.len())))
 }
 
 fn log_body_extra(extra: &BTreeMap<&[u8], borrow::Value<'_>>) {
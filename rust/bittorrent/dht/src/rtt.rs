@@ -0,0 +1,94 @@
+//! Round-Trip Time Estimator
+//!
+//! It keeps a sliding window of the most recent RTT samples, from which the adaptive lookup mode
+//! derives its per-node timeout.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub(crate) struct RttEstimator {
+    samples: VecDeque<Duration>,
+    capacity: usize,
+}
+
+impl RttEstimator {
+    // Below this, quantiles are too noisy to be useful.
+    const MIN_SAMPLES: usize = 16;
+
+    pub(crate) fn new() -> Self {
+        Self::with_capacity(*crate::rtt_num_samples())
+    }
+
+    fn with_capacity(capacity: usize) -> Self {
+        assert!(capacity > 0);
+        Self {
+            samples: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub(crate) fn record(&mut self, rtt: Duration) {
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(rtt);
+    }
+
+    /// Returns the `q`-quantile of the samples, or `None` if there are not enough samples.
+    pub(crate) fn quantile(&self, q: f64) -> Option<Duration> {
+        assert!((0.0..=1.0).contains(&q));
+        let n = self.samples.len();
+        if n < Self::MIN_SAMPLES.min(self.capacity) {
+            return None;
+        }
+        let mut samples: Vec<_> = self.samples.iter().copied().collect();
+        // Nearest-rank method.
+        let rank = ((q * n as f64).ceil() as usize).clamp(1, n);
+        Some(*samples.select_nth_unstable(rank - 1).1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    #[test]
+    fn quantile() {
+        let mut rtt = RttEstimator::with_capacity(100);
+        for i in 1..RttEstimator::MIN_SAMPLES {
+            rtt.record(ms(i.try_into().unwrap()));
+        }
+        assert_eq!(rtt.quantile(0.5), None);
+
+        let mut rtt = RttEstimator::with_capacity(100);
+        for i in (1..=100).rev() {
+            rtt.record(ms(i));
+        }
+        assert_eq!(rtt.quantile(0.0), Some(ms(1)));
+        assert_eq!(rtt.quantile(0.5), Some(ms(50)));
+        assert_eq!(rtt.quantile(0.9), Some(ms(90)));
+        assert_eq!(rtt.quantile(1.0), Some(ms(100)));
+
+        // Old samples are evicted.
+        for _ in 0..100 {
+            rtt.record(ms(7));
+        }
+        assert_eq!(rtt.quantile(1.0), Some(ms(7)));
+    }
+
+    #[test]
+    fn small_capacity() {
+        let mut rtt = RttEstimator::with_capacity(2);
+        rtt.record(ms(1));
+        assert_eq!(rtt.quantile(0.5), None);
+        rtt.record(ms(2));
+        rtt.record(ms(3));
+        assert_eq!(rtt.quantile(0.0), Some(ms(2)));
+        assert_eq!(rtt.quantile(1.0), Some(ms(3)));
+    }
+}
@@ -0,0 +1,181 @@
+//! BEP 42 DHT Security Extension
+//!
+//! BEP 42 restricts the node id to a function of the node's IP address, which makes it costly for
+//! an attacker to choose node ids around a target (a sybil attack).
+
+use std::net::IpAddr;
+
+use crate::NodeId;
+
+const IPV4_MASK: [u8; 4] = [0x03, 0x0f, 0x3f, 0xff];
+const IPV6_MASK: [u8; 8] = [0x01, 0x03, 0x07, 0x0f, 0x1f, 0x3f, 0x7f, 0xff];
+
+/// Checks whether the node id conforms to BEP 42.
+///
+/// BEP 42 exempts local addresses, whose nodes may choose any id.
+pub(crate) fn is_conformant(id: &NodeId, ip: IpAddr) -> bool {
+    let ip = ip.to_canonical();
+    if is_exempt(ip) {
+        return true;
+    }
+    let id = id.as_array();
+    let crc = compute_crc(ip, id[19]);
+    id[0] == (crc >> 24) as u8
+        && id[1] == (crc >> 16) as u8
+        && id[2] & 0xf8 == (crc >> 8) as u8 & 0xf8
+}
+
+/// Returns a node id that conforms to BEP 42 for the IP address.
+///
+/// It keeps the random bits of `id`, including `id[19]`, from which the CRC is seeded, and
+/// overwrites the top 21 bits.  It returns `id` unchanged for an exempt address.
+pub(crate) fn make_conformant(id: &NodeId, ip: IpAddr) -> NodeId {
+    let ip = ip.to_canonical();
+    let mut id = *id.as_array();
+    if is_exempt(ip) {
+        return NodeId::new(id);
+    }
+    let crc = compute_crc(ip, id[19]);
+    id[0] = (crc >> 24) as u8;
+    id[1] = (crc >> 16) as u8;
+    id[2] = ((crc >> 8) as u8 & 0xf8) | (id[2] & 0x07);
+    NodeId::new(id)
+}
+
+fn is_exempt(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_private() || ip.is_link_local() || ip.is_loopback(),
+        IpAddr::V6(ip) => {
+            let segment = ip.segments()[0];
+            ip.is_loopback()
+                || (segment & 0xfe00) == 0xfc00 // Unique local (fc00::/7).
+                || (segment & 0xffc0) == 0xfe80 // Link local (fe80::/10).
+        }
+    }
+}
+
+fn compute_crc(ip: IpAddr, rand: u8) -> u32 {
+    let r = rand & 0x7;
+    match ip {
+        IpAddr::V4(ip) => {
+            let mut octets = ip.octets();
+            for (octet, mask) in octets.iter_mut().zip(IPV4_MASK) {
+                *octet &= mask;
+            }
+            octets[0] |= r << 5;
+            crc32c(&octets)
+        }
+        IpAddr::V6(ip) => {
+            let mut octets: [u8; 8] = ip.octets()[..8].try_into().unwrap();
+            for (octet, mask) in octets.iter_mut().zip(IPV6_MASK) {
+                *octet &= mask;
+            }
+            octets[0] |= r << 5;
+            crc32c(&octets)
+        }
+    }
+}
+
+fn crc32c(data: &[u8]) -> u32 {
+    const POLYNOMIAL: u32 = 0x82f6_3b78; // Reversed Castagnoli polynomial.
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (POLYNOMIAL & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use super::*;
+
+    #[test]
+    fn test_crc32c() {
+        assert_eq!(crc32c(b""), 0);
+        assert_eq!(crc32c(b"123456789"), 0xe3069283);
+    }
+
+    #[test]
+    fn test_is_conformant() {
+        // Test vectors of BEP 42.
+        for (ip, id) in [
+            (
+                "124.31.75.21",
+                hex!("5fbfbff10c5d6a4ec8a88e4c6ab4c28b95eee401"),
+            ),
+            (
+                "21.75.31.124",
+                hex!("5a3ce9c14e7a08645677bbd1cfe7d8f956d53256"),
+            ),
+            (
+                "65.23.51.170",
+                hex!("a5d43220bc8f112a3d426c84764f8c2a1150e616"),
+            ),
+            (
+                "84.124.73.14",
+                hex!("1b0321dd1bb1fe518101ceef99462b947a01ff41"),
+            ),
+            (
+                "43.213.53.83",
+                hex!("e56f6cbf5b7c4be0237986d5243b87aa6d51305a"),
+            ),
+        ] {
+            let ip: IpAddr = ip.parse().unwrap();
+            assert_eq!(is_conformant(&NodeId::new(id), ip), true);
+
+            let mut mismatch = id;
+            mismatch[0] ^= 1;
+            assert_eq!(is_conformant(&NodeId::new(mismatch), ip), false);
+
+            // Only the top 21 bits are checked.
+            let mut id = id;
+            id[2] ^= 0x07;
+            id[10] ^= 0xff;
+            assert_eq!(is_conformant(&NodeId::new(id), ip), true);
+        }
+
+        let id = NodeId::min();
+        assert_eq!(is_conformant(&id, "1.2.3.4".parse().unwrap()), false);
+        assert_eq!(is_conformant(&id, "2001:db8::1".parse().unwrap()), false);
+        for ip in [
+            "10.0.0.1",
+            "192.168.1.1",
+            "127.0.0.1",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+        ] {
+            assert_eq!(is_conformant(&id, ip.parse().unwrap()), true);
+        }
+    }
+
+    #[test]
+    fn test_make_conformant() {
+        for ip in ["124.31.75.21", "21.75.31.124", "2001:db8::1"] {
+            let ip: IpAddr = ip.parse().unwrap();
+            for _ in 0..16 {
+                let id = NodeId::new(rand::random());
+                let conformant = make_conformant(&id, ip);
+                assert_eq!(is_conformant(&conformant, ip), true);
+                assert_eq!(conformant.as_array()[3..], id.as_array()[3..]);
+                assert_eq!(conformant.as_array()[2] & 0x07, id.as_array()[2] & 0x07);
+            }
+        }
+
+        // Test vector of BEP 42.
+        let mut id = hex!("5fbfbff10c5d6a4ec8a88e4c6ab4c28b95eee401");
+        id[..3].fill(0);
+        assert_eq!(
+            make_conformant(&NodeId::new(id), "124.31.75.21".parse().unwrap()).as_array()[..3],
+            hex!("5fbfb8"),
+        );
+
+        let id = NodeId::min();
+        assert_eq!(make_conformant(&id, "10.0.0.1".parse().unwrap()), id);
+    }
+}
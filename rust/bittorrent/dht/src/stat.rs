@@ -0,0 +1,48 @@
+//! Abuse Counters
+//!
+//! We count the queries that the node agent rejects or drops, so that operators can observe
+//! attacks.  The counters are session-level, i.e., shared by all DHT instances in this process.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static INVALID_TOKENS: AtomicU64 = AtomicU64::new(0);
+static RATE_LIMITED_QUERIES: AtomicU64 = AtomicU64::new(0);
+static REJECTED_PEERS: AtomicU64 = AtomicU64::new(0);
+static NONCONFORMANT_NODE_IDS: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Abuse {
+    /// `announce_peer` and `put` queries with an invalid or expired token.
+    pub invalid_tokens: u64,
+    /// Queries that exceed the per-IP rate limit.
+    pub rate_limited_queries: u64,
+    /// Announced peers that are not stored because the swarm is full.
+    pub rejected_peers: u64,
+    /// Nodes that are not added to the routing table because their ids violate BEP 42.
+    pub nonconformant_node_ids: u64,
+}
+
+pub fn abuse() -> Abuse {
+    Abuse {
+        invalid_tokens: INVALID_TOKENS.load(Ordering::SeqCst),
+        rate_limited_queries: RATE_LIMITED_QUERIES.load(Ordering::SeqCst),
+        rejected_peers: REJECTED_PEERS.load(Ordering::SeqCst),
+        nonconformant_node_ids: NONCONFORMANT_NODE_IDS.load(Ordering::SeqCst),
+    }
+}
+
+pub(crate) fn add_invalid_token() {
+    INVALID_TOKENS.fetch_add(1, Ordering::SeqCst);
+}
+
+pub(crate) fn add_rate_limited_query() {
+    RATE_LIMITED_QUERIES.fetch_add(1, Ordering::SeqCst);
+}
+
+pub(crate) fn add_rejected_peer() {
+    REJECTED_PEERS.fetch_add(1, Ordering::SeqCst);
+}
+
+pub(crate) fn add_nonconformant_node_id() {
+    NONCONFORMANT_NODE_IDS.fetch_add(1, Ordering::SeqCst);
+}
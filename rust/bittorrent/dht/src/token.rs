@@ -1,17 +1,22 @@
+use std::collections::BTreeMap;
 use std::net::{IpAddr, SocketAddr};
 use std::ops::RangeInclusive;
+use std::sync::Mutex;
 use std::time::{Duration, Instant};
 
 use sha1::{Digest, Sha1};
 
+use g1_base::sync::MutexExt;
+
 //
 // Implementer's Notes: BEP 5 recommends generating tokens by computing the SHA-1 hash of the IP
 // address concatenated with a secret that changes every five minutes, and accepting tokens that
 // are up to ten minutes old.
 //
-// For now, we deviate from the recommendation in BEP 5 for the sake of simplicity by generating a
-// token as the SHA-1 hash of the IP address, the port, "age", and the secret.  Age is defined as
-// the current time divided by the token generation period.
+// We generate a token as the SHA-1 hash of the IP address, the port, "age", and the secret of that
+// age.  Age is defined as the current time divided by the token generation period.  Unless a fixed
+// secret is configured, each age has its own random secret, which is discarded once the age falls
+// out of the valid range, so that a leaked secret does not let anyone forge future tokens.
 //
 
 #[derive(Debug)]
@@ -19,13 +24,16 @@ pub(crate) struct TokenSource {
     start: Instant,
     period: u32,
     valid_since: Age,
-    secret: [u8; 8],
+    fixed_secret: Option<Secret>,
+    secrets: Mutex<BTreeMap<Age, Secret>>,
 }
 
 pub(crate) type Token = [u8; 20];
 
 type Age = u64;
 
+type Secret = [u8; 8];
+
 impl TokenSource {
     pub(crate) fn new() -> Self {
         Self::with_state(
@@ -36,13 +44,19 @@ impl TokenSource {
         )
     }
 
-    fn with_state(start: Instant, period: Duration, valid_since: Duration, secret: u64) -> Self {
+    fn with_state(
+        start: Instant,
+        period: Duration,
+        valid_since: Duration,
+        fixed_secret: Option<u64>,
+    ) -> Self {
         let period = u32::try_from(period.as_secs()).unwrap();
         Self {
             start,
             period,
             valid_since: (valid_since / period).as_secs(),
-            secret: secret.to_be_bytes(),
+            fixed_secret: fixed_secret.map(u64::to_be_bytes),
+            secrets: Mutex::new(BTreeMap::new()),
         }
     }
 
@@ -61,15 +75,19 @@ impl TokenSource {
     }
 
     fn generate_at(&self, endpoint: SocketAddr, age: Age) -> Token {
-        let mut hasher = Sha1::new();
-        match endpoint.ip() {
-            IpAddr::V4(address) => hasher.update(address.octets()),
-            IpAddr::V6(address) => hasher.update(address.octets()),
-        }
-        hasher.update(endpoint.port().to_be_bytes());
-        hasher.update(age.to_be_bytes());
-        hasher.update(self.secret);
-        hasher.finalize().into()
+        let secret = self.fixed_secret.unwrap_or_else(|| {
+            let mut secrets = self.secrets.must_lock();
+            let valid_from = age.saturating_sub(self.valid_since);
+            secrets.retain(|secret_age, _| *secret_age >= valid_from);
+            *secrets.entry(age).or_insert_with(rand::random)
+        });
+        hash(endpoint, age, secret)
+    }
+
+    /// Returns the secret of the age, or `None` if we have not generated any token in that age.
+    fn get_secret(&self, age: Age) -> Option<Secret> {
+        self.fixed_secret
+            .or_else(|| self.secrets.must_lock().get(&age).copied())
     }
 
     pub(crate) fn validate(&self, endpoint: SocketAddr, token: &[u8]) -> bool {
@@ -82,8 +100,23 @@ impl TokenSource {
         token: &[u8],
         mut valid_range: RangeInclusive<u64>,
     ) -> bool {
-        valid_range.any(|age| self.generate_at(endpoint, age) == token)
+        valid_range.any(|age| {
+            self.get_secret(age)
+                .is_some_and(|secret| hash(endpoint, age, secret) == token)
+        })
+    }
+}
+
+fn hash(endpoint: SocketAddr, age: Age, secret: Secret) -> Token {
+    let mut hasher = Sha1::new();
+    match endpoint.ip() {
+        IpAddr::V4(address) => hasher.update(address.octets()),
+        IpAddr::V6(address) => hasher.update(address.octets()),
     }
+    hasher.update(endpoint.port().to_be_bytes());
+    hasher.update(age.to_be_bytes());
+    hasher.update(secret);
+    hasher.finalize().into()
 }
 
 #[cfg(test)]
@@ -107,7 +140,7 @@ mod tests {
     #[test]
     fn age() {
         let t0 = Instant::now();
-        let src = TokenSource::with_state(t0, S3, S0, 0);
+        let src = TokenSource::with_state(t0, S3, S0, Some(0));
         assert_eq!(src.age(t0 - S4), 0);
         assert_eq!(src.age(t0 - S3), 0);
         assert_eq!(src.age(t0 - S2), 0);
@@ -127,7 +160,7 @@ mod tests {
         let t1 = t0 + S1;
         let t2 = t0 + S2;
         let t3 = t0 + S3;
-        let make_src = |valid_since| TokenSource::with_state(t0, S1, valid_since, 0);
+        let make_src = |valid_since| TokenSource::with_state(t0, S1, valid_since, Some(0));
 
         let src = make_src(S0);
         assert_eq!(src.valid_range(t0), 0..=0);
@@ -151,7 +184,7 @@ mod tests {
     #[test]
     fn generate() {
         let endpoint = "127.0.0.1:8000".parse().unwrap();
-        let src = TokenSource::with_state(Instant::now(), S1, S0, 0x0102030405060708);
+        let src = TokenSource::with_state(Instant::now(), S1, S0, Some(0x0102030405060708));
         assert_eq!(
             src.generate_at(endpoint, 0),
             digest(&hex!("7f000001 1f40 0000000000000000 0102030405060708")),
@@ -173,7 +206,7 @@ mod tests {
         let t2 = t0 + S2;
         let t3 = t0 + S3;
         let make_src =
-            |valid_since| TokenSource::with_state(t0, S1, valid_since, 0x0102030405060708);
+            |valid_since| TokenSource::with_state(t0, S1, valid_since, Some(0x0102030405060708));
 
         let endpoint = "127.0.0.1:8000".parse().unwrap();
         let tokens = [
@@ -239,4 +272,36 @@ mod tests {
             assert_eq!(validate(&tokens[3], t3), true);
         }
     }
+
+    #[test]
+    fn rotate_secret() {
+        let t0 = Instant::now();
+        let src = TokenSource::with_state(t0, S1, S1, None);
+        let endpoint = "127.0.0.1:8000".parse().unwrap();
+        let validate = |token, age| src.validate_in(endpoint, token, src.valid_range(t0 + age));
+
+        // We have not generated any token yet.
+        assert_eq!(validate(&[0u8; 20], S0), false);
+
+        let t0_token = src.generate_at(endpoint, 0);
+        assert_eq!(src.generate_at(endpoint, 0), t0_token);
+        assert_eq!(validate(&t0_token, S0), true);
+        assert_eq!(src.secrets.must_lock().len(), 1);
+
+        let t1_token = src.generate_at(endpoint, 1);
+        assert_ne!(t1_token, t0_token);
+        assert_eq!(validate(&t0_token, S1), true);
+        assert_eq!(validate(&t1_token, S1), true);
+        assert_eq!(src.secrets.must_lock().len(), 2);
+
+        // The secret of age 0 is discarded.
+        let t2_token = src.generate_at(endpoint, 2);
+        assert_eq!(validate(&t0_token, S0), false);
+        assert_eq!(validate(&t1_token, S2), true);
+        assert_eq!(validate(&t2_token, S2), true);
+        assert_eq!(
+            src.secrets.must_lock().keys().copied().collect::<Vec<_>>(),
+            [1, 2],
+        );
+    }
 }
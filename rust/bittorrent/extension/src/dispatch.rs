@@ -0,0 +1,199 @@
+//! Extension Message Dispatcher
+//!
+//! It decodes incoming extension messages and routes them to the registered handlers.  It also
+//! keeps the `ExtensionIdMap` up to date with the peer's handshake, which the embedder needs for
+//! encoding outgoing messages.
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+
+use bytes::Bytes;
+use serde::de::Error as _;
+
+use bittorrent_bencode::serde as serde_bencode;
+
+use crate::{
+    DontHave, DontHaveOwner, ExtensionIdMap, Handshake, HandshakeOwner, Holepunch, HolepunchOwner,
+    MessageOwner, Metadata, MetadataOwner, PeerExchange, PeerExchangeOwner,
+};
+
+pub type HandlerFuture = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+type Handler<T> = Box<dyn FnMut(T) -> HandlerFuture + Send + 'static>;
+
+#[derive(Default)]
+pub struct Dispatcher {
+    id_map: ExtensionIdMap,
+    handshake: Option<Handler<HandshakeOwner<Bytes>>>,
+    metadata: Option<Handler<MetadataOwner<Bytes>>>,
+    peer_exchange: Option<Handler<PeerExchangeOwner<Bytes>>>,
+    holepunch: Option<Handler<HolepunchOwner<Bytes>>>,
+    donthave: Option<Handler<DontHaveOwner<Bytes>>>,
+    custom: Option<Handler<MessageOwner<Bytes>>>,
+}
+
+impl fmt::Debug for Dispatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Dispatcher")
+            .field("id_map", &self.id_map)
+            .field("handshake", &self.handshake.is_some())
+            .field("metadata", &self.metadata.is_some())
+            .field("peer_exchange", &self.peer_exchange.is_some())
+            .field("holepunch", &self.holepunch.is_some())
+            .field("donthave", &self.donthave.is_some())
+            .field("custom", &self.custom.is_some())
+            .finish()
+    }
+}
+
+macro_rules! define_on {
+    ($on:ident, $field:ident, $owner:ident $(,)?) => {
+        pub fn $on<F, Fut>(&mut self, mut handler: F) -> &mut Self
+        where
+            F: FnMut($owner<Bytes>) -> Fut + Send + 'static,
+            Fut: Future<Output = ()> + Send + 'static,
+        {
+            self.$field = Some(Box::new(move |message| Box::pin(handler(message))));
+            self
+        }
+    };
+}
+
+impl Dispatcher {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    define_on!(on_handshake, handshake, HandshakeOwner);
+    define_on!(on_metadata, metadata, MetadataOwner);
+    define_on!(on_peer_exchange, peer_exchange, PeerExchangeOwner);
+    define_on!(on_holepunch, holepunch, HolepunchOwner);
+    define_on!(on_donthave, donthave, DontHaveOwner);
+    // Messages of all custom extensions go to this handler.
+    define_on!(on_custom, custom, MessageOwner);
+
+    /// Returns the map of the peer's extension ids, which is updated by the peer's handshake.
+    pub fn id_map(&self) -> &ExtensionIdMap {
+        &self.id_map
+    }
+
+    /// Decodes an extension message and calls its handler.
+    ///
+    /// Messages that do not have a registered handler are dropped.
+    pub async fn dispatch(&mut self, id: u8, buffer: Bytes) -> Result<(), serde_bencode::Error> {
+        crate::get(id).map_err(serde_bencode::Error::custom)?;
+        match id {
+            Handshake::ID => {
+                let handshake = HandshakeOwner::try_from(buffer)?;
+                self.id_map.update(handshake.deref());
+                call(&mut self.handshake, id, handshake).await;
+            }
+            Metadata::ID => {
+                let metadata = MetadataOwner::try_from(buffer)?;
+                call(&mut self.metadata, id, metadata).await;
+            }
+            PeerExchange::ID => {
+                let peer_exchange = PeerExchangeOwner::try_from(buffer)?;
+                call(&mut self.peer_exchange, id, peer_exchange).await;
+            }
+            Holepunch::ID => {
+                let holepunch = HolepunchOwner::try_from(buffer)?;
+                call(&mut self.holepunch, id, holepunch).await;
+            }
+            DontHave::ID => {
+                let donthave = DontHaveOwner::try_from(buffer)?;
+                call(&mut self.donthave, id, donthave).await;
+            }
+            _ => {
+                let custom = crate::decode(id, buffer)?;
+                call(&mut self.custom, id, custom).await;
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn call<T>(handler: &mut Option<Handler<T>>, id: u8, message: T) {
+    match handler {
+        Some(handler) => handler(message).await,
+        None => tracing::debug!(id, "drop extension message without handler"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::{Arc, Mutex};
+
+    use bytes::BytesMut;
+
+    use crate::{Enabled, Request};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn dispatch() {
+        let handshakes = Arc::new(Mutex::new(Vec::new()));
+        let metadata = Arc::new(Mutex::new(Vec::new()));
+
+        let mut dispatcher = Dispatcher::new();
+        {
+            let handshakes = handshakes.clone();
+            let metadata = metadata.clone();
+            dispatcher
+                .on_handshake(move |handshake| {
+                    let handshakes = handshakes.clone();
+                    async move {
+                        let size = handshake.deref().metadata_size;
+                        handshakes.lock().unwrap().push(size);
+                    }
+                })
+                .on_metadata(move |message| {
+                    let metadata = metadata.clone();
+                    async move {
+                        if let Metadata::Request(request) = message.deref() {
+                            metadata.lock().unwrap().push(request.piece);
+                        }
+                    }
+                });
+        }
+        assert_eq!(
+            dispatcher.id_map().peer_extensions(),
+            Enabled::new(false, false, false, false)
+        );
+
+        let mut buffer = BytesMut::new();
+        Handshake {
+            extension_ids: BTreeMap::from([("ut_metadata", 3)]),
+            metadata_size: Some(42),
+            piece_layers_size: None,
+            upload_only: None,
+            extra: BTreeMap::new(),
+        }
+        .encode_into(&mut buffer);
+        dispatcher
+            .dispatch(Handshake::ID, buffer.freeze())
+            .await
+            .unwrap();
+        assert_eq!(*handshakes.lock().unwrap(), [Some(42)]);
+        assert_eq!(
+            dispatcher.id_map().peer_extensions(),
+            Enabled::new(true, false, false, false)
+        );
+
+        let mut buffer = BytesMut::new();
+        Metadata::Request(Request::new(1)).encode_into(&mut buffer);
+        dispatcher
+            .dispatch(Metadata::ID, buffer.freeze())
+            .await
+            .unwrap();
+        assert_eq!(*metadata.lock().unwrap(), [1]);
+
+        assert!(dispatcher.dispatch(99, Bytes::new()).await.is_err());
+        assert!(dispatcher
+            .dispatch(Metadata::ID, Bytes::from_static(b"x"))
+            .await
+            .is_err());
+    }
+}
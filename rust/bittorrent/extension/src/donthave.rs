@@ -0,0 +1,83 @@
+use std::marker::PhantomData;
+
+use bytes::{Buf, BufMut};
+use serde::de::Error as _;
+use snafu::prelude::*;
+
+use bittorrent_bencode::serde as serde_bencode;
+
+use crate::{Error, ExpectDontHaveSizeSnafu};
+
+g1_param::define!(pub(crate) enable: bool = false); // BEP 54
+
+//
+// Implementer's Notes: Like BEP 55 messages, BEP 54 messages are not bencoded; the payload is
+// simply the big-endian piece index that the sender no longer has.
+//
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct DontHave<'a> {
+    pub piece: u32,
+    // A donthave message does not borrow from the buffer, but `define_owner!` requires the
+    // borrower type to have a lifetime parameter.
+    _buffer: PhantomData<&'a [u8]>,
+}
+
+const SIZE: usize = 4;
+
+impl DontHave<'_> {
+    // TODO: How can we make this id value match the global `EXTENSIONS` array index?
+    pub const ID: u8 = 4;
+
+    pub fn new(piece: u32) -> Self {
+        Self {
+            piece,
+            _buffer: PhantomData,
+        }
+    }
+
+    pub fn decode(buffer: &[u8]) -> Result<Self, serde_bencode::Error> {
+        Self::decode_impl(buffer).map_err(serde_bencode::Error::custom)
+    }
+
+    fn decode_impl(mut buffer: &[u8]) -> Result<Self, Error> {
+        let size = buffer.len();
+        ensure!(size == SIZE, ExpectDontHaveSizeSnafu { size });
+        Ok(Self::new(buffer.get_u32()))
+    }
+
+    pub fn encode(&self, buffer: &mut impl BufMut) {
+        buffer.put_u32(self.piece);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+    use hex_literal::hex;
+
+    use super::*;
+
+    #[test]
+    fn codec() {
+        for (piece, expect) in [
+            (0, hex!("00000000")),
+            (1, hex!("00000001")),
+            (0x01020304, hex!("01020304")),
+            (u32::MAX, hex!("ffffffff")),
+        ] {
+            let donthave = DontHave::new(piece);
+            let mut buffer = BytesMut::new();
+            donthave.encode(&mut buffer);
+            assert_eq!(&*buffer, &expect);
+            assert_eq!(DontHave::decode(&expect), Ok(donthave));
+        }
+    }
+
+    #[test]
+    fn decode_error() {
+        for buffer in [hex!("").as_slice(), &hex!("000000"), &hex!("0000000000")] {
+            assert!(DontHave::decode(buffer).is_err(), "{buffer:?}");
+        }
+    }
+}
@@ -0,0 +1,144 @@
+//! Direct Encoder of Extension Messages
+//!
+//! An extension message is a dictionary of a few known keys, plus the `extra` keys that we keep
+//! from decoding.  Instead of going through `serde` (which builds an `own::Value` tree and copies
+//! every byte string into it), we merge the two and write them directly into the buffer.
+
+use std::collections::BTreeMap;
+
+use bytes::BufMut;
+
+use bittorrent_bencode::borrow;
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Field<'a> {
+    Integer(i64),
+    ByteString(&'a [u8]),
+    ExtensionIds(&'a BTreeMap<&'a str, u8>),
+}
+
+/// Encodes a dictionary of the known fields and the extra entries.
+///
+/// `fields` must be sorted by key.  A field overrides the extra entry of the same key unless the
+/// field is `None`.
+pub(crate) fn encode_dict(
+    fields: &[(&[u8], Option<Field>)],
+    extra: &BTreeMap<&[u8], borrow::Value>,
+    buffer: &mut impl BufMut,
+) {
+    debug_assert!(fields.is_sorted_by_key(|(key, _)| *key));
+    let mut fields = fields
+        .iter()
+        .filter_map(|(key, field)| Some((*key, (*field)?)))
+        .peekable();
+    let mut extra = extra.iter().peekable();
+    buffer.put_u8(b'd');
+    loop {
+        let field_key = fields.peek().map(|(key, _)| *key);
+        let extra_key = extra.peek().map(|(key, _)| **key);
+        let take_field = match (field_key, extra_key) {
+            (None, None) => break,
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (Some(field_key), Some(extra_key)) => {
+                if field_key == extra_key {
+                    extra.next();
+                }
+                field_key <= extra_key
+            }
+        };
+        if take_field {
+            let (key, field) = fields.next().unwrap();
+            encode_byte_string(key, buffer);
+            field.encode(buffer);
+        } else {
+            let (key, value) = extra.next().unwrap();
+            encode_byte_string(key, buffer);
+            value.encode(buffer);
+        }
+    }
+    buffer.put_u8(b'e');
+}
+
+/// Returns a byte string field, or `None` if it is empty.
+///
+/// We treat "not present" the same as "present but empty" for some byte string fields.
+pub(crate) fn non_empty(bytes: &[u8]) -> Option<Field<'_>> {
+    (!bytes.is_empty()).then_some(Field::ByteString(bytes))
+}
+
+impl Field<'_> {
+    fn encode(&self, buffer: &mut impl BufMut) {
+        match self {
+            Self::Integer(int) => borrow::Value::Integer(*int).encode(buffer),
+            Self::ByteString(bytes) => encode_byte_string(bytes, buffer),
+            Self::ExtensionIds(extension_ids) => {
+                buffer.put_u8(b'd');
+                for (name, id) in extension_ids.iter() {
+                    encode_byte_string(name.as_bytes(), buffer);
+                    borrow::Value::Integer((*id).into()).encode(buffer);
+                }
+                buffer.put_u8(b'e');
+            }
+        }
+    }
+}
+
+fn encode_byte_string(bytes: &[u8], buffer: &mut impl BufMut) {
+    borrow::Value::ByteString(bytes).encode(buffer);
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+
+    use super::*;
+
+    #[test]
+    fn test_encode_dict() {
+        fn test(
+            fields: &[(&[u8], Option<Field>)],
+            extra: &[(&[u8], borrow::Value)],
+            expect: &[u8],
+        ) {
+            let mut buffer = BytesMut::new();
+            encode_dict(fields, &extra.iter().cloned().collect(), &mut buffer);
+            assert_eq!(buffer, expect);
+        }
+
+        test(&[], &[], b"de");
+        test(&[(b"a".as_slice(), None)], &[], b"de");
+        test(
+            &[
+                (b"b".as_slice(), Some(Field::Integer(1))),
+                (b"d".as_slice(), Some(Field::ByteString(b"x"))),
+            ],
+            &[
+                (b"a".as_slice(), 2.into()),
+                (b"c".as_slice(), 3.into()),
+                (b"e".as_slice(), 4.into()),
+            ],
+            b"d1:ai2e1:bi1e1:ci3e1:d1:x1:ei4ee",
+        );
+        // A field overrides the extra entry unless it is `None`.
+        test(
+            &[
+                (b"a".as_slice(), Some(Field::Integer(1))),
+                (b"b".as_slice(), None),
+            ],
+            &[(b"a".as_slice(), 2.into()), (b"b".as_slice(), 3.into())],
+            b"d1:ai1e1:bi3ee",
+        );
+        test(
+            &[(
+                b"m".as_slice(),
+                Some(Field::ExtensionIds(&BTreeMap::from([
+                    ("foo", 1),
+                    ("bar", 2),
+                ]))),
+            )],
+            &[],
+            b"d1:md3:bari2e3:fooi1eee",
+        );
+    }
+}
@@ -0,0 +1,319 @@
+//! BEP 9 Metadata Fetcher
+//!
+//! `MetadataFetcher` is a state machine that does not perform any I/O by itself.  The caller feeds
+//! it peers and metadata messages, and sends out the requests that it schedules.
+
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+
+use bittorrent_base::InfoHash;
+
+use crate::metadata::{Data, Metadata, Reject, Request};
+
+#[derive(Debug)]
+pub struct MetadataFetcher<P> {
+    info_hash: InfoHash,
+    metadata: Vec<u8>,
+    // It is empty until we learn the metadata size.
+    pieces: Vec<PieceState<P>>,
+    peers: HashMap<P, PeerState>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum PieceState<P> {
+    Missing,
+    Inflight { peer: P, deadline: Instant },
+    Received,
+}
+
+#[derive(Debug, Default)]
+struct PeerState {
+    num_inflights: usize,
+    // Pieces that the peer has rejected; we do not request them from the peer again.
+    rejected: HashSet<usize>,
+}
+
+impl<P> MetadataFetcher<P>
+where
+    P: Clone + Eq + Hash,
+{
+    pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+    /// Maximum number of outstanding requests to a peer.
+    pub const MAX_INFLIGHTS: usize = 2;
+
+    pub fn new(info_hash: InfoHash) -> Self {
+        Self {
+            info_hash,
+            metadata: Vec::new(),
+            pieces: Vec::new(),
+            peers: HashMap::new(),
+        }
+    }
+
+    pub fn metadata_size(&self) -> Option<usize> {
+        (!self.pieces.is_empty()).then_some(self.metadata.len())
+    }
+
+    /// Sets the metadata size, which is usually learned from a peer's handshake.
+    ///
+    /// It is a no-op if the size has already been set.
+    pub fn set_metadata_size(&mut self, metadata_size: usize) {
+        if self.metadata_size().is_some() || metadata_size == 0 {
+            return;
+        }
+        self.metadata.resize(metadata_size, 0);
+        self.pieces
+            .resize(Metadata::num_pieces(metadata_size), PieceState::Missing);
+    }
+
+    pub fn add_peer(&mut self, peer: P) {
+        self.peers.entry(peer).or_default();
+    }
+
+    /// Removes the peer and reschedules its outstanding requests.
+    pub fn remove_peer(&mut self, peer: &P) {
+        if self.peers.remove(peer).is_none() {
+            return;
+        }
+        for state in self.pieces.iter_mut() {
+            if matches!(state, PieceState::Inflight { peer: p, .. } if p == peer) {
+                *state = PieceState::Missing;
+            }
+        }
+    }
+
+    /// Returns the next request to send and the peer to send it to.
+    ///
+    /// The caller should call this repeatedly until it returns `None`, and call it again after
+    /// feeding the fetcher new peers or messages, or after `next_deadline`.
+    pub fn next_request(&mut self, now: Instant) -> Option<(P, Request<'static>)> {
+        self.expire(now);
+        for (piece, state) in self.pieces.iter_mut().enumerate() {
+            if *state != PieceState::Missing {
+                continue;
+            }
+            let Some((peer, peer_state)) = self
+                .peers
+                .iter_mut()
+                .filter(|(_, peer_state)| {
+                    peer_state.num_inflights < Self::MAX_INFLIGHTS
+                        && !peer_state.rejected.contains(&piece)
+                })
+                .min_by_key(|(_, peer_state)| peer_state.num_inflights)
+            else {
+                continue;
+            };
+            peer_state.num_inflights += 1;
+            *state = PieceState::Inflight {
+                peer: peer.clone(),
+                deadline: now + Self::REQUEST_TIMEOUT,
+            };
+            return Some((peer.clone(), Request::new(piece)));
+        }
+        None
+    }
+
+    /// Returns the earliest deadline of the outstanding requests.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.pieces
+            .iter()
+            .filter_map(|state| match state {
+                PieceState::Inflight { deadline, .. } => Some(*deadline),
+                _ => None,
+            })
+            .min()
+    }
+
+    /// Handles a data message and returns the metadata once it is completed and verified.
+    ///
+    /// If the assembled metadata does not match the info hash, the fetcher starts over.
+    pub fn handle_data(&mut self, peer: &P, data: &Data) -> Option<Bytes> {
+        let metadata_size = self.metadata_size()?;
+        let Some(state) = self.pieces.get(data.piece) else {
+            tracing::warn!(piece = data.piece, "invalid metadata piece");
+            return None;
+        };
+        if *state == PieceState::Received {
+            return None;
+        }
+        self.release(data.piece);
+
+        let range = Metadata::byte_range(data.piece, metadata_size);
+        if data.payload.len() != range.len() {
+            tracing::warn!(
+                piece = data.piece,
+                size = data.payload.len(),
+                expect = range.len(),
+                "invalid metadata piece size",
+            );
+            // Treat it like a rejection so that we do not request it from the peer again.
+            self.reject(peer, data.piece);
+            return None;
+        }
+        self.metadata[range].copy_from_slice(data.payload);
+        self.pieces[data.piece] = PieceState::Received;
+
+        if self
+            .pieces
+            .iter()
+            .any(|state| *state != PieceState::Received)
+        {
+            return None;
+        }
+        if InfoHash::v1(&self.metadata) != self.info_hash {
+            tracing::warn!(info_hash = ?self.info_hash, "metadata info hash mismatch");
+            self.pieces.fill(PieceState::Missing);
+            return None;
+        }
+        Some(Bytes::from(std::mem::take(&mut self.metadata)))
+    }
+
+    pub fn handle_reject(&mut self, peer: &P, reject: &Reject) {
+        if !matches!(
+            self.pieces.get(reject.piece),
+            Some(PieceState::Inflight { .. })
+        ) {
+            return;
+        }
+        self.release(reject.piece);
+        self.reject(peer, reject.piece);
+    }
+
+    fn expire(&mut self, now: Instant) {
+        for piece in 0..self.pieces.len() {
+            let expired = match self.pieces[piece] {
+                PieceState::Inflight { deadline, .. } => deadline <= now,
+                _ => false,
+            };
+            if expired {
+                tracing::debug!(piece, "metadata request timeout");
+                self.release(piece);
+            }
+        }
+    }
+
+    /// Marks an inflight piece as missing.
+    fn release(&mut self, piece: usize) {
+        let state = std::mem::replace(&mut self.pieces[piece], PieceState::Missing);
+        if let PieceState::Inflight { peer, .. } = state {
+            if let Some(peer_state) = self.peers.get_mut(&peer) {
+                peer_state.num_inflights -= 1;
+            }
+        }
+    }
+
+    fn reject(&mut self, peer: &P, piece: usize) {
+        if let Some(peer_state) = self.peers.get_mut(peer) {
+            peer_state.rejected.insert(piece);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_data(piece: usize, payload: &[u8]) -> Data<'_> {
+        Data::new(piece, None, payload)
+    }
+
+    fn assert_request(
+        fetcher: &mut MetadataFetcher<u8>,
+        now: Instant,
+        expect: Option<(u8, usize)>,
+    ) {
+        assert_eq!(
+            fetcher
+                .next_request(now)
+                .map(|(peer, request)| (peer, request.piece)),
+            expect,
+        );
+    }
+
+    #[test]
+    fn fetch() {
+        let metadata: Vec<u8> = (0..Metadata::BLOCK_SIZE * 2 + 1).map(|i| i as u8).collect();
+        let info_hash = InfoHash::v1(&metadata);
+        let blocks: Vec<_> = metadata.chunks(Metadata::BLOCK_SIZE).collect();
+        let t0 = Instant::now();
+
+        let mut fetcher = MetadataFetcher::new(info_hash);
+        fetcher.add_peer(1);
+        assert_eq!(fetcher.metadata_size(), None);
+        assert_request(&mut fetcher, t0, None);
+
+        fetcher.set_metadata_size(metadata.len());
+        fetcher.set_metadata_size(42);
+        assert_eq!(fetcher.metadata_size(), Some(metadata.len()));
+
+        assert_request(&mut fetcher, t0, Some((1, 0)));
+        assert_request(&mut fetcher, t0, Some((1, 1)));
+        // Peer 1 has reached `MAX_INFLIGHTS`.
+        assert_request(&mut fetcher, t0, None);
+        assert_eq!(
+            fetcher.next_deadline(),
+            Some(t0 + MetadataFetcher::<u8>::REQUEST_TIMEOUT),
+        );
+
+        fetcher.add_peer(2);
+        assert_request(&mut fetcher, t0, Some((2, 2)));
+        assert_request(&mut fetcher, t0, None);
+
+        // Peer 2 rejects piece 2, and we will not request it from peer 2 again.
+        fetcher.handle_reject(&2, &Reject::new(2));
+        assert_request(&mut fetcher, t0, None);
+
+        assert_eq!(fetcher.handle_data(&1, &new_data(0, blocks[0])), None);
+        assert_request(&mut fetcher, t0, Some((1, 2)));
+
+        // Wrong payload size.
+        assert_eq!(fetcher.handle_data(&1, &new_data(2, blocks[0])), None);
+        assert_request(&mut fetcher, t0, None);
+
+        // Peer 1 is removed, and its outstanding request is rescheduled.
+        fetcher.remove_peer(&1);
+        assert_request(&mut fetcher, t0, Some((2, 1)));
+        // Both peer 1 and 2 have rejected piece 2.
+        assert_request(&mut fetcher, t0, None);
+        fetcher.add_peer(3);
+        assert_request(&mut fetcher, t0, Some((3, 2)));
+
+        assert_eq!(fetcher.handle_data(&2, &new_data(1, blocks[1])), None);
+        assert_eq!(
+            fetcher.handle_data(&3, &new_data(2, blocks[2])),
+            Some(Bytes::from(metadata)),
+        );
+    }
+
+    #[test]
+    fn timeout() {
+        let info_hash = InfoHash::new([0; 20]);
+        let t0 = Instant::now();
+        let t1 = t0 + MetadataFetcher::<u8>::REQUEST_TIMEOUT;
+
+        let mut fetcher = MetadataFetcher::new(info_hash);
+        fetcher.add_peer(1);
+        fetcher.set_metadata_size(1);
+        assert_request(&mut fetcher, t0, Some((1, 0)));
+        assert_request(&mut fetcher, t0, None);
+        assert_request(&mut fetcher, t1 - Duration::from_secs(1), None);
+        assert_request(&mut fetcher, t1, Some((1, 0)));
+    }
+
+    #[test]
+    fn info_hash_mismatch() {
+        let info_hash = InfoHash::new([0; 20]);
+        let t0 = Instant::now();
+
+        let mut fetcher = MetadataFetcher::new(info_hash);
+        fetcher.add_peer(1);
+        fetcher.set_metadata_size(1);
+        assert_request(&mut fetcher, t0, Some((1, 0)));
+        assert_eq!(fetcher.handle_data(&1, &new_data(0, b"x")), None);
+        // The fetcher starts over.
+        assert_request(&mut fetcher, t0, Some((1, 0)));
+    }
+}
@@ -1,6 +1,8 @@
 use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
 
-use bytes::BufMut;
+use bytes::BytesMut;
 use serde::{Deserialize, Serialize};
 use serde_bytes::Bytes;
 
@@ -10,10 +12,13 @@ use bittorrent_bencode::{
     borrow,
     convert::{from_dict, to_dict, to_int, to_str},
     dict::{DictionaryInsert, DictionaryRemove},
-    own, serde as serde_bencode, FormatDictionary,
+    own, FormatDictionary,
 };
 
-use crate::{metadata, Error, EXTENSIONS};
+use crate::encode::{self, Field};
+use crate::{metadata, DecodeMode, Error, ExtensionRegistry, PeerExchange, EXTENSIONS};
+
+g1_param::define!(pub(crate) decode_mode: DecodeMode = DecodeMode::LenientLogged);
 
 #[derive(Clone, DebugExt, Deserialize, Eq, PartialEq, Serialize)]
 #[serde(
@@ -29,6 +34,12 @@ pub struct Handshake<'a> {
     pub extension_ids: BTreeMap<&'a str, u8>,
 
     pub metadata_size: Option<usize>, // BEP 9
+    // BEP 52: A peer that is able to exchange the piece layers of a v2 torrent advertises their
+    // size, which is distinct from `metadata_size`.  We do not advertise it ourselves until we
+    // can fetch and verify the piece layers.
+    pub piece_layers_size: Option<usize>,
+
+    pub upload_only: Option<bool>, // BEP 21
 
     #[debug(with = FormatDictionary)]
     pub extra: BTreeMap<&'a [u8], borrow::Value<'a>>,
@@ -40,28 +51,259 @@ impl Handshake<'_> {
 
     pub fn new(metadata_size: Option<usize>) -> Self {
         Self {
-            extension_ids: EXTENSIONS
-                .iter()
-                .enumerate()
-                .filter_map(|(id, extension)| {
-                    let id = u8::try_from(id).unwrap();
-                    (id != 0 && (extension.is_enabled)()).then_some((extension.name, id))
-                })
-                .collect(),
+            extension_ids: ExtensionRegistry::get().enabled().collect(),
             metadata_size,
+            piece_layers_size: None,
+            upload_only: None,
             extra: BTreeMap::new(),
         }
     }
 
-    pub fn encode(&self, buffer: &mut impl BufMut) {
-        self.serialize(serde_bencode::Serializer)
-            .unwrap()
-            .encode(buffer);
+    /// True if the peer supports exchanging v2 metadata.
+    pub fn supports_v2(&self) -> bool {
+        self.piece_layers_size.is_some()
+    }
+
+    /// Returns our address as the peer sees it (`yourip`).
+    ///
+    /// Like the capabilities, parsing is best-effort.
+    pub fn your_ip(&self) -> Option<IpAddr> {
+        match self.extra.get(YOUR_IP) {
+            Some(borrow::Value::ByteString(your_ip)) => match your_ip.len() {
+                4 => Some(<[u8; 4]>::try_from(*your_ip).unwrap().into()),
+                16 => Some(<[u8; 16]>::try_from(*your_ip).unwrap().into()),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    pub fn encode_into(&self, buffer: &mut BytesMut) {
+        encode::encode_dict(
+            &[
+                (
+                    EXTENSION_IDS,
+                    (!self.extension_ids.is_empty())
+                        .then_some(Field::ExtensionIds(&self.extension_ids)),
+                ),
+                (
+                    METADATA_SIZE,
+                    self.metadata_size
+                        .map(|size| Field::Integer(size.try_into().unwrap())),
+                ),
+                (
+                    PIECE_LAYERS_SIZE,
+                    self.piece_layers_size
+                        .map(|size| Field::Integer(size.try_into().unwrap())),
+                ),
+                (
+                    UPLOAD_ONLY,
+                    self.upload_only
+                        .map(|upload_only| Field::Integer(upload_only.into())),
+                ),
+            ],
+            &self.extra,
+            buffer,
+        );
+    }
+}
+
+/// Capabilities that a peer announces in the handshake.
+///
+/// Except `upload_only`, these keys are not defined by any BEP, and we keep them in `extra`.
+/// Parsing is best-effort; a key of an unexpected type is treated as absent.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Capabilities {
+    /// The peer neither downloads the entire torrent nor seeds it (`share_mode`).
+    pub share_mode: Option<bool>,
+    pub upload_only: Option<bool>, // BEP 21
+    /// The number of outstanding requests that the peer accepts (`reqq`).
+    pub request_queue_size: Option<usize>,
+    /// How long ago the peer completed the torrent (`complete_ago`, in minutes).
+    pub complete_ago: Option<Duration>,
+}
+
+impl Handshake<'_> {
+    pub fn capabilities(&self) -> Capabilities {
+        let get_int = |key| match self.extra.get(key) {
+            Some(borrow::Value::Integer(int)) => Some(*int),
+            _ => None,
+        };
+        Capabilities {
+            share_mode: get_int(SHARE_MODE).map(|share_mode| share_mode != 0),
+            upload_only: self.upload_only,
+            request_queue_size: get_int(REQUEST_QUEUE_SIZE).and_then(|size| size.try_into().ok()),
+            // A negative value means that the peer has not completed the torrent.
+            complete_ago: get_int(COMPLETE_AGO)
+                .and_then(|minutes| u64::try_from(minutes).ok())
+                .map(|minutes| Duration::from_secs(minutes.saturating_mul(60))),
+        }
+    }
+
+    /// Sets the capabilities, overwriting the existing ones.
+    pub fn set_capabilities(&mut self, capabilities: &Capabilities) {
+        fn set(extra: &mut BTreeMap<&[u8], borrow::Value>, key: &'static [u8], int: Option<i64>) {
+            match int {
+                Some(int) => extra.insert(key, borrow::Value::Integer(int)),
+                None => extra.remove(key),
+            };
+        }
+
+        self.upload_only = capabilities.upload_only;
+        set(
+            &mut self.extra,
+            SHARE_MODE,
+            capabilities.share_mode.map(i64::from),
+        );
+        set(
+            &mut self.extra,
+            REQUEST_QUEUE_SIZE,
+            capabilities
+                .request_queue_size
+                .map(|size| size.try_into().unwrap()),
+        );
+        set(
+            &mut self.extra,
+            COMPLETE_AGO,
+            capabilities
+                .complete_ago
+                .map(|complete_ago| (complete_ago.as_secs() / 60).try_into().unwrap()),
+        );
+    }
+}
+
+/// Builds an outgoing handshake with the optional keys that BEP 10 suggests.
+///
+/// The handshake borrows the values from the builder.
+#[derive(Clone, Debug, Default)]
+pub struct HandshakeBuilder {
+    metadata_size: Option<usize>,
+    capabilities: Capabilities,
+    client: Option<String>,
+    // Store addresses in their compact forms, which the handshake borrows.
+    your_ip: Option<Vec<u8>>,
+    ipv4: Option<[u8; 4]>,
+    ipv6: Option<[u8; 16]>,
+    port: Option<u16>,
+    disable_peer_exchange: bool,
+}
+
+impl HandshakeBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn metadata_size(mut self, metadata_size: Option<usize>) -> Self {
+        self.metadata_size = metadata_size;
+        self
+    }
+
+    pub fn capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    pub fn share_mode(mut self, share_mode: Option<bool>) -> Self {
+        self.capabilities.share_mode = share_mode;
+        self
+    }
+
+    pub fn upload_only(mut self, upload_only: Option<bool>) -> Self {
+        self.capabilities.upload_only = upload_only;
+        self
+    }
+
+    /// Sets the client name and version (`v`).
+    pub fn client(mut self, client: Option<String>) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Sets the number of outstanding requests that we accept (`reqq`).
+    pub fn request_queue_size(mut self, request_queue_size: Option<usize>) -> Self {
+        self.capabilities.request_queue_size = request_queue_size;
+        self
+    }
+
+    pub fn complete_ago(mut self, complete_ago: Option<Duration>) -> Self {
+        self.capabilities.complete_ago = complete_ago;
+        self
+    }
+
+    /// Sets the peer's address as we see it (`yourip`).
+    pub fn your_ip(mut self, your_ip: Option<IpAddr>) -> Self {
+        self.your_ip = your_ip.map(|your_ip| match your_ip {
+            IpAddr::V4(ip) => ip.octets().to_vec(),
+            IpAddr::V6(ip) => ip.octets().to_vec(),
+        });
+        self
+    }
+
+    /// Sets our IPv4 address (`ipv4`).
+    pub fn ipv4(mut self, ipv4: Option<Ipv4Addr>) -> Self {
+        self.ipv4 = ipv4.map(|ip| ip.octets());
+        self
+    }
+
+    /// Sets our IPv6 address (`ipv6`).
+    pub fn ipv6(mut self, ipv6: Option<Ipv6Addr>) -> Self {
+        self.ipv6 = ipv6.map(|ip| ip.octets());
+        self
+    }
+
+    /// Sets our listening port (`p`).
+    pub fn port(mut self, port: Option<u16>) -> Self {
+        self.port = port;
+        self
+    }
+
+    /// Withholds PEX from the extension ids when it is disabled for this torrent, even though it
+    /// is enabled process-wide.
+    pub fn peer_exchange(mut self, peer_exchange: bool) -> Self {
+        self.disable_peer_exchange = !peer_exchange;
+        self
+    }
+
+    pub fn build(&self) -> Handshake<'_> {
+        let mut handshake = Handshake::new(self.metadata_size);
+        if self.disable_peer_exchange {
+            let name = EXTENSIONS[usize::from(PeerExchange::ID)].name;
+            handshake.extension_ids.remove(name);
+        }
+        handshake.set_capabilities(&self.capabilities);
+        let extra = &mut handshake.extra;
+        if let Some(client) = &self.client {
+            extra.insert(CLIENT, borrow::Value::ByteString(client.as_bytes()));
+        }
+        if let Some(your_ip) = &self.your_ip {
+            extra.insert(YOUR_IP, borrow::Value::ByteString(your_ip));
+        }
+        if let Some(ipv4) = &self.ipv4 {
+            extra.insert(IPV4, borrow::Value::ByteString(ipv4));
+        }
+        if let Some(ipv6) = &self.ipv6 {
+            extra.insert(IPV6, borrow::Value::ByteString(ipv6));
+        }
+        if let Some(port) = self.port {
+            extra.insert(PORT, borrow::Value::Integer(port.into()));
+        }
+        handshake
     }
 }
 
 const EXTENSION_IDS: &[u8] = b"m";
 const METADATA_SIZE: &[u8] = b"metadata_size"; // BEP 9
+const PIECE_LAYERS_SIZE: &[u8] = b"piece_layers_size"; // BEP 52
+const UPLOAD_ONLY: &[u8] = b"upload_only"; // BEP 21
+
+const CLIENT: &[u8] = b"v";
+const REQUEST_QUEUE_SIZE: &[u8] = b"reqq";
+const SHARE_MODE: &[u8] = b"share_mode";
+const COMPLETE_AGO: &[u8] = b"complete_ago";
+const YOUR_IP: &[u8] = b"yourip";
+const IPV4: &[u8] = b"ipv4";
+const IPV6: &[u8] = b"ipv6";
+const PORT: &[u8] = b"p";
 
 impl<'a> TryFrom<BTreeMap<&'a [u8], borrow::Value<'a>>> for Handshake<'a> {
     type Error = Error;
@@ -76,6 +318,13 @@ impl<'a> TryFrom<BTreeMap<&'a [u8], borrow::Value<'a>>> for Handshake<'a> {
                 .remove_int::<Error>(METADATA_SIZE)?
                 .map(metadata::to_metadata_size)
                 .transpose()?,
+            piece_layers_size: dict
+                .remove_int::<Error>(PIECE_LAYERS_SIZE)?
+                .map(metadata::to_metadata_size)
+                .transpose()?,
+            upload_only: dict
+                .remove_int::<Error>(UPLOAD_ONLY)?
+                .map(|upload_only| upload_only != 0),
             extra: dict,
         })
     }
@@ -95,6 +344,14 @@ impl<'a> From<Handshake<'a>> for BTreeMap<&'a Bytes, own::Value> {
             handshake.metadata_size,
             metadata::from_metadata_size,
         );
+        dict.insert_from(
+            PIECE_LAYERS_SIZE,
+            handshake.piece_layers_size,
+            metadata::from_metadata_size,
+        );
+        dict.insert_from(UPLOAD_ONLY, handshake.upload_only, |upload_only| {
+            i64::from(upload_only).into()
+        });
         dict
     }
 }
@@ -130,6 +387,8 @@ fn from_extension_id(id: u8) -> own::Value {
 
 #[cfg(test)]
 mod tests {
+    use bittorrent_bencode::serde as serde_bencode;
+
     use super::*;
 
     #[test]
@@ -137,6 +396,12 @@ mod tests {
         assert_eq!(EXTENSIONS[usize::from(Handshake::ID)].name, "");
     }
 
+    fn assert_encode_into(handshake: &Handshake) {
+        let mut buffer = BytesMut::new();
+        handshake.encode_into(&mut buffer);
+        assert_eq!(buffer, serde_bencode::to_bytes(handshake).unwrap());
+    }
+
     #[test]
     fn new() {
         assert_eq!(
@@ -144,11 +409,134 @@ mod tests {
             Handshake {
                 extension_ids: BTreeMap::from([("ut_metadata", 1), ("ut_pex", 2)]),
                 metadata_size: Some(42),
+                piece_layers_size: None,
+                upload_only: None,
                 extra: BTreeMap::new(),
             },
         );
     }
 
+    #[test]
+    fn builder() {
+        let builder = HandshakeBuilder::new()
+            .metadata_size(Some(42))
+            .upload_only(Some(true))
+            .client(Some("foo 1.0".to_string()))
+            .request_queue_size(Some(256))
+            .your_ip(Some("1.2.3.4".parse().unwrap()))
+            .ipv4(Some("5.6.7.8".parse().unwrap()))
+            .ipv6(Some("::1".parse().unwrap()))
+            .port(Some(6881));
+        let mut ipv6 = [0u8; 16];
+        ipv6[15] = 1;
+        assert_eq!(
+            builder.build(),
+            Handshake {
+                extension_ids: BTreeMap::from([("ut_metadata", 1), ("ut_pex", 2)]),
+                metadata_size: Some(42),
+                piece_layers_size: None,
+                upload_only: Some(true),
+                extra: BTreeMap::from([
+                    (b"v".as_slice(), borrow::Value::new_byte_string(b"foo 1.0")),
+                    (b"reqq".as_slice(), 256.into()),
+                    (
+                        b"yourip".as_slice(),
+                        borrow::Value::new_byte_string(&[1, 2, 3, 4])
+                    ),
+                    (
+                        b"ipv4".as_slice(),
+                        borrow::Value::new_byte_string(&[5, 6, 7, 8])
+                    ),
+                    (b"ipv6".as_slice(), borrow::Value::new_byte_string(&ipv6)),
+                    (b"p".as_slice(), 6881.into()),
+                ]),
+            },
+        );
+
+        assert_encode_into(&builder.build());
+        assert_eq!(builder.build().supports_v2(), false);
+        let mut handshake = Handshake::new(None);
+        handshake.piece_layers_size = Some(43);
+        assert_eq!(handshake.supports_v2(), true);
+
+        let builder = HandshakeBuilder::new().your_ip(Some("::1".parse().unwrap()));
+        assert_eq!(
+            builder.build().extra,
+            BTreeMap::from([(b"yourip".as_slice(), borrow::Value::new_byte_string(&ipv6))]),
+        );
+        assert_eq!(builder.build().your_ip(), Some("::1".parse().unwrap()));
+        assert_eq!(
+            HandshakeBuilder::new()
+                .your_ip(Some("1.2.3.4".parse().unwrap()))
+                .build()
+                .your_ip(),
+            Some("1.2.3.4".parse().unwrap()),
+        );
+        assert_eq!(Handshake::new(None).your_ip(), None);
+        let mut handshake = Handshake::new(None);
+        handshake
+            .extra
+            .insert(b"yourip".as_slice(), borrow::Value::new_byte_string(b"x"));
+        assert_eq!(handshake.your_ip(), None);
+
+        assert_eq!(HandshakeBuilder::new().build(), Handshake::new(None));
+        assert_eq!(
+            HandshakeBuilder::new()
+                .peer_exchange(false)
+                .build()
+                .extension_ids,
+            BTreeMap::from([("ut_metadata", 1)]),
+        );
+        assert_eq!(
+            HandshakeBuilder::new().peer_exchange(true).build(),
+            Handshake::new(None),
+        );
+    }
+
+    #[test]
+    fn capabilities() {
+        let capabilities = Capabilities {
+            share_mode: Some(true),
+            upload_only: Some(false),
+            request_queue_size: Some(256),
+            complete_ago: Some(Duration::from_secs(120)),
+        };
+        let builder = HandshakeBuilder::new().capabilities(capabilities.clone());
+        let handshake = builder.build();
+        assert_eq!(handshake.upload_only, Some(false));
+        assert_eq!(
+            handshake.extra,
+            BTreeMap::from([
+                (b"complete_ago".as_slice(), 2.into()),
+                (b"reqq".as_slice(), 256.into()),
+                (b"share_mode".as_slice(), 1.into()),
+            ]),
+        );
+        assert_eq!(handshake.capabilities(), capabilities);
+        assert_encode_into(&handshake);
+
+        let mut handshake = Handshake::new(None);
+        handshake.set_capabilities(&capabilities);
+        handshake.set_capabilities(&Capabilities::default());
+        assert_eq!(handshake, Handshake::new(None));
+
+        let mut handshake = Handshake::new(None);
+        handshake.extra = BTreeMap::from([
+            (b"complete_ago".as_slice(), (-1).into()),
+            (b"reqq".as_slice(), borrow::Value::new_byte_string(b"x")),
+            (b"share_mode".as_slice(), 0.into()),
+        ]);
+        assert_eq!(
+            handshake.capabilities(),
+            Capabilities {
+                share_mode: Some(false),
+                upload_only: None,
+                request_queue_size: None,
+                complete_ago: None,
+            },
+        );
+    }
+
     #[test]
     fn conversion() {
         fn test<'a>(decode: BTreeMap<&'a [u8], borrow::Value<'a>>, handshake: Handshake<'a>) {
@@ -157,6 +545,7 @@ mod tests {
                 .map(|(key, value)| (Bytes::new(key), value.to_owned()))
                 .collect();
             assert_eq!(Handshake::try_from(decode), Ok(handshake.clone()));
+            assert_encode_into(&handshake);
             assert_eq!(BTreeMap::from(handshake), encode);
         }
 
@@ -165,6 +554,8 @@ mod tests {
             Handshake {
                 extension_ids: BTreeMap::from([]),
                 metadata_size: None,
+                piece_layers_size: None,
+                upload_only: None,
                 extra: BTreeMap::from([]),
             },
         );
@@ -175,11 +566,15 @@ mod tests {
                     BTreeMap::from([(b"foo".as_slice(), 0.into())]).into(),
                 ),
                 (b"metadata_size".as_slice(), 1.into()),
+                (b"piece_layers_size".as_slice(), 3.into()),
+                (b"upload_only".as_slice(), 1.into()),
                 (b"bar".as_slice(), 2.into()),
             ]),
             Handshake {
                 extension_ids: BTreeMap::from([("foo", 0)]),
                 metadata_size: Some(1),
+                piece_layers_size: Some(3),
+                upload_only: Some(true),
                 extra: BTreeMap::from([(b"bar".as_slice(), 2.into())]),
             },
         );
@@ -0,0 +1,194 @@
+use std::marker::PhantomData;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use bytes::{Buf, BufMut};
+use serde::de::Error as _;
+use snafu::prelude::*;
+
+use bittorrent_bencode::serde as serde_bencode;
+
+use crate::{Error, ExpectHolepunchSizeSnafu};
+
+g1_param::define!(pub(crate) enable: bool = false); // BEP 55
+
+//
+// Implementer's Notes: Unlike other extension messages, BEP 55 messages are not bencoded; they are
+// in a fixed binary format:
+//
+//   msg_type (u8) | addr_type (u8) | addr (4 or 16 bytes) | port (u16) | err_code (u32)
+//
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Holepunch<'a> {
+    pub message_type: HolepunchType,
+    /// For `Rendezvous`, the target peer; for `Connect`, the peer to connect to; for `Error`, the
+    /// target peer of the failed rendezvous.
+    pub endpoint: SocketAddr,
+    /// Set only in `Error` messages.
+    pub error: Option<HolepunchError>,
+    // A holepunch message does not borrow from the buffer, but `define_owner!` requires the
+    // borrower type to have a lifetime parameter.
+    _buffer: PhantomData<&'a [u8]>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u8)]
+pub enum HolepunchType {
+    Rendezvous = 0x00,
+    Connect = 0x01,
+    Error = 0x02,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u32)]
+pub enum HolepunchError {
+    /// The target endpoint is invalid.
+    NoSuchPeer = 0x01,
+    /// The relaying peer is not connected to the target peer.
+    NotConnected = 0x02,
+    /// The target peer does not support the holepunch extension.
+    NoSupport = 0x03,
+    /// The target endpoint belongs to the initiating peer.
+    NoSelf = 0x04,
+}
+
+const ADDR_TYPE_V4: u8 = 0x00;
+const ADDR_TYPE_V6: u8 = 0x01;
+
+impl Holepunch<'_> {
+    // TODO: How can we make this id value match the global `EXTENSIONS` array index?
+    pub const ID: u8 = 3;
+
+    fn new(
+        message_type: HolepunchType,
+        endpoint: SocketAddr,
+        error: Option<HolepunchError>,
+    ) -> Self {
+        Self {
+            message_type,
+            endpoint,
+            error,
+            _buffer: PhantomData,
+        }
+    }
+
+    /// Asks the receiving peer to relay a `Connect` message to the target peer.
+    pub fn rendezvous(endpoint: SocketAddr) -> Self {
+        Self::new(HolepunchType::Rendezvous, endpoint, None)
+    }
+
+    /// Tells the receiving peer to initiate a connection to the given peer.
+    pub fn connect(endpoint: SocketAddr) -> Self {
+        Self::new(HolepunchType::Connect, endpoint, None)
+    }
+
+    pub fn error(endpoint: SocketAddr, error: HolepunchError) -> Self {
+        Self::new(HolepunchType::Error, endpoint, Some(error))
+    }
+
+    pub fn decode(buffer: &[u8]) -> Result<Self, serde_bencode::Error> {
+        Self::decode_impl(buffer).map_err(serde_bencode::Error::custom)
+    }
+
+    fn decode_impl(mut buffer: &[u8]) -> Result<Self, Error> {
+        let size = buffer.len();
+        let addr_size = match buffer.get(1).copied() {
+            Some(ADDR_TYPE_V4) | None => 4,
+            Some(ADDR_TYPE_V6) => 16,
+            Some(addr_type) => return Err(Error::UnknownHolepunchAddressType { addr_type }),
+        };
+        let expect = 2 + addr_size + 2 + 4;
+        ensure!(size == expect, ExpectHolepunchSizeSnafu { size, expect });
+
+        let message_type = buffer.get_u8();
+        let ip = if buffer.get_u8() == ADDR_TYPE_V4 {
+            IpAddr::V4(Ipv4Addr::from(buffer.get_u32()))
+        } else {
+            IpAddr::V6(Ipv6Addr::from(buffer.get_u128()))
+        };
+        let endpoint = SocketAddr::new(ip, buffer.get_u16());
+        let error_code = buffer.get_u32();
+
+        let message_type = match message_type {
+            0x00 => HolepunchType::Rendezvous,
+            0x01 => HolepunchType::Connect,
+            0x02 => HolepunchType::Error,
+            _ => return Err(Error::UnknownHolepunchMessageType { message_type }),
+        };
+        let error = match error_code {
+            0x00 => None,
+            0x01 => Some(HolepunchError::NoSuchPeer),
+            0x02 => Some(HolepunchError::NotConnected),
+            0x03 => Some(HolepunchError::NoSupport),
+            0x04 => Some(HolepunchError::NoSelf),
+            _ => return Err(Error::UnknownHolepunchErrorCode { error_code }),
+        };
+
+        Ok(Self::new(message_type, endpoint, error))
+    }
+
+    pub fn encode(&self, buffer: &mut impl BufMut) {
+        buffer.put_u8(self.message_type as u8);
+        match self.endpoint.ip() {
+            IpAddr::V4(ip) => {
+                buffer.put_u8(ADDR_TYPE_V4);
+                buffer.put_u32(ip.into());
+            }
+            IpAddr::V6(ip) => {
+                buffer.put_u8(ADDR_TYPE_V6);
+                buffer.put_u128(ip.into());
+            }
+        }
+        buffer.put_u16(self.endpoint.port());
+        buffer.put_u32(self.error.map_or(0, |error| error as u32));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+    use hex_literal::hex;
+
+    use super::*;
+
+    fn test_codec(holepunch: Holepunch, expect: &[u8]) {
+        let mut buffer = BytesMut::new();
+        holepunch.encode(&mut buffer);
+        assert_eq!(&*buffer, expect);
+        assert_eq!(Holepunch::decode(expect), Ok(holepunch));
+    }
+
+    #[test]
+    fn codec() {
+        test_codec(
+            Holepunch::rendezvous("127.0.0.1:8000".parse().unwrap()),
+            &hex!("00 00 7f000001 1f40 00000000"),
+        );
+        test_codec(
+            Holepunch::connect("[::1]:8000".parse().unwrap()),
+            &hex!("01 01 00000000000000000000000000000001 1f40 00000000"),
+        );
+        test_codec(
+            Holepunch::error(
+                "127.0.0.1:8000".parse().unwrap(),
+                HolepunchError::NotConnected,
+            ),
+            &hex!("02 00 7f000001 1f40 00000002"),
+        );
+    }
+
+    #[test]
+    fn decode_error() {
+        for buffer in [
+            hex!("").as_slice(),
+            &hex!("00 00 7f000001 1f40"),
+            &hex!("00 00 7f000001 1f40 00000000 00"),
+            &hex!("00 01 7f000001 1f40 00000000"),
+            &hex!("00 02 7f000001 1f40 00000000"),
+            &hex!("03 00 7f000001 1f40 00000000"),
+            &hex!("02 00 7f000001 1f40 00000005"),
+        ] {
+            assert!(Holepunch::decode(buffer).is_err(), "{buffer:?}");
+        }
+    }
+}
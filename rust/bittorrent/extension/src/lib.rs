@@ -1,13 +1,20 @@
 #![feature(iterator_try_collect)]
 
+mod dispatch;
+mod donthave;
+mod encode;
+mod fetch;
 mod handshake;
+mod holepunch;
 mod metadata;
 mod pex;
 
+use std::cell::Cell;
 use std::convert::Infallible;
+use std::sync::OnceLock;
 
-use bytes::Bytes;
-use serde::de::Error as _;
+use bytes::{BufMut, Bytes, BytesMut};
+use serde::{de::Error as _, Deserialize};
 use snafu::prelude::*;
 
 use bittorrent_bencode::{convert, dict, own::Value, serde as serde_bencode};
@@ -22,28 +29,52 @@ use bittorrent_bencode::{convert, dict, own::Value, serde as serde_bencode};
 pub struct Enabled {
     pub metadata: bool,
     pub peer_exchange: bool,
+    pub holepunch: bool,
+    pub donthave: bool,
 }
 
 impl Enabled {
     pub fn load() -> Self {
-        Self::new(*metadata::enable(), *pex::enable())
+        Self::new(
+            *metadata::enable(),
+            *pex::enable(),
+            *holepunch::enable(),
+            *donthave::enable(),
+        )
     }
 
-    pub fn new(metadata: bool, peer_exchange: bool) -> Self {
+    pub fn new(metadata: bool, peer_exchange: bool, holepunch: bool, donthave: bool) -> Self {
         Self {
             metadata,
             peer_exchange,
+            holepunch,
+            donthave,
         }
     }
 }
 
+/// Decodes the payload of an extension message.
+pub type Decode = fn(Bytes) -> Result<MessageOwner<Bytes>, serde_bencode::Error>;
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub(crate) struct Extension {
     name: &'static str,
     is_enabled: fn() -> bool,
-    decode: fn(Bytes) -> Result<MessageOwner<Bytes>, serde_bencode::Error>,
+    decode: Decode,
 }
 
+/// Extensions that we support.
+///
+/// It starts with the built-in extensions.  An application may register custom extensions and
+/// then install the registry at startup, before any extension message is encoded or decoded.
+#[derive(Clone, Debug)]
+pub struct ExtensionRegistry {
+    // NOTE: The vector index also serves as our extension id.
+    extensions: Vec<Extension>,
+}
+
+static REGISTRY: OnceLock<ExtensionRegistry> = OnceLock::new();
+
 // NOTE: The array index also serves as our extension id.
 pub(crate) const EXTENSIONS: [Extension; NUM_EXTENSIONS] = [
     // BEP 10 Handshake
@@ -64,48 +95,225 @@ pub(crate) const EXTENSIONS: [Extension; NUM_EXTENSIONS] = [
         is_enabled: || *pex::enable(),
         decode: |buffer| Ok(PeerExchangeOwner::try_from(buffer)?.try_into().unwrap()),
     },
+    // BEP 55 Holepunch
+    Extension {
+        name: "ut_holepunch",
+        is_enabled: || *holepunch::enable(),
+        decode: |buffer| Ok(HolepunchOwner::try_from(buffer)?.try_into().unwrap()),
+    },
+    // BEP 54 Don't Have
+    Extension {
+        name: "lt_donthave",
+        is_enabled: || *donthave::enable(),
+        decode: |buffer| Ok(DontHaveOwner::try_from(buffer)?.try_into().unwrap()),
+    },
 ];
 
-pub(crate) const NUM_EXTENSIONS: usize = 3;
+pub(crate) const NUM_EXTENSIONS: usize = 5;
 
-pub fn decode(id: u8, buffer: Bytes) -> Result<MessageOwner<Bytes>, serde_bencode::Error> {
-    fn get(id: u8) -> Result<&'static Extension, Error> {
-        let extension = EXTENSIONS
+impl Default for ExtensionRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ExtensionRegistry {
+    /// Creates a registry of the built-in extensions.
+    pub fn new() -> Self {
+        Self {
+            extensions: EXTENSIONS.to_vec(),
+        }
+    }
+
+    /// Returns the installed registry, or the built-in one if none has been installed.
+    pub fn get() -> &'static Self {
+        REGISTRY.get_or_init(Self::new)
+    }
+
+    /// Installs the registry, which fails if a registry has been installed or used already.
+    pub fn install(self) -> Result<(), Self> {
+        REGISTRY.set(self)
+    }
+
+    /// Registers a custom extension and returns its extension id.
+    ///
+    /// `decode` should decode the payload into a `Message::Custom` of the same `name`.
+    pub fn register(&mut self, name: &'static str, decode: Decode) -> Result<u8, Error> {
+        ensure!(
+            !name.is_empty() && self.find(name).is_none(),
+            DuplicateExtensionNameSnafu { name },
+        );
+        let id = u8::try_from(self.extensions.len())
+            .ok()
+            .context(TooManyExtensionsSnafu)?;
+        self.extensions.push(Extension {
+            name,
+            is_enabled: || true,
+            decode,
+        });
+        Ok(id)
+    }
+
+    /// Returns the extension id of `name`.
+    pub fn find(&self, name: &str) -> Option<u8> {
+        self.extensions
+            .iter()
+            .position(|extension| extension.name == name)
+            .map(|id| u8::try_from(id).unwrap())
+    }
+
+    pub fn decode(
+        &self,
+        id: u8,
+        buffer: Bytes,
+    ) -> Result<MessageOwner<Bytes>, serde_bencode::Error> {
+        let extension = self.lookup(id).map_err(serde_bencode::Error::custom)?;
+        (extension.decode)(buffer)
+    }
+
+    fn lookup(&self, id: u8) -> Result<&Extension, Error> {
+        let extension = self
+            .extensions
             .get(usize::from(id))
-            .context(UnknownExtensionIdSnafu { id })?;
-        ensure!((extension.is_enabled)(), ExpectExtensionEnabledSnafu { id });
-        Ok(extension)
+            .context(UnknownExtensionIdSnafu { id });
+        let extension = extension.and_then(|extension| {
+            ensure!((extension.is_enabled)(), ExpectExtensionEnabledSnafu { id });
+            Ok(extension)
+        });
+        if extension.is_err() {
+            note(Deviation::UnknownExtensionId(id));
+        }
+        extension
+    }
+
+    /// Iterates over the enabled extensions and their ids, excluding the handshake.
+    pub(crate) fn enabled(&self) -> impl Iterator<Item = (&'static str, u8)> + '_ {
+        self.extensions
+            .iter()
+            .enumerate()
+            .skip(1)
+            .filter(|(_, extension)| (extension.is_enabled)())
+            .map(|(id, extension)| (extension.name, u8::try_from(id).unwrap()))
+    }
+}
+
+pub fn decode(id: u8, buffer: Bytes) -> Result<MessageOwner<Bytes>, serde_bencode::Error> {
+    ExtensionRegistry::get().decode(id, buffer)
+}
+
+/// Similar to `decode`, except that it also returns the protocol deviation, if any, that the peer
+/// committed.
+///
+/// A deviation may accompany a successfully decoded message (e.g., `LenientDecode`), and a decode
+/// error is always accompanied by a deviation.
+pub fn decode_checked(
+    id: u8,
+    buffer: Bytes,
+) -> (
+    Result<MessageOwner<Bytes>, serde_bencode::Error>,
+    Option<Deviation>,
+) {
+    DEVIATION.set(None);
+    let result = decode(id, buffer);
+    let deviation = DEVIATION.take();
+    let deviation = match &result {
+        Ok(_) => deviation,
+        Err(_) => Some(deviation.unwrap_or(Deviation::Malformed)),
+    };
+    (result, deviation)
+}
+
+/// How to decode a message that does not conform to BEP 3.
+///
+/// Each extension that supports lenient decoding has its own `decode_mode` parameter.  Either way,
+/// a successful lenient decode is reported as `Deviation::LenientDecode`.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DecodeMode {
+    /// Rejects the message.
+    Strict,
+    /// Falls back to the lenient decoder and logs the strict decode error.
+    #[default]
+    LenientLogged,
+    /// Falls back to the lenient decoder silently.
+    Lenient,
+}
+
+/// Protocol deviation of a peer that the decoder observed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Deviation {
+    /// The message does not conform to BEP 3 and was decoded by the lenient decoder.
+    LenientDecode,
+    /// The message has an extension id that we did not assign or have not enabled.
+    UnknownExtensionId(u8),
+    /// The metadata message has an invalid piece index.
+    InvalidPiece(i64),
+    /// The message cannot be decoded.
+    Malformed,
+}
+
+thread_local! {
+    // The owner types decode their borrowers through `TryFrom`, which leaves no room for returning
+    // side information; thus, the decode path notes the deviation here, and `decode_checked`
+    // collects it.  The first deviation noted wins.
+    static DEVIATION: Cell<Option<Deviation>> = const { Cell::new(None) };
+}
+
+pub(crate) fn note(deviation: Deviation) {
+    if DEVIATION.get().is_none() {
+        DEVIATION.set(Some(deviation));
     }
+}
 
-    let extension = get(id).map_err(serde_bencode::Error::custom)?;
-    (extension.decode)(buffer)
+fn get(id: u8) -> Result<&'static Extension, Error> {
+    ExtensionRegistry::get().lookup(id)
 }
 
 /// Maps our extension ids to a peer's extension ids.
-#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct ExtensionIdMap {
-    map: [u8; NUM_EXTENSIONS - 1],
+    map: Vec<u8>,
+    // Unlike extension ids, this is not a mapping, but it is also announced in the handshake.
+    upload_only: bool,
+}
+
+impl Default for ExtensionIdMap {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ExtensionIdMap {
     pub fn new() -> Self {
-        Default::default()
+        Self {
+            map: vec![0; ExtensionRegistry::get().extensions.len() - 1],
+            upload_only: false,
+        }
     }
 
     pub fn update(&mut self, peer_handshake: &Handshake) {
-        for (id, extension) in EXTENSIONS.iter().enumerate() {
-            if id != 0 {
-                if let Some(peer_extension_id) = peer_handshake.extension_ids.get(extension.name) {
-                    self.map[id - 1] = *peer_extension_id;
-                }
+        let extensions = &ExtensionRegistry::get().extensions;
+        for (id, extension) in extensions.iter().enumerate().skip(1) {
+            if let Some(peer_extension_id) = peer_handshake.extension_ids.get(extension.name) {
+                self.map[id - 1] = *peer_extension_id;
             }
         }
+        if let Some(upload_only) = peer_handshake.upload_only {
+            self.upload_only = upload_only;
+        }
+    }
+
+    /// True if the peer announces that it will not download from us (BEP 21).
+    pub fn peer_upload_only(&self) -> bool {
+        self.upload_only
     }
 
     pub fn peer_extensions(&self) -> Enabled {
         Enabled::new(
             self.get(Metadata::ID).is_some(),
             self.get(PeerExchange::ID).is_some(),
+            self.get(Holepunch::ID).is_some(),
+            self.get(DontHave::ID).is_some(),
         )
     }
 
@@ -117,7 +325,7 @@ impl ExtensionIdMap {
         if id == 0 {
             return Some(0);
         }
-        let peer_extension_id = self.map[usize::from(id) - 1];
+        let peer_extension_id = self.map.get(usize::from(id) - 1).copied()?;
         (peer_extension_id != 0).then_some(peer_extension_id)
     }
 }
@@ -137,16 +345,45 @@ g1_base::impl_owner_try_from!(MetadataOwner for MessageOwner);
 g1_base::define_owner!(#[derive(Debug)] pub PeerExchangeOwner for PeerExchange);
 g1_base::impl_owner_try_from!(PeerExchangeOwner for MessageOwner);
 
+g1_base::define_owner!(#[derive(Debug)] pub HolepunchOwner for Holepunch);
+g1_base::impl_owner_try_from!(HolepunchOwner for MessageOwner);
+
+g1_base::define_owner!(#[derive(Debug)] pub DontHaveOwner for DontHave);
+g1_base::impl_owner_try_from!(DontHaveOwner for MessageOwner);
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Message<'a> {
     Handshake(Handshake<'a>),
     Metadata(Metadata<'a>),
     PeerExchange(PeerExchange<'a>),
+    Holepunch(Holepunch<'a>),
+    /// The piece that the peer no longer has.
+    DontHave(u32),
+    /// Message of a custom extension that the application registers.
+    Custom(Custom<'a>),
+}
+
+/// Payload of a custom extension message.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Custom<'a> {
+    /// Name of the extension in the handshake, under which it is registered.
+    pub name: &'static str,
+    pub payload: &'a [u8],
 }
 
-pub use crate::handshake::Handshake;
+impl<'a> Custom<'a> {
+    pub fn new(name: &'static str, payload: &'a [u8]) -> Self {
+        Self { name, payload }
+    }
+}
+
+pub use crate::dispatch::{Dispatcher, HandlerFuture};
+pub use crate::donthave::DontHave;
+pub use crate::fetch::MetadataFetcher;
+pub use crate::handshake::{Capabilities, Handshake, HandshakeBuilder};
+pub use crate::holepunch::{Holepunch, HolepunchError, HolepunchType};
 pub use crate::metadata::{Data, Metadata, Reject, Request};
-pub use crate::pex::{PeerContactInfo, PeerExchange, PeerFlag};
+pub use crate::pex::{PeerContactInfo, PeerExchange, PeerFlag, PexFilter, PexPayload, PexState};
 
 impl Message<'_> {
     pub(crate) fn id(&self) -> u8 {
@@ -154,11 +391,28 @@ impl Message<'_> {
             Self::Handshake(_) => Handshake::ID,
             Self::Metadata(_) => Metadata::ID,
             Self::PeerExchange(_) => PeerExchange::ID,
+            Self::Holepunch(_) => Holepunch::ID,
+            Self::DontHave(_) => DontHave::ID,
+            Self::Custom(custom) => ExtensionRegistry::get()
+                .find(custom.name)
+                .unwrap_or_else(|| std::panic!("unregistered extension: {}", custom.name)),
         }
     }
 
     pub fn is_enabled(&self) -> bool {
-        (EXTENSIONS[usize::from(self.id())].is_enabled)()
+        (ExtensionRegistry::get().extensions[usize::from(self.id())].is_enabled)()
+    }
+
+    /// Encodes the message payload, which does not include the extension id.
+    pub fn encode(&self, buffer: &mut BytesMut) {
+        match self {
+            Self::Handshake(handshake) => handshake.encode_into(buffer),
+            Self::Metadata(metadata) => metadata.encode_into(buffer),
+            Self::PeerExchange(peer_exchange) => peer_exchange.encode_into(buffer),
+            Self::Holepunch(holepunch) => holepunch.encode(buffer),
+            Self::DontHave(piece) => DontHave::new(*piece).encode(buffer),
+            Self::Custom(custom) => buffer.put_slice(custom.payload),
+        }
     }
 }
 
@@ -176,19 +430,25 @@ impl<'a> TryFrom<&'a [u8]> for Message<'a> {
     }
 }
 
-// Unfortunately, some implementations do not adhere to BEP 3.  Let us try a lenient decoder; if it
-// still does not work, return the original error.
+// Unfortunately, some implementations do not adhere to BEP 3.  Unless the mode is strict, let us
+// try a lenient decoder; if it still does not work, return the original error.
 macro_rules! decode_lenient {
-    ($decode:path, $buffer:ident, $error:ident $(,)?) => {
-        $decode($buffer)
-            .inspect(|_| {
-                tracing::debug!(
-                    buffer = $buffer.escape_ascii().to_string(),
-                    error = %$error,
-                    "extension message strict decode error",
-                );
-            })
-            .map_err(|_| $error)
+    ($mode:expr, $decode:path, $buffer:ident, $error:ident $(,)?) => {
+        match $mode {
+            crate::DecodeMode::Strict => Err($error),
+            mode => $decode($buffer)
+                .inspect(|_| {
+                    crate::note(crate::Deviation::LenientDecode);
+                    if mode == crate::DecodeMode::LenientLogged {
+                        tracing::debug!(
+                            buffer = $buffer.escape_ascii().to_string(),
+                            error = %$error,
+                            "extension message strict decode error",
+                        );
+                    }
+                })
+                .map_err(|_| $error),
+        }
     };
 }
 
@@ -197,7 +457,12 @@ impl<'a> TryFrom<&'a [u8]> for Handshake<'a> {
 
     fn try_from(buffer: &'a [u8]) -> Result<Self, Self::Error> {
         serde_bencode::from_bytes(buffer).or_else(|error| {
-            decode_lenient!(serde_bencode::from_bytes_lenient_two_pass, buffer, error)
+            decode_lenient!(
+                *handshake::decode_mode(),
+                serde_bencode::from_bytes_lenient_two_pass,
+                buffer,
+                error,
+            )
         })
     }
 }
@@ -214,7 +479,14 @@ impl<'a> TryFrom<&'a [u8]> for Metadata<'a> {
     type Error = serde_bencode::Error;
 
     fn try_from(buffer: &'a [u8]) -> Result<Self, Self::Error> {
-        Self::decode(buffer).or_else(|error| decode_lenient!(Self::decode_lenient, buffer, error))
+        Self::decode(buffer).or_else(|error| {
+            decode_lenient!(
+                *metadata::decode_mode(),
+                Self::decode_lenient,
+                buffer,
+                error,
+            )
+        })
     }
 }
 
@@ -231,7 +503,12 @@ impl<'a> TryFrom<&'a [u8]> for PeerExchange<'a> {
 
     fn try_from(buffer: &'a [u8]) -> Result<Self, Self::Error> {
         serde_bencode::from_bytes(buffer).or_else(|error| {
-            decode_lenient!(serde_bencode::from_bytes_lenient_two_pass, buffer, error)
+            decode_lenient!(
+                *pex::decode_mode(),
+                serde_bencode::from_bytes_lenient_two_pass,
+                buffer,
+                error,
+            )
         })
     }
 }
@@ -244,6 +521,40 @@ impl<'a> TryFrom<PeerExchange<'a>> for Message<'a> {
     }
 }
 
+// No lenient decoding here since BEP 55 messages are not bencoded.
+impl<'a> TryFrom<&'a [u8]> for Holepunch<'a> {
+    type Error = serde_bencode::Error;
+
+    fn try_from(buffer: &'a [u8]) -> Result<Self, Self::Error> {
+        Self::decode(buffer)
+    }
+}
+
+impl<'a> TryFrom<Holepunch<'a>> for Message<'a> {
+    type Error = Infallible;
+
+    fn try_from(holepunch: Holepunch<'a>) -> Result<Self, Self::Error> {
+        Ok(Message::Holepunch(holepunch))
+    }
+}
+
+// No lenient decoding here since BEP 54 messages are not bencoded.
+impl<'a> TryFrom<&'a [u8]> for DontHave<'a> {
+    type Error = serde_bencode::Error;
+
+    fn try_from(buffer: &'a [u8]) -> Result<Self, Self::Error> {
+        Self::decode(buffer)
+    }
+}
+
+impl<'a> TryFrom<DontHave<'a>> for Message<'a> {
+    type Error = Infallible;
+
+    fn try_from(donthave: DontHave<'a>) -> Result<Self, Self::Error> {
+        Ok(Message::DontHave(donthave.piece))
+    }
+}
+
 //
 // Error
 //
@@ -268,10 +579,14 @@ pub enum Error {
     //
     // BEP 10
     //
+    #[snafu(display("duplicate extension name: \"{name}\""))]
+    DuplicateExtensionName { name: String },
     #[snafu(display("expect extension to be enabled: {id}"))]
     ExpectExtensionEnabled { id: u8 },
     #[snafu(display("invalid extension id: {id}"))]
     InvalidExtensionId { id: i64 },
+    #[snafu(display("too many extensions"))]
+    TooManyExtensions,
     #[snafu(display("unknown extension id: {id}"))]
     UnknownExtensionId { id: u8 },
 
@@ -292,6 +607,24 @@ pub enum Error {
     ExpectPeerExchangeEndpointsSize { size: usize, expect: usize },
     #[snafu(display("invalid peer exchange endpoints: {endpoints:?}"))]
     InvalidPeerExchangeEndpoints { endpoints: Vec<u8> },
+
+    //
+    // BEP 55
+    //
+    #[snafu(display("expect holepunch message size == {expect}: {size}"))]
+    ExpectHolepunchSize { size: usize, expect: usize },
+    #[snafu(display("unknown holepunch address type: {addr_type}"))]
+    UnknownHolepunchAddressType { addr_type: u8 },
+    #[snafu(display("unknown holepunch error code: {error_code}"))]
+    UnknownHolepunchErrorCode { error_code: u32 },
+    #[snafu(display("unknown holepunch message type: {message_type}"))]
+    UnknownHolepunchMessageType { message_type: u8 },
+
+    //
+    // BEP 54
+    //
+    #[snafu(display("expect donthave message size == 4: {size}"))]
+    ExpectDontHaveSize { size: usize },
 }
 
 impl From<convert::Error> for Error {
@@ -318,26 +651,121 @@ impl From<dict::Error> for Error {
 mod tests {
     use std::collections::BTreeMap;
 
+    use bittorrent_bencode::borrow;
+
     use super::*;
 
     #[test]
     fn update() {
         let mut map = ExtensionIdMap::new();
-        assert_eq!(map, ExtensionIdMap { map: [0, 0] });
+        assert_eq!(
+            map,
+            ExtensionIdMap {
+                map: vec![0, 0, 0, 0],
+                upload_only: false,
+            }
+        );
 
         map.update(&Handshake {
             extension_ids: BTreeMap::from([("foo", 42), ("ut_metadata", 99)]),
             metadata_size: None,
+            piece_layers_size: None,
+            upload_only: None,
             extra: BTreeMap::from([]),
         });
-        assert_eq!(map, ExtensionIdMap { map: [99, 0] });
+        assert_eq!(
+            map,
+            ExtensionIdMap {
+                map: vec![99, 0, 0, 0],
+                upload_only: false,
+            }
+        );
 
         map.update(&Handshake {
             extension_ids: BTreeMap::from([("ut_metadata", 0), ("ut_pex", 100)]),
             metadata_size: None,
+            piece_layers_size: None,
+            upload_only: Some(true),
             extra: BTreeMap::from([]),
         });
-        assert_eq!(map, ExtensionIdMap { map: [0, 100] });
+        assert_eq!(
+            map,
+            ExtensionIdMap {
+                map: vec![0, 100, 0, 0],
+                upload_only: true,
+            }
+        );
+        assert_eq!(map.peer_upload_only(), true);
+
+        map.update(&Handshake {
+            extension_ids: BTreeMap::from([]),
+            metadata_size: None,
+            piece_layers_size: None,
+            upload_only: None,
+            extra: BTreeMap::from([]),
+        });
+        assert_eq!(map.peer_upload_only(), true);
+
+        map.update(&Handshake {
+            extension_ids: BTreeMap::from([]),
+            metadata_size: None,
+            piece_layers_size: None,
+            upload_only: Some(false),
+            extra: BTreeMap::from([]),
+        });
+        assert_eq!(map.peer_upload_only(), false);
+    }
+
+    #[test]
+    fn test_decode_checked() {
+        let mut buffer = BytesMut::new();
+        Metadata::Request(Request::new(1)).encode_into(&mut buffer);
+        let (result, deviation) = decode_checked(Metadata::ID, buffer.freeze());
+        assert!(result.is_ok());
+        assert_eq!(deviation, None);
+
+        let (result, deviation) = decode_checked(
+            Metadata::ID,
+            Bytes::from_static(b"d5:piecei1e8:msg_typei0ee"),
+        );
+        assert!(result.is_ok());
+        assert_eq!(deviation, Some(Deviation::LenientDecode));
+
+        let (result, deviation) = decode_checked(
+            Metadata::ID,
+            Bytes::from_static(b"d8:msg_typei0e5:piecei-1ee"),
+        );
+        assert!(result.is_err());
+        assert_eq!(deviation, Some(Deviation::InvalidPiece(-1)));
+
+        let (result, deviation) = decode_checked(99, Bytes::new());
+        assert!(result.is_err());
+        assert_eq!(deviation, Some(Deviation::UnknownExtensionId(99)));
+
+        let (result, deviation) = decode_checked(Metadata::ID, Bytes::from_static(b"x"));
+        assert!(result.is_err());
+        assert_eq!(deviation, Some(Deviation::Malformed));
+
+        // The deviation of a previous call does not leak into the next call.
+        let mut buffer = BytesMut::new();
+        Metadata::Reject(Reject::new(2)).encode_into(&mut buffer);
+        assert_eq!(decode_checked(Metadata::ID, buffer.freeze()).1, None);
+    }
+
+    #[test]
+    fn test_decode_lenient() {
+        let buffer = b"d5:piecei1e8:msg_typei0ee".as_slice();
+        for (mode, expect) in [
+            (DecodeMode::Strict, None),
+            (DecodeMode::LenientLogged, Some(Deviation::LenientDecode)),
+            (DecodeMode::Lenient, Some(Deviation::LenientDecode)),
+        ] {
+            DEVIATION.set(None);
+            let error = Metadata::decode(buffer).unwrap_err();
+            let result = decode_lenient!(mode, Metadata::decode_lenient, buffer, error);
+            assert_eq!(result.is_ok(), expect.is_some(), "{mode:?}");
+            assert_eq!(DEVIATION.take(), expect, "{mode:?}");
+        }
     }
 
     #[test]
@@ -350,6 +778,8 @@ mod tests {
         map.update(&Handshake {
             extension_ids: BTreeMap::from([("ut_metadata", 99)]),
             metadata_size: None,
+            piece_layers_size: None,
+            upload_only: None,
             extra: BTreeMap::from([]),
         });
         assert_eq!(map.get(0), Some(0));
@@ -359,10 +789,151 @@ mod tests {
         map.update(&Handshake {
             extension_ids: BTreeMap::from([("ut_metadata", 0), ("ut_pex", 100)]),
             metadata_size: None,
+            piece_layers_size: None,
+            upload_only: None,
             extra: BTreeMap::from([]),
         });
         assert_eq!(map.get(0), Some(0));
         assert_eq!(map.get(1), None);
         assert_eq!(map.get(2), Some(100));
+        assert_eq!(map.get(3), None);
+
+        map.update(&Handshake {
+            extension_ids: BTreeMap::from([("ut_holepunch", 101)]),
+            metadata_size: None,
+            piece_layers_size: None,
+            upload_only: None,
+            extra: BTreeMap::from([]),
+        });
+        assert_eq!(map.get(3), Some(101));
+        assert_eq!(
+            map.peer_extensions(),
+            Enabled::new(false, true, true, false)
+        );
+
+        map.update(&Handshake {
+            extension_ids: BTreeMap::from([("lt_donthave", 102)]),
+            metadata_size: None,
+            piece_layers_size: None,
+            upload_only: None,
+            extra: BTreeMap::from([]),
+        });
+        assert_eq!(map.get(4), Some(102));
+        assert_eq!(map.peer_extensions(), Enabled::new(false, true, true, true));
+    }
+
+    #[derive(Debug)]
+    struct Comment<'a>(&'a [u8]);
+
+    impl<'a> TryFrom<&'a [u8]> for Comment<'a> {
+        type Error = serde_bencode::Error;
+
+        fn try_from(buffer: &'a [u8]) -> Result<Self, Self::Error> {
+            Ok(Self(buffer))
+        }
+    }
+
+    impl<'a> TryFrom<Comment<'a>> for Message<'a> {
+        type Error = Infallible;
+
+        fn try_from(comment: Comment<'a>) -> Result<Self, Self::Error> {
+            Ok(Message::Custom(Custom::new("ut_comment", comment.0)))
+        }
+    }
+
+    g1_base::define_owner!(#[derive(Debug)] CommentOwner for Comment);
+    g1_base::impl_owner_try_from!(CommentOwner for MessageOwner);
+
+    #[test]
+    fn registry() {
+        let mut registry = ExtensionRegistry::new();
+        assert_eq!(registry.find("ut_pex"), Some(PeerExchange::ID));
+        assert_eq!(registry.find("ut_comment"), None);
+
+        let id = registry
+            .register("ut_comment", |buffer| {
+                Ok(CommentOwner::try_from(buffer)?.try_into().unwrap())
+            })
+            .unwrap();
+        assert_eq!(usize::from(id), NUM_EXTENSIONS);
+        assert_eq!(registry.find("ut_comment"), Some(id));
+        assert_eq!(registry.enabled().last(), Some(("ut_comment", id)),);
+
+        let message = registry.decode(id, Bytes::from_static(b"hello")).unwrap();
+        assert_eq!(
+            message.deref(),
+            &Message::Custom(Custom::new("ut_comment", b"hello")),
+        );
+        assert!(registry.decode(id + 1, Bytes::new()).is_err());
+
+        assert_eq!(
+            registry.register("ut_comment", |_| std::unreachable!()),
+            Err(Error::DuplicateExtensionName {
+                name: "ut_comment".to_string(),
+            }),
+        );
+        assert_eq!(
+            registry.register("", |_| std::unreachable!()),
+            Err(Error::DuplicateExtensionName {
+                name: "".to_string(),
+            }),
+        );
+        for i in registry.extensions.len()..256 {
+            let name = format!("x_{i}").leak();
+            assert_eq!(
+                registry.register(name, |_| std::unreachable!()),
+                Ok(u8::try_from(i).unwrap()),
+            );
+        }
+        assert_eq!(
+            registry.register("x_256", |_| std::unreachable!()),
+            Err(Error::TooManyExtensions),
+        );
+    }
+
+    #[test]
+    fn donthave() {
+        let mut buffer = BytesMut::new();
+        DontHave::new(42).encode(&mut buffer);
+        let message = DontHaveOwner::try_from(buffer.freeze()).unwrap();
+        let message: MessageOwner<Bytes> = message.try_into().unwrap();
+        assert_eq!(message.deref(), &Message::DontHave(42));
+        assert_eq!(message.deref().id(), DontHave::ID);
+    }
+
+    #[test]
+    fn encode() {
+        fn test(message: Message, expect: &[u8]) {
+            let mut buffer = BytesMut::new();
+            message.encode(&mut buffer);
+            assert_eq!(buffer, expect);
+        }
+
+        test(
+            Message::Handshake(Handshake {
+                extension_ids: BTreeMap::from([("ut_metadata", 1)]),
+                metadata_size: Some(42),
+                piece_layers_size: None,
+                upload_only: None,
+                extra: BTreeMap::from([(b"v".as_slice(), borrow::Value::new_byte_string(b"x"))]),
+            }),
+            b"d1:md11:ut_metadatai1ee13:metadata_sizei42e1:v1:xe",
+        );
+        test(
+            Message::Metadata(Metadata::Data(Data::new(1, Some(2), b"xy"))),
+            b"d8:msg_typei1e5:piecei1e10:total_sizei2eexy",
+        );
+        test(
+            Message::PeerExchange(PeerExchange::new(
+                b"",
+                b"",
+                b"",
+                b"",
+                b"\x7f\x00\x00\x01\x1f\x41",
+                b"",
+            )),
+            b"d7:dropped6:\x7f\x00\x00\x01\x1f\x41e",
+        );
+        test(Message::DontHave(42), &[0, 0, 0, 42]);
     }
 }
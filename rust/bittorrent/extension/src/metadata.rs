@@ -2,7 +2,7 @@ use std::cmp;
 use std::collections::BTreeMap;
 use std::ops::Range;
 
-use bytes::BufMut;
+use bytes::{BufMut, BytesMut};
 use serde::{de::Error as _, Serialize};
 use serde_bytes::Bytes;
 
@@ -15,9 +15,11 @@ use bittorrent_bencode::{
     own, serde as serde_bencode, FormatDictionary,
 };
 
-use crate::Error;
+use crate::encode::{self, Field};
+use crate::{DecodeMode, Deviation, Error};
 
 g1_param::define!(pub(crate) enable: bool = true); // BEP 9
+g1_param::define!(pub(crate) decode_mode: DecodeMode = DecodeMode::LenientLogged);
 
 // We do not use `serde` to deserialize the metadata because the bencode-then-payload format does
 // not conform well to the way the `serde` API works.
@@ -29,9 +31,14 @@ pub enum Metadata<'a> {
     Reject(Reject<'a>),
 }
 
+// BEP 52 extends the exchange to the piece layers of a v2 torrent, which is a separate byte
+// string from the info dictionary.  A message with `piece_layers` set refers to the piece layers,
+// and its `piece` and `total_size` are in terms of the piece layers rather than the info dict.
+
 #[derive(Clone, DebugExt, Eq, PartialEq)]
 pub struct Request<'a> {
     pub piece: usize,
+    pub piece_layers: bool, // BEP 52
 
     #[debug(with = FormatDictionary)]
     pub extra: BTreeMap<&'a [u8], borrow::Value<'a>>,
@@ -40,6 +47,7 @@ pub struct Request<'a> {
 #[derive(Clone, DebugExt, Eq, PartialEq)]
 pub struct Data<'a> {
     pub piece: usize,
+    pub piece_layers: bool, // BEP 52
     pub total_size: Option<usize>,
     pub payload: &'a [u8],
 
@@ -50,6 +58,7 @@ pub struct Data<'a> {
 #[derive(Clone, DebugExt, Eq, PartialEq)]
 pub struct Reject<'a> {
     pub piece: usize,
+    pub piece_layers: bool, // BEP 52
 
     #[debug(with = FormatDictionary)]
     pub extra: BTreeMap<&'a [u8], borrow::Value<'a>>,
@@ -83,23 +92,57 @@ impl<'a> Metadata<'a> {
         Self::try_from((header.to_strict(), buffer)).map_err(serde_bencode::Error::custom)
     }
 
-    pub fn encode(&self, buffer: &mut impl BufMut) {
-        self.serialize(serde_bencode::Serializer)
-            .unwrap()
-            .encode(buffer);
+    pub fn encode_into(&self, buffer: &mut BytesMut) {
+        let (message_type, piece, total_size, extra) = match self {
+            Self::Request(request) => (REQUEST, request.piece, None, &request.extra),
+            Self::Data(data) => (DATA, data.piece, data.total_size, &data.extra),
+            Self::Reject(reject) => (REJECT, reject.piece, None, &reject.extra),
+        };
+        encode::encode_dict(
+            &[
+                (MESSAGE_TYPE, Some(Field::Integer(message_type))),
+                (PIECE, Some(Field::Integer(piece.try_into().unwrap()))),
+                (
+                    PIECE_LAYERS,
+                    self.is_piece_layers().then_some(Field::Integer(1)),
+                ),
+                (
+                    TOTAL_SIZE,
+                    total_size.map(|size| Field::Integer(size.try_into().unwrap())),
+                ),
+            ],
+            extra,
+            buffer,
+        );
         if let Self::Data(data) = self {
             buffer.put_slice(data.payload);
         }
     }
+
+    pub fn is_piece_layers(&self) -> bool {
+        match self {
+            Self::Request(request) => request.piece_layers,
+            Self::Data(data) => data.piece_layers,
+            Self::Reject(reject) => reject.piece_layers,
+        }
+    }
 }
 
 impl Request<'_> {
     pub fn new(piece: usize) -> Self {
         Self {
             piece,
+            piece_layers: false,
             extra: BTreeMap::new(),
         }
     }
+
+    pub fn new_piece_layers(piece: usize) -> Self {
+        Self {
+            piece_layers: true,
+            ..Self::new(piece)
+        }
+    }
 }
 
 impl<'a> Data<'a> {
@@ -107,24 +150,41 @@ impl<'a> Data<'a> {
         assert!(payload.len() <= Metadata::BLOCK_SIZE);
         Self {
             piece,
+            piece_layers: false,
             total_size,
             payload,
             extra: BTreeMap::new(),
         }
     }
+
+    pub fn new_piece_layers(piece: usize, total_size: Option<usize>, payload: &'a [u8]) -> Self {
+        Self {
+            piece_layers: true,
+            ..Self::new(piece, total_size, payload)
+        }
+    }
 }
 
 impl Reject<'_> {
     pub fn new(piece: usize) -> Self {
         Self {
             piece,
+            piece_layers: false,
             extra: BTreeMap::new(),
         }
     }
+
+    pub fn new_piece_layers(piece: usize) -> Self {
+        Self {
+            piece_layers: true,
+            ..Self::new(piece)
+        }
+    }
 }
 
 const MESSAGE_TYPE: &[u8] = b"msg_type";
 const PIECE: &[u8] = b"piece";
+const PIECE_LAYERS: &[u8] = b"piece_layers"; // BEP 52
 const TOTAL_SIZE: &[u8] = b"total_size";
 
 const REQUEST: i64 = 0;
@@ -154,6 +214,7 @@ impl<'a> TryFrom<BTreeMap<&'a [u8], borrow::Value<'a>>> for Request<'a> {
                 .must_remove(PIECE)
                 .and_then(to_int)
                 .and_then(to_piece)?,
+            piece_layers: remove_piece_layers(&mut dict)?,
             extra: dict,
         })
     }
@@ -170,6 +231,7 @@ impl<'a> TryFrom<(BTreeMap<&'a [u8], borrow::Value<'a>>, &'a [u8])> for Data<'a>
                 .must_remove(PIECE)
                 .and_then(to_int)
                 .and_then(to_piece)?,
+            piece_layers: remove_piece_layers(&mut dict)?,
             total_size: dict
                 .remove_int::<Error>(TOTAL_SIZE)?
                 .map(to_metadata_size)
@@ -189,6 +251,7 @@ impl<'a> TryFrom<BTreeMap<&'a [u8], borrow::Value<'a>>> for Reject<'a> {
                 .must_remove(PIECE)
                 .and_then(to_int)
                 .and_then(to_piece)?,
+            piece_layers: remove_piece_layers(&mut dict)?,
             extra: dict,
         })
     }
@@ -209,6 +272,7 @@ impl<'a> From<Request<'a>> for BTreeMap<&'a Bytes, own::Value> {
         let mut dict = from_dict(request.extra, Bytes::new);
         dict.insert(Bytes::new(MESSAGE_TYPE), REQUEST.into());
         dict.insert(Bytes::new(PIECE), from_piece(request.piece));
+        insert_piece_layers(&mut dict, request.piece_layers);
         dict
     }
 }
@@ -218,6 +282,7 @@ impl<'a> From<Data<'a>> for BTreeMap<&'a Bytes, own::Value> {
         let mut dict = from_dict(data.extra, Bytes::new);
         dict.insert(Bytes::new(MESSAGE_TYPE), DATA.into());
         dict.insert(Bytes::new(PIECE), from_piece(data.piece));
+        insert_piece_layers(&mut dict, data.piece_layers);
         dict.insert_from(TOTAL_SIZE, data.total_size, from_metadata_size);
         dict
     }
@@ -228,20 +293,36 @@ impl<'a> From<Reject<'a>> for BTreeMap<&'a Bytes, own::Value> {
         let mut dict = from_dict(reject.extra, Bytes::new);
         dict.insert(Bytes::new(MESSAGE_TYPE), REJECT.into());
         dict.insert(Bytes::new(PIECE), from_piece(reject.piece));
+        insert_piece_layers(&mut dict, reject.piece_layers);
         dict
     }
 }
 
 fn to_piece(piece: i64) -> Result<usize, Error> {
-    piece
-        .try_into()
-        .map_err(|_| Error::InvalidMetadataPiece { piece })
+    piece.try_into().map_err(|_| {
+        crate::note(Deviation::InvalidPiece(piece));
+        Error::InvalidMetadataPiece { piece }
+    })
 }
 
 fn from_piece(piece: usize) -> own::Value {
     i64::try_from(piece).unwrap().into()
 }
 
+fn remove_piece_layers<'a>(
+    dict: &mut BTreeMap<&'a [u8], borrow::Value<'a>>,
+) -> Result<bool, Error> {
+    Ok(dict
+        .remove_int::<Error>(PIECE_LAYERS)?
+        .is_some_and(|piece_layers| piece_layers != 0))
+}
+
+fn insert_piece_layers(dict: &mut BTreeMap<&Bytes, own::Value>, piece_layers: bool) {
+    if piece_layers {
+        dict.insert(Bytes::new(PIECE_LAYERS), 1.into());
+    }
+}
+
 pub(crate) fn to_metadata_size(size: i64) -> Result<usize, Error> {
     size.try_into()
         .map_err(|_| Error::InvalidMetadataSize { size })
@@ -253,8 +334,6 @@ pub(crate) fn from_metadata_size(size: usize) -> own::Value {
 
 #[cfg(test)]
 mod tests {
-    use bytes::BytesMut;
-
     use super::*;
 
     #[test]
@@ -288,7 +367,7 @@ mod tests {
     fn conversion() {
         fn test(testdata: &[u8], expect: Metadata) {
             let mut buffer = BytesMut::new();
-            expect.encode(&mut buffer);
+            expect.encode_into(&mut buffer);
             assert_eq!(buffer, testdata);
 
             assert_eq!(Metadata::decode(testdata), Ok(expect));
@@ -302,6 +381,7 @@ mod tests {
             b"d3:foo3:bar8:msg_typei0e5:piecei42ee",
             Metadata::Request(Request {
                 piece: 42,
+                piece_layers: false,
                 extra: BTreeMap::from([(
                     b"foo".as_slice(),
                     borrow::Value::new_byte_string(b"bar"),
@@ -323,6 +403,23 @@ mod tests {
             Metadata::Reject(Reject::new(42)),
         );
 
+        test(
+            b"d8:msg_typei0e5:piecei42e12:piece_layersi1ee",
+            Metadata::Request(Request::new_piece_layers(42)),
+        );
+        test(
+            b"d8:msg_typei1e5:piecei42e12:piece_layersi1e10:total_sizei43eehello world",
+            Metadata::Data(Data::new_piece_layers(42, Some(43), b"hello world")),
+        );
+        test(
+            b"d8:msg_typei2e5:piecei42e12:piece_layersi1ee",
+            Metadata::Reject(Reject::new_piece_layers(42)),
+        );
+        assert_eq!(
+            Metadata::decode(b"d8:msg_typei0e5:piecei42e12:piece_layersi0ee"),
+            Ok(Metadata::Request(Request::new(42))),
+        );
+
         assert_eq!(
             Metadata::try_from((
                 BTreeMap::from([(b"msg_type".as_slice(), (-1).into())]).into(),
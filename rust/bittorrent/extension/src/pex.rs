@@ -1,6 +1,7 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::iter;
-use std::net::{SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::net::{IpAddr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::time::{Duration, Instant};
 
 use bitvec::prelude::*;
 use bytes::{BufMut, BytesMut};
@@ -14,12 +15,24 @@ use bittorrent_base::compact::Compact;
 use bittorrent_bencode::{
     borrow,
     convert::{from_bytes, from_dict, to_bytes},
-    own, serde as serde_bencode, FormatDictionary,
+    own, FormatDictionary,
 };
 
-use crate::{Error, ExpectPeerExchangeEndpointsSizeSnafu};
+use crate::encode::{self, non_empty};
+use crate::{DecodeMode, Error, ExpectPeerExchangeEndpointsSizeSnafu};
 
 g1_param::define!(pub(crate) enable: bool = true); // BEP 11
+g1_param::define!(pub(crate) decode_mode: DecodeMode = DecodeMode::LenientLogged);
+
+// Peer-quality filtering of the peers that we propagate via PEX.  Propagating junk peers wastes the
+// connection attempts of the whole swarm.
+//
+// Propagate only the peers that we have successfully connected to (rather than accepted from).
+g1_param::define!(pub(crate) filter_unreachable: bool = false);
+// Do not propagate LAN (or otherwise non-global) peers to global peers.
+g1_param::define!(pub(crate) filter_local: bool = true);
+// Propagate only the peers of the remote peer's address family.
+g1_param::define!(pub(crate) filter_cross_family: bool = false);
 
 //
 // Implementer's Notes: we currently treat "not present" the same as "present but empty".
@@ -87,6 +100,22 @@ impl<'a> PeerExchange<'a> {
         }
     }
 
+    /// Decodes both IPv4 and IPv6 added peers, with IPv4 peers first.
+    pub fn decode_added(&self) -> Result<Vec<PeerContactInfo>, Error> {
+        Ok(self
+            .decode_added_v4()?
+            .chain(self.decode_added_v6()?)
+            .collect())
+    }
+
+    /// Decodes both IPv4 and IPv6 dropped peers, with IPv4 peers first.
+    pub fn decode_dropped(&self) -> Result<Vec<SocketAddr>, Error> {
+        Ok(self
+            .decode_dropped_v4()?
+            .chain(self.decode_dropped_v6()?)
+            .collect())
+    }
+
     pub fn decode_added_v4(&self) -> Result<impl Iterator<Item = PeerContactInfo> + 'a, Error> {
         Ok(
             decode_endpoints::<SocketAddrV4>(self.added_v4, self.added_flags_v4.len())?
@@ -113,10 +142,11 @@ impl<'a> PeerExchange<'a> {
         decode_endpoints::<SocketAddrV6>(self.dropped_v6, 0)
     }
 
+    /// Encodes a PEX message, splitting the peers into the IPv4 and IPv6 keys.
     pub fn encode(
         added: impl Iterator<Item = PeerContactInfo>,
         dropped: impl Iterator<Item = SocketAddr>,
-        buffer: &mut impl BufMut,
+        buffer: &mut BytesMut,
     ) {
         let (added_v4, added_v6, added_flags_v4, added_flags_v6) = Self::encode_added(added);
         let (dropped_v4, dropped_v6) = Self::encode_dropped(dropped);
@@ -129,9 +159,22 @@ impl<'a> PeerExchange<'a> {
             &dropped_v4,
             &dropped_v6,
         );
-        this.serialize(serde_bencode::Serializer)
-            .unwrap()
-            .encode(buffer);
+        this.encode_into(buffer);
+    }
+
+    pub fn encode_into(&self, buffer: &mut BytesMut) {
+        encode::encode_dict(
+            &[
+                (ADDED_V4, non_empty(self.added_v4)),
+                (ADDED_FLAGS_V4, non_empty(self.added_flags_v4)),
+                (ADDED_V6, non_empty(self.added_v6)),
+                (ADDED_FLAGS_V6, non_empty(self.added_flags_v6)),
+                (DROPPED_V4, non_empty(self.dropped_v4)),
+                (DROPPED_V6, non_empty(self.dropped_v6)),
+            ],
+            &self.extra,
+            buffer,
+        );
     }
 
     pub fn encode_added(
@@ -195,6 +238,187 @@ impl PeerContactInfo {
     }
 }
 
+/// Accumulates the added and dropped peers that we have yet to exchange with a remote peer.
+#[derive(Clone, Debug, Default)]
+pub struct PexState {
+    // Peers that the remote peer has learned from us and that we have not dropped since.
+    exchanged: HashSet<SocketAddr>,
+    added: HashMap<SocketAddr, PeerContactInfo>,
+    dropped: HashSet<SocketAddr>,
+    last_send: Option<Instant>,
+}
+
+/// Decides which peers are worth propagating to a remote peer via PEX.
+#[derive(Clone, Debug, Default)]
+pub struct PexFilter {
+    filter_unreachable: bool,
+    filter_local: bool,
+    filter_cross_family: bool,
+    banned: HashSet<IpAddr>,
+}
+
+/// Added and dropped peers of a PEX message.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PexPayload {
+    pub added: Vec<PeerContactInfo>,
+    pub dropped: Vec<SocketAddr>,
+}
+
+impl PexState {
+    /// BEP 11 specifies that we should not send PEX messages more than once a minute.
+    pub const MIN_INTERVAL: Duration = Duration::from_secs(60);
+    /// BEP 11 limits each message to 50 added and 50 dropped peers.
+    pub const MAX_PEERS: usize = 50;
+
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn add(&mut self, contact_info: PeerContactInfo) {
+        let endpoint = contact_info.endpoint;
+        self.dropped.remove(&endpoint);
+        if !self.exchanged.contains(&endpoint) {
+            self.added.insert(endpoint, contact_info);
+        }
+    }
+
+    pub fn remove(&mut self, endpoint: SocketAddr) {
+        if self.added.remove(&endpoint).is_none() && self.exchanged.contains(&endpoint) {
+            self.dropped.insert(endpoint);
+        }
+    }
+
+    /// Adds the given peers and removes all other peers.
+    pub fn update(&mut self, peers: impl IntoIterator<Item = PeerContactInfo>) {
+        let peers: HashMap<_, _> = peers
+            .into_iter()
+            .map(|contact_info| (contact_info.endpoint, contact_info))
+            .collect();
+        let removed: Vec<_> = self
+            .exchanged
+            .iter()
+            .chain(self.added.keys())
+            .filter(|endpoint| !peers.contains_key(endpoint))
+            .copied()
+            .collect();
+        for endpoint in removed {
+            self.remove(endpoint);
+        }
+        for contact_info in peers.into_values() {
+            self.add(contact_info);
+        }
+    }
+
+    /// Returns the next PEX payload, or `None` if it is too soon to send or there is nothing to
+    /// send.
+    ///
+    /// The caller is expected to send the returned payload, as it is considered exchanged.
+    pub fn take(&mut self, now: Instant) -> Option<PexPayload> {
+        if let Some(last_send) = self.last_send {
+            if now < last_send + Self::MIN_INTERVAL {
+                return None;
+            }
+        }
+        if self.added.is_empty() && self.dropped.is_empty() {
+            return None;
+        }
+
+        let added: Vec<_> = self.added.values().take(Self::MAX_PEERS).copied().collect();
+        for contact_info in &added {
+            self.added.remove(&contact_info.endpoint);
+            self.exchanged.insert(contact_info.endpoint);
+        }
+        let dropped: Vec<_> = self.dropped.iter().take(Self::MAX_PEERS).copied().collect();
+        for endpoint in &dropped {
+            self.dropped.remove(endpoint);
+            self.exchanged.remove(endpoint);
+        }
+
+        self.last_send = Some(now);
+        Some(PexPayload { added, dropped })
+    }
+}
+
+impl PexFilter {
+    pub fn load() -> Self {
+        Self::new(
+            *filter_unreachable(),
+            *filter_local(),
+            *filter_cross_family(),
+        )
+    }
+
+    pub fn new(filter_unreachable: bool, filter_local: bool, filter_cross_family: bool) -> Self {
+        Self {
+            filter_unreachable,
+            filter_local,
+            filter_cross_family,
+            banned: HashSet::new(),
+        }
+    }
+
+    /// Excludes the address from propagation, e.g., after its peer misbehaved.
+    pub fn ban(&mut self, ip: IpAddr) {
+        self.banned.insert(ip.to_canonical());
+    }
+
+    pub fn is_banned(&self, ip: IpAddr) -> bool {
+        self.banned.contains(&ip.to_canonical())
+    }
+
+    /// Returns true if we should propagate the peer to the remote peer.
+    pub fn accept(&self, remote_endpoint: SocketAddr, contact_info: &PeerContactInfo) -> bool {
+        let remote_ip = remote_endpoint.ip().to_canonical();
+        let ip = contact_info.endpoint.ip().to_canonical();
+        if self.is_banned(ip) {
+            return false;
+        }
+        if self.filter_unreachable && !contact_info.get_flag(PeerFlag::Reachable) {
+            return false;
+        }
+        if self.filter_local && is_local(ip) && !is_local(remote_ip) {
+            return false;
+        }
+        if self.filter_cross_family && ip.is_ipv4() != remote_ip.is_ipv4() {
+            return false;
+        }
+        true
+    }
+}
+
+/// Returns true if the address is not globally routable, e.g., a LAN address.
+fn is_local(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                // Shared address space (RFC 6598), which is used by carrier-grade NATs.
+                || (a == 100 && (b & 0xc0) == 64)
+        }
+        IpAddr::V6(ip) => {
+            let a = ip.segments()[0];
+            ip.is_loopback()
+                || ip.is_unspecified()
+                // Unique local (fc00::/7) and link-local (fe80::/10) addresses.
+                || (a & 0xfe00) == 0xfc00
+                || (a & 0xffc0) == 0xfe80
+        }
+    }
+}
+
+impl PexPayload {
+    pub fn encode(&self, buffer: &mut BytesMut) {
+        PeerExchange::encode(
+            self.added.iter().copied(),
+            self.dropped.iter().copied(),
+            buffer,
+        );
+    }
+}
+
 const ADDED_V4: &[u8] = b"added";
 const ADDED_V6: &[u8] = b"added6";
 const ADDED_FLAGS_V4: &[u8] = b"added.f";
@@ -277,6 +501,8 @@ fn insert_bytes<'a>(dict: &mut BTreeMap<&'a Bytes, own::Value>, key: &'a [u8], b
 mod tests {
     use hex_literal::hex;
 
+    use bittorrent_bencode::serde as serde_bencode;
+
     use super::*;
 
     #[test]
@@ -331,6 +557,9 @@ mod tests {
                 .map(|(key, value)| (Bytes::new(key), value.to_owned()))
                 .collect();
             assert_eq!(PeerExchange::try_from(decode), Ok(peer_exchange.clone()));
+            let mut buffer = BytesMut::new();
+            peer_exchange.encode_into(&mut buffer);
+            assert_eq!(buffer, serde_bencode::to_bytes(peer_exchange).unwrap());
             assert_eq!(BTreeMap::from(peer_exchange.clone()), encode);
         }
 
@@ -508,6 +737,43 @@ mod tests {
         );
     }
 
+    #[test]
+    fn mixed_family_round_trip() {
+        let added = [
+            PeerContactInfo::new("127.0.0.1:8001".parse().unwrap(), [].into_iter()),
+            PeerContactInfo::new(
+                "[::2]:8002".parse().unwrap(),
+                [PeerFlag::SupportUtp].into_iter(),
+            ),
+            PeerContactInfo::new(
+                "127.0.0.3:8003".parse().unwrap(),
+                [PeerFlag::PreferEncryption].into_iter(),
+            ),
+            PeerContactInfo::new(
+                "[::4]:8004".parse().unwrap(),
+                [PeerFlag::UploadOnly, PeerFlag::Reachable].into_iter(),
+            ),
+        ];
+        let dropped: [SocketAddr; 3] = [
+            "[::5]:8005".parse().unwrap(),
+            "127.0.0.6:8006".parse().unwrap(),
+            "[::7]:8007".parse().unwrap(),
+        ];
+
+        let mut buffer = BytesMut::new();
+        PeerExchange::encode(added.iter().copied(), dropped.iter().copied(), &mut buffer);
+        let peer_exchange: PeerExchange = serde_bencode::from_bytes(&buffer).unwrap();
+
+        assert_eq!(
+            peer_exchange.decode_added(),
+            Ok(vec![added[0], added[2], added[1], added[3]]),
+        );
+        assert_eq!(
+            peer_exchange.decode_dropped(),
+            Ok(vec![dropped[1], dropped[0], dropped[2]]),
+        );
+    }
+
     #[test]
     fn test_decode_endpoints() {
         fn test_ok(endpoints: &[u8], expect: Vec<SocketAddr>) {
@@ -552,4 +818,133 @@ mod tests {
             true,
         );
     }
+
+    fn ci(endpoint: &str) -> PeerContactInfo {
+        PeerContactInfo::new(endpoint.parse().unwrap(), iter::empty())
+    }
+
+    fn ep(endpoint: &str) -> SocketAddr {
+        endpoint.parse().unwrap()
+    }
+
+    fn sorted(mut payload: PexPayload) -> PexPayload {
+        payload
+            .added
+            .sort_by_key(|contact_info| contact_info.endpoint);
+        payload.dropped.sort();
+        payload
+    }
+
+    #[test]
+    fn pex_state() {
+        let t0 = Instant::now();
+        let t1 = t0 + PexState::MIN_INTERVAL;
+
+        let mut state = PexState::new();
+        assert_eq!(state.take(t0), None);
+
+        state.add(ci("127.0.0.1:8001"));
+        state.add(ci("127.0.0.1:8002"));
+        state.add(ci("127.0.0.1:8003"));
+        state.remove(ep("127.0.0.1:8003"));
+        state.remove(ep("127.0.0.1:8004"));
+        assert_eq!(
+            state.take(t0).map(sorted),
+            Some(PexPayload {
+                added: vec![ci("127.0.0.1:8001"), ci("127.0.0.1:8002")],
+                dropped: vec![],
+            }),
+        );
+        assert_eq!(state.take(t0), None);
+
+        state.remove(ep("127.0.0.1:8001"));
+        state.add(ci("127.0.0.1:8002"));
+        assert_eq!(state.take(t1 - Duration::from_secs(1)), None);
+        assert_eq!(
+            state.take(t1),
+            Some(PexPayload {
+                added: vec![],
+                dropped: vec![ep("127.0.0.1:8001")],
+            }),
+        );
+
+        // Removing and then re-adding an exchanged peer cancels out.
+        let t2 = t1 + PexState::MIN_INTERVAL;
+        state.remove(ep("127.0.0.1:8002"));
+        state.add(ci("127.0.0.1:8002"));
+        assert_eq!(state.take(t2), None);
+
+        state.update([ci("127.0.0.1:8001"), ci("127.0.0.1:8003")]);
+        assert_eq!(
+            state.take(t2).map(sorted),
+            Some(PexPayload {
+                added: vec![ci("127.0.0.1:8001"), ci("127.0.0.1:8003")],
+                dropped: vec![ep("127.0.0.1:8002")],
+            }),
+        );
+    }
+
+    #[test]
+    fn pex_state_max_peers() {
+        let t0 = Instant::now();
+        let t1 = t0 + PexState::MIN_INTERVAL;
+
+        let mut state = PexState::new();
+        state.update((0..60).map(|i| ci(&format!("127.0.0.1:{}", 8000 + i))));
+        let payload = state.take(t0).unwrap();
+        assert_eq!(payload.added.len(), PexState::MAX_PEERS);
+        assert_eq!(payload.dropped.len(), 0);
+
+        state.update([]);
+        let payload = state.take(t1).unwrap();
+        assert_eq!(payload.added.len(), 0);
+        assert_eq!(payload.dropped.len(), PexState::MAX_PEERS);
+
+        assert_eq!(state.take(t1 + PexState::MIN_INTERVAL), None);
+    }
+
+    #[test]
+    fn pex_filter() {
+        let global = ep("1.2.3.4:8000");
+        let local = ep("192.168.0.1:8000");
+        let reachable = PeerContactInfo::new(ep("5.6.7.8:8001"), [PeerFlag::Reachable].into_iter());
+
+        let filter = PexFilter::new(false, false, false);
+        for contact_info in [
+            ci("5.6.7.8:8001"),
+            ci("10.0.0.1:8001"),
+            ci("[2001:db8::1]:8001"),
+        ] {
+            assert!(filter.accept(global, &contact_info));
+        }
+
+        let mut filter = PexFilter::new(true, false, false);
+        assert!(!filter.accept(global, &ci("5.6.7.8:8001")));
+        assert!(filter.accept(global, &reachable));
+        filter.ban("::ffff:5.6.7.8".parse().unwrap());
+        assert!(filter.is_banned("5.6.7.8".parse().unwrap()));
+        assert!(!filter.accept(global, &reachable));
+
+        let filter = PexFilter::new(false, true, false);
+        for peer in [
+            "10.0.0.1:8001",
+            "127.0.0.1:8001",
+            "169.254.0.1:8001",
+            "100.64.0.1:8001",
+            "[fd00::1]:8001",
+            "[fe80::1]:8001",
+            "[::ffff:192.168.0.2]:8001",
+        ] {
+            assert!(!filter.accept(global, &ci(peer)), "{peer}");
+            assert!(filter.accept(local, &ci(peer)), "{peer}");
+        }
+        assert!(filter.accept(global, &ci("100.128.0.1:8001")));
+        assert!(filter.accept(global, &ci("[2001:db8::1]:8001")));
+
+        let filter = PexFilter::new(false, false, true);
+        assert!(filter.accept(global, &ci("5.6.7.8:8001")));
+        assert!(filter.accept(global, &ci("[::ffff:5.6.7.8]:8001")));
+        assert!(!filter.accept(global, &ci("[2001:db8::1]:8001")));
+        assert!(!filter.accept(ep("[2001:db8::2]:8000"), &ci("5.6.7.8:8001")));
+    }
 }
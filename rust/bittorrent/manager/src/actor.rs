@@ -1,14 +1,16 @@
 use std::collections::{btree_map::Entry, BTreeMap, HashMap};
 use std::future::Future;
 use std::io::Error;
+use std::net::IpAddr;
 use std::sync::{Arc, Mutex};
 
-use tokio::sync::{broadcast::Sender, mpsc::UnboundedReceiver};
+use tokio::sync::broadcast::Sender;
 use tokio::task::Id;
 
 use g1_base::fmt::{DebugExt, InsertPlaceholder};
 use g1_base::future::ReadyQueue;
 use g1_base::sync::MutexExt;
+use g1_tokio::sync::metered::mpsc::UnboundedReceiver;
 use g1_tokio::task::{Cancel, JoinQueue};
 
 use bittorrent_base::{InfoHash, PeerId};
@@ -17,20 +19,26 @@ use bittorrent_utp::UtpConnector;
 
 use crate::{
     net::{Connector, Listener},
-    Endpoint, Socket, Update,
+    Connection, Endpoint, Hint, Preference, Socket, Update,
 };
 
 #[derive(DebugExt)]
 pub(crate) struct Actor {
     cancel: Cancel,
 
-    connect_recv: UnboundedReceiver<(Endpoint, Option<PeerId>)>,
+    connect_recv: UnboundedReceiver<(Endpoint, Option<PeerId>, Hint)>,
+    #[allow(clippy::type_complexity)]
     #[debug(with = InsertPlaceholder)]
-    connected_futures: ReadyQueue<(Endpoint, Connector, Result<Socket, Error>)>,
+    connected_futures: ReadyQueue<(Endpoint, Connector, Result<(Socket, Preference), Error>)>,
 
     listener: Listener,
+    #[allow(clippy::type_complexity)]
     #[debug(with = InsertPlaceholder)]
-    accepted_futures: ReadyQueue<(Endpoint, Option<Endpoint>, Result<Socket, Error>)>,
+    accepted_futures: ReadyQueue<(
+        Endpoint,
+        Option<Endpoint>,
+        Result<(Socket, Preference), Error>,
+    )>,
 
     #[debug(with = InsertPlaceholder)]
     socket_shutdown: ReadyQueue<()>,
@@ -45,6 +53,9 @@ pub(crate) struct Actor {
 #[derive(Debug)]
 pub(crate) struct Peers {
     info_hash: InfoHash,
+    self_id: PeerId,
+    bind_ip_ipv4: Option<IpAddr>,
+    bind_ip_ipv6: Option<IpAddr>,
     utp_connector_ipv4: Option<UtpConnector>,
     utp_connector_ipv6: Option<UtpConnector>,
     sends: Sends,
@@ -58,6 +69,7 @@ pub(crate) struct Peers {
 
     // Use `BTreeMap` for the same reason above.
     peers: BTreeMap<Endpoint, Peer>,
+    connections: HashMap<Endpoint, Connection>,
     // Only for `remove_by_id`.
     peer_endpoints: HashMap<Id, Endpoint>,
 }
@@ -68,7 +80,7 @@ struct ConnectorInUse;
 impl Actor {
     pub(crate) fn new(
         cancel: Cancel,
-        connect_recv: UnboundedReceiver<(Endpoint, Option<PeerId>)>,
+        connect_recv: UnboundedReceiver<(Endpoint, Option<PeerId>, Hint)>,
         listener: Listener,
         peers: Arc<Mutex<Peers>>,
         update_send: Sender<(Endpoint, Update)>,
@@ -94,8 +106,8 @@ impl Actor {
                 () = self.cancel.wait() => break,
 
                 peer_endpoint = self.connect_recv.recv() => {
-                    let Some((peer_endpoint, peer_id)) = peer_endpoint else { break };
-                    self.handle_connect(peer_endpoint, peer_id);
+                    let Some((peer_endpoint, peer_id, hint)) = peer_endpoint else { break };
+                    self.handle_connect(peer_endpoint, peer_id, hint);
                 }
                 connected = self.connected_futures.pop_ready() => {
                     self.handle_connected(connected.unwrap());
@@ -126,7 +138,7 @@ impl Actor {
     }
 
     #[tracing::instrument(name = "mgr/connect", skip(self))]
-    fn handle_connect(&self, peer_endpoint: Endpoint, peer_id: Option<PeerId>) {
+    fn handle_connect(&self, peer_endpoint: Endpoint, peer_id: Option<PeerId>, hint: Hint) {
         let mut connector = {
             let mut peers = self.peers.must_lock();
             if peers.contains(peer_endpoint) {
@@ -144,6 +156,7 @@ impl Actor {
         if peer_id.is_some() {
             connector.set_peer_id(peer_id);
         }
+        connector.apply_hint(hint);
         assert!(self
             .connected_futures
             .push(async move {
@@ -156,13 +169,25 @@ impl Actor {
     #[tracing::instrument(name = "mgr/connect", fields(?peer_endpoint), skip_all)]
     fn handle_connected(
         &self,
-        (peer_endpoint, connector, socket): (Endpoint, Connector, Result<Socket, Error>),
+        (peer_endpoint, connector, socket): (
+            Endpoint,
+            Connector,
+            Result<(Socket, Preference), Error>,
+        ),
     ) {
         let guard = {
             let mut peers = self.peers.must_lock();
             peers.return_connector(peer_endpoint, connector);
             match socket {
-                Ok(socket) => peers.spawn(peer_endpoint, socket),
+                Ok((socket, (transport, cipher))) => peers.spawn(
+                    peer_endpoint,
+                    socket,
+                    Connection {
+                        transport,
+                        cipher,
+                        outgoing: true,
+                    },
+                ),
                 Err(error) => {
                     // Log it at debug level since its cause has already been logged by `connect`.
                     tracing::debug!(%error, "peer socket connect error");
@@ -178,7 +203,7 @@ impl Actor {
         (peer_endpoint, peer_listening_endpoint, socket): (
             Endpoint,
             Option<Endpoint>,
-            impl Future<Output = Result<Socket, Error>> + Send + 'static,
+            impl Future<Output = Result<(Socket, Preference), Error>> + Send + 'static,
         ),
     ) {
         assert!(self
@@ -193,10 +218,10 @@ impl Actor {
         (peer_endpoint, peer_listening_endpoint, socket): (
             Endpoint,
             Option<Endpoint>,
-            Result<Socket, Error>,
+            Result<(Socket, Preference), Error>,
         ),
     ) {
-        let socket = match socket {
+        let (socket, (transport, cipher)) = match socket {
             Ok(socket) => socket,
             Err(error) => {
                 tracing::warn!(%error, "peer socket accept error");
@@ -209,7 +234,15 @@ impl Actor {
             if let Some(peer_listening_endpoint) = peer_listening_endpoint {
                 peers.insert_connector(peer_listening_endpoint);
             }
-            peers.spawn(peer_endpoint, socket)
+            peers.spawn(
+                peer_endpoint,
+                socket,
+                Connection {
+                    transport,
+                    cipher,
+                    outgoing: false,
+                },
+            )
         };
         self.handle_peer_start(peer_endpoint, guard);
     }
@@ -263,17 +296,24 @@ impl Actor {
 impl Peers {
     pub(crate) fn new(
         info_hash: InfoHash,
+        self_id: PeerId,
+        bind_ip_ipv4: Option<IpAddr>,
+        bind_ip_ipv6: Option<IpAddr>,
         utp_connector_ipv4: Option<UtpConnector>,
         utp_connector_ipv6: Option<UtpConnector>,
         sends: Sends,
     ) -> Self {
         Self {
             info_hash,
+            self_id,
+            bind_ip_ipv4,
+            bind_ip_ipv6,
             utp_connector_ipv4,
             utp_connector_ipv6,
             sends,
             connectors: BTreeMap::new(),
             peers: BTreeMap::new(),
+            connections: HashMap::new(),
             peer_endpoints: HashMap::new(),
         }
     }
@@ -306,7 +346,10 @@ impl Peers {
     fn new_connector(&self, peer_endpoint: Endpoint) -> Connector {
         Connector::new(
             self.info_hash.clone(),
+            self.self_id.clone(),
             peer_endpoint,
+            self.bind_ip_ipv4,
+            self.bind_ip_ipv6,
             self.utp_connector_ipv4.clone(),
             self.utp_connector_ipv6.clone(),
         )
@@ -334,7 +377,16 @@ impl Peers {
         self.peers.get(&peer_endpoint).cloned()
     }
 
-    fn spawn(&mut self, peer_endpoint: Endpoint, socket: Socket) -> Result<PeerGuard, Socket> {
+    pub(crate) fn connection(&self, peer_endpoint: Endpoint) -> Option<Connection> {
+        self.connections.get(&peer_endpoint).copied()
+    }
+
+    fn spawn(
+        &mut self,
+        peer_endpoint: Endpoint,
+        socket: Socket,
+        connection: Connection,
+    ) -> Result<PeerGuard, Socket> {
         match self.peers.entry(peer_endpoint) {
             Entry::Occupied(_) => Err(socket),
             Entry::Vacant(entry) => {
@@ -344,6 +396,7 @@ impl Peers {
                     .insert(guard.id(), peer_endpoint)
                     .is_none());
                 entry.insert(peer);
+                self.connections.insert(peer_endpoint, connection);
                 Ok(guard)
             }
         }
@@ -352,6 +405,7 @@ impl Peers {
     fn remove_by_id(&mut self, id: Id) -> Endpoint {
         let peer_endpoint = self.peer_endpoints.remove(&id).unwrap();
         self.peers.remove(&peer_endpoint).unwrap();
+        self.connections.remove(&peer_endpoint).unwrap();
         peer_endpoint
     }
 }
@@ -2,7 +2,7 @@ use std::io;
 
 use snafu::prelude::*;
 
-use crate::{Endpoint, Transport};
+use crate::{Cipher, Endpoint, Transport};
 
 #[derive(Clone, Debug, Eq, PartialEq, Snafu)]
 pub enum Error {
@@ -16,6 +16,9 @@ pub enum Error {
     },
     #[snafu(display("peer unreachable: {peer_endpoint:?}"))]
     Unreachable { peer_endpoint: Endpoint },
+
+    #[snafu(display("cipher is not accepted: {cipher:?}"))]
+    CipherNotAccepted { cipher: Cipher },
 }
 
 impl From<Error> for io::Error {
@@ -25,6 +28,7 @@ impl From<Error> for io::Error {
                 Error::UtpNotEnabled { .. } => io::ErrorKind::Other,
                 Error::ConnectTimeout { .. } => io::ErrorKind::TimedOut,
                 Error::Unreachable { .. } => io::ErrorKind::ConnectionRefused,
+                Error::CipherNotAccepted { .. } => io::ErrorKind::ConnectionAborted,
             },
             error,
         )
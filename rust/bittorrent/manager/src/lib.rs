@@ -1,5 +1,7 @@
 pub mod error;
 
+pub mod slot;
+
 mod actor;
 mod manager;
 mod net;
@@ -7,7 +9,10 @@ mod net;
 use std::net::SocketAddr;
 use std::time::Duration;
 
+use serde::Deserialize;
+
 use g1_tokio::io::DynStream;
+use g1_tokio::net::tcp::TcpConfig;
 
 g1_param::define!(update_queue_size: usize = 256);
 
@@ -16,6 +21,16 @@ g1_param::define!(
     parse = g1_param::parse::duration;
 );
 
+// Applied to both outgoing and accepted peer TCP connections.
+g1_param::define!(tcp_config: TcpConfig = Default::default());
+
+// Applied to accepted peer connections of both transports.
+g1_param::define!(accept_policy: AcceptPolicy = Default::default());
+
+// Extra weight of a torrent that misses all of its data, relative to a seeding torrent, in
+// connection slot allocation.
+g1_param::define!(leech_weight: f64 = 3.0);
+
 pub use crate::manager::{Manager, ManagerGuard};
 
 pub type Preference = (Transport, Cipher);
@@ -32,6 +47,45 @@ pub enum Cipher {
     Plaintext,
 }
 
+/// Ciphers that we accept on inbound peer connections.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AcceptPolicy {
+    /// Accept both MSE and plaintext handshakes.
+    #[default]
+    Any,
+    /// Reject plaintext handshakes, including MSE handshakes that negotiate plaintext.
+    RequireMse,
+    /// Reject MSE handshakes.
+    Plaintext,
+}
+
+impl AcceptPolicy {
+    pub(crate) fn allow(self, cipher: Cipher) -> bool {
+        match self {
+            Self::Any => true,
+            Self::RequireMse => cipher == Cipher::Mse,
+            Self::Plaintext => cipher == Cipher::Plaintext,
+        }
+    }
+}
+
+/// Describes how a peer connection was established.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Connection {
+    pub transport: Transport,
+    pub cipher: Cipher,
+    /// True if we connected to the peer, which implies that the peer is reachable.
+    pub outgoing: bool,
+}
+
+/// Hints about a peer that we have not connected to, e.g., PEX peer flags (BEP 11).
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Hint {
+    pub prefer_encryption: bool,
+    pub support_utp: bool,
+}
+
 // NOTE: For now, we use the peer endpoint to uniquely identify a peer, regardless of the transport
 // layer protocol (TCP vs uTP) used by the peer.
 pub type Endpoint = SocketAddr;
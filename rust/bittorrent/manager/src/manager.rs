@@ -3,13 +3,11 @@ use std::sync::{Arc, Mutex};
 
 use tokio::{
     net::TcpListener,
-    sync::{
-        broadcast::{self, Sender},
-        mpsc::{self, UnboundedSender},
-    },
+    sync::broadcast::{self, Sender},
 };
 
 use g1_base::sync::MutexExt;
+use g1_tokio::sync::metered::mpsc::{self, UnboundedSender};
 use g1_tokio::task::JoinGuard;
 
 use bittorrent_base::{InfoHash, PeerId};
@@ -19,12 +17,12 @@ use bittorrent_utp::UtpSocket;
 use crate::{
     actor::{Actor, Peers},
     net::Listener,
-    Endpoint, Update,
+    Connection, Endpoint, Hint, Update,
 };
 
 #[derive(Clone, Debug)]
 pub struct Manager {
-    connect_send: UnboundedSender<(Endpoint, Option<PeerId>)>,
+    connect_send: UnboundedSender<(Endpoint, Option<PeerId>, Hint)>,
     peers: Arc<Mutex<Peers>>,
     update_send: Sender<(Endpoint, Update)>,
 }
@@ -34,17 +32,32 @@ pub type ManagerGuard = JoinGuard<Result<(), Error>>;
 impl Manager {
     pub fn spawn(
         info_hash: InfoHash,
+        self_id: PeerId,
         tcp_listener_ipv4: Option<TcpListener>,
         tcp_listener_ipv6: Option<TcpListener>,
         utp_socket_ipv4: Option<&UtpSocket>,
         utp_socket_ipv6: Option<&UtpSocket>,
     ) -> (Self, Recvs, ManagerGuard) {
-        tracing::info!(self_id = ?bittorrent_base::self_id());
-
-        let (connect_send, connect_recv) = mpsc::unbounded_channel();
+        tracing::info!(?self_id);
+
+        let (connect_send, connect_recv) = mpsc::unbounded_channel("bittorrent/mgr-connect");
+
+        // Outgoing connections bind to the same local address as the listener unless it is the
+        // unspecified address.
+        let bind_ip = |listener: &Option<TcpListener>| {
+            listener
+                .as_ref()?
+                .local_addr()
+                .ok()
+                .map(|endpoint| endpoint.ip())
+                .filter(|ip| !ip.is_unspecified())
+        };
+        let bind_ip_ipv4 = bind_ip(&tcp_listener_ipv4);
+        let bind_ip_ipv6 = bind_ip(&tcp_listener_ipv6);
 
         let listener = Listener::new(
             info_hash.clone(),
+            self_id.clone(),
             tcp_listener_ipv4,
             tcp_listener_ipv6,
             utp_socket_ipv4.map(UtpSocket::listener),
@@ -55,6 +68,9 @@ impl Manager {
 
         let peers = Arc::new(Mutex::new(Peers::new(
             info_hash,
+            self_id,
+            bind_ip_ipv4,
+            bind_ip_ipv6,
             utp_socket_ipv4.map(UtpSocket::connector),
             utp_socket_ipv6.map(UtpSocket::connector),
             sends,
@@ -85,7 +101,11 @@ impl Manager {
     }
 
     pub fn connect(&self, peer_endpoint: Endpoint, peer_id: Option<PeerId>) {
-        let _ = self.connect_send.send((peer_endpoint, peer_id));
+        self.connect_with_hint(peer_endpoint, peer_id, Hint::default());
+    }
+
+    pub fn connect_with_hint(&self, peer_endpoint: Endpoint, peer_id: Option<PeerId>, hint: Hint) {
+        let _ = self.connect_send.send((peer_endpoint, peer_id, hint));
     }
 
     pub fn peer_endpoints(&self) -> Vec<Endpoint> {
@@ -100,6 +120,10 @@ impl Manager {
         self.peers.must_lock().get(peer_endpoint)
     }
 
+    pub fn connection(&self, peer_endpoint: Endpoint) -> Option<Connection> {
+        self.peers.must_lock().connection(peer_endpoint)
+    }
+
     pub fn subscribe(&self) -> broadcast::Receiver<(Endpoint, Update)> {
         self.update_send.subscribe()
     }
@@ -1,5 +1,6 @@
 use std::future::Future;
 use std::io::{Error, ErrorKind};
+use std::net::{IpAddr, SocketAddr};
 use std::time::Duration;
 
 use futures::future::{BoxFuture, FutureExt, OptionFuture};
@@ -12,14 +13,14 @@ use tracing::{field, Instrument, Span};
 use g1_tokio::{
     bstream::{StreamRecv, StreamSend},
     io::DynStream,
-    net::tcp::TcpStream,
+    net::{socks5, tcp::TcpStream},
 };
 
 use bittorrent_base::{Features, InfoHash, PeerId};
 use bittorrent_mse::MseStream;
 use bittorrent_utp::{UtpConnector, UtpListener};
 
-use crate::{error, Cipher, Endpoint, Preference, Socket, Transport};
+use crate::{error, AcceptPolicy, Cipher, Endpoint, Hint, Preference, Socket, Transport};
 
 type Prefs = [Preference; 4];
 
@@ -32,8 +33,13 @@ pub(crate) struct Connector {
     peer_endpoint: Endpoint,
 
     prefs: Prefs,
+    // Once we have connected to the peer, `prefs` reflects what works, and we ignore hints.
+    connected: bool,
 
     connect_timeout: Duration,
+    // Local addresses that outgoing TCP connections bind to.
+    bind_ip_ipv4: Option<IpAddr>,
+    bind_ip_ipv6: Option<IpAddr>,
     utp_connector_ipv4: Option<UtpConnector>,
     utp_connector_ipv6: Option<UtpConnector>,
 }
@@ -43,6 +49,7 @@ pub(crate) struct Listener {
     info_hash: InfoHash,
     self_id: PeerId,
     self_features: Features,
+    accept_policy: AcceptPolicy,
 
     tcp_listener_ipv4: Option<TcpListener>,
     tcp_listener_ipv6: Option<TcpListener>,
@@ -60,27 +67,35 @@ impl Connector {
 
     pub(crate) fn new(
         info_hash: InfoHash,
+        self_id: PeerId,
         peer_endpoint: Endpoint,
+        bind_ip_ipv4: Option<IpAddr>,
+        bind_ip_ipv6: Option<IpAddr>,
         utp_connector_ipv4: Option<UtpConnector>,
         utp_connector_ipv6: Option<UtpConnector>,
     ) -> Self {
         Self::with_param(
             info_hash,
-            bittorrent_base::self_id().clone(),
+            self_id,
             Features::load(),
             peer_endpoint,
             *crate::connect_timeout(),
+            bind_ip_ipv4,
+            bind_ip_ipv6,
             utp_connector_ipv4,
             utp_connector_ipv6,
         )
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn with_param(
         info_hash: InfoHash,
         self_id: PeerId,
         self_features: Features,
         peer_endpoint: Endpoint,
         connect_timeout: Duration,
+        bind_ip_ipv4: Option<IpAddr>,
+        bind_ip_ipv6: Option<IpAddr>,
         utp_connector_ipv4: Option<UtpConnector>,
         utp_connector_ipv6: Option<UtpConnector>,
     ) -> Self {
@@ -91,7 +106,10 @@ impl Connector {
             peer_id: None,
             peer_endpoint,
             prefs: Self::DEFAULT_PREFS,
+            connected: false,
             connect_timeout,
+            bind_ip_ipv4,
+            bind_ip_ipv6,
             utp_connector_ipv4,
             utp_connector_ipv6,
         }
@@ -101,7 +119,20 @@ impl Connector {
         self.peer_id = peer_id;
     }
 
-    pub(crate) async fn connect(&mut self) -> Result<Socket, Error> {
+    pub(crate) fn apply_hint(&mut self, hint: Hint) {
+        if self.connected {
+            return;
+        }
+        // `sort_by_key` is stable; therefore, the current order is preserved among equal keys.
+        self.prefs.sort_by_key(|&(transport, cipher)| {
+            (
+                hint.prefer_encryption && cipher != Cipher::Mse,
+                hint.support_utp && transport != Transport::Utp,
+            )
+        });
+    }
+
+    pub(crate) async fn connect(&mut self) -> Result<(Socket, Preference), Error> {
         for (i, (transport, cipher)) in self.prefs.iter().copied().enumerate() {
             let result = self
                 .try_connect(transport, cipher)
@@ -137,9 +168,10 @@ impl Connector {
                     peer_endpoint = ?self.peer_endpoint,
                 ))
                 .await;
-            if result.is_ok() {
+            if let Ok(socket) = result {
                 self.prefs[0..=i].rotate_right(1);
-                return result;
+                self.connected = true;
+                return Ok((socket, (transport, cipher)));
             }
         }
         Err(error::Error::Unreachable {
@@ -179,19 +211,37 @@ impl Connector {
     }
 
     async fn tcp_connect(&self) -> Result<DynStream<'static>, Error> {
-        let socket = if self.peer_endpoint.is_ipv4() {
-            TcpSocket::new_v4()
+        let proxy = *bittorrent_base::socks5_proxy();
+        let endpoint = proxy.unwrap_or(self.peer_endpoint);
+        let (socket, bind_ip) = if endpoint.is_ipv4() {
+            (TcpSocket::new_v4()?, self.bind_ip_ipv4)
         } else {
-            assert!(self.peer_endpoint.is_ipv6());
-            TcpSocket::new_v6()
-        }?;
+            assert!(endpoint.is_ipv6());
+            (TcpSocket::new_v6()?, self.bind_ip_ipv6)
+        };
         socket.set_reuseaddr(true)?;
-        Ok(Box::new(TcpStream::from(
-            socket.connect(self.peer_endpoint).await?,
-        )))
+        crate::tcp_config().apply(&socket)?;
+        if let Some(interface) = bittorrent_base::bind_interface() {
+            g1_tokio::net::bind_device(&socket, interface)?;
+        }
+        if let Some(bind_ip) = bind_ip {
+            socket.bind(SocketAddr::new(bind_ip, 0))?;
+        }
+        let mut stream = socket.connect(endpoint).await?;
+        if proxy.is_some() {
+            socks5::connect(&mut stream, &self.peer_endpoint.into()).await?;
+        }
+        Ok(Box::new(TcpStream::from(stream)))
     }
 
     async fn utp_connect(&self) -> Result<DynStream<'static>, Error> {
+        // Do not bypass the proxy.
+        if bittorrent_base::socks5_proxy().is_some() {
+            return Err(error::Error::UtpNotEnabled {
+                peer_endpoint: self.peer_endpoint,
+            }
+            .into());
+        }
         let connector = if self.peer_endpoint.is_ipv4() {
             self.utp_connector_ipv4.as_ref()
         } else {
@@ -208,6 +258,7 @@ impl Connector {
 impl Listener {
     pub(crate) fn new(
         info_hash: InfoHash,
+        self_id: PeerId,
         tcp_listener_ipv4: Option<TcpListener>,
         tcp_listener_ipv6: Option<TcpListener>,
         utp_listener_ipv4: Option<UtpListener>,
@@ -215,8 +266,9 @@ impl Listener {
     ) -> Self {
         Self::with_param(
             info_hash,
-            bittorrent_base::self_id().clone(),
+            self_id,
             Features::load(),
+            *crate::accept_policy(),
             tcp_listener_ipv4,
             tcp_listener_ipv6,
             utp_listener_ipv4,
@@ -224,10 +276,12 @@ impl Listener {
         )
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn with_param(
         info_hash: InfoHash,
         self_id: PeerId,
         self_features: Features,
+        accept_policy: AcceptPolicy,
         tcp_listener_ipv4: Option<TcpListener>,
         tcp_listener_ipv6: Option<TcpListener>,
         utp_listener_ipv4: Option<UtpListener>,
@@ -243,6 +297,7 @@ impl Listener {
             info_hash,
             self_id,
             self_features,
+            accept_policy,
             tcp_listener_ipv4,
             tcp_listener_ipv6,
             utp_listener_ipv4,
@@ -256,7 +311,7 @@ impl Listener {
         (
             Endpoint,
             Option<Endpoint>,
-            impl Future<Output = Result<Socket, Error>> + Send + 'static,
+            impl Future<Output = Result<(Socket, Preference), Error>> + Send + 'static,
         ),
         Error,
     > {
@@ -269,6 +324,9 @@ impl Listener {
         macro_rules! tcp_handshake {
             ($stream:ident $(,)?) => {{
                 let (stream, peer_endpoint) = $stream?;
+                if let Err(error) = crate::tcp_config().apply(&stream) {
+                    tracing::warn!(?peer_endpoint, %error, "tcp config");
+                }
                 (
                     Transport::Tcp,
                     peer_endpoint,
@@ -276,6 +334,8 @@ impl Listener {
                         self.info_hash.clone(),
                         self.self_id.clone(),
                         self.self_features,
+                        self.accept_policy,
+                        Transport::Tcp,
                         TcpStream::from(stream),
                     )),
                 )
@@ -292,6 +352,8 @@ impl Listener {
                         self.info_hash.clone(),
                         self.self_id.clone(),
                         self.self_features,
+                        self.accept_policy,
+                        Transport::Utp,
                         stream,
                     )),
                 )
@@ -301,7 +363,7 @@ impl Listener {
         let (transport, peer_endpoint, handshake): (
             _,
             _,
-            BoxFuture<'static, Result<Socket, Error>>,
+            BoxFuture<'static, Result<(Socket, Preference), Error>>,
         ) = tokio::select! {
             Some(stream) = accept!(tcp_listener_ipv4) => tcp_handshake!(stream),
             Some(stream) = accept!(tcp_listener_ipv6) => tcp_handshake!(stream),
@@ -330,11 +392,16 @@ impl Listener {
         info_hash: InfoHash,
         self_id: PeerId,
         self_features: Features,
+        accept_policy: AcceptPolicy,
+        transport: Transport,
         stream: Stream,
-    ) -> Result<Socket, Error>
+    ) -> Result<(Socket, Preference), Error>
     where
         Stream: StreamRecv<Error = Error> + StreamSend<Error = Error> + Send + 'static,
     {
+        // `bittorrent_mse::accept` sniffs the plaintext BitTorrent handshake (under its handshake
+        // timeout) and leaves it in the receive buffer without responding to it, so a single
+        // listening port serves both, and we may still reject a plaintext handshake silently.
         let stream = bittorrent_mse::accept(stream, info_hash.as_ref()).await?;
 
         let cipher = match stream {
@@ -342,6 +409,8 @@ impl Listener {
             MseStream::Plaintext(_) => Cipher::Plaintext,
         };
         Span::current().record("cipher", field::debug(&cipher));
+        // An MSE handshake may negotiate plaintext.
+        ensure_accept(accept_policy, cipher)?;
 
         // TODO: Should we look up the peer id if we have connected to this peer before?
         let peer_id = None;
@@ -349,5 +418,34 @@ impl Listener {
         Socket::accept(stream.into(), info_hash, self_id, self_features, peer_id)
             .await
             .inspect(|socket| tracing::debug!(peer_id = ?socket.peer_id()))
+            .map(|socket| (socket, (transport, cipher)))
+    }
+}
+
+fn ensure_accept(accept_policy: AcceptPolicy, cipher: Cipher) -> Result<(), Error> {
+    if accept_policy.allow(cipher) {
+        Ok(())
+    } else {
+        Err(error::Error::CipherNotAccepted { cipher }.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accept_policy() {
+        for (accept_policy, mse, plaintext) in [
+            (AcceptPolicy::Any, true, true),
+            (AcceptPolicy::RequireMse, true, false),
+            (AcceptPolicy::Plaintext, false, true),
+        ] {
+            assert_eq!(ensure_accept(accept_policy, Cipher::Mse).is_ok(), mse);
+            assert_eq!(
+                ensure_accept(accept_policy, Cipher::Plaintext).is_ok(),
+                plaintext,
+            );
+        }
     }
 }
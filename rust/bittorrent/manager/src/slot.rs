@@ -0,0 +1,181 @@
+//! Connection Slot Allocation
+//!
+//! When a process runs multiple torrents, each of which has its own `Manager`, the torrents share
+//! a global limit on peer connections.  Rather than serving them first-come-first-served, we
+//! allocate the connection slots in proportion to their need, and periodically rebalance by
+//! disconnecting peers of over-served torrents to make room for under-served ones.
+//!
+//! NOTE: For now, this module only provides the allocation policy.  A `Manager` does not enforce
+//! a quota on its own; it is up to the caller to stop connecting to new peers beyond the quota,
+//! and to choose which peers to disconnect (e.g., those from which we receive the least data).
+
+/// How much a torrent needs peer connections.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Demand {
+    /// Fraction of the torrent data that we are missing, between 0 and 1.
+    pub missing: f64,
+    /// Number of peers of the swarm that we know of, which caps the slots of the torrent.
+    pub swarm_size: usize,
+    /// Number of peers that we are connected to.
+    pub num_connected: usize,
+}
+
+impl Demand {
+    fn weight(&self) -> f64 {
+        1.0 + *crate::leech_weight() * self.missing.clamp(0.0, 1.0)
+    }
+}
+
+/// Allocates `capacity` connection slots across torrents in proportion to their weights, where a
+/// torrent never receives more slots than its swarm size, and the slots it leaves unused are
+/// redistributed to the others.
+pub fn allocate(capacity: usize, demands: &[Demand]) -> Vec<usize> {
+    let mut quotas = vec![0; demands.len()];
+    let mut remaining = capacity;
+    let mut active: Vec<usize> = (0..demands.len())
+        .filter(|&i| demands[i].swarm_size > 0)
+        .collect();
+    while remaining > 0 && !active.is_empty() {
+        let total_weight: f64 = active.iter().map(|&i| demands[i].weight()).sum();
+        let mut allocated = 0;
+        for &i in &active {
+            let share = (remaining as f64 * demands[i].weight() / total_weight).floor() as usize;
+            let share = share
+                .min(demands[i].swarm_size - quotas[i])
+                .min(remaining - allocated);
+            quotas[i] += share;
+            allocated += share;
+        }
+        // Hand out the remainder one slot at a time, starting from the neediest torrent.
+        if allocated == 0 {
+            active.sort_by(|&i, &j| demands[j].weight().total_cmp(&demands[i].weight()));
+            for &i in active.iter().take(remaining) {
+                quotas[i] += 1;
+                allocated += 1;
+            }
+        }
+        remaining -= allocated;
+        active.retain(|&i| quotas[i] < demands[i].swarm_size);
+    }
+    quotas
+}
+
+/// Returns the number of peers to disconnect from each torrent, so that the under-served torrents
+/// may connect to up to their quotas.
+///
+/// It does not disconnect more peers than necessary, taking them from the most over-served
+/// torrents first.
+pub fn rebalance(capacity: usize, demands: &[Demand]) -> Vec<usize> {
+    let quotas = allocate(capacity, demands);
+    let num_connected: usize = demands.iter().map(|demand| demand.num_connected).sum();
+    let deficit: usize = demands
+        .iter()
+        .zip(&quotas)
+        .map(|(demand, quota)| quota.saturating_sub(demand.num_connected))
+        .sum();
+    let mut num_to_free = deficit.saturating_sub(capacity.saturating_sub(num_connected));
+
+    let mut excesses: Vec<_> = demands
+        .iter()
+        .zip(&quotas)
+        .map(|(demand, quota)| demand.num_connected.saturating_sub(*quota))
+        .collect();
+    let mut disconnects = vec![0; demands.len()];
+    while num_to_free > 0 {
+        let Some((i, _)) = excesses
+            .iter()
+            .enumerate()
+            .filter(|(_, excess)| **excess > 0)
+            .max_by_key(|(i, excess)| (**excess, std::cmp::Reverse(*i)))
+        else {
+            break;
+        };
+        excesses[i] -= 1;
+        disconnects[i] += 1;
+        num_to_free -= 1;
+    }
+    disconnects
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn d(missing: f64, swarm_size: usize, num_connected: usize) -> Demand {
+        Demand {
+            missing,
+            swarm_size,
+            num_connected,
+        }
+    }
+
+    #[test]
+    fn allocate() {
+        assert_eq!(super::allocate(10, &[]), Vec::<usize>::new());
+        assert_eq!(super::allocate(0, &[d(1.0, 100, 0)]), vec![0]);
+        assert_eq!(super::allocate(10, &[d(1.0, 100, 0)]), vec![10]);
+        assert_eq!(super::allocate(10, &[d(1.0, 4, 0)]), vec![4]);
+        assert_eq!(super::allocate(10, &[d(1.0, 0, 0)]), vec![0]);
+
+        // Leeching torrents weigh 4 times as much as seeding ones.
+        assert_eq!(
+            super::allocate(50, &[d(1.0, 100, 0), d(0.0, 100, 0)]),
+            vec![40, 10],
+        );
+        assert_eq!(
+            super::allocate(35, &[d(0.5, 100, 0), d(0.0, 100, 0)]),
+            vec![25, 10],
+        );
+        // Unused slots are redistributed.
+        assert_eq!(
+            super::allocate(50, &[d(1.0, 5, 0), d(0.0, 100, 0)]),
+            vec![5, 45],
+        );
+        // The remainder goes to the neediest torrents.
+        assert_eq!(
+            super::allocate(3, &[d(0.0, 100, 0), d(1.0, 100, 0), d(0.0, 100, 0)]),
+            vec![0, 3, 0],
+        );
+        assert_eq!(
+            super::allocate(7, &[d(0.0, 100, 0), d(0.0, 100, 0)]),
+            vec![4, 3],
+        );
+
+        for capacity in 0..100 {
+            let demands = [d(0.3, 10, 0), d(0.9, 40, 0), d(0.0, 30, 0)];
+            let quotas = super::allocate(capacity, &demands);
+            assert_eq!(quotas.iter().sum::<usize>(), capacity.min(80));
+        }
+    }
+
+    #[test]
+    fn rebalance() {
+        // Free slots are used before disconnecting anyone.
+        assert_eq!(
+            super::rebalance(50, &[d(1.0, 100, 0), d(0.0, 100, 30)]),
+            vec![0, 20],
+        );
+        assert_eq!(
+            super::rebalance(50, &[d(1.0, 100, 30), d(0.0, 100, 10)]),
+            vec![0, 0],
+        );
+        assert_eq!(
+            super::rebalance(50, &[d(1.0, 100, 35), d(0.0, 100, 15)]),
+            vec![0, 5],
+        );
+        assert_eq!(
+            super::rebalance(50, &[d(1.0, 100, 0), d(0.0, 100, 50)]),
+            vec![0, 40],
+        );
+        // Take from the most over-served torrent first.
+        assert_eq!(
+            super::rebalance(60, &[d(1.0, 100, 30), d(0.0, 100, 20), d(0.0, 100, 14)]),
+            vec![0, 8, 2],
+        );
+        // A torrent under its quota keeps its peers.
+        assert_eq!(
+            super::rebalance(50, &[d(1.0, 100, 40), d(0.0, 100, 10)]),
+            vec![0, 0],
+        );
+    }
+}
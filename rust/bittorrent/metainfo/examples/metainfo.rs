@@ -31,7 +31,9 @@ fn main() -> Result<(), Box<dyn error::Error>> {
             io::stdout().write_all(&serde_bencode::to_bytes(&metainfo)?)?;
         }
         Output::InfoHash => {
-            println!("{:?}", Hex(&metainfo.info.compute_info_hash()));
+            for info_hash in metainfo.info.compute_info_hashes() {
+                println!("{:?}", Hex(info_hash.as_bytes()));
+            }
         }
         Output::Rust => {
             println!("{:#?}", metainfo);
@@ -9,7 +9,6 @@ use std::fmt;
 
 use serde::{Deserialize, Serialize};
 use serde_bytes::Bytes;
-use sha1::{Digest, Sha1};
 use snafu::prelude::*;
 
 use g1_base::{
@@ -17,9 +16,10 @@ use g1_base::{
     fmt::{DebugExt, Hex},
 };
 
-use bittorrent_base::{Dimension, INFO_HASH_SIZE};
+use bittorrent_base::{AnyInfoHash, Dimension, InfoHash, INFO_HASH_V2_SIZE};
 use bittorrent_bencode::{borrow, own, FormatDictionary};
 
+pub use self::owner_impl::find_raw_info;
 pub use self::sanity::Insanity;
 
 g1_base::define_owner!(#[derive(Debug)] pub MetainfoOwner for Metainfo);
@@ -52,6 +52,9 @@ pub struct Metainfo<'a> {
     pub creation_date: Option<Timestamp>,
     pub encoding: Option<&'a str>,
     pub info: Info<'a>,
+    // BEP 52 The BitTorrent Protocol Specification v2
+    #[debug(with = FormatPieceLayers)]
+    pub piece_layers: Option<BTreeMap<&'a [u8], Vec<&'a [u8]>>>,
 
     #[debug(with = FormatDictionary)]
     pub extra: BTreeMap<&'a [u8], borrow::Value<'a>>,
@@ -73,6 +76,11 @@ pub struct Info<'a> {
     pub pieces: Vec<&'a [u8]>,
     // BEP 27 Private Torrents
     pub private: Option<bool>,
+    // BEP 52 The BitTorrent Protocol Specification v2
+    //
+    // For now, we only support hybrid torrents, in which the v1 fields are also present.
+    pub meta_version: Option<u64>,
+    pub file_tree: Option<FileTree<'a>>,
 
     #[debug(with = FormatDictionary)]
     pub extra: BTreeMap<&'a [u8], borrow::Value<'a>>,
@@ -94,11 +102,30 @@ pub struct File<'a> {
     pub path: Vec<&'a str>,
     pub length: u64,
     pub md5sum: Option<&'a str>,
+    // BEP 47 Padding files and extended file attributes
+    pub attr: Option<&'a str>,
+    pub symlink_path: Option<Vec<&'a str>>,
+    pub mtime: Option<Timestamp>,
 
     #[debug(with = FormatDictionary)]
     pub extra: BTreeMap<&'a [u8], borrow::Value<'a>>,
 }
 
+/// BEP 52 file tree, which maps a path component to a file or a subdirectory.
+pub type FileTree<'a> = BTreeMap<&'a str, FileTreeNode<'a>>;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum FileTreeNode<'a> {
+    File {
+        length: u64,
+        // It is absent for empty files.
+        pieces_root: Option<&'a [u8]>,
+    },
+    Directory(FileTree<'a>),
+}
+
+pub const MERKLE_HASH_SIZE: usize = 32;
+
 #[derive(Clone, Debug, Eq, PartialEq, Snafu)]
 pub enum Error {
     #[snafu(display("expect byte string: {value:?}"))]
@@ -112,10 +139,14 @@ pub enum Error {
 
     #[snafu(display("invalid length: {length}"))]
     InvalidLength { length: i64 },
+    #[snafu(display("invalid meta version: {meta_version}"))]
+    InvalidMetaVersion { meta_version: i64 },
     #[snafu(display("invalid node: {node:?}"))]
     InvalidNode { node: Vec<own::Value> },
     #[snafu(display("invalid piece hash size: {size}"))]
     InvalidPieceHashSize { size: usize },
+    #[snafu(display("invalid pieces root size: {size}"))]
+    InvalidPiecesRootSize { size: usize },
     #[snafu(display("invalid port: {port}"))]
     InvalidPort { port: i64 },
     #[snafu(display("invalid timestamp: {timestamp}"))]
@@ -126,13 +157,30 @@ pub enum Error {
     #[snafu(display("missing dictionary key: \"{key}\""))]
     MissingDictionaryKey { key: String },
 
+    #[snafu(display("invalid bencode: {source}"))]
+    InvalidBencode { source: bittorrent_bencode::Error },
+
     #[snafu(display("insane: {symptoms:?}"))]
     Insane { symptoms: Vec<Insanity> },
 }
 
 impl Info<'_> {
-    pub fn compute_info_hash(&self) -> [u8; INFO_HASH_SIZE] {
-        Sha1::digest(self.raw_info).into()
+    pub fn compute_info_hash(&self) -> InfoHash {
+        InfoHash::v1(self.raw_info)
+    }
+
+    /// Computes the BEP 52 info hash, which is meaningful only when `meta_version` is present.
+    pub fn compute_info_hash_v2(&self) -> [u8; INFO_HASH_V2_SIZE] {
+        InfoHash::v2(self.raw_info)
+    }
+
+    /// Computes the v1 info hash and, for a hybrid torrent, the v2 info hash.
+    pub fn compute_info_hashes(&self) -> Vec<AnyInfoHash> {
+        let mut info_hashes = vec![AnyInfoHash::v1(self.raw_info)];
+        if self.meta_version.is_some() {
+            info_hashes.push(AnyInfoHash::v2(self.raw_info));
+        }
+        info_hashes
     }
 
     pub fn length(&self) -> u64 {
@@ -152,6 +200,60 @@ impl Info<'_> {
     }
 }
 
+impl<'a> FileTreeNode<'a> {
+    /// Returns the files under this node in depth-first order, together with their paths.
+    #[allow(clippy::type_complexity)]
+    pub fn files(&self) -> Vec<(Vec<&'a str>, u64, Option<&'a [u8]>)> {
+        let mut files = Vec::new();
+        self.collect_files(&mut Vec::new(), &mut files);
+        files
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn collect_files(
+        &self,
+        path: &mut Vec<&'a str>,
+        files: &mut Vec<(Vec<&'a str>, u64, Option<&'a [u8]>)>,
+    ) {
+        match self {
+            Self::File {
+                length,
+                pieces_root,
+            } => files.push((path.clone(), *length, *pieces_root)),
+            Self::Directory(tree) => {
+                for (name, node) in tree {
+                    path.push(name);
+                    node.collect_files(path, files);
+                    path.pop();
+                }
+            }
+        }
+    }
+}
+
+impl File<'_> {
+    /// Padding file, which is not stored on disk and whose content is all zeros.
+    pub fn is_padding(&self) -> bool {
+        self.has_attr('p')
+    }
+
+    pub fn is_executable(&self) -> bool {
+        self.has_attr('x')
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        self.has_attr('h')
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.has_attr('l')
+    }
+
+    fn has_attr(&self, attr: char) -> bool {
+        self.attr.is_some_and(|a| a.contains(attr))
+    }
+}
+
 #[cfg(any(test, feature = "test_harness"))]
 mod test_harness {
     use super::*;
@@ -168,6 +270,7 @@ mod test_harness {
                 creation_date: None,
                 encoding: None,
                 info: Info::new_dummy(),
+                piece_layers: None,
                 extra: BTreeMap::new(),
             }
         }
@@ -185,6 +288,8 @@ mod test_harness {
                 piece_length: 0,
                 pieces: vec![],
                 private: None,
+                meta_version: None,
+                file_tree: None,
                 extra: BTreeMap::new(),
             }
         }
@@ -196,6 +301,9 @@ mod test_harness {
                 path: vec![],
                 length: 0,
                 md5sum: None,
+                attr: None,
+                symlink_path: None,
+                mtime: None,
                 extra: BTreeMap::new(),
             }
         }
@@ -211,3 +319,18 @@ impl fmt::Debug for FormatPieces<'_> {
             .finish()
     }
 }
+
+struct FormatPieceLayers<'a>(&'a Option<BTreeMap<&'a [u8], Vec<&'a [u8]>>>);
+
+impl fmt::Debug for FormatPieceLayers<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Piece layers are large; we only print the pieces roots and the number of hashes.
+        match self.0 {
+            Some(layers) => f
+                .debug_map()
+                .entries(layers.iter().map(|(root, layer)| (Hex(*root), layer.len())))
+                .finish(),
+            None => f.write_str("None"),
+        }
+    }
+}
@@ -1,8 +1,25 @@
 use std::convert::Infallible;
 
-use bittorrent_bencode::serde as serde_bencode;
+use snafu::prelude::*;
 
-use crate::{Info, Metainfo};
+use bittorrent_bencode::{
+    borrow, convert::to_dict, dict::DictionaryRemove, serde as serde_bencode,
+};
+
+use crate::{Error, Info, InvalidBencodeSnafu, Metainfo};
+
+/// Returns the info dictionary exactly as it appears in the metainfo buffer.
+///
+/// Compute info hashes from it rather than from a re-encoded info dictionary, which differs when
+/// the metainfo is not canonically encoded.  For the same reason, the buffer is decoded leniently.
+pub fn find_raw_info(metainfo: &[u8]) -> Result<&[u8], Error> {
+    let value = borrow::Value::<false>::try_from(metainfo)
+        .context(InvalidBencodeSnafu)?
+        .to_strict();
+    let (mut dict, _) = to_dict::<Error>(value)?;
+    let (_, raw_info) = to_dict::<Error>(dict.must_remove::<Error>(b"info")?)?;
+    Ok(raw_info.unwrap())
+}
 
 impl<'a> TryFrom<&'a [u8]> for Metainfo<'a> {
     type Error = serde_bencode::Error;
@@ -66,4 +83,35 @@ mod tests {
         // the info part.  You must avoid using the raw buffer.
         assert_ne!(InfoOwner::as_slice(&info_owner), &testdata);
     }
+
+    #[test]
+    fn find_raw_info() {
+        // Dictionary keys are not sorted.
+        let testdata = b"d4:infod4:name3:bar6:lengthi1ee8:announce3:fooe";
+        assert_eq!(
+            super::find_raw_info(testdata),
+            Ok(b"d4:name3:bar6:lengthi1ee".as_slice()),
+        );
+
+        assert_eq!(
+            super::find_raw_info(b"d"),
+            Err(Error::InvalidBencode {
+                source: bittorrent_bencode::Error::Incomplete,
+            }),
+        );
+        assert_eq!(
+            super::find_raw_info(b"i1e"),
+            Err(Error::ExpectDictionary { value: 1.into() }),
+        );
+        assert_eq!(
+            super::find_raw_info(b"de"),
+            Err(Error::MissingDictionaryKey {
+                key: "info".to_string(),
+            }),
+        );
+        assert_eq!(
+            super::find_raw_info(b"d4:infoi1ee"),
+            Err(Error::ExpectDictionary { value: 1.into() }),
+        );
+    }
 }
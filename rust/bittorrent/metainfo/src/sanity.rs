@@ -30,6 +30,11 @@ pub enum Insanity {
     InvalidPieceLength {
         piece_length: u64,
     },
+    MissingFileTree,
+    #[snafu(display("unsupported meta_version: {meta_version}"))]
+    UnsupportedMetaVersion {
+        meta_version: u64,
+    },
 }
 
 impl Metainfo<'_> {
@@ -88,6 +93,7 @@ impl Info<'_> {
             .chain(self.check_files())
             .chain(self.check_pieces())
             .chain(self.check_invalid_piece_length())
+            .chain(self.check_meta_version())
     }
 
     fn check_empty_name(&self) -> Option<Insanity> {
@@ -137,11 +143,22 @@ impl Info<'_> {
             })
         }
     }
+
+    fn check_meta_version(&self) -> Option<Insanity> {
+        match self.meta_version {
+            Some(2) => self
+                .file_tree
+                .is_none()
+                .then_some(Insanity::MissingFileTree),
+            Some(meta_version) => Some(Insanity::UnsupportedMetaVersion { meta_version }),
+            None => None,
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::File;
+    use crate::{File, FileTree};
 
     use super::*;
 
@@ -279,5 +296,24 @@ mod tests {
         file.length = 100;
         info.mode = Mode::MultiFile { files: vec![file] };
         assert_eq!(info.sanity_check(), Ok(()));
+
+        info.meta_version = Some(2);
+        assert_eq!(
+            info.sanity_check(),
+            Err(Error::Insane {
+                symptoms: vec![Insanity::MissingFileTree],
+            }),
+        );
+
+        info.file_tree = Some(FileTree::new());
+        assert_eq!(info.sanity_check(), Ok(()));
+
+        info.meta_version = Some(3);
+        assert_eq!(
+            info.sanity_check(),
+            Err(Error::Insane {
+                symptoms: vec![Insanity::UnsupportedMetaVersion { meta_version: 3 }],
+            }),
+        );
     }
 }
@@ -3,6 +3,8 @@
 //! Generally, `to_foo` converts `borrow::Value` to `Foo`, and `from_foo` converts `Foo` to
 //! `own::Value`.
 
+use std::collections::BTreeMap;
+
 use snafu::prelude::*;
 
 use g1_chrono::Timestamp;
@@ -10,11 +12,14 @@ use g1_chrono::Timestamp;
 use bittorrent_base::PIECE_HASH_SIZE;
 use bittorrent_bencode::{
     borrow,
-    convert::{self, from_str, from_vec, to_int, to_str, to_vec},
+    convert::{self, from_str, from_vec, to_bytes, to_dict, to_int, to_str, to_vec},
     dict, own,
 };
 
-use crate::{Error, InvalidNodeSnafu, InvalidPieceHashSizeSnafu};
+use crate::{
+    Error, InvalidNodeSnafu, InvalidPieceHashSizeSnafu, InvalidPiecesRootSizeSnafu,
+    MERKLE_HASH_SIZE,
+};
 
 impl From<convert::Error> for Error {
     fn from(error: convert::Error) -> Self {
@@ -67,6 +72,17 @@ pub(super) fn from_private(private: bool) -> own::Value {
     i64::from(private).into()
 }
 
+pub(super) fn to_meta_version(meta_version: i64) -> Result<u64, Error> {
+    match u64::try_from(meta_version) {
+        Ok(meta_version) if meta_version > 0 => Ok(meta_version),
+        _ => Err(Error::InvalidMetaVersion { meta_version }),
+    }
+}
+
+pub(super) fn from_meta_version(meta_version: u64) -> own::Value {
+    i64::try_from(meta_version).unwrap().into()
+}
+
 pub(super) fn to_announce_list(value: borrow::Value) -> Result<Vec<Vec<&str>>, Error> {
     to_vec(value, |list| to_vec(list, to_str))
 }
@@ -122,23 +138,68 @@ pub(super) fn from_url_list(url_list: Vec<&str>) -> own::Value {
 }
 
 pub(super) fn to_pieces(value: borrow::Value) -> Result<Vec<&[u8]>, Error> {
+    to_hashes(value, PIECE_HASH_SIZE)
+}
+
+pub(super) fn from_pieces(pieces: Vec<&[u8]>) -> own::Value {
+    from_hashes(pieces, PIECE_HASH_SIZE)
+}
+
+pub(super) fn to_pieces_root(value: borrow::Value<'_>) -> Result<&[u8], Error> {
+    let pieces_root = to_bytes::<Error>(value)?;
+    ensure!(
+        pieces_root.len() == MERKLE_HASH_SIZE,
+        InvalidPiecesRootSizeSnafu {
+            size: pieces_root.len(),
+        }
+    );
+    Ok(pieces_root)
+}
+
+pub(super) fn to_piece_layers(
+    value: borrow::Value<'_>,
+) -> Result<BTreeMap<&[u8], Vec<&[u8]>>, Error> {
+    to_dict::<Error>(value)?
+        .0
+        .into_iter()
+        .map(|(pieces_root, layer)| {
+            Ok((
+                to_pieces_root(borrow::Value::ByteString(pieces_root))?,
+                to_hashes(layer, MERKLE_HASH_SIZE)?,
+            ))
+        })
+        .try_collect()
+}
+
+pub(super) fn from_piece_layers(piece_layers: BTreeMap<&[u8], Vec<&[u8]>>) -> own::Value {
+    piece_layers
+        .into_iter()
+        .map(|(pieces_root, layer)| {
+            (
+                own::ByteString::from(pieces_root),
+                from_hashes(layer, MERKLE_HASH_SIZE),
+            )
+        })
+        .collect::<BTreeMap<own::ByteString, own::Value>>()
+        .into()
+}
+
+fn to_hashes(value: borrow::Value<'_>, hash_size: usize) -> Result<Vec<&[u8]>, Error> {
     let bytes = value
         .as_byte_string()
         .ok_or_else(|| Error::ExpectByteString {
             value: value.to_owned(),
         })?;
     ensure!(
-        bytes.len() % PIECE_HASH_SIZE == 0,
+        bytes.len() % hash_size == 0,
         InvalidPieceHashSizeSnafu { size: bytes.len() }
     );
-    Ok(bytes.chunks_exact(PIECE_HASH_SIZE).collect())
+    Ok(bytes.chunks_exact(hash_size).collect())
 }
 
-pub(super) fn from_pieces(pieces: Vec<&[u8]>) -> own::Value {
-    let mut bytes = own::ByteString::with_capacity(pieces.len() * PIECE_HASH_SIZE);
-    pieces
-        .iter()
-        .for_each(|piece| bytes.extend_from_slice(piece));
+fn from_hashes(hashes: Vec<&[u8]>, hash_size: usize) -> own::Value {
+    let mut bytes = own::ByteString::with_capacity(hashes.len() * hash_size);
+    hashes.iter().for_each(|hash| bytes.extend_from_slice(hash));
     bytes.into()
 }
 
@@ -267,5 +328,50 @@ mod tests {
             Error::InvalidPieceHashSize { size: 1 },
             to_pieces,
         );
+
+        // meta_version
+        ok(
+            2.into(),
+            2,
+            |value| to_int(value).and_then(to_meta_version),
+            from_meta_version,
+        );
+        err(
+            0.into(),
+            Error::InvalidMetaVersion { meta_version: 0 },
+            |value| to_int(value).and_then(to_meta_version),
+        );
+
+        // pieces_root
+        assert_eq!(
+            to_pieces_root(new_bytes(&[0u8; 32])),
+            Ok([0u8; 32].as_slice()),
+        );
+        err(
+            new_bytes(b"0"),
+            Error::InvalidPiecesRootSize { size: 1 },
+            to_pieces_root,
+        );
+
+        // piece_layers
+        ok(
+            BTreeMap::from([([0u8; 32].as_slice(), new_bytes(&[1u8; 64]))]).into(),
+            BTreeMap::from([(
+                [0u8; 32].as_slice(),
+                vec![[1u8; 32].as_slice(), [1u8; 32].as_slice()],
+            )]),
+            to_piece_layers,
+            from_piece_layers,
+        );
+        err(
+            BTreeMap::from([(b"0".as_slice(), new_bytes(b""))]).into(),
+            Error::InvalidPiecesRootSize { size: 1 },
+            to_piece_layers,
+        );
+        err(
+            BTreeMap::from([([0u8; 32].as_slice(), new_bytes(&[1u8; 20]))]).into(),
+            Error::InvalidPieceHashSize { size: 20 },
+            to_piece_layers,
+        );
     }
 }
@@ -6,12 +6,12 @@ use serde_bytes::Bytes;
 
 use bittorrent_bencode::{
     borrow,
-    convert::{from_dict, from_str, from_vec, to_dict, to_int, to_str, to_vec},
+    convert::{from_bytes, from_dict, from_str, from_vec, to_dict, to_int, to_str, to_vec},
     dict::{DictionaryInsert, DictionaryRemove},
     own,
 };
 
-use crate::{Error, File, Info, Metainfo, Mode};
+use crate::{Error, File, FileTree, FileTreeNode, Info, Metainfo, Mode};
 
 use self::convert::*;
 
@@ -25,18 +25,28 @@ const CREATED_BY: &[u8] = b"created by";
 const CREATION_DATE: &[u8] = b"creation date";
 const ENCODING: &[u8] = b"encoding";
 const INFO: &[u8] = b"info";
+const PIECE_LAYERS: &[u8] = b"piece layers";
 
 // `Info` dictionary keys.
 const NAME: &[u8] = b"name";
 const PIECE_LENGTH: &[u8] = b"piece length";
 const PIECES: &[u8] = b"pieces";
 const PRIVATE: &[u8] = b"private";
+const META_VERSION: &[u8] = b"meta version";
+const FILE_TREE: &[u8] = b"file tree";
 
 // `Mode` and `File` dictionary keys.
 const LENGTH: &[u8] = b"length";
 const MD5SUM: &[u8] = b"md5sum";
 const FILES: &[u8] = b"files";
 const PATH: &[u8] = b"path";
+const ATTR: &[u8] = b"attr";
+const SYMLINK_PATH: &[u8] = b"symlink path";
+const MTIME: &[u8] = b"mtime";
+
+// `FileTreeNode` dictionary keys.
+const FILE_TREE_FILE: &[u8] = b"";
+const PIECES_ROOT: &[u8] = b"pieces root";
 
 impl<'a> TryFrom<BTreeMap<&'a [u8], borrow::Value<'a>>> for Metainfo<'a> {
     type Error = Error;
@@ -59,6 +69,7 @@ impl<'a> TryFrom<BTreeMap<&'a [u8], borrow::Value<'a>>> for Metainfo<'a> {
                 .transpose()?,
             encoding: dict.remove_str::<Error>(ENCODING)?,
             info: Info::try_from(dict.must_remove::<Error>(INFO)?)?,
+            piece_layers: dict.remove(PIECE_LAYERS).map(to_piece_layers).transpose()?,
 
             extra: dict,
         };
@@ -81,6 +92,7 @@ impl<'a> From<Metainfo<'a>> for BTreeMap<&'a Bytes, own::Value> {
         dict.insert_from(CREATION_DATE, metainfo.creation_date, from_timestamp);
         dict.insert_from(ENCODING, metainfo.encoding, from_str);
         dict.insert(Bytes::new(INFO), metainfo.info.into());
+        dict.insert_from(PIECE_LAYERS, metainfo.piece_layers, from_piece_layers);
 
         dict
     }
@@ -101,6 +113,11 @@ impl<'a> TryFrom<borrow::Value<'a>> for Info<'a> {
                 .and_then(to_length)?,
             pieces: dict.must_remove::<Error>(PIECES).and_then(to_pieces)?,
             private: dict.remove_int::<Error>(PRIVATE)?.map(to_private),
+            meta_version: dict
+                .remove_int::<Error>(META_VERSION)?
+                .map(to_meta_version)
+                .transpose()?,
+            file_tree: dict.remove(FILE_TREE).map(to_file_tree).transpose()?,
             extra: dict,
         };
         this.sanity_check()?;
@@ -116,6 +133,8 @@ impl<'a> From<Info<'a>> for own::Value {
         dict.insert(PIECE_LENGTH.into(), from_length(info.piece_length));
         dict.insert(PIECES.into(), from_pieces(info.pieces));
         dict.insert_from(PRIVATE, info.private, from_private);
+        dict.insert_from(META_VERSION, info.meta_version, from_meta_version);
+        dict.insert_from(FILE_TREE, info.file_tree, from_file_tree);
         dict.into()
     }
 }
@@ -171,6 +190,15 @@ impl<'a> TryFrom<borrow::Value<'a>> for File<'a> {
                 .and_then(to_int)
                 .and_then(to_length)?,
             md5sum: dict.remove_str::<Error>(MD5SUM)?,
+            attr: dict.remove_str::<Error>(ATTR)?,
+            symlink_path: dict
+                .remove(SYMLINK_PATH)
+                .map(|value| to_vec(value, to_str::<Error>))
+                .transpose()?,
+            mtime: dict
+                .remove_int::<Error>(MTIME)?
+                .map(to_timestamp)
+                .transpose()?,
             extra: dict,
         })
     }
@@ -182,10 +210,78 @@ impl<'a> From<File<'a>> for own::Value {
         dict.insert(PATH.into(), from_vec(file.path, from_str));
         dict.insert(LENGTH.into(), from_length(file.length));
         dict.insert_from(MD5SUM, file.md5sum, from_str);
+        dict.insert_from(ATTR, file.attr, from_str);
+        dict.insert_from(SYMLINK_PATH, file.symlink_path, |path| {
+            from_vec(path, from_str)
+        });
+        dict.insert_from(MTIME, file.mtime, from_timestamp);
         dict.into()
     }
 }
 
+// In a file tree, a file is represented as a dictionary whose only key is the empty string.
+impl<'a> TryFrom<borrow::Value<'a>> for FileTreeNode<'a> {
+    type Error = Error;
+
+    fn try_from(value: borrow::Value<'a>) -> Result<Self, Self::Error> {
+        let (mut dict, _) = to_dict::<Error>(value)?;
+        let Some(file) = dict.remove(FILE_TREE_FILE) else {
+            return Ok(Self::Directory(to_file_tree_dict(dict)?));
+        };
+        // TODO: A file should not have siblings under the empty key, but for now we are not
+        // checking this.
+        let (mut file, _) = to_dict::<Error>(file)?;
+        Ok(Self::File {
+            length: file
+                .must_remove::<Error>(LENGTH)
+                .and_then(to_int)
+                .and_then(to_length)?,
+            pieces_root: file.remove(PIECES_ROOT).map(to_pieces_root).transpose()?,
+        })
+    }
+}
+
+impl<'a> From<FileTreeNode<'a>> for own::Value {
+    fn from(node: FileTreeNode<'a>) -> Self {
+        match node {
+            FileTreeNode::File {
+                length,
+                pieces_root,
+            } => {
+                let mut file = BTreeMap::<own::ByteString, own::Value>::new();
+                file.insert(LENGTH.into(), from_length(length));
+                file.insert_from(PIECES_ROOT, pieces_root, from_bytes);
+                BTreeMap::from([(own::ByteString::from(FILE_TREE_FILE), file.into())]).into()
+            }
+            FileTreeNode::Directory(tree) => from_file_tree(tree),
+        }
+    }
+}
+
+fn to_file_tree(value: borrow::Value) -> Result<FileTree, Error> {
+    to_file_tree_dict(to_dict::<Error>(value)?.0)
+}
+
+fn to_file_tree_dict<'a>(
+    dict: BTreeMap<&'a [u8], borrow::Value<'a>>,
+) -> Result<FileTree<'a>, Error> {
+    dict.into_iter()
+        .map(|(name, node)| {
+            Ok((
+                to_str::<Error>(borrow::Value::ByteString(name))?,
+                FileTreeNode::try_from(node)?,
+            ))
+        })
+        .try_collect()
+}
+
+fn from_file_tree(tree: FileTree) -> own::Value {
+    tree.into_iter()
+        .map(|(name, node)| (own::ByteString::from(name.as_bytes()), node.into()))
+        .collect::<BTreeMap<own::ByteString, own::Value>>()
+        .into()
+}
+
 #[cfg(test)]
 mod tests {
     use g1_chrono::{Timestamp, TimestampExt};
@@ -277,8 +373,11 @@ mod tests {
                 piece_length: 512,
                 pieces: vec![b"01234567890123456789".as_slice()],
                 private: Some(true),
+                meta_version: None,
+                file_tree: None,
                 extra: BTreeMap::from([(b"info extra stuff".as_slice(), 2.into())]),
             },
+            piece_layers: None,
             extra: BTreeMap::from([(b"extra stuff".as_slice(), 3.into())]),
         };
         test(dict, expect);
@@ -289,16 +388,29 @@ mod tests {
                 (b"name".as_slice(), new_bytes(b"foo")),
                 (
                     b"files",
-                    vec![BTreeMap::from([
-                        (
-                            b"path".as_slice(),
-                            vec![new_bytes(b"spam"), new_bytes(b"egg")].into(),
-                        ),
-                        (b"length".as_slice(), 100.into()),
-                        (b"md5sum".as_slice(), new_bytes(b"deadbeef")),
-                        (b"extra stuff".as_slice(), 42.into()),
-                    ])
-                    .into()]
+                    vec![
+                        BTreeMap::from([
+                            (
+                                b"path".as_slice(),
+                                vec![new_bytes(b"spam"), new_bytes(b"egg")].into(),
+                            ),
+                            (b"length".as_slice(), 100.into()),
+                            (b"md5sum".as_slice(), new_bytes(b"deadbeef")),
+                            (b"extra stuff".as_slice(), 42.into()),
+                        ])
+                        .into(),
+                        BTreeMap::from([
+                            (b"path".as_slice(), vec![new_bytes(b"link")].into()),
+                            (b"length".as_slice(), 0.into()),
+                            (b"attr".as_slice(), new_bytes(b"lh")),
+                            (
+                                b"symlink path".as_slice(),
+                                vec![new_bytes(b"spam"), new_bytes(b"egg")].into(),
+                            ),
+                            (b"mtime".as_slice(), 43.into()),
+                        ])
+                        .into(),
+                    ]
                     .into(),
                 ),
                 (b"piece length".as_slice(), 512.into()),
@@ -319,20 +431,145 @@ mod tests {
                 raw_info: b"".as_slice(),
                 name: "foo",
                 mode: Mode::MultiFile {
-                    files: vec![File {
-                        path: vec!["spam", "egg"],
-                        length: 100,
-                        md5sum: Some("deadbeef"),
-                        extra: BTreeMap::from([(b"extra stuff".as_slice(), 42.into())]),
-                    }],
+                    files: vec![
+                        File {
+                            path: vec!["spam", "egg"],
+                            length: 100,
+                            md5sum: Some("deadbeef"),
+                            attr: None,
+                            symlink_path: None,
+                            mtime: None,
+                            extra: BTreeMap::from([(b"extra stuff".as_slice(), 42.into())]),
+                        },
+                        File {
+                            path: vec!["link"],
+                            length: 0,
+                            md5sum: None,
+                            attr: Some("lh"),
+                            symlink_path: Some(vec!["spam", "egg"]),
+                            mtime: Some(Timestamp::from_timestamp_secs(43).unwrap()),
+                            extra: BTreeMap::new(),
+                        },
+                    ],
                 },
                 piece_length: 512,
                 pieces: vec![b"01234567890123456789".as_slice()],
                 private: None,
+                meta_version: None,
+                file_tree: None,
                 extra: BTreeMap::new(),
             },
+            piece_layers: None,
             extra: BTreeMap::new(),
         };
         test(dict, expect);
+
+        // BEP 52 hybrid torrent.
+        let root = [1u8; 32];
+        let layer = [2u8; 64];
+        let dict = BTreeMap::from([
+            (
+                b"info".as_slice(),
+                BTreeMap::from([
+                    (b"name".as_slice(), new_bytes(b"foo")),
+                    (b"length".as_slice(), 100.into()),
+                    (b"piece length".as_slice(), 512.into()),
+                    (b"pieces".as_slice(), new_bytes(b"01234567890123456789")),
+                    (b"meta version".as_slice(), 2.into()),
+                    (
+                        b"file tree".as_slice(),
+                        BTreeMap::from([(
+                            b"dir".as_slice(),
+                            BTreeMap::from([
+                                (
+                                    b"empty".as_slice(),
+                                    BTreeMap::from([(
+                                        b"".as_slice(),
+                                        BTreeMap::from([(b"length".as_slice(), 0.into())]).into(),
+                                    )])
+                                    .into(),
+                                ),
+                                (
+                                    b"spam".as_slice(),
+                                    BTreeMap::from([(
+                                        b"".as_slice(),
+                                        BTreeMap::from([
+                                            (b"length".as_slice(), 100.into()),
+                                            (b"pieces root".as_slice(), new_bytes(&root)),
+                                        ])
+                                        .into(),
+                                    )])
+                                    .into(),
+                                ),
+                            ])
+                            .into(),
+                        )])
+                        .into(),
+                    ),
+                ])
+                .into(),
+            ),
+            (
+                b"piece layers".as_slice(),
+                BTreeMap::from([(root.as_slice(), new_bytes(&layer))]).into(),
+            ),
+        ]);
+        let expect = Metainfo {
+            announce: None,
+            announce_list: None,
+            nodes: None,
+            url_list: None,
+            comment: None,
+            created_by: None,
+            creation_date: None,
+            encoding: None,
+            info: Info {
+                raw_info: b"".as_slice(),
+                name: "foo",
+                mode: Mode::SingleFile {
+                    length: 100,
+                    md5sum: None,
+                },
+                piece_length: 512,
+                pieces: vec![b"01234567890123456789".as_slice()],
+                private: None,
+                meta_version: Some(2),
+                file_tree: Some(BTreeMap::from([(
+                    "dir",
+                    FileTreeNode::Directory(BTreeMap::from([
+                        (
+                            "empty",
+                            FileTreeNode::File {
+                                length: 0,
+                                pieces_root: None,
+                            },
+                        ),
+                        (
+                            "spam",
+                            FileTreeNode::File {
+                                length: 100,
+                                pieces_root: Some(root.as_slice()),
+                            },
+                        ),
+                    ])),
+                )])),
+                extra: BTreeMap::new(),
+            },
+            piece_layers: Some(BTreeMap::from([(
+                root.as_slice(),
+                vec![[2u8; 32].as_slice(), [2u8; 32].as_slice()],
+            )])),
+            extra: BTreeMap::new(),
+        };
+        test(dict, expect.clone());
+
+        let tree = FileTreeNode::Directory(expect.info.file_tree.unwrap());
+        assert_eq!(
+            tree.files(),
+            vec![
+                (vec!["dir", "empty"], 0, None),
+                (vec!["dir", "spam"], 100, Some(root.as_slice())),
+            ],
+        );
     }
 }
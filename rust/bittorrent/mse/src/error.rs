@@ -17,6 +17,10 @@ pub enum Error {
         crypto_select: u32,
         expect: u32,
     },
+    #[snafu(display("expect hash_2 of any info hash: {hash_2:?}"))]
+    ExpectHash2 {
+        hash_2: Vec<u8>,
+    },
     #[snafu(display("expect padding size in {PADDING_SIZE_RANGE:?}: {size}"))]
     ExpectPaddingSize {
         size: usize,
@@ -50,6 +54,7 @@ impl From<Error> for io::Error {
         match error {
             Error::ExpectCryptoProvide { .. }
             | Error::ExpectCryptoSelect { .. }
+            | Error::ExpectHash2 { .. }
             | Error::ExpectPaddingSize { .. }
             | Error::ExpectPayloadSize { .. }
             | Error::ExpectRecv { .. }
@@ -2,7 +2,7 @@
 
 use std::io::Error;
 
-use bytes::{Buf, BufMut};
+use bytes::{Buf, BufMut, Bytes};
 use snafu::prelude::*;
 use tokio::time;
 
@@ -11,27 +11,50 @@ use g1_tokio::bstream::{transform::Transform, StreamBuffer, StreamRecv, StreamSe
 use bittorrent_base::{payload_size_limit, PROTOCOL_ID};
 
 use crate::{
-    error::{self, ExpectCryptoProvideSnafu, ExpectPayloadSizeSnafu},
-    MseStream,
+    error::{self, ExpectCryptoProvideSnafu, ExpectHash2Snafu, ExpectPayloadSizeSnafu},
+    MseStream, HASH_SIZE,
 };
 
 use super::{
-    encode_size, load_crypto_provide, timeout, AcceptSide, Handshake, CRYPTO_PLAINTEXT, CRYPTO_RC4,
-    PADDING_SIZE_RANGE, VC,
+    encode_size, timeout, AcceptSide, Handshake, CRYPTO_PLAINTEXT, CRYPTO_RC4, PADDING_SIZE_RANGE,
+    VC,
 };
 
-impl<Stream> Handshake<'_, Stream, AcceptSide>
+impl<Stream> Handshake<Stream, AcceptSide>
 where
     Stream: StreamRecv<Error = Error> + StreamSend<Error = Error> + Send,
 {
-    pub(super) async fn handshake(self) -> Result<MseStream<Stream>, Error> {
-        time::timeout(*timeout(), self.handshake_impl())
+    /// Performs the handshake with the info hash that `lookup` returns for HASH('req2', SKEY).
+    pub(super) async fn handshake<Lookup, InfoHash>(
+        self,
+        lookup: Lookup,
+    ) -> Result<MseStream<Stream>, Error>
+    where
+        Lookup: FnOnce(&[u8; HASH_SIZE]) -> Option<InfoHash>,
+        InfoHash: AsRef<[u8]>,
+    {
+        time::timeout(*timeout(), self.handshake_impl(lookup))
             .await
             .map_err(|_| error::Error::Timeout)?
     }
 
-    async fn handshake_impl(mut self) -> Result<MseStream<Stream>, Error> {
+    async fn handshake_impl<Lookup, InfoHash>(
+        mut self,
+        lookup: Lookup,
+    ) -> Result<MseStream<Stream>, Error>
+    where
+        Lookup: FnOnce(&[u8; HASH_SIZE]) -> Option<InfoHash>,
+        InfoHash: AsRef<[u8]>,
+    {
         if self.check_peer_not_implement_mse().await? {
+            let self_crypto_provide = self.policy.crypto_provide();
+            ensure!(
+                (self_crypto_provide & CRYPTO_PLAINTEXT) != 0,
+                ExpectCryptoProvideSnafu {
+                    crypto_provide: CRYPTO_PLAINTEXT,
+                    expect: self_crypto_provide,
+                },
+            );
             return Ok(self.finish(CRYPTO_PLAINTEXT));
         }
 
@@ -41,7 +64,8 @@ where
         let hash_1 = self.compute_hash_1();
         self.resynchronize(&hash_1, *PADDING_SIZE_RANGE.end() + hash_1.len())
             .await?;
-        self.recv_expect("hash_2", &self.compute_hash_2()).await?;
+        self.info_hash = self.recv_hash_2(lookup).await?;
+        self.new_ciphers();
 
         let mut vc = VC;
         self.decrypt.as_mut().unwrap().transform(&mut vc);
@@ -52,24 +76,16 @@ where
         if unknown != 0 {
             tracing::info!(unknown, "unknown crypto_provide bits");
         }
-        let self_crypto_provide = load_crypto_provide();
-        let crypto_provide = peer_crypto_provide & self_crypto_provide;
+        let self_crypto_provide = self.policy.crypto_provide();
+        let crypto_select = self.policy.crypto_select(peer_crypto_provide);
         ensure!(
-            crypto_provide != 0,
+            crypto_select != 0,
             ExpectCryptoProvideSnafu {
                 crypto_provide: peer_crypto_provide,
                 expect: self_crypto_provide,
             }
         );
 
-        // Prefer CRYPTO_RC4 over CRYPTO_PLAINTEXT.
-        let crypto_select = if (crypto_provide & CRYPTO_RC4) != 0 {
-            CRYPTO_RC4
-        } else {
-            assert_ne!(crypto_provide & CRYPTO_PLAINTEXT, 0);
-            CRYPTO_PLAINTEXT
-        };
-
         // Receive padding_c.
         self.recv_padding().await?;
 
@@ -90,6 +106,21 @@ where
         })
     }
 
+    /// Receives hash_2 and looks up the info hash from which it is computed.
+    async fn recv_hash_2<Lookup, InfoHash>(&mut self, lookup: Lookup) -> Result<Bytes, Error>
+    where
+        Lookup: FnOnce(&[u8; HASH_SIZE]) -> Option<InfoHash>,
+        InfoHash: AsRef<[u8]>,
+    {
+        self.stream.recv_fill(HASH_SIZE).await?;
+        let hash_2 = <[u8; HASH_SIZE]>::try_from(&self.stream.recv_buffer()[0..HASH_SIZE]).unwrap();
+        let info_hash = lookup(&self.recover_skey_hash(hash_2)).context(ExpectHash2Snafu {
+            hash_2: hash_2.to_vec(),
+        })?;
+        self.stream.recv_buffer().advance(HASH_SIZE);
+        Ok(Bytes::copy_from_slice(info_hash.as_ref()))
+    }
+
     async fn recv_initial_payload(&mut self) -> Result<(), Error> {
         let size = self.recv_decrypt_size().await?;
         let limit = *payload_size_limit();
@@ -3,7 +3,7 @@
 use std::io::Error;
 use std::marker::PhantomData;
 
-use bytes::{Buf, BufMut, BytesMut};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use crypto_bigint::ArrayEncoding;
 use rand::Rng;
 use snafu::prelude::*;
@@ -19,7 +19,7 @@ use crate::{
         self, ExpectPaddingSizeSnafu, ExpectRecvPublicKeySizeSnafu, ExpectRecvSnafu,
         ExpectResynchronizeSnafu,
     },
-    MseStream, HASH_SIZE,
+    MsePolicy, MseStream, HASH_SIZE,
 };
 
 use super::{
@@ -31,13 +31,19 @@ const REQ1: &[u8] = b"req1";
 const REQ2: &[u8] = b"req2";
 const REQ3: &[u8] = b"req3";
 
-impl<'a, Stream, Side> Handshake<'a, Stream, Side> {
-    pub(super) fn new(stream: Stream, info_hash: &'a [u8]) -> Self {
+/// Computes HASH('req2', SKEY), by which `accept_with` looks up the info hash (SKEY).
+pub fn compute_skey_hash(info_hash: &[u8]) -> [u8; HASH_SIZE] {
+    compute_hash([REQ2, info_hash]).into()
+}
+
+impl<Stream, Side> Handshake<Stream, Side> {
+    pub(super) fn new(stream: Stream, info_hash: Bytes, policy: MsePolicy) -> Self {
         let private_key = dh::generate_private_key();
         let self_public_key = dh::compute_public_key(&private_key);
         Self {
             stream,
             info_hash,
+            policy,
             private_key,
             self_public_key,
             secret: Default::default(),
@@ -51,17 +57,25 @@ impl<'a, Stream, Side> Handshake<'a, Stream, Side> {
         compute_hash([REQ1, &self.secret]).into()
     }
 
-    pub(super) fn compute_hash_2(&self) -> [u8; HASH_SIZE] {
-        let mut hash_2 = compute_hash([REQ2, self.info_hash]);
+    pub(super) fn compute_hash_2(&self, info_hash: &[u8]) -> [u8; HASH_SIZE] {
+        self.xor_req3_hash(compute_skey_hash(info_hash))
+    }
+
+    /// Recovers HASH('req2', SKEY) from hash_2, which is HASH('req2', SKEY) xor HASH('req3', S).
+    pub(super) fn recover_skey_hash(&self, hash_2: [u8; HASH_SIZE]) -> [u8; HASH_SIZE] {
+        self.xor_req3_hash(hash_2)
+    }
+
+    fn xor_req3_hash(&self, mut hash: [u8; HASH_SIZE]) -> [u8; HASH_SIZE] {
         {
-            let mut hash_2 = SliceCompoundAssignOp(&mut hash_2);
-            hash_2 ^= compute_hash([REQ3, &self.secret]);
+            let mut hash = SliceCompoundAssignOp(&mut hash);
+            hash ^= compute_hash([REQ3, &self.secret]);
         }
-        hash_2.into()
+        hash
     }
 }
 
-impl<Stream, Side> Handshake<'_, Stream, Side>
+impl<Stream, Side> Handshake<Stream, Side>
 where
     Stream: StreamRecv<Error = Error> + StreamSend<Error = Error> + Send,
     Side: HandshakeSide,
@@ -101,10 +115,15 @@ where
 
     fn set_peer_public_key(&mut self, peer_public_key: DhKey) {
         let secret = dh::compute_secret(&peer_public_key, &self.private_key);
-        let (decrypt, encrypt) = Side::new_mse_rc4(&secret, self.info_hash);
+        self.secret = secret.to_be_byte_array();
+    }
+
+    /// Creates the ciphers from the secret and the info hash (SKEY).
+    pub(super) fn new_ciphers(&mut self) {
+        let secret = DhKey::from_be_byte_array(self.secret);
+        let (decrypt, encrypt) = Side::new_mse_rc4(&secret, &self.info_hash);
         self.decrypt = Some(Box::new(decrypt));
         self.encrypt = Some(Box::new(encrypt));
-        self.secret = secret.to_be_byte_array();
     }
 
     pub(super) fn finish(mut self, crypto_select: u32) -> MseStream<Stream> {
@@ -133,7 +152,7 @@ where
     }
 }
 
-impl<Stream, Side> Handshake<'_, Stream, Side>
+impl<Stream, Side> Handshake<Stream, Side>
 where
     Stream: StreamRecv<Error = Error> + Send,
 {
@@ -231,9 +250,11 @@ where
 
 fn put_random_padding(buffer: &mut BytesMut) {
     let mut padding = [0u8; *PADDING_SIZE_RANGE.end()];
-    let mut rng = rand::thread_rng();
-    let size = rng.gen_range(PADDING_SIZE_RANGE);
-    rng.fill(&mut padding[0..size]);
+    let size = g1_base::rand::with_rng(|rng| {
+        let size = rng.gen_range(PADDING_SIZE_RANGE);
+        rng.fill(&mut padding[0..size]);
+        size
+    });
     buffer.put_slice(&padding[0..size]);
 }
 
@@ -249,7 +270,7 @@ mod tests {
     async fn resynchronize() {
         async fn test_ok(data: &[u8], pattern: &[u8], upper_bound: usize, expect: &[u8]) {
             let (stream, mut mock) = RecvStream::new_mock(4096);
-            let mut handshake = Handshake::<_, ()>::new(stream, b"");
+            let mut handshake = Handshake::<_, ()>::new(stream, Bytes::new(), MsePolicy::default());
             mock.write_all(data).await.unwrap();
             handshake.resynchronize(pattern, upper_bound).await.unwrap();
             assert_eq!(handshake.stream.buffer().as_ref(), expect);
@@ -257,7 +278,7 @@ mod tests {
 
         async fn test_err(data: &[u8], pattern: &[u8], upper_bound: usize, expect_size: usize) {
             let (stream, mut mock) = RecvStream::new_mock(4096);
-            let mut handshake = Handshake::<_, ()>::new(stream, b"");
+            let mut handshake = Handshake::<_, ()>::new(stream, Bytes::new(), MsePolicy::default());
             mock.write_all(data).await.unwrap();
             assert_eq!(
                 handshake
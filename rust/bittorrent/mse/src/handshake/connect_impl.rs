@@ -14,14 +14,14 @@ use crate::{
 };
 
 use super::{
-    encode_size, load_crypto_provide, timeout, ConnectSide, Handshake, CRYPTO_PLAINTEXT,
-    CRYPTO_RC4, PADDING_SIZE_RANGE, VC,
+    encode_size, timeout, ConnectSide, Handshake, CRYPTO_PLAINTEXT, CRYPTO_RC4, PADDING_SIZE_RANGE,
+    VC,
 };
 
 // Send empty initial payload for now.
 const SELF_INITIAL_PAYLOAD: [u8; 0] = [];
 
-impl<Stream> Handshake<'_, Stream, ConnectSide>
+impl<Stream> Handshake<Stream, ConnectSide>
 where
     Stream: StreamRecv<Error = Error> + StreamSend<Error = Error> + Send,
 {
@@ -33,15 +33,16 @@ where
 
     async fn handshake_impl(mut self) -> Result<MseStream<Stream>, Error> {
         self.exchange_key().await?;
+        self.new_ciphers();
 
         let hash_1 = self.compute_hash_1();
-        let hash_2 = self.compute_hash_2();
+        let hash_2 = self.compute_hash_2(&self.info_hash);
         {
             let mut buffer = self.stream.send_buffer();
             buffer.put_slice(&hash_1);
             buffer.put_slice(&hash_2);
         }
-        let crypto_provide = load_crypto_provide();
+        let crypto_provide = self.policy.crypto_provide();
         self.put_crypto_provide(crypto_provide);
         self.stream.send_all().await?;
 
@@ -66,6 +67,8 @@ where
 
         self.recv_padding().await?;
 
+        // In case the peer selects more than one crypto method.
+        let crypto_select = self.policy.crypto_select(crypto_select);
         Ok(self.finish(crypto_select))
     }
 
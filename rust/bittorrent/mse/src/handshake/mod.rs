@@ -3,16 +3,22 @@ mod common_impl;
 mod connect_impl;
 mod dh;
 
+use std::collections::HashMap;
 use std::io::Error;
 use std::marker::PhantomData;
 use std::ops::RangeInclusive;
 use std::time::Duration;
 
+use bytes::Bytes;
 use crypto_bigint::{ByteArray, U768};
 
 use g1_tokio::bstream::{StreamRecv, StreamSend};
 
-use crate::{cipher::MseRc4, MseStream};
+use bittorrent_base::InfoHash;
+
+use crate::{cipher::MseRc4, MsePolicy, MseStream, HASH_SIZE};
+
+pub use self::common_impl::compute_skey_hash;
 
 g1_param::define!(
     timeout: Duration = Duration::from_secs(60);
@@ -27,20 +33,138 @@ pub async fn connect<Stream>(stream: Stream, info_hash: &[u8]) -> Result<MseStre
 where
     Stream: StreamRecv<Error = Error> + StreamSend<Error = Error> + Send,
 {
-    Handshake::<_, ConnectSide>::new(stream, info_hash)
+    connect_with_policy(stream, info_hash, crate::load_policy()).await
+}
+
+pub async fn connect_with_policy<Stream>(
+    stream: Stream,
+    info_hash: &[u8],
+    policy: MsePolicy,
+) -> Result<MseStream<Stream>, Error>
+where
+    Stream: StreamRecv<Error = Error> + StreamSend<Error = Error> + Send,
+{
+    Handshake::<_, ConnectSide>::new(stream, Bytes::copy_from_slice(info_hash), policy)
         .handshake()
         .await
 }
 
+/// Accepts either an MSE handshake or, if the peer does not implement MSE, a plaintext BitTorrent
+/// handshake, which is left in the stream's receive buffer.
 pub async fn accept<Stream>(stream: Stream, info_hash: &[u8]) -> Result<MseStream<Stream>, Error>
 where
     Stream: StreamRecv<Error = Error> + StreamSend<Error = Error> + Send,
 {
-    Handshake::<_, AcceptSide>::new(stream, info_hash)
-        .handshake()
+    accept_with_policy(stream, info_hash, crate::load_policy()).await
+}
+
+pub async fn accept_with_policy<Stream>(
+    stream: Stream,
+    info_hash: &[u8],
+    policy: MsePolicy,
+) -> Result<MseStream<Stream>, Error>
+where
+    Stream: StreamRecv<Error = Error> + StreamSend<Error = Error> + Send,
+{
+    let expect = compute_skey_hash(info_hash);
+    accept_with_lookup_and_policy(
+        stream,
+        |skey_hash| (*skey_hash == expect).then_some(info_hash),
+        policy,
+    )
+    .await
+}
+
+/// Same as `accept`, except that it accepts an MSE handshake for any of the info hashes, so that a
+/// listener may serve multiple torrents.
+///
+/// The caller should learn which info hash the peer chooses from the BitTorrent handshake that
+/// follows.
+pub async fn accept_auto<Stream>(
+    stream: Stream,
+    skey_hashes: &SkeyHashes,
+) -> Result<MseStream<Stream>, Error>
+where
+    Stream: StreamRecv<Error = Error> + StreamSend<Error = Error> + Send,
+{
+    accept_auto_with_policy(stream, skey_hashes, crate::load_policy()).await
+}
+
+pub async fn accept_auto_with_policy<Stream>(
+    stream: Stream,
+    skey_hashes: &SkeyHashes,
+    policy: MsePolicy,
+) -> Result<MseStream<Stream>, Error>
+where
+    Stream: StreamRecv<Error = Error> + StreamSend<Error = Error> + Send,
+{
+    accept_with_lookup_and_policy(stream, |skey_hash| skey_hashes.get(skey_hash), policy).await
+}
+
+/// Same as `accept`, except that it looks up the info hash (SKEY) that the peer chooses by
+/// HASH('req2', SKEY), so that a listener may serve many torrents with a precomputed table; see
+/// `compute_skey_hash`.
+pub async fn accept_with<Stream, Lookup>(
+    stream: Stream,
+    lookup: Lookup,
+) -> Result<MseStream<Stream>, Error>
+where
+    Stream: StreamRecv<Error = Error> + StreamSend<Error = Error> + Send,
+    Lookup: FnOnce(&[u8; HASH_SIZE]) -> Option<InfoHash>,
+{
+    accept_with_lookup_and_policy(stream, lookup, crate::load_policy()).await
+}
+
+pub async fn accept_with_lookup_and_policy<Stream, Lookup, Skey>(
+    stream: Stream,
+    lookup: Lookup,
+    policy: MsePolicy,
+) -> Result<MseStream<Stream>, Error>
+where
+    Stream: StreamRecv<Error = Error> + StreamSend<Error = Error> + Send,
+    Lookup: FnOnce(&[u8; HASH_SIZE]) -> Option<Skey>,
+    Skey: AsRef<[u8]>,
+{
+    // The info hash is set when we receive hash_2.
+    Handshake::<_, AcceptSide>::new(stream, Bytes::new(), policy)
+        .handshake(lookup)
         .await
 }
 
+/// Info hashes keyed by HASH('req2', SKEY), which a listener that serves multiple torrents builds
+/// once rather than on every handshake.
+#[derive(Clone, Debug, Default)]
+pub struct SkeyHashes(HashMap<[u8; HASH_SIZE], Bytes>);
+
+impl SkeyHashes {
+    pub fn new<I, T>(info_hashes: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<[u8]>,
+    {
+        let mut this = Self::default();
+        for info_hash in info_hashes {
+            this.insert(info_hash.as_ref());
+        }
+        this
+    }
+
+    pub fn insert(&mut self, info_hash: &[u8]) {
+        self.0.insert(
+            compute_skey_hash(info_hash),
+            Bytes::copy_from_slice(info_hash),
+        );
+    }
+
+    pub fn remove(&mut self, info_hash: &[u8]) {
+        self.0.remove(&compute_skey_hash(info_hash));
+    }
+
+    fn get(&self, skey_hash: &[u8; HASH_SIZE]) -> Option<Bytes> {
+        self.0.get(skey_hash).cloned()
+    }
+}
+
 // Exposed to `cipher`.
 pub(crate) type DhKey = U768;
 
@@ -56,9 +180,10 @@ const VC: [u8; 8] = [0u8; 8];
 const CRYPTO_PLAINTEXT: u32 = 0x00000001;
 const CRYPTO_RC4: u32 = 0x00000002;
 
-struct Handshake<'a, Stream, Side> {
+struct Handshake<Stream, Side> {
     stream: Stream,
-    info_hash: &'a [u8],
+    info_hash: Bytes,
+    policy: MsePolicy,
     private_key: DhKey,
     self_public_key: DhKey,
     secret: ByteArray<DhKey>,
@@ -86,8 +211,28 @@ impl HandshakeSide for AcceptSide {
     }
 }
 
-fn load_crypto_provide() -> u32 {
-    CRYPTO_PLAINTEXT | if *crate::rc4_enable() { CRYPTO_RC4 } else { 0 }
+impl MsePolicy {
+    fn crypto_provide(self) -> u32 {
+        match self {
+            Self::RequireRc4 => CRYPTO_RC4,
+            Self::PreferRc4 | Self::AllowPlaintext => CRYPTO_PLAINTEXT | CRYPTO_RC4,
+            Self::RequirePlaintext => CRYPTO_PLAINTEXT,
+        }
+    }
+
+    /// Selects one crypto method from those that both sides provide.
+    fn crypto_select(self, crypto_provide: u32) -> u32 {
+        let crypto_provide = crypto_provide & self.crypto_provide();
+        let preferred = match self {
+            Self::AllowPlaintext => CRYPTO_PLAINTEXT,
+            _ => CRYPTO_RC4,
+        };
+        if (crypto_provide & preferred) != 0 {
+            preferred
+        } else {
+            crypto_provide & !preferred
+        }
+    }
 }
 
 fn encode_size(x: usize) -> [u8; 2] {
@@ -96,9 +241,12 @@ fn encode_size(x: usize) -> [u8; 2] {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+
     use bytes::BufMut;
-    use tokio::io;
+    use tokio::io::{self, AsyncWriteExt, DuplexStream};
 
+    use g1_test::faults::{FaultConfig, Faults, FaultyIo};
     use g1_tokio::{
         bstream::StreamBuffer,
         io::{DynStream, Stream},
@@ -106,6 +254,11 @@ mod tests {
 
     use super::*;
 
+    fn new_faulty_mock(faults: &Faults) -> (Stream<FaultyIo<DuplexStream>>, DuplexStream) {
+        let (stream, mock) = io::duplex(4096);
+        (Stream::new(FaultyIo::new(stream, faults.clone())), mock)
+    }
+
     #[tokio::test]
     async fn handshake() {
         let (stream_a, mut mock_a) = Stream::new_mock(4096);
@@ -135,6 +288,238 @@ mod tests {
         copy_task.await.unwrap().unwrap();
     }
 
+    #[tokio::test]
+    async fn handshake_auto() {
+        let (stream_a, mut mock_a) = Stream::new_mock(4096);
+        let (stream_b, mut mock_b) = Stream::new_mock(4096);
+
+        let peer_a_task = tokio::spawn(async move {
+            let mut stream_a = DynStream::from(connect(stream_a, b"bar").await?);
+            stream_a.send_buffer().put_slice(b"ping");
+            stream_a.send_all().await?;
+            Ok::<_, Error>(())
+        });
+        let peer_b_task = tokio::spawn(async move {
+            let mut stream_b = DynStream::from(
+                accept_auto(
+                    stream_b,
+                    &SkeyHashes::new([b"foo".as_slice(), b"bar", b"spam"]),
+                )
+                .await?,
+            );
+            stream_b.recv_fill(4).await?;
+            assert_eq!(stream_b.recv_buffer().as_ref(), b"ping");
+            Ok::<_, Error>(())
+        });
+        let copy_task =
+            tokio::spawn(async move { io::copy_bidirectional(&mut mock_a, &mut mock_b).await });
+
+        peer_a_task.await.unwrap().unwrap();
+        peer_b_task.await.unwrap().unwrap();
+        copy_task.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn handshake_lookup() {
+        let info_hash = InfoHash::new([1u8; 20]);
+        let table = HashMap::from([(compute_skey_hash(info_hash.as_ref()), info_hash.clone())]);
+
+        let (stream_a, mut mock_a) = Stream::new_mock(4096);
+        let (stream_b, mut mock_b) = Stream::new_mock(4096);
+
+        let peer_a_task = tokio::spawn(async move {
+            let mut stream_a = DynStream::from(connect(stream_a, info_hash.as_ref()).await?);
+            stream_a.send_buffer().put_slice(b"ping");
+            stream_a.send_all().await?;
+            Ok::<_, Error>(())
+        });
+        let peer_b_task = tokio::spawn(async move {
+            let mut stream_b = DynStream::from(
+                accept_with(stream_b, |skey_hash| table.get(skey_hash).cloned()).await?,
+            );
+            stream_b.recv_fill(4).await?;
+            assert_eq!(stream_b.recv_buffer().as_ref(), b"ping");
+            Ok::<_, Error>(())
+        });
+        let copy_task =
+            tokio::spawn(async move { io::copy_bidirectional(&mut mock_a, &mut mock_b).await });
+
+        peer_a_task.await.unwrap().unwrap();
+        peer_b_task.await.unwrap().unwrap();
+        copy_task.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn handshake_auto_no_match() {
+        let (stream_a, mut mock_a) = Stream::new_mock(4096);
+        let (stream_b, mut mock_b) = Stream::new_mock(4096);
+
+        let peer_a_task = tokio::spawn(async move { connect(stream_a, b"bar").await.map(drop) });
+        let peer_b_task = tokio::spawn(async move {
+            accept_auto(stream_b, &SkeyHashes::new([b"foo".as_slice(), b"spam"]))
+                .await
+                .map(drop)
+        });
+        let copy_task =
+            tokio::spawn(async move { io::copy_bidirectional(&mut mock_a, &mut mock_b).await });
+
+        assert!(peer_a_task.await.unwrap().is_err());
+        assert!(peer_b_task.await.unwrap().is_err());
+        let _ = copy_task.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn handshake_auto_plaintext() {
+        let (stream, mut mock) = Stream::new_mock(4096);
+        mock.write_all(b"\x13BitTorrent protocolping")
+            .await
+            .unwrap();
+        let mut stream = DynStream::from(
+            accept_auto(stream, &SkeyHashes::new([b"foo", b"bar"]))
+                .await
+                .unwrap(),
+        );
+        stream.recv_fill(1 + 19 + 4).await.unwrap();
+        assert_eq!(
+            stream.recv_buffer().as_ref(),
+            b"\x13BitTorrent protocolping",
+        );
+    }
+
+    #[tokio::test]
+    async fn handshake_policy() {
+        async fn test(connect_policy: MsePolicy, accept_policy: MsePolicy) -> [Option<bool>; 2] {
+            let (stream_a, mut mock_a) = Stream::new_mock(4096);
+            let (stream_b, mut mock_b) = Stream::new_mock(4096);
+            let peer_a_task =
+                tokio::spawn(
+                    async move { connect_with_policy(stream_a, b"foo", connect_policy).await },
+                );
+            let peer_b_task =
+                tokio::spawn(
+                    async move { accept_with_policy(stream_b, b"foo", accept_policy).await },
+                );
+            let copy_task =
+                tokio::spawn(async move { io::copy_bidirectional(&mut mock_a, &mut mock_b).await });
+            let is_rc4 = |stream: Result<MseStream<_>, _>| {
+                stream
+                    .ok()
+                    .map(|stream| matches!(stream, MseStream::Rc4(_)))
+            };
+            let is_rc4 = [
+                is_rc4(peer_a_task.await.unwrap()),
+                is_rc4(peer_b_task.await.unwrap()),
+            ];
+            let _ = copy_task.await.unwrap();
+            is_rc4
+        }
+
+        use MsePolicy::*;
+        for (connect_policy, accept_policy, expect) in [
+            (PreferRc4, PreferRc4, Some(true)),
+            (PreferRc4, AllowPlaintext, Some(false)),
+            (AllowPlaintext, PreferRc4, Some(true)),
+            (RequireRc4, AllowPlaintext, Some(true)),
+            (RequirePlaintext, PreferRc4, Some(false)),
+            (RequireRc4, RequirePlaintext, None),
+            (RequirePlaintext, RequireRc4, None),
+        ] {
+            assert_eq!(
+                test(connect_policy, accept_policy).await,
+                [expect, expect],
+                "{connect_policy:?} {accept_policy:?}",
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn peer_not_implement_mse_require_rc4() {
+        let (stream, mut mock) = Stream::new_mock(4096);
+        mock.write_all(b"\x13BitTorrent protocol").await.unwrap();
+        assert!(accept_with_policy(stream, b"foo", MsePolicy::RequireRc4)
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn crypto_select() {
+        use MsePolicy::*;
+        const BOTH: u32 = CRYPTO_PLAINTEXT | CRYPTO_RC4;
+        for (policy, crypto_provide, expect) in [
+            (RequireRc4, BOTH, CRYPTO_RC4),
+            (RequireRc4, CRYPTO_PLAINTEXT, 0),
+            (PreferRc4, BOTH, CRYPTO_RC4),
+            (PreferRc4, CRYPTO_PLAINTEXT, CRYPTO_PLAINTEXT),
+            (PreferRc4, 0x4, 0),
+            (AllowPlaintext, BOTH, CRYPTO_PLAINTEXT),
+            (AllowPlaintext, CRYPTO_RC4, CRYPTO_RC4),
+            (RequirePlaintext, BOTH, CRYPTO_PLAINTEXT),
+            (RequirePlaintext, CRYPTO_RC4, 0),
+        ] {
+            assert_eq!(
+                policy.crypto_select(crypto_provide),
+                expect,
+                "{policy:?} {crypto_provide}",
+            );
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn handshake_latency() {
+        let faults = Faults::new(0);
+        faults.set(FaultConfig {
+            latency: Duration::from_millis(100),
+            ..Default::default()
+        });
+        let (stream_a, mut mock_a) = new_faulty_mock(&faults);
+        let (stream_b, mut mock_b) = new_faulty_mock(&faults);
+
+        let peer_a_task = tokio::spawn(async move {
+            let mut stream_a = DynStream::from(connect(stream_a, b"foo").await?);
+            stream_a.send_buffer().put_slice(b"ping");
+            stream_a.send_all().await?;
+            stream_a.recv_fill(4).await?;
+            assert_eq!(stream_a.recv_buffer().as_ref(), b"pong");
+            Ok::<_, Error>(())
+        });
+        let peer_b_task = tokio::spawn(async move {
+            let mut stream_b = DynStream::from(accept(stream_b, b"foo").await?);
+            stream_b.recv_fill(4).await?;
+            assert_eq!(stream_b.recv_buffer().as_ref(), b"ping");
+            stream_b.send_buffer().put_slice(b"pong");
+            stream_b.send_all().await?;
+            Ok::<_, Error>(())
+        });
+        let copy_task =
+            tokio::spawn(async move { io::copy_bidirectional(&mut mock_a, &mut mock_b).await });
+
+        peer_a_task.await.unwrap().unwrap();
+        peer_b_task.await.unwrap().unwrap();
+        copy_task.await.unwrap().unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn handshake_corrupted() {
+        let faults = Faults::new(0);
+        faults.set(FaultConfig {
+            corrupt_rate: 1.0,
+            ..Default::default()
+        });
+        let (stream_a, mut mock_a) = new_faulty_mock(&faults);
+        let (stream_b, mut mock_b) = new_faulty_mock(&faults);
+
+        let peer_a_task = tokio::spawn(async move { connect(stream_a, b"foo").await.map(drop) });
+        let peer_b_task = tokio::spawn(async move { accept(stream_b, b"foo").await.map(drop) });
+        let copy_task =
+            tokio::spawn(async move { io::copy_bidirectional(&mut mock_a, &mut mock_b).await });
+
+        // Both sides should fail, rather than hang or succeed with garbled keys.
+        assert!(peer_a_task.await.unwrap().is_err());
+        assert!(peer_b_task.await.unwrap().is_err());
+        assert!(faults.stats().num_corrupted > 0);
+        let _ = copy_task.await.unwrap();
+    }
+
     #[tokio::test]
     async fn peer_not_implement_mse() {
         let (mut stream_a, mut mock_a) = Stream::new_mock(4096);
@@ -7,6 +7,7 @@ mod handshake;
 
 use std::io::Error;
 
+use serde::Deserialize;
 use sha1::{digest::Output, Digest, Sha1, Sha1Core};
 
 use g1_tokio::{
@@ -16,9 +17,45 @@ use g1_tokio::{
 
 use self::cipher::{MseRc4, Plaintext};
 
-pub use self::handshake::{accept, connect};
+pub use self::handshake::{
+    accept, accept_auto, accept_auto_with_policy, accept_with, accept_with_lookup_and_policy,
+    accept_with_policy, compute_skey_hash, connect, connect_with_policy, SkeyHashes,
+};
+
+// Applied to handshakes that do not override it.
+g1_param::define!(policy: MsePolicy = Default::default());
 
-g1_param::define!(rc4_enable: bool = true);
+g1_param::define!(
+    /// Superseded by `policy`: `true` maps to `prefer_rc4`, and `false` to `allow_plaintext`.
+    /// When set, it overrides `policy`.
+    #[deprecated(note = "use `policy` instead")]
+    rc4_enable: Option<bool> = None
+);
+
+/// Crypto methods that we provide and select in a handshake.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MsePolicy {
+    /// Provide RC4 only, and reject peers that do not implement MSE.
+    RequireRc4,
+    /// Provide both, and select RC4 when the peer provides it.
+    #[default]
+    PreferRc4,
+    /// Provide both, and select plaintext when the peer provides it.
+    AllowPlaintext,
+    /// Provide plaintext only.
+    RequirePlaintext,
+}
+
+/// Returns the policy applied to handshakes that do not override it.
+#[allow(deprecated)]
+fn load_policy() -> MsePolicy {
+    match *rc4_enable() {
+        Some(true) => MsePolicy::PreferRc4,
+        Some(false) => MsePolicy::AllowPlaintext,
+        None => *policy(),
+    }
+}
 
 // Implementer's Notes: Our strategy is to defer the creation of trait objects to the latest
 // possible point, as Rust is not great in supporting trait objects.
@@ -51,7 +88,7 @@ where
     }
 }
 
-pub(crate) const HASH_SIZE: usize = 20;
+pub const HASH_SIZE: usize = 20;
 
 pub(crate) fn compute_hash<'a, I>(data_iter: I) -> Output<Sha1Core>
 where
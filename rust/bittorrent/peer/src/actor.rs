@@ -12,15 +12,16 @@ use g1_tokio::bstream::{StreamRecv, StreamSend};
 use g1_tokio::task::Cancel;
 
 use bittorrent_base::{BlockDesc, PieceIndex};
-use bittorrent_extension::{ExtensionIdMap, Message as ExtensionMessage};
+use bittorrent_extension::{Deviation, ExtensionIdMap, Message as ExtensionMessage};
 use bittorrent_socket::{Message, Socket};
 
 use crate::{
     chan::{Endpoint, Sends},
     incoming::{self, Reject, Response},
+    misbehavior::Score,
     outgoing,
     state::ConnStateLower,
-    Full, KeepAliveTimeout, Possession,
+    Full, KeepAliveTimeout, Misbehaving, Possession,
 };
 
 #[derive(Debug)]
@@ -41,6 +42,8 @@ pub(crate) struct Actor<Stream> {
 
     peer_allowed_fast: HashSet<PieceIndex>,
 
+    misbehavior: Score,
+
     peer_endpoint: Endpoint,
     sends: Sends,
 }
@@ -85,6 +88,7 @@ where
             recv_keep_alive_interval: time::interval(*crate::recv_keep_alive_timeout()),
             send_keep_alive_interval: time::interval(*crate::send_keep_alive_timeout()),
             peer_allowed_fast: HashSet::new(),
+            misbehavior: Score::new(),
             peer_endpoint,
             sends,
         }
@@ -268,7 +272,17 @@ where
             }
 
             Message::Extended(id, payload) => {
-                let message = bittorrent_extension::decode(id, payload).map_err(Error::other)?;
+                let (message, deviation) = bittorrent_extension::decode_checked(id, payload);
+                if let Some(deviation) = deviation {
+                    self.handle_deviation(deviation)?;
+                }
+                let message = match message {
+                    Ok(message) => message,
+                    Err(error) => {
+                        tracing::debug!(id, %error, "drop undecodable extension message");
+                        return Ok(());
+                    }
+                };
                 if let ExtensionMessage::Handshake(handshake) = message.deref() {
                     self.extension_ids.must_lock().update(handshake);
                 }
@@ -278,6 +292,16 @@ where
         }
     }
 
+    fn handle_deviation(&mut self, deviation: Deviation) -> Result<(), Error> {
+        tracing::debug!(?deviation, "peer protocol deviation");
+        if self.misbehavior.add(deviation) {
+            let score = self.misbehavior.get();
+            tracing::warn!(score, "disconnect misbehaving peer");
+            return Err(Error::new(ErrorKind::InvalidData, Misbehaving { score }));
+        }
+        Ok(())
+    }
+
     async fn handle_self_choking(&mut self, value: bool) -> Result<(), Error> {
         self.send(if value {
             Message::Choke
@@ -666,7 +690,7 @@ mod tests {
         let (mut actor, mock, .., mut recvs) = Actor::new_mock();
         assert_eq!(
             actor.extension_ids.must_lock().peer_extensions(),
-            Enabled::new(false, false),
+            Enabled::new(false, false, false, false),
         );
         assert_matches!(
             actor
@@ -683,7 +707,27 @@ mod tests {
         );
         assert_eq!(
             actor.extension_ids.must_lock().peer_extensions(),
-            Enabled::new(true, false),
+            Enabled::new(true, false, false, false),
+        );
+        drop(actor);
+        assert_mock(mock, &[]).await;
+    }
+
+    #[tokio::test]
+    async fn handle_recv_extended_deviation() {
+        let (mut actor, mock, .., mut recvs) = Actor::new_mock();
+        for _ in 0..*crate::misbehavior_threshold() / 10 {
+            assert_matches!(
+                actor.handle_recv(Message::Extended(99, Bytes::new())).await,
+                Ok(()),
+            );
+        }
+        assert_matches!(recvs.extension_recv.try_recv(), Err(_));
+        assert_matches!(
+            actor
+                .handle_recv(Message::Extended(99, Bytes::new()))
+                .await,
+            Err(error) if error.kind() == ErrorKind::InvalidData,
         );
         drop(actor);
         assert_mock(mock, &[]).await;
@@ -3,6 +3,7 @@
 mod actor;
 mod chan;
 mod incoming;
+mod misbehavior;
 mod outgoing;
 mod peer;
 mod state;
@@ -31,7 +32,7 @@ g1_param::define!(
 );
 
 g1_param::define!(interested_queue_size: usize = 256);
-g1_param::define!(request_queue_size: usize = 256);
+g1_param::define!(pub request_queue_size: usize = 256);
 
 g1_param::define!(possession_queue_size: usize = 256);
 g1_param::define!(suggest_queue_size: usize = 256);
@@ -42,12 +43,25 @@ g1_param::define!(port_queue_size: usize = 256);
 
 g1_param::define!(extension_queue_size: usize = 256);
 
+// A peer is disconnected when its misbehavior score exceeds the threshold.
+g1_param::define!(misbehavior_threshold: u32 = 100);
+g1_param::define!(
+    misbehavior_decay_period: Duration = Duration::from_secs(10);
+    parse = g1_param::parse::duration;
+);
+
 pub use crate::chan::{new_channels, Endpoint, ExtensionMessageOwner, Recvs, Sends};
 pub use crate::peer::{Peer, PeerGuard};
 
 #[derive(Clone, Debug, Eq, PartialEq, Snafu)]
 pub struct KeepAliveTimeout;
 
+#[derive(Clone, Debug, Eq, PartialEq, Snafu)]
+#[snafu(display("peer misbehavior score exceeds threshold: {score}"))]
+pub struct Misbehaving {
+    pub score: u32,
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Incompatible;
 
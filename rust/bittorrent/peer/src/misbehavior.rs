@@ -0,0 +1,88 @@
+//! Misbehavior Score
+//!
+//! We tolerate occasional protocol deviations (some implementations are merely quirky), but we
+//! disconnect a peer whose score, which decays over time, exceeds the threshold.
+
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use bittorrent_extension::Deviation;
+
+#[derive(Debug)]
+pub(crate) struct Score {
+    score: u32,
+    last_decay: Instant,
+}
+
+fn weight(deviation: Deviation) -> u32 {
+    match deviation {
+        Deviation::LenientDecode => 1,
+        Deviation::UnknownExtensionId(_) => 10,
+        Deviation::InvalidPiece(_) | Deviation::Malformed => 25,
+    }
+}
+
+impl Score {
+    pub(crate) fn new() -> Self {
+        Self {
+            score: 0,
+            last_decay: Instant::now(),
+        }
+    }
+
+    /// Adds the deviation to the score and returns true if the score exceeds the threshold.
+    pub(crate) fn add(&mut self, deviation: Deviation) -> bool {
+        self.decay(*crate::misbehavior_decay_period());
+        self.score = self.score.saturating_add(weight(deviation));
+        self.score > *crate::misbehavior_threshold()
+    }
+
+    pub(crate) fn get(&self) -> u32 {
+        self.score
+    }
+
+    /// Decays the score by one point per `period`.
+    fn decay(&mut self, period: Duration) {
+        if period.is_zero() {
+            self.score = 0;
+            self.last_decay = Instant::now();
+            return;
+        }
+        let n = self.last_decay.elapsed().as_nanos() / period.as_nanos();
+        if n > 0 {
+            self.score = self.score.saturating_sub(n.try_into().unwrap_or(u32::MAX));
+            self.last_decay += period * u32::try_from(n).unwrap_or(u32::MAX);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn add() {
+        let period = *crate::misbehavior_decay_period();
+        let threshold = *crate::misbehavior_threshold();
+
+        let mut score = Score::new();
+        assert_eq!(score.add(Deviation::LenientDecode), false);
+        assert_eq!(score.get(), 1);
+
+        tokio::time::advance(period).await;
+        assert_eq!(score.add(Deviation::UnknownExtensionId(99)), false);
+        assert_eq!(score.get(), 10);
+
+        tokio::time::advance(period * 5).await;
+        assert_eq!(score.add(Deviation::LenientDecode), false);
+        assert_eq!(score.get(), 6);
+
+        while !score.add(Deviation::Malformed) {}
+        assert!(score.get() > threshold);
+
+        tokio::time::advance(period * score.get()).await;
+        assert_eq!(score.add(Deviation::LenientDecode), false);
+        assert_eq!(score.get(), 1);
+    }
+}
@@ -124,6 +124,10 @@ impl Peer {
         self.0.extension_ids.must_lock().peer_extensions()
     }
 
+    pub fn peer_upload_only(&self) -> bool {
+        self.0.extension_ids.must_lock().peer_upload_only()
+    }
+
     pub fn cancel(&self) {
         self.0.cancel.set();
     }
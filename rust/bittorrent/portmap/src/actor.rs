@@ -0,0 +1,194 @@
+use std::io::Error;
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::Duration;
+
+use tokio::sync::watch;
+use tokio::time;
+
+use g1_tokio::task::Cancel;
+
+use crate::{gateway, natpmp, upnp, Mapping};
+
+// Guard against gateways that grant absurdly short lifetimes.
+const MIN_REFRESH_PERIOD: Duration = Duration::from_secs(30);
+
+// It should be shorter than `JoinGuard`'s shutdown timeout.
+const UNMAP_TIMEOUT: Duration = Duration::from_millis(500);
+
+#[derive(Debug)]
+pub(crate) struct Actor {
+    cancel: Cancel,
+    mappings: Vec<Mapping>,
+    external_address_send: watch::Sender<Option<IpAddr>>,
+    mappings_send: watch::Sender<Vec<Mapping>>,
+}
+
+#[derive(Debug)]
+enum Gateway {
+    NatPmp(natpmp::Client),
+    Upnp(upnp::Client),
+}
+
+impl Actor {
+    pub(crate) fn new(
+        cancel: Cancel,
+        mappings: Vec<Mapping>,
+        external_address_send: watch::Sender<Option<IpAddr>>,
+        mappings_send: watch::Sender<Vec<Mapping>>,
+    ) -> Self {
+        Self {
+            cancel,
+            mappings,
+            external_address_send,
+            mappings_send,
+        }
+    }
+
+    pub(crate) async fn run(self) -> Result<(), Error> {
+        let mut gateway = None;
+        loop {
+            let result = tokio::select! {
+                () = self.cancel.wait() => break,
+                result = self.refresh(&mut gateway) => result,
+            };
+            let period = match result {
+                // Renew the mappings halfway through their lifetime, as recommended by RFC 6886.
+                Ok(lifetime) => (lifetime / 2).max(MIN_REFRESH_PERIOD),
+                Err(error) => {
+                    tracing::warn!(%error, "port mapping");
+                    // Re-discover the gateway, as it might have changed.
+                    gateway = None;
+                    self.external_address_send.send_replace(None);
+                    self.mappings_send.send_replace(Vec::new());
+                    *crate::retry_period()
+                }
+            };
+            tokio::select! {
+                () = self.cancel.wait() => break,
+                () = time::sleep(period) => {}
+            }
+        }
+
+        if let Some(gateway) = gateway {
+            if time::timeout(UNMAP_TIMEOUT, self.unmap(&gateway))
+                .await
+                .is_err()
+            {
+                tracing::warn!("unmap timeout");
+            }
+        }
+        Ok(())
+    }
+
+    /// Maps (or re-maps) all ports and returns the shortest lifetime among them.
+    async fn refresh(&self, gateway: &mut Option<Gateway>) -> Result<Duration, Error> {
+        if gateway.is_none() {
+            *gateway = Some(Gateway::discover().await?);
+        }
+        let gateway = gateway.as_ref().unwrap();
+
+        let external_address = IpAddr::V4(gateway.external_address().await?);
+        self.external_address_send.send_if_modified(|address| {
+            if *address == Some(external_address) {
+                return false;
+            }
+            tracing::info!(%external_address, "external address");
+            *address = Some(external_address);
+            true
+        });
+
+        let mut lifetime = *crate::lease_duration();
+        let mut mappings = Vec::with_capacity(self.mappings.len());
+        for mapping in &self.mappings {
+            // Suggest the external port that the gateway granted earlier, as RFC 6886 recommends.
+            let mapping = self
+                .mappings_send
+                .borrow()
+                .iter()
+                .find(|granted| {
+                    (granted.protocol, granted.port) == (mapping.protocol, mapping.port)
+                })
+                .copied()
+                .unwrap_or(*mapping);
+            let (granted, granted_lifetime) =
+                gateway.map(mapping, *crate::lease_duration()).await?;
+            tracing::debug!(?granted, ?granted_lifetime, "map");
+            lifetime = lifetime.min(granted_lifetime);
+            mappings.push(granted);
+        }
+        self.mappings_send.send_if_modified(|current| {
+            if *current == mappings {
+                return false;
+            }
+            tracing::info!(?mappings, "port mappings");
+            *current = mappings;
+            true
+        });
+        Ok(lifetime)
+    }
+
+    async fn unmap(&self, gateway: &Gateway) {
+        for mapping in &self.mappings {
+            match gateway.unmap(*mapping).await {
+                Ok(()) => tracing::debug!(?mapping, "unmap"),
+                Err(error) => tracing::warn!(?mapping, %error, "unmap"),
+            }
+        }
+    }
+}
+
+impl Gateway {
+    async fn discover() -> Result<Self, Error> {
+        // UPnP IGD does not need the default gateway, and so we fall through to it on error.
+        match gateway::default_gateway() {
+            Ok(Some(gateway)) => match Self::discover_natpmp(gateway).await {
+                Ok(client) => return Ok(client),
+                Err(error) => tracing::debug!(%gateway, %error, "nat-pmp"),
+            },
+            Ok(None) => tracing::debug!("default gateway not found"),
+            Err(error) => tracing::warn!(%error, "default gateway"),
+        }
+        let client = upnp::Client::discover().await?;
+        tracing::info!("use upnp igd");
+        Ok(Self::Upnp(client))
+    }
+
+    async fn discover_natpmp(gateway: Ipv4Addr) -> Result<Self, Error> {
+        let client = natpmp::Client::connect(gateway).await?;
+        // Probe the gateway to find out whether it supports NAT-PMP.
+        client.external_address().await?;
+        tracing::info!(%gateway, "use nat-pmp");
+        Ok(Self::NatPmp(client))
+    }
+
+    async fn external_address(&self) -> Result<Ipv4Addr, Error> {
+        match self {
+            Self::NatPmp(client) => client.external_address().await,
+            Self::Upnp(client) => client.external_address().await,
+        }
+    }
+
+    /// Maps the port and returns the granted mapping and lifetime.
+    async fn map(
+        &self,
+        mapping: Mapping,
+        lifetime: Duration,
+    ) -> Result<(Mapping, Duration), Error> {
+        match self {
+            Self::NatPmp(client) => client.map(mapping, lifetime).await,
+            // UPnP IGD either grants the requested external port or fails, and since we always
+            // request the internal port, so is the granted one.
+            Self::Upnp(client) => client
+                .map(mapping.protocol, mapping.port, lifetime)
+                .await
+                .map(|lifetime| (Mapping::new(mapping.protocol, mapping.port), lifetime)),
+        }
+    }
+
+    async fn unmap(&self, mapping: Mapping) -> Result<(), Error> {
+        match self {
+            Self::NatPmp(client) => client.map(mapping, Duration::ZERO).await.map(|_| ()),
+            Self::Upnp(client) => client.unmap(mapping.protocol, mapping.port).await,
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use std::io::Error;
+use std::net::Ipv4Addr;
+
+const ROUTE_PATH: &str = "/proc/net/route";
+
+/// Returns the gateway of the default route.
+pub(crate) fn default_gateway() -> Result<Option<Ipv4Addr>, Error> {
+    if let Some(gateway) = *crate::gateway() {
+        return Ok(Some(gateway));
+    }
+    Ok(parse_route(&std::fs::read_to_string(ROUTE_PATH)?))
+}
+
+fn parse_route(route: &str) -> Option<Ipv4Addr> {
+    // Skip the header line.
+    for line in route.lines().skip(1) {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [_, destination, gateway, ..] = fields.as_slice() else {
+            continue;
+        };
+        if *destination != "00000000" {
+            continue;
+        }
+        // The kernel prints addresses in host byte order.
+        let Ok(gateway) = u32::from_str_radix(gateway, 16) else {
+            continue;
+        };
+        if gateway != 0 {
+            return Some(gateway.to_ne_bytes().into());
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_route() {
+        let gateway = Ipv4Addr::new(192, 168, 1, 1);
+        let gateway_hex = format!("{:08X}", u32::from_ne_bytes(gateway.octets()));
+        let route = format!(
+            "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT\n\
+             eth0\t0001A8C0\t00000000\t0001\t0\t0\t0\t00FFFFFF\t0\t0\t0\n\
+             eth0\t00000000\t{gateway_hex}\t0003\t0\t0\t0\t00000000\t0\t0\t0\n",
+        );
+        assert_eq!(super::parse_route(&route), Some(gateway));
+
+        assert_eq!(super::parse_route(""), None);
+        assert_eq!(
+            super::parse_route(
+                "Iface\tDestination\tGateway\n\
+                 eth0\t0001A8C0\t00000000\n",
+            ),
+            None,
+        );
+    }
+}
@@ -0,0 +1,118 @@
+//! Port Mapping
+//!
+//! It maps the ports of our listeners on the gateway, first with NAT-PMP (RFC 6886) and then,
+//! if that fails, with UPnP IGD.
+
+mod actor;
+mod gateway;
+mod natpmp;
+mod upnp;
+
+use std::fmt;
+use std::io::Error;
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::Duration;
+
+use tokio::sync::watch;
+
+use g1_tokio::task::JoinGuard;
+
+use crate::actor::Actor;
+
+// Overrides the gateway address discovered from the routing table.
+g1_param::define!(gateway: Option<Ipv4Addr> = None);
+
+g1_param::define!(
+    lease_duration: Duration = Duration::from_secs(2 * 3600);
+    parse = g1_param::parse::duration;
+);
+g1_param::define!(
+    retry_period: Duration = Duration::from_secs(5 * 60);
+    parse = g1_param::parse::duration;
+);
+
+g1_param::define!(
+    upnp_search_timeout: Duration = Duration::from_secs(3);
+    parse = g1_param::parse::duration;
+);
+g1_param::define!(
+    upnp_request_timeout: Duration = Duration::from_secs(5);
+    parse = g1_param::parse::duration;
+);
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Mapping {
+    pub protocol: Protocol,
+    pub port: u16,
+    /// The external port that we request, or that the gateway grants, which NAT-PMP gateways may
+    /// choose differently from the requested one.
+    pub external_port: u16,
+}
+
+#[derive(Clone, Debug)]
+pub struct PortMap {
+    external_address_recv: watch::Receiver<Option<IpAddr>>,
+    mappings_recv: watch::Receiver<Vec<Mapping>>,
+}
+
+pub type PortMapGuard = JoinGuard<Result<(), Error>>;
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Tcp => "TCP",
+            Self::Udp => "UDP",
+        })
+    }
+}
+
+impl Mapping {
+    /// Creates a mapping that requests the same external port as the internal one.
+    pub fn new(protocol: Protocol, port: u16) -> Self {
+        Self {
+            protocol,
+            port,
+            external_port: port,
+        }
+    }
+}
+
+impl PortMap {
+    pub fn spawn(mappings: Vec<Mapping>) -> (Self, PortMapGuard) {
+        let (external_address_send, external_address_recv) = watch::channel(None);
+        let (mappings_send, mappings_recv) = watch::channel(Vec::new());
+        (
+            Self {
+                external_address_recv,
+                mappings_recv,
+            },
+            PortMapGuard::spawn(move |cancel| {
+                Actor::new(cancel, mappings, external_address_send, mappings_send).run()
+            }),
+        )
+    }
+
+    /// Returns our external address as reported by the gateway.
+    pub fn external_address(&self) -> Option<IpAddr> {
+        *self.external_address_recv.borrow()
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<Option<IpAddr>> {
+        self.external_address_recv.clone()
+    }
+
+    /// Returns the mappings granted by the gateway, which is empty when there is no gateway.
+    pub fn mappings(&self) -> Vec<Mapping> {
+        self.mappings_recv.borrow().clone()
+    }
+
+    pub fn subscribe_mappings(&self) -> watch::Receiver<Vec<Mapping>> {
+        self.mappings_recv.clone()
+    }
+}
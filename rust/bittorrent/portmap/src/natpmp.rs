@@ -0,0 +1,256 @@
+//! NAT Port Mapping Protocol (RFC 6886)
+
+use std::io::{Error, ErrorKind};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use tokio::net::UdpSocket;
+use tokio::time;
+
+use crate::{Mapping, Protocol};
+
+const PORT: u16 = 5351;
+
+const VERSION: u8 = 0;
+
+const OPCODE_EXTERNAL_ADDRESS: u8 = 0;
+const OPCODE_MAP_UDP: u8 = 1;
+const OPCODE_MAP_TCP: u8 = 2;
+const OPCODE_RESPONSE: u8 = 128;
+
+const RESULT_SUCCESS: u16 = 0;
+
+const EXTERNAL_ADDRESS_RESPONSE_SIZE: usize = 12;
+const MAP_RESPONSE_SIZE: usize = 16;
+
+// RFC 6886 specifies 9 attempts, which would take 64 seconds in total.  We give up much earlier
+// and fall back to UPnP IGD instead.
+const INITIAL_TIMEOUT: Duration = Duration::from_millis(250);
+const NUM_ATTEMPTS: usize = 4;
+
+#[derive(Debug)]
+pub(crate) struct Client {
+    socket: UdpSocket,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+struct MapResponse {
+    internal_port: u16,
+    external_port: u16,
+    lifetime: Duration,
+}
+
+impl Client {
+    pub(crate) async fn connect(gateway: Ipv4Addr) -> Result<Self, Error> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+        socket.connect(SocketAddr::from((gateway, PORT))).await?;
+        Ok(Self { socket })
+    }
+
+    pub(crate) async fn external_address(&self) -> Result<Ipv4Addr, Error> {
+        let response = self
+            .request(&[VERSION, OPCODE_EXTERNAL_ADDRESS], OPCODE_EXTERNAL_ADDRESS)
+            .await?;
+        decode_external_address_response(&response)
+    }
+
+    /// Maps the port and returns the granted mapping and lifetime.
+    ///
+    /// The gateway may grant an external port other than the suggested `mapping.external_port`.
+    /// A zero `lifetime` deletes the mapping.
+    pub(crate) async fn map(
+        &self,
+        mapping: Mapping,
+        lifetime: Duration,
+    ) -> Result<(Mapping, Duration), Error> {
+        let Mapping {
+            protocol,
+            port,
+            external_port,
+        } = mapping;
+        let opcode = match protocol {
+            Protocol::Tcp => OPCODE_MAP_TCP,
+            Protocol::Udp => OPCODE_MAP_UDP,
+        };
+        // When deleting a mapping, the suggested external port must be 0.
+        let external_port = if lifetime.is_zero() { 0 } else { external_port };
+        let request = encode_map_request(opcode, port, external_port, lifetime);
+        let response = decode_map_response(&self.request(&request, opcode).await?)?;
+        if response.internal_port != port {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "expect nat-pmp internal port {}: {}",
+                    port, response.internal_port,
+                ),
+            ));
+        }
+        if !lifetime.is_zero() && response.external_port != external_port {
+            tracing::info!(
+                %protocol,
+                port,
+                external_port = response.external_port,
+                "nat-pmp maps to a different external port",
+            );
+        }
+        Ok((
+            Mapping {
+                external_port: response.external_port,
+                ..mapping
+            },
+            response.lifetime,
+        ))
+    }
+
+    async fn request(&self, request: &[u8], opcode: u8) -> Result<Vec<u8>, Error> {
+        let mut buffer = [0u8; MAP_RESPONSE_SIZE];
+        let mut timeout = INITIAL_TIMEOUT;
+        for _ in 0..NUM_ATTEMPTS {
+            self.socket.send(request).await?;
+            let deadline = time::Instant::now() + timeout;
+            // Discard responses to our earlier requests, if any.
+            while let Ok(size) = time::timeout_at(deadline, self.socket.recv(&mut buffer)).await {
+                let response = &buffer[..size?];
+                if response.len() >= 2 && response[1] == OPCODE_RESPONSE + opcode {
+                    return Ok(response.to_vec());
+                }
+                tracing::debug!(?response, "discard nat-pmp response");
+            }
+            timeout *= 2;
+        }
+        Err(Error::new(ErrorKind::TimedOut, "nat-pmp request timeout"))
+    }
+}
+
+fn encode_map_request(
+    opcode: u8,
+    internal_port: u16,
+    external_port: u16,
+    lifetime: Duration,
+) -> [u8; 12] {
+    let lifetime = u32::try_from(lifetime.as_secs()).unwrap_or(u32::MAX);
+    let mut request = [0u8; 12];
+    request[0] = VERSION;
+    request[1] = opcode;
+    // request[2..4] is reserved.
+    request[4..6].copy_from_slice(&internal_port.to_be_bytes());
+    request[6..8].copy_from_slice(&external_port.to_be_bytes());
+    request[8..12].copy_from_slice(&lifetime.to_be_bytes());
+    request
+}
+
+fn decode_header(response: &[u8], size: usize) -> Result<(), Error> {
+    if response.len() != size {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "expect nat-pmp response size == {}: {}",
+                size,
+                response.len()
+            ),
+        ));
+    }
+    if response[0] != VERSION {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("unsupported nat-pmp version: {}", response[0]),
+        ));
+    }
+    let result = u16::from_be_bytes(response[2..4].try_into().unwrap());
+    if result != RESULT_SUCCESS {
+        return Err(Error::other(format!(
+            "nat-pmp error: {}",
+            result_message(result)
+        )));
+    }
+    // We do not make use of the seconds since start of epoch (`response[4..8]`) for now.
+    Ok(())
+}
+
+fn decode_external_address_response(response: &[u8]) -> Result<Ipv4Addr, Error> {
+    decode_header(response, EXTERNAL_ADDRESS_RESPONSE_SIZE)?;
+    Ok(<[u8; 4]>::try_from(&response[8..12]).unwrap().into())
+}
+
+fn decode_map_response(response: &[u8]) -> Result<MapResponse, Error> {
+    decode_header(response, MAP_RESPONSE_SIZE)?;
+    Ok(MapResponse {
+        internal_port: u16::from_be_bytes(response[8..10].try_into().unwrap()),
+        external_port: u16::from_be_bytes(response[10..12].try_into().unwrap()),
+        lifetime: Duration::from_secs(
+            u32::from_be_bytes(response[12..16].try_into().unwrap()).into(),
+        ),
+    })
+}
+
+fn result_message(result: u16) -> &'static str {
+    match result {
+        1 => "unsupported version",
+        2 => "not authorized or refused",
+        3 => "network failure",
+        4 => "out of resources",
+        5 => "unsupported opcode",
+        _ => "unknown result code",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_map_request() {
+        assert_eq!(
+            super::encode_map_request(OPCODE_MAP_TCP, 6881, 6881, Duration::from_secs(7200)),
+            [0, 2, 0, 0, 0x1a, 0xe1, 0x1a, 0xe1, 0, 0, 0x1c, 0x20],
+        );
+        assert_eq!(
+            super::encode_map_request(OPCODE_MAP_UDP, 6881, 0, Duration::ZERO),
+            [0, 1, 0, 0, 0x1a, 0xe1, 0, 0, 0, 0, 0, 0],
+        );
+    }
+
+    #[test]
+    fn decode_external_address_response() {
+        assert_eq!(
+            super::decode_external_address_response(&[0, 128, 0, 0, 0, 0, 0, 1, 203, 0, 113, 7])
+                .unwrap(),
+            Ipv4Addr::new(203, 0, 113, 7),
+        );
+        assert_eq!(
+            super::decode_external_address_response(&[0, 128, 0, 3, 0, 0, 0, 1, 0, 0, 0, 0])
+                .unwrap_err()
+                .to_string(),
+            "nat-pmp error: network failure",
+        );
+        assert_eq!(
+            super::decode_external_address_response(&[0, 128, 0, 0])
+                .unwrap_err()
+                .kind(),
+            ErrorKind::InvalidData,
+        );
+    }
+
+    #[test]
+    fn decode_map_response() {
+        assert_eq!(
+            super::decode_map_response(&[
+                0, 130, 0, 0, 0, 0, 0, 1, 0x1a, 0xe1, 0x1a, 0xe2, 0, 0, 0x0e, 0x10,
+            ])
+            .unwrap(),
+            MapResponse {
+                internal_port: 6881,
+                external_port: 6882,
+                lifetime: Duration::from_secs(3600),
+            },
+        );
+        assert_eq!(
+            super::decode_map_response(&[
+                1, 130, 0, 0, 0, 0, 0, 1, 0x1a, 0xe1, 0x1a, 0xe2, 0, 0, 0x0e, 0x10,
+            ])
+            .unwrap_err()
+            .kind(),
+            ErrorKind::InvalidData,
+        );
+    }
+}
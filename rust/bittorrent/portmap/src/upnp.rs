@@ -0,0 +1,454 @@
+//! UPnP Internet Gateway Device Protocol
+
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use reqwest::{StatusCode, Url};
+use tokio::net::UdpSocket;
+use tokio::time;
+
+use crate::Protocol;
+
+const SSDP_ENDPOINT: SocketAddr =
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(239, 255, 255, 250)), 1900);
+const SEARCH_TARGET: &str = "urn:schemas-upnp-org:device:InternetGatewayDevice:1";
+
+// In the order of preference.
+const SERVICE_TYPES: &[&str] = &[
+    "urn:schemas-upnp-org:service:WANIPConnection:2",
+    "urn:schemas-upnp-org:service:WANIPConnection:1",
+    "urn:schemas-upnp-org:service:WANPPPConnection:1",
+];
+
+const DESCRIPTION: &str = "bittorrent";
+
+// UPnP error code `OnlyPermanentLeasesSupported`.
+const ONLY_PERMANENT_LEASES_SUPPORTED: &str = "725";
+
+#[derive(Debug)]
+struct Fault {
+    action: String,
+    status: StatusCode,
+    code: Option<String>,
+    description: Option<String>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Client {
+    client: reqwest::Client,
+    control_url: Url,
+    service_type: &'static str,
+    internal_address: Ipv4Addr,
+}
+
+impl Client {
+    pub(crate) async fn discover() -> Result<Self, Error> {
+        let location = search().await?;
+        tracing::debug!(%location, "upnp igd");
+
+        let client = reqwest::Client::builder()
+            .timeout(*crate::upnp_request_timeout())
+            .build()
+            .map_err(Error::other)?;
+
+        let description = client
+            .get(location.clone())
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(Error::other)?
+            .text()
+            .await
+            .map_err(Error::other)?;
+        let (service_type, control_url) = find_service(&description).ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                "upnp igd wan connection service not found",
+            )
+        })?;
+        let control_url = location.join(control_url).map_err(Error::other)?;
+
+        // Find out which of our addresses the gateway can reach.
+        let gateway = control_url
+            .socket_addrs(|| None)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::other(format!("invalid upnp control url: {}", control_url)))?;
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+        socket.connect(gateway).await?;
+        let IpAddr::V4(internal_address) = socket.local_addr()?.ip() else {
+            return Err(Error::other(format!("expect ipv4 gateway: {}", gateway)));
+        };
+
+        Ok(Self {
+            client,
+            control_url,
+            service_type,
+            internal_address,
+        })
+    }
+
+    pub(crate) async fn external_address(&self) -> Result<Ipv4Addr, Error> {
+        let response = self.call("GetExternalIPAddress", &[]).await?;
+        let address = find_element(&response, "NewExternalIPAddress")
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "upnp external address not found"))?;
+        address.trim().parse().map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid upnp external address: {:?}", address),
+            )
+        })
+    }
+
+    /// Maps the port and returns the granted lifetime.
+    pub(crate) async fn map(
+        &self,
+        protocol: Protocol,
+        port: u16,
+        lifetime: Duration,
+    ) -> Result<Duration, Error> {
+        match self.add_port_mapping(protocol, port, lifetime).await {
+            Err(error) if Fault::code(&error) == Some(ONLY_PERMANENT_LEASES_SUPPORTED) => {
+                // Fall back to a permanent lease, which we still refresh as if it were not.
+                tracing::debug!(%protocol, port, "upnp igd only supports permanent leases");
+                self.add_port_mapping(protocol, port, Duration::ZERO)
+                    .await
+                    .map(|()| lifetime)
+            }
+            result => result.map(|()| lifetime),
+        }
+    }
+
+    pub(crate) async fn unmap(&self, protocol: Protocol, port: u16) -> Result<(), Error> {
+        let port = port.to_string();
+        let protocol = protocol.to_string();
+        self.call(
+            "DeletePortMapping",
+            &[
+                ("NewRemoteHost", ""),
+                ("NewExternalPort", &port),
+                ("NewProtocol", &protocol),
+            ],
+        )
+        .await
+        .map(|_| ())
+    }
+
+    async fn add_port_mapping(
+        &self,
+        protocol: Protocol,
+        port: u16,
+        lifetime: Duration,
+    ) -> Result<(), Error> {
+        let port = port.to_string();
+        let protocol = protocol.to_string();
+        let internal_address = self.internal_address.to_string();
+        let lifetime = lifetime.as_secs().to_string();
+        self.call(
+            "AddPortMapping",
+            &[
+                ("NewRemoteHost", ""),
+                ("NewExternalPort", &port),
+                ("NewProtocol", &protocol),
+                ("NewInternalPort", &port),
+                ("NewInternalClient", &internal_address),
+                ("NewEnabled", "1"),
+                ("NewPortMappingDescription", DESCRIPTION),
+                ("NewLeaseDuration", &lifetime),
+            ],
+        )
+        .await
+        .map(|_| ())
+    }
+
+    async fn call(&self, action: &str, args: &[(&str, &str)]) -> Result<String, Error> {
+        let response = self
+            .client
+            .post(self.control_url.clone())
+            .header("Content-Type", "text/xml; charset=\"utf-8\"")
+            .header(
+                "SOAPAction",
+                format!("\"{}#{}\"", self.service_type, action),
+            )
+            .body(encode_call(self.service_type, action, args))
+            .send()
+            .await
+            .map_err(Error::other)?;
+        let status = response.status();
+        let body = response.text().await.map_err(Error::other)?;
+        if status != StatusCode::OK {
+            return Err(Error::other(Fault {
+                action: action.to_string(),
+                status,
+                code: find_element(&body, "errorCode").map(|code| code.trim().to_string()),
+                description: find_element(&body, "errorDescription")
+                    .map(|description| description.trim().to_string()),
+            }));
+        }
+        Ok(body)
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "upnp {} error: status={}", self.action, self.status)?;
+        if let Some(code) = &self.code {
+            write!(f, " code={}", code)?;
+        }
+        if let Some(description) = &self.description {
+            write!(f, " description={:?}", description)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Fault {}
+
+impl Fault {
+    fn code(error: &Error) -> Option<&str> {
+        error.get_ref()?.downcast_ref::<Self>()?.code.as_deref()
+    }
+}
+
+async fn search() -> Result<Url, Error> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    socket.set_multicast_ttl_v4(2)?;
+    let request = format!(
+        "M-SEARCH * HTTP/1.1\r\n\
+         HOST: {}\r\n\
+         MAN: \"ssdp:discover\"\r\n\
+         MX: 2\r\n\
+         ST: {}\r\n\
+         \r\n",
+        SSDP_ENDPOINT, SEARCH_TARGET,
+    );
+    socket.send_to(request.as_bytes(), SSDP_ENDPOINT).await?;
+
+    let deadline = time::Instant::now() + *crate::upnp_search_timeout();
+    let mut buffer = [0u8; 2048];
+    while let Ok(result) = time::timeout_at(deadline, socket.recv_from(&mut buffer)).await {
+        let (size, endpoint) = result?;
+        let Ok(response) = std::str::from_utf8(&buffer[..size]) else {
+            tracing::debug!(%endpoint, "discard non-utf8 ssdp response");
+            continue;
+        };
+        match parse_location(response).map(Url::parse) {
+            Some(Ok(location)) => return Ok(location),
+            Some(Err(error)) => tracing::debug!(%endpoint, %error, "invalid ssdp location"),
+            None => tracing::debug!(%endpoint, response, "discard ssdp response"),
+        }
+    }
+    Err(Error::new(ErrorKind::TimedOut, "upnp igd not found"))
+}
+
+fn parse_location(response: &str) -> Option<&str> {
+    let mut lines = response.lines();
+    if !lines.next()?.starts_with("HTTP/1.1 200") {
+        return None;
+    }
+    lines.find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.trim()
+            .eq_ignore_ascii_case("location")
+            .then_some(value.trim())
+    })
+}
+
+fn find_service(description: &str) -> Option<(&'static str, &str)> {
+    let services: Vec<_> = find_elements(description, "service")
+        .filter_map(|service| {
+            Some((
+                find_element(service, "serviceType")?.trim(),
+                find_element(service, "controlURL")?.trim(),
+            ))
+        })
+        .collect();
+    SERVICE_TYPES.iter().find_map(|expect| {
+        services
+            .iter()
+            .find(|(service_type, _)| service_type == expect)
+            .map(|(_, control_url)| (*expect, *control_url))
+    })
+}
+
+fn find_element<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    find_elements(xml, tag).next()
+}
+
+/// Returns the contents of the elements of the given tag.
+///
+/// NOTE: This is not a real XML parser.  It ignores namespace prefixes and does not handle nested
+/// elements of the same tag, both of which are good enough for IGD descriptions and responses.
+fn find_elements<'a>(mut xml: &'a str, tag: &str) -> impl Iterator<Item = &'a str> {
+    let tag = tag.to_string();
+    std::iter::from_fn(move || loop {
+        let (start, name, _, rest) = find_tag(xml)?;
+        xml = rest;
+        if start && local_name(name) == tag {
+            let content = xml;
+            loop {
+                let (start, name, offset, rest) = find_tag(xml)?;
+                if !start && local_name(name) == tag {
+                    let content = &content[..content.len() - xml.len() + offset];
+                    xml = rest;
+                    return Some(content);
+                }
+                xml = rest;
+            }
+        }
+    })
+}
+
+/// Finds the next start or end tag and returns its name, its offset, and the text after it.
+fn find_tag(xml: &str) -> Option<(bool, &str, usize, &str)> {
+    let mut offset = 0;
+    loop {
+        let i = offset + xml[offset..].find('<')?;
+        let j = i + xml[i..].find('>')?;
+        let tag = &xml[i + 1..j];
+        let rest = &xml[j + 1..];
+        if tag.starts_with('?') || tag.starts_with('!') || tag.ends_with('/') {
+            offset = j + 1;
+            continue;
+        }
+        let (start, tag) = match tag.strip_prefix('/') {
+            Some(tag) => (false, tag),
+            None => (true, tag),
+        };
+        let name = tag.split_whitespace().next().unwrap_or("");
+        return Some((start, name, i, rest));
+    }
+}
+
+fn local_name(name: &str) -> &str {
+    name.rsplit_once(':').map_or(name, |(_, name)| name)
+}
+
+fn encode_call(service_type: &str, action: &str, args: &[(&str, &str)]) -> String {
+    let mut body = format!(
+        "<?xml version=\"1.0\"?>\
+         <s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" \
+         s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">\
+         <s:Body><u:{} xmlns:u=\"{}\">",
+        action, service_type,
+    );
+    for (name, value) in args {
+        body.push_str(&format!("<{0}>{1}</{0}>", name, value));
+    }
+    body.push_str(&format!("</u:{}></s:Body></s:Envelope>", action));
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_location() {
+        assert_eq!(
+            super::parse_location(
+                "HTTP/1.1 200 OK\r\n\
+                 CACHE-CONTROL: max-age=120\r\n\
+                 Location: http://192.168.1.1:5000/rootDesc.xml\r\n\
+                 ST: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\n\
+                 \r\n",
+            ),
+            Some("http://192.168.1.1:5000/rootDesc.xml"),
+        );
+        assert_eq!(
+            super::parse_location("HTTP/1.1 200 OK\r\nST: upnp:rootdevice\r\n\r\n"),
+            None,
+        );
+        assert_eq!(
+            super::parse_location("NOTIFY * HTTP/1.1\r\nLOCATION: http://x/\r\n\r\n"),
+            None,
+        );
+    }
+
+    #[test]
+    fn find_service() {
+        let description = r#"<?xml version="1.0"?>
+<root xmlns="urn:schemas-upnp-org:device-1-0">
+  <device>
+    <deviceType>urn:schemas-upnp-org:device:InternetGatewayDevice:1</deviceType>
+    <serviceList>
+      <service>
+        <serviceType>urn:schemas-upnp-org:service:Layer3Forwarding:1</serviceType>
+        <controlURL>/ctl/L3F</controlURL>
+      </service>
+    </serviceList>
+    <deviceList>
+      <device>
+        <serviceList>
+          <service>
+            <serviceType>urn:schemas-upnp-org:service:WANPPPConnection:1</serviceType>
+            <controlURL>/ctl/PPPConn</controlURL>
+          </service>
+          <service>
+            <serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType>
+            <SCPDURL />
+            <controlURL>/ctl/IPConn</controlURL>
+          </service>
+        </serviceList>
+      </device>
+    </deviceList>
+  </device>
+</root>"#;
+        assert_eq!(
+            super::find_service(description),
+            Some((
+                "urn:schemas-upnp-org:service:WANIPConnection:1",
+                "/ctl/IPConn",
+            )),
+        );
+        assert_eq!(super::find_service("<root></root>"), None);
+    }
+
+    #[test]
+    fn find_element() {
+        let response = r#"<?xml version="1.0"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/">
+<s:Body>
+<u:GetExternalIPAddressResponse xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+<NewExternalIPAddress>203.0.113.7</NewExternalIPAddress>
+</u:GetExternalIPAddressResponse>
+</s:Body>
+</s:Envelope>"#;
+        assert_eq!(
+            super::find_element(response, "NewExternalIPAddress"),
+            Some("203.0.113.7"),
+        );
+        assert_eq!(
+            super::find_element(response, "GetExternalIPAddressResponse").map(str::trim),
+            Some("<NewExternalIPAddress>203.0.113.7</NewExternalIPAddress>"),
+        );
+        assert_eq!(super::find_element(response, "errorCode"), None);
+        assert_eq!(
+            find_elements("<a>1</a><b>2</b><a>3</a>", "a").collect::<Vec<_>>(),
+            vec!["1", "3"],
+        );
+    }
+
+    #[test]
+    fn encode_call() {
+        assert_eq!(
+            super::encode_call(
+                "urn:schemas-upnp-org:service:WANIPConnection:1",
+                "DeletePortMapping",
+                &[("NewExternalPort", "6881"), ("NewProtocol", "TCP")],
+            ),
+            "<?xml version=\"1.0\"?>\
+             <s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" \
+             s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">\
+             <s:Body>\
+             <u:DeletePortMapping xmlns:u=\"urn:schemas-upnp-org:service:WANIPConnection:1\">\
+             <NewExternalPort>6881</NewExternalPort>\
+             <NewProtocol>TCP</NewProtocol>\
+             </u:DeletePortMapping>\
+             </s:Body>\
+             </s:Envelope>",
+        );
+    }
+}
@@ -12,11 +12,14 @@ use bittorrent_base::{Features, InfoHash, PeerId, INFO_HASH_SIZE, PEER_ID_SIZE,
 
 use crate::error;
 
-type Reserved = [u8; RESERVED_SIZE];
+pub(crate) type Reserved = [u8; RESERVED_SIZE];
 type ReservedBits = BitSlice<u8, Msb0>;
 
 const RESERVED_SIZE: usize = 8;
 
+pub(crate) const HANDSHAKE_SIZE: usize =
+    1 + PROTOCOL_ID.len() + RESERVED_SIZE + INFO_HASH_SIZE + PEER_ID_SIZE;
+
 const RESERVED_AZUREUS_MESSAGING: usize = 0;
 const RESERVED_LOCATION_AWARE: usize = 20;
 const RESERVED_EXTENSION: usize = 43; // BEP 10
@@ -54,16 +57,16 @@ pub(crate) async fn connect<Stream>(
     self_id: PeerId,
     self_features: Features,
     expect_peer_id: Option<PeerId>,
-) -> Result<(PeerId, Features), Error>
+) -> Result<(PeerId, Features, Reserved), Error>
 where
     Stream: StreamRecv<Error = Error> + StreamSend<Error = Error> + Send,
 {
     time::timeout(*crate::handshake_timeout(), async {
         send_handshake(stream, info_hash.clone(), self_features).await?;
-        let peer_features = recv_handshake(stream, info_hash).await?;
+        let (peer_features, peer_reserved) = recv_handshake(stream, info_hash).await?;
         send_self_id(stream, self_id).await?;
         let peer_id = recv_peer_id(stream, expect_peer_id).await?;
-        Ok((peer_id, peer_features))
+        Ok((peer_id, peer_features, peer_reserved))
     })
     .await
     .map_err(|_| error::Error::HandshakeTimeout)?
@@ -75,7 +78,7 @@ pub(crate) async fn accept<Stream>(
     self_id: PeerId,
     self_features: Features,
     expect_peer_id: Option<PeerId>,
-) -> Result<(PeerId, Features), Error>
+) -> Result<(PeerId, Features, Reserved), Error>
 where
     Stream: StreamRecv<Error = Error> + StreamSend<Error = Error> + Send,
 {
@@ -83,17 +86,21 @@ where
         // TODO: BEP 3 suggests that if we are serving multiple torrents on a single port (which we
         // are not currently), we may wait for the incoming connection's info hash and then send
         // the corresponding one.
-        let peer_features = recv_handshake(stream, info_hash.clone()).await?;
+        let (peer_features, peer_reserved) = recv_handshake(stream, info_hash.clone()).await?;
         send_handshake(stream, info_hash, self_features).await?;
         send_self_id(stream, self_id).await?;
         let peer_id = recv_peer_id(stream, expect_peer_id).await?;
-        Ok((peer_id, peer_features))
+        Ok((peer_id, peer_features, peer_reserved))
     })
     .await
     .map_err(|_| error::Error::HandshakeTimeout)?
 }
 
-async fn recv_handshake<Stream>(stream: &mut Stream, info_hash: InfoHash) -> Result<Features, Error>
+/// Returns the peer's features and its raw reserved bytes.
+async fn recv_handshake<Stream>(
+    stream: &mut Stream,
+    info_hash: InfoHash,
+) -> Result<(Features, Reserved), Error>
 where
     Stream: StreamRecv<Error = Error> + Send,
 {
@@ -124,6 +131,7 @@ where
     let mut reserved = Reserved::default();
     stream.buffer().copy_to_slice(&mut reserved);
     let peer_features = new_features(&reserved);
+    let peer_reserved = reserved;
     reserved_clear_known_bits(&mut reserved);
     if reserved != [0u8; RESERVED_SIZE] {
         tracing::warn!(reserved = ?Hex(&reserved), "unknown reserved bits");
@@ -142,7 +150,7 @@ where
         buffer.advance(INFO_HASH_SIZE);
     }
 
-    Ok(peer_features)
+    Ok((peer_features, peer_reserved))
 }
 
 async fn send_handshake<Stream>(
@@ -251,6 +259,7 @@ mod tests {
                         Some(peer_id.clone()),
                     )
                     .await
+                    .map(|(peer_id, peer_features, _)| (peer_id, peer_features))
                     .map_err(|error| error.downcast::<error::Error>().unwrap());
                     assert_eq!(
                         result,
@@ -1,7 +1,9 @@
 pub mod error;
 
 mod handshake;
+mod limit;
 mod message;
+mod stat;
 
 use std::io::Error;
 use std::time::Duration;
@@ -12,7 +14,9 @@ use g1_tokio::bstream::{StreamBuffer, StreamRecv, StreamSend};
 
 use bittorrent_base::{Features, InfoHash, PeerId};
 
+pub use limit::{rate_limits, set_rate_limits, RateLimits};
 pub use message::Message;
+pub use stat::{overhead, Overhead};
 
 g1_param::define!(
     handshake_timeout: Duration = Duration::from_secs(8);
@@ -25,6 +29,9 @@ pub struct Socket<Stream> {
     self_features: Features,
     peer_id: PeerId,
     peer_features: Features,
+    peer_reserved: handshake::Reserved,
+    // Piece payload that we have received but not yet charged to the download limiter.
+    download_debt: usize,
 }
 
 macro_rules! gen_handshake {
@@ -44,8 +51,12 @@ macro_rules! gen_handshake {
                 expect_peer_id,
             )
             .await;
-            let (peer_id, peer_features) = match result {
-                Ok(pair) => pair,
+            let (peer_id, peer_features, peer_reserved) = match result {
+                Ok(triple) => {
+                    stat::add_send(handshake::HANDSHAKE_SIZE);
+                    stat::add_recv(handshake::HANDSHAKE_SIZE);
+                    triple
+                }
                 Err(error) => {
                     if let Err(error) = stream.shutdown().await {
                         tracing::warn!(%error, "peer stream shutdown error");
@@ -58,6 +69,8 @@ macro_rules! gen_handshake {
                 self_features,
                 peer_id,
                 peer_features,
+                peer_reserved,
+                download_debt: 0,
             })
         }
     };
@@ -82,6 +95,12 @@ where
         self.peer_features
     }
 
+    /// Returns the reserved bytes of the peer's handshake, including the bits that we do not
+    /// decode into `Features`.
+    pub fn peer_reserved(&self) -> [u8; 8] {
+        self.peer_reserved
+    }
+
     fn check_features(&self, message: &Message) -> Result<(), error::Error> {
         ensure!(
             message.get_feature(self.self_features).unwrap_or(true),
@@ -99,8 +118,18 @@ where
     }
 
     pub async fn recv(&mut self) -> Result<Message, Error> {
+        // Cancel Safety: We charge the previous piece before receiving the next message, rather
+        // than after receiving a piece, so that we never drop a received message.
+        if self.download_debt > 0 {
+            limit::acquire_download(self.download_debt).await;
+            self.download_debt = 0;
+        }
         let message = Message::recv_from(&mut self.stream).await?;
         self.check_features(&message)?;
+        stat::add_recv(message.overhead());
+        if let Message::Piece(_, payload) = &message {
+            self.download_debt = payload.len();
+        }
         Ok(message)
     }
 
@@ -116,6 +145,10 @@ where
             if let Err(error) = self.check_features(&message) {
                 panic!("send_many: {}", error); // `panic!` because it is our fault.
             }
+            if let Message::Piece(_, payload) = &message {
+                limit::acquire_upload(payload.len()).await;
+            }
+            stat::add_send(message.overhead());
             message.encode(&mut *self.stream.send_buffer());
         }
         self.stream.send_all().await
@@ -146,6 +179,8 @@ mod test_harness {
                 self_features,
                 peer_id,
                 peer_features,
+                peer_reserved: Default::default(),
+                download_debt: 0,
             }
         }
     }
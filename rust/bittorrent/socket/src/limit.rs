@@ -0,0 +1,169 @@
+//! Rate Limits
+//!
+//! We limit the throughput of piece payload.  Like the overhead counters, the limits are
+//! session-level, i.e., shared by all sockets in this process, and changing them takes effect
+//! immediately, including for sockets that are waiting on the limiter.
+
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+use tokio::time::{self, Instant};
+
+use g1_base::sync::MutexExt;
+
+static UPLOAD: LazyLock<RateLimiter> = LazyLock::new(RateLimiter::new);
+static DOWNLOAD: LazyLock<RateLimiter> = LazyLock::new(RateLimiter::new);
+
+// Waiters re-check the limiter at least this often so that they notice rate changes.
+const MAX_WAIT: Duration = Duration::from_millis(100);
+
+/// Rate limits in bytes per second, where `None` means unlimited.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct RateLimits {
+    pub upload: Option<u64>,
+    pub download: Option<u64>,
+}
+
+pub fn rate_limits() -> RateLimits {
+    RateLimits {
+        upload: UPLOAD.rate(),
+        download: DOWNLOAD.rate(),
+    }
+}
+
+pub fn set_rate_limits(limits: RateLimits) {
+    UPLOAD.set_rate(limits.upload);
+    DOWNLOAD.set_rate(limits.download);
+}
+
+pub(crate) async fn acquire_upload(n: usize) {
+    UPLOAD.acquire(n).await
+}
+
+pub(crate) async fn acquire_download(n: usize) {
+    DOWNLOAD.acquire(n).await
+}
+
+#[derive(Debug)]
+struct RateLimiter(Mutex<State>);
+
+#[derive(Debug)]
+struct State {
+    rate: Option<u64>,
+    // It may go negative so that a message larger than the bucket can still pass.
+    n: f64,
+    last_fill: Instant,
+}
+
+impl RateLimiter {
+    fn new() -> Self {
+        Self(Mutex::new(State {
+            rate: None,
+            n: 0.0,
+            last_fill: Instant::now(),
+        }))
+    }
+
+    fn rate(&self) -> Option<u64> {
+        self.0.must_lock().rate
+    }
+
+    fn set_rate(&self, rate: Option<u64>) {
+        let rate = rate.filter(|rate| *rate > 0);
+        let mut state = self.0.must_lock();
+        if state.rate != rate {
+            state.rate = rate;
+            state.n = 0.0;
+            state.last_fill = Instant::now();
+        }
+    }
+
+    async fn acquire(&self, n: usize) {
+        while let Some(wait) = self.try_acquire(n) {
+            time::sleep(wait.min(MAX_WAIT)).await;
+        }
+    }
+
+    /// Returns how long the caller should wait before retrying.
+    fn try_acquire(&self, n: usize) -> Option<Duration> {
+        let mut state = self.0.must_lock();
+        let rate = state.rate? as f64;
+        let now = Instant::now();
+        let t = now.duration_since(state.last_fill).as_secs_f64();
+        // The bucket size is one second's worth of tokens.
+        state.n = (state.n + rate * t).min(rate);
+        state.last_fill = now;
+        if state.n >= 0.0 {
+            state.n -= n as f64;
+            None
+        } else {
+            Some(Duration::from_secs_f64(-state.n / rate))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    // `tokio::time::sleep` rounds up to milliseconds.
+    fn assert_elapsed(t0: Instant, millis: u64) {
+        let elapsed = t0.elapsed();
+        let expect = Duration::from_millis(millis);
+        assert!(
+            expect <= elapsed && elapsed <= expect + Duration::from_millis(5),
+            "expect {expect:?}: {elapsed:?}",
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn acquire() {
+        let limiter = RateLimiter::new();
+        let t0 = Instant::now();
+        limiter.acquire(1000000).await;
+        assert_elapsed(t0, 0);
+
+        limiter.set_rate(Some(100));
+        let t0 = Instant::now();
+        limiter.acquire(250).await;
+        assert_elapsed(t0, 0);
+        limiter.acquire(10).await;
+        assert_elapsed(t0, 2500);
+        limiter.acquire(10).await;
+        assert_elapsed(t0, 2600);
+
+        // The bucket is capped at one second's worth of tokens.
+        time::advance(Duration::from_secs(10)).await;
+        let t0 = Instant::now();
+        limiter.acquire(110).await;
+        assert_elapsed(t0, 0);
+        limiter.acquire(1).await;
+        assert_elapsed(t0, 100);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn set_rate() {
+        let limiter = Arc::new(RateLimiter::new());
+        assert_eq!(limiter.rate(), None);
+        limiter.set_rate(Some(0));
+        assert_eq!(limiter.rate(), None);
+        limiter.set_rate(Some(10));
+        assert_eq!(limiter.rate(), Some(10));
+
+        limiter.acquire(1000).await;
+        let t0 = Instant::now();
+        let acquire = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.acquire(1).await }
+        });
+        time::sleep(Duration::from_secs(1)).await;
+        assert!(!acquire.is_finished());
+
+        // Lifting the limit takes effect for the waiter.
+        limiter.set_rate(None);
+        acquire.await.unwrap();
+        assert!(t0.elapsed() <= Duration::from_secs(1) + MAX_WAIT);
+    }
+}
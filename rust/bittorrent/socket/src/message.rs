@@ -258,6 +258,26 @@ impl Message {
         }
     }
 
+    /// Returns the number of bytes of the encoded message, excluding the piece payload.
+    pub(crate) fn overhead(&self) -> usize {
+        let payload_size = match self {
+            Self::KeepAlive => return mem::size_of::<u32>(),
+            Self::Choke
+            | Self::Unchoke
+            | Self::Interested
+            | Self::NotInterested
+            | Self::HaveAll
+            | Self::HaveNone => 0,
+            Self::Have(_) | Self::Suggest(_) | Self::AllowedFast(_) => 4,
+            Self::Bitfield(payload) => payload.len(),
+            Self::Request(_) | Self::Cancel(_) | Self::Reject(_) => 12,
+            Self::Piece(..) => 8,
+            Self::Port(_) => 2,
+            Self::Extended(_, payload) => 1 + payload.len(),
+        };
+        mem::size_of::<u32>() + 1 + payload_size
+    }
+
     pub(crate) fn get_feature(&self, features: Features) -> Option<bool> {
         match self {
             Self::Port(_) => Some(features.dht),
@@ -356,6 +376,12 @@ mod tests {
             let mut buffer = BytesMut::new();
             expect.encode(&mut buffer);
             assert_eq!(&buffer, test_data);
+
+            let payload_size = match &expect {
+                Message::Piece(_, payload) => payload.len(),
+                _ => 0,
+            };
+            assert_eq!(expect.overhead() + payload_size, test_data.len());
         }
 
         async fn test_eq(test_data: &[u8]) {
@@ -0,0 +1,31 @@
+//! Protocol Overhead
+//!
+//! We count the bytes of the peer wire protocol that are not piece payload (handshakes, message
+//! headers, and non-piece messages).  The counters are session-level, i.e., shared by all sockets
+//! in this process.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static SEND: AtomicU64 = AtomicU64::new(0);
+static RECV: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Overhead {
+    pub send: u64,
+    pub recv: u64,
+}
+
+pub fn overhead() -> Overhead {
+    Overhead {
+        send: SEND.load(Ordering::SeqCst),
+        recv: RECV.load(Ordering::SeqCst),
+    }
+}
+
+pub(crate) fn add_send(n: usize) {
+    SEND.fetch_add(u64::try_from(n).unwrap(), Ordering::SeqCst);
+}
+
+pub(crate) fn add_recv(n: usize) {
+    RECV.fetch_add(u64::try_from(n).unwrap(), Ordering::SeqCst);
+}
@@ -1,5 +1,6 @@
 use std::io::Error;
-use std::path::Path;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
 
 use async_trait::async_trait;
 use bytes::{Buf, BufMut, Bytes, BytesMut};
@@ -8,19 +9,31 @@ use tokio::{fs::File, io::AsyncWriteExt};
 use g1_tokio::io::AsyncReadBufExact;
 
 use bittorrent_base::{BlockDesc, Dimension, PieceIndex};
-use bittorrent_metainfo::Info;
+use bittorrent_metainfo::{Info, Timestamp};
 
 use crate::{
     coord::CoordSys,
-    io::{self, PieceHasher},
-    metainfo, Bitfield, FileBlockDesc, FileBlockOffset, PieceHash,
+    io::{self, PieceHasher, WriteBuffer},
+    metainfo::{self, EntryKind},
+    Bitfield, FileBlockDesc, FileBlockOffset, PieceHash,
 };
 
+// BEP 47
+g1_param::define!(restore_mtime: bool = false);
+
 #[derive(Debug)]
 pub struct Storage {
     coord_sys: CoordSys,
     piece_hashes: Vec<PieceHash>,
-    files: Vec<File>,
+    // It is `None` for padding files.
+    files: Vec<Option<File>>,
+    // Indexed by the metainfo file index, which, unlike the index of `files`, includes empty
+    // files.
+    file_pieces: Vec<Option<RangeInclusive<PieceIndex>>>,
+    verified: Bitfield,
+    // We restore the mtimes when all pieces are verified.
+    mtimes: Vec<(PathBuf, Timestamp)>,
+    write_buffer: WriteBuffer,
 }
 
 impl Storage {
@@ -28,11 +41,23 @@ impl Storage {
     ///
     /// NOTE: This does not roll back (i.e., remove the created directories) on error.
     pub async fn open(info: &Info<'_>, dim: Dimension, torrent_dir: &Path) -> Result<Self, Error> {
-        let paths = metainfo::new_paths(info, torrent_dir)?;
+        let entries = metainfo::new_entries(info, torrent_dir)?;
+        let mut offset = 0;
+        let file_pieces = entries
+            .iter()
+            .map(|entry| {
+                let start = offset;
+                offset += entry.size;
+                (entry.size > 0).then(|| {
+                    PieceIndex::from(usize::try_from(start / dim.piece_size).unwrap())
+                        ..=PieceIndex::from(usize::try_from((offset - 1) / dim.piece_size).unwrap())
+                })
+            })
+            .collect();
         let coord_sys = CoordSys::new(
             dim,
-            paths.iter().filter_map(|(_, size)| {
-                let size = *size;
+            entries.iter().filter_map(|entry| {
+                let size = entry.size;
                 if size > 0 {
                     Some(size)
                 } else {
@@ -41,24 +66,83 @@ impl Storage {
             }),
         )?;
         // TODO: Is there an async version of `map`?
-        let mut files = Vec::with_capacity(paths.len());
-        for (path, size) in paths {
-            let file = io::open(&path, size).await?;
-            if size > 0 {
+        let mut files = Vec::with_capacity(entries.len());
+        let mut mtimes = Vec::new();
+        for entry in entries {
+            let file = match entry.kind {
+                EntryKind::Regular { executable } => {
+                    let file = io::open(&entry.path, entry.size).await?;
+                    if executable {
+                        io::set_executable(&entry.path).await?;
+                    }
+                    if let Some(mtime) = entry.mtime {
+                        mtimes.push((entry.path, mtime));
+                    }
+                    Some(file)
+                }
+                EntryKind::Padding => None,
+                EntryKind::Symlink { target } => {
+                    io::symlink(&target, &entry.path).await?;
+                    None
+                }
+            };
+            if entry.size > 0 {
                 files.push(file);
             }
         }
+        let piece_hashes = metainfo::new_piece_hashes(info);
         Ok(Self {
             coord_sys,
-            piece_hashes: metainfo::new_piece_hashes(info),
+            verified: Bitfield::repeat(false, piece_hashes.len()),
+            piece_hashes,
             files,
+            file_pieces,
+            mtimes,
+            write_buffer: WriteBuffer::new(*crate::write_buffer_size()),
         })
     }
 
-    async fn prepare(&mut self, offset: FileBlockOffset) -> Result<&mut File, Error> {
-        let file = &mut self.files[usize::from(offset.0)];
+    /// Seeks the file, or returns `None` if it is a padding file.
+    async fn prepare(&mut self, offset: FileBlockOffset) -> Result<Option<&mut File>, Error> {
+        let Some(file) = &mut self.files[usize::from(offset.0)] else {
+            return Ok(None);
+        };
         offset.seek(file).await?;
-        Ok(file)
+        Ok(Some(file))
+    }
+
+    async fn flush(&mut self) -> Result<(), Error> {
+        if let Some((offset, data)) = self.write_buffer.take() {
+            // Padding files are never buffered.
+            self.prepare(offset)
+                .await?
+                .unwrap()
+                .write_all(&data)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn buffered_write(&mut self, offset: FileBlockOffset, data: &[u8]) -> Result<(), Error> {
+        if !self.write_buffer.is_contiguous(offset) {
+            self.flush().await?;
+        }
+        self.write_buffer.push(offset, data);
+        if self.write_buffer.is_full() {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    async fn set_verified(&mut self, index: PieceIndex, verified: bool) -> Result<(), Error> {
+        let was_completed = self.verified.all();
+        self.verified.set(usize::from(index), verified);
+        if !was_completed && self.verified.all() && *restore_mtime() {
+            for (path, mtime) in &self.mtimes {
+                io::set_mtime(path, *mtime).await?;
+            }
+        }
+        Ok(())
     }
 }
 
@@ -74,24 +158,31 @@ impl crate::Storage for Storage {
     }
 
     async fn verify(&mut self, index: PieceIndex) -> Result<bool, Error> {
+        self.flush().await?;
         let mut hasher = PieceHasher::new();
         for desc in self.coord_sys.dim.block_descs(index) {
             for FileBlockDesc(offset, size) in self.coord_sys.to_file_descs(desc)? {
                 let size = usize::try_from(size).unwrap();
-                hasher.update(self.prepare(offset).await?, size).await?;
+                match self.prepare(offset).await? {
+                    Some(file) => hasher.update(file, size).await?,
+                    None => hasher.update_zeros(size),
+                }
             }
         }
-        Ok(self.piece_hashes[usize::from(index)] == hasher.finalize())
+        let verified = self.piece_hashes[usize::from(index)] == hasher.finalize();
+        self.set_verified(index, verified).await?;
+        Ok(verified)
     }
 
     async fn read(&mut self, desc: BlockDesc, buffer: &mut BytesMut) -> Result<(), Error> {
+        self.flush().await?;
         for FileBlockDesc(offset, size) in self.coord_sys.to_file_descs(desc)? {
             let size = usize::try_from(size).unwrap();
             assert!(buffer.remaining_mut() >= size);
-            self.prepare(offset)
-                .await?
-                .read_buf_exact(&mut buffer.limit(size))
-                .await?;
+            match self.prepare(offset).await? {
+                Some(file) => file.read_buf_exact(&mut buffer.limit(size)).await?,
+                None => buffer.put_bytes(0, size),
+            }
         }
         Ok(())
     }
@@ -100,23 +191,39 @@ impl crate::Storage for Storage {
         for FileBlockDesc(offset, size) in self.coord_sys.to_file_descs(desc)? {
             let size = usize::try_from(size).unwrap();
             assert!(buffer.remaining() >= size);
-            self.prepare(offset)
-                .await?
-                .write_all_buf(&mut buffer.take(size))
-                .await?;
+            let data = buffer.split_to(size);
+            // Discard the content of padding files.
+            if self.files[usize::from(offset.0)].is_some() {
+                self.buffered_write(offset, &data).await?;
+            }
         }
         Ok(())
     }
+
+    fn is_padding(&self, desc: BlockDesc) -> bool {
+        let Ok(mut descs) = self.coord_sys.to_file_descs(desc) else {
+            return false;
+        };
+        descs.all(|FileBlockDesc(FileBlockOffset(index, _), _)| {
+            self.files[usize::from(index)].is_none()
+        })
+    }
+
+    fn file_pieces(&self, file: usize) -> Option<RangeInclusive<PieceIndex>> {
+        self.file_pieces.get(file)?.clone()
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
     use hex_literal::hex;
     use tempfile;
 
     use bittorrent_metainfo::{File as MetainfoFile, Mode};
 
-    use crate::test_harness::*;
+    use crate::{test_harness::*, Storage as _};
 
     use super::*;
 
@@ -132,27 +239,27 @@ mod tests {
         assert_file(&path.join("empty/d"), &[]).await;
     }
 
-    fn new_info() -> Info<'static> {
-        fn new_file(path: Vec<&'static str>, length: u64) -> MetainfoFile<'static> {
-            let mut file = MetainfoFile::new_dummy();
-            file.path = path;
-            file.length = length;
-            file
-        }
+    fn new_info_file(path: Vec<&'static str>, length: u64) -> MetainfoFile<'static> {
+        let mut file = MetainfoFile::new_dummy();
+        file.path = path;
+        file.length = length;
+        file
+    }
 
+    fn new_info() -> Info<'static> {
         let mut info = Info::new_dummy();
         info.name = "test";
         info.mode = Mode::MultiFile {
             files: vec![
-                new_file(vec!["empty", "a"], 0),
-                new_file(vec!["1"], 1), // piece 0
-                new_file(vec!["2"], 1), // piece 0
-                new_file(vec!["3"], 1), // piece 0
-                new_file(vec!["empty", "b"], 0),
-                new_file(vec!["4"], 6), // piece 0, 1
-                new_file(vec!["empty", "c"], 0),
-                new_file(vec!["5"], 15), // piece 1, 2, 3
-                new_file(vec!["empty", "d"], 0),
+                new_info_file(vec!["empty", "a"], 0),
+                new_info_file(vec!["1"], 1), // piece 0
+                new_info_file(vec!["2"], 1), // piece 0
+                new_info_file(vec!["3"], 1), // piece 0
+                new_info_file(vec!["empty", "b"], 0),
+                new_info_file(vec!["4"], 6), // piece 0, 1
+                new_info_file(vec!["empty", "c"], 0),
+                new_info_file(vec!["5"], 15), // piece 1, 2, 3
+                new_info_file(vec!["empty", "d"], 0),
             ],
         };
         info.piece_length = 7;
@@ -180,6 +287,32 @@ mod tests {
         assert_bitfield(&mut storage, &[true, false, true, false]).await;
     }
 
+    #[tokio::test]
+    async fn file_pieces() {
+        let info = new_info();
+        let dim = info.new_dimension(16384);
+        let tempdir = tempfile::tempdir().unwrap();
+        let storage = Storage::open(&info, dim, tempdir.path()).await.unwrap();
+        let expect = [
+            None,
+            Some((0, 0)),
+            Some((0, 0)),
+            Some((0, 0)),
+            None,
+            Some((0, 1)),
+            None,
+            Some((1, 3)),
+            None,
+            None,
+        ];
+        for (file, expect) in expect.into_iter().enumerate() {
+            assert_eq!(
+                storage.file_pieces(file),
+                expect.map(|(start, end): (usize, usize)| start.into()..=end.into()),
+            );
+        }
+    }
+
     #[tokio::test]
     async fn read_write() {
         let info = new_info();
@@ -214,4 +347,45 @@ mod tests {
         .await;
         read(&mut storage, (2, 0, 7), &hex!("11223344556677")).await;
     }
+
+    #[tokio::test]
+    async fn padding() {
+        let mut pad = MetainfoFile::new_dummy();
+        pad.path = vec![".pad", "4"];
+        pad.length = 4;
+        pad.attr = Some("p");
+        let mut file = MetainfoFile::new_dummy();
+        file.path = vec!["2"];
+        file.length = 7;
+        file.attr = Some("x");
+
+        let mut info = new_info();
+        info.mode = Mode::MultiFile {
+            files: vec![new_info_file(vec!["1"], 3), pad, file],
+        };
+        info.pieces = vec![
+            hex!("77ce0377defbd11b77b1f4ad54ca40ea5ef28490").as_slice(),
+            hex!("77ce0377defbd11b77b1f4ad54ca40ea5ef28490").as_slice(),
+        ];
+        let dim = info.new_dimension(16384);
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join(info.name);
+        let mut storage = Storage::open(&info, dim, tempdir.path()).await.unwrap();
+        assert_eq!(path.join(".pad").exists(), false);
+        assert_ne!(
+            path.join("2").metadata().unwrap().permissions().mode() & 0o111,
+            0,
+        );
+        assert_bitfield(&mut storage, &[true, true]).await;
+        assert_eq!(storage.is_padding((0, 0, 7).into()), false);
+        assert_eq!(storage.is_padding((0, 2, 2).into()), false);
+        assert_eq!(storage.is_padding((0, 3, 4).into()), true);
+        assert_eq!(storage.is_padding((0, 4, 2).into()), true);
+
+        write(&mut storage, (0, 0, 7), &hex!("112233 ffffffff")).await;
+        assert_file(&path.join("1"), &hex!("112233")).await;
+        assert_eq!(path.join(".pad").exists(), false);
+        read(&mut storage, (0, 0, 7), &hex!("112233 00000000")).await;
+        assert_bitfield(&mut storage, &[false, true]).await;
+    }
 }
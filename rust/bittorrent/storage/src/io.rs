@@ -1,8 +1,9 @@
 use std::cmp;
-use std::io::Error;
-use std::os::fd::AsRawFd;
+use std::io::{Error, ErrorKind};
+use std::os::{fd::AsRawFd, unix::fs::PermissionsExt};
 use std::path::{Component, Path};
 
+use bytes::{Bytes, BytesMut};
 use sha1::{Digest, Sha1};
 use snafu::prelude::*;
 use tokio::{
@@ -10,13 +11,30 @@ use tokio::{
     io::AsyncReadExt,
 };
 
-use crate::{error, PieceHash};
+use bittorrent_metainfo::Timestamp;
+
+use crate::{error, FileBlockOffset, PieceHash};
 
 #[derive(Debug)]
 pub(crate) struct PieceHasher {
     hasher: Sha1,
 }
 
+/// Write-Back Buffer
+///
+/// It coalesces contiguous writes to a file, even across piece boundaries, into one large write,
+/// which is much faster on spinning disks when we download pieces in disk order.
+///
+/// NOTE: The buffered data is lost if the storage is dropped before it is flushed.  This is fine
+/// because it only belongs to pieces that have not been verified yet.
+#[derive(Debug)]
+pub(crate) struct WriteBuffer {
+    capacity: usize,
+    // Offset at which the buffered data starts.
+    offset: Option<FileBlockOffset>,
+    buffer: BytesMut,
+}
+
 impl PieceHasher {
     pub(crate) fn new() -> Self {
         Self {
@@ -39,11 +57,58 @@ impl PieceHasher {
         Ok(())
     }
 
+    /// Updates the hasher with zeros, which is the content of a padding file.
+    pub(crate) fn update_zeros(&mut self, mut size: usize) {
+        const ZEROS: [u8; 4096] = [0; 4096];
+        while size > 0 {
+            let buf_size = cmp::min(ZEROS.len(), size);
+            self.hasher.update(&ZEROS[..buf_size]);
+            size -= buf_size;
+        }
+    }
+
     pub(crate) fn finalize(self) -> PieceHash {
         self.hasher.finalize().into()
     }
 }
 
+impl WriteBuffer {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            offset: None,
+            buffer: BytesMut::new(),
+        }
+    }
+
+    /// True if the data at `offset` may be appended to the buffer.
+    pub(crate) fn is_contiguous(&self, offset: FileBlockOffset) -> bool {
+        match self.offset {
+            Some(FileBlockOffset(index, start)) => {
+                offset == FileBlockOffset(index, start + u64::try_from(self.buffer.len()).unwrap())
+            }
+            None => true,
+        }
+    }
+
+    pub(crate) fn is_full(&self) -> bool {
+        self.buffer.len() >= self.capacity
+    }
+
+    // NOTE: Caller must check `is_contiguous`.
+    pub(crate) fn push(&mut self, offset: FileBlockOffset, data: &[u8]) {
+        assert!(self.is_contiguous(offset));
+        self.offset.get_or_insert(offset);
+        self.buffer.extend_from_slice(data);
+    }
+
+    pub(crate) fn take(&mut self) -> Option<(FileBlockOffset, Bytes)> {
+        self.offset
+            .take()
+            .map(|offset| (offset, self.buffer.split().freeze()))
+    }
+}
+
 pub(crate) async fn open(path: &Path, size: u64) -> Result<File, Error> {
     if let Some(parent) = path.parent() {
         fs::create_dir_all(parent).await?;
@@ -61,6 +126,32 @@ pub(crate) async fn open(path: &Path, size: u64) -> Result<File, Error> {
     Ok(file)
 }
 
+/// Sets the executable bits wherever the corresponding read bits are set.
+pub(crate) async fn set_executable(path: &Path) -> Result<(), Error> {
+    let mut permissions = fs::metadata(path).await?.permissions();
+    let mode = permissions.mode();
+    permissions.set_mode(mode | ((mode & 0o444) >> 2));
+    fs::set_permissions(path, permissions).await
+}
+
+pub(crate) async fn symlink(target: &Path, path: &Path) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    match fs::read_link(path).await {
+        Ok(current) if current == target => return Ok(()),
+        Ok(_) => fs::remove_file(path).await?,
+        Err(error) if error.kind() == ErrorKind::NotFound => {}
+        Err(error) => return Err(error),
+    }
+    fs::symlink(target, path).await
+}
+
+pub(crate) async fn set_mtime(path: &Path, mtime: Timestamp) -> Result<(), Error> {
+    let file = OpenOptions::new().write(true).open(path).await?;
+    file.into_std().await.set_modified(mtime.into())
+}
+
 pub(crate) fn expect_dir(path: &Path) -> Result<&Path, error::Error> {
     ensure!(
         path.is_dir(),
@@ -142,6 +233,40 @@ mod tests {
             hasher.finalize(),
             hex!("f907b7bf318b79fd6b9da589646f8b1dac77d0c8"),
         );
+
+        let mut hasher = PieceHasher::new();
+        hasher.update(&mut zero, 1000).await.unwrap();
+        hasher.update_zeros(9000);
+        assert_eq!(
+            hasher.finalize(),
+            hex!("f907b7bf318b79fd6b9da589646f8b1dac77d0c8"),
+        );
+    }
+
+    #[test]
+    fn write_buffer() {
+        let mut buffer = WriteBuffer::new(8);
+        assert_eq!(buffer.is_contiguous((0, 10).into()), true);
+        assert_eq!(buffer.take(), None);
+
+        buffer.push((0, 10).into(), b"abc");
+        assert_eq!(buffer.is_contiguous((0, 13).into()), true);
+        assert_eq!(buffer.is_contiguous((0, 12).into()), false);
+        assert_eq!(buffer.is_contiguous((1, 13).into()), false);
+        assert_eq!(buffer.is_full(), false);
+
+        buffer.push((0, 13).into(), b"defgh");
+        assert_eq!(buffer.is_full(), true);
+        assert_eq!(
+            buffer.take(),
+            Some(((0, 10).into(), Bytes::from_static(b"abcdefgh"))),
+        );
+        assert_eq!(buffer.take(), None);
+        assert_eq!(buffer.is_contiguous((1, 0).into()), true);
+
+        let mut buffer = WriteBuffer::new(0);
+        buffer.push((0, 0).into(), b"a");
+        assert_eq!(buffer.is_full(), true);
     }
 
     #[tokio::test]
@@ -157,6 +282,28 @@ mod tests {
         assert_file_size(&path, 0);
     }
 
+    #[tokio::test]
+    async fn test_set_executable() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("a");
+        let _ = open(&path, 0).await.unwrap();
+        fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640))
+            .await
+            .unwrap();
+        set_executable(&path).await.unwrap();
+        assert_eq!(path.metadata().unwrap().permissions().mode() & 0o777, 0o750);
+    }
+
+    #[tokio::test]
+    async fn test_symlink() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("a/b");
+        for target in ["x", "x", "../y"] {
+            symlink(Path::new(target), &path).await.unwrap();
+            assert_eq!(path.read_link().unwrap(), Path::new(target));
+        }
+    }
+
     #[test]
     fn test_expect_dir() {
         assert_eq!(expect_dir(Path::new(".")), Ok(Path::new(".")));
@@ -9,6 +9,7 @@ mod io;
 mod metainfo;
 
 use std::io::Error;
+use std::ops::RangeInclusive;
 
 use async_trait::async_trait;
 use bitvec::prelude::*;
@@ -20,6 +21,12 @@ use tokio::{
 
 use bittorrent_base::{BlockDesc, PieceIndex, PIECE_HASH_SIZE};
 
+g1_param::define!(
+    /// Size of the write-back buffer that coalesces contiguous writes (0 disables it).
+    write_buffer_size: usize = 0;
+    parse = g1_param::parse::byte_size;
+);
+
 // Use the same bit layout as the wire format for faster conversion.
 pub type Bitfield = BitVec<u8, Msb0>;
 
@@ -35,6 +42,19 @@ pub trait Storage {
 
     // Use a concrete type for the same reason above.
     async fn write(&mut self, desc: BlockDesc, buffer: &mut Bytes) -> Result<(), Error>;
+
+    /// True if the block lies entirely within BEP 47 padding files.
+    ///
+    /// The content of padding files is all zeros, and so we do not have to download it.
+    fn is_padding(&self, _desc: BlockDesc) -> bool {
+        false
+    }
+
+    /// Returns the pieces that overlap the file, where `file` is the index of the file in the
+    /// metainfo, or `None` if the file does not exist or is empty.
+    fn file_pieces(&self, _file: usize) -> Option<RangeInclusive<PieceIndex>> {
+        None
+    }
 }
 
 pub(crate) type PieceHash = [u8; PIECE_HASH_SIZE];
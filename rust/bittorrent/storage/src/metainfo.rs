@@ -1,9 +1,26 @@
 use std::path::{Path, PathBuf};
 
-use bittorrent_metainfo::{Info, Mode};
+use bittorrent_metainfo::{File, Info, Mode, Timestamp};
 
 use crate::{error, io, PieceHash};
 
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Entry {
+    pub(crate) path: PathBuf,
+    pub(crate) size: u64,
+    pub(crate) kind: EntryKind,
+    pub(crate) mtime: Option<Timestamp>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) enum EntryKind {
+    Regular { executable: bool },
+    // BEP 47 padding file, which is not stored on disk.
+    Padding,
+    // BEP 47 symlink, whose target is relative to the link's parent directory.
+    Symlink { target: PathBuf },
+}
+
 pub(crate) fn new_piece_hashes(info: &Info) -> Vec<PieceHash> {
     info.pieces
         .iter()
@@ -11,28 +28,93 @@ pub(crate) fn new_piece_hashes(info: &Info) -> Vec<PieceHash> {
         .collect()
 }
 
-pub(crate) fn new_paths(
-    info: &Info,
-    torrent_dir: &Path,
-) -> Result<Vec<(PathBuf, u64)>, error::Error> {
-    let mut paths = Vec::new();
+pub(crate) fn new_entries(info: &Info, torrent_dir: &Path) -> Result<Vec<Entry>, error::Error> {
+    let mut entries = Vec::new();
     let torrent_dir = io::expect_dir(torrent_dir)?;
     match info.mode {
         Mode::SingleFile { length, .. } => {
-            paths.push((torrent_dir.join(io::expect_relpath(info.name)?), length));
+            entries.push(Entry {
+                path: torrent_dir.join(io::expect_relpath(info.name)?),
+                size: length,
+                kind: EntryKind::Regular { executable: false },
+                mtime: None,
+            });
         }
         Mode::MultiFile { ref files } => {
             let info_name = io::expect_relpath(info.name)?;
             for file in files {
-                paths.push((
-                    [Ok(torrent_dir), Ok(info_name)]
+                entries.push(Entry {
+                    path: [Ok(torrent_dir), Ok(info_name)]
                         .into_iter()
                         .chain(file.path.iter().copied().map(io::expect_relpath))
                         .try_collect()?,
-                    file.length,
-                ));
+                    size: file.length,
+                    kind: new_entry_kind(file)?,
+                    mtime: file.mtime,
+                });
             }
         }
     }
-    Ok(paths)
+    Ok(entries)
+}
+
+fn new_entry_kind(file: &File) -> Result<EntryKind, error::Error> {
+    if file.is_padding() {
+        return Ok(EntryKind::Padding);
+    }
+    // BEP 47 specifies that a symlink should have zero length.  We fall back to a regular file
+    // otherwise.
+    if let (true, 0, Some(symlink_path)) = (file.is_symlink(), file.length, &file.symlink_path) {
+        // `symlink_path` is relative to the torrent root, but we create relative symlinks.
+        let target = file
+            .path
+            .iter()
+            .skip(1)
+            .map(|_| Ok(Path::new("..")))
+            .chain(symlink_path.iter().copied().map(io::expect_relpath))
+            .try_collect()?;
+        return Ok(EntryKind::Symlink { target });
+    }
+    Ok(EntryKind::Regular {
+        executable: file.is_executable(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entry_kind() {
+        fn test(path: Vec<&'static str>, attr: &'static str, length: u64, expect: EntryKind) {
+            let mut file = File::new_dummy();
+            file.path = path;
+            file.length = length;
+            file.attr = Some(attr);
+            file.symlink_path = Some(vec!["x", "y"]);
+            assert_eq!(new_entry_kind(&file), Ok(expect));
+        }
+
+        test(vec!["a"], "", 1, EntryKind::Regular { executable: false });
+        test(vec!["a"], "xh", 1, EntryKind::Regular { executable: true });
+        test(vec![".pad", "1"], "p", 1, EntryKind::Padding);
+        test(
+            vec!["a"],
+            "l",
+            0,
+            EntryKind::Symlink {
+                target: "x/y".into(),
+            },
+        );
+        test(
+            vec!["a", "b", "c"],
+            "l",
+            0,
+            EntryKind::Symlink {
+                target: "../../x/y".into(),
+            },
+        );
+        // Fall back to a regular file.
+        test(vec!["a"], "l", 1, EntryKind::Regular { executable: false });
+    }
 }
@@ -1,4 +1,5 @@
 use std::io::Error;
+use std::ops::RangeInclusive;
 use std::path::Path;
 
 use async_trait::async_trait;
@@ -12,8 +13,8 @@ use bittorrent_metainfo::Info;
 
 use crate::{
     coord::CoordSys,
-    io::{self, PieceHasher},
-    metainfo, Bitfield, PieceHash,
+    io::{self, PieceHasher, WriteBuffer},
+    metainfo, Bitfield, FileBlockOffset, PieceHash,
 };
 
 #[derive(Debug)]
@@ -21,6 +22,7 @@ pub struct Storage {
     coord_sys: CoordSys,
     piece_hashes: Vec<PieceHash>,
     file: File,
+    write_buffer: WriteBuffer,
 }
 
 impl Storage {
@@ -35,9 +37,29 @@ impl Storage {
             coord_sys,
             piece_hashes: metainfo::new_piece_hashes(info),
             file: io::open(&path, size).await?,
+            write_buffer: WriteBuffer::new(*crate::write_buffer_size()),
         })
     }
 
+    async fn flush(&mut self) -> Result<(), Error> {
+        if let Some((offset, data)) = self.write_buffer.take() {
+            offset.seek(&mut self.file).await?;
+            self.file.write_all(&data).await?;
+        }
+        Ok(())
+    }
+
+    async fn buffered_write(&mut self, offset: FileBlockOffset, data: &[u8]) -> Result<(), Error> {
+        if !self.write_buffer.is_contiguous(offset) {
+            self.flush().await?;
+        }
+        self.write_buffer.push(offset, data);
+        if self.write_buffer.is_full() {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
     async fn prepare(&mut self, desc: BlockDesc) -> Result<Option<usize>, Error> {
         let BlockDesc(offset, size) = self.coord_sys.check_block_desc(desc)?;
         match self.coord_sys.to_file_offset(offset)? {
@@ -60,6 +82,7 @@ impl Storage {
 #[async_trait]
 impl crate::Storage for Storage {
     async fn scan(&mut self) -> Result<Bitfield, Error> {
+        self.flush().await?;
         let mut bitfield = Bitfield::with_capacity(self.piece_hashes.len());
         let _ = self.prepare((0, 0, 0).into()).await?.unwrap();
         for index in 0..self.piece_hashes.len() {
@@ -78,6 +101,7 @@ impl crate::Storage for Storage {
     }
 
     async fn verify(&mut self, index: PieceIndex) -> Result<bool, Error> {
+        self.flush().await?;
         let size = self.coord_sys.dim.piece_size(index).try_into().unwrap();
         let index = usize::from(index);
         // Do NOT pass `(index, 0, size)` to `prepare` because it is almost certain that `size`
@@ -88,15 +112,24 @@ impl crate::Storage for Storage {
     }
 
     async fn read(&mut self, desc: BlockDesc, buffer: &mut BytesMut) -> Result<(), Error> {
+        self.flush().await?;
         let size = self.prepare(desc).await?.unwrap_or(0);
         assert!(buffer.remaining_mut() >= size);
         self.file.read_buf_exact(&mut buffer.limit(size)).await
     }
 
     async fn write(&mut self, desc: BlockDesc, buffer: &mut Bytes) -> Result<(), Error> {
-        let size = self.prepare(desc).await?.unwrap_or(0);
+        let BlockDesc(offset, size) = self.coord_sys.check_block_desc(desc)?;
+        let Some(offset) = self.coord_sys.to_file_offset(offset)? else {
+            return Ok(());
+        };
+        let size = usize::try_from(size).unwrap();
         assert!(buffer.remaining() >= size);
-        self.file.write_all_buf(&mut buffer.take(size)).await
+        self.buffered_write(offset, &buffer.split_to(size)).await
+    }
+
+    fn file_pieces(&self, file: usize) -> Option<RangeInclusive<PieceIndex>> {
+        (file == 0).then(|| PieceIndex(0)..=PieceIndex(self.coord_sys.dim.num_pieces - 1))
     }
 }
 
@@ -1,4 +1,5 @@
 use std::error::Error;
+use std::net::{Ipv4Addr, SocketAddr};
 use std::path::PathBuf;
 
 use clap::{Parser, ValueEnum};
@@ -8,7 +9,6 @@ use tokio::signal;
 
 use g1_cli::{param::ParametersConfig, tracing::TracingConfig};
 
-use bittorrent_base::InfoHash;
 use bittorrent_bencode::serde as serde_bencode;
 use bittorrent_metainfo::Metainfo;
 use bittorrent_tracker::{
@@ -27,6 +27,8 @@ struct Program {
 
     #[arg(long)]
     tracker: bool,
+    #[arg(long, conflicts_with = "tracker")]
+    scrape: bool,
 
     #[arg(long, default_value_t = 6881)]
     port: u16,
@@ -47,6 +49,7 @@ enum EventEnum {
     Started,
     Completed,
     Stopped,
+    Paused,
 }
 
 #[derive(Debug)]
@@ -67,8 +70,9 @@ impl Program {
         if self.tracker {
             let (tracker, mut tracker_guard) = Tracker::spawn(
                 &metainfo,
-                InfoHash::new(metainfo.info.compute_info_hash()),
-                self.port,
+                metainfo.info.compute_info_hash(),
+                self_id.clone(),
+                SocketAddr::from((Ipv4Addr::UNSPECIFIED, self.port)),
                 Torrent::new(
                     self.num_bytes_send,
                     self.num_bytes_recv,
@@ -89,10 +93,14 @@ impl Program {
             if let Err(error) = tracker_guard.shutdown().await? {
                 return Err(error);
             }
+        } else if self.scrape {
+            let client = Client::new(&metainfo);
+            let response = client.scrape(&[metainfo.info.compute_info_hash()]).await?;
+            println!("{:#?}", response.deref());
         } else {
             let mut client = Client::new(&metainfo);
             let request = Request::new(
-                InfoHash::new(metainfo.info.compute_info_hash()),
+                metainfo.info.compute_info_hash(),
                 self_id,
                 self.port,
                 self.num_bytes_send,
@@ -114,6 +122,7 @@ impl From<EventEnum> for Event {
             EventEnum::Started => Self::Started,
             EventEnum::Completed => Self::Completed,
             EventEnum::Stopped => Self::Stopped,
+            EventEnum::Paused => Self::Paused,
         }
     }
 }
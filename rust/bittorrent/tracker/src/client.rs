@@ -1,52 +1,136 @@
 use std::error::Error;
+use std::net::IpAddr;
 
 use bytes::Bytes;
 use reqwest::StatusCode;
 
+use bittorrent_base::InfoHash;
 use bittorrent_metainfo::Metainfo;
 
 use crate::{
+    error,
     request::{AnnounceUrls, Request},
     response::ResponseOwner,
+    scrape, udp,
 };
 
 #[derive(Debug)]
 pub struct Client {
     urls: AnnounceUrls,
+    client: reqwest::Client,
+    local_address: Option<IpAddr>,
 }
 
 impl Client {
     pub fn new(metainfo: &Metainfo) -> Self {
+        Self::with_local_address(metainfo, None)
+    }
+
+    /// Creates a client whose connections to trackers are bound to `local_address`.
+    pub fn with_local_address(metainfo: &Metainfo, local_address: Option<IpAddr>) -> Self {
         Self {
             urls: AnnounceUrls::new(metainfo),
+            client: reqwest::Client::builder()
+                .local_address(local_address)
+                .timeout(*crate::request_timeout())
+                .build()
+                .unwrap(),
+            local_address,
         }
     }
 
+    /// Announces to the trackers.
+    ///
+    /// It tries the announce URLs in the order specified by BEP 12 until one of them succeeds, and
+    /// returns `AnnounceUrlsFailed` if all of them fail.
     pub async fn get(
         &mut self,
         request: &Request<'_>,
     ) -> Result<ResponseOwner<Bytes>, Box<dyn Error>> {
-        let mut announce_url = self.urls.url().to_string();
-        announce_url.push('?');
+        loop {
+            let announce_url = self.urls.url().to_string();
+            let response = match udp::Url::parse(&announce_url) {
+                Some(Ok(url)) => udp::announce(url, self.local_address, request).await,
+                Some(Err(error)) => Err(error.into()),
+                None => self.announce(&announce_url, request).await,
+            };
+            match response {
+                Ok(response) => match ResponseOwner::try_from(response) {
+                    Ok(response) => {
+                        tracing::debug!(response.body = ?response);
+                        self.urls.succeed();
+                        return Ok(response);
+                    }
+                    Err(error) => tracing::warn!(announce_url, %error, "invalid tracker response"),
+                },
+                Err(error) => tracing::warn!(announce_url, %error, "tracker request error"),
+            }
+            self.urls.fail()?;
+        }
+    }
+
+    async fn announce(&self, url: &str, request: &Request<'_>) -> Result<Bytes, Box<dyn Error>> {
+        let mut announce_url = url.to_string();
+        push_query_separator(&mut announce_url);
         request.append_url_query_to(&mut announce_url);
         tracing::debug!(announce_url);
+        self.fetch(&announce_url).await
+    }
+
+    /// Scrapes the current tracker (BEP 48).
+    pub async fn scrape(
+        &self,
+        info_hashes: &[InfoHash],
+    ) -> Result<scrape::ResponseOwner<Bytes>, Box<dyn Error>> {
+        let announce_url = self.urls.url();
+        let mut scrape_url = scrape::to_scrape_url(announce_url).ok_or_else(|| {
+            error::Error::ScrapeNotSupported {
+                url: announce_url.to_string(),
+            }
+        })?;
+        push_query_separator(&mut scrape_url);
+        scrape::append_url_query_to(info_hashes, &mut scrape_url);
+        tracing::debug!(scrape_url);
 
-        let response = reqwest::get(&announce_url).await?;
+        let response = scrape::ResponseOwner::try_from(self.fetch(&scrape_url).await?)?;
+        tracing::debug!(response.body = ?response);
+        Ok(response)
+    }
+
+    async fn fetch(&self, url: &str) -> Result<Bytes, Box<dyn Error>> {
+        let response = self.client.get(url).send().await?;
         if response.status() == StatusCode::OK {
             tracing::debug!(response.headers = ?response.headers());
-            self.urls.succeed();
         } else {
-            // At the moment, we do not implement retry.
             tracing::warn!(
                 response.status = ?response.status(),
                 response.headers = ?response.headers(),
             );
-            self.urls.fail()?;
+            response.error_for_status_ref()?;
         }
+        Ok(response.bytes().await?)
+    }
+}
 
-        let response = response.bytes().await?;
-        let response = ResponseOwner::try_from(response)?;
-        tracing::debug!(response.body = ?response);
-        Ok(response)
+/// Appends the separator between a URL and the query string that we are going to append.
+///
+/// Some announce URLs already carry a query string (e.g., a passkey).
+fn push_query_separator(url: &mut String) {
+    url.push(if url.contains('?') { '&' } else { '?' });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_query_separator() {
+        let mut url = "http://x/announce".to_string();
+        push_query_separator(&mut url);
+        assert_eq!(url, "http://x/announce?");
+
+        let mut url = "http://x/announce?passkey=y".to_string();
+        push_query_separator(&mut url);
+        assert_eq!(url, "http://x/announce?passkey=y&");
     }
 }
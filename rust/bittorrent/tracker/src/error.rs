@@ -8,6 +8,8 @@ use bittorrent_bencode::{convert, dict, own};
 pub enum Error {
     #[snafu(display("all announce urls failed"))]
     AnnounceUrlsFailed,
+    #[snafu(display("scrape not supported: {url}"))]
+    ScrapeNotSupported { url: String },
 
     #[snafu(display("expect byte string: {value:?}"))]
     ExpectByteString { value: own::Value },
@@ -30,6 +32,8 @@ pub enum Error {
 
     #[snafu(display("tracker failure: {reason}"))]
     Failure { reason: String },
+    #[snafu(display("invalid info_hash: {info_hash}"))]
+    InvalidInfoHash { info_hash: String },
     #[snafu(display("invalid interval: {interval}"))]
     InvalidInterval { interval: i64 },
     #[snafu(display("invalid num_peers: {num_peers}"))]
@@ -40,6 +44,16 @@ pub enum Error {
     InvalidPeerList { peers: own::Value },
     #[snafu(display("invalid port: {port}"))]
     InvalidPort { port: i64 },
+
+    //
+    // BEP 15
+    //
+    #[snafu(display("invalid udp tracker response: {response:?}"))]
+    InvalidUdpResponse { response: Vec<u8> },
+    #[snafu(display("invalid udp tracker url: {url}"))]
+    InvalidUdpUrl { url: String },
+    #[snafu(display("udp tracker timeout"))]
+    UdpTimeout,
 }
 
 impl From<convert::Error> for Error {
@@ -1,12 +1,39 @@
 #![feature(iterator_try_collect)]
 
+use std::time::Duration;
+
 pub mod client;
 pub mod error;
 pub mod request;
 pub mod response;
+pub mod scrape;
 
 mod tracker;
+mod udp;
 
-pub use crate::tracker::{Endpoint, PeerContactInfo, Torrent, Tracker, TrackerGuard};
+pub use crate::tracker::{Endpoint, PeerContactInfo, Status, Torrent, Tracker, TrackerGuard};
 
 g1_param::define!(peer_queue_size: usize = 128);
+
+g1_param::define!(
+    request_timeout: Duration = Duration::from_secs(30);
+    parse = g1_param::parse::duration;
+);
+
+// Backoff of re-announcing after all announce URLs failed.
+g1_param::define!(
+    retry_backoff_base: Duration = Duration::from_secs(15);
+    parse = g1_param::parse::duration;
+);
+g1_param::define!(
+    retry_backoff_max: Duration = Duration::from_secs(1800);
+    parse = g1_param::parse::duration;
+);
+
+// BEP 15 specifies 15 seconds and up to 8 retransmissions, which add up to nearly an hour; we
+// give up much sooner and move on to the next announce URL.
+g1_param::define!(
+    udp_timeout: Duration = Duration::from_secs(15);
+    parse = g1_param::parse::duration;
+);
+g1_param::define!(udp_num_retransmits: usize = 1);
@@ -13,7 +13,7 @@ use crate::error::Error;
 // probably should %-escape all non-alphanumeric characters.
 //
 // [URL spec]: https://url.spec.whatwg.org/#special-query-percent-encode-set
-const QUERY: &AsciiSet = NON_ALPHANUMERIC;
+pub(crate) const QUERY: &AsciiSet = NON_ALPHANUMERIC;
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub(crate) struct AnnounceUrls {
@@ -44,22 +44,25 @@ pub enum Event {
     Started,
     Completed,
     Stopped,
+    // BEP 21 Partial Seeds: We have all the pieces that we want, but not all the pieces.
+    Paused,
 }
 
 impl AnnounceUrls {
     pub(crate) fn new(metainfo: &Metainfo) -> Self {
         Self {
             urls: if let Some(list) = &metainfo.announce_list {
-                let mut rng = rand::thread_rng();
-                list.iter()
-                    .map(|urls| {
-                        let mut urls: VecDeque<_> =
-                            urls.iter().map(|url| url.to_string()).collect();
-                        // Shuffle the URLs as specified by BEP 12.
-                        urls.make_contiguous().shuffle(&mut rng);
-                        urls
-                    })
-                    .collect()
+                g1_base::rand::with_rng(|rng| {
+                    list.iter()
+                        .map(|urls| {
+                            let mut urls: VecDeque<_> =
+                                urls.iter().map(|url| url.to_string()).collect();
+                            // Shuffle the URLs as specified by BEP 12.
+                            urls.make_contiguous().shuffle(rng);
+                            urls
+                        })
+                        .collect()
+                })
             } else if let Some(url) = &metainfo.announce {
                 vec![VecDeque::from([url.to_string()])]
             } else {
@@ -175,6 +178,7 @@ impl Request<'_> {
                 Event::Started => "started",
                 Event::Completed => "completed",
                 Event::Stopped => "stopped",
+                Event::Paused => "paused",
             });
         }
         if let Some(ip) = self.ip {
@@ -396,6 +400,13 @@ mod tests {
             peer_id=%00%00%00%00%00%00%00%00%00%00%00%00%00%00%00%00%00%00%00%00&\
             port=0&uploaded=0&downloaded=0&left=0&compact=1&event=stopped",
         );
+        request.event = Some(Event::Paused);
+        assert_eq!(
+            request.to_string(),
+            "info_hash=%00%00%00%00%00%00%00%00%00%00%00%00%00%00%00%00%00%00%00%00&\
+            peer_id=%00%00%00%00%00%00%00%00%00%00%00%00%00%00%00%00%00%00%00%00&\
+            port=0&uploaded=0&downloaded=0&left=0&compact=1&event=paused",
+        );
         request.event = None;
 
         request.ip = Some("127.0.0.1".parse().unwrap());
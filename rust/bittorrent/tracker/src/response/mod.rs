@@ -1,7 +1,7 @@
 mod serde_impl;
 
 use std::collections::BTreeMap;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::time::Duration;
 
 use serde::Deserialize;
@@ -25,6 +25,7 @@ pub struct Response<'a> {
     pub complete: Option<u64>,
     pub incomplete: Option<u64>,
     pub peers: Vec<PeerContactInfo<'a>>,
+    pub external_ip: Option<IpAddr>, // BEP 24
 
     #[debug(with = FormatDictionary)]
     pub extra: BTreeMap<&'a [u8], borrow::Value<'a>>,
@@ -1,5 +1,5 @@
 use std::collections::BTreeMap;
-use std::net::{IpAddr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
 use std::time::Duration;
 
 use snafu::prelude::*;
@@ -17,6 +17,7 @@ use crate::error::{Error, InvalidPeerIdSnafu};
 use super::{Endpoint, PeerContactInfo, Response};
 
 const COMPLETE: &[u8] = b"complete";
+const EXTERNAL_IP: &[u8] = b"external ip"; // BEP 24 Tracker Returns External IP
 const FAILURE_REASON: &[u8] = b"failure reason";
 const INCOMPLETE: &[u8] = b"incomplete";
 const INTERVAL: &[u8] = b"interval";
@@ -77,6 +78,10 @@ impl<'a> TryFrom<BTreeMap<&'a [u8], borrow::Value<'a>>> for Response<'a> {
                 .map(to_num_peers)
                 .transpose()?,
             peers,
+            external_ip: dict
+                .remove(EXTERNAL_IP)
+                .map(|external_ip| to_bytes(external_ip).and_then(to_external_ip))
+                .transpose()?,
             extra: dict,
         })
     }
@@ -145,6 +150,14 @@ where
     Ok(T::decode_many(peers)?.map(|result| Ok(SocketAddr::from(result?).into())))
 }
 
+fn to_external_ip(external_ip: &[u8]) -> Result<IpAddr, Error> {
+    if external_ip.len() == Ipv4Addr::SIZE {
+        Ok(Ipv4Addr::decode(external_ip)?.into())
+    } else {
+        Ok(Ipv6Addr::decode(external_ip)?.into())
+    }
+}
+
 fn to_peer_id(peer_id: &[u8]) -> Result<&[u8], Error> {
     ensure!(
         peer_id.len() == PEER_ID_SIZE,
@@ -203,6 +216,7 @@ mod tests {
                         "20020000000000000000000000000002 90ab"
                     )),
                 ),
+                (b"external ip", new_bytes(&[10, 0, 0, 1])),
                 (b"x", 5.into()),
             ])),
             Ok(Response {
@@ -229,6 +243,7 @@ mod tests {
                         extra: new_btree_map([]),
                     },
                 ],
+                external_ip: Some("10.0.0.1".parse().unwrap()),
                 extra: new_btree_map([(b"x", 5.into())]),
             }),
         );
@@ -323,6 +338,25 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_to_external_ip() {
+        assert_eq!(
+            to_external_ip(&[127, 0, 0, 1]),
+            Ok("127.0.0.1".parse().unwrap()),
+        );
+        assert_eq!(
+            to_external_ip(&hex!("10010000000000000000000000000001")),
+            Ok("1001::1".parse().unwrap()),
+        );
+        assert_eq!(
+            to_external_ip(&[127, 0, 0]),
+            Err(Error::ExpectCompactSize {
+                size: 3,
+                expect: 16,
+            }),
+        );
+    }
+
     #[test]
     fn test_to_interval() {
         assert_eq!(to_interval(0), Ok(Duration::from_secs(0)));
@@ -0,0 +1,254 @@
+//! BEP 48 Tracker Protocol Extension: Scrape
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use g1_base::fmt::{DebugExt, Hex};
+
+use bittorrent_base::InfoHash;
+use bittorrent_bencode::{
+    borrow, convert::to_dict, dict::DictionaryRemove, serde as serde_bencode, FormatDictionary,
+};
+
+use crate::{error::Error, request::QUERY};
+
+g1_base::define_owner!(#[derive(Debug)] pub ResponseOwner for Response);
+
+#[derive(Clone, DebugExt, Deserialize, Eq, PartialEq)]
+#[serde(try_from = "BTreeMap<&[u8], borrow::Value>")]
+pub struct Response<'a> {
+    pub files: HashMap<InfoHash, File<'a>>,
+    pub min_request_interval: Option<Duration>,
+
+    // Lets the derived `Deserialize` borrow from the input, as `files` alone does not.
+    #[serde(borrow)]
+    #[debug(with = FormatDictionary)]
+    pub extra: BTreeMap<&'a [u8], borrow::Value<'a>>,
+}
+
+#[derive(Clone, DebugExt, Eq, PartialEq)]
+pub struct File<'a> {
+    /// Number of seeders.
+    pub complete: u64,
+    /// Number of completed downloads.
+    pub downloaded: u64,
+    /// Number of leechers.
+    pub incomplete: u64,
+    pub name: Option<&'a str>,
+
+    #[debug(with = FormatDictionary)]
+    pub extra: BTreeMap<&'a [u8], borrow::Value<'a>>,
+}
+
+const COMPLETE: &[u8] = b"complete";
+const DOWNLOADED: &[u8] = b"downloaded";
+const FAILURE_REASON: &[u8] = b"failure reason";
+const FILES: &[u8] = b"files";
+const FLAGS: &[u8] = b"flags";
+const INCOMPLETE: &[u8] = b"incomplete";
+const MIN_REQUEST_INTERVAL: &[u8] = b"min_request_interval";
+const NAME: &[u8] = b"name";
+
+/// Derives the scrape URL from the announce URL, or returns `None` if the tracker does not support
+/// scrape.
+///
+/// BEP 48 specifies that the scrape URL is derived by replacing the "announce" at the beginning
+/// of the last path component with "scrape".
+pub fn to_scrape_url(announce_url: &str) -> Option<String> {
+    let (prefix, last) = announce_url.rsplit_once('/')?;
+    let suffix = last.strip_prefix("announce")?;
+    Some(format!("{prefix}/scrape{suffix}"))
+}
+
+pub fn append_url_query_to(info_hashes: &[InfoHash], query: &mut String) {
+    for (i, info_hash) in info_hashes.iter().enumerate() {
+        if i != 0 {
+            query.push('&');
+        }
+        query.push_str("info_hash=");
+        query.extend(percent_encoding::percent_encode(info_hash.as_ref(), QUERY));
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for Response<'a> {
+    type Error = serde_bencode::Error;
+
+    fn try_from(buffer: &'a [u8]) -> Result<Self, Self::Error> {
+        serde_bencode::from_bytes(buffer)
+    }
+}
+
+impl<'a> TryFrom<BTreeMap<&'a [u8], borrow::Value<'a>>> for Response<'a> {
+    type Error = Error;
+
+    fn try_from(mut dict: BTreeMap<&'a [u8], borrow::Value<'a>>) -> Result<Self, Self::Error> {
+        if let Some(reason) = dict.remove_str::<Error>(FAILURE_REASON)? {
+            return Err(Error::Failure {
+                reason: String::from(reason),
+            });
+        }
+        let files = to_dict::<Error>(dict.must_remove::<Error>(FILES)?)?
+            .0
+            .into_iter()
+            .map(|(info_hash, file)| {
+                Ok::<_, Error>((
+                    to_info_hash(info_hash)?,
+                    to_dict::<Error>(file)?.0.try_into()?,
+                ))
+            })
+            .try_collect()?;
+        let min_request_interval = match dict.remove(FLAGS) {
+            Some(flags) => to_dict::<Error>(flags)?
+                .0
+                .remove_int::<Error>(MIN_REQUEST_INTERVAL)?
+                .map(to_interval)
+                .transpose()?,
+            None => None,
+        };
+        Ok(Self {
+            files,
+            min_request_interval,
+            extra: dict,
+        })
+    }
+}
+
+impl<'a> TryFrom<BTreeMap<&'a [u8], borrow::Value<'a>>> for File<'a> {
+    type Error = Error;
+
+    fn try_from(mut dict: BTreeMap<&'a [u8], borrow::Value<'a>>) -> Result<Self, Self::Error> {
+        // Be lenient and treat missing counts as zero.
+        let mut remove_count = |key| {
+            dict.remove_int::<Error>(key)?
+                .map(to_count)
+                .transpose()
+                .map(Option::unwrap_or_default)
+        };
+        let complete = remove_count(COMPLETE)?;
+        let downloaded = remove_count(DOWNLOADED)?;
+        let incomplete = remove_count(INCOMPLETE)?;
+        Ok(Self {
+            complete,
+            downloaded,
+            incomplete,
+            name: dict.remove_str::<Error>(NAME)?,
+            extra: dict,
+        })
+    }
+}
+
+fn to_info_hash(info_hash: &[u8]) -> Result<InfoHash, Error> {
+    info_hash.try_into().map_err(|_| Error::InvalidInfoHash {
+        info_hash: format!("{:?}", Hex(info_hash)),
+    })
+}
+
+fn to_interval(interval: i64) -> Result<Duration, Error> {
+    Ok(Duration::from_secs(
+        interval
+            .try_into()
+            .map_err(|_| Error::InvalidInterval { interval })?,
+    ))
+}
+
+fn to_count(num_peers: i64) -> Result<u64, Error> {
+    num_peers
+        .try_into()
+        .map_err(|_| Error::InvalidNumPeers { num_peers })
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use super::*;
+
+    #[test]
+    fn test_to_scrape_url() {
+        // Examples of BEP 48.
+        for (announce_url, expect) in [
+            (
+                "http://example.com/announce",
+                Some("http://example.com/scrape"),
+            ),
+            (
+                "http://example.com/x/announce",
+                Some("http://example.com/x/scrape"),
+            ),
+            (
+                "http://example.com/announce.php",
+                Some("http://example.com/scrape.php"),
+            ),
+            ("http://example.com/a", None),
+            (
+                "http://example.com/announce?x2%0644",
+                Some("http://example.com/scrape?x2%0644"),
+            ),
+            ("http://example.com/announce?x=2/4", None),
+            ("http://example.com/x%064announce", None),
+        ] {
+            assert_eq!(to_scrape_url(announce_url).as_deref(), expect);
+        }
+    }
+
+    #[test]
+    fn test_append_url_query_to() {
+        let mut query = String::new();
+        append_url_query_to(&[], &mut query);
+        assert_eq!(query, "");
+
+        let info_hashes = [
+            InfoHash::new(hex!("0123456789abcdef0123456789abcdef01234567")),
+            InfoHash::new([b'a'; 20]),
+        ];
+        append_url_query_to(&info_hashes, &mut query);
+        assert_eq!(
+            query,
+            "info_hash=%01%23Eg%89%AB%CD%EF%01%23Eg%89%AB%CD%EF%01%23Eg&\
+            info_hash=aaaaaaaaaaaaaaaaaaaa",
+        );
+    }
+
+    #[test]
+    fn response() {
+        let info_hash = hex!("0123456789abcdef0123456789abcdef01234567");
+        assert_eq!(
+            Response::try_from(
+                b"d5:filesd20:\x01\x23\x45\x67\x89\xab\xcd\xef\x01\x23\x45\x67\x89\xab\xcd\xef\
+                \x01\x23\x45\x67d8:completei1e10:downloadedi2e10:incompletei3e4:name3:fooee\
+                5:flagsd20:min_request_intervali60eee"
+                    .as_slice()
+            )
+            .unwrap(),
+            Response {
+                files: HashMap::from([(
+                    InfoHash::new(info_hash),
+                    File {
+                        complete: 1,
+                        downloaded: 2,
+                        incomplete: 3,
+                        name: Some("foo"),
+                        extra: BTreeMap::new(),
+                    },
+                )]),
+                min_request_interval: Some(Duration::from_secs(60)),
+                extra: BTreeMap::new(),
+            },
+        );
+
+        assert_eq!(
+            Response::try_from(b"d5:filesdee".as_slice()).unwrap(),
+            Response {
+                files: HashMap::new(),
+                min_request_interval: None,
+                extra: BTreeMap::new(),
+            },
+        );
+
+        assert!(Response::try_from(b"d14:failure reason3:xyze".as_slice()).is_err());
+        assert!(Response::try_from(b"d5:filesd3:xyzdeee".as_slice()).is_err());
+        assert!(Response::try_from(b"de".as_slice()).is_err());
+    }
+}
@@ -1,5 +1,6 @@
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
+use std::time::Duration;
 
 use futures::future::OptionFuture;
 use tokio::{
@@ -15,7 +16,6 @@ use bittorrent_metainfo::Metainfo;
 
 use crate::{
     client::Client,
-    error,
     request::{Event, Request},
     response,
 };
@@ -41,11 +41,22 @@ pub enum Endpoint {
     DomainName(String, u16),
 }
 
+/// What the tracker told us in its latest response, aside from peers.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Status {
+    /// Private trackers use warnings to communicate issues like low share ratio.
+    pub warning_message: Option<String>,
+    /// BEP 24 external IP.
+    pub external_ip: Option<IpAddr>,
+}
+
 #[derive(Clone, Debug)]
 pub struct Tracker {
     // Wrap it in an `Arc` so that `Clone` can be derived for `Tracker`.
     event_send: Arc<watch::Sender<Option<Event>>>,
     peer_recv: mpmc::Receiver<PeerContactInfo>,
+    status_send: Arc<watch::Sender<Status>>,
+    port_send: Arc<watch::Sender<u16>>,
 }
 
 pub type TrackerGuard = JoinGuard<Result<(), Error>>;
@@ -56,14 +67,29 @@ struct Actor<T> {
 
     info_hash: InfoHash,
     self_id: PeerId,
-    port: u16,
+    port_recv: watch::Receiver<u16>,
     torrent: T,
 
     client: Client,
     next_request_at: Option<Instant>,
+    retry_backoff: Duration,
+
+    /// Identifies us to trackers in case our IP address changes.
+    key: String,
+    tracker_id: Option<String>,
 
     event_recv: watch::Receiver<Option<Event>>,
     peer_send: mpmc::Sender<PeerContactInfo>,
+    status_send: Arc<watch::Sender<Status>>,
+}
+
+impl<'a> From<&'a response::Response<'a>> for Status {
+    fn from(response: &'a response::Response<'a>) -> Self {
+        Self {
+            warning_message: response.warning_message.map(String::from),
+            external_ip: response.external_ip,
+        }
+    }
 }
 
 impl<'a> From<&'a response::PeerContactInfo<'a>> for PeerContactInfo {
@@ -87,10 +113,15 @@ impl<'a> From<&'a response::Endpoint<'a>> for Endpoint {
 }
 
 impl Tracker {
+    /// Spawns a tracker actor.
+    ///
+    /// It announces `self_id` and the port of `self_endpoint`, and unless the latter is the
+    /// unspecified address, binds connections to trackers to its address.
     pub fn spawn<T>(
         metainfo: &Metainfo,
         info_hash: InfoHash,
-        port: u16,
+        self_id: PeerId,
+        self_endpoint: SocketAddr,
         torrent: T,
     ) -> (Self, TrackerGuard)
     where
@@ -99,21 +130,27 @@ impl Tracker {
     {
         let (event_send, event_recv) = watch::channel(None);
         let (peer_send, peer_recv) = mpmc::channel(*crate::peer_queue_size());
+        let status_send = Arc::new(watch::channel(Status::default()).0);
+        let (port_send, port_recv) = watch::channel(self_endpoint.port());
         (
             Self {
                 event_send: Arc::new(event_send),
                 peer_recv,
+                status_send: status_send.clone(),
+                port_send: Arc::new(port_send),
             },
             JoinGuard::spawn(move |cancel| {
                 Actor::new(
                     cancel,
                     metainfo,
                     info_hash,
-                    bittorrent_base::self_id().clone(),
-                    port,
+                    self_id,
+                    self_endpoint,
+                    port_recv,
                     torrent,
                     event_recv,
                     peer_send,
+                    status_send,
                 )
                 .run()
             }),
@@ -132,6 +169,11 @@ impl Tracker {
         self.send_event(Some(Event::Stopped));
     }
 
+    /// Announces that we are a partial seed (BEP 21).
+    pub fn pause(&self) {
+        self.send_event(Some(Event::Paused));
+    }
+
     fn send_event(&self, new_event: Option<Event>) {
         self.event_send.send_if_modified(|event| {
             if event == &new_event {
@@ -139,7 +181,12 @@ impl Tracker {
             }
             let accept = match event {
                 None => true,
-                Some(Event::Started) => {
+                Some(Event::Started) => matches!(
+                    new_event,
+                    Some(Event::Completed) | Some(Event::Stopped) | Some(Event::Paused),
+                ),
+                // A partial seed may select more pieces and then complete the download.
+                Some(Event::Paused) => {
                     matches!(new_event, Some(Event::Completed) | Some(Event::Stopped))
                 }
                 Some(Event::Completed) => matches!(new_event, Some(Event::Stopped)),
@@ -158,6 +205,28 @@ impl Tracker {
     pub async fn next(&self) -> Option<PeerContactInfo> {
         self.peer_recv.recv().await
     }
+
+    pub fn status(&self) -> Status {
+        self.status_send.borrow().clone()
+    }
+
+    /// Subscribes to status changes.
+    pub fn subscribe(&self) -> watch::Receiver<Status> {
+        self.status_send.subscribe()
+    }
+
+    /// Changes the port that we announce (e.g., to the external port that the gateway maps), and
+    /// re-announces if we have announced before.
+    pub fn set_port(&self, new_port: u16) {
+        self.port_send.send_if_modified(|port| {
+            if *port == new_port {
+                return false;
+            }
+            tracing::info!(port = new_port, "announce port");
+            *port = new_port;
+            true
+        });
+    }
 }
 
 impl<T> Actor<T> {
@@ -167,21 +236,28 @@ impl<T> Actor<T> {
         metainfo: &Metainfo,
         info_hash: InfoHash,
         self_id: PeerId,
-        port: u16,
+        self_endpoint: SocketAddr,
+        port_recv: watch::Receiver<u16>,
         torrent: T,
         event_recv: watch::Receiver<Option<Event>>,
         peer_send: mpmc::Sender<PeerContactInfo>,
+        status_send: Arc<watch::Sender<Status>>,
     ) -> Self {
+        let local_address = Some(self_endpoint.ip()).filter(|ip| !ip.is_unspecified());
         Self {
             cancel,
             info_hash,
             self_id,
-            port,
+            port_recv,
             torrent,
-            client: Client::new(metainfo),
+            client: Client::with_local_address(metainfo, local_address),
             next_request_at: None,
+            retry_backoff: *crate::retry_backoff_base(),
+            key: format!("{:08x}", rand::random::<u32>()),
+            tracker_id: None,
             event_recv,
             peer_send,
+            status_send,
         }
     }
 }
@@ -212,6 +288,12 @@ where
                         self.request(event).await?;
                     }
                 }
+                Ok(()) = self.port_recv.changed() => {
+                    // Do not announce before `Tracker::start` is called.
+                    if self.next_request_at.is_some() {
+                        self.request(None).await?;
+                    }
+                }
                 Some(()) = &mut timeout => {
                     self.request(None).await?;
                 }
@@ -223,32 +305,48 @@ where
     async fn request(&mut self, event: Option<Event>) -> Result<(), Error> {
         tracing::info!(?event, "->tracker");
 
-        let request = Request::new(
+        let mut request = Request::new(
             self.info_hash.clone(),
             self.self_id.clone(),
-            self.port,
+            *self.port_recv.borrow(),
             self.torrent.num_bytes_send(),
             self.torrent.num_bytes_recv(),
             self.torrent.num_bytes_left(),
             event,
         );
+        request.key = Some(&self.key);
+        request.tracker_id = self.tracker_id.as_deref();
 
         let response_owner = match self.client.get(&request).await {
             Ok(response_owner) => response_owner,
             Err(error) => {
-                if matches!(
-                    error.downcast_ref::<error::Error>(),
-                    Some(error::Error::AnnounceUrlsFailed),
-                ) {
-                    return Err(error::Error::AnnounceUrlsFailed.into());
-                }
-                tracing::warn!(%error, "tracker error");
-                return Ok(()); // For now, we ignore all other types of error.
+                // Rather than giving up, we keep retrying with exponential backoff, in case the
+                // trackers are only temporarily unavailable.
+                tracing::warn!(%error, retry_backoff = ?self.retry_backoff, "tracker error");
+                self.next_request_at = Some(Instant::now() + self.retry_backoff);
+                self.retry_backoff = (self.retry_backoff * 2).min(*crate::retry_backoff_max());
+                return Ok(());
             }
         };
         let response = response_owner.deref();
 
+        self.retry_backoff = *crate::retry_backoff_base();
         self.next_request_at = Some(Instant::now() + response.interval);
+        if let Some(tracker_id) = response.tracker_id {
+            self.tracker_id = Some(tracker_id.to_string());
+        }
+
+        let new_status = Status::from(response);
+        if let Some(warning_message) = &new_status.warning_message {
+            tracing::warn!(%warning_message, "tracker warning");
+        }
+        self.status_send.send_if_modified(|status| {
+            if *status == new_status {
+                return false;
+            }
+            *status = new_status;
+            true
+        });
 
         for peer in &response.peers {
             match self.peer_send.try_send(peer.into()) {
@@ -0,0 +1,439 @@
+//! UDP Tracker Protocol (BEP 15)
+//!
+//! Announces go through the SOCKS5 proxy (with UDP ASSOCIATE) when `socks5_proxy` is set.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use tokio::net::{TcpSocket, UdpSocket};
+use tokio::time;
+
+use g1_tokio::net::socks5::{Address, UdpAssociation};
+
+use bittorrent_bencode::own;
+
+use crate::error::Error;
+use crate::request::{Event, Request};
+
+const PROTOCOL_ID: u64 = 0x41727101980;
+
+const ACTION_CONNECT: u32 = 0;
+const ACTION_ANNOUNCE: u32 = 1;
+const ACTION_ERROR: u32 = 3;
+
+const EVENT_NONE: u32 = 0;
+const EVENT_COMPLETED: u32 = 1;
+const EVENT_STARTED: u32 = 2;
+const EVENT_STOPPED: u32 = 3;
+
+const NUM_WANT_DEFAULT: i32 = -1;
+
+/// UDP tracker URL, e.g., `udp://tracker.example.com:6969/announce`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Url<'a> {
+    host: &'a str,
+    port: u16,
+}
+
+#[derive(Debug)]
+enum Transport {
+    Direct(UdpSocket),
+    Socks5(UdpAssociation, Address),
+}
+
+impl<'a> Url<'a> {
+    /// Parses a UDP tracker URL, or returns `None` if it is not one.
+    pub(crate) fn parse(url: &'a str) -> Option<Result<Self, Error>> {
+        let authority = url.strip_prefix("udp://")?;
+        // Strip the path (and the query string), which BEP 41 defines but we do not support.
+        let authority = authority.split(['/', '?']).next().unwrap();
+        Some(
+            authority
+                .rsplit_once(':')
+                .and_then(|(host, port)| {
+                    let host = host
+                        .strip_prefix('[')
+                        .and_then(|host| host.strip_suffix(']'))
+                        .unwrap_or(host);
+                    Some(Self {
+                        host,
+                        port: port.parse().ok()?,
+                    })
+                })
+                .filter(|url| !url.host.is_empty())
+                .ok_or_else(|| Error::InvalidUdpUrl {
+                    url: url.to_string(),
+                }),
+        )
+    }
+
+    fn address(&self) -> Address {
+        match self.host.parse::<IpAddr>() {
+            Ok(ip) => SocketAddr::new(ip, self.port).into(),
+            Err(_) => Address::DomainName(self.host.to_string(), self.port),
+        }
+    }
+}
+
+/// Announces to a UDP tracker and returns the response in the encoding of HTTP trackers.
+pub(crate) async fn announce(
+    url: Url<'_>,
+    local_address: Option<IpAddr>,
+    request: &Request<'_>,
+) -> Result<Bytes, Box<dyn std::error::Error>> {
+    let transport = match *bittorrent_base::socks5_proxy() {
+        Some(proxy) => Transport::socks5(proxy, local_address, url.address()).await?,
+        None => Transport::direct(&url, local_address).await?,
+    };
+
+    let transaction_id = rand::random();
+    let (response, _) = transport
+        .request(&encode_connect(transaction_id), transaction_id)
+        .await?;
+    let connection_id = decode_connect(response)?;
+
+    let transaction_id = rand::random();
+    let (response, tracker) = transport
+        .request(
+            &encode_announce(connection_id, transaction_id, request),
+            transaction_id,
+        )
+        .await?;
+    Ok(decode_announce(response, tracker.is_ipv6())?)
+}
+
+impl Transport {
+    async fn direct(url: &Url<'_>, local_address: Option<IpAddr>) -> Result<Self, io::Error> {
+        let tracker = g1_tokio::net::lookup_host_first((url.host, url.port)).await?;
+        let socket = UdpSocket::bind(bind_endpoint(tracker, local_address)).await?;
+        socket.connect(tracker).await?;
+        Ok(Self::Direct(socket))
+    }
+
+    async fn socks5(
+        proxy: SocketAddr,
+        local_address: Option<IpAddr>,
+        tracker: Address,
+    ) -> Result<Self, io::Error> {
+        let control = if proxy.is_ipv4() {
+            TcpSocket::new_v4()?
+        } else {
+            TcpSocket::new_v6()?
+        };
+        let bind_endpoint = bind_endpoint(proxy, local_address);
+        if !bind_endpoint.ip().is_unspecified() {
+            control.bind(bind_endpoint)?;
+        }
+        let control = control.connect(proxy).await?;
+        let socket = UdpSocket::bind(bind_endpoint).await?;
+        Ok(Self::Socks5(
+            UdpAssociation::new(control, socket).await?,
+            tracker,
+        ))
+    }
+
+    /// Sends a request, retransmitting it on timeout, and returns the response and the address of
+    /// the tracker.
+    async fn request(
+        &self,
+        request: &[u8],
+        transaction_id: u32,
+    ) -> Result<(Bytes, SocketAddr), Box<dyn std::error::Error>> {
+        let mut timeout = *crate::udp_timeout();
+        for _ in 0..=*crate::udp_num_retransmits() {
+            self.send(request).await?;
+            match time::timeout(timeout, self.recv_response(transaction_id)).await {
+                Ok(result) => return result,
+                Err(_) => {
+                    tracing::debug!(?timeout, "udp tracker timeout");
+                    // BEP 15 doubles the timeout on each retransmission.
+                    timeout *= 2;
+                }
+            }
+        }
+        Err(Error::UdpTimeout.into())
+    }
+
+    async fn send(&self, payload: &[u8]) -> Result<(), io::Error> {
+        match self {
+            Self::Direct(socket) => socket.send(payload).await.map(|_| ()),
+            Self::Socks5(association, tracker) => association.send_to(payload, tracker).await,
+        }
+    }
+
+    async fn recv(&self) -> Result<(Bytes, SocketAddr), io::Error> {
+        match self {
+            Self::Direct(socket) => {
+                let mut buffer = BytesMut::zeroed(65536);
+                let size = socket.recv(&mut buffer).await?;
+                buffer.truncate(size);
+                Ok((buffer.freeze(), socket.peer_addr()?))
+            }
+            Self::Socks5(association, _) => {
+                let (payload, source) = association.recv_from().await?;
+                let source = match source {
+                    Address::SocketAddr(source) => source,
+                    // The proxy should reply with the tracker IP address, but if it does not, we
+                    // assume that it is an IPv4 address.
+                    Address::DomainName(..) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+                };
+                Ok((payload.freeze(), source))
+            }
+        }
+    }
+
+    async fn recv_response(
+        &self,
+        transaction_id: u32,
+    ) -> Result<(Bytes, SocketAddr), Box<dyn std::error::Error>> {
+        loop {
+            let (mut response, tracker) = self.recv().await?;
+            if response.len() < 8 {
+                tracing::debug!(?response, "drop truncated udp tracker response");
+                continue;
+            }
+            let action = response.get_u32();
+            if response.get_u32() != transaction_id {
+                tracing::debug!("drop udp tracker response of another transaction");
+                continue;
+            }
+            if action == ACTION_ERROR {
+                return Err(Error::Failure {
+                    reason: String::from_utf8_lossy(&response).into_owned(),
+                }
+                .into());
+            }
+            return Ok((response, tracker));
+        }
+    }
+}
+
+fn bind_endpoint(peer: SocketAddr, local_address: Option<IpAddr>) -> SocketAddr {
+    // Bind to `local_address` only when it is of the same address family as `peer`.
+    let ip = match (peer, local_address) {
+        (SocketAddr::V4(_), Some(ip @ IpAddr::V4(_))) => ip,
+        (SocketAddr::V6(_), Some(ip @ IpAddr::V6(_))) => ip,
+        (SocketAddr::V4(_), _) => Ipv4Addr::UNSPECIFIED.into(),
+        (SocketAddr::V6(_), _) => Ipv6Addr::UNSPECIFIED.into(),
+    };
+    SocketAddr::new(ip, 0)
+}
+
+fn encode_connect(transaction_id: u32) -> BytesMut {
+    let mut buffer = BytesMut::with_capacity(16);
+    buffer.put_u64(PROTOCOL_ID);
+    buffer.put_u32(ACTION_CONNECT);
+    buffer.put_u32(transaction_id);
+    buffer
+}
+
+/// Decodes the response body (after the action and the transaction id).
+fn decode_connect(mut response: Bytes) -> Result<u64, Error> {
+    if response.len() < 8 {
+        return Err(Error::InvalidUdpResponse {
+            response: response.to_vec(),
+        });
+    }
+    Ok(response.get_u64())
+}
+
+fn encode_announce(connection_id: u64, transaction_id: u32, request: &Request<'_>) -> BytesMut {
+    let mut buffer = BytesMut::with_capacity(98);
+    buffer.put_u64(connection_id);
+    buffer.put_u32(ACTION_ANNOUNCE);
+    buffer.put_u32(transaction_id);
+    buffer.put_slice(request.info_hash.as_ref());
+    buffer.put_slice(request.self_id.as_ref());
+    buffer.put_u64(request.downloaded);
+    buffer.put_u64(request.left);
+    buffer.put_u64(request.uploaded);
+    buffer.put_u32(match request.event {
+        None => EVENT_NONE,
+        Some(Event::Completed) => EVENT_COMPLETED,
+        Some(Event::Started) => EVENT_STARTED,
+        Some(Event::Stopped) => EVENT_STOPPED,
+        // BEP 15 does not define the paused event.
+        Some(Event::Paused) => EVENT_NONE,
+    });
+    buffer.put_u32(match request.ip {
+        Some(IpAddr::V4(ip)) => ip.into(),
+        _ => 0,
+    });
+    // Our key is a hex string, and so we send it as is if it fits.
+    buffer.put_u32(
+        request
+            .key
+            .and_then(|key| u32::from_str_radix(key, 16).ok())
+            .unwrap_or(0),
+    );
+    buffer.put_i32(request.num_want.map_or(NUM_WANT_DEFAULT, i32::from));
+    buffer.put_u16(request.port);
+    buffer
+}
+
+/// Decodes the response body (after the action and the transaction id) and re-encodes it in the
+/// encoding of HTTP trackers, so that `ResponseOwner` may decode it.
+fn decode_announce(mut response: Bytes, is_ipv6: bool) -> Result<Bytes, Error> {
+    if response.len() < 12 {
+        return Err(Error::InvalidUdpResponse {
+            response: response.to_vec(),
+        });
+    }
+    let interval = response.get_u32();
+    let leechers = response.get_u32();
+    let seeders = response.get_u32();
+
+    fn key(key: &[u8]) -> own::ByteString {
+        key.into()
+    }
+    let peers = own::Value::from(own::ByteString::from(response.as_ref()));
+    let mut dict = BTreeMap::from([
+        (key(b"interval"), i64::from(interval).into()),
+        (key(b"incomplete"), i64::from(leechers).into()),
+        (key(b"complete"), i64::from(seeders).into()),
+    ]);
+    // Peers are of the address family of the tracker.
+    if is_ipv6 {
+        dict.insert(key(b"peers"), own::Value::from(own::ByteString::new()));
+        dict.insert(key(b"peers6"), peers);
+    } else {
+        dict.insert(key(b"peers"), peers);
+    }
+
+    let mut buffer = BytesMut::new();
+    own::Value::from(dict).encode(&mut buffer);
+    Ok(buffer.freeze())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use hex_literal::hex;
+
+    use bittorrent_base::{InfoHash, PeerId};
+
+    use crate::response::ResponseOwner;
+
+    use super::*;
+
+    #[test]
+    fn parse() {
+        fn test(url: &str, expect: Option<Result<Url, Error>>) {
+            assert_eq!(Url::parse(url), expect);
+        }
+
+        test("http://x/announce", None);
+        test(
+            "udp://tracker.example.com:6969/announce",
+            Some(Ok(Url {
+                host: "tracker.example.com",
+                port: 6969,
+            })),
+        );
+        test(
+            "udp://127.0.0.1:80",
+            Some(Ok(Url {
+                host: "127.0.0.1",
+                port: 80,
+            })),
+        );
+        test(
+            "udp://[::1]:80?passkey=x",
+            Some(Ok(Url {
+                host: "::1",
+                port: 80,
+            })),
+        );
+        for url in ["udp://x/announce", "udp://:80", "udp://x:y"] {
+            test(
+                url,
+                Some(Err(Error::InvalidUdpUrl {
+                    url: url.to_string(),
+                })),
+            );
+        }
+
+        assert_eq!(
+            Url::parse("udp://[::1]:80").unwrap().unwrap().address(),
+            Address::SocketAddr("[::1]:80".parse().unwrap()),
+        );
+        assert_eq!(
+            Url::parse("udp://x:80").unwrap().unwrap().address(),
+            Address::DomainName("x".to_string(), 80),
+        );
+    }
+
+    #[test]
+    fn connect() {
+        assert_eq!(
+            encode_connect(0x01020304),
+            hex!("00000417 27101980 00000000 01020304").as_slice(),
+        );
+        assert_eq!(
+            decode_connect(Bytes::from_static(&hex!("0102030405060708"))),
+            Ok(0x0102030405060708),
+        );
+        assert!(decode_connect(Bytes::from_static(&hex!("01020304"))).is_err());
+    }
+
+    #[test]
+    fn announce() {
+        let mut request = Request::new(
+            InfoHash::new([1; 20]),
+            PeerId::new([2; 20]),
+            0x1234,
+            3,
+            4,
+            5,
+            Some(Event::Started),
+        );
+        request.key = Some("0a0b0c0d");
+        assert_eq!(
+            encode_announce(0x0102030405060708, 9, &request),
+            [
+                hex!("0102030405060708 00000001 00000009").as_slice(),
+                &[1; 20],
+                &[2; 20],
+                &hex!("0000000000000004 0000000000000005 0000000000000003"),
+                &hex!("00000002 00000000 0a0b0c0d 00000040 1234"),
+            ]
+            .concat(),
+        );
+    }
+
+    #[test]
+    fn test_decode_announce() {
+        let response = decode_announce(
+            Bytes::from_static(&hex!("0000012c 00000002 00000003 7f000001 1a2b")),
+            false,
+        )
+        .unwrap();
+        let response = ResponseOwner::try_from(response).unwrap();
+        let response = response.deref();
+        assert_eq!(response.interval, Duration::from_secs(300));
+        assert_eq!(response.incomplete, Some(2));
+        assert_eq!(response.complete, Some(3));
+        assert_eq!(
+            response.peers,
+            vec!["127.0.0.1:6699".parse::<SocketAddr>().unwrap().into()],
+        );
+
+        let response = decode_announce(
+            Bytes::from_static(&hex!(
+                "0000012c 00000000 00000000 00000000000000000000000000000001 1a2b"
+            )),
+            true,
+        )
+        .unwrap();
+        let response = ResponseOwner::try_from(response).unwrap();
+        assert_eq!(
+            response.deref().peers,
+            vec!["[::1]:6699".parse::<SocketAddr>().unwrap().into()],
+        );
+
+        assert!(decode_announce(Bytes::from_static(&hex!("0000012c")), false).is_err());
+    }
+}
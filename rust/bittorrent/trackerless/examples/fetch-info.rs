@@ -23,7 +23,7 @@ use bittorrent_extension::Enabled;
 use bittorrent_manager::{Manager, ManagerGuard};
 use bittorrent_peer::Recvs;
 use bittorrent_trackerless::{InfoOwner, Trackerless};
-use bittorrent_utp::UtpSocket;
+use bittorrent_utp::{UtpConfig, UtpSocket};
 
 type Fork = bittorrent_udp::Fork<OwnedUdpStream>;
 type Fanin = sink::Fanin<OwnedUdpSink>;
@@ -68,7 +68,7 @@ impl Program {
             return Err(Error::other("no peers available"));
         }
 
-        let mut utp_socket = UtpSocket::new(udp_socket, utp_stream, utp_sink);
+        let mut utp_socket = UtpSocket::new(udp_socket, utp_stream, utp_sink, UtpConfig::default());
 
         let (manager, mut recvs, mut manager_guard) = self.new_manager(&utp_socket)?;
         for peer_endpoint in peer_endpoints {
@@ -141,6 +141,7 @@ impl Program {
             };
         Ok(Manager::spawn(
             self.info_hash.clone(),
+            bittorrent_base::self_id().clone(),
             tcp_listener_v4,
             tcp_listener_v6,
             utp_socket_v4,
@@ -160,7 +161,7 @@ impl Program {
         dht_stream: Fork,
         dht_sink: Fanin,
     ) -> Result<(BTreeSet<SocketAddr>, DhtGuard), Error> {
-        let (dht, guard) = Dht::spawn(udp_socket.local_addr()?, dht_stream, dht_sink);
+        let (dht, guard) = Dht::spawn(udp_socket.local_addr()?, None, dht_stream, dht_sink);
         let (peers, _) = dht.lookup_peers(self.info_hash.clone()).await;
         Ok((peers, guard))
     }
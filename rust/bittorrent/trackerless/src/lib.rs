@@ -1,12 +1,15 @@
-use std::collections::VecDeque;
+use std::time::Instant;
 
 use bytes::{Bytes, BytesMut};
 use snafu::prelude::*;
 use tokio::sync::broadcast::{error::RecvError, Receiver};
+use tokio::time;
 
 use bittorrent_base::{Features, InfoHash};
 use bittorrent_bencode::serde as serde_bencode;
-use bittorrent_extension::{Enabled, Handshake, Message, Metadata, PeerExchange, Reject, Request};
+use bittorrent_extension::{
+    Enabled, Handshake, Message, Metadata, MetadataFetcher, PeerExchange, Reject,
+};
 use bittorrent_manager::{Endpoint, Manager, Update};
 use bittorrent_peer::{ExtensionMessageOwner, Peer, Recvs};
 
@@ -15,12 +18,6 @@ pub enum Error {
     ExtensionChannelClosed,
     PeerUpdateChannelClosed,
 
-    #[snafu(display("expect info_hash == {expect:?}: {info_hash:?}"))]
-    ExpectInfoHash {
-        info_hash: InfoHash,
-        expect: InfoHash,
-    },
-
     #[snafu(display("decode error: {source}"))]
     Decode {
         source: serde_bencode::Error,
@@ -33,16 +30,12 @@ pub enum Error {
 
 #[derive(Debug)]
 pub struct Trackerless<'a> {
-    info_hash: InfoHash,
     self_extensions: Enabled,
     manager: &'a Manager,
     peer_update_recv: Receiver<(Endpoint, Update)>,
     recvs: &'a mut Recvs,
 
-    info: BytesMut,
-    metadata_size: Option<usize>,
-    pieces: VecDeque<usize>,
-    inflights: VecDeque<usize>,
+    fetcher: MetadataFetcher<Endpoint>,
 }
 
 pub type InfoOwner = bittorrent_metainfo::InfoOwner<Bytes>;
@@ -54,83 +47,35 @@ impl<'a> Trackerless<'a> {
         assert!(self_extensions.metadata);
         let peer_update_recv = manager.subscribe();
         Self {
-            info_hash,
             self_extensions,
             manager,
             peer_update_recv,
             recvs,
 
-            info: BytesMut::new(),
-            metadata_size: None,
-            pieces: VecDeque::new(),
-            inflights: VecDeque::new(),
+            fetcher: MetadataFetcher::new(info_hash),
         }
     }
 
     pub async fn fetch(mut self) -> Result<InfoOwner, Error> {
-        while !self.is_completed() {
-            tokio::select! {
+        loop {
+            let deadline = self.fetcher.next_deadline();
+            let timeout = time::sleep_until(deadline.unwrap_or_else(Instant::now).into());
+            let info = tokio::select! {
                 update = self.peer_update_recv.recv() => {
                     self.handle_peer_update(update)?;
+                    None
                 }
                 extension = self.recvs.extension_recv.recv() => {
-                    self.handle_extension(extension)?;
+                    self.handle_extension(extension)?
                 }
+                () = timeout, if deadline.is_some() => None,
+            };
+            if let Some(info) = info {
+                // `MetadataFetcher` has verified the info hash.
+                return InfoOwner::try_from(info).context(DecodeSnafu);
             }
+            self.send_requests();
         }
-        self.finish()
-    }
-
-    //
-    // Fetch State
-    //
-
-    fn is_completed(&self) -> bool {
-        self.metadata_size.is_some() && self.pieces.is_empty() && self.inflights.is_empty()
-    }
-
-    fn next_piece(&mut self) -> Option<usize> {
-        Some(match self.pieces.pop_front() {
-            Some(piece) => {
-                self.inflights.push_back(piece);
-                piece
-            }
-            None => {
-                // All piece requests have been sent out, let us re-send them in case some fail.
-                // This does not seem particularly efficient, but it should suffice for now.
-                let piece = self.inflights.front().copied()?;
-                self.inflights.rotate_left(1);
-                piece
-            }
-        })
-    }
-
-    fn remove_inflight(&mut self, piece: usize) -> bool {
-        for (i, inflight) in self.inflights.iter().copied().enumerate() {
-            if inflight == piece {
-                self.inflights.swap_remove_back(i);
-                return true;
-            }
-        }
-        false
-    }
-
-    fn finish(self) -> Result<InfoOwner, Error> {
-        let Self {
-            info_hash: expect,
-            info,
-            ..
-        } = self;
-        let info = InfoOwner::try_from(info.freeze()).context(DecodeSnafu)?;
-        let info_hash = info.deref().compute_info_hash();
-        ensure!(
-            info_hash == expect.as_ref(),
-            ExpectInfoHashSnafu {
-                info_hash: InfoHash::new(info_hash),
-                expect,
-            },
-        );
-        Ok(info)
     }
 
     //
@@ -155,7 +100,7 @@ impl<'a> Trackerless<'a> {
                             self.send_handshake(&peer);
                         }
                     }
-                    Update::Stop => {} // Do nothing for now.
+                    Update::Stop => self.fetcher.remove_peer(&peer_endpoint),
                 }
                 Ok(())
             }
@@ -167,10 +112,11 @@ impl<'a> Trackerless<'a> {
         }
     }
 
+    /// Returns the info blob once it is fetched.
     fn handle_extension(
         &mut self,
         extension: Option<(Endpoint, ExtensionMessageOwner)>,
-    ) -> Result<(), Error> {
+    ) -> Result<Option<Bytes>, Error> {
         let (peer_endpoint, message) = extension.context(ExtensionChannelClosedSnafu)?;
 
         let span = tracing::info_span!("trackerless", ?peer_endpoint);
@@ -180,13 +126,13 @@ impl<'a> Trackerless<'a> {
             ($predicate:expr, $log:expr $(,)?) => {
                 if !$predicate {
                     tracing::warn!(?message, $log);
-                    return Ok(());
+                    return Ok(None);
                 }
             };
         }
 
         let Some(peer) = self.manager.get(peer_endpoint) else {
-            return Ok(());
+            return Ok(None);
         };
         ensure_peer!(
             peer.peer_features().extension,
@@ -201,7 +147,7 @@ impl<'a> Trackerless<'a> {
                     peer.peer_extensions().metadata,
                     "peer claims non-support for metadata extension",
                 );
-                self.handle_metadata(&peer, metadata);
+                return Ok(self.handle_metadata(&peer, metadata));
             }
             Message::PeerExchange(peer_exchange) => {
                 assert!(self.self_extensions.peer_exchange);
@@ -211,66 +157,65 @@ impl<'a> Trackerless<'a> {
                 );
                 self.handle_peer_exchange(peer_exchange)?;
             }
+            Message::Holepunch(_) => {
+                ensure_peer!(
+                    self.self_extensions.holepunch,
+                    "peer sends holepunch message without our support",
+                );
+            }
+            Message::DontHave(_) => {
+                ensure_peer!(
+                    self.self_extensions.donthave,
+                    "peer sends donthave message without our support",
+                );
+            }
+            Message::Custom(custom) => {
+                tracing::debug!(name = custom.name, "drop custom extension message");
+            }
         }
-        Ok(())
+        Ok(None)
     }
 
     fn handle_handshake(&mut self, peer: &Peer, handshake: &Handshake) {
-        if self.metadata_size.is_none() {
-            assert!(self.pieces.is_empty());
-            let Some(metadata_size) = handshake.metadata_size else {
-                return;
-            };
-            self.info.resize(metadata_size, 0);
-            self.metadata_size = Some(metadata_size);
-            self.pieces.extend(0..Metadata::num_pieces(metadata_size));
-
-            for peer in self.manager.peers() {
-                if peer.peer_features().extension && peer.peer_extensions().metadata {
-                    let Some(piece) = self.next_piece() else {
-                        break;
-                    };
-                    self.send_metadata(&peer, Metadata::Request(Request::new(piece)));
-                }
-            }
-        } else if peer.peer_features().extension && peer.peer_extensions().metadata {
-            let Some(piece) = self.next_piece() else {
-                return;
-            };
-            self.send_metadata(peer, Metadata::Request(Request::new(piece)));
+        if let Some(metadata_size) = handshake.metadata_size {
+            self.fetcher.set_metadata_size(metadata_size);
+        }
+        if peer.peer_extensions().metadata {
+            self.fetcher.add_peer(peer.peer_endpoint());
         }
     }
 
-    fn handle_metadata(&mut self, peer: &Peer, metadata: &Metadata) {
+    fn handle_metadata(&mut self, peer: &Peer, metadata: &Metadata) -> Option<Bytes> {
+        // We do not advertise v2 capability, and we do not fetch the piece layers, which we could
+        // not verify anyway.  Peers should not send them, but we handle them gracefully.
+        if metadata.is_piece_layers() {
+            if let Metadata::Request(request) = metadata {
+                self.send_metadata(
+                    peer,
+                    Metadata::Reject(Reject::new_piece_layers(request.piece)),
+                );
+            }
+            return None;
+        }
         match metadata {
             Metadata::Request(request) => {
-                self.send_metadata(peer, Metadata::Reject(Reject::new(request.piece)))
+                self.send_metadata(peer, Metadata::Reject(Reject::new(request.piece)));
+                None
             }
             Metadata::Data(data) => {
-                if self.remove_inflight(data.piece) {
-                    tracing::info!(piece = data.piece, "receive metadata piece");
-                    self.info[Metadata::byte_range(data.piece, self.metadata_size.unwrap())]
-                        .copy_from_slice(data.payload);
-
-                    let Some(piece) = self.next_piece() else {
-                        return;
-                    };
-                    self.send_metadata(peer, Metadata::Request(Request::new(piece)));
-                }
+                tracing::info!(piece = data.piece, "receive metadata piece");
+                self.fetcher.handle_data(&peer.peer_endpoint(), data)
             }
             Metadata::Reject(reject) => {
-                if self.remove_inflight(reject.piece) {
-                    self.pieces.push_back(reject.piece);
-                }
+                self.fetcher.handle_reject(&peer.peer_endpoint(), reject);
+                None
             }
         }
     }
 
     fn handle_peer_exchange(&mut self, peer_exchange: &PeerExchange) -> Result<(), Error> {
         // TODO: How can we ensure that the manager is able to connect to IPv6 addresses?
-        let v4 = peer_exchange.decode_added_v4().context(ExtensionSnafu)?;
-        let v6 = peer_exchange.decode_added_v6().context(ExtensionSnafu)?;
-        for contact_info in v4.chain(v6) {
+        for contact_info in peer_exchange.decode_added().context(ExtensionSnafu)? {
             self.manager.connect(contact_info.endpoint, None);
         }
         Ok(())
@@ -280,13 +225,22 @@ impl<'a> Trackerless<'a> {
     // Send Helpers
     //
 
+    fn send_requests(&mut self) {
+        while let Some((peer_endpoint, request)) = self.fetcher.next_request(Instant::now()) {
+            match self.manager.get(peer_endpoint) {
+                Some(peer) => self.send_metadata(&peer, Metadata::Request(request)),
+                None => self.fetcher.remove_peer(&peer_endpoint),
+            }
+        }
+    }
+
     fn send_handshake(&self, peer: &Peer) {
         assert!(peer.peer_features().extension);
         // TODO: We do not have a builder API for message owners.  As a workaround, we employ an
         // encode-decode trick for now.
         let message = {
             let mut buffer = BytesMut::new();
-            Handshake::new(self.metadata_size).encode(&mut buffer);
+            Handshake::new(self.fetcher.metadata_size()).encode_into(&mut buffer);
             bittorrent_extension::decode(Handshake::ID, buffer.freeze()).unwrap()
         };
         peer.send_extension(message).unwrap();
@@ -304,7 +258,7 @@ impl<'a> Trackerless<'a> {
         // encode-decode trick for now.
         let message = {
             let mut buffer = BytesMut::new();
-            metadata.encode(&mut buffer);
+            metadata.encode_into(&mut buffer);
             bittorrent_extension::decode(Metadata::ID, buffer.freeze()).unwrap()
         };
         peer.send_extension(message).unwrap();
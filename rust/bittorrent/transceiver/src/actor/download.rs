@@ -1,5 +1,6 @@
 //! Download Handlers
 
+use std::collections::HashSet;
 use std::io::Error;
 
 use bytes::Bytes;
@@ -9,9 +10,39 @@ use bittorrent_base::{BlockDesc, PieceIndex};
 use bittorrent_manager::Endpoint;
 use bittorrent_peer::{Full, Peer, Possession};
 
-use super::{Actor, Update};
+use crate::queue::RecvStats;
+
+use super::{Actor, FilePriority, Update};
 
 impl Actor {
+    pub(super) fn handle_file_priority(&mut self, (file, priority): (usize, FilePriority)) {
+        tracing::info!(file, ?priority, "file priority");
+        match priority {
+            FilePriority::Normal => {
+                self.preview_files.remove(&file);
+            }
+            FilePriority::Preview => {
+                let Some(pieces) = self.storage.file_pieces(file) else {
+                    tracing::warn!(file, "invalid file index");
+                    return;
+                };
+                let pieces = usize::from(*pieces.start())..=usize::from(*pieces.end());
+                let n = *crate::preview_num_pieces();
+                self.preview_files.insert(
+                    file,
+                    pieces
+                        .clone()
+                        .take(n)
+                        .chain(pieces.rev().take(n))
+                        .map(PieceIndex::from)
+                        .collect(),
+                );
+            }
+        }
+        let preview: HashSet<_> = self.preview_files.values().flatten().copied().collect();
+        self.scheduler.set_preview(preview, Instant::now());
+    }
+
     #[tracing::instrument(name = "txrx/down", fields(?peer_endpoint), skip_all)]
     pub(super) fn handle_possession(
         &mut self,
@@ -25,6 +56,9 @@ impl Actor {
             peer.cancel();
             return;
         }
+        if self.scheduler.num_peer_pieces(peer_endpoint) > 0 {
+            self.choker.release(peer_endpoint);
+        }
         self.send_requests(&peer);
     }
 
@@ -93,19 +127,12 @@ impl Actor {
     }
 
     pub(super) fn check_endgame(&mut self) {
-        if self.endgame {
-            return;
-        }
-        if to_f64(self.scheduler.len()) > to_f64(self.dim.num_pieces) * self.endgame_threshold {
-            return;
+        if self
+            .endgame
+            .check(&mut self.scheduler, self.dim.num_pieces, Instant::now())
+        {
+            tracing::info!("enter endgame");
         }
-        tracing::info!("enter endgame");
-        self.endgame = true;
-        self.scheduler
-            .set_max_assignments(self.endgame_max_assignments);
-        self.scheduler
-            .set_max_replicates(self.endgame_max_replicates);
-        self.scheduler.schedule(Instant::now());
     }
 
     pub(super) fn send_requests(&mut self, peer: &Peer) {
@@ -114,7 +141,12 @@ impl Actor {
             return;
         };
         for piece in assignments {
-            let mut queue = self.queues.get_or_default(piece);
+            if self.is_unverified(piece) {
+                continue;
+            }
+            let mut queue = self
+                .queues
+                .get_or_default(piece, |block| self.storage.is_padding(block));
             while let Some(request) = queue.pop_request() {
                 match peer.request(request) {
                     Ok(Some(response_recv)) => {
@@ -145,11 +177,15 @@ impl Actor {
         let piece = block.0 .0;
 
         // Skip this block if we already have it.
-        if self.self_pieces[usize::from(piece)] {
+        if self.self_pieces[usize::from(piece)] || self.is_unverified(piece) {
+            self.torrent.wasted.add(block.1);
             return Ok(());
         }
-        let mut queue = self.queues.get_or_default(piece);
+        let mut queue = self
+            .queues
+            .get_or_default(piece, |block| self.storage.is_padding(block));
         if queue.add_progress(peer_endpoint, block) == 0 {
+            self.torrent.wasted.add(block.1);
             return Ok(());
         }
 
@@ -160,8 +196,41 @@ impl Actor {
         }
         let recv_stats = queue.remove();
 
+        // Verify the pieces in disk-order batches: We defer verifying a piece while its successor
+        // is being downloaded, so that the storage may flush adjacent pieces in one large write.
+        if self
+            .unverified
+            .last()
+            .is_some_and(|(last, _)| usize::from(*last) + 1 != usize::from(piece))
+        {
+            self.verify_unverified().await?;
+        }
+        self.unverified.push((piece, recv_stats));
+        let next = usize::from(piece) + 1;
+        if self.unverified.len() < *crate::verify_batch_size()
+            && next < self.dim.num_pieces
+            && self.queues.contains(next.into())
+        {
+            return Ok(());
+        }
+        self.verify_unverified().await
+    }
+
+    fn is_unverified(&self, piece: PieceIndex) -> bool {
+        self.unverified.iter().any(|(p, _)| *p == piece)
+    }
+
+    async fn verify_unverified(&mut self) -> Result<(), Error> {
+        for (piece, recv_stats) in std::mem::take(&mut self.unverified) {
+            self.verify(piece, recv_stats).await?;
+        }
+        Ok(())
+    }
+
+    async fn verify(&mut self, piece: PieceIndex, recv_stats: RecvStats) -> Result<(), Error> {
         if !self.storage.verify(piece).await? {
             tracing::warn!(?piece, ?recv_stats, "verification fail");
+            self.torrent.wasted.add(recv_stats.values().sum());
             return Ok(());
         }
 
@@ -187,7 +256,3 @@ impl Actor {
         Ok(())
     }
 }
-
-fn to_f64(x: usize) -> f64 {
-    u32::try_from(x).unwrap().into()
-}
@@ -1,11 +1,21 @@
 //! Extension Handlers
 
+use std::collections::HashMap;
+use std::iter;
+
 use bytes::BytesMut;
+use tokio::time::Instant;
 
-use bittorrent_extension::{Data, Error, Handshake, Message, Metadata, PeerExchange};
-use bittorrent_manager::Endpoint;
+use bittorrent_base::PieceIndex;
+use bittorrent_extension::{
+    Data, Handshake, Holepunch, HolepunchError, HolepunchType, Message, Metadata, PeerContactInfo,
+    PeerExchange, PeerFlag, Reject,
+};
+use bittorrent_manager::{Cipher, Connection, Endpoint, Hint, Transport};
 use bittorrent_peer::{ExtensionMessageOwner, Peer};
 
+use crate::holepunch::check_rendezvous;
+
 use super::Actor;
 
 impl Actor {
@@ -33,7 +43,20 @@ impl Actor {
             "close peer who claims non-support for extension",
         );
         match message.deref() {
-            Message::Handshake(_) => {} // Nothing to do here.
+            Message::Handshake(handshake) => {
+                if let Some(your_ip) = handshake.your_ip() {
+                    // Drop the hint when the receiver lags behind.
+                    let _ = self
+                        .self_addr
+                        .your_ip_send
+                        .try_send((peer_endpoint.ip(), your_ip));
+                }
+                // Seeds have nothing to exchange with each other.
+                if self.self_pieces.all() && peer.peer_upload_only() {
+                    tracing::debug!("close upload-only peer");
+                    peer.cancel();
+                }
+            }
             Message::Metadata(metadata) => {
                 ensure_peer!(
                     peer.peer_extensions().metadata,
@@ -48,11 +71,38 @@ impl Actor {
                 );
                 self.handle_peer_exchange(&peer, peer_exchange);
             }
+            Message::Holepunch(holepunch) => {
+                ensure_peer!(
+                    peer.peer_extensions().holepunch,
+                    "close peer who claims non-support for holepunch extension",
+                );
+                self.handle_holepunch(&peer, holepunch);
+            }
+            Message::DontHave(piece) => {
+                ensure_peer!(
+                    peer.peer_extensions().donthave,
+                    "close peer who claims non-support for donthave extension",
+                );
+                let piece = PieceIndex::from(usize::try_from(*piece).unwrap());
+                if let Err(error) = self.scheduler.notify_dont_have(peer_endpoint, piece) {
+                    tracing::warn!(%error, "close peer due to invalid donthave message");
+                    peer.cancel();
+                }
+            }
+            Message::Custom(custom) => {
+                tracing::debug!(name = custom.name, "drop custom extension message");
+            }
         }
     }
 
     fn handle_metadata(&mut self, peer: &Peer, metadata: &Metadata) {
         match metadata {
+            // We only have v1 metadata, and thus we reject requests for the piece layers.
+            Metadata::Request(request) if request.piece_layers => {
+                let message =
+                    Metadata::Reject(Reject::new_piece_layers(request.piece)).to_message();
+                peer.send_extension(message).unwrap();
+            }
             Metadata::Request(request) => {
                 let metadata_size = self.raw_info.len();
 
@@ -74,26 +124,160 @@ impl Actor {
         }
     }
 
+    fn handle_holepunch(&mut self, peer: &Peer, holepunch: &Holepunch) {
+        match holepunch.message_type {
+            HolepunchType::Rendezvous => {
+                let initiator = peer.peer_endpoint();
+                let target = self.manager.get(holepunch.endpoint);
+                let outcome = check_rendezvous(
+                    initiator,
+                    holepunch.endpoint,
+                    target
+                        .as_ref()
+                        .map(|target| target.peer_extensions().holepunch),
+                )
+                .and_then(|()| {
+                    target
+                        .unwrap()
+                        .send_extension(Holepunch::connect(initiator).to_message())
+                        .map_err(|_| HolepunchError::NoSupport)
+                });
+                let message = match outcome {
+                    Ok(()) => Holepunch::connect(holepunch.endpoint),
+                    Err(error) => Holepunch::error(holepunch.endpoint, error),
+                };
+                let _ = peer.send_extension(message.to_message());
+                self.relay_stats.record(outcome);
+                tracing::debug!(
+                    ?holepunch,
+                    ?outcome,
+                    success_rate = ?self.relay_stats.success_rate(),
+                    "relay rendezvous",
+                );
+            }
+            HolepunchType::Connect => {
+                // BEP 55 holepunching is mostly done over uTP.
+                self.manager.connect_with_hint(
+                    holepunch.endpoint,
+                    None,
+                    Hint {
+                        prefer_encryption: false,
+                        support_utp: true,
+                    },
+                );
+            }
+            HolepunchType::Error => tracing::debug!(?holepunch, "rendezvous error"),
+        }
+    }
+
     fn handle_peer_exchange(&mut self, peer: &Peer, peer_exchange: &PeerExchange) {
-        let result: Result<_, Error> = try {
-            (
-                peer_exchange.decode_added_v4()?,
-                peer_exchange.decode_added_v6()?,
-            )
-        };
-        match result {
-            Ok((v4, v6)) => {
+        // We do not announce PEX in our handshake when it is disabled for this torrent, but a peer
+        // may send it anyway.
+        if !self.peer_exchange {
+            tracing::debug!(?peer_exchange, "ignore pex message");
+            return;
+        }
+        match peer_exchange.decode_added() {
+            Ok(added) => {
+                let is_seed = self.self_pieces.all();
                 // TODO: How can we ensure that the manager is able to connect to IPv6 addresses?
-                for contact_info in v4.chain(v6) {
-                    self.manager.connect(contact_info.endpoint, None);
+                for contact_info in added {
+                    // Seeds have nothing to exchange with each other.
+                    if is_seed && contact_info.get_flag(PeerFlag::UploadOnly) {
+                        continue;
+                    }
+                    self.manager.connect_with_hint(
+                        contact_info.endpoint,
+                        None,
+                        to_hint(&contact_info),
+                    );
                 }
             }
             Err(error) => {
                 tracing::warn!(?peer_exchange, %error, "invalid pex message");
+                self.pex_filter.ban(peer.peer_endpoint().ip());
                 peer.cancel();
             }
         }
     }
+
+    /// Sends the changes in our peer list since the last PEX message to each peer.
+    pub(super) fn send_peer_exchanges(&mut self) {
+        if !self.self_features.extension || !self.peer_exchange {
+            return;
+        }
+
+        let now = Instant::now().into_std();
+        let peers = self.manager.peers();
+        let contact_infos: HashMap<Endpoint, PeerContactInfo> = peers
+            .iter()
+            .filter_map(|peer| {
+                let peer_endpoint = peer.peer_endpoint();
+                let is_seed = self.scheduler.num_peer_pieces(peer_endpoint) == self.dim.num_pieces;
+                to_contact_info(
+                    peer_endpoint,
+                    self.manager.connection(peer_endpoint)?,
+                    is_seed,
+                    peer.peer_extensions().holepunch,
+                )
+            })
+            .map(|contact_info| (contact_info.endpoint, contact_info))
+            .collect();
+
+        for peer in &peers {
+            if !peer.peer_extensions().peer_exchange {
+                continue;
+            }
+            let peer_endpoint = peer.peer_endpoint();
+            let state = self.peer_exchanged.entry(peer_endpoint).or_default();
+            state.update(
+                contact_infos
+                    .values()
+                    .filter(|contact_info| {
+                        contact_info.endpoint != peer_endpoint
+                            && self.pex_filter.accept(peer_endpoint, contact_info)
+                    })
+                    .copied(),
+            );
+            let Some(payload) = state.take(now) else {
+                continue;
+            };
+
+            let mut buffer = BytesMut::new();
+            payload.encode(&mut buffer);
+            let message = bittorrent_extension::decode(PeerExchange::ID, buffer.freeze()).unwrap();
+            let _ = peer.send_extension(message);
+        }
+    }
+}
+
+/// Returns the contact info that we advertise via PEX, or `None` if we do not know the peer's
+/// listening endpoint.
+fn to_contact_info(
+    peer_endpoint: Endpoint,
+    connection: Connection,
+    is_seed: bool,
+    support_holepunch: bool,
+) -> Option<PeerContactInfo> {
+    // The endpoint of an accepted TCP connection is not the peer's listening endpoint.  We make
+    // the same assumption as the manager that the uTP connecting endpoint is.
+    if !connection.outgoing && connection.transport == Transport::Tcp {
+        return None;
+    }
+    let mut contact_info = PeerContactInfo::new(peer_endpoint, iter::empty());
+    contact_info.set_flag(PeerFlag::PreferEncryption, connection.cipher == Cipher::Mse);
+    contact_info.set_flag(PeerFlag::UploadOnly, is_seed);
+    contact_info.set_flag(PeerFlag::SupportUtp, connection.transport == Transport::Utp);
+    contact_info.set_flag(PeerFlag::SupportHolepunch, support_holepunch);
+    contact_info.set_flag(PeerFlag::Reachable, connection.outgoing);
+    Some(contact_info)
+}
+
+fn to_hint(contact_info: &PeerContactInfo) -> Hint {
+    Hint {
+        prefer_encryption: contact_info.get_flag(PeerFlag::PreferEncryption),
+        support_utp: contact_info.get_flag(PeerFlag::SupportUtp),
+    }
 }
 
 //
@@ -106,6 +290,14 @@ pub(super) trait ToMessage {
 }
 
 impl ToMessage for Handshake<'_> {
+    fn to_message(&self) -> ExtensionMessageOwner {
+        let mut buffer = BytesMut::new();
+        self.encode_into(&mut buffer);
+        bittorrent_extension::decode(Self::ID, buffer.freeze()).unwrap()
+    }
+}
+
+impl ToMessage for Holepunch<'_> {
     fn to_message(&self) -> ExtensionMessageOwner {
         let mut buffer = BytesMut::new();
         self.encode(&mut buffer);
@@ -116,7 +308,86 @@ impl ToMessage for Handshake<'_> {
 impl ToMessage for Metadata<'_> {
     fn to_message(&self) -> ExtensionMessageOwner {
         let mut buffer = BytesMut::new();
-        self.encode(&mut buffer);
+        self.encode_into(&mut buffer);
         bittorrent_extension::decode(Self::ID, buffer.freeze()).unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contact_info() {
+        let endpoint: Endpoint = "127.0.0.1:8000".parse().unwrap();
+
+        assert_eq!(
+            to_contact_info(
+                endpoint,
+                Connection {
+                    transport: Transport::Tcp,
+                    cipher: Cipher::Mse,
+                    outgoing: false,
+                },
+                false,
+                false,
+            ),
+            None,
+        );
+
+        let contact_info = to_contact_info(
+            endpoint,
+            Connection {
+                transport: Transport::Tcp,
+                cipher: Cipher::Mse,
+                outgoing: true,
+            },
+            true,
+            true,
+        )
+        .unwrap();
+        assert_eq!(
+            contact_info,
+            PeerContactInfo::new(
+                endpoint,
+                [
+                    PeerFlag::PreferEncryption,
+                    PeerFlag::UploadOnly,
+                    PeerFlag::SupportHolepunch,
+                    PeerFlag::Reachable,
+                ]
+                .into_iter(),
+            ),
+        );
+        assert_eq!(
+            to_hint(&contact_info),
+            Hint {
+                prefer_encryption: true,
+                support_utp: false,
+            },
+        );
+
+        let contact_info = to_contact_info(
+            endpoint,
+            Connection {
+                transport: Transport::Utp,
+                cipher: Cipher::Plaintext,
+                outgoing: false,
+            },
+            false,
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            contact_info,
+            PeerContactInfo::new(endpoint, [PeerFlag::SupportUtp].into_iter()),
+        );
+        assert_eq!(
+            to_hint(&contact_info),
+            Hint {
+                prefer_encryption: false,
+                support_utp: true,
+            },
+        );
+    }
+}
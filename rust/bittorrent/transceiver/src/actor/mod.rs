@@ -19,16 +19,21 @@ mod peer;
 mod run;
 mod upload;
 
+use std::collections::HashMap;
+use std::net::IpAddr;
 use std::sync::Arc;
 
 use bytes::Bytes;
 use tokio::sync::{
     broadcast::{Receiver, Sender},
+    mpsc::{self, UnboundedReceiver},
     oneshot::error::RecvError,
+    watch,
 };
 
 use bittorrent_base::{BlockDesc, Dimension, Features, PieceIndex};
 use bittorrent_dht::Dht;
+use bittorrent_extension::{PexFilter, PexState};
 use bittorrent_manager::{Endpoint, Manager, Update as PeerUpdate};
 use bittorrent_peer::Recvs;
 use bittorrent_storage::{Bitfield, Storage};
@@ -40,7 +45,10 @@ use g1_base::{
 use g1_tokio::task::Cancel;
 
 use crate::{
-    queue::Queues,
+    choke::Choker,
+    endgame::Endgame,
+    holepunch::RelayStats,
+    queue::{Queues, RecvStats},
     schedule::Scheduler,
     stat::{Stats, TorrentInner},
 };
@@ -54,6 +62,30 @@ pub enum Update {
     Stop,
 }
 
+/// Our addresses, which we advertise to peers in the extension handshake, and what peers tell us
+/// about them.
+#[derive(Clone, Debug)]
+pub struct SelfAddr {
+    /// The TCP listening ports (`p`), which we send to peers of the same address family.
+    pub port_ipv4: Option<u16>,
+    pub port_ipv6: Option<u16>,
+    /// Our external addresses (`ipv4` and `ipv6`), which we send to peers of either family.
+    pub external_addr_v4_recv: watch::Receiver<Option<IpAddr>>,
+    pub external_addr_v6_recv: watch::Receiver<Option<IpAddr>>,
+    /// Sends `(peer_ip, your_ip)`, where `your_ip` is our address as the peer sees it (`yourip`).
+    pub your_ip_send: mpsc::Sender<(IpAddr, IpAddr)>,
+}
+
+/// Download priority of a file.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum FilePriority {
+    #[default]
+    Normal,
+    /// Downloads the first and last `preview_num_pieces` pieces of the file before all others, so
+    /// that a media player may probe the container, and then reverts to the normal strategy.
+    Preview,
+}
+
 pub type DynStorage = Box<dyn Storage + Send + 'static>;
 
 #[derive(DebugExt)]
@@ -67,19 +99,28 @@ pub(crate) struct Actor {
 
     // For now, we do not evict any `stats` entries.
     stats: Stats,
-    reciprocate_margin: u64,
+    choker: Choker,
 
     scheduler: Scheduler,
-    endgame: bool,
-    endgame_threshold: f64,
-    endgame_max_assignments: usize,
-    endgame_max_replicates: usize,
+    endgame: Endgame,
+    // Preview pieces of each file, keyed by the metainfo file index.
+    preview_files: HashMap<usize, Vec<PieceIndex>>,
+    priority_recv: UnboundedReceiver<(usize, FilePriority)>,
 
     queues: Queues,
+    // Completed pieces of which verification is deferred, in ascending and adjacent order.
+    unverified: Vec<(PieceIndex, RecvStats)>,
     #[debug(with = InsertPlaceholder)]
     responses: ReadyQueue<(Endpoint, BlockDesc, Result<Bytes, RecvError>)>,
 
     manager: Manager,
+    self_addr: SelfAddr,
+    // What we have exchanged with each peer via PEX.
+    // False if PEX is disabled for this torrent.
+    peer_exchange: bool,
+    peer_exchanged: HashMap<Endpoint, PexState>,
+    pex_filter: PexFilter,
+    relay_stats: RelayStats,
 
     peer_update_recv: Receiver<(Endpoint, PeerUpdate)>,
     recvs: Recvs,
@@ -104,13 +145,16 @@ impl Actor {
         self_pieces: Bitfield,
 
         manager: Manager,
+        self_addr: SelfAddr,
         recvs: Recvs,
         storage: DynStorage,
         dht_ipv4: Option<Dht>,
         dht_ipv6: Option<Dht>,
+        peer_exchange: bool,
 
         torrent: Arc<TorrentInner>,
         update_send: Sender<Update>,
+        priority_recv: UnboundedReceiver<(usize, FilePriority)>,
     ) -> Self {
         let scheduler = Scheduler::new(dim.clone(), &self_pieces);
         let queues = Queues::new(dim.clone());
@@ -124,18 +168,23 @@ impl Actor {
             self_pieces,
 
             stats: Stats::new(),
-            reciprocate_margin: *crate::reciprocate_margin(),
+            choker: Choker::new(),
 
             scheduler,
-            endgame: false,
-            endgame_threshold: *crate::endgame_threshold(),
-            endgame_max_assignments: *crate::endgame_max_assignments(),
-            endgame_max_replicates: *crate::endgame_max_replicates(),
+            endgame: Endgame::new(),
+            preview_files: HashMap::new(),
+            priority_recv,
 
             queues,
+            unverified: Vec::new(),
             responses: ReadyQueue::new(),
 
             manager,
+            self_addr,
+            peer_exchange,
+            peer_exchanged: HashMap::new(),
+            pex_filter: PexFilter::load(),
+            relay_stats: RelayStats::default(),
 
             peer_update_recv,
             recvs,
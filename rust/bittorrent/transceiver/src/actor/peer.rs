@@ -1,8 +1,10 @@
 //! Peer Handlers
 
+use std::net::{IpAddr, SocketAddr};
+
 use bytes::Bytes;
 
-use bittorrent_extension::Handshake;
+use bittorrent_extension::HandshakeBuilder;
 use bittorrent_manager::{Endpoint, Update};
 use bittorrent_peer::{Peer, Possession};
 
@@ -16,10 +18,15 @@ impl Actor {
                 let Some(peer) = self.manager.get(peer_endpoint) else {
                     return;
                 };
+                let client = peer.peer_id().client();
+                tracing::debug!(?client, "peer client");
+                self.stats.get_mut(peer_endpoint).client = client;
                 self.send_handshake(&peer);
             }
             Update::Stop => {
                 self.queues.remove_peer(peer_endpoint);
+                self.choker.release(peer_endpoint);
+                self.peer_exchanged.remove(&peer_endpoint);
             }
         }
         self.scheduler.notify_peer_update(peer_endpoint, update);
@@ -54,7 +61,31 @@ impl Actor {
         }
 
         if self.self_features.extension && peer_features.extension {
-            let message = Handshake::new(Some(self.raw_info.len())).to_message();
+            // TODO: Send another handshake when we complete the torrent.
+            let port = match peer.peer_endpoint() {
+                SocketAddr::V4(_) => self.self_addr.port_ipv4,
+                SocketAddr::V6(_) => self.self_addr.port_ipv6,
+            };
+            let external_addr_v4 = *self.self_addr.external_addr_v4_recv.borrow();
+            let external_addr_v6 = *self.self_addr.external_addr_v6_recv.borrow();
+            let message = HandshakeBuilder::new()
+                .metadata_size(Some(self.raw_info.len()))
+                .upload_only(self.self_pieces.all().then_some(true))
+                .client(crate::client().clone())
+                .request_queue_size(Some(*bittorrent_peer::request_queue_size()))
+                .your_ip(Some(peer.peer_endpoint().ip()))
+                .ipv4(match external_addr_v4 {
+                    Some(IpAddr::V4(ip)) => Some(ip),
+                    _ => None,
+                })
+                .ipv6(match external_addr_v6 {
+                    Some(IpAddr::V6(ip)) => Some(ip),
+                    _ => None,
+                })
+                .port(port)
+                .peer_exchange(self.peer_exchange)
+                .build()
+                .to_message();
             peer.send_extension(message).unwrap();
         }
     }
@@ -24,6 +24,8 @@ impl Actor {
             self.handle_peer_update((peer.peer_endpoint(), PeerUpdate::Start));
         }
 
+        let mut peer_exchange_interval = time::interval(*crate::peer_exchange_interval());
+
         let seed_at_start = self.scheduler.is_completed();
         let mut was_idle = true;
         let _ = self.update_send.send(Update::Start);
@@ -62,6 +64,7 @@ impl Actor {
                     let Some(message) = message else { break };
                     self.handle_extension(message);
                 }
+                _ = peer_exchange_interval.tick() => self.send_peer_exchanges(),
 
                 //
                 // Upload
@@ -96,6 +99,9 @@ impl Actor {
                     let Some(message) = message else { break };
                     self.handle_block(message).await?;
                 }
+                // The channel is closed when all `Transceiver` handles are dropped, which does not
+                // stop the download.
+                Some(message) = self.priority_recv.recv() => self.handle_file_priority(message),
 
                 message = self.responses.pop_ready() => {
                     // We can call `unwrap` because `responses` is never closed.
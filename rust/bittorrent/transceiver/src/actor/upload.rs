@@ -12,7 +12,7 @@ use super::Actor;
 
 impl Actor {
     #[tracing::instrument(name = "txrx/up", fields(?peer_endpoint), skip_all)]
-    pub(super) fn handle_interested(&self, peer_endpoint: Endpoint) {
+    pub(super) fn handle_interested(&mut self, peer_endpoint: Endpoint) {
         let Some(peer) = self.manager.get(peer_endpoint) else {
             return;
         };
@@ -49,8 +49,12 @@ impl Actor {
         Ok(())
     }
 
-    fn should_choke_peer(&self, peer: Endpoint, request_size: u64) -> bool {
-        let stat = self.stats.get(peer);
-        stat.send + request_size > stat.recv + self.reciprocate_margin
+    fn should_choke_peer(&mut self, peer: Endpoint, request_size: u64) -> bool {
+        self.choker.should_choke(
+            peer,
+            self.stats.get(peer),
+            self.scheduler.num_peer_pieces(peer),
+            request_size,
+        )
     }
 }
@@ -0,0 +1,81 @@
+//! Choking Policy
+//!
+//! We unchoke a peer as long as what we have sent to it does not exceed what we have received
+//! from it by more than `reciprocate_margin`.  Newcomers, which have nothing to reciprocate with,
+//! may still be unchoked via donated slots.
+
+use bittorrent_manager::Endpoint;
+
+use crate::{donate::Donation, stat::Stat};
+
+#[derive(Debug)]
+pub(crate) struct Choker {
+    reciprocate_margin: u64,
+    donation: Donation,
+}
+
+impl Choker {
+    pub(crate) fn new() -> Self {
+        Self::with_params(*crate::reciprocate_margin(), *crate::newcomer_slots())
+    }
+
+    pub(crate) fn with_params(reciprocate_margin: u64, newcomer_slots: usize) -> Self {
+        Self {
+            reciprocate_margin,
+            donation: Donation::with_num_slots(newcomer_slots),
+        }
+    }
+
+    /// Returns true if we should choke the peer instead of sending it `request_size` bytes.
+    pub(crate) fn should_choke(
+        &mut self,
+        peer: Endpoint,
+        stat: &Stat,
+        num_peer_pieces: usize,
+        request_size: u64,
+    ) -> bool {
+        if stat.send + request_size <= stat.recv + self.reciprocate_margin {
+            return false;
+        }
+        !(num_peer_pieces == 0 && self.donation.try_donate(peer))
+    }
+
+    /// Releases the donated slot of the peer, which should be called once it has a piece.
+    pub(crate) fn release(&mut self, peer: Endpoint) -> bool {
+        self.donation.release(peer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ep(endpoint: &str) -> Endpoint {
+        endpoint.parse().unwrap()
+    }
+
+    fn new_stat(recv: u64, send: u64) -> Stat {
+        Stat {
+            recv,
+            send,
+            client: None,
+        }
+    }
+
+    #[test]
+    fn should_choke() {
+        let p1 = ep("127.0.0.1:8001");
+        let p2 = ep("127.0.0.2:8002");
+
+        let mut choker = Choker::with_params(10, 1);
+        assert_eq!(choker.should_choke(p1, &new_stat(0, 0), 1, 10), false);
+        assert_eq!(choker.should_choke(p1, &new_stat(5, 10), 1, 5), false);
+        assert_eq!(choker.should_choke(p1, &new_stat(5, 10), 1, 6), true);
+
+        // Newcomers.
+        assert_eq!(choker.should_choke(p1, &new_stat(0, 10), 0, 1), false);
+        assert_eq!(choker.should_choke(p2, &new_stat(0, 10), 0, 1), true);
+        assert_eq!(choker.release(p1), true);
+        assert_eq!(choker.should_choke(p2, &new_stat(0, 10), 0, 1), false);
+    }
+}
@@ -0,0 +1,73 @@
+//! Unchoke Slot Donation
+//!
+//! Under the reciprocation policy alone, newcomers, namely peers that have no pieces, would never
+//! be unchoked because they have nothing to reciprocate with.  When we are a major seed, this
+//! slows down the bootstrap of the swarm.  To mitigate this, we donate a small number of slots to
+//! newcomers regardless of reciprocation.  A newcomer releases its slot once it has a piece.
+
+use std::collections::HashSet;
+
+use bittorrent_manager::Endpoint;
+
+#[derive(Debug)]
+pub(crate) struct Donation {
+    peers: HashSet<Endpoint>,
+    num_slots: usize,
+}
+
+impl Donation {
+    pub(crate) fn with_num_slots(num_slots: usize) -> Self {
+        Self {
+            peers: HashSet::new(),
+            num_slots,
+        }
+    }
+
+    /// Donates a slot to the peer if one is available.
+    ///
+    /// It returns true if the peer holds a slot, including one that was donated earlier.
+    pub(crate) fn try_donate(&mut self, peer: Endpoint) -> bool {
+        if self.peers.contains(&peer) {
+            return true;
+        }
+        if self.peers.len() >= self.num_slots {
+            return false;
+        }
+        self.peers.insert(peer);
+        true
+    }
+
+    pub(crate) fn release(&mut self, peer: Endpoint) -> bool {
+        self.peers.remove(&peer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ep(endpoint: &str) -> Endpoint {
+        endpoint.parse().unwrap()
+    }
+
+    #[test]
+    fn donation() {
+        let p1 = ep("127.0.0.1:8001");
+        let p2 = ep("127.0.0.1:8002");
+        let p3 = ep("127.0.0.1:8003");
+
+        let mut donation = Donation::with_num_slots(2);
+        assert_eq!(donation.try_donate(p1), true);
+        assert_eq!(donation.try_donate(p1), true);
+        assert_eq!(donation.try_donate(p2), true);
+        assert_eq!(donation.try_donate(p3), false);
+
+        assert_eq!(donation.release(p1), true);
+        assert_eq!(donation.release(p1), false);
+        assert_eq!(donation.try_donate(p3), true);
+        assert_eq!(donation.try_donate(p1), false);
+
+        let mut donation = Donation::with_num_slots(0);
+        assert_eq!(donation.try_donate(p1), false);
+    }
+}
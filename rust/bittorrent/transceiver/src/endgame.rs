@@ -0,0 +1,100 @@
+//! Endgame Mode
+//!
+//! When only a few pieces remain, we raise the scheduler limits so that the remaining pieces are
+//! requested from more than one peer, and a slow peer does not hold up the completion of the
+//! download.
+
+use tokio::time::Instant;
+
+use crate::schedule::Scheduler;
+
+#[derive(Debug)]
+pub(crate) struct Endgame {
+    entered: bool,
+    threshold: f64,
+    max_assignments: usize,
+    max_replicates: usize,
+}
+
+impl Endgame {
+    pub(crate) fn new() -> Self {
+        Self::with_params(
+            *crate::endgame_threshold(),
+            *crate::endgame_max_assignments(),
+            *crate::endgame_max_replicates(),
+        )
+    }
+
+    pub(crate) fn with_params(
+        threshold: f64,
+        max_assignments: usize,
+        max_replicates: usize,
+    ) -> Self {
+        Self {
+            entered: false,
+            threshold,
+            max_assignments,
+            max_replicates,
+        }
+    }
+
+    /// Enters the endgame mode if the fraction of the remaining pieces falls below the threshold.
+    ///
+    /// It returns true if it has just entered the endgame mode.
+    pub(crate) fn check(
+        &mut self,
+        scheduler: &mut Scheduler,
+        num_pieces: usize,
+        now: Instant,
+    ) -> bool {
+        // There is nothing left to do in the endgame mode once the download is completed.
+        if self.entered || scheduler.is_completed() {
+            return false;
+        }
+        if to_f64(scheduler.len()) > to_f64(num_pieces) * self.threshold {
+            return false;
+        }
+        self.entered = true;
+        scheduler.set_max_assignments(self.max_assignments);
+        scheduler.set_max_replicates(self.max_replicates);
+        scheduler.schedule(now);
+        true
+    }
+}
+
+fn to_f64(x: usize) -> f64 {
+    u32::try_from(x).unwrap().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use bitvec::prelude::*;
+
+    use bittorrent_base::Dimension;
+
+    use super::*;
+
+    #[test]
+    fn check() {
+        let dim = Dimension::new(10, 1, 10, 1);
+        let mut self_pieces = bitvec![u8, Msb0; 1; 10];
+        self_pieces[7..].fill(false);
+        let mut scheduler = Scheduler::new(dim, &self_pieces);
+        let now = Instant::now();
+
+        let mut endgame = Endgame::with_params(0.2, 4, 4);
+        assert_eq!(endgame.check(&mut scheduler, 10, now), false);
+        assert_eq!(endgame.entered, false);
+
+        scheduler.notify_verified(7.into());
+        assert_eq!(endgame.check(&mut scheduler, 10, now), true);
+        assert_eq!(endgame.entered, true);
+        assert_eq!(endgame.check(&mut scheduler, 10, now), false);
+        assert_eq!(endgame.entered, true);
+
+        let scheduler = &mut Scheduler::new(Dimension::new(1, 1, 1, 1), bits![u8, Msb0; 1]);
+        let mut endgame = Endgame::with_params(1.0, 4, 4);
+        assert_eq!(endgame.check(scheduler, 1, now), false);
+        assert_eq!(endgame.entered, false);
+    }
+}
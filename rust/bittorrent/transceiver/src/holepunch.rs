@@ -0,0 +1,121 @@
+//! BEP 55 Holepunch Relay
+//!
+//! When two of our peers cannot reach each other, e.g., both are behind NATs, one of them may ask
+//! us to relay a rendezvous, and we send a `Connect` message to each of them so that they may
+//! connect to each other simultaneously.
+
+use bittorrent_extension::HolepunchError;
+use bittorrent_manager::Endpoint;
+
+/// Relay outcomes, from which we derive how useful our relaying is to the swarm.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct RelayStats {
+    pub(crate) num_relayed: u64,
+    pub(crate) num_no_such_peer: u64,
+    pub(crate) num_not_connected: u64,
+    pub(crate) num_no_support: u64,
+    pub(crate) num_no_self: u64,
+}
+
+/// Checks whether we can relay a rendezvous from the initiator to the target.
+///
+/// `target_support` is `None` if we are not connected to the target, and otherwise whether the
+/// target supports the holepunch extension.
+///
+/// NOTE: We relay the initiator's endpoint as is, which is its listening endpoint only if it
+/// connects to us via uTP or we connect to it.  In practice, holepunching is mostly done over uTP.
+pub(crate) fn check_rendezvous(
+    initiator: Endpoint,
+    target: Endpoint,
+    target_support: Option<bool>,
+) -> Result<(), HolepunchError> {
+    if target.ip().is_unspecified() || target.port() == 0 {
+        return Err(HolepunchError::NoSuchPeer);
+    }
+    if target == initiator {
+        return Err(HolepunchError::NoSelf);
+    }
+    match target_support {
+        Some(true) => Ok(()),
+        Some(false) => Err(HolepunchError::NoSupport),
+        None => Err(HolepunchError::NotConnected),
+    }
+}
+
+impl RelayStats {
+    pub(crate) fn record(&mut self, outcome: Result<(), HolepunchError>) {
+        *match outcome {
+            Ok(()) => &mut self.num_relayed,
+            Err(HolepunchError::NoSuchPeer) => &mut self.num_no_such_peer,
+            Err(HolepunchError::NotConnected) => &mut self.num_not_connected,
+            Err(HolepunchError::NoSupport) => &mut self.num_no_support,
+            Err(HolepunchError::NoSelf) => &mut self.num_no_self,
+        } += 1;
+    }
+
+    pub(crate) fn num_requests(&self) -> u64 {
+        self.num_relayed
+            + self.num_no_such_peer
+            + self.num_not_connected
+            + self.num_no_support
+            + self.num_no_self
+    }
+
+    /// Returns the fraction of rendezvous requests that we relay.
+    pub(crate) fn success_rate(&self) -> Option<f64> {
+        let num_requests = self.num_requests();
+        (num_requests > 0).then(|| self.num_relayed as f64 / num_requests as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rendezvous() {
+        let initiator: Endpoint = "127.0.0.1:8000".parse().unwrap();
+        let target: Endpoint = "127.0.0.2:8000".parse().unwrap();
+
+        assert_eq!(check_rendezvous(initiator, target, Some(true)), Ok(()));
+        assert_eq!(
+            check_rendezvous(initiator, target, Some(false)),
+            Err(HolepunchError::NoSupport),
+        );
+        assert_eq!(
+            check_rendezvous(initiator, target, None),
+            Err(HolepunchError::NotConnected),
+        );
+        assert_eq!(
+            check_rendezvous(initiator, initiator, Some(true)),
+            Err(HolepunchError::NoSelf),
+        );
+        for target in ["0.0.0.0:8000", "127.0.0.2:0"] {
+            assert_eq!(
+                check_rendezvous(initiator, target.parse().unwrap(), Some(true)),
+                Err(HolepunchError::NoSuchPeer),
+            );
+        }
+    }
+
+    #[test]
+    fn relay_stats() {
+        let mut stats = RelayStats::default();
+        assert_eq!(stats.success_rate(), None);
+
+        stats.record(Ok(()));
+        stats.record(Ok(()));
+        stats.record(Ok(()));
+        stats.record(Err(HolepunchError::NotConnected));
+        assert_eq!(
+            stats,
+            RelayStats {
+                num_relayed: 3,
+                num_not_connected: 1,
+                ..Default::default()
+            },
+        );
+        assert_eq!(stats.num_requests(), 4);
+        assert_eq!(stats.success_rate(), Some(0.75));
+    }
+}
@@ -1,22 +1,32 @@
-#![feature(try_blocks)]
 #![feature(type_alias_impl_trait)]
 #![cfg_attr(test, feature(duration_constants))]
 
 mod actor;
 mod bitfield;
+mod choke;
+mod donate;
+mod endgame;
+mod holepunch;
 mod progress;
 mod queue;
 mod schedule;
+#[cfg(test)]
+mod sim;
 mod stat;
 mod transceiver;
 
 use std::time::Duration;
 
-pub use crate::actor::{DynStorage, Update};
-pub use crate::stat::Torrent;
+pub use crate::actor::{DynStorage, FilePriority, SelfAddr, Update};
+pub use crate::stat::{Counters, Torrent};
 pub use crate::transceiver::{Transceiver, TransceiverGuard, TransceiverSpawn};
 
-g1_param::define!(reciprocate_margin: u64 = 256 * 1024);
+g1_param::define!(
+    reciprocate_margin: u64 = 256 * 1024;
+    parse = g1_param::parse::byte_size;
+);
+// Number of unchoke slots donated to peers that have no pieces.
+g1_param::define!(newcomer_slots: usize = 2);
 
 g1_param::define!(endgame_threshold: f64 = 0.02);
 g1_param::define!(endgame_max_assignments: usize = 4);
@@ -30,4 +40,25 @@ g1_param::define!(
     parse = g1_param::parse::duration;
 );
 
+// BEP 11 specifies that we should not send PEX messages more than once a minute.
+g1_param::define!(
+    peer_exchange_interval: Duration = Duration::from_secs(60);
+    parse = g1_param::parse::duration;
+);
+
 g1_param::define!(update_queue_size: usize = 32);
+
+// Number of pieces at each end of a file that `FilePriority::Preview` downloads first.
+g1_param::define!(preview_num_pieces: usize = 2);
+
+g1_param::define!(
+    /// Maximum number of adjacent completed pieces whose verification is deferred, so that they
+    /// are verified in disk order (1 disables deferral).
+    verify_batch_size: usize = 1;
+    validate = |size: &usize| *size > 0;
+);
+
+// BEP 10 suggests that the client name and version be sent in the extension handshake.
+g1_param::define!(
+    client: Option<String> = Some(concat!("g1 ", env!("CARGO_PKG_VERSION")).to_string())
+);
@@ -37,14 +37,24 @@ impl Queues {
         }
     }
 
+    pub(crate) fn contains(&self, piece: PieceIndex) -> bool {
+        self.queues.contains_key(&piece)
+    }
+
     pub(crate) fn get_mut(&mut self, piece: PieceIndex) -> Option<&mut Queue> {
         self.queues.get_mut(&piece)
     }
 
-    pub(crate) fn get_or_default(&mut self, piece: PieceIndex) -> QueueStub {
+    /// Returns the queue of the piece, creating it if it does not exist.
+    ///
+    /// `is_padding` tells which blocks lie within padding files; we do not request them.
+    pub(crate) fn get_or_default<F>(&mut self, piece: PieceIndex, is_padding: F) -> QueueStub<'_>
+    where
+        F: Fn(BlockDesc) -> bool,
+    {
         QueueStub(match self.queues.entry(piece) {
             Entry::Occupied(entry) => entry,
-            Entry::Vacant(entry) => entry.insert_entry(Queue::new(&self.dim, piece)),
+            Entry::Vacant(entry) => entry.insert_entry(Queue::new(&self.dim, piece, is_padding)),
         })
     }
 
@@ -76,10 +86,25 @@ impl DerefMut for QueueStub<'_> {
 }
 
 impl Queue {
-    fn new(dim: &Dimension, piece: PieceIndex) -> Self {
+    fn new<F>(dim: &Dimension, piece: PieceIndex, is_padding: F) -> Self
+    where
+        F: Fn(BlockDesc) -> bool,
+    {
+        let (padding, mut requests): (BTreeSet<_>, BTreeSet<_>) =
+            dim.block_descs(piece).partition(|block| is_padding(*block));
+        let mut progress = Progress::new(dim, piece);
+        // Padding files are always shorter than a piece, but let us be defensive here; we request
+        // the entire piece rather than having nothing to request.
+        if requests.is_empty() {
+            requests = padding;
+        } else {
+            for block in padding {
+                progress.add(block);
+            }
+        }
         Self {
-            requests: dim.block_descs(piece).collect(),
-            progress: Progress::new(dim, piece),
+            requests,
+            progress,
             recv_stats: RecvStats::new(),
             piece,
         }
@@ -135,7 +160,7 @@ mod tests {
         let mut queues = Queues::new(Dimension::new(3, 1, 3, 1));
         queues.assert_pieces([]);
 
-        let mut q = queues.get_or_default(0.into());
+        let mut q = queues.get_or_default(0.into(), |_| false);
         assert_eq!(q.pop_request(), Some((0, 0, 1).into()));
         assert_eq!(q.is_completed(), false);
         assert_eq!(q.add_progress(p0, (0, 0, 1).into()), 1);
@@ -144,9 +169,30 @@ mod tests {
         queues.assert_pieces([0]);
 
         assert_eq!(
-            queues.get_or_default(0.into()).remove(),
+            queues.get_or_default(0.into(), |_| false).remove(),
             RecvStats::from([(p0, 1)]),
         );
         queues.assert_pieces([]);
     }
+
+    #[test]
+    fn padding() {
+        let p0: Endpoint = "127.0.0.1:8000".parse().unwrap();
+
+        let mut queues = Queues::new(Dimension::new(2, 4, 8, 1));
+
+        let mut q = queues.get_or_default(0.into(), |block| block.0 .1 >= 2);
+        assert_eq!(q.pop_request(), Some((0, 0, 1).into()));
+        assert_eq!(q.pop_request(), Some((0, 1, 1).into()));
+        assert_eq!(q.pop_request(), None);
+        assert_eq!(q.add_progress(p0, (0, 0, 2).into()), 2);
+        assert_eq!(q.is_completed(), true);
+
+        // We request the entire piece if it is all padding.
+        let mut q = queues.get_or_default(1.into(), |_| true);
+        for offset in 0..4 {
+            assert_eq!(q.pop_request(), Some((1, offset, 1).into()));
+        }
+        assert_eq!(q.is_completed(), false);
+    }
 }
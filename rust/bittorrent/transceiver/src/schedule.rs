@@ -1,5 +1,5 @@
 use std::cmp;
-use std::collections::BTreeSet;
+use std::collections::{BTreeSet, HashSet};
 use std::mem;
 use std::time::Duration;
 
@@ -32,6 +32,8 @@ pub(crate) struct Scheduler {
 
     peer_pieces: NaiveHashBiGraph<Endpoint, PieceIndex>,
     schedule: Vec<PieceIndex>,
+    // Pieces that are scheduled before all others, regardless of their rarity.
+    preview: HashSet<PieceIndex>,
 
     assignments: NaiveHashBiGraph<Endpoint, PieceIndex>,
     max_assignments: usize,
@@ -58,6 +60,7 @@ impl Scheduler {
 
             peer_pieces: NaiveHashBiGraph::new(),
             schedule: self_pieces.iter_zeros().map(PieceIndex::from).collect(),
+            preview: HashSet::new(),
 
             assignments: NaiveHashBiGraph::new(),
             max_assignments: *crate::max_assignments(),
@@ -81,15 +84,18 @@ impl Scheduler {
         !self.schedule.is_empty() && self.assignments.is_empty()
     }
 
-    /// Sorts the schedule by rarest-first.
+    /// Sorts the schedule by preview-first and then rarest-first.
     ///
-    /// NOTE: You must call this whenever `peer_pieces` is updated.
+    /// NOTE: You must call this whenever `peer_pieces` or `preview` is updated.
     fn sort_schedule(&mut self) {
         self.schedule.sort_by_key(|&piece| {
-            self.peer_pieces
-                .inverse_get(piece)
-                .map(|peers| peers.len())
-                .unwrap_or(0)
+            (
+                !self.preview.contains(&piece),
+                self.peer_pieces
+                    .inverse_get(piece)
+                    .map(|peers| peers.len())
+                    .unwrap_or(0),
+            )
         })
     }
 
@@ -214,6 +220,13 @@ impl Scheduler {
         self.schedule.len()
     }
 
+    pub(crate) fn num_peer_pieces(&self, peer: Endpoint) -> usize {
+        self.peer_pieces
+            .get(peer)
+            .map(|pieces| pieces.len())
+            .unwrap_or(0)
+    }
+
     fn position(&self, piece: PieceIndex) -> Option<usize> {
         self.schedule.iter().position(|&p| p == piece)
     }
@@ -268,6 +281,15 @@ impl Scheduler {
         self.max_replicates = max_replicates;
     }
 
+    /// Sets the pieces to be scheduled first.
+    ///
+    /// Assignments of other pieces are not revoked; the preview pieces take the free slots.
+    pub(crate) fn set_preview(&mut self, preview: HashSet<PieceIndex>, now: Instant) {
+        self.preview = preview;
+        self.sort_schedule();
+        self.schedule(now);
+    }
+
     pub(crate) fn take_updated(&mut self) -> BTreeSet<Endpoint> {
         mem::take(&mut self.updated)
     }
@@ -360,6 +382,32 @@ impl Scheduler {
         Ok(())
     }
 
+    /// Removes the piece from the peer's pieces (BEP 54).
+    ///
+    /// Unlike `notify_possession`, this also takes back the piece if it is assigned to the peer,
+    /// which will reject our requests for it anyway.
+    pub(crate) fn notify_dont_have(
+        &mut self,
+        peer: Endpoint,
+        piece: PieceIndex,
+    ) -> Result<(), Error> {
+        let piece = self
+            .dim
+            .check_piece_index(piece)
+            .context(InvalidPieceIndexSnafu { piece })?;
+        if !self.peer_pieces.remove(peer, piece) {
+            return Ok(());
+        }
+        self.sort_schedule();
+
+        if self.assignments.remove(peer, piece) {
+            let now = Instant::now();
+            self.schedule_peer(peer, now);
+            self.schedule_piece(piece, now);
+        }
+        Ok(())
+    }
+
     pub(crate) fn notify_response_error(&mut self, peer: Endpoint, piece: PieceIndex) {
         if !self.assignments.remove(peer, piece) {
             return;
@@ -546,6 +594,13 @@ mod tests {
         scheduler.peer_pieces.insert(p1, 1.into());
         scheduler.sort_schedule();
         scheduler.assert_schedule([2, 0, 1]);
+
+        scheduler.set_preview(HashSet::from([1.into()]), Instant::now());
+        scheduler.assert_schedule([1, 2, 0]);
+        scheduler.set_preview(HashSet::from([0.into(), 1.into()]), Instant::now());
+        scheduler.assert_schedule([0, 1, 2]);
+        scheduler.set_preview(HashSet::new(), Instant::now());
+        scheduler.assert_schedule([2, 0, 1]);
     }
 
     #[test]
@@ -1035,6 +1090,50 @@ mod tests {
         scheduler.assert_assignments([(p0, 0), (p0, 1), (p1, 1), (p1, 2)]);
     }
 
+    #[test]
+    fn notify_dont_have() {
+        let p0 = ep("127.0.0.1:8000");
+        let p1 = ep("127.0.0.1:8001");
+
+        let mut scheduler = Scheduler::new(Dimension::new(3, 1, 3, 1), bf![0; 3]);
+        scheduler.set_max_assignments(1);
+        scheduler.set_max_replicates(1);
+        assert_eq!(
+            scheduler.notify_possession(p0, Possession::Bitfield(Bytes::from_static(&[0x80]))),
+            Ok(()),
+        );
+        assert_eq!(
+            scheduler.notify_possession(p1, Possession::Have(0.into())),
+            Ok(()),
+        );
+        scheduler.take_updated();
+        scheduler.assert_schedule([1, 2, 0]);
+        scheduler.assert_peer_pieces([(p0, 0), (p1, 0)]);
+        scheduler.assert_assignments([(p0, 0)]);
+
+        assert_eq!(
+            scheduler.notify_dont_have(p0, 4.into()),
+            Err(Error::InvalidPieceIndex { piece: 4.into() }),
+        );
+        assert_eq!(scheduler.notify_dont_have(p1, 1.into()), Ok(()));
+        scheduler.assert_peer_pieces([(p0, 0), (p1, 0)]);
+        scheduler.assert_assignments([(p0, 0)]);
+        scheduler.assert_updated([]);
+
+        assert_eq!(scheduler.notify_dont_have(p0, 0.into()), Ok(()));
+        scheduler.assert_invariant();
+        scheduler.assert_schedule([1, 2, 0]);
+        scheduler.assert_peer_pieces([(p1, 0)]);
+        scheduler.assert_assignments([(p1, 0)]);
+        scheduler.assert_updated([p1]);
+
+        assert_eq!(scheduler.notify_dont_have(p1, 0.into()), Ok(()));
+        scheduler.assert_invariant();
+        scheduler.assert_schedule([1, 2, 0]);
+        scheduler.assert_peer_pieces([]);
+        scheduler.assert_assignments([]);
+    }
+
     #[tokio::test(start_paused = true)]
     async fn notify_response_error() {
         let now = Instant::now();
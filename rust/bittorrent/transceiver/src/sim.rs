@@ -0,0 +1,504 @@
+//! Swarm Simulation
+//!
+//! `Swarm` simulates a swarm of virtual peers exchanging pieces with us, and it drives the same
+//! scheduling, endgame, and choking policies as the actor.  It is a discrete-event simulation on
+//! the paused `tokio` clock (tests should use `#[tokio::test(start_paused = true)]`), and thus it
+//! is deterministic and runs much faster than real time.
+//!
+//! It does not simulate the wire protocol.  A virtual peer serves one piece at a time at its
+//! bandwidth, and a piece arrives after the peer's latency.  A virtual peer requests a block from
+//! us at its demand rate, and we either send it or choke the peer.
+
+use std::cmp;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use bitvec::prelude::*;
+use bytes::Bytes;
+use tokio::time::{self, Instant};
+
+use bittorrent_base::{Dimension, PieceIndex};
+use bittorrent_manager::{Endpoint, Update};
+use bittorrent_peer::Possession;
+
+use crate::{choke::Choker, endgame::Endgame, schedule::Scheduler, stat::Stats};
+
+#[derive(Clone, Debug)]
+pub(crate) struct PeerConfig {
+    /// Pieces that the peer has.
+    pub(crate) pieces: Vec<usize>,
+    /// When the peer joins, relative to the start of the simulation.
+    pub(crate) join: Duration,
+    /// Upload bandwidth in bytes per second.
+    pub(crate) bandwidth: u64,
+    pub(crate) latency: Duration,
+    pub(crate) behavior: Behavior,
+    /// Number of bytes per second that the peer requests from us.
+    pub(crate) demand: u64,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Behavior {
+    /// Serves every request.
+    Honest,
+    /// Never responds, and every request to it times out.
+    Snub,
+}
+
+#[derive(Debug)]
+pub(crate) struct Swarm {
+    dim: Dimension,
+
+    self_pieces: BitVec<u8, Msb0>,
+    scheduler: Scheduler,
+    endgame: Endgame,
+    stats: Stats,
+    choker: Choker,
+
+    peers: BTreeMap<Endpoint, VirtualPeer>,
+    transfers: Vec<Transfer>,
+
+    start: Option<Instant>,
+    report: Report,
+}
+
+#[derive(Debug)]
+struct VirtualPeer {
+    config: PeerConfig,
+    joined: bool,
+    // The peer serves one piece at a time, and it is busy until this instant.
+    busy_until: Instant,
+    next_request: Option<Instant>,
+}
+
+#[derive(Debug)]
+struct Transfer {
+    peer: Endpoint,
+    piece: PieceIndex,
+    end: Instant,
+    is_ok: bool,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Report {
+    /// Piece requests in the order they are sent.
+    pub(crate) requests: Vec<(Endpoint, PieceIndex)>,
+    /// Pieces in the order they are completed, and when they are completed.
+    pub(crate) completions: Vec<(Endpoint, PieceIndex, Duration)>,
+    pub(crate) num_errors: usize,
+    /// Number of bytes we receive for pieces that we already have.
+    pub(crate) wasted: u64,
+    pub(crate) endgame: Option<Duration>,
+    /// Number of bytes we send to each peer.
+    pub(crate) send: BTreeMap<Endpoint, u64>,
+    /// Number of requests for which we choke each peer.
+    pub(crate) choked: BTreeMap<Endpoint, usize>,
+}
+
+impl Swarm {
+    pub(crate) const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+    pub(crate) fn new(
+        dim: Dimension,
+        self_pieces: BitVec<u8, Msb0>,
+        endgame: Endgame,
+        choker: Choker,
+    ) -> Self {
+        assert_eq!(self_pieces.len(), dim.num_pieces);
+        let scheduler = Scheduler::new(dim.clone(), &self_pieces);
+        Self {
+            dim,
+
+            self_pieces,
+            scheduler,
+            endgame,
+            stats: Stats::new(),
+            choker,
+
+            peers: BTreeMap::new(),
+            transfers: Vec::new(),
+
+            start: None,
+            report: Report::default(),
+        }
+    }
+
+    pub(crate) fn add_peer(&mut self, peer: Endpoint, config: PeerConfig) {
+        assert!(self.start.is_none());
+        let now = Instant::now();
+        assert!(self
+            .peers
+            .insert(
+                peer,
+                VirtualPeer {
+                    config,
+                    joined: false,
+                    busy_until: now,
+                    next_request: None,
+                },
+            )
+            .is_none());
+    }
+
+    /// Runs the simulation until the download completes or `limit` is reached.
+    pub(crate) async fn run_until_complete(&mut self, limit: Duration) -> &Report {
+        self.run(limit, true).await
+    }
+
+    /// Runs the simulation for `duration`.
+    pub(crate) async fn run_for(&mut self, duration: Duration) -> &Report {
+        self.run(duration, false).await
+    }
+
+    async fn run(&mut self, limit: Duration, until_complete: bool) -> &Report {
+        let start = *self.start.get_or_insert_with(Instant::now);
+        let deadline = Instant::now() + limit;
+        self.check_endgame(start);
+        loop {
+            let now = Instant::now();
+            self.handle_events(now);
+            if until_complete && self.scheduler.is_completed() {
+                break;
+            }
+            let Some(next) = self.next_event(now) else {
+                break;
+            };
+            if next > deadline {
+                time::advance(deadline - now).await;
+                break;
+            }
+            time::advance(next - now).await;
+        }
+        &self.report
+    }
+
+    fn next_event(&self, now: Instant) -> Option<Instant> {
+        let start = self.start.unwrap();
+        let transfers = self.transfers.iter().map(|transfer| transfer.end);
+        let peers = self.peers.values().filter_map(|peer| {
+            if peer.joined {
+                peer.next_request
+            } else {
+                Some(start + peer.config.join)
+            }
+        });
+        transfers
+            .chain(peers)
+            .chain(self.scheduler.next_backoff(now))
+            .min()
+    }
+
+    fn handle_events(&mut self, now: Instant) {
+        let start = self.start.unwrap();
+
+        let joins: Vec<_> = self
+            .peers
+            .iter()
+            .filter(|(_, peer)| !peer.joined && start + peer.config.join <= now)
+            .map(|(&endpoint, _)| endpoint)
+            .collect();
+        for endpoint in joins {
+            self.join(endpoint, now);
+        }
+
+        let (mut due, transfers) = std::mem::take(&mut self.transfers)
+            .into_iter()
+            .partition::<Vec<_>, _>(|transfer| transfer.end <= now);
+        self.transfers = transfers;
+        due.sort_by_key(|transfer| transfer.end);
+        for transfer in due {
+            self.complete_transfer(transfer, start, now);
+        }
+
+        let requests: Vec<_> = self
+            .peers
+            .iter()
+            .filter(|(_, peer)| peer.next_request.is_some_and(|next| next <= now))
+            .map(|(&endpoint, _)| endpoint)
+            .collect();
+        for endpoint in requests {
+            self.handle_request(endpoint, now);
+        }
+
+        self.scheduler.remove_expired_backoffs(now);
+        self.send_requests(now);
+    }
+
+    fn join(&mut self, endpoint: Endpoint, now: Instant) {
+        let peer = self.peers.get_mut(&endpoint).unwrap();
+        peer.joined = true;
+        peer.busy_until = now;
+        peer.next_request = (peer.config.demand > 0)
+            .then(|| now + to_duration(self.dim.block_size, peer.config.demand));
+
+        let mut bitfield = bitvec![u8, Msb0; 0; self.dim.num_pieces];
+        for &piece in &peer.config.pieces {
+            bitfield.set(piece, true);
+        }
+        self.scheduler.notify_peer_update(endpoint, Update::Start);
+        self.scheduler
+            .notify_possession(
+                endpoint,
+                Possession::Bitfield(Bytes::from(bitfield.into_vec())),
+            )
+            .unwrap();
+        if self.scheduler.num_peer_pieces(endpoint) > 0 {
+            self.choker.release(endpoint);
+        }
+    }
+
+    fn send_requests(&mut self, now: Instant) {
+        for endpoint in self.scheduler.take_updated() {
+            for piece in self.scheduler.assignments(endpoint).unwrap_or_default() {
+                if self
+                    .transfers
+                    .iter()
+                    .any(|transfer| transfer.peer == endpoint && transfer.piece == piece)
+                {
+                    continue;
+                }
+                self.start_transfer(endpoint, piece, now);
+            }
+        }
+    }
+
+    fn start_transfer(&mut self, endpoint: Endpoint, piece: PieceIndex, now: Instant) {
+        let peer = self.peers.get_mut(&endpoint).unwrap();
+        let transfer = match peer.config.behavior {
+            Behavior::Honest => {
+                let size = self.dim.piece_size(piece);
+                peer.busy_until =
+                    cmp::max(peer.busy_until, now) + to_duration(size, peer.config.bandwidth);
+                Transfer {
+                    peer: endpoint,
+                    piece,
+                    end: peer.busy_until + peer.config.latency,
+                    is_ok: true,
+                }
+            }
+            Behavior::Snub => Transfer {
+                peer: endpoint,
+                piece,
+                end: now + Self::REQUEST_TIMEOUT,
+                is_ok: false,
+            },
+        };
+        self.report.requests.push((endpoint, piece));
+        self.transfers.push(transfer);
+    }
+
+    fn complete_transfer(&mut self, transfer: Transfer, start: Instant, now: Instant) {
+        let Transfer {
+            peer, piece, is_ok, ..
+        } = transfer;
+        if !is_ok {
+            self.report.num_errors += 1;
+            self.scheduler.notify_response_error(peer, piece);
+            return;
+        }
+
+        let size = self.dim.piece_size(piece);
+        if self.self_pieces[usize::from(piece)] {
+            self.report.wasted += size;
+            return;
+        }
+        self.self_pieces.set(usize::from(piece), true);
+        self.stats.get_mut(peer).recv += size;
+        self.report.completions.push((peer, piece, now - start));
+
+        self.scheduler.notify_verified(piece);
+        self.check_endgame(start);
+    }
+
+    fn check_endgame(&mut self, start: Instant) {
+        let now = Instant::now();
+        if self
+            .endgame
+            .check(&mut self.scheduler, self.dim.num_pieces, now)
+        {
+            self.report.endgame = Some(now - start);
+        }
+    }
+
+    fn handle_request(&mut self, endpoint: Endpoint, now: Instant) {
+        let peer = self.peers.get_mut(&endpoint).unwrap();
+        let demand = peer.config.demand;
+        peer.next_request = Some(now + to_duration(self.dim.block_size, demand));
+
+        // The peer requests a block of the first piece that we have and it does not have.
+        let Some(piece) = self
+            .self_pieces
+            .iter_ones()
+            .find(|piece| !peer.config.pieces.contains(piece))
+        else {
+            return;
+        };
+        let size = cmp::min(self.dim.block_size, self.dim.piece_size(piece.into()));
+
+        if self.choker.should_choke(
+            endpoint,
+            self.stats.get(endpoint),
+            self.scheduler.num_peer_pieces(endpoint),
+            size,
+        ) {
+            *self.report.choked.entry(endpoint).or_default() += 1;
+        } else {
+            self.stats.get_mut(endpoint).send += size;
+            *self.report.send.entry(endpoint).or_default() += size;
+        }
+    }
+}
+
+fn to_duration(size: u64, rate: u64) -> Duration {
+    Duration::from_nanos(size * 1_000_000_000 / rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PIECE_SIZE: u64 = 16384;
+
+    fn ep(endpoint: &str) -> Endpoint {
+        endpoint.parse().unwrap()
+    }
+
+    fn new_dim(num_pieces: usize) -> Dimension {
+        let size = PIECE_SIZE * u64::try_from(num_pieces).unwrap();
+        Dimension::new(num_pieces, PIECE_SIZE, size, PIECE_SIZE / 4)
+    }
+
+    fn new_swarm(num_pieces: usize, endgame: Endgame) -> Swarm {
+        Swarm::new(
+            new_dim(num_pieces),
+            bitvec![u8, Msb0; 0; num_pieces],
+            endgame,
+            Choker::with_params(0, 0),
+        )
+    }
+
+    fn new_config(pieces: impl IntoIterator<Item = usize>, bandwidth: u64) -> PeerConfig {
+        PeerConfig {
+            pieces: pieces.into_iter().collect(),
+            join: Duration::ZERO,
+            bandwidth,
+            latency: Duration::from_millis(50),
+            behavior: Behavior::Honest,
+            demand: 0,
+        }
+    }
+
+    // Disable the endgame mode.
+    fn no_endgame() -> Endgame {
+        Endgame::with_params(0.0, 1, 1)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rarest_first() {
+        let p1 = ep("127.0.0.1:6881");
+        let p2 = ep("127.0.0.2:6881");
+
+        let mut swarm = new_swarm(4, no_endgame());
+        swarm.add_peer(p1, new_config(0..3, 1_000_000));
+        swarm.add_peer(
+            p2,
+            PeerConfig {
+                join: Duration::from_millis(1),
+                ..new_config(0..4, 1_000_000)
+            },
+        );
+        let report = swarm.run_until_complete(Duration::from_secs(60)).await;
+
+        // Piece 3, which only p2 has, is the rarest.
+        assert_eq!(
+            report.requests.iter().find(|(peer, _)| *peer == p2),
+            Some(&(p2, 3.into())),
+        );
+        assert_eq!(report.completions.len(), 4);
+        assert_eq!(report.num_errors, 0);
+        assert_eq!(report.wasted, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn endgame() {
+        async fn run(endgame: Endgame) -> (Duration, Option<Duration>) {
+            let fast = ep("127.0.0.1:6881");
+            let slow = ep("127.0.0.2:6881");
+
+            let mut swarm = new_swarm(4, endgame);
+            swarm.add_peer(fast, new_config(0..4, 1_000_000));
+            swarm.add_peer(slow, new_config(0..4, 1_000));
+            let report = swarm.run_until_complete(Duration::from_secs(3600)).await;
+            assert_eq!(report.completions.len(), 4);
+            (report.completions.last().unwrap().2, report.endgame)
+        }
+
+        // The slow peer holds up the completion.
+        let (completed, endgame) = run(no_endgame()).await;
+        assert!(completed > Duration::from_secs(16), "{completed:?}");
+        assert_eq!(endgame, None);
+
+        let (completed, endgame) = run(Endgame::with_params(0.5, 4, 4)).await;
+        assert!(completed < Duration::from_secs(1), "{completed:?}");
+        assert!(endgame.is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn snub() {
+        let snub = ep("127.0.0.1:6881");
+        let honest = ep("127.0.0.2:6881");
+
+        let mut swarm = new_swarm(4, no_endgame());
+        swarm.add_peer(
+            snub,
+            PeerConfig {
+                behavior: Behavior::Snub,
+                ..new_config(0..4, 1_000_000)
+            },
+        );
+        swarm.add_peer(honest, new_config(0..4, 1_000));
+        let report = swarm.run_until_complete(Duration::from_secs(3600)).await;
+
+        assert_eq!(report.completions.len(), 4);
+        assert!(report
+            .completions
+            .iter()
+            .all(|(peer, _, _)| *peer == honest));
+        assert_ne!(report.num_errors, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn choke() {
+        let p1 = ep("127.0.0.1:6881");
+        let newcomer1 = ep("127.0.0.2:6881");
+        let newcomer2 = ep("127.0.0.3:6881");
+        let block_size = new_dim(4).block_size;
+
+        let mut swarm = Swarm::new(
+            new_dim(4),
+            bitvec![u8, Msb0; 1; 4],
+            no_endgame(),
+            Choker::with_params(block_size * 2, 1),
+        );
+        for (peer, pieces) in [(p1, vec![0]), (newcomer1, vec![]), (newcomer2, vec![])] {
+            swarm.add_peer(
+                peer,
+                PeerConfig {
+                    demand: block_size,
+                    ..new_config(pieces, 1_000_000)
+                },
+            );
+        }
+        let report = swarm.run_for(Duration::from_secs(10)).await;
+
+        // Every peer is unchoked within the reciprocation margin, and only one newcomer receives
+        // a donated slot.
+        assert_eq!(report.send.get(&p1), Some(&(block_size * 2)));
+        assert_eq!(report.send.get(&newcomer1), Some(&(block_size * 10)));
+        assert_eq!(report.send.get(&newcomer2), Some(&(block_size * 2)));
+        assert_eq!(report.choked.get(&p1), Some(&8));
+        assert_eq!(report.choked.get(&newcomer1), None);
+        assert_eq!(report.choked.get(&newcomer2), Some(&8));
+        assert!(report.requests.is_empty());
+    }
+}
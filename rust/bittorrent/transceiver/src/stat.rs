@@ -1,10 +1,12 @@
 use std::collections::HashMap;
 use std::net::IpAddr;
+use std::ops::Add;
 use std::sync::{
     atomic::{AtomicU64, Ordering},
     Arc,
 };
 
+use bittorrent_base::Client;
 use bittorrent_manager::Endpoint;
 
 #[derive(Clone, Debug)]
@@ -15,7 +17,22 @@ pub(crate) struct TorrentInner {
     pub(crate) send: Accumulator,
     pub(crate) recv: Accumulator,
     pub(crate) have: Accumulator,
+    pub(crate) wasted: Accumulator,
     size: u64,
+    // Counters of the previous sessions.
+    base: Counters,
+}
+
+/// Byte counters of a torrent.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Counters {
+    /// Number of piece payload bytes we send.
+    pub send: u64,
+    /// Number of piece payload bytes we receive and verify.
+    pub recv: u64,
+    /// Number of piece payload bytes we receive but discard, which are either duplicates or parts
+    /// of pieces that fail verification.
+    pub wasted: u64,
 }
 
 #[derive(Debug)]
@@ -35,21 +52,51 @@ pub(crate) struct Stat {
     pub(crate) recv: u64,
     /// Number of bytes sent to this peer.
     pub(crate) send: u64,
+    /// Client that this peer runs, as parsed from its peer id.
+    pub(crate) client: Option<Client>,
 }
 
 impl Torrent {
     pub(crate) fn new(inner: Arc<TorrentInner>) -> Self {
         Self(inner)
     }
+
+    /// Returns the counters of the current session.
+    pub fn session(&self) -> Counters {
+        Counters {
+            send: self.0.send.get(),
+            recv: self.0.recv.get(),
+            wasted: self.0.wasted.get(),
+        }
+    }
+
+    /// Returns the counters accumulated over all sessions.
+    pub fn total(&self) -> Counters {
+        self.0.base + self.session()
+    }
 }
 
 impl TorrentInner {
-    pub(crate) fn new(have: u64, size: u64) -> Self {
+    pub(crate) fn new(have: u64, size: u64, base: Counters) -> Self {
         Self {
             send: Accumulator(AtomicU64::new(0)),
             recv: Accumulator(AtomicU64::new(0)),
             have: Accumulator(AtomicU64::new(have)),
+            wasted: Accumulator(AtomicU64::new(0)),
             size,
+            base,
+        }
+    }
+}
+
+impl Add for Counters {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self::Output {
+        Self {
+            send: self.send + other.send,
+            recv: self.recv + other.recv,
+            wasted: self.wasted + other.wasted,
         }
     }
 }
@@ -64,6 +111,8 @@ impl Accumulator {
     }
 }
 
+// BEP 3 specifies that we report to the tracker the number of bytes since the `started` event,
+// and so we report the counters of the current session rather than the totals.
 impl bittorrent_tracker::Torrent for Torrent {
     fn num_bytes_send(&self) -> u64 {
         self.0.send.get()
@@ -84,7 +133,9 @@ impl Stats {
     }
 
     pub(crate) fn get(&self, peer: Endpoint) -> &Stat {
-        self.0.get(&peer.ip()).unwrap_or(&Stat::ZERO)
+        // `Stat` is not promotable to a `'static` constant because of `client`.
+        static ZERO: Stat = Stat::new();
+        self.0.get(&peer.ip()).unwrap_or(&ZERO)
     }
 
     pub(crate) fn get_mut(&mut self, peer: Endpoint) -> &mut Stat {
@@ -93,10 +144,12 @@ impl Stats {
 }
 
 impl Stat {
-    const ZERO: Self = Self::new();
-
     const fn new() -> Self {
-        Self { recv: 0, send: 0 }
+        Self {
+            recv: 0,
+            send: 0,
+            client: None,
+        }
     }
 }
 
@@ -108,21 +161,45 @@ mod tests {
 
     #[test]
     fn torrent() {
-        let torrent = Torrent::new(Arc::new(TorrentInner::new(7, 11)));
+        let base = Counters {
+            send: 100,
+            recv: 200,
+            wasted: 300,
+        };
+        let torrent = Torrent::new(Arc::new(TorrentInner::new(7, 11, base)));
         assert_eq!(torrent.0.have.0.load(Ordering::SeqCst), 7);
         assert_eq!(torrent.0.size, 11);
 
         assert_eq!(torrent.num_bytes_send(), 0);
         assert_eq!(torrent.num_bytes_recv(), 0);
         assert_eq!(torrent.num_bytes_left(), 4);
+        assert_eq!(torrent.session(), Counters::default());
+        assert_eq!(torrent.total(), base);
 
         torrent.0.send.add(1);
         torrent.0.recv.add(2);
         torrent.0.have.add(3);
+        torrent.0.wasted.add(4);
 
         assert_eq!(torrent.num_bytes_send(), 1);
         assert_eq!(torrent.num_bytes_recv(), 2);
         assert_eq!(torrent.num_bytes_left(), 1);
+        assert_eq!(
+            torrent.session(),
+            Counters {
+                send: 1,
+                recv: 2,
+                wasted: 4,
+            },
+        );
+        assert_eq!(
+            torrent.total(),
+            Counters {
+                send: 101,
+                recv: 202,
+                wasted: 304,
+            },
+        );
 
         torrent.0.have.add(4);
         assert_eq!(torrent.num_bytes_left(), 0);
@@ -2,7 +2,10 @@ use std::io::Error;
 use std::sync::Arc;
 
 use bytes::Bytes;
-use tokio::sync::broadcast::{self, Receiver, Sender};
+use tokio::sync::{
+    broadcast::{self, Receiver, Sender},
+    mpsc::{self, UnboundedSender},
+};
 
 use g1_tokio::task::JoinGuard;
 
@@ -12,9 +15,9 @@ use bittorrent_manager::Manager;
 use bittorrent_peer::Recvs;
 
 use crate::{
-    actor::{Actor, DynStorage, Update},
+    actor::{Actor, DynStorage, FilePriority, SelfAddr, Update},
     bitfield::{Bitfield, BitfieldExt},
-    stat::{Torrent, TorrentInner},
+    stat::{Counters, Torrent, TorrentInner},
 };
 
 #[derive(Clone, Debug)]
@@ -22,6 +25,7 @@ pub struct Transceiver {
     pub torrent: Torrent,
     // Only for subscribing.
     update_send: Sender<Update>,
+    priority_send: UnboundedSender<(usize, FilePriority)>,
 }
 
 pub type TransceiverGuard = JoinGuard<Result<(), Error>>;
@@ -29,14 +33,20 @@ pub type TransceiverGuard = JoinGuard<Result<(), Error>>;
 pub type TransceiverSpawn = impl FnOnce() -> (Transceiver, TransceiverGuard);
 
 impl Transceiver {
+    /// Prepares spawning the transceiver, where `peer_exchange` may disable PEX for this torrent
+    /// even though it is enabled process-wide, and `self_addr` is what we advertise to peers.
+    #[allow(clippy::too_many_arguments)]
     pub async fn prepare_spawn(
         raw_info: Bytes,
         dim: Dimension,
         manager: Manager,
+        self_addr: SelfAddr,
         recvs: Recvs,
         mut storage: DynStorage,
         dht_ipv4: Option<Dht>,
         dht_ipv6: Option<Dht>,
+        peer_exchange: bool,
+        base: Counters,
     ) -> Result<(TransceiverSpawn, Torrent, Receiver<Update>), Error> {
         let self_pieces = storage.scan().await?;
         Bitfield::from_bytes(self_pieces.as_raw_slice(), dim.num_pieces)
@@ -48,10 +58,12 @@ impl Transceiver {
                 .map(|piece| dim.piece_size(piece.into()))
                 .sum(),
             dim.size,
+            base,
         ));
         let torrent = Torrent::new(torrent_inner.clone());
 
         let (update_send, update_recv) = broadcast::channel(*crate::update_queue_size());
+        let (priority_send, priority_recv) = mpsc::unbounded_channel();
 
         let spawn = {
             let torrent = torrent.clone();
@@ -60,6 +72,7 @@ impl Transceiver {
                     Transceiver {
                         torrent,
                         update_send: update_send.clone(),
+                        priority_send,
                     },
                     JoinGuard::spawn(move |cancel| {
                         Actor::new(
@@ -68,12 +81,15 @@ impl Transceiver {
                             dim,
                             self_pieces,
                             manager,
+                            self_addr,
                             recvs,
                             storage,
                             dht_ipv4,
                             dht_ipv6,
+                            peer_exchange,
                             torrent_inner,
                             update_send,
+                            priority_recv,
                         )
                         .run()
                     }),
@@ -87,4 +103,9 @@ impl Transceiver {
     pub fn subscribe(&self) -> Receiver<Update> {
         self.update_send.subscribe()
     }
+
+    /// Sets the download priority of a file, where `file` is its index in the metainfo.
+    pub fn set_file_priority(&self, file: usize, priority: FilePriority) {
+        let _ = self.priority_send.send((file, priority));
+    }
 }
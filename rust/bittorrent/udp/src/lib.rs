@@ -4,6 +4,7 @@ use std::net::SocketAddr;
 use bytes::Bytes;
 
 use g1_futures::stream;
+use g1_tokio::net::udp::Datagram;
 
 //
 // Implementer's Notes: `fork` cannot send an input item to multiple forks.  Currently, all errors
@@ -14,12 +15,17 @@ g1_param::define!(dht_queue_size: usize = 256);
 g1_param::define!(utp_queue_size: usize = 256);
 g1_param::define!(error_queue_size: usize = 32);
 
-pub type Fork<Stream> = stream::Fork<Stream, fn(&Item) -> bool, Item>;
-type Item = Result<(SocketAddr, Bytes), Error>;
+pub type Fork<Stream, D = (SocketAddr, Bytes)> =
+    stream::Fork<Stream, fn(&Item<D>) -> bool, Item<D>>;
+type Item<D = (SocketAddr, Bytes)> = Result<D, Error>;
 
 // TODO: Support BEP 15 UDP Tracker Protocol.
-pub fn fork<Stream>(stream: Stream) -> (Fork<Stream>, Fork<Stream>, Fork<Stream>) {
-    <[Fork<Stream>; 3]>::into(stream::fork(
+pub fn fork<Stream, D>(stream: Stream) -> (Fork<Stream, D>, Fork<Stream, D>, Fork<Stream, D>)
+where
+    Stream: futures::stream::Stream<Item = Item<D>>,
+    D: Datagram,
+{
+    <[Fork<Stream, D>; 3]>::into(stream::fork(
         stream,
         [
             (is_dht, *dht_queue_size()),
@@ -29,15 +35,59 @@ pub fn fork<Stream>(stream: Stream) -> (Fork<Stream>, Fork<Stream>, Fork<Stream>
     ))
 }
 
-fn is_dht(item: &Item) -> bool {
+fn is_dht<D: Datagram>(item: &Item<D>) -> bool {
     // All DHT messages must begin with the ASCII letter 'd' as they are Bencode dictionaries.
-    matches!(item, Ok((_, payload)) if payload[0] == b'd')
+    matches!(item, Ok(datagram) if datagram.payload().first() == Some(&b'd'))
 }
 
-fn is_utp(item: &Item) -> bool {
-    matches!(item, Ok((_, payload)) if (payload[0] & 0xf0) <= 0x40 && (payload[0] & 0x0f) == 0x01)
+fn is_utp<D: Datagram>(item: &Item<D>) -> bool {
+    // The first byte of a uTP packet is its type (0-4) and version (1).  Note that an empty
+    // datagram is valid UDP, and we must not index into it.
+    let Ok(datagram) = item else {
+        return false;
+    };
+    datagram
+        .payload()
+        .first()
+        .is_some_and(|x| (x & 0xf0) <= 0x40 && (x & 0x0f) == 0x01)
 }
 
-fn is_error(_: &Item) -> bool {
+fn is_error<D>(_: &Item<D>) -> bool {
     true
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+
+    use super::*;
+
+    fn ok(payload: &'static [u8]) -> Item {
+        Ok((
+            "127.0.0.1:6881".parse().unwrap(),
+            Bytes::from_static(payload),
+        ))
+    }
+
+    #[test]
+    fn classify() {
+        assert_eq!(is_dht(&ok(b"d1:ad2:id20:")), true);
+        assert_eq!(is_utp(&ok(b"d1:ad2:id20:")), false);
+
+        for packet in [b"\x01\x00", b"\x21\x00", b"\x41\x00"] {
+            assert_eq!(is_dht(&ok(packet)), false);
+            assert_eq!(is_utp(&ok(packet)), true);
+        }
+        // Unknown type or version.
+        assert_eq!(is_utp(&ok(b"\x51\x00")), false);
+        assert_eq!(is_utp(&ok(b"\x02\x00")), false);
+
+        assert_eq!(is_dht(&ok(b"")), false);
+        assert_eq!(is_utp(&ok(b"")), false);
+
+        let error: Item = Err(ErrorKind::Other.into());
+        assert_eq!(is_dht(&error), false);
+        assert_eq!(is_utp(&error), false);
+        assert_eq!(is_error(&error), true);
+    }
+}
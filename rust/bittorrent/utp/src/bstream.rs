@@ -11,6 +11,9 @@ use tokio::{
 
 use g1_tokio::bstream::{SendBuffer, StreamIntoSplit, StreamRecv, StreamSend, StreamSplit};
 
+use crate::rate::{RateLimit, RateLimits};
+use crate::stats::{StatsRecv, UtpStats};
+
 #[derive(Debug)]
 pub struct UtpStream {
     recv: UtpRecvStream,
@@ -23,6 +26,10 @@ pub struct UtpRecvStream {
     peer_endpoint: SocketAddr,
     buffer: BytesMut,
     incoming_recv: IncomingRecv,
+    stats_recv: StatsRecv,
+    // Per-stream and socket-level limits.
+    rate_limit: RateLimit,
+    socket_rate_limit: RateLimit,
 }
 
 #[derive(Debug)]
@@ -34,6 +41,10 @@ pub struct UtpSendStream {
     // Wrap the sender in an `Option` so that we can gracefully close the receiver by dropping the
     // sender.
     outgoing_send: Option<OutgoingSend>,
+    stats_recv: StatsRecv,
+    // Per-stream and socket-level limits.
+    rate_limit: RateLimit,
+    socket_rate_limit: RateLimit,
 }
 
 g1_param::define!(incoming_queue_size: usize = 32);
@@ -70,10 +81,36 @@ impl UtpStream {
     pub fn peer_endpoint(&self) -> SocketAddr {
         self.recv.peer_endpoint
     }
+
+    /// Half-closes the connection.
+    ///
+    /// It sends the remaining data and then the finish packet, which is resent until the peer
+    /// acks it.  The receive half remains open until the peer finishes its stream.
+    pub async fn shutdown_send(&mut self) -> Result<(), Error> {
+        self.send.shutdown().await
+    }
+
+    /// Returns the latest stats of the connection.
+    pub fn stats(&self) -> UtpStats {
+        self.recv.stats()
+    }
+
+    pub fn recv_rate_limit(&self) -> &RateLimit {
+        self.recv.rate_limit()
+    }
+
+    pub fn send_rate_limit(&self) -> &RateLimit {
+        self.send.rate_limit()
+    }
 }
 
 impl UtpRecvStream {
-    pub(crate) fn new(socket: Arc<UdpSocket>, peer_endpoint: SocketAddr) -> (Self, IncomingSend) {
+    pub(crate) fn new(
+        socket: Arc<UdpSocket>,
+        peer_endpoint: SocketAddr,
+        stats_recv: StatsRecv,
+        rate_limits: &RateLimits,
+    ) -> (Self, IncomingSend) {
         let (incoming_send, incoming_recv) = mpsc::channel(*incoming_queue_size());
         (
             Self {
@@ -81,6 +118,9 @@ impl UtpRecvStream {
                 peer_endpoint,
                 buffer: BytesMut::with_capacity(*bittorrent_base::recv_buffer_capacity()),
                 incoming_recv,
+                stats_recv,
+                rate_limit: RateLimit::default(),
+                socket_rate_limit: rate_limits.recv.clone(),
             },
             incoming_send,
         )
@@ -93,10 +133,24 @@ impl UtpRecvStream {
     pub fn peer_endpoint(&self) -> SocketAddr {
         self.peer_endpoint
     }
+
+    pub fn stats(&self) -> UtpStats {
+        *self.stats_recv.borrow()
+    }
+
+    /// Returns the per-stream limit, which is unlimited by default.
+    pub fn rate_limit(&self) -> &RateLimit {
+        &self.rate_limit
+    }
 }
 
 impl UtpSendStream {
-    pub(crate) fn new(socket: Arc<UdpSocket>, peer_endpoint: SocketAddr) -> (Self, OutgoingRecv) {
+    pub(crate) fn new(
+        socket: Arc<UdpSocket>,
+        peer_endpoint: SocketAddr,
+        stats_recv: StatsRecv,
+        rate_limits: &RateLimits,
+    ) -> (Self, OutgoingRecv) {
         let (outgoing_send, outgoing_recv) = mpsc::channel(OUTGOING_QUEUE_SIZE);
         (
             Self {
@@ -106,6 +160,9 @@ impl UtpSendStream {
                     *bittorrent_base::send_buffer_capacity(),
                 )),
                 outgoing_send: Some(outgoing_send),
+                stats_recv,
+                rate_limit: RateLimit::default(),
+                socket_rate_limit: rate_limits.send.clone(),
             },
             outgoing_recv,
         )
@@ -118,6 +175,15 @@ impl UtpSendStream {
     pub fn peer_endpoint(&self) -> SocketAddr {
         self.peer_endpoint
     }
+
+    pub fn stats(&self) -> UtpStats {
+        *self.stats_recv.borrow()
+    }
+
+    /// Returns the per-stream limit, which is unlimited by default.
+    pub fn rate_limit(&self) -> &RateLimit {
+        &self.rate_limit
+    }
 }
 
 #[async_trait]
@@ -133,7 +199,12 @@ impl StreamRecv for UtpRecvStream {
     async fn recv_or_eof(&mut self) -> Result<Option<usize>, Self::Error> {
         match self.incoming_recv.recv().await.transpose()? {
             Some(payload) => {
+                // Buffer the payload before waiting on the limits so that it is not lost if the
+                // caller cancels us.  Delaying the next receive fills up the receive window, which
+                // in turn throttles the peer.
                 self.buffer.put_slice(&payload);
+                self.rate_limit.acquire(payload.len()).await;
+                self.socket_rate_limit.acquire(payload.len()).await;
                 Ok(Some(payload.len()))
             }
             None => Ok(None),
@@ -161,6 +232,9 @@ impl StreamSend for UtpSendStream {
             .outgoing_send
             .as_mut()
             .ok_or_else(new_broken_pipe_error)?;
+        let size = self.buffer.as_ref().unwrap().len();
+        self.rate_limit.acquire(size).await;
+        self.socket_rate_limit.acquire(size).await;
         let buffer = self.buffer.take().unwrap();
         let (result_send, result_recv) = oneshot::channel();
         if let Err(error) = outgoing_send.try_send((buffer, result_send)) {
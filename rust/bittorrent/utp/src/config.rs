@@ -0,0 +1,48 @@
+use std::time::Duration;
+
+/// Transport Configuration
+///
+/// It defaults to the `g1_param` values, and an embedder may override it per socket, e.g., to tune
+/// the congestion control for a LAN or a WAN.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct UtpConfig {
+    /// Target queuing delay of the LEDBAT congestion control.
+    pub congestion_control_target: Duration,
+    /// Gain of the LEDBAT congestion control, which is the maximum increase of the congestion
+    /// window per RTT, in bytes.
+    pub max_congestion_window_increase_per_rtt: usize,
+    /// Initial congestion window, in bytes.
+    pub send_window_size_limit: usize,
+    /// Lower bound of the congestion window, to which it is reset upon RTT timeout.
+    pub min_congestion_window: usize,
+    /// Upper bound of the congestion window.
+    pub max_congestion_window: usize,
+    /// Whether to negotiate ECN with the peer.  It has no effect on sockets that cannot read the
+    /// ECN codepoints of the datagrams, i.e., those not created by `UtpSocket::with_ecn`.
+    pub ecn: bool,
+    /// Limit on the number of times a packet can be resent.
+    pub resend_limit: usize,
+    /// Interval of the keep-alive packets sent when the connection is otherwise silent, or `None`
+    /// to disable them.
+    pub keep_alive_interval: Option<Duration>,
+    /// Timeout for receiving any packet, after which the connection is closed, or `None` to
+    /// keep idle connections open indefinitely.
+    pub idle_timeout: Option<Duration>,
+}
+
+impl Default for UtpConfig {
+    fn default() -> Self {
+        Self {
+            congestion_control_target: *crate::congestion_control_target(),
+            max_congestion_window_increase_per_rtt: *crate::max_congestion_window_increase_per_rtt(
+            ),
+            send_window_size_limit: *crate::send_window_size_limit(),
+            min_congestion_window: *crate::min_congestion_window(),
+            max_congestion_window: *crate::max_congestion_window(),
+            ecn: *crate::ecn(),
+            resend_limit: *crate::resend_limit(),
+            keep_alive_interval: *crate::keep_alive_interval(),
+            idle_timeout: *crate::idle_timeout(),
+        }
+    }
+}
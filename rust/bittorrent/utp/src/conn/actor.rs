@@ -10,15 +10,21 @@ use tokio::{
 };
 
 use g1_base::sync::MutexExt;
-use g1_tokio::task::{Cancel, JoinGuard};
+use g1_tokio::{
+    net::udp::Ecn,
+    task::{Cancel, JoinGuard},
+};
 
 use crate::bstream::{self, UtpRecvStream, UtpSendStream, UtpStream};
 use crate::packet::{Packet, PacketType};
+use crate::rate::RateLimits;
+use crate::stats::{SocketCounters, StatsSend, UtpStats};
 use crate::timestamp::Timestamp;
 
 use super::{
-    handshake::Handshake, state::State, ConnectedSend, Connection, ConnectionGuard, Error,
-    IncomingRecv, InvalidPacketSnafu, OutgoingSend, PacketSizeRecv, MIN_PACKET_SIZE,
+    handshake::Handshake, keep_alive::Activity, state::State, ConnectedSend, Connection,
+    ConnectionGuard, Error, IncomingRecv, InvalidPacketSnafu, OutgoingSend, PacketSizeRecv,
+    MIN_PACKET_SIZE,
 };
 
 #[derive(Debug)]
@@ -26,9 +32,14 @@ pub(super) struct Actor<S = InitState> {
     cancel: Cancel,
     pub(super) state: S,
     peer_endpoint: SocketAddr,
+    /// True if the peer has agreed to ECN, in which case we mark data packets ECT(0).
+    ecn: bool,
     outgoing_send: OutgoingSend,
     stream_incoming_send: bstream::IncomingSend,
     pub(super) notifiers: Notifiers,
+    pub(super) activity: Activity,
+    stats_send: StatsSend,
+    socket_counters: Arc<SocketCounters>,
 }
 
 #[derive(Debug)]
@@ -55,11 +66,20 @@ impl Connection {
         peer_endpoint: SocketAddr,
         connected_send: ConnectedSend,
         outgoing_send: OutgoingSend,
+        socket_counters: Arc<SocketCounters>,
+        rate_limits: &RateLimits,
     ) -> (Self, ConnectionGuard, UtpStream) {
         let (incoming_send, incoming_recv) = mpsc::channel(*super::incoming_queue_size());
         let (packet_size_send, packet_size_recv) = watch::channel(MIN_PACKET_SIZE);
-        let (recv, stream_incoming_send) = UtpRecvStream::new(socket.clone(), peer_endpoint);
-        let (send, stream_outgoing_recv) = UtpSendStream::new(socket, peer_endpoint);
+        let (stats_send, stats_recv) = watch::channel(UtpStats::default());
+        let (recv, stream_incoming_send) = UtpRecvStream::new(
+            socket.clone(),
+            peer_endpoint,
+            stats_recv.clone(),
+            rate_limits,
+        );
+        let (send, stream_outgoing_recv) =
+            UtpSendStream::new(socket, peer_endpoint, stats_recv, rate_limits);
         (
             Self {
                 incoming_send,
@@ -78,6 +98,8 @@ impl Connection {
                     peer_endpoint,
                     outgoing_send,
                     stream_incoming_send,
+                    stats_send,
+                    socket_counters,
                 )
                 .run()
             }),
@@ -123,9 +145,11 @@ impl Actor<InitState> {
             }
         };
         let _ = connected_send.send(Ok(()));
+        this.ecn = state.ecn;
         // Wrap the state in a `Mutex` in order to work around the "single mutable borrow" rule in
         // the `tokio::try_join!` block.
         let (this, _) = this.into_state(Mutex::new(state));
+        this.publish_stats();
 
         tokio::select! {
             () = cancel.wait() => Ok(()),
@@ -134,6 +158,7 @@ impl Actor<InitState> {
                     .map(|((), ())| ())
             } => result,
             result = this.rtt_timer() => result,
+            result = this.keep_alive() => result,
             result = this.recv_packet_size(packet_size_recv) => result,
         }
         .inspect_err(|error| {
@@ -157,14 +182,20 @@ impl<S> Actor<S> {
         peer_endpoint: SocketAddr,
         outgoing_send: OutgoingSend,
         stream_incoming_send: bstream::IncomingSend,
+        stats_send: StatsSend,
+        socket_counters: Arc<SocketCounters>,
     ) -> Self {
         Self {
             cancel,
             state,
             peer_endpoint,
+            ecn: false,
             outgoing_send,
             stream_incoming_send,
             notifiers: Notifiers::new(),
+            activity: Activity::new(),
+            stats_send,
+            socket_counters,
         }
     }
 
@@ -174,9 +205,13 @@ impl<S> Actor<S> {
                 cancel: self.cancel,
                 state: next_state,
                 peer_endpoint: self.peer_endpoint,
+                ecn: self.ecn,
                 outgoing_send: self.outgoing_send,
                 stream_incoming_send: self.stream_incoming_send,
                 notifiers: self.notifiers,
+                activity: self.activity,
+                stats_send: self.stats_send,
+                socket_counters: self.socket_counters,
             },
             self.state,
         )
@@ -198,10 +233,19 @@ impl<S> Actor<S> {
             payload_size = packet.payload.len(),
             "send",
         );
+        // Following RFC 3168, we do not mark control packets, as they are not subject to
+        // congestion control.
+        let ecn = if self.ecn && packet.header.packet_type() == PacketType::Data {
+            Ecn::Ect0
+        } else {
+            Ecn::NotEct
+        };
         self.outgoing_send
-            .send((self.peer_endpoint, packet))
+            .send((self.peer_endpoint, packet, ecn))
             .await
-            .map_err(|_| Error::BrokenPipe)
+            .map_err(|_| Error::BrokenPipe)?;
+        self.activity.touch_send();
+        Ok(())
     }
 
     pub(super) fn stream_incoming_is_closed(&self) -> bool {
@@ -231,10 +275,15 @@ impl Actor<Mutex<State>> {
         incoming_recv: &mut IncomingRecv,
     ) -> Result<(Packet, Timestamp), Error> {
         loop {
-            let (packet, recv_at) = incoming_recv.recv().await.ok_or(Error::UnexpectedEof)?;
+            let (packet, recv_at, ecn) = incoming_recv.recv().await.ok_or(Error::UnexpectedEof)?;
             let packet = Packet::try_from(packet).context(InvalidPacketSnafu)?;
-            let recv_id = self.state.must_lock().recv_id;
+            let mut state = self.state.must_lock();
+            let recv_id = state.recv_id;
             if packet.header.conn_id == recv_id {
+                if ecn == Ecn::Ce {
+                    state.recv_ce();
+                }
+                drop(state);
                 tracing::trace!(
                     ?packet.header,
                     ?packet.selective_ack,
@@ -242,6 +291,7 @@ impl Actor<Mutex<State>> {
                     "recv",
                 );
                 self.notifiers.rtt_timer.notify_one();
+                self.activity.touch_recv();
                 return Ok((packet, recv_at));
             }
             tracing::warn!(
@@ -260,7 +310,22 @@ impl Actor<Mutex<State>> {
             payload_size = packet.payload.len(),
             "send",
         );
-        let _ = self.outgoing_send.try_send((self.peer_endpoint, packet));
+        let _ = self
+            .outgoing_send
+            .try_send((self.peer_endpoint, packet, Ecn::NotEct));
+    }
+
+    /// Publishes the stats to the stream and adds the increments of the counters to the socket.
+    pub(super) fn publish_stats(&self) {
+        let stats = self.state.must_lock().stats();
+        self.stats_send.send_if_modified(|old| {
+            if *old == stats {
+                return false;
+            }
+            self.socket_counters.add(old, &stats);
+            *old = stats;
+            true
+        });
     }
 
     async fn recv_packet_size(&self, mut packet_size_recv: PacketSizeRecv) -> Result<(), Error> {
@@ -302,6 +367,8 @@ mod test_harness {
                     peer_endpoint,
                     outgoing_send,
                     stream_incoming_send,
+                    watch::channel(UtpStats::default()).0,
+                    Arc::new(SocketCounters::default()),
                 ),
                 outgoing_recv,
                 stream_incoming_recv,
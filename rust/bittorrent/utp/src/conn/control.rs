@@ -13,13 +13,34 @@ use crate::timestamp::Timestamp;
 //   we will follow BEP 29 for now.
 // * The delay values are of type `u32`, but they are stored internally as `u64` to simplify
 //   wraparound handling.
+// * BEP 29 specifies the base delay as the minimum delay of the last two minutes, but keeping
+//   every sample of the window is costly at high packet rates.  We follow RFC 6817 instead, which
+//   keeps only the minimum delay of each period.
 //
 
+/// Base Delay History
+///
+/// It keeps the minimum delays of the last `history_size` periods, and the base delay is the
+/// minimum of them.  The history shifts when a period ends, discarding the oldest minimum.
 #[derive(Debug)]
 pub(super) struct DelayWindow {
+    // Start of the period and the minimum delay in that period.
     delays: VecDeque<(Timestamp, u64)>,
     min_delay: Option<u64>,
-    window_size: Duration,
+    period: Duration,
+    history_size: usize,
+}
+
+/// Clock Skew Estimator
+///
+/// When the peer's clock runs faster than ours, the delays of our packets, which the peer
+/// measures, drift upward, and we would mistake the drift for queueing delay.  Meanwhile, the
+/// delays of the peer's packets, which we measure, drift downward by the same amount.  Like
+/// libutp, we estimate the drift from the decrease in the base delay of the peer's packets.
+#[derive(Debug)]
+pub(super) struct ClockSkew {
+    their_delays: DelayWindow,
+    max_correction: u64,
 }
 
 fn to_u32(x: u64) -> u32 {
@@ -39,11 +60,13 @@ fn measure(origin: u32, target: u32) -> i64 {
 }
 
 impl DelayWindow {
-    pub(super) fn new(window_size: Duration) -> Self {
+    pub(super) fn new(period: Duration, history_size: usize) -> Self {
+        assert!(history_size > 0);
         Self {
-            delays: VecDeque::new(),
+            delays: VecDeque::with_capacity(history_size + 1),
             min_delay: None,
-            window_size,
+            period,
+            history_size,
         }
     }
 
@@ -70,15 +93,21 @@ impl DelayWindow {
             self.shift(1 << u32::BITS);
             self.to_internal(delay).unwrap()
         });
-        self.delays.push_back((now, delay));
+        match self.delays.back_mut() {
+            Some((start, min_delay)) if now < *start + self.period => {
+                *min_delay = cmp::min(*min_delay, delay);
+            }
+            _ => self.delays.push_back((now, delay)),
+        }
         self.min_delay = Some(cmp::min(delay, self.min_delay.unwrap_or(delay)));
         self.clear(now);
     }
 
     pub(super) fn clear(&mut self, now: Timestamp) {
+        let history_period = self.period * u32::try_from(self.history_size).unwrap();
         let mut recompute_min_delay = false;
         while let Some((t, _)) = self.delays.front().copied() {
-            if t + self.window_size <= now {
+            if t + history_period <= now || self.delays.len() > self.history_size {
                 self.delays.pop_front();
                 recompute_min_delay = true;
             } else {
@@ -90,8 +119,51 @@ impl DelayWindow {
         }
     }
 
+    /// Raises the base delay by `drift` microseconds to compensate for the clock skew.
+    pub(super) fn correct_skew(&mut self, drift: u32) {
+        if drift != 0 {
+            self.shift(drift.into());
+        }
+    }
+
     pub(super) fn subtract_min_delay(&self, delay: u32) -> u32 {
-        u32::try_from(self.to_internal(delay).unwrap() - self.min_delay.unwrap()).unwrap()
+        // The base delay may exceed `delay` after it is raised by `correct_skew`.
+        u32::try_from(
+            self.to_internal(delay)
+                .unwrap()
+                .saturating_sub(self.min_delay.unwrap()),
+        )
+        .unwrap()
+    }
+}
+
+impl ClockSkew {
+    pub(super) fn new(period: Duration, history_size: usize, max_correction: Duration) -> Self {
+        Self {
+            their_delays: DelayWindow::new(period, history_size),
+            max_correction: max_correction.as_micros().try_into().unwrap_or(u64::MAX),
+        }
+    }
+
+    /// Records the delay of a packet from the peer and returns the drift of the peer's clock
+    /// since the last call, in microseconds.
+    ///
+    /// A drift exceeding `max_correction` is more likely a change of the network path than a
+    /// clock skew, and it is ignored.
+    pub(super) fn push(&mut self, now: Timestamp, their_delay: u32) -> u32 {
+        let prev_min_delay = self.their_delays.min_delay;
+        self.their_delays.push(now, their_delay);
+        match (prev_min_delay, self.their_delays.min_delay) {
+            (Some(prev_min_delay), Some(min_delay)) if min_delay < prev_min_delay => {
+                let drift = prev_min_delay - min_delay;
+                if drift <= self.max_correction {
+                    u32::try_from(drift).unwrap_or(0)
+                } else {
+                    0
+                }
+            }
+            _ => 0,
+        }
     }
 }
 
@@ -135,7 +207,7 @@ mod tests {
 
     #[test]
     fn to_internal() {
-        let mut window = DelayWindow::new(Duration::ZERO);
+        let mut window = DelayWindow::new(Duration::ZERO, 1);
 
         assert_eq!(window.min_delay, None);
         assert_eq!(window.to_internal(0), Some(0));
@@ -172,31 +244,37 @@ mod tests {
 
     #[test]
     fn push() {
-        let mut window = DelayWindow::new(Duration::from_millis(500));
+        let mut window = DelayWindow::new(Duration::from_millis(500), 2);
         assert_window(&window, &[]);
 
         window.push(Timestamp::ZERO, 10);
         assert_window(&window, &[10]);
         window.push(Timestamp::ZERO, 11);
-        assert_window(&window, &[10, 11]);
+        assert_window(&window, &[10]);
         window.push(Timestamp::ZERO, 9);
-        assert_window(&window, &[10, 11, 9]);
+        assert_window(&window, &[9]);
 
         window.push(Timestamp::ZERO + Duration::SECOND, 12);
         assert_window(&window, &[12]);
-
-        window.clear(Timestamp::ZERO + Duration::from_secs(2));
+        window.push(Timestamp::ZERO + Duration::from_millis(1600), 13);
+        assert_window(&window, &[12, 13]);
+        window.push(Timestamp::ZERO + Duration::from_millis(1900), 11);
+        assert_window(&window, &[12, 11]);
+        window.push(Timestamp::ZERO + Duration::from_millis(2200), 14);
+        assert_window(&window, &[11, 14]);
+
+        window.clear(Timestamp::ZERO + Duration::from_secs(10));
         assert_window(&window, &[]);
 
         window.push(Timestamp::ZERO, 10);
         assert_window(&window, &[10]);
         window.push(Timestamp::ZERO, u32::MAX);
-        assert_window(&window, &[N + 10, N - 1]);
+        assert_window(&window, &[N - 1]);
     }
 
     #[test]
     fn push_empty() {
-        let mut window = DelayWindow::new(Duration::ZERO);
+        let mut window = DelayWindow::new(Duration::ZERO, 1);
         assert_window(&window, &[]);
         for _ in 0..3 {
             window.push(Timestamp::ZERO, 10);
@@ -206,7 +284,7 @@ mod tests {
 
     #[test]
     fn subtract_min_delay() {
-        let mut window = DelayWindow::new(Duration::from_millis(500));
+        let mut window = DelayWindow::new(Duration::from_millis(500), 2);
 
         window.push(Timestamp::ZERO, 10);
         assert_window(&window, &[10]);
@@ -215,9 +293,41 @@ mod tests {
         assert_eq!(window.subtract_min_delay(12), 2);
 
         window.push(Timestamp::ZERO, u32::MAX);
-        assert_window(&window, &[N + 10, N - 1]);
+        assert_window(&window, &[N - 1]);
         assert_eq!(window.subtract_min_delay(u32::MAX), 0);
         assert_eq!(window.subtract_min_delay(0), 1);
         assert_eq!(window.subtract_min_delay(1), 2);
     }
+
+    #[test]
+    fn correct_skew() {
+        let mut window = DelayWindow::new(Duration::from_millis(500), 2);
+        window.correct_skew(5);
+        assert_window(&window, &[]);
+
+        window.push(Timestamp::ZERO, 10);
+        window.correct_skew(5);
+        assert_window(&window, &[15]);
+        assert_eq!(window.subtract_min_delay(20), 5);
+        assert_eq!(window.subtract_min_delay(10), 0);
+
+        window.push(Timestamp::ZERO, 12);
+        assert_window(&window, &[12]);
+    }
+
+    #[test]
+    fn clock_skew() {
+        let mut skew = ClockSkew::new(Duration::from_millis(500), 2, Duration::from_micros(100));
+        assert_eq!(skew.push(Timestamp::ZERO, 1000), 0);
+        assert_eq!(skew.push(Timestamp::ZERO, 1010), 0);
+        assert_eq!(skew.push(Timestamp::ZERO, 990), 10);
+        assert_eq!(skew.push(Timestamp::ZERO, 990), 0);
+        // Exceeds `max_correction`.
+        assert_eq!(skew.push(Timestamp::ZERO, 800), 0);
+        assert_eq!(skew.push(Timestamp::ZERO, 750), 50);
+
+        // The base delay rises when the old periods expire.
+        assert_eq!(skew.push(Timestamp::ZERO + Duration::SECOND, 760), 0);
+        assert_eq!(skew.push(Timestamp::ZERO + Duration::SECOND, 755), 5);
+    }
 }
@@ -2,6 +2,7 @@ use bytes::Bytes;
 use snafu::prelude::*;
 use tokio::time;
 
+use crate::config::UtpConfig;
 use crate::packet::{Packet, PacketType};
 use crate::timestamp::{self, Timestamp};
 
@@ -9,7 +10,7 @@ use super::{
     actor::Actor,
     state::State,
     window::{RecvWindow, SendWindow},
-    Error, ExpectPacketTypeSnafu, IncomingRecv, InvalidPacketSnafu,
+    ConnectionRefusedSnafu, Error, ExpectPacketTypeSnafu, IncomingRecv, InvalidPacketSnafu,
 };
 
 #[derive(Debug)]
@@ -19,35 +20,43 @@ pub(crate) struct Handshake {
     seq: u16,
     ack: u16,
     recv_window_size: usize,
-    send_window_size_limit: usize,
     packet_size: usize,
+    ecn: bool,
+    config: UtpConfig,
 }
 
 impl Handshake {
-    pub(crate) fn new_connect() -> Self {
-        let recv_id = rand::random();
+    pub(crate) fn new_connect(config: UtpConfig) -> Self {
+        let recv_id = g1_base::rand::random();
         Self::new(
+            config,
             recv_id,
             recv_id.wrapping_add(1),
             // BEP 29 specifies that seq should be initialized to 1, but libutp initializes it with
             // a random value.
-            rand::random(),
+            g1_base::rand::random(),
         )
     }
 
-    pub(crate) fn new_accept() -> Self {
-        Self::new(0, 0, rand::random())
+    pub(crate) fn new_accept(config: UtpConfig) -> Self {
+        Self::new(config, 0, 0, g1_base::rand::random())
     }
 
-    fn new(recv_id: u16, send_id: u16, seq: u16) -> Self {
+    /// Returns the connection id of the packets that we receive.
+    pub(crate) fn recv_id(&self) -> u16 {
+        self.recv_id
+    }
+
+    fn new(config: UtpConfig, recv_id: u16, send_id: u16, seq: u16) -> Self {
         Self {
             recv_id,
             send_id,
             seq,
             ack: 0,
             recv_window_size: *crate::recv_window_size(),
-            send_window_size_limit: *crate::send_window_size_limit(),
             packet_size: *crate::packet_size(),
+            ecn: false,
+            config,
         }
     }
 
@@ -58,7 +67,7 @@ impl Handshake {
     }
 
     fn make_synchronize_packet(&mut self) -> Packet {
-        Packet::new(
+        let mut packet = Packet::new(
             PacketType::Synchronize,
             self.recv_id,
             timestamp::now(),
@@ -68,11 +77,16 @@ impl Handshake {
             self.ack,
             None,
             Bytes::new(),
-        )
+        );
+        // Advertise ECN support to the peer.
+        if self.config.ecn {
+            packet.set_ecn_echo(Some(0));
+        }
+        packet
     }
 
     fn new_syn_ack_packet(&self) -> Packet {
-        Packet::new(
+        let mut packet = Packet::new(
             PacketType::State,
             self.send_id,
             timestamp::now(),
@@ -84,17 +98,24 @@ impl Handshake {
             self.ack,
             None,
             Bytes::new(),
-        )
+        );
+        if self.ecn {
+            packet.set_ecn_echo(Some(0));
+        }
+        packet
     }
 
     fn new_state(&self) -> State {
-        State::new(
+        let mut state = State::new(
             self.recv_id,
             self.send_id,
             RecvWindow::new(self.recv_window_size, self.ack),
-            SendWindow::new(self.send_window_size_limit, self.seq),
+            SendWindow::new(self.config.send_window_size_limit, self.seq),
             self.packet_size,
-        )
+            self.config,
+        );
+        state.ecn = self.ecn;
+        state
     }
 }
 
@@ -122,6 +143,8 @@ impl Actor<Handshake> {
         .await
         .map_err(|_| Error::ConnectTimeout)??;
         let packet_type = packet.header.packet_type();
+        // Surface this as distinct from a timeout so that the caller does not retry in vain.
+        ensure!(packet_type != PacketType::Reset, ConnectionRefusedSnafu);
         ensure!(
             packet_type == PacketType::State,
             ExpectPacketTypeSnafu {
@@ -133,6 +156,7 @@ impl Actor<Handshake> {
         // sets ack to seq - 1.  If I have to guess, it is probably because the accept side of the
         // code in libutp does not post-increment seq when sending the syn-ack packet.
         self.state.ack = packet.header.seq.wrapping_sub(1);
+        self.state.ecn = self.state.config.ecn && packet.ecn_echo.is_some();
 
         let mut state = self.state.new_state();
         state.update_send_delay(&packet.header, recv_at);
@@ -166,6 +190,7 @@ impl Actor<Handshake> {
         self.state.recv_id = packet.header.conn_id.wrapping_add(1);
         self.state.send_id = packet.header.conn_id;
         self.state.ack = packet.header.seq;
+        self.state.ecn = self.state.config.ecn && packet.ecn_echo.is_some();
 
         let outgoing_packet = self.state.new_syn_ack_packet();
         self.outgoing_send(outgoing_packet).await?;
@@ -190,7 +215,8 @@ impl Actor<Handshake> {
         conn_id: Option<u16>,
     ) -> Result<(Packet, Timestamp), Error> {
         loop {
-            let (packet, recv_at) = incoming_recv.recv().await.ok_or(Error::UnexpectedEof)?;
+            // We do not mark handshake packets, and so we ignore their ECN codepoints.
+            let (packet, recv_at, _) = incoming_recv.recv().await.ok_or(Error::UnexpectedEof)?;
             let packet = Packet::try_from(packet).context(InvalidPacketSnafu)?;
             let expect = conn_id.unwrap_or(packet.header.conn_id);
             if packet.header.conn_id == expect {
@@ -200,6 +226,7 @@ impl Actor<Handshake> {
                     payload_size = packet.payload.len(),
                     "recv",
                 );
+                self.activity.touch_recv();
                 return Ok((packet, recv_at));
             }
             tracing::warn!(
@@ -216,6 +243,8 @@ mod tests {
     use bytes::BytesMut;
     use tokio::sync::mpsc;
 
+    use g1_tokio::net::udp::Ecn;
+
     use super::{
         super::{IncomingSend, OutgoingRecv},
         *,
@@ -224,11 +253,11 @@ mod tests {
     #[tokio::test]
     async fn handshake() {
         async fn forward(mut outgoing_recv: OutgoingRecv, incoming_send: IncomingSend) {
-            while let Some((_, packet)) = outgoing_recv.recv().await {
+            while let Some((_, packet, ecn)) = outgoing_recv.recv().await {
                 let mut buffer = BytesMut::with_capacity(packet.size());
                 packet.encode(&mut buffer);
                 if incoming_send
-                    .send((buffer.freeze(), Timestamp::ZERO))
+                    .send((buffer.freeze(), Timestamp::ZERO, ecn))
                     .await
                     .is_err()
                 {
@@ -237,10 +266,14 @@ mod tests {
             }
         }
 
-        let (mut connector, connector_outgoing_recv, _) =
-            Actor::new_mock(Handshake::new_connect(), "127.0.0.1:10000".parse().unwrap());
-        let (mut acceptor, acceptor_outgoing_recv, _) =
-            Actor::new_mock(Handshake::new_accept(), "127.0.0.1:20000".parse().unwrap());
+        let (mut connector, connector_outgoing_recv, _) = Actor::new_mock(
+            Handshake::new_connect(UtpConfig::default()),
+            "127.0.0.1:10000".parse().unwrap(),
+        );
+        let (mut acceptor, acceptor_outgoing_recv, _) = Actor::new_mock(
+            Handshake::new_accept(UtpConfig::default()),
+            "127.0.0.1:20000".parse().unwrap(),
+        );
 
         let (connector_incoming_send, mut connector_incoming_recv) = mpsc::channel(32);
         let (acceptor_incoming_send, mut acceptor_incoming_recv) = mpsc::channel(32);
@@ -264,6 +297,9 @@ mod tests {
         let (acceptor, acceptor_incoming_recv, acceptor_state) =
             acceptor_task.await.unwrap().unwrap();
 
+        assert_eq!(connector_state.ecn, true);
+        assert_eq!(acceptor_state.ecn, true);
+
         assert_eq!(connector_state.recv_id, acceptor_state.send_id);
         assert_eq!(connector_state.send_id, acceptor_state.recv_id);
 
@@ -300,4 +336,40 @@ mod tests {
         connector_forward_task.await.unwrap();
         acceptor_forward_task.await.unwrap();
     }
+
+    #[tokio::test]
+    async fn connect_refused() {
+        let (mut connector, mut outgoing_recv, _) = Actor::new_mock(
+            Handshake::new_connect(UtpConfig::default()),
+            "127.0.0.1:10000".parse().unwrap(),
+        );
+        let (incoming_send, mut incoming_recv) = mpsc::channel(32);
+
+        let reset = Packet::new(
+            PacketType::Reset,
+            connector.state.recv_id,
+            Timestamp::ZERO,
+            0,
+            0,
+            0,
+            0,
+            None,
+            Bytes::new(),
+        );
+        let mut buffer = BytesMut::with_capacity(reset.size());
+        reset.encode(&mut buffer);
+        incoming_send
+            .send((buffer.freeze(), Timestamp::ZERO, Ecn::NotEct))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            connector.handshake(&mut incoming_recv).await.unwrap_err(),
+            Error::ConnectionRefused,
+        );
+        assert_eq!(
+            outgoing_recv.recv().await.unwrap().1.header.packet_type(),
+            PacketType::Synchronize,
+        );
+    }
 }
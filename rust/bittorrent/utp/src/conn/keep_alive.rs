@@ -0,0 +1,137 @@
+use std::sync::Mutex;
+
+use futures::future::OptionFuture;
+use tokio::time::{self, Instant};
+
+use g1_base::sync::MutexExt;
+
+use super::{actor::Actor, state::State, Error};
+
+/// Times of the last packets received from and sent to the peer.
+#[derive(Debug)]
+pub(super) struct Activity {
+    recv_at: Mutex<Instant>,
+    send_at: Mutex<Instant>,
+}
+
+impl Activity {
+    pub(super) fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            recv_at: Mutex::new(now),
+            send_at: Mutex::new(now),
+        }
+    }
+
+    pub(super) fn touch_recv(&self) {
+        *self.recv_at.must_lock() = Instant::now();
+    }
+
+    pub(super) fn touch_send(&self) {
+        *self.send_at.must_lock() = Instant::now();
+    }
+}
+
+impl Actor<Mutex<State>> {
+    /// Sends an ack packet when we have not sent anything for `keep_alive_interval`, so that the
+    /// NAT mappings along the path do not expire, and closes the connection when we have not
+    /// received anything for `idle_timeout`.
+    pub(super) async fn keep_alive(&self) -> Result<(), Error> {
+        let config = self.state.must_lock().config;
+        loop {
+            let idle_deadline = config
+                .idle_timeout
+                .map(|timeout| *self.activity.recv_at.must_lock() + timeout);
+            let keep_alive_deadline = config
+                .keep_alive_interval
+                .map(|interval| *self.activity.send_at.must_lock() + interval);
+            // Both deadlines only move forward; we re-check them after the sleep.
+            tokio::select! {
+                Some(()) = OptionFuture::from(idle_deadline.map(time::sleep_until)) => {
+                    let recv_at = *self.activity.recv_at.must_lock();
+                    if recv_at + config.idle_timeout.unwrap() <= Instant::now() {
+                        tracing::debug!("idle timeout");
+                        return Err(Error::IdleTimeout);
+                    }
+                }
+                Some(()) = OptionFuture::from(keep_alive_deadline.map(time::sleep_until)) => {
+                    let send_at = *self.activity.send_at.must_lock();
+                    if send_at + config.keep_alive_interval.unwrap() <= Instant::now() {
+                        tracing::trace!("keep alive");
+                        let packet = self.state.must_lock().new_ack_packet();
+                        self.outgoing_send_dont_reset_rtt_timer(packet).await?;
+                    }
+                }
+                else => return std::future::pending().await,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    use crate::config::UtpConfig;
+    use crate::packet::PacketType;
+
+    use super::{
+        super::window::{RecvWindow, SendWindow},
+        *,
+    };
+
+    fn new_actor(config: UtpConfig) -> (Actor<Mutex<State>>, super::super::OutgoingRecv) {
+        let state = Mutex::new(State::new(
+            0,
+            1,
+            RecvWindow::new(64, 0),
+            SendWindow::new(64, 0),
+            150,
+            config,
+        ));
+        let (actor, outgoing_recv, _) =
+            Actor::new_mock(state, "127.0.0.1:8000".parse::<SocketAddr>().unwrap());
+        (actor, outgoing_recv)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn keep_alive() {
+        let (actor, mut outgoing_recv) = new_actor(UtpConfig {
+            keep_alive_interval: Some(Duration::from_secs(10)),
+            idle_timeout: Some(Duration::from_secs(25)),
+            ..UtpConfig::default()
+        });
+        let start = Instant::now();
+
+        assert_eq!(actor.keep_alive().await, Err(Error::IdleTimeout));
+        assert_eq!(start.elapsed(), Duration::from_secs(25));
+
+        for _ in 0..2 {
+            let (_, packet, _) = outgoing_recv.try_recv().unwrap();
+            assert_eq!(packet.header.packet_type(), PacketType::State);
+        }
+        assert!(outgoing_recv.try_recv().is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn keep_alive_touch() {
+        let (actor, mut outgoing_recv) = new_actor(UtpConfig {
+            keep_alive_interval: None,
+            idle_timeout: Some(Duration::from_secs(25)),
+            ..UtpConfig::default()
+        });
+        let start = Instant::now();
+
+        tokio::select! {
+            result = actor.keep_alive() => panic!("{result:?}"),
+            () = async {
+                time::sleep(Duration::from_secs(20)).await;
+                actor.activity.touch_recv();
+            } => {}
+        }
+        assert_eq!(actor.keep_alive().await, Err(Error::IdleTimeout));
+        assert_eq!(start.elapsed(), Duration::from_secs(45));
+        assert!(outgoing_recv.try_recv().is_err());
+    }
+}
@@ -1,6 +1,7 @@
 mod actor;
 mod control;
 mod handshake;
+mod keep_alive;
 mod recv;
 mod rtt;
 mod send;
@@ -17,7 +18,7 @@ use tokio::sync::{
     oneshot, watch,
 };
 
-use g1_tokio::task::JoinGuard;
+use g1_tokio::{net::udp::Ecn, task::JoinGuard};
 
 use crate::packet::{self, Packet, PacketType};
 use crate::timestamp::Timestamp;
@@ -32,10 +33,12 @@ pub(crate) enum Error {
     ConnectTimeout,
     AcceptTimeout,
 
+    ConnectionRefused,
     ConnectionReset,
 
     RecvBufferTimeout,
     RecvGracePeriodExpired,
+    IdleTimeout,
 
     #[snafu(display("resend limit exceeded: seq={seq}"))]
     ResendLimitExceeded {
@@ -84,7 +87,7 @@ pub(crate) type ConnectedSend = oneshot::Sender<Connected>;
 g1_param::define!(incoming_queue_size: usize = 512);
 g1_param::define!(outgoing_queue_size: usize = 4096);
 
-pub(crate) type Incoming = (Bytes, Timestamp);
+pub(crate) type Incoming = (Bytes, Timestamp, Ecn);
 pub(crate) type IncomingRecv = Receiver<Incoming>;
 pub(crate) type IncomingSend = Sender<Incoming>;
 
@@ -96,7 +99,7 @@ pub(crate) struct Connection {
 
 pub(crate) type ConnectionGuard = JoinGuard<Result<(), Error>>;
 
-pub(crate) type Outgoing = (SocketAddr, Packet);
+pub(crate) type Outgoing = (SocketAddr, Packet, Ecn);
 pub(crate) type OutgoingRecv = Receiver<Outgoing>;
 pub(crate) type OutgoingSend = Sender<Outgoing>;
 
@@ -121,6 +124,10 @@ impl Error {
             Error::ConnectTimeout => io::Error::new(io::ErrorKind::TimedOut, "utp connect timeout"),
             Error::AcceptTimeout => io::Error::new(io::ErrorKind::TimedOut, "utp accept timeout"),
 
+            Error::ConnectionRefused => io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                "utp connection is refused by peer",
+            ),
             Error::ConnectionReset => io::Error::new(
                 io::ErrorKind::ConnectionReset,
                 "utp connection is reset by peer",
@@ -132,6 +139,7 @@ impl Error {
             Error::RecvGracePeriodExpired => {
                 io::Error::new(io::ErrorKind::TimedOut, "utp recv grace period expired")
             }
+            Error::IdleTimeout => io::Error::new(io::ErrorKind::TimedOut, "utp idle timeout"),
 
             Error::ResendLimitExceeded { .. } => {
                 io::Error::new(io::ErrorKind::TimedOut, self.clone())
@@ -48,6 +48,7 @@ impl Actor<Mutex<State>> {
             }
 
             let (packets, payloads) = self.handle_packet(&mut recv_state, packet, recv_at)?;
+            self.publish_stats();
 
             for packet in packets {
                 self.outgoing_send(packet).await?;
@@ -192,6 +193,12 @@ impl Actor<Mutex<State>> {
                 window_size_limit = state.send_window.size_limit,
                 "congestion control",
             );
+            if state.apply_ecn_echo(packet.ecn_echo, recv_at) {
+                tracing::debug!(
+                    window_size_limit = state.send_window.size_limit,
+                    "congestion experienced",
+                );
+            }
         }
 
         let mut num_lost = 0;
@@ -202,10 +209,11 @@ impl Actor<Mutex<State>> {
             }
         }
         if num_lost > 0 {
+            state.num_lost += num_lost;
             // BEP 29 specifies that, to mimic TCP, the window size limit should be halved, when a
             // packet is lost.
             let new_size_limit = state.send_window.size_limit / 2;
-            state.send_window.set_size_limit(new_size_limit);
+            state.set_size_limit(new_size_limit);
             self.notifiers.send.notify_one();
             tracing::debug!(
                 num_lost,
@@ -224,6 +232,10 @@ mod tests {
     use hex_literal::hex;
     use tokio::sync::mpsc;
 
+    use g1_tokio::net::udp::Ecn;
+
+    use crate::config::UtpConfig;
+
     use super::{
         super::window::{RecvWindow, SendWindow},
         *,
@@ -236,6 +248,7 @@ mod tests {
             RecvWindow::new(10, 0x100),
             SendWindow::new(0, 0x200),
             150, // You cannot set the packet size smaller than the minimum packet size.
+            UtpConfig::default(),
         ))
     }
 
@@ -244,11 +257,13 @@ mod tests {
         let (actor, mut outgoing_recv, mut stream_incoming_recv) =
             Actor::new_mock(new_state(), "127.0.0.1:10000".parse().unwrap());
         actor.state.must_lock().send_window.close();
+        actor.state.must_lock().ecn = true;
         let (incoming_send, incoming_recv) = mpsc::channel(8);
         incoming_send
             .send((
                 Bytes::from_static(&hex!("11 00 1000 00000000 00000000 00000000 0102 0000")),
                 Timestamp::ZERO,
+                Ecn::NotEct,
             ))
             .await
             .unwrap();
@@ -258,6 +273,7 @@ mod tests {
                     "01 00 1000 00000000 00000000 00000000 0101 0000 1234"
                 )),
                 Timestamp::ZERO,
+                Ecn::Ce,
             ))
             .await
             .unwrap();
@@ -281,6 +297,7 @@ mod tests {
         assert_eq!(packet.header.window_size, 10);
         assert_eq!(packet.header.seq, 0x200);
         assert_eq!(packet.header.ack, 0x101);
+        assert_eq!(packet.ecn_echo, Some(1));
         // fin-ack
         let packet = &packets[1].1;
         assert_eq!(
@@ -290,6 +307,7 @@ mod tests {
         assert_eq!(packet.header.window_size, 10);
         assert_eq!(packet.header.seq, 0x200);
         assert_eq!(packet.header.ack, 0x102);
+        assert_eq!(packet.ecn_echo, Some(1));
 
         let mut payloads = Vec::new();
         while let Some(payload) = stream_incoming_recv.recv().await {
@@ -313,6 +331,7 @@ mod tests {
             .send((
                 Bytes::from_static(&hex!("31 00 1000 00000000 00000000 00000000 0000 0000")),
                 Timestamp::ZERO,
+                Ecn::NotEct,
             ))
             .await
             .unwrap();
@@ -328,6 +347,7 @@ mod tests {
             .send((
                 Bytes::from_static(&hex!("41 00 1000 00000000 00000000 00000000 0000 0000")),
                 Timestamp::ZERO,
+                Ecn::NotEct,
             ))
             .await
             .unwrap();
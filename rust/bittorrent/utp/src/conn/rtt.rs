@@ -80,14 +80,22 @@ impl Actor<Mutex<State>> {
             for seq in state.send_window.seqs().collect::<Vec<_>>() {
                 if state.send_window.get(seq).unwrap().num_acks == 0 {
                     packets.push(state.make_resend_data_packet(seq)?.unwrap());
+                    state.num_lost += 1;
                     is_packet_timeout = true;
                 }
             }
+            // Linger until the peer acks the finish packet, or else the peer may never see the
+            // end of the stream.
+            if let Some(packet) = state.make_resend_finish_packet() {
+                packets.push(packet);
+                is_packet_timeout = true;
+            }
             // Here we deviate from BEP 29.  I think it makes more sense to condition the RTT
             // timeout on the in-flight queue not being empty.
             if is_packet_timeout {
                 state.set_packet_size(MIN_PACKET_SIZE);
-                state.send_window.set_size_limit(MIN_PACKET_SIZE);
+                let min_congestion_window = state.config.min_congestion_window;
+                state.set_size_limit(min_congestion_window);
                 state.send_window.rtt.expire();
                 self.notifiers.send.notify_one();
 
@@ -101,6 +109,7 @@ impl Actor<Mutex<State>> {
                 );
             }
         }
+        self.publish_stats();
         for packet in packets {
             self.outgoing_send_dont_reset_rtt_timer(packet).await?;
         }
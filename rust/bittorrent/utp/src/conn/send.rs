@@ -51,6 +51,7 @@ impl Actor<Mutex<State>> {
                 .make_data_packet(payload, was_timeout);
             match packet {
                 Some(packet) => {
+                    self.publish_stats();
                     self.outgoing_send(packet).await?;
                     now = Instant::now();
                     was_timeout = false;
@@ -58,8 +59,11 @@ impl Actor<Mutex<State>> {
                 None => {
                     // This is roughly the time when `rtt_timer` returns `ResendLimitExceeded`.  It
                     // seems reasonable to use this value as the send timeout.
-                    let send_timeout = self.state.must_lock().send_window.rtt.timeout
-                        * (1 + *crate::resend_limit()).try_into().unwrap();
+                    let send_timeout = {
+                        let state = self.state.must_lock();
+                        state.send_window.rtt.timeout
+                            * (1 + state.config.resend_limit).try_into().unwrap()
+                    };
                     tokio::select! {
                         () = time::sleep_until(now + send_timeout) => {
                             tracing::debug!("send timeout");
@@ -77,15 +81,18 @@ impl Actor<Mutex<State>> {
 #[cfg(test)]
 mod tests {
     use std::assert_matches::assert_matches;
-    use std::net::SocketAddr;
     use std::time::Duration;
 
     use tokio::sync::{mpsc, oneshot};
 
-    use crate::packet::{Packet, PacketType};
+    use crate::config::UtpConfig;
+    use crate::packet::PacketType;
 
     use super::{
-        super::window::{RecvWindow, SendWindow},
+        super::{
+            window::{RecvWindow, SendWindow},
+            Outgoing,
+        },
         *,
     };
 
@@ -98,6 +105,7 @@ mod tests {
             RecvWindow::new(0, 1000),
             SendWindow::new(SEND_WINDOW_SIZE, 2000),
             150, // You cannot set the packet size smaller than the minimum packet size.
+            UtpConfig::default(),
         ));
         state.must_lock().send_window.set_size(0, SEND_WINDOW_SIZE);
         state
@@ -117,9 +125,9 @@ mod tests {
         );
     }
 
-    fn assert_data_packets(packets: &[(SocketAddr, Packet)], expect: &[(u16, &[u8])]) {
+    fn assert_data_packets(packets: &[Outgoing], expect: &[(u16, &[u8])]) {
         assert_eq!(packets.len(), expect.len());
-        for ((_, packet), (seq, payload)) in packets.iter().zip(expect.iter()) {
+        for ((_, packet, _), (seq, payload)) in packets.iter().zip(expect.iter()) {
             assert_eq!(
                 (packet.header.type_version & 0xf0) >> 4,
                 PacketType::Data as u8
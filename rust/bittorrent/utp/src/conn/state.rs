@@ -4,11 +4,13 @@ use std::time::Duration;
 use bytes::{Bytes, BytesMut};
 use snafu::prelude::*;
 
+use crate::config::UtpConfig;
 use crate::packet::{Packet, PacketHeader, PacketType, SelectiveAck};
+use crate::stats::UtpStats;
 use crate::timestamp::{self, Timestamp};
 
 use super::{
-    control::DelayWindow,
+    control::{ClockSkew, DelayWindow},
     window::{RecvWindow, SendWindow},
     Error, ResendLimitExceededSnafu, MIN_PACKET_SIZE,
 };
@@ -21,7 +23,24 @@ pub(super) struct State {
     pub(super) send_window: SendWindow,
     pub(super) send_delay: u32,
     pub(super) delay_window: DelayWindow,
+    pub(super) clock_skew: ClockSkew,
     pub(super) packet_size: usize,
+    pub(super) config: UtpConfig,
+
+    /// True if both sides have agreed to ECN during the handshake.
+    pub(super) ecn: bool,
+    // Number of CE-marked packets we have received, which we echo to the peer.
+    num_ce_recv: u32,
+    // The last echo from the peer.
+    num_ce_echoed: u32,
+    // When we last reduced the congestion window in response to an echo.
+    ce_reduced_at: Option<Timestamp>,
+
+    // Queuing delay of our packets as of the last congestion control update.
+    queuing_delay: u32,
+    num_sent: u64,
+    pub(super) num_lost: u64,
+    num_resent: u64,
 }
 
 impl State {
@@ -31,6 +50,7 @@ impl State {
         recv_window: RecvWindow,
         send_window: SendWindow,
         packet_size: usize,
+        config: UtpConfig,
     ) -> Self {
         Self {
             recv_id,
@@ -39,15 +59,47 @@ impl State {
             send_window,
             send_delay: 0,
             delay_window: DelayWindow::new(
-                Duration::from_secs(120), // Window size specified by BEP 29.
+                *crate::base_delay_period(),
+                *crate::base_delay_history_size(),
+            ),
+            clock_skew: ClockSkew::new(
+                *crate::base_delay_period(),
+                *crate::base_delay_history_size(),
+                *crate::max_clock_skew_correction(),
             ),
             packet_size: cmp::max(packet_size, MIN_PACKET_SIZE),
+            config,
+            ecn: false,
+            num_ce_recv: 0,
+            num_ce_echoed: 0,
+            ce_reduced_at: None,
+            queuing_delay: 0,
+            num_sent: 0,
+            num_lost: 0,
+            num_resent: 0,
+        }
+    }
+
+    pub(super) fn stats(&self) -> UtpStats {
+        let rtt = &self.send_window.rtt;
+        UtpStats {
+            rtt: rtt.average,
+            rtt_var: rtt.variance,
+            rtt_timeout: rtt.timeout,
+            cwnd: self.send_window.size_limit,
+            bytes_in_flight: self.send_window.used,
+            delay: Duration::from_micros(self.queuing_delay.into()),
+            num_sent: self.num_sent,
+            num_lost: self.num_lost,
+            num_resent: self.num_resent,
         }
     }
 
     // NOTE: You must call this method whenever you receive a packet.
     pub(super) fn update_send_delay(&mut self, header: &PacketHeader, recv_at: Timestamp) {
         self.send_delay = timestamp::as_micros_u32(recv_at).wrapping_sub(header.send_at);
+        let drift = self.clock_skew.push(recv_at, self.send_delay);
+        self.delay_window.correct_skew(drift);
         // BEP 29 specifies that we should ignore `header.send_delay` when it is 0, indicating that
         // the socket is newly opened.
         if header.send_delay != 0 {
@@ -67,26 +119,71 @@ impl State {
             / to_f64(cmp::max(self.send_window.used, self.send_window.size_limit));
 
         let target = f64::from(
-            u32::try_from(crate::congestion_control_target().as_micros() % (1 << u32::BITS))
+            u32::try_from(self.config.congestion_control_target.as_micros() % (1 << u32::BITS))
                 .unwrap(),
         );
-        let off_target = target - f64::from(self.delay_window.subtract_min_delay(send_delay));
+        self.queuing_delay = self.delay_window.subtract_min_delay(send_delay);
+        let off_target = target - f64::from(self.queuing_delay);
         let delay_factor = off_target / target;
 
         // Again, BEP 29 does not explicitly specify this, but it appears that libutp restricts the
         // range of `scale_gain`.
-        let scale_gain_limit = to_f64(*crate::max_congestion_window_increase_per_rtt());
+        let scale_gain_limit = to_f64(self.config.max_congestion_window_increase_per_rtt);
         let scale_gain = (scale_gain_limit * delay_factor * window_factor)
             .clamp(-scale_gain_limit, scale_gain_limit);
         let scale_gain = unsafe { scale_gain.to_int_unchecked::<isize>() };
 
-        self.send_window.set_size_limit(
+        self.set_size_limit(
             self.send_window
                 .size_limit
                 .saturating_add_signed(scale_gain),
         );
     }
 
+    /// Records a CE-marked packet, which the next ack echoes to the peer.
+    pub(super) fn recv_ce(&mut self) {
+        if self.ecn {
+            self.num_ce_recv = self.num_ce_recv.wrapping_add(1);
+        }
+    }
+
+    /// Halves `send_window.size_limit` when the peer echoes new CE marks, and returns true if it
+    /// does.
+    ///
+    /// Following RFC 3168, we treat CE marks as packet losses, but we react to them at most once
+    /// per RTT.
+    pub(super) fn apply_ecn_echo(&mut self, ecn_echo: Option<u32>, recv_at: Timestamp) -> bool {
+        let Some(ecn_echo) = ecn_echo else {
+            return false;
+        };
+        if !self.ecn {
+            return false;
+        }
+        // The echo is a wrapping counter; ignore stale echoes from reordered acks.
+        let delta = ecn_echo.wrapping_sub(self.num_ce_echoed);
+        if delta == 0 || delta > u32::MAX / 2 {
+            return false;
+        }
+        self.num_ce_echoed = ecn_echo;
+        if self.ce_reduced_at.is_some_and(|reduced_at| {
+            recv_at.saturating_sub(reduced_at) < self.send_window.rtt.average
+        }) {
+            return false;
+        }
+        self.ce_reduced_at = Some(recv_at);
+        let new_size_limit = self.send_window.size_limit / 2;
+        self.set_size_limit(new_size_limit);
+        true
+    }
+
+    /// Sets the congestion window, clamped to the bounds of the config.
+    pub(super) fn set_size_limit(&mut self, size_limit: usize) {
+        self.send_window.set_size_limit(size_limit.clamp(
+            self.config.min_congestion_window,
+            self.config.max_congestion_window,
+        ));
+    }
+
     pub(super) fn set_packet_size(&mut self, packet_size: usize) {
         self.packet_size = cmp::max(packet_size, MIN_PACKET_SIZE);
     }
@@ -109,6 +206,7 @@ impl State {
         // It is okay to call `Bytes::clone` because it shares the underlying buffer and,
         // therefore, is very cheap.
         let seq = self.send_window.push(payload.clone());
+        self.num_sent += 1;
         Some(self.new_data_packet(seq, payload))
     }
 
@@ -117,7 +215,7 @@ impl State {
             return Ok(None);
         };
         ensure!(
-            inflight.num_resends < *crate::resend_limit(),
+            inflight.num_resends < self.config.resend_limit,
             ResendLimitExceededSnafu { seq },
         );
         // It is okay to call `Bytes::clone` because it shares the underlying buffer and,
@@ -127,6 +225,8 @@ impl State {
         // for now.
         inflight.set_send_at(timestamp::now());
         inflight.increment_resend();
+        self.num_sent += 1;
+        self.num_resent += 1;
         Ok(Some(self.new_data_packet(seq, payload)))
     }
 
@@ -142,24 +242,28 @@ impl State {
 
     pub(super) fn new_ack_packet(&self) -> Packet {
         let (ack, selective_ack) = self.recv_window.selective_ack();
-        self.new_packet(
+        let mut packet = self.new_packet(
             PacketType::State,
             self.send_window.seq,
             ack,
             selective_ack,
             Bytes::new(),
-        )
+        );
+        self.set_ecn_echo(&mut packet);
+        packet
     }
 
     pub(super) fn new_fin_ack_packet(&self) -> Option<Packet> {
         if self.recv_window.is_completed() {
-            Some(self.new_packet(
+            let mut packet = self.new_packet(
                 PacketType::State,
                 self.send_window.seq,
                 self.recv_window.eof.unwrap(),
                 None,
                 Bytes::new(),
-            ))
+            );
+            self.set_ecn_echo(&mut packet);
+            Some(packet)
         } else {
             None
         }
@@ -167,10 +271,9 @@ impl State {
 
     pub(super) fn make_finish_packet(&mut self) -> Packet {
         // BEP 29 seems to suggest that seq is not post-incremented when making a finish packet,
-        // but libutp post-increments it.  Here, we mimic libutp.
-        //
-        // TODO: What is the seq if we send another finish packet?
-        let seq = self.send_window.next_seq();
+        // but libutp post-increments it.  Here, we mimic libutp.  When we resend the finish
+        // packet, we reuse its seq.
+        let seq = self.send_window.fin_seq();
         self.new_packet(
             PacketType::Finish,
             seq,
@@ -180,6 +283,20 @@ impl State {
         )
     }
 
+    /// Makes a copy of the finish packet if the peer has not acked it, or returns `None` if the
+    /// peer has acked it or we give up resending it.
+    pub(super) fn make_resend_finish_packet(&mut self) -> Option<Packet> {
+        let resend_limit = self.config.resend_limit;
+        let fin = self.send_window.unacked_fin_mut()?;
+        if fin.num_resends >= resend_limit {
+            tracing::debug!(seq = fin.seq, "give up resending finish packet");
+            fin.abandon();
+            return None;
+        }
+        fin.num_resends += 1;
+        Some(self.make_finish_packet())
+    }
+
     pub(super) fn new_reset_packet(&self) -> Packet {
         self.new_packet(
             PacketType::Reset,
@@ -190,6 +307,12 @@ impl State {
         )
     }
 
+    fn set_ecn_echo(&self, packet: &mut Packet) {
+        if self.ecn {
+            packet.set_ecn_echo(Some(self.num_ce_recv));
+        }
+    }
+
     fn new_packet(
         &self,
         packet_type: PacketType,
@@ -215,3 +338,65 @@ impl State {
 fn to_f64(x: usize) -> f64 {
     u32::try_from(x).unwrap().into()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_state() -> State {
+        let mut state = State::new(
+            0x1000,
+            0x1001,
+            RecvWindow::new(10, 0x100),
+            SendWindow::new(1000, 0x200),
+            150,
+            UtpConfig::default(),
+        );
+        state.ecn = true;
+        state.send_window.rtt.average = Duration::from_millis(100);
+        state
+    }
+
+    #[test]
+    fn recv_ce() {
+        let mut state = new_state();
+        assert_eq!(state.new_ack_packet().ecn_echo, Some(0));
+        state.recv_ce();
+        state.recv_ce();
+        assert_eq!(state.new_ack_packet().ecn_echo, Some(2));
+
+        state.ecn = false;
+        state.recv_ce();
+        assert_eq!(state.new_ack_packet().ecn_echo, None);
+    }
+
+    #[test]
+    fn apply_ecn_echo() {
+        let mut state = new_state();
+        let t0 = Duration::from_secs(1);
+
+        assert_eq!(state.apply_ecn_echo(None, t0), false);
+        assert_eq!(state.apply_ecn_echo(Some(0), t0), false);
+        assert_eq!(state.send_window.size_limit, 1000);
+
+        assert_eq!(state.apply_ecn_echo(Some(1), t0), true);
+        assert_eq!(state.send_window.size_limit, 500);
+
+        // At most once per RTT.
+        let t1 = t0 + Duration::from_millis(50);
+        assert_eq!(state.apply_ecn_echo(Some(2), t1), false);
+        assert_eq!(state.send_window.size_limit, 500);
+
+        // Stale echo.
+        let t2 = t0 + Duration::from_millis(200);
+        assert_eq!(state.apply_ecn_echo(Some(1), t2), false);
+        assert_eq!(state.send_window.size_limit, 500);
+
+        assert_eq!(state.apply_ecn_echo(Some(3), t2), true);
+        assert_eq!(state.send_window.size_limit, 250);
+
+        state.ecn = false;
+        assert_eq!(state.apply_ecn_echo(Some(4), t2 * 2), false);
+        assert_eq!(state.send_window.size_limit, 250);
+    }
+}
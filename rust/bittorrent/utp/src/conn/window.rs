@@ -42,6 +42,8 @@ pub(super) struct SendWindow {
     pub(super) rtt: Rtt,
 
     eof: bool,
+    /// The finish packet, which we resend until the peer acks it.
+    fin: Option<Fin>,
 }
 
 #[derive(Debug)]
@@ -55,6 +57,13 @@ pub(super) struct Inflight {
     pub(super) num_acks: usize,
 }
 
+#[derive(Debug)]
+pub(super) struct Fin {
+    pub(super) seq: u16,
+    pub(super) num_resends: usize,
+    acked: bool,
+}
+
 /// Measures the distance between two seqs.
 fn measure(origin_seq: u16, target_seq: u16) -> i32 {
     const N: i32 = 1 << u16::BITS;
@@ -234,11 +243,12 @@ impl SendWindow {
             last_num_acks: None,
             rtt: Rtt::new(),
             eof: false,
+            fin: None,
         }
     }
 
     pub(super) fn is_completed(&self) -> bool {
-        self.eof && self.inflights.is_empty()
+        self.eof && self.inflights.is_empty() && self.fin.as_ref().is_none_or(|fin| fin.acked)
     }
 
     pub(super) fn close(&mut self) {
@@ -313,6 +323,23 @@ impl SendWindow {
         self.search(seq).map(|i| &mut self.inflights[i])
     }
 
+    /// Returns the seq of the finish packet, allocating it on the first call.
+    pub(super) fn fin_seq(&mut self) -> u16 {
+        assert!(self.eof);
+        match &self.fin {
+            Some(fin) => fin.seq,
+            None => {
+                let seq = self.next_seq();
+                self.fin = Some(Fin::new(seq));
+                seq
+            }
+        }
+    }
+
+    pub(super) fn unacked_fin_mut(&mut self) -> Option<&mut Fin> {
+        self.fin.as_mut().filter(|fin| !fin.acked)
+    }
+
     // Externally, this should only be called by `State::make_finish_packet`.
     pub(super) fn next_seq(&mut self) -> u16 {
         let seq = self.seq;
@@ -334,6 +361,12 @@ impl SendWindow {
         selective_ack: &Option<SelectiveAck>,
         recv_at: Timestamp,
     ) {
+        if let Some(fin) = self.fin.as_mut() {
+            if measure(fin.seq, ack) >= 0 {
+                fin.acked = true;
+            }
+        }
+
         let seq0 = match self.inflights.front() {
             Some(inflight) => inflight.seq,
             None => return,
@@ -425,6 +458,21 @@ impl SendWindow {
     }
 }
 
+impl Fin {
+    fn new(seq: u16) -> Self {
+        Self {
+            seq,
+            num_resends: 0,
+            acked: false,
+        }
+    }
+
+    /// Stops resending the finish packet, as if the peer had acked it.
+    pub(super) fn abandon(&mut self) {
+        self.acked = true;
+    }
+}
+
 impl Inflight {
     fn new(seq: u16, payload: Bytes) -> Self {
         Self {
@@ -881,6 +929,32 @@ mod tests {
         assert_eq!(window.is_completed(), true);
     }
 
+    #[test]
+    fn send_window_fin() {
+        let mut window = SendWindow::new(0, 0);
+        assert_eq!(window.push(Bytes::new()), 0);
+        window.close();
+        assert_eq!(window.fin_seq(), 1);
+        assert_eq!(window.fin_seq(), 1);
+        assert_eq!(window.seq, 2);
+
+        window.recv_ack(0, &None, timestamp::now());
+        assert_eq!(window.remove(), true);
+        assert_eq!(window.is_completed(), false);
+        assert_eq!(window.unacked_fin_mut().unwrap().seq, 1);
+
+        window.recv_ack(1, &None, timestamp::now());
+        assert_eq!(window.is_completed(), true);
+        assert_eq!(window.unacked_fin_mut().is_none(), true);
+
+        let mut window = SendWindow::new(0, 0);
+        window.close();
+        assert_eq!(window.fin_seq(), 0);
+        assert_eq!(window.is_completed(), false);
+        window.unacked_fin_mut().unwrap().abandon();
+        assert_eq!(window.is_completed(), true);
+    }
+
     #[test]
     fn set_size() {
         let mut window = SendWindow::new(0, 0);
@@ -8,6 +8,8 @@ use snafu::prelude::*;
 pub enum Error {
     #[snafu(display("udp socket was closed"))]
     Closed,
+    #[snafu(display("utp connection id collision: {peer_endpoint:?}"))]
+    ConnIdCollision { peer_endpoint: SocketAddr },
     #[snafu(display("duplicated utp connection: {peer_endpoint:?}"))]
     Duplicated { peer_endpoint: SocketAddr },
     #[snafu(display("utp handshake error: {peer_endpoint:?}"))]
@@ -7,19 +7,32 @@
 pub mod error;
 
 mod bstream;
+mod config;
 mod conn;
 mod mtu;
 mod packet;
+mod rate;
 mod socket;
+mod stats;
+mod time_wait;
 mod timestamp;
 
 use std::time::Duration;
 
 pub use crate::bstream::{UtpRecvStream, UtpSendStream, UtpStream};
+pub use crate::config::UtpConfig;
+pub use crate::rate::RateLimit;
 pub use crate::socket::{UtpConnector, UtpListener, UtpSocket};
+pub use crate::stats::{UtpSocketStats, UtpStats};
 
-g1_param::define!(recv_window_size: usize = 65536);
-g1_param::define!(send_window_size_limit: usize = 65536);
+g1_param::define!(
+    recv_window_size: usize = 65536;
+    parse = g1_param::parse::byte_size;
+);
+g1_param::define!(
+    send_window_size_limit: usize = 65536;
+    parse = g1_param::parse::byte_size;
+);
 g1_param::define!(packet_size: usize = 150);
 
 g1_param::define!(
@@ -30,12 +43,42 @@ g1_param::define!(
     accept_timeout: Duration = Duration::from_secs(2);
     parse = g1_param::parse::duration;
 );
+g1_param::define!(
+    /// Delay before the connection id of a closed connection may be reused.
+    time_wait_period: Duration = Duration::from_secs(4);
+    parse = g1_param::parse::duration;
+);
 
 g1_param::define!(
     congestion_control_target: Duration = Duration::from_millis(100);
     parse = g1_param::parse::duration;
 );
 g1_param::define!(max_congestion_window_increase_per_rtt: usize = 3000);
+g1_param::define!(
+    min_congestion_window: usize = 150;
+    parse = g1_param::parse::byte_size;
+);
+g1_param::define!(
+    max_congestion_window: usize = 16 * 1024 * 1024;
+    parse = g1_param::parse::byte_size;
+);
+
+// RFC 6817 specifies keeping the minimum delays of the last 10 one-minute periods.
+g1_param::define!(
+    base_delay_period: Duration = Duration::from_secs(60);
+    parse = g1_param::parse::duration;
+);
+g1_param::define!(base_delay_history_size: usize = 10);
+g1_param::define!(
+    /// Upper bound of the clock skew correction per packet.
+    max_clock_skew_correction: Duration = Duration::from_millis(10);
+    parse = g1_param::parse::duration;
+);
+
+g1_param::define!(
+    /// Whether to negotiate Explicit Congestion Notification (RFC 3168) with peers.
+    ecn: bool = true
+);
 
 g1_param::define!(
     /// Upper bound of the RTT timeout.
@@ -66,6 +109,30 @@ g1_param::define!(
     resend_limit: usize = 2
 );
 
+g1_param::define!(
+    /// Interval of the keep-alive packets.
+    // libutp sends a keep-alive every 29 seconds, which is shorter than most NAT mapping timeouts.
+    keep_alive_interval: Option<Duration> = Some(Duration::from_secs(29));
+    parse = g1_param::parse::opt_duration;
+);
+g1_param::define!(
+    /// Timeout for receiving any packet (including the peer's keep-alives) on an established
+    /// connection.
+    idle_timeout: Option<Duration> = Some(Duration::from_secs(120));
+    parse = g1_param::parse::opt_duration;
+);
+
+g1_param::define!(
+    /// Socket-level limit on the payload sent, in bytes per second.
+    send_rate_limit: Option<u64> = None;
+    parse = g1_param::parse::opt_byte_size;
+);
+g1_param::define!(
+    /// Socket-level limit on the payload received, in bytes per second.
+    recv_rate_limit: Option<u64> = None;
+    parse = g1_param::parse::opt_byte_size;
+);
+
 g1_param::define!(path_mtu_queue_size: usize = 64);
 g1_param::define!(path_mtu_max_probe_size: usize = 2400);
 g1_param::define!(
@@ -32,6 +32,7 @@ pub(crate) enum Error {
 pub(crate) struct Packet {
     pub(crate) header: PacketHeader,
     pub(crate) selective_ack: Option<SelectiveAck>,
+    pub(crate) ecn_echo: Option<u32>,
     pub(crate) payload: Bytes,
 }
 
@@ -80,6 +81,9 @@ const EXTENSION_SELECTIVE_ACK: u8 = 1;
 const EXTENSION_DEPRECATED: u8 = 2;
 // Unofficial extension from libtorrent.
 const EXTENSION_CLOSE_REASON: u8 = 3;
+// Our own extension, which carries the number of CE-marked packets received.  Peers that do not
+// recognize it skip it, as we do above.
+const EXTENSION_ECN_ECHO: u8 = 4;
 
 impl TryFrom<Bytes> for Packet {
     type Error = Error;
@@ -99,6 +103,7 @@ impl TryFrom<Bytes> for Packet {
         ensure!(version == VERSION, ExpectVersionSnafu { version });
 
         let mut selective_ack = None;
+        let mut ecn_echo = None;
         let mut extension = header.extension;
         while extension != EXTENSION_NONE {
             let (data, next) = decode_extension(&mut buffer)?;
@@ -143,6 +148,19 @@ impl TryFrom<Bytes> for Packet {
                     let close_reason = (&data[2..4]).get_u16();
                     tracing::debug!(close_reason);
                 }
+                EXTENSION_ECN_ECHO => {
+                    ensure!(ecn_echo.is_none(), DuplicatedExtensionSnafu { extension });
+                    let expect = 4;
+                    ensure!(
+                        size == expect,
+                        ExpectExtensionSizeSnafu {
+                            extension,
+                            size,
+                            expect,
+                        },
+                    );
+                    ecn_echo = Some(data.as_ref().get_u32());
+                }
                 _ => {
                     tracing::warn!(extension, ?data, "unknown extension");
                 }
@@ -153,6 +171,7 @@ impl TryFrom<Bytes> for Packet {
         Ok(Self {
             header,
             selective_ack,
+            ecn_echo,
             payload: buffer,
         })
     }
@@ -198,10 +217,20 @@ impl Packet {
                 ack,
             },
             selective_ack,
+            ecn_echo: None,
             payload,
         }
     }
 
+    pub(crate) fn set_ecn_echo(&mut self, ecn_echo: Option<u32>) {
+        self.ecn_echo = ecn_echo;
+        self.header.extension = match (&self.selective_ack, ecn_echo) {
+            (Some(_), _) => EXTENSION_SELECTIVE_ACK,
+            (None, Some(_)) => EXTENSION_ECN_ECHO,
+            (None, None) => EXTENSION_NONE,
+        };
+    }
+
     pub(crate) fn size(&self) -> usize {
         PacketHeader::SIZE
             + self
@@ -209,6 +238,7 @@ impl Packet {
                 .as_ref()
                 .map(|SelectiveAck(bitmask)| 2 + bitmask.len())
                 .unwrap_or(0)
+            + self.ecn_echo.map_or(0, |_| 2 + 4)
             + self.payload.len()
     }
 
@@ -218,10 +248,18 @@ impl Packet {
     {
         buffer.put_packet_header(&self.header);
         if let Some(SelectiveAck(bitmask)) = &self.selective_ack {
-            buffer.put_u8(EXTENSION_NONE);
+            buffer.put_u8(match self.ecn_echo {
+                Some(_) => EXTENSION_ECN_ECHO,
+                None => EXTENSION_NONE,
+            });
             buffer.put_u8(bitmask.len().try_into().unwrap());
             buffer.put_slice(bitmask);
         }
+        if let Some(ecn_echo) = self.ecn_echo {
+            buffer.put_u8(EXTENSION_NONE);
+            buffer.put_u8(4);
+            buffer.put_u32(ecn_echo);
+        }
         buffer.put_slice(&self.payload);
     }
 }
@@ -323,7 +361,37 @@ mod tests {
             "00 04 00000000"
             "deadbeef"
         ));
-        assert_eq!(Packet::try_from(bytes), Ok(packet));
+        assert_eq!(Packet::try_from(bytes), Ok(packet.clone()));
+
+        let mut packet = packet;
+        packet.set_ecn_echo(Some(0x11223344));
+        test(
+            packet.clone(),
+            &hex!(
+                "41 01 1234 01020304 05060708 090a0b0c 5678 9abc"
+                "04 04 01020304"
+                "00 04 11223344"
+                "deadbeef"
+            ),
+        );
+        packet.selective_ack = None;
+        packet.set_ecn_echo(Some(0x11223344));
+        test(
+            packet.clone(),
+            &hex!(
+                "41 04 1234 01020304 05060708 090a0b0c 5678 9abc"
+                "00 04 11223344"
+                "deadbeef"
+            ),
+        );
+        packet.set_ecn_echo(None);
+        test(
+            packet,
+            &hex!(
+                "41 00 1234 01020304 05060708 090a0b0c 5678 9abc"
+                "deadbeef"
+            ),
+        );
     }
 
     #[test]
@@ -370,9 +438,22 @@ mod tests {
             },
         );
 
+        test(
+            &hex!("01 04 0001 00000002 00000003 00000004 0005 0006 00 01 ff"),
+            Error::ExpectExtensionSize {
+                extension: 4,
+                size: 1,
+                expect: 4,
+            },
+        );
+
         test(
             &hex!("01 01 0001 00000002 00000003 00000004 0005 0006 01 04 01020304 00 00"),
             Error::DuplicatedExtension { extension: 1 },
         );
+        test(
+            &hex!("01 04 0001 00000002 00000003 00000004 0005 0006 04 04 01020304 00 04 01020304"),
+            Error::DuplicatedExtension { extension: 4 },
+        );
     }
 }
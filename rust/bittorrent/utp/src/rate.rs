@@ -0,0 +1,155 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::time::{self, Instant};
+
+use g1_base::sync::MutexExt;
+
+// Waiters re-check the limit at least this often so that they notice rate changes.
+const MAX_WAIT: Duration = Duration::from_millis(100);
+
+/// Token-bucket rate limit in bytes per second.
+///
+/// It is a handle; clones share the same bucket, and changing the rate takes effect immediately,
+/// including for streams that are waiting on it.
+#[derive(Clone, Debug)]
+pub struct RateLimit(Arc<Mutex<State>>);
+
+/// Socket-level rate limits, which are shared by all streams of the socket.
+#[derive(Clone, Debug)]
+pub(crate) struct RateLimits {
+    pub(crate) send: RateLimit,
+    pub(crate) recv: RateLimit,
+}
+
+#[derive(Debug)]
+struct State {
+    rate: Option<u64>,
+    // It may go negative so that a payload larger than the bucket can still pass.
+    n: f64,
+    last_fill: Instant,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+impl RateLimit {
+    /// Creates a rate limit, where `None` means unlimited.
+    pub fn new(rate: Option<u64>) -> Self {
+        let this = Self(Arc::new(Mutex::new(State {
+            rate: None,
+            n: 0.0,
+            last_fill: Instant::now(),
+        })));
+        this.set_rate(rate);
+        this
+    }
+
+    pub fn rate(&self) -> Option<u64> {
+        self.0.must_lock().rate
+    }
+
+    pub fn set_rate(&self, rate: Option<u64>) {
+        let rate = rate.filter(|rate| *rate > 0);
+        let mut state = self.0.must_lock();
+        if state.rate != rate {
+            state.rate = rate;
+            state.n = 0.0;
+            state.last_fill = Instant::now();
+        }
+    }
+
+    pub(crate) async fn acquire(&self, n: usize) {
+        while let Some(wait) = self.try_acquire(n) {
+            time::sleep(wait.min(MAX_WAIT)).await;
+        }
+    }
+
+    /// Returns how long the caller should wait before retrying.
+    fn try_acquire(&self, n: usize) -> Option<Duration> {
+        let mut state = self.0.must_lock();
+        let rate = state.rate? as f64;
+        let now = Instant::now();
+        let t = now.duration_since(state.last_fill).as_secs_f64();
+        // The bucket size is one second's worth of tokens.
+        state.n = (state.n + rate * t).min(rate);
+        state.last_fill = now;
+        if state.n >= 0.0 {
+            state.n -= n as f64;
+            None
+        } else {
+            Some(Duration::from_secs_f64(-state.n / rate))
+        }
+    }
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            send: RateLimit::new(*crate::send_rate_limit()),
+            recv: RateLimit::new(*crate::recv_rate_limit()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `tokio::time::sleep` rounds up to milliseconds.
+    fn assert_elapsed(t0: Instant, millis: u64) {
+        let elapsed = t0.elapsed();
+        let expect = Duration::from_millis(millis);
+        assert!(
+            expect <= elapsed && elapsed <= expect + Duration::from_millis(5),
+            "expect {expect:?}: {elapsed:?}",
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn acquire() {
+        let limit = RateLimit::new(None);
+        let t0 = Instant::now();
+        limit.acquire(1000000).await;
+        assert_elapsed(t0, 0);
+
+        limit.set_rate(Some(100));
+        let t0 = Instant::now();
+        limit.acquire(250).await;
+        assert_elapsed(t0, 0);
+        limit.acquire(10).await;
+        assert_elapsed(t0, 2500);
+        limit.acquire(10).await;
+        assert_elapsed(t0, 2600);
+
+        // The bucket is capped at one second's worth of tokens.
+        time::advance(Duration::from_secs(10)).await;
+        let t0 = Instant::now();
+        limit.acquire(110).await;
+        assert_elapsed(t0, 0);
+        limit.acquire(1).await;
+        assert_elapsed(t0, 100);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn set_rate() {
+        let limit = RateLimit::new(Some(0));
+        assert_eq!(limit.rate(), None);
+
+        limit.set_rate(Some(100));
+        assert_eq!(limit.clone().rate(), Some(100));
+        limit.acquire(1000).await;
+
+        // A waiter notices the rate change.
+        let t0 = Instant::now();
+        let waiter = limit.clone();
+        tokio::join!(waiter.acquire(1), async {
+            time::sleep(Duration::from_millis(950)).await;
+            limit.set_rate(None);
+        });
+        assert_elapsed(t0, 1000);
+    }
+}
@@ -1,11 +1,13 @@
 use std::collections::HashMap;
 use std::io::{Error, ErrorKind};
+use std::iter;
 use std::net::SocketAddr;
 use std::panic;
 use std::sync::Arc;
 
 use bytes::{Bytes, BytesMut};
 use futures::{
+    future,
     sink::{Sink, SinkExt},
     stream::{Stream, TryStreamExt},
 };
@@ -16,16 +18,22 @@ use tokio::{
 };
 
 use g1_tokio::{
+    net::udp::Ecn,
     sync::mpmc,
     task::{Cancel, JoinGuard, JoinQueue},
 };
 
 use crate::bstream::UtpStream;
+use crate::config::UtpConfig;
 use crate::conn::{
     self, ConnectedRecv, Connection, Handshake, Incoming, OutgoingRecv, OutgoingSend,
 };
 use crate::error;
 use crate::mtu::{self, PathMtuProber, PathMtuProberGuard};
+use crate::packet::{Packet, PacketType};
+use crate::rate::{RateLimit, RateLimits};
+use crate::stats::{SocketCounters, UtpSocketStats};
+use crate::time_wait::TimeWait;
 use crate::timestamp;
 
 #[derive(Debug)]
@@ -35,6 +43,9 @@ pub struct UtpSocket {
     connect_send: ConnectSend,
     accept_recv: AcceptRecv,
 
+    counters: Arc<SocketCounters>,
+    rate_limits: RateLimits,
+
     guard: JoinGuard<Result<(), Error>>,
 }
 
@@ -65,6 +76,12 @@ struct Actor<UdpStream, UdpSink> {
     tasks: JoinQueue<Result<(), conn::Error>>,
     peer_endpoints: HashMap<Id, SocketAddr>,
     stubs: HashMap<SocketAddr, Connection>,
+    // Connection ids of the packets that each connection receives.
+    recv_ids: HashMap<SocketAddr, u16>,
+    time_wait: TimeWait,
+    config: UtpConfig,
+    counters: Arc<SocketCounters>,
+    rate_limits: RateLimits,
     outgoing_recv: OutgoingRecv,
     outgoing_send: OutgoingSend,
 
@@ -75,6 +92,9 @@ struct Actor<UdpStream, UdpSink> {
 g1_param::define!(connect_queue_size: usize = 64);
 g1_param::define!(accept_queue_size: usize = 64);
 
+// Number of tries to pick a connection id that does not collide with those in `TimeWait`.
+const NUM_CONN_ID_TRIES: usize = 8;
+
 type Connect = (SocketAddr, oneshot::Sender<Result<UtpStream, Error>>);
 type ConnectRecv = mpsc::Receiver<Connect>;
 type ConnectSend = mpsc::Sender<Connect>;
@@ -83,23 +103,71 @@ type AcceptRecv = mpmc::Receiver<UtpStream>;
 type AcceptSend = mpmc::Sender<UtpStream>;
 
 impl UtpSocket {
-    pub fn new<UdpStream, UdpSink>(socket: Arc<UdpSocket>, stream: UdpStream, sink: UdpSink) -> Self
+    pub fn new<UdpStream, UdpSink>(
+        socket: Arc<UdpSocket>,
+        stream: UdpStream,
+        sink: UdpSink,
+        config: UtpConfig,
+    ) -> Self
     where
         UdpStream: Stream<Item = Result<(SocketAddr, Bytes), Error>> + Send + Unpin + 'static,
         UdpSink: Sink<(SocketAddr, Bytes), Error = Error> + Send + Unpin + 'static,
     {
+        let stream =
+            stream.map_ok(|(peer_endpoint, payload)| (peer_endpoint, payload, Ecn::NotEct));
+        let sink = sink.with(|(peer_endpoint, payload, _): (SocketAddr, Bytes, Ecn)| {
+            future::ready(Ok((peer_endpoint, payload)))
+        });
+        // We cannot read the ECN codepoints of the datagrams.
+        let config = UtpConfig {
+            ecn: false,
+            ..config
+        };
+        Self::with_ecn(socket, stream, sink, config)
+    }
+
+    /// Creates a socket over datagrams that carry their ECN codepoints, such as those of
+    /// `g1_tokio::net::udp::UdpSocket::with_ecn`.
+    pub fn with_ecn<UdpStream, UdpSink>(
+        socket: Arc<UdpSocket>,
+        stream: UdpStream,
+        sink: UdpSink,
+        config: UtpConfig,
+    ) -> Self
+    where
+        UdpStream: Stream<Item = Result<(SocketAddr, Bytes, Ecn), Error>> + Send + Unpin + 'static,
+        UdpSink: Sink<(SocketAddr, Bytes, Ecn), Error = Error> + Send + Unpin + 'static,
+    {
+        assert!(config.min_congestion_window <= config.max_congestion_window);
         let (connect_send, connect_recv) = mpsc::channel(*connect_queue_size());
         let (accept_send, accept_recv) = mpmc::channel(*accept_queue_size());
+        let counters = Arc::new(SocketCounters::default());
+        let rate_limits = RateLimits::default();
         let guard = {
             let socket = socket.clone();
+            let counters = counters.clone();
+            let rate_limits = rate_limits.clone();
             JoinGuard::spawn(move |cancel| {
-                Actor::new(cancel, socket, stream, sink, connect_recv, accept_send).run()
+                Actor::new(
+                    cancel,
+                    socket,
+                    stream,
+                    sink,
+                    connect_recv,
+                    accept_send,
+                    config,
+                    counters,
+                    rate_limits,
+                )
+                .run()
             })
         };
         Self {
             socket,
             connect_send,
             accept_recv,
+            counters,
+            rate_limits,
             guard,
         }
     }
@@ -116,6 +184,21 @@ impl UtpSocket {
         UtpListener::new(self.socket.clone(), self.accept_recv.clone())
     }
 
+    /// Returns the counters aggregated over the connections of the socket.
+    pub fn stats(&self) -> UtpSocketStats {
+        self.counters.snapshot()
+    }
+
+    /// Returns the socket-level limit on the payload sent, which is shared by all streams.
+    pub fn send_rate_limit(&self) -> &RateLimit {
+        &self.rate_limits.send
+    }
+
+    /// Returns the socket-level limit on the payload received, which is shared by all streams.
+    pub fn recv_rate_limit(&self) -> &RateLimit {
+        &self.rate_limits.recv
+    }
+
     pub async fn join(&mut self) {
         self.guard.join().await
     }
@@ -172,9 +255,10 @@ impl UtpListener {
 
 impl<UdpStream, UdpSink> Actor<UdpStream, UdpSink>
 where
-    UdpStream: Stream<Item = Result<(SocketAddr, Bytes), Error>> + Unpin + 'static,
-    UdpSink: Sink<(SocketAddr, Bytes), Error = Error> + Unpin + 'static,
+    UdpStream: Stream<Item = Result<(SocketAddr, Bytes, Ecn), Error>> + Unpin + 'static,
+    UdpSink: Sink<(SocketAddr, Bytes, Ecn), Error = Error> + Unpin + 'static,
 {
+    #[allow(clippy::too_many_arguments)]
     fn new(
         cancel: Cancel,
         socket: Arc<UdpSocket>,
@@ -182,6 +266,9 @@ where
         sink: UdpSink,
         connect_recv: ConnectRecv,
         accept_send: AcceptSend,
+        config: UtpConfig,
+        counters: Arc<SocketCounters>,
+        rate_limits: RateLimits,
     ) -> Self {
         let (outgoing_recv, outgoing_send) = conn::new_outgoing_queue();
 
@@ -199,6 +286,11 @@ where
             tasks: JoinQueue::with_cancel(cancel),
             peer_endpoints: HashMap::new(),
             stubs: HashMap::new(),
+            recv_ids: HashMap::new(),
+            time_wait: TimeWait::new(),
+            config,
+            counters,
+            rate_limits,
             outgoing_recv,
             outgoing_send,
             prober,
@@ -227,17 +319,17 @@ where
                     }
                     incoming = self.stream.try_next() => {
                         let recv_at = timestamp::now();
-                        let (peer_endpoint, payload) = incoming?.ok_or_else(|| {
+                        let (peer_endpoint, payload, ecn) = incoming?.ok_or_else(|| {
                             Error::new(ErrorKind::UnexpectedEof, error::Error::Closed)
                         })?;
-                        self.handle_incoming(peer_endpoint, (payload, recv_at));
+                        self.handle_incoming(peer_endpoint, (payload, recv_at, ecn));
                     }
                     outgoing = self.outgoing_recv.recv() => {
-                        let (peer_endpoint, mut packet) = outgoing.unwrap();
+                        let (peer_endpoint, mut packet, ecn) = outgoing.unwrap();
                         let mut buffer = BytesMut::with_capacity(packet.size());
                         packet.header.set_send_at(timestamp::now());
                         packet.encode(&mut buffer);
-                        self.sink.send((peer_endpoint, buffer.freeze())).await?;
+                        self.sink.send((peer_endpoint, buffer.freeze(), ecn)).await?;
                     }
                     path_mtu = self.prober.path_mtu_recv.recv() => {
                         let Some((peer_endpoint, path_mtu)) = path_mtu else { break };
@@ -293,7 +385,19 @@ where
             )));
             return;
         }
-        let (stream, connected_recv) = self.spawn(peer_endpoint, Handshake::new_connect);
+        let Some(handshake) = iter::repeat_with(|| Handshake::new_connect(self.config))
+            .take(NUM_CONN_ID_TRIES)
+            .find(|handshake| !self.time_wait.contains(peer_endpoint, handshake.recv_id()))
+        else {
+            let _ = result_send.send(Err(Error::new(
+                ErrorKind::AddrInUse,
+                error::Error::ConnIdCollision { peer_endpoint },
+            )));
+            return;
+        };
+        let recv_id = handshake.recv_id();
+        let (stream, connected_recv) = self.spawn(peer_endpoint, handshake);
+        self.recv_ids.insert(peer_endpoint, recv_id);
         tokio::spawn(Self::handle_connected(
             peer_endpoint,
             stream,
@@ -331,8 +435,12 @@ where
     }
 
     #[tracing::instrument("utp/accept", fields(?peer_endpoint), skip_all)]
-    fn handle_accept(&mut self, peer_endpoint: SocketAddr) {
-        let (stream, connected_recv) = self.spawn(peer_endpoint, Handshake::new_accept);
+    fn handle_accept(&mut self, peer_endpoint: SocketAddr, recv_id: Option<u16>) {
+        let (stream, connected_recv) =
+            self.spawn(peer_endpoint, Handshake::new_accept(self.config));
+        if let Some(recv_id) = recv_id {
+            self.recv_ids.insert(peer_endpoint, recv_id);
+        }
         tokio::spawn(Self::handle_accepted(
             peer_endpoint,
             stream,
@@ -369,7 +477,25 @@ where
 
     fn handle_incoming(&mut self, peer_endpoint: SocketAddr, incoming: Incoming) {
         if !self.stubs.contains_key(&peer_endpoint) {
-            self.handle_accept(peer_endpoint);
+            // We let the connection actor handle invalid packets.
+            let header = Packet::try_from(incoming.0.clone())
+                .ok()
+                .map(|packet| packet.header);
+            if let Some(header) = &header {
+                if self.time_wait.contains(peer_endpoint, header.conn_id) {
+                    tracing::debug!(
+                        ?peer_endpoint,
+                        conn_id = header.conn_id,
+                        "drop packet of closed utp connection",
+                    );
+                    return;
+                }
+            }
+            // When accepting, our recv id is the peer's synchronize conn id + 1.
+            let recv_id = header
+                .filter(|header| header.packet_type() == PacketType::Synchronize)
+                .map(|header| header.conn_id.wrapping_add(1));
+            self.handle_accept(peer_endpoint, recv_id);
         }
 
         let span = tracing::info_span!("utp/incoming", ?peer_endpoint);
@@ -387,16 +513,19 @@ where
     fn spawn(
         &mut self,
         peer_endpoint: SocketAddr,
-        new_handshake: fn() -> Handshake,
+        handshake: Handshake,
     ) -> (UtpStream, ConnectedRecv) {
         let (connected_send, connected_recv) = oneshot::channel();
         let (stub, guard, stream) = conn::Connection::spawn(
-            new_handshake(),
+            handshake,
             self.socket.clone(),
             peer_endpoint,
             connected_send,
             self.outgoing_send.clone(),
+            self.counters.clone(),
+            &self.rate_limits,
         );
+        self.counters.connect();
         let id = guard.id();
         self.tasks.push(guard).unwrap();
         assert!(self.peer_endpoints.insert(id, peer_endpoint).is_none());
@@ -405,7 +534,12 @@ where
     }
 
     fn remove(&mut self, peer_endpoint: SocketAddr) {
-        self.stubs.remove(&peer_endpoint);
+        if self.stubs.remove(&peer_endpoint).is_some() {
+            self.counters.disconnect();
+        }
+        if let Some(recv_id) = self.recv_ids.remove(&peer_endpoint) {
+            self.time_wait.insert(peer_endpoint, recv_id);
+        }
     }
 }
 
@@ -419,6 +553,11 @@ fn handle_conn_result(
         Ok(Err(error)) => {
             if error == conn::Error::ConnectTimeout {
                 tracing::debug!(?peer_endpoint, "utp connect timeout");
+            } else if matches!(
+                error,
+                conn::Error::ConnectionRefused | conn::Error::ConnectionReset,
+            ) {
+                tracing::debug!(?peer_endpoint, %error, "utp connection reset by peer");
             } else {
                 tracing::warn!(?peer_endpoint, %error, "utp connection error");
             }
@@ -427,3 +566,94 @@ fn handle_conn_result(
     }
     peer_endpoint
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bytes::BufMut;
+
+    use g1_test::faults::{FaultConfig, Faults, FaultyStream};
+    use g1_tokio::bstream::{StreamBuffer, StreamRecv, StreamSend};
+    use g1_tokio::net::udp;
+
+    use super::*;
+
+    async fn new_utp_socket(faults: &Faults) -> (UtpSocket, SocketAddr) {
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let endpoint = socket.local_addr().unwrap();
+        let (stream, sink) = udp::UdpSocket::new(socket.clone()).into_split();
+        let stream = FaultyStream::new(stream, faults.clone());
+        (
+            UtpSocket::new(socket, stream, sink, UtpConfig::default()),
+            endpoint,
+        )
+    }
+
+    #[tokio::test]
+    async fn faulty_network() {
+        let faults = Faults::new(0);
+        let (mut socket_a, endpoint_a) = new_utp_socket(&faults).await;
+        let (mut socket_b, _) = new_utp_socket(&faults).await;
+
+        let (listener_a, connector_b) = (socket_a.listener(), socket_b.connector());
+        let (stream_a, stream_b) =
+            tokio::join!(listener_a.accept(), connector_b.connect(endpoint_a));
+        let (mut stream_a, mut stream_b) = (stream_a.unwrap(), stream_b.unwrap());
+
+        // uTP relies on the UDP checksum, and so we do not inject corruption here.
+        faults.set(FaultConfig {
+            latency: Duration::from_millis(1),
+            reorder_rate: 0.2,
+            ..Default::default()
+        });
+
+        let data = (0..65536).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        stream_b.send_buffer().put_slice(&data);
+        let (send_result, recv_result) =
+            tokio::join!(stream_b.send_all(), stream_a.recv_fill(data.len()));
+        send_result.unwrap();
+        recv_result.unwrap();
+        assert_eq!(stream_a.recv_buffer().as_ref(), data.as_slice());
+        assert!(faults.stats().num_reordered > 0);
+
+        let stats = stream_b.stats();
+        assert!(stats.num_sent > 0);
+        assert!(stats.rtt > Duration::ZERO);
+        assert_eq!(socket_a.stats().num_connections, 1);
+        assert_eq!(socket_b.stats().num_connections, 1);
+        assert!(socket_b.stats().num_sent >= stats.num_sent);
+
+        faults.heal();
+        stream_b.shutdown().await.unwrap();
+        drop(stream_a);
+        drop(stream_b);
+        socket_a.shutdown().await.unwrap();
+        socket_b.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn lossy_network() {
+        let faults = Faults::new(0);
+        faults.set(FaultConfig {
+            drop_rate: 1.0,
+            ..Default::default()
+        });
+        let (mut socket_a, endpoint_a) = new_utp_socket(&faults).await;
+        let (mut socket_b, _) = new_utp_socket(&faults).await;
+
+        assert_eq!(
+            socket_b
+                .connector()
+                .connect(endpoint_a)
+                .await
+                .unwrap_err()
+                .kind(),
+            ErrorKind::TimedOut,
+        );
+        assert!(faults.stats().num_dropped > 0);
+
+        socket_a.shutdown().await.unwrap();
+        socket_b.shutdown().await.unwrap();
+    }
+}
@@ -0,0 +1,115 @@
+//! Connection Statistics
+//!
+//! NOTE: We do not have a metrics subsystem yet.  For now, the counters aggregated over the
+//! connections are exposed through `UtpSocket::stats`, and it is up to the caller to export them.
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use tokio::sync::watch;
+
+/// Snapshot of the congestion control state and the counters of a connection.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct UtpStats {
+    /// Smoothed RTT estimate.
+    pub rtt: Duration,
+    pub rtt_var: Duration,
+    pub rtt_timeout: Duration,
+    /// Congestion window, which is the send window size limit tuned by congestion control.
+    pub cwnd: usize,
+    /// Payload bytes that are sent but not acked yet.
+    pub bytes_in_flight: usize,
+    /// Queuing delay of our packets, which is the delay measured by the peer minus the base delay.
+    pub delay: Duration,
+
+    /// Number of data packets sent, including resends.
+    pub num_sent: u64,
+    /// Number of data packets deemed lost, either by duplicated acks or by RTT timeout.
+    pub num_lost: u64,
+    pub num_resent: u64,
+}
+
+/// Counters aggregated over the connections of a `UtpSocket`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct UtpSocketStats {
+    pub num_connections: usize,
+    pub num_sent: u64,
+    pub num_lost: u64,
+    pub num_resent: u64,
+}
+
+pub(crate) type StatsRecv = watch::Receiver<UtpStats>;
+pub(crate) type StatsSend = watch::Sender<UtpStats>;
+
+#[derive(Debug, Default)]
+pub(crate) struct SocketCounters {
+    num_connections: AtomicUsize,
+    num_sent: AtomicU64,
+    num_lost: AtomicU64,
+    num_resent: AtomicU64,
+}
+
+impl SocketCounters {
+    pub(crate) fn connect(&self) {
+        self.num_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn disconnect(&self) {
+        self.num_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Adds the increments of the counters of a connection from `old` to `new`.
+    pub(crate) fn add(&self, old: &UtpStats, new: &UtpStats) {
+        self.num_sent
+            .fetch_add(new.num_sent - old.num_sent, Ordering::Relaxed);
+        self.num_lost
+            .fetch_add(new.num_lost - old.num_lost, Ordering::Relaxed);
+        self.num_resent
+            .fetch_add(new.num_resent - old.num_resent, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> UtpSocketStats {
+        UtpSocketStats {
+            num_connections: self.num_connections.load(Ordering::Relaxed),
+            num_sent: self.num_sent.load(Ordering::Relaxed),
+            num_lost: self.num_lost.load(Ordering::Relaxed),
+            num_resent: self.num_resent.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(num_sent: u64, num_lost: u64, num_resent: u64) -> UtpStats {
+        UtpStats {
+            num_sent,
+            num_lost,
+            num_resent,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn socket_counters() {
+        let counters = SocketCounters::default();
+        assert_eq!(counters.snapshot(), UtpSocketStats::default());
+
+        counters.connect();
+        counters.connect();
+        counters.add(&stats(0, 0, 0), &stats(3, 1, 1));
+        counters.add(&stats(3, 1, 1), &stats(5, 1, 2));
+        counters.add(&stats(0, 0, 0), &stats(4, 0, 0));
+        counters.disconnect();
+        assert_eq!(
+            counters.snapshot(),
+            UtpSocketStats {
+                num_connections: 1,
+                num_sent: 9,
+                num_lost: 1,
+                num_resent: 2,
+            },
+        );
+    }
+}
@@ -0,0 +1,97 @@
+//! TIME_WAIT-style Connection Id Reuse Delay
+//!
+//! After a connection is closed, the peer may still send us packets of that connection (e.g., a
+//! retransmitted data packet or the peer's reset).  We remember the connection id for a while so
+//! that `UtpSocket` drops these stragglers instead of mistaking them for a new incoming
+//! connection, and so that a new outgoing connection to the same peer does not reuse the id.
+
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+
+use tokio::time::Instant;
+
+#[derive(Debug)]
+pub(crate) struct TimeWait {
+    // Since the reuse delay is a constant, the queue is ordered by deadline.
+    queue: VecDeque<(Instant, SocketAddr, u16)>,
+    // Number of queue entries of each (peer endpoint, connection id) pair.
+    conn_ids: HashMap<(SocketAddr, u16), usize>,
+}
+
+impl TimeWait {
+    pub(crate) fn new() -> Self {
+        Self {
+            queue: VecDeque::new(),
+            conn_ids: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn insert(&mut self, peer_endpoint: SocketAddr, conn_id: u16) {
+        let now = Instant::now();
+        self.expire(now);
+        self.queue
+            .push_back((now + *crate::time_wait_period(), peer_endpoint, conn_id));
+        *self.conn_ids.entry((peer_endpoint, conn_id)).or_default() += 1;
+    }
+
+    pub(crate) fn contains(&mut self, peer_endpoint: SocketAddr, conn_id: u16) -> bool {
+        self.expire(Instant::now());
+        self.conn_ids.contains_key(&(peer_endpoint, conn_id))
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while let Some((deadline, peer_endpoint, conn_id)) = self.queue.front().copied() {
+            if deadline > now {
+                break;
+            }
+            self.queue.pop_front();
+            let key = (peer_endpoint, conn_id);
+            let count = self.conn_ids.get_mut(&key).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                self.conn_ids.remove(&key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time;
+
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn time_wait() {
+        let e1: SocketAddr = "127.0.0.1:8001".parse().unwrap();
+        let e2: SocketAddr = "127.0.0.1:8002".parse().unwrap();
+        let period = *crate::time_wait_period();
+
+        let mut time_wait = TimeWait::new();
+        assert_eq!(time_wait.contains(e1, 1), false);
+
+        time_wait.insert(e1, 1);
+        assert_eq!(time_wait.contains(e1, 1), true);
+        assert_eq!(time_wait.contains(e1, 2), false);
+        assert_eq!(time_wait.contains(e2, 1), false);
+
+        time::advance(period / 2).await;
+        time_wait.insert(e1, 1);
+        time_wait.insert(e2, 2);
+
+        time::advance(period / 2).await;
+        assert_eq!(time_wait.contains(e1, 1), true);
+        assert_eq!(time_wait.contains(e2, 2), true);
+
+        time::advance(period / 2).await;
+        assert_eq!(time_wait.contains(e1, 1), false);
+        assert_eq!(time_wait.contains(e2, 2), false);
+        assert_eq!(time_wait.queue.len(), 0);
+        assert_eq!(time_wait.conn_ids.len(), 0);
+
+        time::advance(Duration::from_secs(1)).await;
+        assert_eq!(time_wait.contains(e1, 1), false);
+    }
+}
@@ -1,13 +1,15 @@
 use std::io::Error;
 use std::path::PathBuf;
 
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use futures::future::FutureExt;
-use tokio::signal;
+use tokio::signal::{self, unix::SignalKind};
 
-use g1_cli::{param::ParametersConfig, tracing::TracingConfig};
+use g1_cli::{
+    metrics::MetricsConfig, otlp::OtlpConfig, param::ParametersConfig, tracing::TracingConfig,
+};
 
-use ddcache_server::Server;
+use ddcache_server::{Repair, Server};
 
 #[derive(Debug, Parser)]
 #[command(version = g1_cli::version!(), after_help = ParametersConfig::render())]
@@ -15,26 +17,113 @@ struct Ddcached {
     #[command(flatten)]
     tracing: TracingConfig,
     #[command(flatten)]
+    otlp: OtlpConfig,
+    #[command(flatten)]
+    metrics: MetricsConfig,
+    #[command(flatten)]
     parameters: ParametersConfig,
 
+    /// Check the storage integrity and exit without starting the server
+    #[arg(long)]
+    fsck: bool,
+    /// Repair actions of the integrity check
+    #[arg(long, value_delimiter = ',', requires = "fsck")]
+    repair: Vec<RepairAction>,
+
     storage_dir: PathBuf,
 }
 
+#[derive(Clone, Debug, ValueEnum)]
+enum RepairAction {
+    /// Remove the index entries whose blob is missing
+    DropEntry,
+    /// Add valid orphan blobs to the index and remove the others
+    AdoptOrphan,
+    /// Update the index entries whose size does not match the blob
+    RecomputeSize,
+}
+
 impl Ddcached {
     async fn execute(&self) -> Result<(), Error> {
-        let (_, mut guard) = Server::spawn(&self.storage_dir).await?;
-        tokio::select! {
-            () = signal::ctrl_c().map(Result::unwrap) => tracing::info!("ctrl-c received!"),
-            () = guard.joinable() => {}
+        if self.fsck {
+            return self.fsck().await;
+        }
+
+        let (server, mut guard) = Server::spawn(&self.storage_dir).await?;
+        // SIGUSR1 cuts a warm standby over to serving as a primary server.
+        let mut cut_over = signal::unix::signal(SignalKind::user_defined1())?;
+        // SIGUSR2 cycles through the normal, drain, and read-only modes.
+        let mut switch_mode = signal::unix::signal(SignalKind::user_defined2())?;
+        // SIGHUP reopens the log file (for logrotate) and reloads parameters.
+        let mut hangup = signal::unix::signal(SignalKind::hangup())?;
+        loop {
+            tokio::select! {
+                () = signal::ctrl_c().map(Result::unwrap) => {
+                    tracing::info!("ctrl-c received!");
+                    break;
+                }
+                Some(()) = cut_over.recv() => match server.mirror() {
+                    Some(mirror) => {
+                        tracing::info!(lag = mirror.lag(), "cut-over requested");
+                        mirror.cut_over();
+                    }
+                    None => tracing::warn!("cut-over requested but we are not a standby"),
+                },
+                Some(()) = switch_mode.recv() => {
+                    server.set_mode(server.mode().next());
+                }
+                Some(()) = hangup.recv() => {
+                    if let Err(error) = self.tracing.reopen() {
+                        tracing::warn!(%error, "log file reopen error");
+                    }
+                    if let Err(error) = self.reload(&server) {
+                        tracing::warn!(%error, "reload error");
+                    }
+                }
+                () = guard.joinable() => break,
+            }
         }
         guard.shutdown().await?
     }
+
+    /// Reloads the parameter values that may change at runtime.
+    ///
+    /// Other parameter values are fixed once they are read, and we report them as not reloaded.
+    fn reload(&self, server: &Server) -> Result<(), Box<dyn std::error::Error>> {
+        let mut parameters = self.parameters.parse()?;
+        server.reload(&mut parameters)?;
+        for parameter in parameters.iter_values() {
+            tracing::info!(
+                parameter.module_path = parameter.module_path,
+                parameter.name = parameter.name,
+                "parameter is not reloadable; restart to apply changes",
+            );
+        }
+        Ok(())
+    }
+
+    async fn fsck(&self) -> Result<(), Error> {
+        let mut repair = Repair::default();
+        for action in &self.repair {
+            match action {
+                RepairAction::DropEntry => repair.drop_entry = true,
+                RepairAction::AdoptOrphan => repair.adopt_orphan = true,
+                RepairAction::RecomputeSize => repair.recompute_size = true,
+            }
+        }
+        let report = ddcache_server::fsck(&self.storage_dir, repair).await?;
+        println!("{report:#?}");
+        Ok(())
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
     let ddcached = Ddcached::parse();
-    ddcached.tracing.init();
+    ddcached.tracing.init_with_otlp(&ddcached.otlp);
+    ddcached.metrics.init();
     ddcached.parameters.init();
-    ddcached.execute().await
+    let result = ddcached.execute().await;
+    ddcached.otlp.shutdown();
+    result
 }
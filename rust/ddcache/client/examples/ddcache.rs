@@ -1,10 +1,11 @@
-use std::fs::OpenOptions;
-use std::io::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Error, Read, Seek, Write};
 use std::path::PathBuf;
 use std::time::Duration;
 
 use bytes::Bytes;
 use clap::{Args, Parser, Subcommand};
+use futures::stream::{self, FuturesUnordered, StreamExt, TryStreamExt};
 
 use tokio::time;
 
@@ -12,7 +13,7 @@ use g1_cli::{param::ParametersConfig, tracing::TracingConfig};
 
 use ddcache_client::Client;
 use ddcache_rpc::service;
-use ddcache_rpc::Timestamp;
+use ddcache_rpc::{Temperature, Timestamp, TimestampExt};
 
 #[derive(Debug, Parser)]
 #[command(after_help = ParametersConfig::render())]
@@ -33,6 +34,9 @@ enum Command {
     Write(Write),
     WriteMetadata(WriteMetadata),
     Remove(Remove),
+    Routes,
+    Dump(Dump),
+    Load(Load),
 }
 
 #[derive(Args, Debug)]
@@ -56,6 +60,10 @@ struct Write {
     metadata: Option<Bytes>,
     #[arg(long)]
     expire_at: Option<Timestamp>,
+    #[arg(long)]
+    stale_at: Option<Timestamp>,
+    #[arg(long, default_value = "normal")]
+    temperature: Temperature,
     file: PathBuf,
 }
 
@@ -66,6 +74,8 @@ struct WriteMetadata {
     metadata: Option<Option<Bytes>>,
     #[arg(long)]
     expire_at: Option<Option<Timestamp>>,
+    #[arg(long)]
+    stale_at: Option<Option<Timestamp>>,
 }
 
 #[derive(Args, Debug)]
@@ -73,6 +83,44 @@ struct Remove {
     key: Bytes,
 }
 
+#[derive(Args, Debug)]
+struct Dump {
+    #[arg(long)]
+    prefix: Option<Bytes>,
+    #[arg(long, default_value = "8")]
+    concurrency: usize,
+    file: PathBuf,
+}
+
+#[derive(Args, Debug)]
+struct Load {
+    #[arg(long)]
+    prefix: Option<Bytes>,
+    #[arg(long, default_value = "8")]
+    concurrency: usize,
+    #[arg(long, default_value = "normal")]
+    temperature: Temperature,
+    file: PathBuf,
+}
+
+// Archive format: `ARCHIVE_MAGIC` followed by a sequence of entries, each of which is made up of:
+// * key: `u32` size and bytes.
+// * metadata: `u8` presence flag, and if present, `u32` size and bytes.
+// * expire_at and stale_at: `u64` Unix timestamps in seconds, where 0 means none.
+// * blob: `u64` size and bytes.
+// Integers are in big endian.
+const ARCHIVE_MAGIC: &[u8] = b"ddcache-archive/1\n";
+
+#[derive(Debug)]
+struct Entry {
+    key: Bytes,
+    metadata: Option<Bytes>,
+    expire_at: Option<Timestamp>,
+    stale_at: Option<Timestamp>,
+    blob: File,
+    size: u64,
+}
+
 impl Program {
     async fn execute(&self) -> Result<(), Error> {
         let (client, mut guard) = Client::spawn(service::pubsub())
@@ -91,6 +139,9 @@ impl Program {
                     Self::write_metadata(client, write_metadata).await?
                 }
                 Command::Remove(remove) => Self::remove(client, remove).await?,
+                Command::Routes => Self::routes(client).await,
+                Command::Dump(dump) => Self::dump(client, dump).await?,
+                Command::Load(load) => Self::load(client, load).await?,
             }
         }
 
@@ -135,6 +186,8 @@ impl Program {
                     &mut file,
                     size,
                     write.expire_at,
+                    write.stale_at,
+                    write.temperature,
                 )
                 .await
         } else {
@@ -145,6 +198,8 @@ impl Program {
                     &mut file,
                     size,
                     write.expire_at,
+                    write.stale_at,
+                    write.temperature,
                 )
                 .await
         }
@@ -159,6 +214,7 @@ impl Program {
                 write_metadata.key.clone(),
                 write_metadata.metadata.clone(),
                 write_metadata.expire_at,
+                write_metadata.stale_at,
             )
             .await
             .map_err(Error::other)?;
@@ -174,6 +230,192 @@ impl Program {
         eprintln!("remove: {}", removed);
         Ok(())
     }
+
+    async fn routes(client: Client) {
+        eprintln!("routes: {:#?}", client.routes().await);
+    }
+
+    async fn dump(client: Client, dump: &Dump) -> Result<(), Error> {
+        let mut keys = client.scan().await.map_err(Error::other)?;
+        if let Some(prefix) = &dump.prefix {
+            keys.retain(|key| key.starts_with(prefix));
+        }
+        eprintln!("dump: scan={}", keys.len());
+
+        let mut output = BufWriter::new(File::create(&dump.file)?);
+        output.write_all(ARCHIVE_MAGIC)?;
+
+        let mut reads = stream::iter(keys)
+            .map(|key| {
+                let client = client.clone();
+                async move {
+                    let mut blob = tempfile::tempfile()?;
+                    let metadata = client
+                        .read(key.clone(), &mut blob, None)
+                        .await
+                        .map_err(Error::other)?;
+                    Ok::<_, Error>((key, metadata, blob))
+                }
+            })
+            .buffer_unordered(dump.concurrency);
+        let mut num_dumped = 0;
+        while let Some((key, metadata, mut blob)) = reads.try_next().await? {
+            // The entry was removed after the scan.
+            let Some(metadata) = metadata else {
+                continue;
+            };
+            // We do not dump dictionaries, without which the blob cannot be decompressed.
+            if metadata.dictionary.is_some() {
+                tracing::warn!(key = %key.escape_ascii(), "skip dictionary-compressed entry");
+                continue;
+            }
+            blob.rewind()?;
+            let size = blob.metadata()?.len();
+            Entry {
+                key,
+                metadata: metadata.metadata,
+                expire_at: metadata.expire_at,
+                stale_at: metadata.stale_at,
+                blob,
+                size,
+            }
+            .write(&mut output)?;
+            num_dumped += 1;
+        }
+        output.flush()?;
+
+        eprintln!("dump: {}", num_dumped);
+        Ok(())
+    }
+
+    async fn load(client: Client, load: &Load) -> Result<(), Error> {
+        let mut input = BufReader::new(File::open(&load.file)?);
+        let mut magic = [0u8; ARCHIVE_MAGIC.len()];
+        input.read_exact(&mut magic)?;
+        if magic != ARCHIVE_MAGIC {
+            return Err(Error::other("invalid archive magic"));
+        }
+
+        let now = Timestamp::now();
+        let temperature = load.temperature;
+        let mut writes = FuturesUnordered::new();
+        let mut num_written = 0;
+        while let Some(entry) = Entry::read(&mut input)? {
+            if let Some(prefix) = &load.prefix {
+                if !entry.key.starts_with(prefix) {
+                    continue;
+                }
+            }
+            if entry.expire_at.is_some_and(|expire_at| expire_at <= now) {
+                continue;
+            }
+
+            let client = client.clone();
+            writes.push(async move {
+                let Entry {
+                    key,
+                    metadata,
+                    expire_at,
+                    stale_at,
+                    mut blob,
+                    size,
+                } = entry;
+                client
+                    .write_all(
+                        key,
+                        metadata,
+                        &mut blob,
+                        usize::try_from(size).unwrap(),
+                        expire_at,
+                        stale_at,
+                        temperature,
+                    )
+                    .await
+                    .map_err(Error::other)
+            });
+            if writes.len() >= load.concurrency {
+                num_written += usize::from(writes.try_next().await?.unwrap());
+            }
+        }
+        while let Some(written) = writes.try_next().await? {
+            num_written += usize::from(written);
+        }
+
+        eprintln!("load: {}", num_written);
+        Ok(())
+    }
+}
+
+impl Entry {
+    fn read<R: BufRead>(input: &mut R) -> Result<Option<Self>, Error> {
+        if input.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+        let key = read_bytes(input)?;
+        let metadata = match read_array::<_, 1>(input)? {
+            [0] => None,
+            [1] => Some(read_bytes(input)?),
+            [flag] => return Err(Error::other(format!("invalid metadata flag: {flag}"))),
+        };
+        let expire_at = read_timestamp(input)?;
+        let stale_at = read_timestamp(input)?;
+        let size = u64::from_be_bytes(read_array(input)?);
+        let mut blob = tempfile::tempfile()?;
+        if io::copy(&mut input.take(size), &mut blob)? != size {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        blob.rewind()?;
+        Ok(Some(Self {
+            key,
+            metadata,
+            expire_at,
+            stale_at,
+            blob,
+            size,
+        }))
+    }
+
+    fn write<W: Write>(&mut self, output: &mut W) -> Result<(), Error> {
+        write_bytes(output, &self.key)?;
+        match &self.metadata {
+            Some(metadata) => {
+                output.write_all(&[1])?;
+                write_bytes(output, metadata)?;
+            }
+            None => output.write_all(&[0])?,
+        }
+        output.write_all(&self.expire_at.timestamp_u64().to_be_bytes())?;
+        output.write_all(&self.stale_at.timestamp_u64().to_be_bytes())?;
+        output.write_all(&self.size.to_be_bytes())?;
+        if io::copy(&mut (&mut self.blob).take(self.size), output)? != self.size {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(())
+    }
+}
+
+fn read_array<R: Read, const N: usize>(input: &mut R) -> Result<[u8; N], Error> {
+    let mut array = [0u8; N];
+    input.read_exact(&mut array)?;
+    Ok(array)
+}
+
+fn read_bytes<R: Read>(input: &mut R) -> Result<Bytes, Error> {
+    let size = u32::from_be_bytes(read_array(input)?);
+    let mut bytes = vec![0u8; usize::try_from(size).unwrap()];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes.into())
+}
+
+fn read_timestamp<R: Read>(input: &mut R) -> Result<Option<Timestamp>, Error> {
+    let secs = u64::from_be_bytes(read_array(input)?);
+    Option::<Timestamp>::from_timestamp_secs(secs)
+        .map_err(|secs| Error::other(format!("invalid timestamp: {secs}")))
+}
+
+fn write_bytes<W: Write>(output: &mut W, bytes: &[u8]) -> Result<(), Error> {
+    output.write_all(&u32::try_from(bytes.len()).unwrap().to_be_bytes())?;
+    output.write_all(bytes)
 }
 
 #[tokio::main]
@@ -1,27 +1,27 @@
+use std::collections::hash_map::Entry;
 use std::io;
 use std::time::Duration;
 
 use futures::future::OptionFuture;
 use futures::sink::SinkExt;
 use futures::stream::StreamExt;
-use snafu::prelude::*;
-use tokio::sync::{mpsc, oneshot, watch};
-use tokio::time::{self, Instant};
+use tokio::sync::oneshot;
+use tokio::time;
 use zmq::{Context, DEALER};
 
 use g1_base::fmt::{DebugExt, InsertPlaceholder};
-use g1_tokio::task::Cancel;
+use g1_tokio::sync::metered::{mpsc, watch};
+use g1_tokio::task::{Cancel, LoopExit};
 use g1_zmq::duplex::Duplex;
-use g1_zmq::envelope::{Envelope, Frame, Multipart};
+use g1_zmq::envelope::Multipart;
+use g1_zmq::failover::{Event, Failover};
+use g1_zmq::rpc;
 use g1_zmq::Socket;
 
-use ddcache_rpc::envelope;
 use ddcache_rpc::service::Server;
 
-use crate::error::{
-    DecodeSnafu, Error, InvalidResponseSnafu, InvalidRoutingIdSnafu, ResponseError,
-};
-use crate::response::{Response, ResponseResult, ResponseSend, ResponseSends, RoutingId};
+use crate::error::{Error, InvalidResponseSnafu, ResponseError};
+use crate::response::{Codec, Partials, ResponseResult, ResponseSend, ResponseSends};
 
 #[derive(DebugExt)]
 pub(crate) struct Actor {
@@ -29,6 +29,7 @@ pub(crate) struct Actor {
     server_recv: ServerRecv,
     request_recv: RequestRecv,
     response_sends: ResponseSends,
+    partials: Partials,
     #[debug(with = InsertPlaceholder)]
     context: Context,
 }
@@ -46,40 +47,44 @@ impl Actor {
             cancel,
             server_recv,
             request_recv,
-            response_sends: ResponseSends::new(),
+            response_sends: ResponseSends::new(*crate::request_timeout()),
+            partials: Partials::new(),
             context: Context::new(),
         }
     }
 
     pub(crate) async fn run(mut self) -> Result<(), io::Error> {
-        let mut duplex = self.connect().await?;
-
-        let mut deadline = None;
-        tokio::pin! { let timeout = OptionFuture::from(None); }
+        let (mut duplex, mut failover) = self.connect().await?;
 
         let mut idle_interval = time::interval(Duration::from_secs(120));
         let mut keepalive_response_recv = None;
 
         idle_interval.reset();
-        loop {
-            let next_deadline = self.response_sends.next_deadline();
-            if deadline != next_deadline {
-                deadline = next_deadline;
-                timeout.set(deadline.map(time::sleep_until).into());
-            }
-
-            tokio::select! {
-                () = self.cancel.wait() => break,
-
+        let cancel = self.cancel.clone();
+        let result = g1_tokio::actor_loop! {
+            cancel: cancel,
+            error: io::Error,
+            select {
                 changed = self.server_recv.changed() => {
                     if changed.is_err() {
-                        break;
+                        break Ok(LoopExit::Closed("server_recv"));
+                    }
+                    (duplex, failover) = self.connect().await?;
+                }
+
+                event = failover.recv() => {
+                    if let Some(event) = failover.handle(duplex.get_mut(), event?)? {
+                        match event {
+                            Event::Retried { .. } => tracing::debug!(?event, "reconnect"),
+                            _ => tracing::info!(?event, "reconnect"),
+                        }
                     }
-                    duplex = self.connect().await?;
                 }
 
                 request = self.request_recv.recv() => {
-                    let Some(request) = request else { break };
+                    let Some(request) = request else {
+                        break Ok(LoopExit::Closed("request_recv"));
+                    };
                     // Block the actor loop on `duplex.send` because it is probably desirable to
                     // derive back pressure from this point.
                     self.handle_request(request, &mut duplex).await;
@@ -96,14 +101,18 @@ impl Actor {
                             }
                         }
                         Some(Err(error)) => tracing::warn!(%error, "recv"),
-                        None => break,
+                        None => break Ok(LoopExit::Closed("duplex")),
                     }
                 }
 
-                Some(()) = &mut timeout => {
-                    self.response_sends.remove_expired(Instant::now());
-                    deadline = None;
-                    timeout.set(None.into());
+                Some((routing_id, response_send)) = self.response_sends.expire() => {
+                    self.partials.remove(&routing_id);
+                    if response_send.is_closed() {
+                        tracing::debug!(routing_id, "cancel");
+                    } else {
+                        tracing::warn!(routing_id, "expire");
+                        let _ = response_send.send(Err(Error::RequestTimeout));
+                    }
                 }
 
                 _ = idle_interval.tick() => {
@@ -121,23 +130,28 @@ impl Actor {
                     keepalive_response_recv = None;
                 }
             }
-        }
-
-        Ok(())
+            cleanup {}
+        };
+        result.map(|_| ())
     }
 
-    async fn connect(&mut self) -> Result<Duplex, io::Error> {
+    async fn connect(&mut self) -> Result<(Duplex, Failover), io::Error> {
         let server = self.server_recv.borrow_and_update().clone();
         tracing::info!(?server, "connect");
 
         let mut socket = Socket::try_from(self.context.socket(DEALER)?)?;
         socket.set_linger(0)?; // Do NOT block the program exit!
 
-        // TODO: Try each endpoint of the target server, as some of them may be unreachable from
-        // our end.
-        socket.connect(&server.endpoints[0])?;
+        // Some endpoints of the target server may be unreachable from our end, and thus we try them
+        // in order.
+        let failover = Failover::connect(
+            &self.context,
+            &mut socket,
+            server.endpoints.clone(),
+            *crate::max_reconnect_retries(),
+        )?;
 
-        Ok(socket.into())
+        Ok((socket.into(), failover))
     }
 
     async fn send_keepalive(&mut self, duplex: &mut Duplex) -> oneshot::Receiver<ResponseResult> {
@@ -151,13 +165,10 @@ impl Actor {
     async fn handle_request(&mut self, (request, response_send): Request, duplex: &mut Duplex) {
         tracing::debug!(?request);
         let routing_id = self.response_sends.insert(response_send);
-        let request = Envelope::new(
-            vec![Frame::from(routing_id.to_be_bytes().as_slice())],
-            Frame::from(Vec::<u8>::from(request)),
-        );
+        let request = rpc::encode_request::<Codec>(routing_id, request);
         // We assume that this error is transient and do not exit.
         // TODO: Should we re-send the request?
-        if let Err(error) = duplex.send(request.into()).await {
+        if let Err(error) = duplex.send(request).await {
             tracing::warn!(%error, "send");
             let _ = self
                 .response_sends
@@ -168,17 +179,38 @@ impl Actor {
     }
 
     fn handle_response(&mut self, frames: Multipart) -> Result<(), ResponseError> {
-        let response = envelope::decode(frames).context(InvalidResponseSnafu)?;
-
-        let routing_id = response.routing_id();
-        ensure!(
-            routing_id.len() == 1 && routing_id[0].len() == 8,
-            InvalidRoutingIdSnafu { response },
-        );
-        let routing_id = RoutingId::from_be_bytes((*routing_id[0]).try_into().unwrap());
+        let (routing_id, (response, more)) = match rpc::decode_response::<Codec>(frames) {
+            Ok(response) => response,
+            Err(rpc::ResponseError::Decode { routing_id, source }) => {
+                (routing_id, (Err(Error::Decode { source }), false))
+            }
+            Err(error) => return InvalidResponseSnafu { error }.fail(),
+        };
+        tracing::debug!(routing_id, ?response, more);
 
-        let response = Self::decode(response);
-        tracing::debug!(?response);
+        if more {
+            if !self.response_sends.contains(routing_id) {
+                tracing::debug!(routing_id, "response_send not found");
+                return Ok(());
+            }
+            // Hold the frame until the last one arrives.
+            if let Ok(Some(response)) = response {
+                match self.partials.entry(routing_id) {
+                    Entry::Occupied(mut entry) => entry.get_mut().merge(response),
+                    Entry::Vacant(entry) => {
+                        entry.insert(response);
+                    }
+                }
+            }
+            return Ok(());
+        }
+        let response = match (self.partials.remove(&routing_id), response) {
+            (Some(mut partial), Ok(Some(response))) => {
+                partial.merge(response);
+                Ok(Some(partial))
+            }
+            (_, response) => response,
+        };
 
         let Some(response_send) = self.response_sends.remove(routing_id) else {
             tracing::debug!(routing_id, "response_send not found");
@@ -188,15 +220,4 @@ impl Actor {
         let _ = response_send.send(response);
         Ok(())
     }
-
-    fn decode(response: Envelope<Frame>) -> ResponseResult {
-        let result: Result<_, capnp::Error> = try {
-            match **envelope::decode_response(response)?.data() {
-                Ok(Some(response)) => Ok(Response::try_from(response)?),
-                Ok(None) => Ok(None),
-                Err(error) => Err(Error::try_from(error)?),
-            }
-        };
-        result.context(DecodeSnafu)?
-    }
 }
@@ -2,36 +2,46 @@ use std::io;
 use std::net::TcpStream;
 use std::os::fd::AsFd;
 
+use bytes::{Bytes, BytesMut};
 use snafu::prelude::*;
-use tokio::io::AsyncWriteExt;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream as AsyncTcpStream;
 use tokio::time;
 
 use g1_tokio::os::{SendFile, Splice};
+use g1_tokio::task::Cancel;
 
 use ddcache_rpc::{BlobRequest, Token};
 
 use crate::error::{Error, IoSnafu, PartialIoSnafu};
+use crate::RawClient;
 
 #[derive(Debug)]
-pub struct RemoteBlob(BlobRequest);
+pub struct RemoteBlob {
+    blob: BlobRequest,
+    // `RawNaiveClient` cannot be shared; for its blobs, `cancel` only aborts the local transfer,
+    // and the server releases the blob when its lease expires.
+    client: Option<RawClient>,
+    cancel: Cancel,
+}
 
 macro_rules! io {
-    ($expect:ident, $io:expr $(,)?) => {
-        time::timeout(*crate::blob_request_timeout(), async move {
-            let size: Result<usize, io::Error> = try { $io };
-            let size = size.context(IoSnafu)?;
-            ensure!(
-                size == $expect,
-                PartialIoSnafu {
-                    size,
-                    expect: $expect,
-                },
-            );
-            Ok(())
-        })
-        .await
-        .map_err(|_| Error::BlobRequestTimeout)?
+    ($self:ident, $expect:ident, $io:expr $(,)?) => {
+        tokio::select! {
+            () = $self.cancel.wait() => Err(Error::BlobRequestCancelled),
+            result = time::timeout(*crate::blob_request_timeout(), async move {
+                let size: Result<usize, io::Error> = try { $io };
+                let size = size.context(IoSnafu)?;
+                ensure!(
+                    size == $expect,
+                    PartialIoSnafu {
+                        size,
+                        expect: $expect,
+                    },
+                );
+                Ok(())
+            }) => result.map_err(|_| Error::BlobRequestTimeout)?,
+        }
     };
 }
 
@@ -43,41 +53,69 @@ impl From<BlobRequest> for RemoteBlob {
 
 impl RemoteBlob {
     pub(crate) fn new(blob: BlobRequest) -> Self {
-        Self(blob)
+        Self {
+            blob,
+            client: None,
+            cancel: Cancel::new(),
+        }
+    }
+
+    pub(crate) fn set_client(&mut self, client: RawClient) {
+        self.client = Some(client);
     }
 
     pub fn token(&self) -> Token {
-        self.0.token
+        self.blob.token
+    }
+
+    /// Aborts the blob transfer, if any, and sends a cancel request to the server so that it
+    /// releases the blob without waiting for the lease to expire.
+    pub async fn cancel(&self) -> Result<(), Error> {
+        self.cancel.set();
+        match &self.client {
+            Some(client) => client.cancel(self.token()).await,
+            None => Ok(()),
+        }
     }
 
     async fn connect(&self) -> Result<TcpStream, io::Error> {
-        let mut stream = AsyncTcpStream::connect(self.0.endpoint).await?;
-        stream.write_u64(self.0.token).await?;
         // Unregister `stream` from the tokio reactor; otherwise, `sendfile` will return `EEXIST`
         // when it attempts to register `stream` with the reactor via `AsyncFd`.
-        stream.into_std()
+        self.connect_async().await?.into_std()
+    }
+
+    async fn connect_async(&self) -> Result<AsyncTcpStream, io::Error> {
+        let mut stream = AsyncTcpStream::connect(self.blob.endpoint).await?;
+        crate::blob_tcp_config().apply(&stream)?;
+        stream.write_u64(self.blob.token).await?;
+        Ok(stream)
     }
 
-    pub async fn read<F>(self, output: &mut F, expect: usize) -> Result<(), Error>
+    pub async fn read<F>(&self, output: &mut F, expect: usize) -> Result<(), Error>
     where
         F: AsFd + Send,
     {
-        io!(expect, self.connect().await?.splice(output, expect).await?)
+        io!(
+            self,
+            expect,
+            self.connect().await?.splice(output, expect).await?,
+        )
     }
 
-    pub async fn write<F>(self, input: &mut F, expect: usize) -> Result<(), Error>
+    pub async fn write<F>(&self, input: &mut F, expect: usize) -> Result<(), Error>
     where
         F: AsFd + Send,
     {
         // TODO: Should we use TCP_CORK here?
         io!(
+            self,
             expect,
             input.splice(&mut self.connect().await?, expect).await?,
         )
     }
 
     pub async fn write_file<F>(
-        self,
+        &self,
         input: &mut F,
         offset: Option<i64>,
         expect: usize,
@@ -87,6 +125,7 @@ impl RemoteBlob {
     {
         // TODO: Should we use TCP_CORK here?
         io!(
+            self,
             expect,
             self.connect()
                 .await?
@@ -94,4 +133,24 @@ impl RemoteBlob {
                 .await?,
         )
     }
+
+    /// Reads the blob into memory, which is more convenient than `read` for small blobs.
+    pub async fn read_bytes(&self, expect: usize) -> Result<Bytes, Error> {
+        let mut buffer = BytesMut::zeroed(expect);
+        let output = &mut buffer;
+        io!(
+            self,
+            expect,
+            self.connect_async().await?.read_exact(output).await?,
+        )?;
+        Ok(buffer.freeze())
+    }
+
+    pub async fn write_bytes(&self, input: &[u8]) -> Result<(), Error> {
+        let expect = input.len();
+        io!(self, expect, {
+            self.connect_async().await?.write_all(input).await?;
+            expect
+        })
+    }
 }
@@ -1,10 +1,10 @@
 use std::io;
+use std::time::Duration;
 
 use snafu::prelude::*;
 
-use g1_zmq::envelope::{Envelope, Frame};
+use g1_zmq::rpc;
 
-use ddcache_rpc::envelope;
 use ddcache_rpc::rpc_capnp::error;
 
 #[derive(Debug, Snafu)]
@@ -33,24 +33,31 @@ pub enum Error {
     // Protocol errors.
     //
     #[snafu(display("server error"))]
-    Server,
+    Server {
+        retryable: bool,
+        retry_after: Option<Duration>,
+    },
     #[snafu(display("server unavailable"))]
-    Unavailable,
+    Unavailable { retry_after: Option<Duration> },
+    #[snafu(display("server timeout after {elapsed:?}"))]
+    Timeout { elapsed: Duration },
 
     #[snafu(display("invalid request"))]
     InvalidRequest,
-    #[snafu(display("expect key size <= {max}"))]
-    MaxKeySizeExceeded { max: u32 },
-    #[snafu(display("expect metadata size <= {max}"))]
-    MaxMetadataSizeExceeded { max: u32 },
-    #[snafu(display("expect blob size <= {max}"))]
-    MaxBlobSizeExceeded { max: u32 },
+    #[snafu(display("expect key size <= {max}: {size}"))]
+    MaxKeySizeExceeded { max: u32, size: u32 },
+    #[snafu(display("expect metadata size <= {max}: {size}"))]
+    MaxMetadataSizeExceeded { max: u32, size: u32 },
+    #[snafu(display("expect blob size <= {max}: {size}"))]
+    MaxBlobSizeExceeded { max: u32, size: u32 },
 
     //
     // Blob I/O error.
     //
     #[snafu(display("blob request timeout"))]
     BlobRequestTimeout,
+    #[snafu(display("blob request cancelled"))]
+    BlobRequestCancelled,
     #[snafu(display("blob io error: {source}"))]
     Io { source: io::Error },
     #[snafu(display("expect read/write {expect} bytes: {size}"))]
@@ -61,22 +68,65 @@ impl TryFrom<error::Reader<'_>> for Error {
     type Error = capnp::Error;
 
     fn try_from(error: error::Reader<'_>) -> Result<Self, Self::Error> {
+        let retry_after = match error.get_retry_after() {
+            0 => None,
+            retry_after => Some(Duration::from_millis(retry_after.into())),
+        };
+        let size = error.get_size();
         Ok(match error.which()? {
-            error::Server(()) => Error::Server,
-            error::Unavailable(()) => Error::Unavailable,
+            error::Server(()) => Error::Server {
+                retryable: error.get_retryable(),
+                retry_after,
+            },
+            error::Unavailable(()) => Error::Unavailable { retry_after },
             error::InvalidRequest(()) => Error::InvalidRequest,
-            error::MaxKeySizeExceeded(max) => Error::MaxKeySizeExceeded { max },
-            error::MaxMetadataSizeExceeded(max) => Error::MaxMetadataSizeExceeded { max },
-            error::MaxBlobSizeExceeded(max) => Error::MaxBlobSizeExceeded { max },
+            error::MaxKeySizeExceeded(max) => Error::MaxKeySizeExceeded { max, size },
+            error::MaxMetadataSizeExceeded(max) => Error::MaxMetadataSizeExceeded { max, size },
+            error::MaxBlobSizeExceeded(max) => Error::MaxBlobSizeExceeded { max, size },
+            error::Timeout(elapsed) => Error::Timeout {
+                elapsed: Duration::from_millis(elapsed.into()),
+            },
         })
     }
 }
 
+impl Error {
+    /// Returns true if the request may succeed when retried as is.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Request { .. }
+            | Self::RequestTimeout
+            | Self::Unavailable { .. }
+            | Self::Timeout { .. }
+            | Self::BlobRequestTimeout
+            | Self::Io { .. }
+            | Self::PartialIo { .. } => true,
+            Self::Server { retryable, .. } => *retryable,
+            Self::Stopped
+            | Self::BlobRequestCancelled
+            | Self::Decode { .. }
+            | Self::UnexpectedResponse
+            | Self::InvalidRequest
+            | Self::MaxKeySizeExceeded { .. }
+            | Self::MaxMetadataSizeExceeded { .. }
+            | Self::MaxBlobSizeExceeded { .. } => false,
+        }
+    }
+
+    /// Returns how long the server asks us to wait before retrying, if it gives a hint.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::Server { retry_after, .. } | Self::Unavailable { retry_after } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Snafu)]
 #[snafu(visibility(pub(crate)))]
 pub(crate) enum ResponseError {
-    #[snafu(display("invalid response: {source}"))]
-    InvalidResponse { source: envelope::Error },
-    #[snafu(display("invalid routing id: {response:?}"))]
-    InvalidRoutingId { response: Envelope<Frame> },
+    #[snafu(display("invalid response: {error:?}"))]
+    InvalidResponse {
+        error: rpc::ResponseError<capnp::Error>,
+    },
 }
@@ -14,17 +14,22 @@ use std::time::Duration;
 use bytes::Bytes;
 use capnp::serialize;
 use snafu::prelude::*;
-use tokio::sync::{mpsc, oneshot, watch};
+use tokio::sync::oneshot;
 use tracing::Instrument;
 use uuid::Uuid;
 use zmq::{Context, REQ};
 
+use g1_tokio::net::tcp::TcpConfig;
+use g1_tokio::sync::metered::{mpsc, watch};
 use g1_tokio::sync::watch::Update;
 use g1_tokio::task::{Cancel, JoinGuard};
 use g1_zmq::Socket;
 
 use ddcache_rpc::service::Server;
-use ddcache_rpc::{Endpoint, ResponseReader, Timestamp, Token};
+use ddcache_rpc::{
+    ChangeCursor, DictionaryId, Endpoint, FsckRepair, FsckReport, ResponseReader, Temperature,
+    Timestamp, Token, WorkloadStats,
+};
 
 use crate::actor::{Actor, RequestSend, ServerSend};
 use crate::error::{DecodeSnafu, RequestSnafu, UnexpectedResponseSnafu};
@@ -38,6 +43,9 @@ g1_param::define!(
     blob_request_timeout: Duration = Duration::from_secs(8);
     parse = g1_param::parse::duration;
 );
+g1_param::define!(blob_tcp_config: TcpConfig = Default::default());
+// Number of consecutive reconnect attempts before failing over to the next server endpoint.
+g1_param::define!(max_reconnect_retries: usize = 8);
 
 pub use crate::blob::RemoteBlob;
 pub use crate::error::Error;
@@ -64,6 +72,12 @@ macro_rules! define_methods {
             Ok(())
         }
 
+        pub async fn ping(&$($mut)* self) -> Result<(), Error> {
+            let response = self.request(ddcache_rpc::Request::Ping).await?;
+            ensure!(response.is_none(), UnexpectedResponseSnafu);
+            Ok(())
+        }
+
         pub async fn read(&$($mut)* self, key: Bytes) -> ResponseResult {
             self.request(ddcache_rpc::Request::Read { key }).await
         }
@@ -73,18 +87,25 @@ macro_rules! define_methods {
                 .await
         }
 
+        #[allow(clippy::too_many_arguments)]
         pub async fn write(
             &$($mut)* self,
             key: Bytes,
             metadata: Option<Bytes>,
             size: usize,
             expire_at: Option<Timestamp>,
+            stale_at: Option<Timestamp>,
+            temperature: Temperature,
+            dictionary: Option<DictionaryId>,
         ) -> ResponseResult {
             self.request(ddcache_rpc::Request::Write {
                 key,
                 metadata,
                 size,
                 expire_at,
+                stale_at,
+                temperature,
+                dictionary,
             })
             .await
         }
@@ -94,11 +115,13 @@ macro_rules! define_methods {
             key: Bytes,
             metadata: Option<Option<Bytes>>,
             expire_at: Option<Option<Timestamp>>,
+            stale_at: Option<Option<Timestamp>>,
         ) -> ResponseResult {
             self.request(ddcache_rpc::Request::WriteMetadata {
                 key,
                 metadata,
                 expire_at,
+                stale_at,
             })
             .await
         }
@@ -111,28 +134,95 @@ macro_rules! define_methods {
             self.request(ddcache_rpc::Request::Pull { key }).await
         }
 
+        #[allow(clippy::too_many_arguments)]
         pub async fn push(
             &$($mut)* self,
             key: Bytes,
             metadata: Option<Bytes>,
             size: usize,
             expire_at: Option<Timestamp>,
+            stale_at: Option<Timestamp>,
+            temperature: Temperature,
+            dictionary: Option<DictionaryId>,
         ) -> ResponseResult {
             self.request(ddcache_rpc::Request::Push {
                 key,
                 metadata,
                 size,
                 expire_at,
+                stale_at,
+                temperature,
+                dictionary,
             })
             .await
         }
+
+        pub async fn changes(
+            &$($mut)* self,
+            cursor: Option<ChangeCursor>,
+            limit: usize,
+        ) -> ResponseResult {
+            self.request(ddcache_rpc::Request::Changes { cursor, limit })
+                .await
+        }
+
+        pub async fn fsck(&$($mut)* self, repair: FsckRepair) -> Result<FsckReport, Error> {
+            let response = self.request(ddcache_rpc::Request::Fsck { repair }).await?;
+            response
+                .and_then(|response| response.fsck)
+                .context(UnexpectedResponseSnafu)
+        }
+
+        pub async fn stats(&$($mut)* self) -> Result<WorkloadStats, Error> {
+            let response = self.request(ddcache_rpc::Request::Stats).await?;
+            response
+                .and_then(|response| response.stats)
+                .context(UnexpectedResponseSnafu)
+        }
+
+        pub async fn install_dictionary(
+            &$($mut)* self,
+            id: DictionaryId,
+            dictionary: Bytes,
+        ) -> Result<(), Error> {
+            let response = self
+                .request(ddcache_rpc::Request::InstallDictionary { id, dictionary })
+                .await?;
+            ensure!(response.is_none(), UnexpectedResponseSnafu);
+            Ok(())
+        }
+
+        pub async fn read_dictionary(&$($mut)* self, id: DictionaryId) -> ResponseResult {
+            self.request(ddcache_rpc::Request::ReadDictionary { id })
+                .await
+        }
+
+        pub async fn find(
+            &$($mut)* self,
+            namespace: Bytes,
+            field: Bytes,
+            value: Bytes,
+            limit: usize,
+        ) -> Result<Vec<Bytes>, Error> {
+            let response = self
+                .request(ddcache_rpc::Request::Find {
+                    namespace,
+                    field,
+                    value,
+                    limit,
+                })
+                .await?;
+            response
+                .and_then(|response| response.keys)
+                .context(UnexpectedResponseSnafu)
+        }
     };
 }
 
 impl RawClient {
     pub fn connect(id: Uuid, server: Server) -> (Self, RawClientGuard) {
-        let (server_send, server_recv) = watch::channel(server);
-        let (request_send, request_recv) = mpsc::channel(16);
+        let (server_send, server_recv) = watch::channel("ddcache/raw-server", server);
+        let (request_send, request_recv) = mpsc::channel("ddcache/raw-request", 16);
         let guard = RawClientGuard::spawn(move |cancel| {
             Actor::new(cancel, server_recv, request_recv)
                 .run()
@@ -148,6 +238,10 @@ impl RawClient {
         )
     }
 
+    pub fn server(&self) -> Server {
+        self.server_send.borrow().clone()
+    }
+
     pub fn update(&self, server: Server) {
         self.server_send.update(server);
     }
@@ -162,7 +256,14 @@ impl RawClient {
             .send((request, response_send))
             .await
             .map_err(|_| Error::Stopped)?;
-        response_recv.await.map_err(|_| Error::Stopped)?
+        let mut response = response_recv.await.map_err(|_| Error::Stopped)??;
+        if let Some(blob) = response
+            .as_mut()
+            .and_then(|response| response.blob.as_mut())
+        {
+            blob.set_client(self.clone());
+        }
+        Ok(response)
     }
 
     define_methods!();
@@ -1,11 +1,14 @@
-use std::collections::{HashMap, VecDeque};
-use std::time::Duration;
+use std::collections::HashMap;
 
+use bytes::Bytes;
 use tokio::sync::oneshot;
-use tokio::time::Instant;
 
+use g1_zmq::envelope::Frame;
+use g1_zmq::rpc::{self, RoutingId};
+
+use ddcache_rpc::envelope;
 use ddcache_rpc::rpc_capnp::response;
-use ddcache_rpc::BlobMetadata;
+use ddcache_rpc::{BlobMetadata, Changes, FsckReport, WorkloadStats};
 
 use crate::blob::RemoteBlob;
 use crate::error::Error;
@@ -16,105 +19,177 @@ use crate::error::Error;
 pub struct Response {
     pub metadata: Option<BlobMetadata>,
     pub blob: Option<RemoteBlob>,
+    pub changes: Option<Changes>,
+    pub fsck: Option<FsckReport>,
+    pub stats: Option<WorkloadStats>,
+    pub dictionary: Option<Bytes>,
+    pub keys: Option<Vec<Bytes>>,
 }
 
 pub type ResponseResult = Result<Option<Response>, Error>;
 
-#[derive(Debug)]
-pub(crate) struct ResponseSends {
-    map: HashMap<RoutingId, ResponseSend>,
-    // For now, we can use `VecDeque` because `timeout` is fixed.
-    deadlines: VecDeque<(Instant, RoutingId)>,
-    timeout: Duration,
-}
+// Partial responses of streaming requests, keyed by routing id.
+pub(crate) type Partials = HashMap<RoutingId, Response>;
 
-pub(crate) type RoutingId = u64;
+pub(crate) type ResponseSends = g1_zmq::rpc::ResponseSends<ResponseResult>;
 pub(crate) type ResponseSend = oneshot::Sender<ResponseResult>;
 
+#[derive(Debug)]
+pub(crate) enum Codec {}
+
+impl rpc::Codec for Codec {
+    type Request = ddcache_rpc::Request;
+    // The response, and whether more frames of the response follow.
+    type Response = (ResponseResult, bool);
+    type Error = capnp::Error;
+
+    fn encode_request(request: Self::Request) -> Frame {
+        // Unlike `RawNaiveClient`, we can receive streaming responses.
+        Frame::from(request.encode(true))
+    }
+
+    fn decode_response(response: Frame) -> Result<Self::Response, Self::Error> {
+        Ok(match *envelope::decode_response_data(response)? {
+            Ok(Some(response)) => (Ok(Response::try_from(response)?), response.get_more()),
+            Ok(None) => (Ok(None), false),
+            Err(error) => (Err(Error::try_from(error)?), false),
+        })
+    }
+}
+
 // Rust's orphan rule prevents us from implementing `TryFrom` for `Option<Response>`.
 impl Response {
     pub(crate) fn try_from(response: response::Reader) -> Result<Option<Self>, capnp::Error> {
         Ok(match ddcache_rpc::Response::try_from(response)? {
-            ddcache_rpc::Response::Cancel => None,
+            ddcache_rpc::Response::Cancel
+            | ddcache_rpc::Response::Ping
+            | ddcache_rpc::Response::InstallDictionary => None,
             ddcache_rpc::Response::Read { metadata, blob } => Some(Self {
                 metadata: Some(metadata),
                 blob: Some(blob.into()),
+                changes: None,
+                fsck: None,
+                stats: None,
+                dictionary: None,
+                keys: None,
             }),
             ddcache_rpc::Response::ReadMetadata { metadata } => Some(Self {
                 metadata: Some(metadata),
                 blob: None,
+                changes: None,
+                fsck: None,
+                stats: None,
+                dictionary: None,
+                keys: None,
             }),
             ddcache_rpc::Response::Write { blob } => Some(Self {
                 metadata: None,
                 blob: Some(blob.into()),
+                changes: None,
+                fsck: None,
+                stats: None,
+                dictionary: None,
+                keys: None,
             }),
             ddcache_rpc::Response::WriteMetadata { metadata } => Some(Self {
                 metadata: Some(metadata),
                 blob: None,
+                changes: None,
+                fsck: None,
+                stats: None,
+                dictionary: None,
+                keys: None,
             }),
             ddcache_rpc::Response::Remove { metadata } => Some(Self {
                 metadata: Some(metadata),
                 blob: None,
+                changes: None,
+                fsck: None,
+                stats: None,
+                dictionary: None,
+                keys: None,
             }),
             ddcache_rpc::Response::Pull { metadata, blob } => Some(Self {
                 metadata: Some(metadata),
                 blob: Some(blob.into()),
+                changes: None,
+                fsck: None,
+                stats: None,
+                dictionary: None,
+                keys: None,
             }),
             ddcache_rpc::Response::Push { blob } => Some(Self {
                 metadata: None,
                 blob: Some(blob.into()),
+                changes: None,
+                fsck: None,
+                stats: None,
+                dictionary: None,
+                keys: None,
+            }),
+            ddcache_rpc::Response::Changes { changes } => Some(Self {
+                metadata: None,
+                blob: None,
+                changes: Some(changes),
+                fsck: None,
+                stats: None,
+                dictionary: None,
+                keys: None,
+            }),
+            ddcache_rpc::Response::Fsck { report } => Some(Self {
+                metadata: None,
+                blob: None,
+                changes: None,
+                fsck: Some(report),
+                stats: None,
+                dictionary: None,
+                keys: None,
+            }),
+            ddcache_rpc::Response::Stats { stats } => Some(Self {
+                metadata: None,
+                blob: None,
+                changes: None,
+                fsck: None,
+                stats: Some(stats),
+                dictionary: None,
+                keys: None,
+            }),
+            ddcache_rpc::Response::ReadDictionary { dictionary } => Some(Self {
+                metadata: None,
+                blob: None,
+                changes: None,
+                fsck: None,
+                stats: None,
+                dictionary: Some(dictionary),
+                keys: None,
+            }),
+            ddcache_rpc::Response::Find { keys } => Some(Self {
+                metadata: None,
+                blob: None,
+                changes: None,
+                fsck: None,
+                stats: None,
+                dictionary: None,
+                keys: Some(keys),
             }),
         })
     }
 }
 
-impl ResponseSends {
-    pub(crate) fn new() -> Self {
-        Self {
-            map: HashMap::new(),
-            deadlines: VecDeque::new(),
-            timeout: *crate::request_timeout(),
+impl Response {
+    /// Merges the next frame of a streaming response into this one; see `Response.more` in
+    /// `rpc.capnp`.
+    pub(crate) fn merge(&mut self, next: Self) {
+        if let (Some(changes), Some(next)) = (self.changes.as_mut(), next.changes) {
+            changes.cursor = next.cursor;
+            changes.keys.extend(next.keys);
+            changes.lag = next.lag;
         }
-    }
-
-    pub(crate) fn next_routing_id(&self) -> RoutingId {
-        for _ in 0..4 {
-            let routing_id = rand::random();
-            // It is a small detail, but we do not generate 0.
-            if routing_id != 0 && !self.map.contains_key(&routing_id) {
-                return routing_id;
-            }
+        if let (Some(stats), Some(next)) = (self.stats.as_mut(), next.stats) {
+            stats.top_keys.extend(next.top_keys);
         }
-        std::panic!("cannot generate random routing id")
-    }
-
-    pub(crate) fn next_deadline(&mut self) -> Option<Instant> {
-        self.deadlines.front().map(|(deadline, _)| *deadline)
-    }
-
-    pub(crate) fn remove_expired(&mut self, now: Instant) {
-        while let Some((deadline, routing_id)) = self.deadlines.front().copied() {
-            if deadline <= now {
-                if let Some(response_send) = self.map.remove(&routing_id) {
-                    tracing::warn!(routing_id, "expire");
-                    let _ = response_send.send(Err(Error::RequestTimeout));
-                }
-                self.deadlines.pop_front();
-            } else {
-                break;
-            }
+        if let (Some(keys), Some(next)) = (self.keys.as_mut(), next.keys) {
+            keys.extend(next);
         }
     }
-
-    pub(crate) fn insert(&mut self, response_send: ResponseSend) -> RoutingId {
-        let routing_id = self.next_routing_id();
-        let deadline = Instant::now() + self.timeout;
-        assert!(self.map.insert(routing_id, response_send).is_none());
-        self.deadlines.push_back((deadline, routing_id));
-        routing_id
-    }
-
-    pub(crate) fn remove(&mut self, routing_id: RoutingId) -> Option<ResponseSend> {
-        self.map.remove(&routing_id)
-    }
 }
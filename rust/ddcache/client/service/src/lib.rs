@@ -6,6 +6,7 @@ use std::io;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
+use bytes::Bytes;
 use futures::stream::StreamExt;
 use snafu::prelude::*;
 use tokio::sync::{broadcast, OwnedSemaphorePermit, Semaphore};
@@ -22,7 +23,7 @@ use g1_tokio::task::{Cancel, JoinGuard, JoinQueue};
 use g1_tokio::time::queue::naive::FixedDelayQueue;
 
 use ddcache_client_raw::{RawClient, RawClientGuard};
-use ddcache_rpc::service::{self, Event, PubSub, Server, Subscriber};
+use ddcache_rpc::service::{self, Event, PubSub, Routing, Server, Subscriber};
 
 #[derive(Clone, Debug, Eq, PartialEq, Snafu)]
 #[snafu(display("not connected to any server"))]
@@ -49,6 +50,23 @@ pub struct ServiceSpawn {
     update_send: UpdateSend,
 }
 
+/// Pins a key, or all keys of a namespace (i.e., a key prefix), to a server.
+///
+/// A pinned server is placed first among the servers that `find` returns, which is useful for
+/// debugging and for migrating keys gradually.  An exact key pin takes precedence over namespace
+/// pins, and among namespace pins, the longest one wins.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Pin {
+    Key(Bytes),
+    Namespace(Bytes),
+}
+
+#[derive(Debug, Default)]
+struct Pins {
+    keys: HashMap<Bytes, Uuid>,
+    namespaces: HashMap<Bytes, Uuid>,
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Update {
     Start(Uuid),
@@ -76,7 +94,12 @@ struct Actor {
 
 #[derive(Debug)]
 struct ServerMap {
+    routing: Routing,
+    // When set, we are migrating from this routing strategy to `routing`, and we accept servers
+    // that use either of them.
+    migrate_from: Option<Routing>,
     servers: Mutex<ServerTable>,
+    pins: Mutex<Pins>,
     tasks: JoinQueue<Result<(), io::Error>>,
     update_send: UpdateSend,
 }
@@ -120,7 +143,13 @@ impl From<ServiceSpawn> for (Service, ServiceGuard) {
             update_send,
             update_recv,
         } = spawn;
-        let servers = Arc::new(ServerMap::new(self_id, servers, update_send));
+        let servers = Arc::new(ServerMap::new(
+            self_id,
+            *ddcache_rpc::routing(),
+            *ddcache_rpc::routing_migrate_from(),
+            servers,
+            update_send,
+        ));
         let dropped = Arc::new(Semaphore::new(1));
         let _dropped = Arc::new(dropped.clone().try_acquire_owned().unwrap());
         (
@@ -146,14 +175,106 @@ impl Service {
         self.servers.all()
     }
 
-    /// Finds servers via the Rendezvous Hashing algorithm.
+    pub fn routing(&self) -> Routing {
+        self.servers.routing
+    }
+
+    pub fn routing_migrate_from(&self) -> Option<Routing> {
+        self.servers.migrate_from
+    }
+
+    /// Finds servers via the Rendezvous Hashing algorithm, honoring the pins.
     pub fn find(
         &self,
         key: &[u8],
         num_replicas: Option<usize>,
     ) -> Result<Vec<(Uuid, Option<RawClient>)>, NotConnectedError> {
+        let num_replicas = num_replicas.unwrap_or(self.num_replicas);
+        let Some(pinned) = self.servers.pins.must_lock().find(key) else {
+            return self.servers.find(self.servers.routing, key, num_replicas);
+        };
+        let mut servers = self.servers.find(self.servers.routing, key, usize::MAX)?;
+        match servers.iter().position(|(id, _)| *id == pinned) {
+            Some(i) => {
+                let server = servers.remove(i);
+                servers.insert(0, server);
+            }
+            None => tracing::debug!(%pinned, "pinned server is not connected"),
+        }
+        servers.truncate(num_replicas);
+        Ok(servers)
+    }
+
+    /// Pins a key or namespace to a server and returns the server that it was pinned to.
+    pub fn pin(&self, pin: Pin, id: Uuid) -> Option<Uuid> {
+        self.servers.pins.must_lock().insert(pin, id)
+    }
+
+    pub fn unpin(&self, pin: &Pin) -> Option<Uuid> {
+        self.servers.pins.must_lock().remove(pin)
+    }
+
+    pub fn pins(&self) -> Vec<(Pin, Uuid)> {
+        self.servers.pins.must_lock().to_vec()
+    }
+
+    /// Finds servers via the routing strategy that we are migrating from, if any.
+    ///
+    /// Callers should fall back to these servers on a read miss, because the key may still reside
+    /// where the old strategy placed it.
+    pub fn find_migrate_from(
+        &self,
+        key: &[u8],
+        num_replicas: Option<usize>,
+    ) -> Result<Option<Vec<(Uuid, Option<RawClient>)>>, NotConnectedError> {
         self.servers
-            .find(key, num_replicas.unwrap_or(self.num_replicas))
+            .migrate_from
+            .map(|routing| {
+                self.servers
+                    .find(routing, key, num_replicas.unwrap_or(self.num_replicas))
+            })
+            .transpose()
+    }
+}
+
+impl Pins {
+    fn get_map(&mut self, pin: &Pin) -> (&mut HashMap<Bytes, Uuid>, Bytes) {
+        match pin {
+            Pin::Key(key) => (&mut self.keys, key.clone()),
+            Pin::Namespace(namespace) => (&mut self.namespaces, namespace.clone()),
+        }
+    }
+
+    fn insert(&mut self, pin: Pin, id: Uuid) -> Option<Uuid> {
+        let (map, key) = self.get_map(&pin);
+        map.insert(key, id)
+    }
+
+    fn remove(&mut self, pin: &Pin) -> Option<Uuid> {
+        let (map, key) = self.get_map(pin);
+        map.remove(&key)
+    }
+
+    fn find(&self, key: &[u8]) -> Option<Uuid> {
+        self.keys.get(key).copied().or_else(|| {
+            self.namespaces
+                .iter()
+                .filter(|(namespace, _)| key.starts_with(namespace))
+                .max_by_key(|(namespace, _)| namespace.len())
+                .map(|(_, id)| *id)
+        })
+    }
+
+    fn to_vec(&self) -> Vec<(Pin, Uuid)> {
+        let keys = self
+            .keys
+            .iter()
+            .map(|(key, id)| (Pin::Key(key.clone()), *id));
+        let namespaces = self
+            .namespaces
+            .iter()
+            .map(|(namespace, id)| (Pin::Namespace(namespace.clone()), *id));
+        keys.chain(namespaces).collect()
     }
 }
 
@@ -284,6 +405,8 @@ impl Actor {
 impl ServerMap {
     fn new(
         self_id: Option<Uuid>,
+        routing: Routing,
+        migrate_from: Option<Routing>,
         init_servers: Vec<(Uuid, Server)>,
         update_send: UpdateSend,
     ) -> Self {
@@ -294,7 +417,10 @@ impl ServerMap {
         }
 
         let this = Self {
+            routing,
+            migrate_from,
             servers: Mutex::new(servers),
+            pins: Mutex::new(Pins::default()),
             tasks: JoinQueue::new(),
             update_send,
         };
@@ -315,6 +441,21 @@ impl ServerMap {
     }
 
     fn connect_impl(&self, servers: &mut ServerTable, id: Uuid, server: Server) {
+        // We refuse to route to a server that disagrees with us on the routing strategy, as the
+        // keys it owns would be mostly misplaced and every request would silently miss.
+        if !self.accepts(server.routing) {
+            tracing::warn!(
+                %id,
+                routing = ?server.routing,
+                expect = ?self.routing,
+                migrate_from = ?self.migrate_from,
+                "refuse server with mismatched routing",
+            );
+            if let Some((_, Some(client))) = servers.get_row(&id) {
+                client.disconnect();
+            }
+            return;
+        }
         match servers.get_row(&id) {
             Some((_, client)) => {
                 if let Some(client) = client {
@@ -333,6 +474,10 @@ impl ServerMap {
         }
     }
 
+    fn accepts(&self, routing: Routing) -> bool {
+        routing == self.routing || Some(routing) == self.migrate_from
+    }
+
     fn disconnect(&self, id: Uuid) {
         if let Some((_, Some(client))) = self.servers.must_lock().get_row(&id) {
             client.disconnect();
@@ -367,6 +512,7 @@ impl ServerMap {
 
     fn find(
         &self,
+        routing: Routing,
         key: &[u8],
         num_replicas: usize,
     ) -> Result<Vec<(Uuid, Option<RawClient>)>, NotConnectedError> {
@@ -375,9 +521,59 @@ impl ServerMap {
             .must_lock()
             .iter()
             .map(|(id, _, client)| (*id, client.clone()))
-            .collect_then_sort_by_key(service::rendezvous_sorting_by_key(key, |(id, _)| *id));
+            .collect_then_sort_by_key(service::rendezvous_sorting_by_key(
+                routing,
+                key,
+                |(id, _)| *id,
+            ));
         ensure!(!servers.is_empty(), NotConnectedSnafu);
         servers.truncate(num_replicas);
         Ok(servers)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pins() {
+        let id1 = Uuid::from_u128(1);
+        let id2 = Uuid::from_u128(2);
+        let id3 = Uuid::from_u128(3);
+
+        let mut pins = Pins::default();
+        assert_eq!(pins.find(b"foo/bar"), None);
+
+        assert_eq!(
+            pins.insert(Pin::Namespace(Bytes::from_static(b"foo/")), id1),
+            None
+        );
+        assert_eq!(pins.find(b"foo/bar"), Some(id1));
+        assert_eq!(pins.find(b"fo"), None);
+
+        // The longest namespace wins.
+        assert_eq!(
+            pins.insert(Pin::Namespace(Bytes::from_static(b"foo/b")), id2),
+            None
+        );
+        assert_eq!(pins.find(b"foo/bar"), Some(id2));
+        assert_eq!(pins.find(b"foo/x"), Some(id1));
+
+        // A key pin takes precedence over namespace pins.
+        assert_eq!(
+            pins.insert(Pin::Key(Bytes::from_static(b"foo/bar")), id3),
+            None
+        );
+        assert_eq!(pins.find(b"foo/bar"), Some(id3));
+        assert_eq!(pins.find(b"foo/baz"), Some(id2));
+
+        assert_eq!(
+            pins.remove(&Pin::Key(Bytes::from_static(b"foo/bar"))),
+            Some(id3)
+        );
+        assert_eq!(pins.remove(&Pin::Key(Bytes::from_static(b"foo/bar"))), None);
+        assert_eq!(pins.find(b"foo/bar"), Some(id2));
+        assert_eq!(pins.to_vec().len(), 2);
+    }
+}
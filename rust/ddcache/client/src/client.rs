@@ -1,26 +1,53 @@
 use std::cmp;
+use std::collections::BTreeSet;
 use std::fs::File;
 use std::os::fd::{AsFd, AsRawFd, BorrowedFd};
+use std::sync::Arc;
+use std::time::Duration;
 
 use bytes::Bytes;
+use futures::future;
 use snafu::prelude::*;
+use tokio::time::{self, Instant};
 use uuid::Uuid;
 
 use etcd_pubsub::SubscriberError;
 
 use ddcache_client_raw::{concurrent, RawClient};
-use ddcache_client_service::Service;
-use ddcache_rpc::service::PubSub;
-use ddcache_rpc::{BlobMetadata, Timestamp};
+use ddcache_client_service::{Pin, Service};
+use ddcache_rpc::service::{PubSub, Routing};
+use ddcache_rpc::{BlobMetadata, DictionaryId, Temperature, Timestamp};
 
-use crate::error::{Error, RequestSnafu};
+use crate::dict::{Dictionaries, Dictionary};
+use crate::error::{
+    DictionaryNotFoundSnafu, Error, InvalidDictionarySnafu, NotReadySnafu, RequestSnafu, ZstdSnafu,
+};
+use crate::retry::{Operation, RetryPolicy};
 
 #[derive(Clone, Debug)]
-pub struct Client(Service);
+pub struct Client(Service, Dictionaries, RetryPolicy);
 
 // For now we just make an alias.
 pub use ddcache_client_service::ServiceGuard as ClientGuard;
 
+/// Snapshot of the route table.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Routes {
+    pub routing: Routing,
+    pub routing_migrate_from: Option<Routing>,
+    pub shards: Vec<Shard>,
+    pub pins: Vec<(Pin, Uuid)>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Shard {
+    pub id: Uuid,
+    pub endpoints: Vec<String>,
+    pub routing: Routing,
+    /// Whether the shard responded to a ping.
+    pub is_healthy: bool,
+}
+
 macro_rules! metadata {
     ($response:ident $(,)?) => {
         $response
@@ -37,10 +64,137 @@ macro_rules! blob {
     };
 }
 
+// We use a macro, rather than a function taking a closure, because some requests borrow their
+// arguments mutably across attempts.
+macro_rules! retry {
+    ($self:ident, $operation:expr, $request:expr $(,)?) => {{
+        let operation = $operation;
+        let policy = $self.2.get(operation);
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let error = match $request.await {
+                Ok(output) => break Ok(output),
+                Err(error) => error,
+            };
+            let Some(backoff) = policy.backoff(attempt, &error) else {
+                break Err(error);
+            };
+            tracing::debug!(?operation, attempt, ?backoff, %error, "retry");
+            time::sleep(backoff).await;
+        }
+    }};
+}
+
 impl Client {
     pub async fn spawn(pubsub: PubSub) -> Result<(Self, ClientGuard), SubscriberError> {
         let (service, guard) = Service::prepare(None, pubsub).await?.into();
-        Ok((Self(service), guard))
+        Ok((
+            Self(service, Dictionaries::default(), RetryPolicy::default()),
+            guard,
+        ))
+    }
+
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.2
+    }
+
+    /// Returns a client that shares the connections of `self` but retries with `policy`.
+    ///
+    /// To override the policy of a single call, write
+    /// `client.with_retry_policy(policy).remove(key)`.
+    pub fn with_retry_policy(&self, policy: RetryPolicy) -> Self {
+        Self(self.0.clone(), self.1.clone(), policy)
+    }
+
+    /// Waits until at least `min_shards` shards are connected and responsive.
+    ///
+    /// Call this at startup so that the first requests do not race against shard discovery.
+    pub async fn wait_ready(&self, min_shards: usize, deadline: Instant) -> Result<(), Error> {
+        // Shards may become responsive without an update; so we also poll periodically.
+        const POLL_PERIOD: Duration = Duration::from_millis(500);
+
+        let mut update_recv = self.0.subscribe();
+        let mut num_ready = 0;
+        loop {
+            let poll = async {
+                num_ready = self.num_ready().await;
+                if num_ready >= min_shards {
+                    return true;
+                }
+                tokio::select! {
+                    _ = update_recv.recv() => {}
+                    () = time::sleep(POLL_PERIOD) => {}
+                }
+                false
+            };
+            match time::timeout_at(deadline, poll).await {
+                Ok(true) => return Ok(()),
+                Ok(false) => {}
+                Err(_) => {
+                    return Err(NotReadySnafu {
+                        num_ready,
+                        min_shards,
+                    }
+                    .build())
+                }
+            }
+        }
+    }
+
+    async fn num_ready(&self) -> usize {
+        self.routes()
+            .await
+            .shards
+            .iter()
+            .filter(|shard| shard.is_healthy)
+            .count()
+    }
+
+    /// Returns a snapshot of the route table, pinging the shards to check their health.
+    ///
+    /// Note that the rendezvous hashing algorithm does not assign key ranges to shards; to find the
+    /// shards of a specific key, call `find_shards`.
+    pub async fn routes(&self) -> Routes {
+        let servers = self.all().into_iter().flatten();
+        let shards = future::join_all(servers.map(|(id, client)| async move {
+            let result = client.ping().await;
+            if let Err(error) = &result {
+                tracing::debug!(%id, %error, "ping");
+            }
+            let server = client.server();
+            Shard {
+                id,
+                endpoints: server.endpoints,
+                routing: server.routing,
+                is_healthy: result.is_ok(),
+            }
+        }))
+        .await;
+        Routes {
+            routing: self.0.routing(),
+            routing_migrate_from: self.0.routing_migrate_from(),
+            shards,
+            pins: self.0.pins(),
+        }
+    }
+
+    /// Returns the ids of the shards that `key` is routed to, in order of preference.
+    pub fn find_shards(&self, key: &[u8]) -> Result<Vec<Uuid>, Error> {
+        Ok(self.find(key)?.map(|(id, _)| id).collect())
+    }
+
+    /// Pins a key or namespace to a shard and returns the shard that it was pinned to.
+    ///
+    /// Pins are local to this client; they do not move the keys that already reside elsewhere.
+    pub fn pin(&self, pin: Pin, id: Uuid) -> Option<Uuid> {
+        tracing::info!(?pin, %id, "pin");
+        self.0.pin(pin, id)
+    }
+
+    pub fn unpin(&self, pin: &Pin) -> Option<Uuid> {
+        tracing::info!(?pin, "unpin");
+        self.0.unpin(pin)
     }
 
     fn all(&self) -> Result<impl Iterator<Item = (Uuid, RawClient)>, Error> {
@@ -51,12 +205,29 @@ impl Client {
         Ok(Self::unwrap_client(self.0.find(key, None)?))
     }
 
+    /// Returns the servers of the routing strategy that we are migrating from, if any.
+    fn find_migrate_from(
+        &self,
+        key: &[u8],
+    ) -> Result<Option<impl Iterator<Item = (Uuid, RawClient)>>, Error> {
+        Ok(self
+            .0
+            .find_migrate_from(key, None)?
+            .map(Self::unwrap_client))
+    }
+
     fn unwrap_client(
         iter: impl IntoIterator<Item = (Uuid, Option<RawClient>)>,
     ) -> impl Iterator<Item = (Uuid, RawClient)> {
         iter.into_iter().map(|(id, client)| (id, client.unwrap()))
     }
 
+    /// Reads a blob.
+    ///
+    /// During a routing migration, it falls back to the servers of the old routing strategy on a
+    /// miss.
+    ///
+    /// It is not retried, as a failed attempt may have partially written to `output`.
     pub async fn read<F>(
         &self,
         key: Bytes,
@@ -66,7 +237,25 @@ impl Client {
     where
         F: AsFd + Send,
     {
-        let servers = self.find(&key)?;
+        let metadata = Self::read_from(self.find(&key)?, key.clone(), output, size).await?;
+        if metadata.is_some() {
+            return Ok(metadata);
+        }
+        match self.find_migrate_from(&key)? {
+            Some(servers) => Self::read_from(servers, key, output, size).await,
+            None => Ok(None),
+        }
+    }
+
+    async fn read_from<F>(
+        servers: impl Iterator<Item = (Uuid, RawClient)>,
+        key: Bytes,
+        output: &mut F,
+        size: Option<usize>,
+    ) -> Result<Option<BlobMetadata>, Error>
+    where
+        F: AsFd + Send,
+    {
         let result: Result<Option<BlobMetadata>, ddcache_client_raw::Error> = try {
             let response = concurrent::request_any(servers, move |client| {
                 let key = key.clone();
@@ -88,7 +277,28 @@ impl Client {
     }
 
     pub async fn read_metadata(&self, key: Bytes) -> Result<Option<BlobMetadata>, Error> {
-        let servers = self.find(&key)?;
+        retry!(
+            self,
+            Operation::ReadMetadata,
+            self.read_metadata_once(key.clone())
+        )
+    }
+
+    async fn read_metadata_once(&self, key: Bytes) -> Result<Option<BlobMetadata>, Error> {
+        let metadata = Self::read_metadata_from(self.find(&key)?, key.clone()).await?;
+        if metadata.is_some() {
+            return Ok(metadata);
+        }
+        match self.find_migrate_from(&key)? {
+            Some(servers) => Self::read_metadata_from(servers, key).await,
+            None => Ok(None),
+        }
+    }
+
+    async fn read_metadata_from(
+        servers: impl Iterator<Item = (Uuid, RawClient)>,
+        key: Bytes,
+    ) -> Result<Option<BlobMetadata>, Error> {
         let result: Result<Option<BlobMetadata>, ddcache_client_raw::Error> = try {
             concurrent::request_any(servers, move |client| {
                 let key = key.clone();
@@ -101,6 +311,9 @@ impl Client {
         result.context(RequestSnafu)
     }
 
+    /// Writes to any replica.
+    ///
+    /// It is not retried, as a failed attempt may have partially consumed `input`.
     pub async fn write_any<F>(
         &self,
         key: Bytes,
@@ -108,6 +321,8 @@ impl Client {
         input: &mut F,
         size: usize,
         expire_at: Option<Timestamp>,
+        stale_at: Option<Timestamp>,
+        temperature: Temperature,
     ) -> Result<bool, Error>
     where
         F: AsFd + Send,
@@ -117,7 +332,11 @@ impl Client {
             let response = concurrent::request_any(servers, move |client| {
                 let key = key.clone();
                 let metadata = metadata.clone();
-                async move { client.write(key, metadata, size, expire_at).await }
+                async move {
+                    client
+                        .write(key, metadata, size, expire_at, stale_at, temperature, None)
+                        .await
+                }
             })
             .await?;
 
@@ -140,6 +359,33 @@ impl Client {
         input: &mut File,
         size: usize,
         expire_at: Option<Timestamp>,
+        stale_at: Option<Timestamp>,
+        temperature: Temperature,
+    ) -> Result<bool, Error> {
+        retry!(
+            self,
+            Operation::WriteAll,
+            self.write_all_once(
+                key.clone(),
+                metadata.clone(),
+                input,
+                size,
+                expire_at,
+                stale_at,
+                temperature
+            )
+        )
+    }
+
+    async fn write_all_once(
+        &self,
+        key: Bytes,
+        metadata: Option<Bytes>,
+        input: &mut File,
+        size: usize,
+        expire_at: Option<Timestamp>,
+        stale_at: Option<Timestamp>,
+        temperature: Temperature,
     ) -> Result<bool, Error> {
         let fd = input.as_raw_fd();
         concurrent::request_all(
@@ -147,7 +393,11 @@ impl Client {
             move |client| {
                 let key = key.clone();
                 let metadata = metadata.clone();
-                async move { client.write(key, metadata, size, expire_at).await }
+                async move {
+                    client
+                        .write(key, metadata, size, expire_at, stale_at, temperature, None)
+                        .await
+                }
             },
             |response| async move {
                 let blob = response
@@ -168,13 +418,32 @@ impl Client {
         key: Bytes,
         metadata: Option<Option<Bytes>>,
         expire_at: Option<Option<Timestamp>>,
+        stale_at: Option<Option<Timestamp>>,
+    ) -> Result<bool, Error> {
+        retry!(
+            self,
+            Operation::WriteMetadata,
+            self.write_metadata_once(key.clone(), metadata.clone(), expire_at, stale_at)
+        )
+    }
+
+    async fn write_metadata_once(
+        &self,
+        key: Bytes,
+        metadata: Option<Option<Bytes>>,
+        expire_at: Option<Option<Timestamp>>,
+        stale_at: Option<Option<Timestamp>>,
     ) -> Result<bool, Error> {
         concurrent::request_all(
             self.find(&key)?,
             move |client| {
                 let key = key.clone();
                 let metadata = metadata.clone();
-                async move { client.write_metadata(key, metadata, expire_at).await }
+                async move {
+                    client
+                        .write_metadata(key, metadata, expire_at, stale_at)
+                        .await
+                }
             },
             |response| async move {
                 let metadata = response
@@ -192,6 +461,10 @@ impl Client {
     /// algorithm) to prevent the scenario where a blob is "accidentally" replicated to additional
     /// shards and later re-replicated.
     pub async fn remove(&self, key: Bytes) -> Result<bool, Error> {
+        retry!(self, Operation::Remove, self.remove_once(key.clone()))
+    }
+
+    async fn remove_once(&self, key: Bytes) -> Result<bool, Error> {
         concurrent::request_all(
             self.all()?,
             move |client| {
@@ -210,3 +483,280 @@ impl Client {
         .context(RequestSnafu)
     }
 }
+
+impl Client {
+    /// Installs a zstd dictionary on all shards and returns its id.
+    ///
+    /// The id is parsed from the dictionary header (see `train_dictionary`).  Installing is
+    /// idempotent, and thus a partially failed installation may simply be retried.
+    pub async fn install_dictionary(&self, dictionary: Bytes) -> Result<DictionaryId, Error> {
+        retry!(
+            self,
+            Operation::InstallDictionary,
+            self.install_dictionary_once(dictionary.clone())
+        )
+    }
+
+    async fn install_dictionary_once(&self, dictionary: Bytes) -> Result<DictionaryId, Error> {
+        let id = Dictionary::parse_id(&dictionary).context(InvalidDictionarySnafu)?;
+        future::try_join_all(self.all()?.map(|(_, client)| {
+            let dictionary = dictionary.clone();
+            async move { client.install_dictionary(id, dictionary).await }
+        }))
+        .await
+        .context(RequestSnafu)?;
+        tracing::info!(id, "install dictionary");
+        self.1.insert(Arc::new(Dictionary::new(id, dictionary)));
+        Ok(id)
+    }
+
+    /// Returns at most `limit` keys, in sorted order, under the namespace whose metadata field
+    /// matches the value.
+    ///
+    /// It queries all shards, as entries are not placed by their metadata.
+    pub async fn find_by_metadata(
+        &self,
+        namespace: Bytes,
+        field: Bytes,
+        value: Bytes,
+        limit: usize,
+    ) -> Result<Vec<Bytes>, Error> {
+        retry!(
+            self,
+            Operation::FindByMetadata,
+            self.find_by_metadata_once(namespace.clone(), field.clone(), value.clone(), limit)
+        )
+    }
+
+    async fn find_by_metadata_once(
+        &self,
+        namespace: Bytes,
+        field: Bytes,
+        value: Bytes,
+        limit: usize,
+    ) -> Result<Vec<Bytes>, Error> {
+        let keys = future::try_join_all(self.all()?.map(|(_, client)| {
+            let namespace = namespace.clone();
+            let field = field.clone();
+            let value = value.clone();
+            async move { client.find(namespace, field, value, limit).await }
+        }))
+        .await
+        .context(RequestSnafu)?;
+        // Deduplicate the replicas.
+        Ok(keys
+            .into_iter()
+            .flatten()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .take(limit)
+            .collect())
+    }
+
+    /// Returns the keys of all entries, in sorted order.
+    ///
+    /// It takes a snapshot of each shard from its change feed; entries may be written or removed
+    /// by the time it returns.
+    pub async fn scan(&self) -> Result<Vec<Bytes>, Error> {
+        retry!(self, Operation::Scan, self.scan_once())
+    }
+
+    async fn scan_once(&self) -> Result<Vec<Bytes>, Error> {
+        let keys = future::try_join_all(self.all()?.map(|(_, client)| async move {
+            client
+                .changes(None, 0)
+                .await?
+                .and_then(|response| response.changes)
+                .map(|changes| changes.keys)
+                .ok_or(ddcache_client_raw::Error::UnexpectedResponse)
+        }))
+        .await
+        .context(RequestSnafu)?;
+        // Deduplicate the replicas.
+        Ok(keys
+            .into_iter()
+            .flatten()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect())
+    }
+
+    /// Sets the dictionary that `write_value` compresses values with and returns the previous one.
+    pub fn use_dictionary(&self, id: Option<DictionaryId>) -> Option<DictionaryId> {
+        self.1.set_current(id)
+    }
+
+    /// Writes a small value, compressing it with the current dictionary.
+    ///
+    /// If the dictionary is unavailable or does not make the value smaller, it falls back to
+    /// writing the value uncompressed.
+    pub async fn write_value(
+        &self,
+        key: Bytes,
+        metadata: Option<Bytes>,
+        value: Bytes,
+        expire_at: Option<Timestamp>,
+        stale_at: Option<Timestamp>,
+        temperature: Temperature,
+    ) -> Result<bool, Error> {
+        retry!(
+            self,
+            Operation::WriteValue,
+            self.write_value_once(
+                key.clone(),
+                metadata.clone(),
+                value.clone(),
+                expire_at,
+                stale_at,
+                temperature
+            )
+        )
+    }
+
+    async fn write_value_once(
+        &self,
+        key: Bytes,
+        metadata: Option<Bytes>,
+        value: Bytes,
+        expire_at: Option<Timestamp>,
+        stale_at: Option<Timestamp>,
+        temperature: Temperature,
+    ) -> Result<bool, Error> {
+        let (value, dictionary) = self.compress(value).await;
+        let size = value.len();
+        let servers = self.find(&key)?;
+        let result: Result<bool, ddcache_client_raw::Error> = try {
+            let response = concurrent::request_any(servers, move |client| {
+                let key = key.clone();
+                let metadata = metadata.clone();
+                async move {
+                    client
+                        .write(
+                            key,
+                            metadata,
+                            size,
+                            expire_at,
+                            stale_at,
+                            temperature,
+                            dictionary,
+                        )
+                        .await
+                }
+            })
+            .await?;
+
+            let Some((_, _, response)) = response else {
+                return Ok(false);
+            };
+            let blob = blob!(response)?;
+
+            blob.write_bytes(&value).await?;
+            true
+        };
+        result.context(RequestSnafu)
+    }
+
+    async fn compress(&self, value: Bytes) -> (Bytes, Option<DictionaryId>) {
+        let Some(id) = self.1.current() else {
+            return (value, None);
+        };
+        let compressed: Result<_, Error> = try {
+            self.dictionary(id)
+                .await?
+                .compress(&value)
+                .context(ZstdSnafu)?
+        };
+        match compressed {
+            Ok(Some(compressed)) => (compressed.into(), Some(id)),
+            Ok(None) => (value, None),
+            Err(error) => {
+                tracing::warn!(id, %error, "compress");
+                (value, None)
+            }
+        }
+    }
+
+    /// Reads a value written by `write_value`, decompressing it if it is compressed.
+    ///
+    /// Note that the returned metadata describes the blob as stored, i.e., `size` is the
+    /// compressed size.
+    pub async fn read_value(&self, key: Bytes) -> Result<Option<(BlobMetadata, Bytes)>, Error> {
+        retry!(
+            self,
+            Operation::ReadValue,
+            self.read_value_once(key.clone())
+        )
+    }
+
+    async fn read_value_once(&self, key: Bytes) -> Result<Option<(BlobMetadata, Bytes)>, Error> {
+        let mut entry = Self::read_value_from(self.find(&key)?, key.clone()).await?;
+        if entry.is_none() {
+            if let Some(servers) = self.find_migrate_from(&key)? {
+                entry = Self::read_value_from(servers, key).await?;
+            }
+        }
+        let Some((metadata, value)) = entry else {
+            return Ok(None);
+        };
+        let value = match metadata.dictionary {
+            Some(id) => self
+                .dictionary(id)
+                .await?
+                .decompress(&value)
+                .context(ZstdSnafu)?
+                .into(),
+            None => value,
+        };
+        Ok(Some((metadata, value)))
+    }
+
+    async fn read_value_from(
+        servers: impl Iterator<Item = (Uuid, RawClient)>,
+        key: Bytes,
+    ) -> Result<Option<(BlobMetadata, Bytes)>, Error> {
+        let result: Result<Option<(BlobMetadata, Bytes)>, ddcache_client_raw::Error> = try {
+            let response = concurrent::request_any(servers, move |client| {
+                let key = key.clone();
+                async move { client.read(key).await }
+            })
+            .await?;
+
+            let Some((_, _, response)) = response else {
+                return Ok(None);
+            };
+            let metadata = metadata!(response)?;
+            let blob = blob!(response)?;
+
+            let value = blob.read_bytes(metadata.size).await?;
+            Some((metadata, value))
+        };
+        result.context(RequestSnafu)
+    }
+
+    /// Returns the dictionary, fetching it from any shard if we do not have it.
+    async fn dictionary(&self, id: DictionaryId) -> Result<Arc<Dictionary>, Error> {
+        if let Some(dictionary) = self.1.get(id) {
+            return Ok(dictionary);
+        }
+        let servers = self.all()?;
+        let result: Result<Option<Bytes>, ddcache_client_raw::Error> = try {
+            concurrent::request_any(servers, move |client| async move {
+                client.read_dictionary(id).await
+            })
+            .await?
+            .map(|(_, _, response)| {
+                response
+                    .dictionary
+                    .ok_or(ddcache_client_raw::Error::UnexpectedResponse)
+            })
+            .transpose()?
+        };
+        let dictionary = result
+            .context(RequestSnafu)?
+            .context(DictionaryNotFoundSnafu { id })?;
+        tracing::debug!(id, "fetch dictionary");
+        let dictionary = Arc::new(Dictionary::new(id, dictionary));
+        self.1.insert(dictionary.clone());
+        Ok(dictionary)
+    }
+}
@@ -0,0 +1,151 @@
+//! Compression of Small Values with zstd Dictionaries
+//!
+//! A small value compresses poorly on its own because there is too little in it to learn from.  A
+//! dictionary trained on samples of similar values primes the compressor, so that even tiny values
+//! compress meaningfully.  The id of the dictionary is stored in the entry's metadata, and a client
+//! that does not have the dictionary fetches it from the servers.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read};
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use zstd::bulk::Compressor;
+use zstd::dict::{DecoderDictionary, EncoderDictionary};
+use zstd::stream::read::Decoder;
+
+use g1_base::sync::MutexExt;
+
+use ddcache_rpc::DictionaryId;
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Dictionaries(Arc<Mutex<Inner>>);
+
+#[derive(Debug, Default)]
+struct Inner {
+    dictionaries: HashMap<DictionaryId, Arc<Dictionary>>,
+    // The dictionary that we compress new values with.
+    current: Option<DictionaryId>,
+}
+
+pub(crate) struct Dictionary {
+    id: DictionaryId,
+    dictionary: Bytes,
+    encoder: EncoderDictionary<'static>,
+    decoder: DecoderDictionary<'static>,
+}
+
+/// Trains a dictionary of at most `max_size` bytes on samples of the values to be compressed.
+pub fn train_dictionary<S>(samples: &[S], max_size: usize) -> Result<Bytes, io::Error>
+where
+    S: AsRef<[u8]>,
+{
+    zstd::dict::from_samples(samples, max_size).map(Bytes::from)
+}
+
+impl Dictionaries {
+    pub(crate) fn get(&self, id: DictionaryId) -> Option<Arc<Dictionary>> {
+        self.0.must_lock().dictionaries.get(&id).cloned()
+    }
+
+    pub(crate) fn insert(&self, dictionary: Arc<Dictionary>) {
+        self.0
+            .must_lock()
+            .dictionaries
+            .insert(dictionary.id, dictionary);
+    }
+
+    pub(crate) fn current(&self) -> Option<DictionaryId> {
+        self.0.must_lock().current
+    }
+
+    pub(crate) fn set_current(&self, id: Option<DictionaryId>) -> Option<DictionaryId> {
+        mem::replace(&mut self.0.must_lock().current, id)
+    }
+}
+
+impl fmt::Debug for Dictionary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Dictionary")
+            .field("id", &self.id)
+            .field("size", &self.dictionary.len())
+            .finish()
+    }
+}
+
+impl Dictionary {
+    /// Parses the dictionary id from the dictionary header.
+    ///
+    /// It returns `None` for a raw-content dictionary, which has no header.
+    pub(crate) fn parse_id(dictionary: &[u8]) -> Option<DictionaryId> {
+        zstd::zstd_safe::get_dict_id_from_dict(dictionary).map(|id| id.get())
+    }
+
+    pub(crate) fn new(id: DictionaryId, dictionary: Bytes) -> Self {
+        Self {
+            id,
+            encoder: EncoderDictionary::copy(&dictionary, zstd::DEFAULT_COMPRESSION_LEVEL),
+            decoder: DecoderDictionary::copy(&dictionary),
+            dictionary,
+        }
+    }
+
+    /// Compresses the value, or returns `None` if compression does not make it smaller.
+    pub(crate) fn compress(&self, value: &[u8]) -> Result<Option<Vec<u8>>, io::Error> {
+        let compressed = Compressor::with_prepared_dictionary(&self.encoder)?.compress(value)?;
+        Ok((compressed.len() < value.len()).then_some(compressed))
+    }
+
+    pub(crate) fn decompress(&self, compressed: &[u8]) -> Result<Vec<u8>, io::Error> {
+        let mut value = Vec::new();
+        Decoder::with_prepared_dictionary(compressed, &self.decoder)?.read_to_end(&mut value)?;
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_samples() -> Vec<Vec<u8>> {
+        (0..1000)
+            .map(|i| {
+                format!(r#"{{"id": {i}, "name": "user-{i}", "email": "user-{i}@example.com"}}"#)
+                    .into_bytes()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn compress() {
+        let samples = new_samples();
+        let raw = train_dictionary(&samples, 4096).unwrap();
+        let id = Dictionary::parse_id(&raw).unwrap();
+        let dictionary = Dictionary::new(id, raw);
+
+        let value = br#"{"id": 1234, "name": "user-1234", "email": "user-1234@example.com"}"#;
+        let compressed = dictionary.compress(value).unwrap().unwrap();
+        assert!(compressed.len() < value.len() / 2, "{}", compressed.len());
+        assert_eq!(dictionary.decompress(&compressed).unwrap(), value);
+
+        // Compression does not help an empty value.
+        assert_eq!(dictionary.compress(b"").unwrap(), None);
+
+        assert_eq!(Dictionary::parse_id(b"raw content"), None);
+        assert!(dictionary.decompress(b"not zstd").is_err());
+    }
+
+    #[test]
+    fn dictionaries() {
+        let dictionaries = Dictionaries::default();
+        assert_eq!(dictionaries.current(), None);
+        assert_eq!(dictionaries.set_current(Some(1)), None);
+        assert_eq!(dictionaries.set_current(None), Some(1));
+
+        assert!(dictionaries.get(1).is_none());
+        dictionaries.insert(Arc::new(Dictionary::new(1, Bytes::from_static(b"foo"))));
+        assert_eq!(dictionaries.get(1).unwrap().id, 1);
+    }
+}
@@ -1,14 +1,47 @@
+use std::io;
+use std::time::Duration;
+
 use snafu::prelude::*;
 
 use ddcache_client_service::NotConnectedError;
+use ddcache_rpc::DictionaryId;
 
 #[derive(Debug, Snafu)]
 #[snafu(visibility(pub(crate)))]
 pub enum Error {
     #[snafu(display("not connected to any shard"))]
     NotConnected,
+    #[snafu(display("only {num_ready} of {min_shards} shards are ready"))]
+    NotReady { num_ready: usize, min_shards: usize },
     #[snafu(display("request error: {source}"))]
     Request { source: ddcache_client_raw::Error },
+    #[snafu(display("expect dictionary id in dictionary header"))]
+    InvalidDictionary,
+    #[snafu(display("dictionary not found: {id}"))]
+    DictionaryNotFound { id: DictionaryId },
+    #[snafu(display("zstd error: {source}"))]
+    Zstd { source: io::Error },
+}
+
+impl Error {
+    /// Returns true if the request may succeed when retried as is.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            // We may connect to a shard later.
+            Self::NotConnected => true,
+            Self::NotReady { .. } => true,
+            Self::Request { source } => source.is_retryable(),
+            Self::InvalidDictionary | Self::DictionaryNotFound { .. } | Self::Zstd { .. } => false,
+        }
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::NotConnected | Self::NotReady { .. } => None,
+            Self::Request { source } => source.retry_after(),
+            Self::InvalidDictionary | Self::DictionaryNotFound { .. } | Self::Zstd { .. } => None,
+        }
+    }
 }
 
 impl From<NotConnectedError> for Error {
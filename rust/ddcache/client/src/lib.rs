@@ -1,9 +1,15 @@
 #![feature(try_blocks)]
 
 mod client;
+mod dict;
 mod error;
+mod retry;
 
-pub use ddcache_rpc::{BlobMetadata, Timestamp};
+pub use ddcache_client_service::Pin;
+pub use ddcache_rpc::service::Routing;
+pub use ddcache_rpc::{BlobMetadata, DictionaryId, Temperature, Timestamp};
 
-pub use crate::client::{Client, ClientGuard};
+pub use crate::client::{Client, ClientGuard, Routes, Shard};
+pub use crate::dict::train_dictionary;
 pub use crate::error::Error;
+pub use crate::retry::{Operation, RetryPolicy, RetryPolicyBuilder};
@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::error::Error;
+
+/// Client operations whose retry policy may be overridden.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Operation {
+    ReadMetadata,
+    ReadValue,
+    WriteAll,
+    WriteMetadata,
+    WriteValue,
+    Remove,
+    InstallDictionary,
+    FindByMetadata,
+    Scan,
+}
+
+/// Decides whether and when a failed operation is retried.
+///
+/// The default policy does not retry at all.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    max_attempts: usize,
+    backoff_base: Duration,
+    backoff_max: Duration,
+    retry_if: fn(&Error) -> bool,
+    overrides: Arc<HashMap<Operation, RetryPolicy>>,
+}
+
+#[derive(Debug)]
+pub struct RetryPolicyBuilder {
+    max_attempts: usize,
+    backoff_base: Duration,
+    backoff_max: Duration,
+    retry_if: fn(&Error) -> bool,
+    overrides: HashMap<Operation, RetryPolicy>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicyBuilder::new().build()
+    }
+}
+
+impl RetryPolicy {
+    pub fn builder() -> RetryPolicyBuilder {
+        RetryPolicyBuilder::new()
+    }
+
+    /// Returns the policy of the operation, which is either its override or `self`.
+    pub fn get(&self, operation: Operation) -> &Self {
+        self.overrides.get(&operation).unwrap_or(self)
+    }
+
+    /// Returns how long to wait before the next attempt, or `None` if we should give up.
+    ///
+    /// `attempt` is the 1-based number of the attempt that failed with `error`.
+    pub(crate) fn backoff(&self, attempt: usize, error: &Error) -> Option<Duration> {
+        if attempt >= self.max_attempts || !(self.retry_if)(error) {
+            return None;
+        }
+        // A server-provided hint takes precedence over our exponential backoff.
+        Some(error.retry_after().unwrap_or_else(|| {
+            let exp = u32::try_from(attempt - 1).unwrap_or(u32::MAX);
+            self.backoff_base
+                .saturating_mul(2u32.saturating_pow(exp))
+                .min(self.backoff_max)
+        }))
+    }
+}
+
+impl Default for RetryPolicyBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RetryPolicyBuilder {
+    pub fn new() -> Self {
+        Self {
+            max_attempts: 1,
+            backoff_base: Duration::from_millis(100),
+            backoff_max: Duration::from_secs(5),
+            retry_if: Error::is_retryable,
+            overrides: HashMap::new(),
+        }
+    }
+
+    /// Sets the total number of attempts, including the first one.
+    pub fn max_attempts(mut self, max_attempts: usize) -> Self {
+        assert!(max_attempts > 0);
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// Sets the exponential backoff, which starts at `base` and doubles up to `max`.
+    pub fn backoff(mut self, base: Duration, max: Duration) -> Self {
+        assert!(base <= max);
+        self.backoff_base = base;
+        self.backoff_max = max;
+        self
+    }
+
+    /// Sets the predicate of retryable errors, which defaults to `Error::is_retryable`.
+    pub fn retry_if(mut self, retry_if: fn(&Error) -> bool) -> Self {
+        self.retry_if = retry_if;
+        self
+    }
+
+    /// Overrides the policy of an operation.
+    ///
+    /// The overrides of `policy` itself are ignored.
+    pub fn override_for(mut self, operation: Operation, policy: RetryPolicy) -> Self {
+        self.overrides.insert(operation, policy);
+        self
+    }
+
+    pub fn build(self) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: self.max_attempts,
+            backoff_base: self.backoff_base,
+            backoff_max: self.backoff_max,
+            retry_if: self.retry_if,
+            overrides: Arc::new(self.overrides),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.backoff(1, &Error::NotConnected), None);
+
+        let policy = RetryPolicy::builder()
+            .max_attempts(5)
+            .backoff(Duration::from_millis(100), Duration::from_millis(300))
+            .build();
+        assert_eq!(
+            policy.backoff(1, &Error::NotConnected),
+            Some(Duration::from_millis(100)),
+        );
+        assert_eq!(
+            policy.backoff(2, &Error::NotConnected),
+            Some(Duration::from_millis(200)),
+        );
+        assert_eq!(
+            policy.backoff(3, &Error::NotConnected),
+            Some(Duration::from_millis(300)),
+        );
+        assert_eq!(
+            policy.backoff(4, &Error::NotConnected),
+            Some(Duration::from_millis(300)),
+        );
+        assert_eq!(policy.backoff(5, &Error::NotConnected), None);
+        assert_eq!(policy.backoff(1, &Error::InvalidDictionary), None);
+
+        let policy = RetryPolicy::builder()
+            .max_attempts(5)
+            .retry_if(|error| matches!(error, Error::InvalidDictionary))
+            .build();
+        assert_eq!(policy.backoff(1, &Error::NotConnected), None);
+        assert_eq!(
+            policy.backoff(1, &Error::InvalidDictionary),
+            Some(Duration::from_millis(100)),
+        );
+    }
+
+    #[test]
+    fn get() {
+        let policy = RetryPolicy::builder()
+            .max_attempts(3)
+            .override_for(Operation::Remove, RetryPolicy::default())
+            .build();
+        assert!(policy
+            .get(Operation::Scan)
+            .backoff(1, &Error::NotConnected)
+            .is_some());
+        assert!(policy
+            .get(Operation::Remove)
+            .backoff(1, &Error::NotConnected)
+            .is_none());
+    }
+}
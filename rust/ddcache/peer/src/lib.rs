@@ -5,17 +5,19 @@ use std::sync::Arc;
 
 use bytes::Bytes;
 use snafu::prelude::*;
-use tokio::sync::{broadcast::error::RecvError, mpsc, OwnedSemaphorePermit, Semaphore};
+use tokio::sync::{broadcast::error::RecvError, OwnedSemaphorePermit, Semaphore};
 use tracing::Instrument;
 use uuid::Uuid;
 
 use etcd_pubsub::SubscriberError;
 use g1_base::future::ReadyQueue;
-use g1_tokio::task::{Cancel, JoinArray, JoinGuard, JoinQueue};
+use g1_tokio::sync::metered::mpsc;
+use g1_tokio::task::{Cancel, JoinArray, JoinGuard, JoinQueue, LoopExit};
 
 use ddcache_client_raw::{concurrent, Error, RawClient};
 use ddcache_client_service::{NotConnectedError, Service, Update, UpdateRecv};
 use ddcache_rpc::service::{self, PubSub};
+use ddcache_rpc::Temperature;
 use ddcache_storage::Storage;
 
 g1_param::define!(push_concurrency: usize = 32);
@@ -73,7 +75,7 @@ impl Peer {
         pubsub: PubSub,
         storage: Storage,
     ) -> Result<(Self, PeerGuard), SubscriberError> {
-        let (pull_send, pull_recv) = mpsc::channel(16);
+        let (pull_send, pull_recv) = mpsc::channel("ddcache/peer-pull", 16);
 
         let spawn = Service::prepare(Some(self_id), pubsub).await?;
         let update_recv = spawn.subscribe();
@@ -123,12 +125,15 @@ impl Actor {
     }
 
     async fn run(mut self) -> Result<(), SubscriberError> {
-        loop {
-            tokio::select! {
-                () = self.cancel.wait() => break,
-
+        let cancel = self.cancel.clone();
+        let result = g1_tokio::actor_loop! {
+            cancel: cancel,
+            error: SubscriberError,
+            select {
                 key = self.pull_recv.recv() => {
-                    let Some(key) = key else { break };
+                    let Some(key) = key else {
+                        break Ok(LoopExit::Closed("pull_recv"));
+                    };
                     self.handle_pull(key);
                 }
 
@@ -139,23 +144,25 @@ impl Actor {
                             // TODO: Should we return an error instead?
                             tracing::warn!(num_skipped, "lag behind on service updates");
                         }
-                        Err(RecvError::Closed) => break,
+                        Err(RecvError::Closed) => break Ok(LoopExit::Closed("update_recv")),
                     }
                 }
 
                 guard = self.tasks.join_next() => {
-                    let Some(guard) = guard else { break };
+                    let Some(guard) = guard else {
+                        break Ok(LoopExit::Closed("tasks"));
+                    };
                     self.handle_task(guard);
                 }
             }
-        }
-
-        self.tasks.cancel();
-        while let Some(guard) = self.tasks.join_next().await {
-            self.handle_task(guard);
-        }
-
-        Ok(())
+            cleanup {
+                self.tasks.cancel();
+                while let Some(guard) = self.tasks.join_next().await {
+                    self.handle_task(guard);
+                }
+            }
+        };
+        result.map(|_| ())
     }
 
     fn handle_pull(&self, key: Bytes) {
@@ -254,14 +261,14 @@ impl Handler {
             })
             .await?;
 
-            let Some((id, client, response)) = response else {
+            let Some((id, _, response)) = response else {
                 return Ok(());
             };
             let metadata = metadata!(response)?;
             let blob = blob!(response)?;
 
             let Some(mut writer) = self.storage.write_new(key) else {
-                if let Err(error) = client.cancel(blob.token()).await {
+                if let Err(error) = blob.cancel().await {
                     tracing::warn!(%id, %error, "cancel");
                 }
                 return Ok(());
@@ -269,12 +276,16 @@ impl Handler {
 
             writer.set_metadata(metadata.metadata);
             writer.set_expire_at(metadata.expire_at);
+            writer.set_stale_at(metadata.stale_at);
+            writer.set_dictionary(metadata.dictionary);
+            // TODO: Pull responses do not carry the temperature hint yet, and thus pulled entries
+            // are always normal.
 
             let output = match writer.open() {
                 Ok(output) => output,
                 Err(error) => {
                     drop(writer);
-                    if let Err(error) = client.cancel(blob.token()).await {
+                    if let Err(error) = blob.cancel().await {
                         tracing::warn!(%id, %error, "cancel");
                     }
                     return Err(HandlerError::Storage { source: error });
@@ -308,7 +319,11 @@ impl Handler {
 
         let keys = keys.filter(move |key| {
             // Check whether the target server is a designated replica of `key`.
-            servers.sort_by_key(service::rendezvous_sorting_by_key(key, |(id, _)| *id));
+            servers.sort_by_key(service::rendezvous_sorting_by_key(
+                self.service.routing(),
+                key,
+                |(id, _)| *id,
+            ));
             servers
                 .iter()
                 .take(self.num_replicas)
@@ -358,7 +373,15 @@ impl Handler {
         tracing::debug!(key = %key.escape_ascii());
         let result: Result<bool, Error> = try {
             let Some(response) = client
-                .push(key, reader.metadata(), size, reader.expire_at())
+                .push(
+                    key,
+                    reader.metadata(),
+                    size,
+                    reader.expire_at(),
+                    reader.stale_at(),
+                    to_temperature(reader.temperature()),
+                    reader.dictionary(),
+                )
                 .await?
             else {
                 return Ok(false);
@@ -370,7 +393,7 @@ impl Handler {
                 Err(error) => {
                     drop(reader);
                     drop(permit);
-                    if let Err(error) = client.cancel(blob.token()).await {
+                    if let Err(error) = blob.cancel().await {
                         tracing::warn!(%error, "cancel");
                     }
                     return Err(HandlerError::Storage { source: error });
@@ -386,9 +409,18 @@ impl Handler {
 
     async fn cleanup(&self) -> Result<(), HandlerError> {
         let mut servers = self.service.all()?;
+        if !servers.iter().any(|(id, _)| id == &self.self_id) {
+            // We are not published yet (e.g., we are a warm standby), and every key would look like
+            // one that we are not a designated replica of.
+            return Ok(());
+        }
         let keys = self.storage.keys().into_iter().filter(move |key| {
             // Check whether we are not a designated replica of `key`.
-            servers.sort_by_key(service::rendezvous_sorting_by_key(key, |(id, _)| *id));
+            servers.sort_by_key(service::rendezvous_sorting_by_key(
+                self.service.routing(),
+                key,
+                |(id, _)| *id,
+            ));
             !servers
                 .iter()
                 .take(self.num_replicas)
@@ -404,3 +436,11 @@ impl Handler {
         Ok(())
     }
 }
+
+fn to_temperature(temperature: ddcache_storage::Temperature) -> Temperature {
+    match temperature {
+        ddcache_storage::Temperature::Cold => Temperature::Cold,
+        ddcache_storage::Temperature::Normal => Temperature::Normal,
+        ddcache_storage::Temperature::Hot => Temperature::Hot,
+    }
+}
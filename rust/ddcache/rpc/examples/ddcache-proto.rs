@@ -16,7 +16,9 @@ use g1_cli::{param::ParametersConfig, tracing::TracingConfig};
 use g1_zmq::Socket;
 
 use ddcache_client_raw::{Error, RawNaiveClient};
-use ddcache_rpc::{Endpoint, RequestOwner, ResponseBuilder, Timestamp, Token};
+use ddcache_rpc::{
+    DictionaryId, Endpoint, RequestOwner, ResponseBuilder, Temperature, Timestamp, Token,
+};
 
 #[derive(Debug, Parser)]
 #[command(after_help = ParametersConfig::render())]
@@ -45,6 +47,7 @@ enum Command {
     Write(Write),
     WriteMetadata(WriteMetadata),
     Remove(Remove),
+    Find(Find),
 
     Pull(Pull),
     Push(Push),
@@ -75,6 +78,12 @@ struct Write {
     metadata: Option<Bytes>,
     #[arg(long)]
     expire_at: Option<Timestamp>,
+    #[arg(long)]
+    stale_at: Option<Timestamp>,
+    #[arg(long, default_value = "normal")]
+    temperature: Temperature,
+    #[arg(long)]
+    dictionary: Option<DictionaryId>,
     file: PathBuf,
 }
 
@@ -85,6 +94,8 @@ struct WriteMetadata {
     metadata: Option<Option<Bytes>>,
     #[arg(long)]
     expire_at: Option<Option<Timestamp>>,
+    #[arg(long)]
+    stale_at: Option<Option<Timestamp>>,
 }
 
 #[derive(Args, Debug)]
@@ -92,6 +103,15 @@ struct Remove {
     key: Bytes,
 }
 
+#[derive(Args, Debug)]
+struct Find {
+    namespace: Bytes,
+    field: Bytes,
+    value: Bytes,
+    #[arg(long, default_value = "100")]
+    limit: usize,
+}
+
 #[derive(Args, Debug)]
 struct Pull {
     key: Bytes,
@@ -105,6 +125,12 @@ struct Push {
     metadata: Option<Bytes>,
     #[arg(long)]
     expire_at: Option<Timestamp>,
+    #[arg(long)]
+    stale_at: Option<Timestamp>,
+    #[arg(long, default_value = "normal")]
+    temperature: Temperature,
+    #[arg(long)]
+    dictionary: Option<DictionaryId>,
     file: PathBuf,
 }
 
@@ -117,6 +143,7 @@ impl Program {
             Command::Write(write) => self.write(write).await?,
             Command::WriteMetadata(write_metadata) => self.write_metadata(write_metadata).await?,
             Command::Remove(remove) => self.remove(remove).await?,
+            Command::Find(find) => self.find(find).await?,
 
             Command::Pull(pull) => self.pull(pull).await?,
             Command::Push(push) => self.push(push).await?,
@@ -179,6 +206,9 @@ impl Program {
                 write.metadata.clone(),
                 size,
                 write.expire_at,
+                write.stale_at,
+                write.temperature,
+                write.dictionary,
             )
             .await?;
         eprintln!("write: {:?}", response);
@@ -202,6 +232,7 @@ impl Program {
                 write_metadata.key.clone(),
                 write_metadata.metadata.clone(),
                 write_metadata.expire_at.clone(),
+                write_metadata.stale_at.clone(),
             )
             .await?;
         eprintln!("write_metadata: {:?}", response);
@@ -217,6 +248,20 @@ impl Program {
         Ok(())
     }
 
+    async fn find(&self, find: &Find) -> Result<(), Error> {
+        let response = RawNaiveClient::connect(self.endpoint.clone())
+            .unwrap()
+            .find(
+                find.namespace.clone(),
+                find.field.clone(),
+                find.value.clone(),
+                find.limit,
+            )
+            .await?;
+        eprintln!("find: {:?}", response);
+        Ok(())
+    }
+
     async fn pull(&self, pull: &Pull) -> Result<(), Error> {
         let response = RawNaiveClient::connect(self.endpoint.clone())
             .unwrap()
@@ -252,6 +297,9 @@ impl Program {
                 push.metadata.clone(),
                 size,
                 push.expire_at,
+                push.stale_at,
+                push.temperature,
+                push.dictionary,
             )
             .await?;
         eprintln!("push: {:?}", response);
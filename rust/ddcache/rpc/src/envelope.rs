@@ -3,6 +3,7 @@ use capnp::serialize;
 use snafu::prelude::*;
 
 use g1_zmq::envelope::{Envelope, Frame, Multipart};
+use g1_zmq::rpc::{self, DecodeError};
 
 use crate::{RequestOwner, ResponseOwner, ResponseResult, ResponseResultOwner};
 
@@ -36,23 +37,23 @@ pub fn decode_request(frames: Multipart) -> Result<Envelope<RequestOwner>, Error
 }
 
 pub fn decode(frames: Multipart) -> Result<Envelope<Frame>, Error> {
-    Envelope::try_from(frames).map_err(|frames| match Envelope::try_from(frames) {
-        Ok(envelope) => Error::ExpectOneDataFrame { envelope },
-        Err(frames) => Error::InvalidFrameSequence { frames },
+    rpc::decode(frames).map_err(|error| match error {
+        DecodeError::ExpectOneDataFrame { envelope } => Error::ExpectOneDataFrame { envelope },
+        DecodeError::InvalidFrameSequence { frames } => Error::InvalidFrameSequence { frames },
     })
 }
 
 pub fn decode_response(
     response: Envelope<Frame>,
 ) -> Result<Envelope<ResponseResultOwner>, capnp::Error> {
-    response
-        .map(ResponseOwner::try_from)
-        .transpose()?
-        .map(|data| {
-            // It is safe to `transpose` because `E` is `capnp::Error`.
-            unsafe { data.map(ResponseResult::try_from).transpose() }
-        })
-        .transpose()
+    response.map(decode_response_data).transpose()
+}
+
+/// Decodes the data frame of a response envelope.
+pub fn decode_response_data(data: Frame) -> Result<ResponseResultOwner, capnp::Error> {
+    let data = ResponseOwner::try_from(data)?;
+    // It is safe to `transpose` because `E` is `capnp::Error`.
+    unsafe { data.map(ResponseResult::try_from).transpose() }
 }
 
 pub fn encode<A>(envelope: Envelope<message::Builder<A>>) -> Envelope<Frame>
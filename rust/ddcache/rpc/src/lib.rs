@@ -9,7 +9,9 @@ pub mod envelope;
 pub mod service;
 
 use std::net::SocketAddr;
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
 use bytes::Bytes;
 use capnp::message;
@@ -18,10 +20,13 @@ use capnp::serialize;
 use g1_capnp::{owner::Owner, result_capnp::result};
 use g1_zmq::envelope::Frame;
 
-use crate::rpc_capnp::{endpoint, error, request, response};
+use crate::rpc_capnp::{change_cursor, endpoint, error, request, response};
 
 // TODO: Should we store this value in etcd instead?
 g1_param::define!(pub num_replicas: usize = 2);
+g1_param::define!(pub routing: service::Routing = service::Routing::V1);
+// Set this to the previous routing strategy while migrating to a new one.
+g1_param::define!(pub routing_migrate_from: Option<service::Routing> = None);
 
 pub type Endpoint = Arc<str>;
 pub type BlobEndpoint = SocketAddr;
@@ -30,6 +35,9 @@ pub use g1_chrono::{Timestamp, TimestampExt};
 
 pub type Token = u64;
 
+/// Id of a zstd dictionary; see `dictionary` in `storage.capnp`.
+pub type DictionaryId = u32;
+
 pub type RequestOwner<Buffer = Frame> = Owner<Buffer, request::Reader<'static>>;
 pub type ResponseOwner<Buffer = Frame> = Owner<Buffer, ResponseReader<'static>>;
 pub type ResponseResultOwner<Buffer = Frame> = Owner<Buffer, ResponseResult<'static>>;
@@ -56,11 +64,15 @@ pub enum Request {
         metadata: Option<Bytes>,
         size: usize,
         expire_at: Option<Timestamp>,
+        stale_at: Option<Timestamp>,
+        temperature: Temperature,
+        dictionary: Option<DictionaryId>,
     },
     WriteMetadata {
         key: Bytes,
         metadata: Option<Option<Bytes>>,
         expire_at: Option<Option<Timestamp>>,
+        stale_at: Option<Option<Timestamp>>,
     },
     Remove {
         key: Bytes,
@@ -77,6 +89,37 @@ pub enum Request {
         metadata: Option<Bytes>,
         size: usize,
         expire_at: Option<Timestamp>,
+        stale_at: Option<Timestamp>,
+        temperature: Temperature,
+        dictionary: Option<DictionaryId>,
+    },
+    Changes {
+        cursor: Option<ChangeCursor>,
+        limit: usize,
+    },
+
+    Ping,
+
+    //
+    // Admin Protocol
+    //
+    Fsck {
+        repair: FsckRepair,
+    },
+    Stats,
+    InstallDictionary {
+        id: DictionaryId,
+        dictionary: Bytes,
+    },
+    ReadDictionary {
+        id: DictionaryId,
+    },
+
+    Find {
+        namespace: Bytes,
+        field: Bytes,
+        value: Bytes,
+        limit: usize,
     },
 }
 
@@ -107,13 +150,47 @@ pub enum Response {
     Push {
         blob: BlobRequest,
     },
+    Changes {
+        changes: Changes,
+    },
+
+    Ping,
+
+    Fsck {
+        report: FsckReport,
+    },
+    Stats {
+        stats: WorkloadStats,
+    },
+    InstallDictionary,
+    ReadDictionary {
+        dictionary: Bytes,
+    },
+
+    Find {
+        keys: Vec<Bytes>,
+    },
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct BlobMetadata {
     pub metadata: Option<Bytes>,
     pub size: usize,
+    /// Hard deadline, after which the entry is removed.
     pub expire_at: Option<Timestamp>,
+    /// Soft deadline, after which the entry is still served but is stale.
+    pub stale_at: Option<Timestamp>,
+    /// The blob content is compressed with this dictionary.
+    pub dictionary: Option<DictionaryId>,
+}
+
+/// A writer's hint of how expensive an entry is to recompute; see `ddcache_storage::Temperature`.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum Temperature {
+    Cold,
+    #[default]
+    Normal,
+    Hot,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -122,6 +199,48 @@ pub struct BlobRequest {
     pub token: Token,
 }
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ChangeCursor {
+    pub epoch: u64,
+    pub sequence: u64,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Changes {
+    pub cursor: ChangeCursor,
+    pub reset: bool,
+    pub keys: Vec<Bytes>,
+    pub lag: u64,
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct FsckRepair {
+    pub drop_entry: bool,
+    pub adopt_orphan: bool,
+    pub recompute_size: bool,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct FsckReport {
+    pub num_entries: u64,
+    pub num_busy: u64,
+    pub num_missing: u64,
+    pub num_size_mismatch: u64,
+    pub num_orphan: u64,
+    pub num_repaired: u64,
+}
+
+/// Approximate workload statistics of a server's current window.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct WorkloadStats {
+    pub window_elapsed: Duration,
+    pub num_distinct_keys: u64,
+    /// Number of writes by blob size; see `Response.Stats` in `rpc.capnp` for the buckets.
+    pub write_sizes: Vec<u64>,
+    /// Most frequently touched keys and their counts, in descending order of count.
+    pub top_keys: Vec<(Bytes, u64)>,
+}
+
 impl<'a> TryFrom<endpoint::Reader<'a>> for BlobEndpoint {
     type Error = capnp::Error;
 
@@ -166,6 +285,9 @@ impl<'a> TryFrom<request::Reader<'a>> for Request {
                     metadata: to_metadata(request.get_metadata()?),
                     size: to_size(request.get_size()),
                     expire_at: to_expire_at(request.get_expire_at())?,
+                    stale_at: to_expire_at(request.get_stale_at())?,
+                    temperature: request.get_temperature()?.into(),
+                    dictionary: to_dictionary(request.get_dictionary()),
                 }
             }
 
@@ -185,6 +307,12 @@ impl<'a> TryFrom<request::Reader<'a>> for Request {
                             Some(to_expire_at(expire_at)?)
                         }
                     },
+                    stale_at: match request.get_stale_at().which()? {
+                        request::write_metadata::stale_at::Dont(()) => None,
+                        request::write_metadata::stale_at::Write(stale_at) => {
+                            Some(to_expire_at(stale_at)?)
+                        }
+                    },
                 }
             }
 
@@ -203,6 +331,47 @@ impl<'a> TryFrom<request::Reader<'a>> for Request {
                     metadata: to_metadata(request.get_metadata()?),
                     size: to_size(request.get_size()),
                     expire_at: to_expire_at(request.get_expire_at())?,
+                    stale_at: to_expire_at(request.get_stale_at())?,
+                    temperature: request.get_temperature()?.into(),
+                    dictionary: to_dictionary(request.get_dictionary()),
+                }
+            }
+
+            request::Changes(request) => {
+                let request = request?;
+                Self::Changes {
+                    cursor: to_cursor(request.get_cursor()?)?,
+                    limit: to_size(request.get_limit()),
+                }
+            }
+
+            request::Ping(()) => Self::Ping,
+
+            request::Fsck(request) => Self::Fsck {
+                repair: request?.try_into()?,
+            },
+
+            request::Stats(()) => Self::Stats,
+
+            request::InstallDictionary(request) => {
+                let request = request?;
+                Self::InstallDictionary {
+                    id: to_dictionary_id(request.get_id())?,
+                    dictionary: Bytes::copy_from_slice(request.get_dictionary()?),
+                }
+            }
+
+            request::ReadDictionary(id) => Self::ReadDictionary {
+                id: to_dictionary_id(id)?,
+            },
+
+            request::Find(request) => {
+                let request = request?;
+                Self::Find {
+                    namespace: Bytes::copy_from_slice(request.get_namespace()?),
+                    field: to_key(request.get_field()?)?,
+                    value: Bytes::copy_from_slice(request.get_value()?),
+                    limit: to_size(request.get_limit()),
                 }
             }
         })
@@ -211,8 +380,17 @@ impl<'a> TryFrom<request::Reader<'a>> for Request {
 
 impl From<Request> for Vec<u8> {
     fn from(request: Request) -> Self {
+        request.encode(false)
+    }
+}
+
+impl Request {
+    /// Encodes the request; see `Request.stream` in `rpc.capnp`.
+    pub fn encode(&self, stream: bool) -> Vec<u8> {
         let mut message = message::Builder::new_default();
-        message.init_root::<request::Builder>().set(&request);
+        let mut request = message.init_root::<request::Builder>();
+        request.set(self);
+        request.set_stream(stream);
         serialize::write_message_to_words(&message)
     }
 }
@@ -238,6 +416,9 @@ impl request::Builder<'_> {
                 metadata,
                 size,
                 expire_at,
+                stale_at,
+                temperature,
+                dictionary,
             } => {
                 assert!(!key.is_empty());
                 let mut this = this.init_write();
@@ -245,12 +426,16 @@ impl request::Builder<'_> {
                 this.set_metadata(metadata.as_deref().unwrap_or(&[]));
                 this.set_size((*size).try_into().unwrap());
                 this.set_expire_at(expire_at.timestamp_u64());
+                this.set_stale_at(stale_at.timestamp_u64());
+                this.set_temperature((*temperature).into());
+                this.set_dictionary(dictionary.unwrap_or(0));
             }
 
             Request::WriteMetadata {
                 key,
                 metadata,
                 expire_at,
+                stale_at,
             } => {
                 assert!(!key.is_empty());
                 let mut this = this.init_write_metadata();
@@ -265,6 +450,11 @@ impl request::Builder<'_> {
                         .init_expire_at()
                         .set_write(expire_at.timestamp_u64());
                 }
+                if let Some(stale_at) = stale_at {
+                    this.reborrow()
+                        .init_stale_at()
+                        .set_write(stale_at.timestamp_u64());
+                }
             }
 
             Request::Remove { key } => {
@@ -282,6 +472,9 @@ impl request::Builder<'_> {
                 metadata,
                 size,
                 expire_at,
+                stale_at,
+                temperature,
+                dictionary,
             } => {
                 assert!(!key.is_empty());
                 let mut this = this.init_push();
@@ -289,6 +482,50 @@ impl request::Builder<'_> {
                 this.set_metadata(metadata.as_deref().unwrap_or(&[]));
                 this.set_size((*size).try_into().unwrap());
                 this.set_expire_at(expire_at.timestamp_u64());
+                this.set_stale_at(stale_at.timestamp_u64());
+                this.set_temperature((*temperature).into());
+                this.set_dictionary(dictionary.unwrap_or(0));
+            }
+
+            Request::Changes { cursor, limit } => {
+                let mut this = this.init_changes();
+                if let Some(cursor) = cursor {
+                    assert_ne!(cursor.epoch, 0);
+                    this.reborrow().init_cursor().set(cursor);
+                }
+                this.set_limit((*limit).try_into().unwrap());
+            }
+
+            Request::Ping => this.set_ping(()),
+
+            Request::Fsck { repair } => this.init_fsck().set(repair),
+
+            Request::Stats => this.set_stats(()),
+
+            Request::InstallDictionary { id, dictionary } => {
+                assert_ne!(*id, 0);
+                let mut this = this.init_install_dictionary();
+                this.set_id(*id);
+                this.set_dictionary(dictionary);
+            }
+
+            Request::ReadDictionary { id } => {
+                assert_ne!(*id, 0);
+                this.set_read_dictionary(*id);
+            }
+
+            Request::Find {
+                namespace,
+                field,
+                value,
+                limit,
+            } => {
+                assert!(!field.is_empty());
+                let mut this = this.init_find();
+                this.set_namespace(namespace);
+                this.set_field(field);
+                this.set_value(value);
+                this.set_limit((*limit).try_into().unwrap());
             }
         }
     }
@@ -336,6 +573,45 @@ impl<'a> TryFrom<response::Reader<'a>> for Response {
             response::Push(response) => Self::Push {
                 blob: response?.get_blob()?.try_into()?,
             },
+
+            response::Changes(response) => {
+                let response = response?;
+                Self::Changes {
+                    changes: Changes {
+                        cursor: response.get_cursor()?.try_into()?,
+                        reset: response.get_reset(),
+                        keys: response
+                            .get_keys()?
+                            .iter()
+                            .map(|key| to_key(key?))
+                            .collect::<Result<_, _>>()?,
+                        lag: response.get_lag(),
+                    },
+                }
+            }
+
+            response::Ping(()) => Self::Ping,
+
+            response::Fsck(response) => Self::Fsck {
+                report: response?.try_into()?,
+            },
+
+            response::Stats(response) => Self::Stats {
+                stats: response?.try_into()?,
+            },
+
+            response::InstallDictionary(()) => Self::InstallDictionary,
+
+            response::ReadDictionary(dictionary) => Self::ReadDictionary {
+                dictionary: Bytes::copy_from_slice(dictionary?),
+            },
+
+            response::Find(keys) => Self::Find {
+                keys: keys?
+                    .iter()
+                    .map(|key| to_key(key?))
+                    .collect::<Result<_, _>>()?,
+            },
         })
     }
 }
@@ -343,11 +619,17 @@ impl<'a> TryFrom<response::Reader<'a>> for Response {
 // Encodes as `Ok(Some(response))`.
 impl From<Response> for Vec<u8> {
     fn from(response: Response) -> Self {
+        response.encode(false)
+    }
+}
+
+impl Response {
+    /// Encodes the response; see `Response.more` in `rpc.capnp`.
+    pub fn encode(&self, more: bool) -> Vec<u8> {
         let mut message = message::Builder::new_default();
-        message
-            .init_root::<ResponseBuilder>()
-            .init_ok()
-            .set(&response);
+        let mut response = message.init_root::<ResponseBuilder>().init_ok();
+        response.set(self);
+        response.set_more(more);
         serialize::write_message_to_words(&message)
     }
 }
@@ -383,27 +665,68 @@ impl response::Builder<'_> {
             }
 
             Response::Push { blob } => this.init_push().init_blob().set(blob),
-        }
-    }
-}
 
-impl<'a> TryFrom<response::metadata::Reader<'a>> for BlobMetadata {
-    type Error = capnp::Error;
+            Response::Changes { changes } => {
+                let mut this = this.init_changes();
+                this.reborrow().init_cursor().set(&changes.cursor);
+                this.set_reset(changes.reset);
+                this.set_lag(changes.lag);
+                let mut keys = this.init_keys(changes.keys.len().try_into().unwrap());
+                for (i, key) in changes.keys.iter().enumerate() {
+                    keys.set(i.try_into().unwrap(), key);
+                }
+            }
 
-    fn try_from(metadata: response::metadata::Reader<'a>) -> Result<Self, Self::Error> {
-        Ok(Self {
-            metadata: to_metadata(metadata.get_metadata()?),
-            size: to_size(metadata.get_size()),
-            expire_at: to_expire_at(metadata.get_expire_at())?,
-        })
+            Response::Ping => this.set_ping(()),
+
+            Response::Fsck { report } => this.init_fsck().set(report),
+
+            Response::Stats { stats } => this.init_stats().set(stats),
+
+            Response::InstallDictionary => this.set_install_dictionary(()),
+
+            Response::ReadDictionary { dictionary } => this.set_read_dictionary(dictionary),
+
+            Response::Find { keys } => {
+                let mut this = this.init_find(keys.len().try_into().unwrap());
+                for (i, key) in keys.iter().enumerate() {
+                    this.set(i.try_into().unwrap(), key);
+                }
+            }
+        }
     }
 }
 
-impl response::metadata::Builder<'_> {
-    pub fn set(&mut self, metadata: &BlobMetadata) {
-        self.set_metadata(metadata.metadata.as_deref().unwrap_or(&[]));
-        self.set_size(metadata.size.try_into().unwrap());
-        self.set_expire_at(metadata.expire_at.timestamp_u64());
+g1_capnp::impl_conversion!(response::metadata => BlobMetadata {
+    metadata: (
+        |metadata: capnp::Result<&[u8]>| metadata.map(to_metadata),
+        from_metadata,
+    ),
+    size: (
+        |size| Ok(to_size(size)),
+        |size: &usize| u32::try_from(*size).unwrap(),
+    ),
+    expire_at: (
+        to_expire_at,
+        |expire_at: &Option<Timestamp>| expire_at.timestamp_u64(),
+    ),
+    stale_at: (
+        to_expire_at,
+        |stale_at: &Option<Timestamp>| stale_at.timestamp_u64(),
+    ),
+    dictionary: (
+        |dictionary| Ok(to_dictionary(dictionary)),
+        |dictionary: &Option<DictionaryId>| dictionary.unwrap_or(0),
+    ),
+});
+
+impl BlobMetadata {
+    /// True if the entry has passed its soft (or hard) deadline.
+    pub fn is_stale(&self, now: Timestamp) -> bool {
+        [self.stale_at, self.expire_at]
+            .into_iter()
+            .flatten()
+            .any(|deadline| deadline <= now)
     }
 }
 
@@ -425,6 +748,94 @@ impl response::blob_request::Builder<'_> {
     }
 }
 
+impl<'a> TryFrom<response::stats::Reader<'a>> for WorkloadStats {
+    type Error = capnp::Error;
+
+    fn try_from(stats: response::stats::Reader<'a>) -> Result<Self, Self::Error> {
+        Ok(Self {
+            window_elapsed: Duration::from_millis(stats.get_window_elapsed()),
+            num_distinct_keys: stats.get_num_distinct_keys(),
+            write_sizes: stats.get_write_sizes()?.iter().collect(),
+            top_keys: stats
+                .get_top_keys()?
+                .iter()
+                .map(|key_count| Ok((to_key(key_count.get_key()?)?, key_count.get_count())))
+                .collect::<Result<_, capnp::Error>>()?,
+        })
+    }
+}
+
+impl response::stats::Builder<'_> {
+    pub fn set(&mut self, stats: &WorkloadStats) {
+        self.set_window_elapsed(stats.window_elapsed.as_millis().try_into().unwrap());
+        self.set_num_distinct_keys(stats.num_distinct_keys);
+        let mut write_sizes = self
+            .reborrow()
+            .init_write_sizes(stats.write_sizes.len().try_into().unwrap());
+        for (i, count) in stats.write_sizes.iter().enumerate() {
+            write_sizes.set(i.try_into().unwrap(), *count);
+        }
+        let mut top_keys = self
+            .reborrow()
+            .init_top_keys(stats.top_keys.len().try_into().unwrap());
+        for (i, (key, count)) in stats.top_keys.iter().enumerate() {
+            let mut key_count = top_keys.reborrow().get(i.try_into().unwrap());
+            key_count.set_key(key);
+            key_count.set_count(*count);
+        }
+    }
+}
+
+impl FromStr for Temperature {
+    type Err = String;
+
+    fn from_str(temperature: &str) -> Result<Self, Self::Err> {
+        match temperature {
+            "cold" => Ok(Self::Cold),
+            "normal" => Ok(Self::Normal),
+            "hot" => Ok(Self::Hot),
+            _ => Err(format!("unknown temperature: {temperature}")),
+        }
+    }
+}
+
+impl From<rpc_capnp::Temperature> for Temperature {
+    fn from(temperature: rpc_capnp::Temperature) -> Self {
+        match temperature {
+            rpc_capnp::Temperature::Cold => Self::Cold,
+            rpc_capnp::Temperature::Normal => Self::Normal,
+            rpc_capnp::Temperature::Hot => Self::Hot,
+        }
+    }
+}
+
+impl From<Temperature> for rpc_capnp::Temperature {
+    fn from(temperature: Temperature) -> Self {
+        match temperature {
+            Temperature::Cold => Self::Cold,
+            Temperature::Normal => Self::Normal,
+            Temperature::Hot => Self::Hot,
+        }
+    }
+}
+
+g1_capnp::impl_conversion!(change_cursor => ChangeCursor { epoch, sequence });
+
+g1_capnp::impl_conversion!(request::fsck => FsckRepair {
+    drop_entry,
+    adopt_orphan,
+    recompute_size,
+});
+
+g1_capnp::impl_conversion!(response::fsck => FsckReport {
+    num_entries,
+    num_busy,
+    num_missing,
+    num_size_mismatch,
+    num_orphan,
+    num_repaired,
+});
+
 fn to_key(key: &[u8]) -> Result<Bytes, capnp::Error> {
     if key.is_empty() {
         Err(capnp::Error {
@@ -436,10 +847,30 @@ fn to_key(key: &[u8]) -> Result<Bytes, capnp::Error> {
     }
 }
 
+fn to_cursor(cursor: change_cursor::Reader) -> Result<Option<ChangeCursor>, capnp::Error> {
+    let cursor = ChangeCursor::try_from(cursor)?;
+    Ok((cursor.epoch != 0).then_some(cursor))
+}
+
 fn to_metadata(metadata: &[u8]) -> Option<Bytes> {
     (!metadata.is_empty()).then(|| Bytes::copy_from_slice(metadata))
 }
 
+fn from_metadata(metadata: &Option<Bytes>) -> &[u8] {
+    metadata.as_deref().unwrap_or(&[])
+}
+
+fn to_dictionary(dictionary: u32) -> Option<DictionaryId> {
+    (dictionary != 0).then_some(dictionary)
+}
+
+fn to_dictionary_id(id: u32) -> Result<DictionaryId, capnp::Error> {
+    to_dictionary(id).ok_or_else(|| capnp::Error {
+        kind: capnp::ErrorKind::Failed,
+        extra: "zero dictionary id".to_string(),
+    })
+}
+
 fn to_size(size: u32) -> usize {
     size.try_into().unwrap()
 }
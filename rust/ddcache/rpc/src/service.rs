@@ -2,7 +2,7 @@ use std::cmp::Reverse;
 use std::hash::Hasher;
 use std::sync::Arc;
 
-use fasthash::{CityHasher, FastHasher};
+use fasthash::{CityHasher, FastHasher, XXHasher};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
@@ -25,27 +25,88 @@ pub fn pubsub() -> PubSub {
 #[serde(default)]
 pub struct Server {
     pub endpoints: Vec<String>,
+    /// Routing strategy that this server uses.
+    ///
+    /// Servers that predate routing versioning do not announce it, and they use `V1`.
+    pub routing: Routing,
+}
+
+/// Key-to-shard routing strategy.
+///
+/// Changing the strategy remaps nearly all keys; clients and servers must agree on it.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Routing {
+    /// Rendezvous hashing with CityHash.
+    #[default]
+    V1,
+    /// Rendezvous hashing with xxHash.
+    V2,
 }
 
 impl<'a> From<&'a [Endpoint]> for Server {
     fn from(endpoints: &'a [Endpoint]) -> Self {
         Self {
             endpoints: endpoints.iter().map(|e| e.to_string()).collect(),
+            routing: *crate::routing(),
         }
     }
 }
 
 // Sort in descending order.
 pub fn rendezvous_sorting_by_key<'a, T>(
+    routing: Routing,
     key: &'a [u8],
     mut server_id: impl FnMut(&T) -> Uuid + 'a,
 ) -> impl FnMut(&T) -> Reverse<u64> + 'a {
-    move |server| Reverse(rendezvous_hash(key, server_id(server)))
+    move |server| Reverse(rendezvous_hash(routing, key, server_id(server)))
+}
+
+pub fn rendezvous_hash(routing: Routing, key: &[u8], server_id: Uuid) -> u64 {
+    fn hash<H: FastHasher>(key: &[u8], server_id: Uuid) -> u64 {
+        let mut hasher = H::new();
+        hasher.write(key);
+        hasher.write(server_id.as_bytes());
+        hasher.finish()
+    }
+
+    match routing {
+        Routing::V1 => hash::<CityHasher>(key, server_id),
+        Routing::V2 => hash::<XXHasher>(key, server_id),
+    }
 }
 
-pub fn rendezvous_hash(key: &[u8], server_id: Uuid) -> u64 {
-    let mut hasher = CityHasher::new();
-    hasher.write(key);
-    hasher.write(server_id.as_bytes());
-    hasher.finish()
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn server() {
+        let server: Server =
+            serde_json::from_str(r#"{"endpoints":["tcp://127.0.0.1:0"]}"#).unwrap();
+        assert_eq!(server.routing, Routing::V1);
+
+        let server = Server {
+            endpoints: Vec::new(),
+            routing: Routing::V2,
+        };
+        let json = serde_json::to_string(&server).unwrap();
+        assert_eq!(json, r#"{"endpoints":[],"routing":"v2"}"#);
+        assert_eq!(serde_json::from_str::<Server>(&json).unwrap(), server);
+    }
+
+    #[test]
+    fn test_rendezvous_hash() {
+        let id = Uuid::from_u128(1);
+        for routing in [Routing::V1, Routing::V2] {
+            assert_eq!(
+                rendezvous_hash(routing, b"foo", id),
+                rendezvous_hash(routing, b"foo", id),
+            );
+        }
+        assert_ne!(
+            rendezvous_hash(Routing::V1, b"foo", id),
+            rendezvous_hash(Routing::V2, b"foo", id),
+        );
+    }
 }
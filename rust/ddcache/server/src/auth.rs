@@ -0,0 +1,371 @@
+//! Per-Endpoint Authentication
+//!
+//! Each bound endpoint may have its own security policy; for example, a server may require CURVE
+//! on a TCP endpoint for remote clients while leaving an IPC endpoint open to colocated processes.
+//! Client allowlists are enforced by a ZeroMQ Authentication Protocol (ZAP, RFC 27) handler, which
+//! keys the policies by the ZAP domain, which we set to the endpoint.  The allowlists may be
+//! reloaded at runtime, but the rest of a policy is captured when the endpoint is bound.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Error;
+use std::sync::{Arc, Mutex};
+
+use serde::Deserialize;
+use zmq::{Context, REP, SNDMORE};
+
+use g1_base::sync::MutexExt;
+use g1_tokio::task::Cancel;
+use g1_zmq::Socket;
+
+use crate::Guard;
+
+const ZAP_ENDPOINT: &str = "inproc://zeromq.zap.01";
+const ZAP_VERSION: &[u8] = b"1.0";
+
+const CURVE_KEY_SIZE: usize = 32;
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum Auth {
+    /// CURVE security with the server secret key (Z85-encoded).
+    ///
+    /// If `client_keys` is set, only clients whose public keys (Z85-encoded) are listed are
+    /// admitted; otherwise, any client that knows the server public key is admitted.
+    Curve {
+        secret_key: String,
+        #[serde(default)]
+        client_keys: Option<BTreeSet<String>>,
+    },
+}
+
+/// Policies keyed by endpoint, which the ZAP handler consults.
+#[derive(Clone, Debug)]
+pub(crate) struct Auths(Arc<Mutex<BTreeMap<String, Auth>>>);
+
+impl Auth {
+    pub(crate) fn is_valid(&self) -> bool {
+        match self {
+            Self::Curve {
+                secret_key,
+                client_keys,
+            } => {
+                is_curve_key(secret_key)
+                    && client_keys
+                        .iter()
+                        .flatten()
+                        .all(|client_key| is_curve_key(client_key))
+            }
+        }
+    }
+
+    /// Applies the policy, or resets to the NULL mechanism if it is `None`, to the endpoints that
+    /// are bound next.
+    pub(crate) fn apply(
+        auth: Option<&Self>,
+        socket: &mut Socket,
+        endpoint: &str,
+    ) -> Result<(), Error> {
+        match auth {
+            Some(Self::Curve { secret_key, .. }) => {
+                socket.set_curve_server(true)?;
+                socket.set_curve_secretkey(secret_key.as_bytes())?;
+                socket.set_zap_domain(endpoint)?;
+            }
+            None => {
+                socket.set_curve_server(false)?;
+                socket.set_zap_domain("")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Auths {
+    pub(crate) fn new(auths: BTreeMap<String, Auth>) -> Self {
+        Self(Arc::new(Mutex::new(auths)))
+    }
+
+    /// Replaces the policies and returns the endpoints whose allowlists have changed.
+    ///
+    /// Only the allowlists may change; changing the endpoints or their secret keys requires a
+    /// restart.
+    pub(crate) fn reload(&self, new_auths: BTreeMap<String, Auth>) -> Result<Vec<String>, Error> {
+        let mut auths = self.0.must_lock();
+        let changed = diff(&auths, &new_auths).map_err(Error::other)?;
+        *auths = new_auths;
+        Ok(changed)
+    }
+}
+
+fn diff(
+    auths: &BTreeMap<String, Auth>,
+    new_auths: &BTreeMap<String, Auth>,
+) -> Result<Vec<String>, &'static str> {
+    if !auths.keys().eq(new_auths.keys()) {
+        return Err("endpoints of auths are not reloadable");
+    }
+    let mut changed = Vec::new();
+    for ((endpoint, auth), new_auth) in auths.iter().zip(new_auths.values()) {
+        match (auth, new_auth) {
+            (
+                Auth::Curve { secret_key, .. },
+                Auth::Curve {
+                    secret_key: new_secret_key,
+                    ..
+                },
+            ) => {
+                if secret_key != new_secret_key {
+                    return Err("secret keys of auths are not reloadable");
+                }
+            }
+        }
+        if auth != new_auth {
+            changed.push(endpoint.clone());
+        }
+    }
+    Ok(changed)
+}
+
+fn is_curve_key(key: &str) -> bool {
+    zmq::z85_decode(key).is_ok_and(|key| key.len() == CURVE_KEY_SIZE)
+}
+
+pub(crate) fn spawn(context: &Context, auths: Auths) -> Result<Guard, Error> {
+    let mut socket = Socket::try_from(context.socket(REP)?)?;
+    socket.set_linger(0)?;
+    socket.bind(ZAP_ENDPOINT)?;
+    Ok(Guard::spawn(move |cancel| run(socket, auths, cancel)))
+}
+
+async fn run(mut socket: Socket, auths: Auths, cancel: Cancel) -> Result<(), Error> {
+    loop {
+        let request = tokio::select! {
+            () = cancel.wait() => break,
+            request = recv_request(&mut socket) => request?,
+        };
+
+        let request_id = request.get(1).cloned().unwrap_or_default();
+        let result = authenticate(&auths.0.must_lock(), &request);
+        let (status_code, status_text) = match result {
+            Ok(()) => ("200", "OK"),
+            Err(reason) => {
+                tracing::debug!(reason, "zap deny");
+                ("400", reason)
+            }
+        };
+
+        socket.send(ZAP_VERSION, SNDMORE).await?;
+        socket.send(request_id, SNDMORE).await?;
+        socket.send(status_code, SNDMORE).await?;
+        socket.send(status_text, SNDMORE).await?;
+        socket.send("", SNDMORE).await?; // User id.
+        socket.send("", 0).await?; // Metadata.
+    }
+    Ok(())
+}
+
+async fn recv_request(socket: &mut Socket) -> Result<Vec<Vec<u8>>, Error> {
+    let mut request = vec![socket.recv_bytes(0).await?];
+    while socket.get_rcvmore()? {
+        request.push(socket.recv_bytes(0).await?);
+    }
+    Ok(request)
+}
+
+fn authenticate(auths: &BTreeMap<String, Auth>, request: &[Vec<u8>]) -> Result<(), &'static str> {
+    let [version, _request_id, domain, _address, _identity, mechanism, credentials @ ..] = request
+    else {
+        return Err("invalid request");
+    };
+    if version != ZAP_VERSION {
+        return Err("unsupported version");
+    }
+
+    let auth = std::str::from_utf8(domain)
+        .ok()
+        .and_then(|domain| auths.get(domain));
+    let Some(Auth::Curve {
+        client_keys: Some(client_keys),
+        ..
+    }) = auth
+    else {
+        return Ok(());
+    };
+
+    if mechanism != b"CURVE" {
+        return Err("mechanism mismatch");
+    }
+    let [client_key] = credentials else {
+        return Err("invalid credentials");
+    };
+    let client_key = zmq::z85_encode(client_key).map_err(|_| "invalid credentials")?;
+    if client_keys.contains(&client_key) {
+        Ok(())
+    } else {
+        Err("client key not allowed")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(domain: &str, mechanism: &str, credentials: &[&[u8]]) -> Vec<Vec<u8>> {
+        let mut request = vec![
+            ZAP_VERSION.to_vec(),
+            b"1".to_vec(),
+            domain.into(),
+            b"127.0.0.1".to_vec(),
+            Vec::new(),
+            mechanism.into(),
+        ];
+        request.extend(credentials.iter().map(|credential| credential.to_vec()));
+        request
+    }
+
+    #[test]
+    fn is_valid() {
+        let (public_key, secret_key) = {
+            let pair = zmq::CurveKeyPair::new().unwrap();
+            (
+                zmq::z85_encode(&pair.public_key).unwrap(),
+                zmq::z85_encode(&pair.secret_key).unwrap(),
+            )
+        };
+
+        assert!(Auth::Curve {
+            secret_key: secret_key.clone(),
+            client_keys: None,
+        }
+        .is_valid());
+        assert!(Auth::Curve {
+            secret_key: secret_key.clone(),
+            client_keys: Some([public_key].into()),
+        }
+        .is_valid());
+
+        assert!(!Auth::Curve {
+            secret_key: "x".into(),
+            client_keys: None,
+        }
+        .is_valid());
+        assert!(!Auth::Curve {
+            secret_key,
+            client_keys: Some(["x".into()].into()),
+        }
+        .is_valid());
+    }
+
+    #[test]
+    fn test_authenticate() {
+        let pair = zmq::CurveKeyPair::new().unwrap();
+        let public_key = zmq::z85_encode(&pair.public_key).unwrap();
+        let secret_key = zmq::z85_encode(&pair.secret_key).unwrap();
+        let other = zmq::CurveKeyPair::new().unwrap();
+
+        let auths = BTreeMap::from([
+            (
+                "tcp://0.0.0.0:1".to_string(),
+                Auth::Curve {
+                    secret_key: secret_key.clone(),
+                    client_keys: Some([public_key].into()),
+                },
+            ),
+            (
+                "tcp://0.0.0.0:2".to_string(),
+                Auth::Curve {
+                    secret_key,
+                    client_keys: None,
+                },
+            ),
+        ]);
+
+        let domain = "tcp://0.0.0.0:1";
+        assert_eq!(
+            authenticate(
+                &auths,
+                &request(domain, "CURVE", &[pair.public_key.as_slice()])
+            ),
+            Ok(()),
+        );
+        assert_eq!(
+            authenticate(
+                &auths,
+                &request(domain, "CURVE", &[other.public_key.as_slice()])
+            ),
+            Err("client key not allowed"),
+        );
+        assert_eq!(
+            authenticate(&auths, &request(domain, "CURVE", &[])),
+            Err("invalid credentials"),
+        );
+        assert_eq!(
+            authenticate(&auths, &request(domain, "NULL", &[])),
+            Err("mechanism mismatch"),
+        );
+        assert_eq!(
+            authenticate(
+                &auths,
+                &request(domain, "CURVE", &[pair.public_key.as_slice()])[..5]
+            ),
+            Err("invalid request"),
+        );
+
+        for domain in ["tcp://0.0.0.0:2", "ipc:///tmp/ddcache", ""] {
+            assert_eq!(
+                authenticate(
+                    &auths,
+                    &request(domain, "CURVE", &[other.public_key.as_slice()])
+                ),
+                Ok(()),
+            );
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let new_key = || zmq::z85_encode(&zmq::CurveKeyPair::new().unwrap().secret_key).unwrap();
+        let secret_key = new_key();
+        let auth = |client_keys: Option<BTreeSet<String>>| Auth::Curve {
+            secret_key: secret_key.clone(),
+            client_keys,
+        };
+        let auths = BTreeMap::from([
+            ("tcp://0.0.0.0:1".to_string(), auth(None)),
+            (
+                "tcp://0.0.0.0:2".to_string(),
+                auth(Some(["x".into()].into())),
+            ),
+        ]);
+
+        assert_eq!(diff(&auths, &auths), Ok(vec![]));
+
+        let mut new_auths = auths.clone();
+        new_auths.insert("tcp://0.0.0.0:1".into(), auth(Some(["y".into()].into())));
+        new_auths.insert("tcp://0.0.0.0:2".into(), auth(None));
+        assert_eq!(
+            diff(&auths, &new_auths),
+            Ok(vec!["tcp://0.0.0.0:1".into(), "tcp://0.0.0.0:2".into()]),
+        );
+
+        let mut new_auths = auths.clone();
+        new_auths.remove("tcp://0.0.0.0:1");
+        assert_eq!(
+            diff(&auths, &new_auths),
+            Err("endpoints of auths are not reloadable"),
+        );
+
+        let mut new_auths = auths.clone();
+        new_auths.insert(
+            "tcp://0.0.0.0:1".into(),
+            Auth::Curve {
+                secret_key: new_key(),
+                client_keys: None,
+            },
+        );
+        assert_eq!(
+            diff(&auths, &new_auths),
+            Err("secret keys of auths are not reloadable"),
+        );
+    }
+}
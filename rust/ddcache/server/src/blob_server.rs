@@ -5,14 +5,14 @@ use std::time::Duration;
 
 use tokio::io::AsyncReadExt;
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::mpsc::{self, Receiver, Sender};
 use tokio::time::{self, Instant};
 use tracing::Instrument;
 
 use g1_tokio::os::{SendFile, Splice};
-use g1_tokio::task::{Cancel, JoinQueue};
+use g1_tokio::sync::metered::mpsc::{self, Receiver, Sender};
+use g1_tokio::task::{Cancel, JoinQueue, LoopExit};
 
-use ddcache_rpc::BlobEndpoint;
+use ddcache_rpc::{BlobEndpoint, Token};
 
 use crate::state::{Io, State};
 use crate::Guard;
@@ -36,7 +36,7 @@ struct Acceptor {
 impl Actor {
     pub(crate) fn spawn(state: Arc<State>) -> Result<(Vec<BlobEndpoint>, Guard), Error> {
         let mut endpoints = Vec::with_capacity(crate::blob_servers().len());
-        let (accept_send, accept_recv) = mpsc::channel(64);
+        let (accept_send, accept_recv) = mpsc::channel("ddcache/blob-accept", 64);
         let tasks = JoinQueue::new();
         for builder in crate::blob_servers() {
             let (listener, endpoint) = builder.build()?;
@@ -72,27 +72,30 @@ impl Actor {
     }
 
     async fn run(mut self) -> Result<(), Error> {
-        loop {
-            tokio::select! {
-                () = self.cancel.wait() => break,
-
+        let cancel = self.cancel.clone();
+        let result = g1_tokio::actor_loop! {
+            cancel: cancel,
+            error: Error,
+            select {
                 accept = self.accept_recv.recv() => {
                     self.handle_accept(accept.unwrap());
                 }
 
                 guard = self.tasks.join_next() => {
-                    let Some(guard) = guard else { break };
+                    let Some(guard) = guard else {
+                        break Ok(LoopExit::Closed("tasks"));
+                    };
                     self.handle_task(guard);
                 }
             }
-        }
-
-        self.tasks.cancel();
-        while let Some(guard) = self.tasks.join_next().await {
-            self.handle_task(guard);
-        }
-
-        Ok(())
+            cleanup {
+                self.tasks.cancel();
+                while let Some(guard) = self.tasks.join_next().await {
+                    self.handle_task(guard);
+                }
+            }
+        };
+        result.map(|_| ())
     }
 
     fn handle_accept(&self, (stream, client_endpoint): (TcpStream, SocketAddr)) {
@@ -103,7 +106,7 @@ impl Actor {
                 async move {
                     tokio::select! {
                         () = cancel.wait() => Ok(()),
-                        result = txrx_blob(stream, state, timeout) => result,
+                        result = txrx_blob(stream, state, timeout, cancel.clone()) => result,
                     }
                 }
                 .instrument(tracing::info_span!("ddcache/blob", %client_endpoint))
@@ -134,20 +137,21 @@ impl Acceptor {
     }
 
     async fn run(self) -> Result<(), Error> {
-        loop {
-            tokio::select! {
-                () = self.cancel.wait() => break,
-
+        let result = g1_tokio::actor_loop! {
+            cancel: self.cancel,
+            error: Error,
+            select {
                 accept = self.listener.accept() => {
                     let accept = accept?;
                     tracing::debug!(client_endpoint = %accept.1, "accept");
                     if self.accept_send.send(accept).await.is_err() {
-                        break;
+                        break Ok(LoopExit::Closed("accept_send"));
                     }
                 }
             }
-        }
-        Ok(())
+            cleanup {}
+        };
+        result.map(|_| ())
     }
 }
 
@@ -155,13 +159,19 @@ async fn txrx_blob(
     mut stream: TcpStream,
     state: Arc<State>,
     timeout: Duration,
+    cancel: Cancel,
 ) -> Result<(), Error> {
     // TODO: How can we ensure that `read_u64` does not buffer data internally?
-    let token = stream.read_u64().await?;
-    let Some(io) = state.remove(token) else {
+    let token = time::timeout(timeout, stream.read_u64())
+        .await
+        .map_err(|_| Error::new(ErrorKind::TimedOut, "recv token timeout"))??;
+    // A `Cancel` request aborts the transfer via `cancel`, which drops `io` and releases the blob
+    // (including the space reserved for a writer).
+    let Some(io) = state.start_transfer(token, cancel) else {
         tracing::debug!(token, "token not found");
         return Ok(());
     };
+    let _guard = TransferGuard { state, token };
 
     // Unregister `stream` from the tokio reactor; otherwise, `sendfile` will return `EEXIST` when
     // it attempts to register `stream` with the reactor via `AsyncFd`.
@@ -209,3 +219,14 @@ async fn txrx_blob(
     }
     Ok(())
 }
+
+struct TransferGuard {
+    state: Arc<State>,
+    token: Token,
+}
+
+impl Drop for TransferGuard {
+    fn drop(&mut self) {
+        self.state.finish_transfer(self.token);
+    }
+}
@@ -1,11 +1,16 @@
 #![feature(try_blocks)]
 #![cfg_attr(test, feature(assert_matches))]
 
+mod auth;
 mod blob_server;
+mod mirror;
+mod mode;
 mod rep;
 mod server;
+mod sketch;
 mod state;
 
+use std::collections::BTreeMap;
 use std::io::Error;
 use std::path::Path;
 use std::sync::Arc;
@@ -14,6 +19,7 @@ use std::time::Duration;
 use uuid::Uuid;
 use zmq::{Context, ROUTER};
 
+use g1_param::Parameters;
 use g1_tokio::net::tcp::TcpListenerBuilder;
 use g1_tokio::task::{JoinArray, JoinGuard};
 use g1_zmq::Socket;
@@ -21,14 +27,28 @@ use g1_zmq::Socket;
 use ddcache_peer::Peer;
 use ddcache_rpc::service;
 use ddcache_rpc::Endpoint;
-use ddcache_storage::Storage;
+use ddcache_storage::{IndexSpec, Storage};
 
+use crate::auth::Auths;
+use crate::mode::ModeSwitch;
 use crate::state::State;
 
+pub use ddcache_storage::{FsckReport, Repair};
+
+pub use crate::auth::Auth;
+pub use crate::mirror::Mirror;
+pub use crate::mode::Mode;
+
 g1_param::define!(self_id: Uuid = Uuid::new_v4());
 
 // TODO: Add the default IPv6 address.
 g1_param::define!(endpoints: Vec<String> = vec!["tcp://127.0.0.1:0".into()]);
+// Security policies keyed by entries of `endpoints`.  Endpoints without a policy use the NULL
+// mechanism, which suits, e.g., an `ipc://` endpoint for colocated clients.
+g1_param::define!(
+    endpoint_auths: BTreeMap<String, Auth> = BTreeMap::new();
+    validate = |auths: &BTreeMap<String, Auth>| auths.values().all(Auth::is_valid);
+);
 g1_param::define!(blob_servers: Vec<TcpListenerBuilder> = vec![
     TcpListenerBuilder {
         endpoint: "127.0.0.1:0".parse().expect("endpoint"),
@@ -37,14 +57,53 @@ g1_param::define!(blob_servers: Vec<TcpListenerBuilder> = vec![
 ]);
 
 // lwm/hwm = low/high water mark
-g1_param::define!(storage_size_lwm: u64 = 768 * 1024 * 1024);
-g1_param::define!(storage_size_hwm: u64 = 1024 * 1024 * 1024);
+g1_param::define!(
+    storage_size_lwm: u64 = 768 * 1024 * 1024;
+    parse = g1_param::parse::byte_size;
+);
+g1_param::define!(
+    storage_size_hwm: u64 = 1024 * 1024 * 1024;
+    parse = g1_param::parse::byte_size;
+);
+
+// Store blobs with identical content only once.
+g1_param::define!(storage_dedup: bool = false);
+
+// Metadata fields to be indexed for the `find` request, keyed by namespace (key prefix).  Metadata
+// of the entries under a namespace is parsed as `field=value` lines.
+g1_param::define!(metadata_indexes: BTreeMap<String, Vec<String>> = BTreeMap::new());
 
 g1_param::define!(max_concurrency: usize = 512);
+// Hint to clients for how long to wait before retrying when `max_concurrency` is reached.
+g1_param::define!(
+    unavailable_retry_after: Duration = Duration::from_millis(100);
+    parse = g1_param::parse::duration;
+);
+
+// Number of keys per frame of a streaming `changes` or `stats` response.
+g1_param::define!(
+    stream_chunk_size: usize = 1024;
+    validate = |chunk_size: &usize| *chunk_size > 0;
+);
 
 g1_param::define!(max_key_size: usize = 128);
 g1_param::define!(max_metadata_size: usize = 128);
-g1_param::define!(max_blob_size: usize = 32 * 1024 * 1024);
+g1_param::define!(
+    max_blob_size: usize = 32 * 1024 * 1024;
+    parse = g1_param::parse::byte_size;
+);
+
+// The server aborts a request that exceeds its timeout and responds with a timeout error.  Reads
+// include `read`, `read_metadata`, and `pull`, and writes include `remove`.  (Blob transfers are
+// bounded by `blob_request_timeout`.)
+g1_param::define!(
+    read_timeout: Duration = Duration::from_secs(4);
+    parse = g1_param::parse::duration;
+);
+g1_param::define!(
+    write_timeout: Duration = Duration::from_secs(4);
+    parse = g1_param::parse::duration;
+);
 
 g1_param::define!(
     blob_lease_timeout: Duration = Duration::from_secs(2);
@@ -55,27 +114,69 @@ g1_param::define!(
     parse = g1_param::parse::duration;
 );
 
+// If set, run as a warm standby of the primary server at this endpoint.
+g1_param::define!(mirror_primary: Option<String> = None);
+g1_param::define!(
+    mirror_period: Duration = Duration::from_secs(1);
+    parse = g1_param::parse::duration;
+);
+g1_param::define!(mirror_batch_size: usize = 1024);
+g1_param::define!(mirror_concurrency: usize = 16);
+g1_param::define!(
+    mirror_cut_over_timeout: Duration = Duration::from_secs(30);
+    parse = g1_param::parse::duration;
+);
+
+// Workload statistics reported to the `stats` admin request are reset after each window.
+g1_param::define!(
+    stats_window: Duration = Duration::from_secs(60);
+    parse = g1_param::parse::duration;
+);
+g1_param::define!(stats_top_keys: usize = 16);
+
 #[derive(Clone, Debug)]
 pub struct Server {
     endpoints: Arc<[Endpoint]>,
+    mirror: Option<Mirror>,
+    mode: ModeSwitch,
+    auths: Auths,
 }
 
-pub type ServerGuard = JoinArray<Result<(), Error>, 4>;
+pub type ServerGuard = JoinArray<Result<(), Error>, 5>;
 
 type Guard = JoinGuard<Result<(), Error>>;
 
 impl Server {
     pub async fn spawn(storage_dir: &Path) -> Result<(Self, ServerGuard), Error> {
-        let storage = Storage::open(storage_dir).await?;
+        let storage = open_storage(storage_dir).await?;
 
         let self_id = *crate::self_id();
         let state = Arc::new(State::new());
         let pubsub = service::pubsub();
 
-        let (socket, endpoints) = bind()?;
+        let context = Context::new();
+        let auths = Auths::new(crate::endpoint_auths().clone());
+        let zap_guard = auth::spawn(&context, auths.clone())?;
+        let (socket, endpoints) = bind(&context)?;
         let (blob_endpoints, blob_guard) = blob_server::Actor::spawn(state.clone())?;
 
-        let publisher_guard = pubsub.clone().spawn(self_id, endpoints.as_slice().into());
+        // A standby server publishes itself only after it is cut over.
+        let (mirror, publisher_guard) = match crate::mirror_primary() {
+            Some(primary) => {
+                let (mirror, guard) = Mirror::spawn(
+                    self_id,
+                    primary.clone(),
+                    storage.clone(),
+                    pubsub.clone(),
+                    endpoints.as_slice().into(),
+                );
+                (Some(mirror), guard)
+            }
+            None => (
+                None,
+                pubsub.clone().spawn(self_id, endpoints.as_slice().into()),
+            ),
+        };
 
         let (peer, mut peer_guard) = Peer::spawn(self_id, pubsub, storage.clone())
             .await
@@ -88,27 +189,91 @@ impl Server {
             peer_guard.shutdown().await?.map_err(Error::other)
         });
 
-        let guard = server::Actor::spawn(socket, blob_endpoints, state, storage, peer);
+        let mode = ModeSwitch::default();
+        let guard =
+            server::Actor::spawn(socket, blob_endpoints, state, storage, peer, mode.clone());
 
         Ok((
             Self {
                 endpoints: endpoints.into(),
+                mirror,
+                mode,
+                auths,
             },
-            ServerGuard::new([guard, blob_guard, publisher_guard, peer_guard]),
+            ServerGuard::new([guard, blob_guard, publisher_guard, peer_guard, zap_guard]),
         ))
     }
 
     pub fn endpoints(&self) -> &[Endpoint] {
         &self.endpoints
     }
+
+    /// Returns the mirror if we are (or were) a warm standby.
+    pub fn mirror(&self) -> Option<&Mirror> {
+        self.mirror.as_ref()
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode.get()
+    }
+
+    /// Switches the serving mode and returns the previous mode.
+    pub fn set_mode(&self, mode: Mode) -> Mode {
+        let old_mode = self.mode.set(mode);
+        tracing::info!(?old_mode, ?mode, "switch mode");
+        old_mode
+    }
+
+    /// Applies the reloaded parameter values that may change at runtime, taking them out of
+    /// `parameters`.
+    ///
+    /// Currently, only the client allowlists of `endpoint_auths` may change.
+    pub fn reload(&self, parameters: &mut Parameters) -> Result<(), Error> {
+        let auths = parameters
+            .take::<BTreeMap<String, Auth>>(std::module_path!(), "endpoint_auths")
+            .map_err(|error| Error::other(error.to_string()))?
+            .unwrap_or_default();
+        let endpoints = self.auths.reload(auths)?;
+        if !endpoints.is_empty() {
+            tracing::info!(?endpoints, "reload client keys");
+        }
+        Ok(())
+    }
 }
 
-fn bind() -> Result<(Socket, Vec<Endpoint>), Error> {
-    let mut socket = Socket::try_from(Context::new().socket(ROUTER)?)?;
+/// Checks the storage integrity without starting a server.
+pub async fn fsck(storage_dir: &Path, repair: Repair) -> Result<FsckReport, Error> {
+    open_storage(storage_dir).await?.fsck(repair).await
+}
+
+async fn open_storage(storage_dir: &Path) -> Result<Storage, Error> {
+    let indexes = crate::metadata_indexes()
+        .iter()
+        .map(|(namespace, fields)| IndexSpec {
+            namespace: namespace.clone().into(),
+            fields: fields.iter().map(|field| field.clone().into()).collect(),
+        })
+        .collect();
+    Storage::open_indexed(storage_dir, *crate::storage_dedup(), indexes).await
+}
+
+fn bind(context: &Context) -> Result<(Socket, Vec<Endpoint>), Error> {
+    if let Some(endpoint) = crate::endpoint_auths()
+        .keys()
+        .find(|endpoint| !crate::endpoints().contains(endpoint))
+    {
+        return Err(Error::other(format!(
+            "auth of unknown endpoint: {endpoint}"
+        )));
+    }
+
+    let mut socket = Socket::try_from(context.socket(ROUTER)?)?;
     socket.set_linger(0)?; // Do NOT block the program exit!
 
     let mut endpoints = Vec::with_capacity(crate::endpoints().len());
     for endpoint in crate::endpoints() {
+        // ZeroMQ captures the security options of an endpoint when it is bound.
+        Auth::apply(crate::endpoint_auths().get(endpoint), &mut socket, endpoint)?;
         socket.bind(endpoint)?;
         endpoints.push(socket.get_last_endpoint().unwrap().unwrap().into());
     }
@@ -0,0 +1,253 @@
+//! Warm-standby Mirroring
+//!
+//! A standby server follows the change feed of a primary server and pulls new or changed entries
+//! from it.  The standby does not publish itself until it is cut over, so that clients and peers
+//! do not route requests to it in the meantime.
+
+use std::collections::HashSet;
+use std::io::Error;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use tokio::time;
+use tracing::Instrument;
+use uuid::Uuid;
+
+use g1_tokio::sync::oneway::Flag;
+use g1_tokio::task::Cancel;
+
+use ddcache_client_raw::RawClient;
+use ddcache_rpc::service::{self, PubSub};
+use ddcache_rpc::ChangeCursor;
+use ddcache_storage::Storage;
+
+use crate::Guard;
+
+#[derive(Clone, Debug)]
+pub struct Mirror {
+    cut_over: Arc<Flag>,
+    stats: Arc<Stats>,
+}
+
+#[derive(Debug)]
+struct Actor {
+    cancel: Cancel,
+    cut_over: Arc<Flag>,
+    stats: Arc<Stats>,
+
+    self_id: Uuid,
+    pubsub: PubSub,
+    server: service::Server,
+
+    client: RawClient,
+    storage: Storage,
+    cursor: Option<ChangeCursor>,
+
+    batch_size: usize,
+    concurrency: usize,
+}
+
+#[derive(Debug, Default)]
+struct Stats {
+    lag: AtomicU64,
+    num_sync: AtomicU64,
+    num_remove: AtomicU64,
+    num_reset: AtomicU64,
+}
+
+impl Mirror {
+    pub(crate) fn spawn(
+        self_id: Uuid,
+        primary: String,
+        storage: Storage,
+        pubsub: PubSub,
+        server: service::Server,
+    ) -> (Self, Guard) {
+        let cut_over = Arc::new(Flag::new());
+        let stats = Arc::new(Stats::default());
+        let guard = {
+            let cut_over = cut_over.clone();
+            let stats = stats.clone();
+            Guard::spawn(move |cancel| {
+                let span = tracing::info_span!("ddcache/mirror", %primary);
+                async move {
+                    let (client, mut client_guard) = RawClient::connect(
+                        self_id,
+                        service::Server {
+                            endpoints: vec![primary],
+                            ..Default::default()
+                        },
+                    );
+                    let result = Actor {
+                        cancel,
+                        cut_over,
+                        stats,
+                        self_id,
+                        pubsub,
+                        server,
+                        client,
+                        storage,
+                        cursor: None,
+                        batch_size: *crate::mirror_batch_size(),
+                        concurrency: *crate::mirror_concurrency(),
+                    }
+                    .run()
+                    .await;
+                    client_guard.shutdown().await??;
+                    result
+                }
+                .instrument(span)
+            })
+        };
+        (Self { cut_over, stats }, guard)
+    }
+
+    /// Returns the number of changes on the primary that we have not pulled yet.
+    pub fn lag(&self) -> u64 {
+        self.stats.lag.load(Ordering::SeqCst)
+    }
+
+    pub fn is_cut_over(&self) -> bool {
+        self.cut_over.is_set()
+    }
+
+    /// Stops mirroring and starts serving as a primary server.
+    pub fn cut_over(&self) {
+        self.cut_over.set();
+    }
+}
+
+impl Actor {
+    async fn run(mut self) -> Result<(), Error> {
+        // Clone these so that they can be awaited alongside `&mut self` methods.
+        let cancel = self.cancel.clone();
+        let cut_over = self.cut_over.clone();
+        let mut interval = time::interval(*crate::mirror_period());
+        let mut log_stats_interval = time::interval(Duration::from_secs(600));
+        loop {
+            tokio::select! {
+                () = cancel.wait() => return Ok(()),
+                () = cut_over.wait() => break,
+                _ = interval.tick() => {}
+                _ = log_stats_interval.tick() => {
+                    tracing::info!(stats = ?self.stats);
+                    continue;
+                }
+            }
+            tokio::select! {
+                () = cancel.wait() => return Ok(()),
+                () = cut_over.wait() => break,
+                result = self.catch_up() => {
+                    if let Err(error) = result {
+                        tracing::warn!(%error, "mirror error");
+                    }
+                }
+            }
+        }
+
+        // Catch up with the primary one last time before serving.
+        let timeout = *crate::mirror_cut_over_timeout();
+        tokio::select! {
+            () = cancel.wait() => return Ok(()),
+            result = time::timeout(timeout, self.catch_up()) => match result {
+                Ok(Ok(())) => {}
+                Ok(Err(error)) => tracing::warn!(%error, "mirror error"),
+                Err(_) => tracing::warn!(?timeout, "mirror catch up timeout"),
+            },
+        }
+        self.client.disconnect();
+        tracing::info!(stats = ?self.stats, "cut over");
+
+        let mut publisher_guard = self.pubsub.spawn(self.self_id, self.server);
+        tokio::select! {
+            () = cancel.wait() => {}
+            () = publisher_guard.joinable() => {}
+        }
+        publisher_guard.shutdown().await?
+    }
+
+    async fn catch_up(&mut self) -> Result<(), Error> {
+        while self.sync().await? > 0 {}
+        Ok(())
+    }
+
+    /// Pulls one batch of changes and returns the remaining lag.
+    async fn sync(&mut self) -> Result<u64, Error> {
+        let changes = self
+            .client
+            .changes(self.cursor, self.batch_size)
+            .await
+            .map_err(Error::other)?
+            .and_then(|response| response.changes)
+            .ok_or_else(|| Error::other("expect changes response"))?;
+
+        if changes.reset {
+            tracing::info!(num_keys = changes.keys.len(), "mirror reset");
+            self.stats.num_reset.fetch_add(1, Ordering::SeqCst);
+            // Remove the entries that the primary no longer has.
+            let keys: HashSet<&Bytes> = changes.keys.iter().collect();
+            for key in self.storage.keys() {
+                if !keys.contains(&key) && self.storage.remove(key).await?.is_some() {
+                    self.stats.num_remove.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+        }
+
+        let this = &*self;
+        stream::iter(changes.keys)
+            .map(|key| this.sync_key(key))
+            .buffer_unordered(this.concurrency)
+            .try_collect::<()>()
+            .await?;
+
+        self.cursor = Some(changes.cursor);
+        self.stats.lag.store(changes.lag, Ordering::SeqCst);
+        Ok(changes.lag)
+    }
+
+    async fn sync_key(&self, key: Bytes) -> Result<(), Error> {
+        let Some(response) = self.client.pull(key.clone()).await.map_err(Error::other)? else {
+            // The primary no longer has the entry.
+            if self.storage.remove(key).await?.is_some() {
+                self.stats.num_remove.fetch_add(1, Ordering::SeqCst);
+            }
+            return Ok(());
+        };
+        let (Some(metadata), Some(blob)) = (response.metadata, response.blob) else {
+            return Err(Error::other("expect pull response"));
+        };
+
+        tracing::debug!(key = %key.escape_ascii(), "sync");
+        let mut writer = self.storage.write(key, true).await?;
+        writer.set_metadata(metadata.metadata);
+        writer.set_expire_at(metadata.expire_at);
+        writer.set_stale_at(metadata.stale_at);
+        writer.set_dictionary(metadata.dictionary);
+        // TODO: Pull responses do not carry the temperature hint yet, and thus mirrored entries
+        // are always normal.
+
+        let output = match writer.open() {
+            Ok(output) => output,
+            Err(error) => {
+                drop(writer);
+                if let Err(error) = blob.cancel().await {
+                    tracing::warn!(%error, "cancel");
+                }
+                return Err(error);
+            }
+        };
+        // On error, `writer` removes the partially written blob when it is dropped.
+        blob.read(output, metadata.size)
+            .await
+            .map_err(Error::other)?;
+        writer.commit()?;
+
+        self.stats.num_sync.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+}
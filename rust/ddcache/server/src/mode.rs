@@ -0,0 +1,133 @@
+//! Serving Modes
+//!
+//! An operator may switch a server into a mode that rejects some requests with `Unavailable`, so
+//! that a rolling restart does not lose the cache contents nor surprise clients with failed
+//! writes.
+
+use std::sync::{
+    atomic::{AtomicU8, Ordering},
+    Arc,
+};
+
+use ddcache_rpc::{FsckRepair, Request, Temperature};
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Mode {
+    #[default]
+    Normal,
+    /// Answers reads but rejects new writes, steering clients to other servers.
+    ///
+    /// Removals are still accepted, so that we do not serve stale entries after clients have
+    /// invalidated them.
+    Drain,
+    /// Rejects all requests that modify the storage, including removals and peer pushes.
+    ReadOnly,
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ModeSwitch(Arc<AtomicU8>);
+
+impl Mode {
+    /// Returns the next mode in the order of `Normal`, `Drain`, and `ReadOnly`.
+    pub fn next(self) -> Self {
+        match self {
+            Self::Normal => Self::Drain,
+            Self::Drain => Self::ReadOnly,
+            Self::ReadOnly => Self::Normal,
+        }
+    }
+
+    pub(crate) fn accepts(self, request: &Request) -> bool {
+        match request {
+            Request::Write { .. } | Request::WriteMetadata { .. } | Request::Push { .. } => {
+                self == Self::Normal
+            }
+            Request::Remove { .. } | Request::InstallDictionary { .. } => self != Self::ReadOnly,
+            Request::Fsck { repair } => self != Self::ReadOnly || *repair == FsckRepair::default(),
+            _ => true,
+        }
+    }
+
+    fn from_u8(mode: u8) -> Self {
+        match mode {
+            0 => Self::Normal,
+            1 => Self::Drain,
+            2 => Self::ReadOnly,
+            _ => unreachable!("mode: {mode}"),
+        }
+    }
+}
+
+impl ModeSwitch {
+    pub(crate) fn get(&self) -> Mode {
+        Mode::from_u8(self.0.load(Ordering::SeqCst))
+    }
+
+    pub(crate) fn set(&self, mode: Mode) -> Mode {
+        Mode::from_u8(self.0.swap(mode as u8, Ordering::SeqCst))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+
+    #[test]
+    fn accepts() {
+        let key = Bytes::from_static(b"k");
+        let read = Request::Read { key: key.clone() };
+        let write = Request::Write {
+            key: key.clone(),
+            metadata: None,
+            size: 0,
+            expire_at: None,
+            stale_at: None,
+            temperature: Temperature::Normal,
+            dictionary: None,
+        };
+        let remove = Request::Remove { key: key.clone() };
+        let push = Request::Push {
+            key,
+            metadata: None,
+            size: 0,
+            expire_at: None,
+            stale_at: None,
+            temperature: Temperature::Normal,
+            dictionary: None,
+        };
+        let fsck = Request::Fsck {
+            repair: FsckRepair::default(),
+        };
+        let repair = Request::Fsck {
+            repair: FsckRepair {
+                drop_entry: true,
+                ..Default::default()
+            },
+        };
+
+        for (mode, expect) in [
+            (Mode::Normal, [true, true, true, true, true, true]),
+            (Mode::Drain, [true, false, true, false, true, true]),
+            (Mode::ReadOnly, [true, false, false, false, true, false]),
+        ] {
+            assert_eq!(
+                [&read, &write, &remove, &push, &fsck, &repair]
+                    .map(|request| mode.accepts(request)),
+                expect,
+                "{mode:?}",
+            );
+        }
+    }
+
+    #[test]
+    fn mode_switch() {
+        let switch = ModeSwitch::default();
+        assert_eq!(switch.get(), Mode::Normal);
+        for mode in [Mode::Drain, Mode::ReadOnly, Mode::Normal] {
+            assert_eq!(switch.set(switch.get().next()), mode.next().next());
+            assert_eq!(switch.get(), mode);
+        }
+    }
+}
@@ -1,4 +1,6 @@
+use std::ops::Range;
 use std::sync::LazyLock;
+use std::time::Duration;
 
 use bytes::Bytes;
 use capnp::message;
@@ -6,14 +8,18 @@ use capnp::serialize;
 
 use g1_zmq::envelope::Frame;
 
+use ddcache_rpc::rpc_capnp::error;
 use ddcache_rpc::{
-    BlobEndpoint, BlobMetadata, BlobRequest, Response, ResponseBuilder, Timestamp, Token,
+    BlobEndpoint, BlobMetadata, BlobRequest, ChangeCursor, Changes, DictionaryId, FsckReport,
+    Response, ResponseBuilder, Timestamp, Token, WorkloadStats,
 };
 
 pub(crate) fn read_response(
     metadata: Option<Bytes>,
     size: usize,
     expire_at: Option<Timestamp>,
+    stale_at: Option<Timestamp>,
+    dictionary: Option<DictionaryId>,
     endpoint: BlobEndpoint,
     token: Token,
 ) -> Frame {
@@ -22,6 +28,8 @@ pub(crate) fn read_response(
             metadata,
             size,
             expire_at,
+            stale_at,
+            dictionary,
         },
         blob: BlobRequest { endpoint, token },
     })
@@ -31,12 +39,16 @@ pub(crate) fn read_metadata_response(
     metadata: Option<Bytes>,
     size: usize,
     expire_at: Option<Timestamp>,
+    stale_at: Option<Timestamp>,
+    dictionary: Option<DictionaryId>,
 ) -> Frame {
     encode(Response::ReadMetadata {
         metadata: BlobMetadata {
             metadata,
             size,
             expire_at,
+            stale_at,
+            dictionary,
         },
     })
 }
@@ -51,12 +63,16 @@ pub(crate) fn write_metadata_response(
     metadata: Option<Bytes>,
     size: usize,
     expire_at: Option<Timestamp>,
+    stale_at: Option<Timestamp>,
+    dictionary: Option<DictionaryId>,
 ) -> Frame {
     encode(Response::WriteMetadata {
         metadata: BlobMetadata {
             metadata,
             size,
             expire_at,
+            stale_at,
+            dictionary,
         },
     })
 }
@@ -65,12 +81,16 @@ pub(crate) fn remove_response(
     metadata: Option<Bytes>,
     size: usize,
     expire_at: Option<Timestamp>,
+    stale_at: Option<Timestamp>,
+    dictionary: Option<DictionaryId>,
 ) -> Frame {
     encode(Response::Remove {
         metadata: BlobMetadata {
             metadata,
             size,
             expire_at,
+            stale_at,
+            dictionary,
         },
     })
 }
@@ -79,6 +99,8 @@ pub(crate) fn pull_response(
     metadata: Option<Bytes>,
     size: usize,
     expire_at: Option<Timestamp>,
+    stale_at: Option<Timestamp>,
+    dictionary: Option<DictionaryId>,
     endpoint: BlobEndpoint,
     token: Token,
 ) -> Frame {
@@ -87,6 +109,8 @@ pub(crate) fn pull_response(
             metadata,
             size,
             expire_at,
+            stale_at,
+            dictionary,
         },
         blob: BlobRequest { endpoint, token },
     })
@@ -98,10 +122,106 @@ pub(crate) fn push_response(endpoint: BlobEndpoint, token: Token) -> Frame {
     })
 }
 
+pub(crate) fn changes_response(changes: ddcache_storage::Changes) -> Frame {
+    encode(Response::Changes {
+        changes: Changes {
+            cursor: ChangeCursor {
+                epoch: changes.cursor.epoch,
+                sequence: changes.cursor.sequence,
+            },
+            reset: changes.reset,
+            keys: changes.keys,
+            lag: changes.lag,
+        },
+    })
+}
+
+/// Splits the changes into frames of at most `chunk_size` keys; see `Response.more` in
+/// `rpc.capnp`.
+pub(crate) fn changes_responses(
+    changes: ddcache_storage::Changes,
+    chunk_size: usize,
+) -> Vec<Frame> {
+    let cursor = ChangeCursor {
+        epoch: changes.cursor.epoch,
+        sequence: changes.cursor.sequence,
+    };
+    let keys = changes.keys;
+    encode_chunks(keys.len(), chunk_size, |range| Response::Changes {
+        changes: Changes {
+            cursor,
+            reset: changes.reset,
+            keys: keys[range].to_vec(),
+            lag: changes.lag,
+        },
+    })
+}
+
+pub(crate) fn read_dictionary_response(dictionary: Bytes) -> Frame {
+    encode(Response::ReadDictionary { dictionary })
+}
+
+pub(crate) fn find_response(keys: Vec<Bytes>) -> Frame {
+    encode(Response::Find { keys })
+}
+
+pub(crate) fn fsck_response(report: ddcache_storage::FsckReport) -> Frame {
+    encode(Response::Fsck {
+        report: FsckReport {
+            num_entries: to_u64(report.num_entries),
+            num_busy: to_u64(report.num_busy),
+            num_missing: to_u64(report.num_missing),
+            num_size_mismatch: to_u64(report.num_size_mismatch),
+            num_orphan: to_u64(report.num_orphan),
+            num_repaired: to_u64(report.num_repaired),
+        },
+    })
+}
+
+pub(crate) fn stats_response(stats: WorkloadStats) -> Frame {
+    encode(Response::Stats { stats })
+}
+
+/// Splits the stats into frames of at most `chunk_size` top keys, where only the first frame
+/// carries the other fields.
+pub(crate) fn stats_responses(stats: WorkloadStats, chunk_size: usize) -> Vec<Frame> {
+    encode_chunks(stats.top_keys.len(), chunk_size, |range| {
+        let first = range.start == 0;
+        Response::Stats {
+            stats: WorkloadStats {
+                window_elapsed: stats.window_elapsed,
+                num_distinct_keys: stats.num_distinct_keys,
+                write_sizes: if first {
+                    stats.write_sizes.clone()
+                } else {
+                    Vec::new()
+                },
+                top_keys: stats.top_keys[range].to_vec(),
+            },
+        }
+    })
+}
+
 fn encode(response: Response) -> Frame {
     Vec::<u8>::from(response).into()
 }
 
+/// Encodes a response of `num_items` items in chunks, setting `more` on all frames but the last.
+/// It produces one frame even when there are no items.
+fn encode_chunks<F>(num_items: usize, chunk_size: usize, mut make_response: F) -> Vec<Frame>
+where
+    F: FnMut(Range<usize>) -> Response,
+{
+    let num_chunks = num_items.div_ceil(chunk_size).max(1);
+    (0..num_chunks)
+        .map(|i| {
+            let start = i * chunk_size;
+            let end = (start + chunk_size).min(num_items);
+            make_response(start..end).encode(i + 1 < num_chunks).into()
+        })
+        .collect()
+}
+
 macro_rules! make_const_response {
     ($name:ident => $($init:tt)*) => {
         pub(crate) fn $name() -> Frame {
@@ -119,23 +239,83 @@ make_const_response!(ok_none_response => /* Do nothing. */);
 
 make_const_response!(cancel_response => .init_ok().set_cancel(()));
 
-make_const_response!(server_error => .init_err().set_server(()));
+make_const_response!(ping_response => .init_ok().set_ping(()));
 
-make_const_response!(unavailable_error => .init_err().set_unavailable(()));
+make_const_response!(install_dictionary_response => .init_ok().set_install_dictionary(()));
 
-make_const_response!(invalid_request_error => .init_err().set_invalid_request(()));
-make_const_response!(
-    max_key_size_exceeded_error =>
-    .init_err().set_max_key_size_exceeded(to_u32(crate::max_key_size()))
-);
-make_const_response!(
-    max_metadata_size_exceeded_error =>
-    .init_err().set_max_metadata_size_exceeded(to_u32(crate::max_metadata_size()))
-);
-make_const_response!(
-    max_blob_size_exceeded_error =>
-    .init_err().set_max_blob_size_exceeded(to_u32(crate::max_blob_size()))
-);
+macro_rules! make_const_error {
+    ($name:ident => |$error:ident| $init:expr) => {
+        pub(crate) fn $name() -> Frame {
+            static RESPONSE: LazyLock<Vec<u8>> = LazyLock::new(|| encode_error(|mut $error| $init));
+            RESPONSE.as_slice().into()
+        }
+    };
+}
+
+// Server errors are mostly storage I/O errors, which are usually transient.
+make_const_error!(server_error => |error| {
+    error.set_retryable(true);
+    error.set_server(());
+});
+
+make_const_error!(unavailable_error => |error| {
+    error.set_retryable(true);
+    error.set_retry_after(
+        crate::unavailable_retry_after()
+            .as_millis()
+            .try_into()
+            .unwrap_or(u32::MAX),
+    );
+    error.set_unavailable(());
+});
+
+make_const_error!(invalid_request_error => |error| error.set_invalid_request(()));
+
+pub(crate) fn max_key_size_exceeded_error(size: usize) -> Frame {
+    encode_error(|mut error| {
+        error.set_size(saturate(size));
+        error.set_max_key_size_exceeded(to_u32(crate::max_key_size()));
+    })
+    .into()
+}
+
+pub(crate) fn max_metadata_size_exceeded_error(size: usize) -> Frame {
+    encode_error(|mut error| {
+        error.set_size(saturate(size));
+        error.set_max_metadata_size_exceeded(to_u32(crate::max_metadata_size()));
+    })
+    .into()
+}
+
+pub(crate) fn max_blob_size_exceeded_error(size: usize) -> Frame {
+    encode_error(|mut error| {
+        error.set_size(saturate(size));
+        error.set_max_blob_size_exceeded(to_u32(crate::max_blob_size()));
+    })
+    .into()
+}
+
+pub(crate) fn timeout_error(elapsed: Duration) -> Frame {
+    encode_error(|mut error| {
+        error.set_retryable(true);
+        error.set_timeout(elapsed.as_millis().try_into().unwrap_or(u32::MAX));
+    })
+    .into()
+}
+
+fn encode_error(init: impl FnOnce(error::Builder)) -> Vec<u8> {
+    let mut message = message::Builder::new_default();
+    init(message.init_root::<ResponseBuilder>().init_err());
+    serialize::write_message_to_words(&message)
+}
+
+fn saturate(x: usize) -> u32 {
+    x.try_into().unwrap_or(u32::MAX)
+}
+
+fn to_u64(x: usize) -> u64 {
+    x.try_into().unwrap()
+}
 
 fn to_u32(x: &usize) -> u32 {
     (*x).try_into().unwrap()
@@ -158,4 +338,137 @@ mod tests {
         assert_matches!(*response, Ok(None));
         Ok(())
     }
+
+    #[test]
+    fn test_error_details() -> Result<(), Error> {
+        let response = ResponseOwner::try_from(max_key_size_exceeded_error(200))?
+            .map(ResponseResult::try_from);
+        let response = unsafe { response.transpose() }?;
+        let Err(error) = &*response else {
+            panic!("expect error");
+        };
+        assert_matches!(error.which()?, error::MaxKeySizeExceeded(128));
+        assert!(!error.get_retryable());
+        assert_eq!(error.get_retry_after(), 0);
+        assert_eq!(error.get_size(), 200);
+
+        let response = ResponseOwner::try_from(unavailable_error())?.map(ResponseResult::try_from);
+        let response = unsafe { response.transpose() }?;
+        let Err(error) = &*response else {
+            panic!("expect error");
+        };
+        assert_matches!(error.which()?, error::Unavailable(()));
+        assert!(error.get_retryable());
+        assert_eq!(error.get_retry_after(), 100);
+
+        let response = ResponseOwner::try_from(timeout_error(Duration::from_millis(4200)))?
+            .map(ResponseResult::try_from);
+        let response = unsafe { response.transpose() }?;
+        let Err(error) = &*response else {
+            panic!("expect error");
+        };
+        assert_matches!(error.which()?, error::Timeout(4200));
+        assert!(error.get_retryable());
+
+        Ok(())
+    }
+
+    fn decode_responses(frames: Vec<Frame>) -> Result<Vec<(Response, bool)>, Error> {
+        frames
+            .into_iter()
+            .map(|frame| {
+                let response = ResponseOwner::try_from(frame)?.map(ResponseResult::try_from);
+                let response = unsafe { response.transpose() }?;
+                let Ok(Some(response)) = &*response else {
+                    panic!("expect response");
+                };
+                Ok((Response::try_from(*response)?, response.get_more()))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_changes_responses() -> Result<(), Error> {
+        fn changes(keys: &[&'static [u8]]) -> ddcache_storage::Changes {
+            ddcache_storage::Changes {
+                cursor: ddcache_storage::Cursor {
+                    epoch: 1,
+                    sequence: 2,
+                },
+                reset: true,
+                keys: keys.iter().copied().map(Bytes::from_static).collect(),
+                lag: 3,
+            }
+        }
+
+        fn expect(keys: &[&'static [u8]], more: bool) -> (Response, bool) {
+            (
+                Response::Changes {
+                    changes: Changes {
+                        cursor: ChangeCursor {
+                            epoch: 1,
+                            sequence: 2,
+                        },
+                        reset: true,
+                        keys: keys.iter().copied().map(Bytes::from_static).collect(),
+                        lag: 3,
+                    },
+                },
+                more,
+            )
+        }
+
+        assert_eq!(
+            decode_responses(changes_responses(changes(&[]), 2))?,
+            vec![expect(&[], false)],
+        );
+        assert_eq!(
+            decode_responses(changes_responses(changes(&[b"a", b"b"]), 2))?,
+            vec![expect(&[b"a", b"b"], false)],
+        );
+        assert_eq!(
+            decode_responses(changes_responses(changes(&[b"a", b"b", b"c"]), 2))?,
+            vec![expect(&[b"a", b"b"], true), expect(&[b"c"], false)],
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_stats_responses() -> Result<(), Error> {
+        let stats = WorkloadStats {
+            window_elapsed: Duration::from_secs(1),
+            num_distinct_keys: 3,
+            write_sizes: vec![1, 2],
+            top_keys: vec![
+                (Bytes::from_static(b"a"), 3),
+                (Bytes::from_static(b"b"), 2),
+                (Bytes::from_static(b"c"), 1),
+            ],
+        };
+        assert_eq!(
+            decode_responses(stats_responses(stats.clone(), 2))?,
+            vec![
+                (
+                    Response::Stats {
+                        stats: WorkloadStats {
+                            top_keys: stats.top_keys[0..2].to_vec(),
+                            ..stats.clone()
+                        },
+                    },
+                    true,
+                ),
+                (
+                    Response::Stats {
+                        stats: WorkloadStats {
+                            write_sizes: Vec::new(),
+                            top_keys: stats.top_keys[2..3].to_vec(),
+                            ..stats.clone()
+                        },
+                    },
+                    false,
+                ),
+            ],
+        );
+        Ok(())
+    }
 }
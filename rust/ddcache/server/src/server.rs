@@ -1,3 +1,4 @@
+use std::future::Future;
 use std::io::Error;
 use std::sync::{
     atomic::{AtomicU64, Ordering},
@@ -9,22 +10,28 @@ use bytes::Bytes;
 use futures::future::OptionFuture;
 use futures::sink::SinkExt;
 use futures::stream::TryStreamExt;
-use tokio::sync::mpsc::{self, UnboundedSender};
 use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 use tokio::time::{self, Instant};
 use tracing::Instrument;
 
-use g1_tokio::task::{Cancel, JoinGuard, JoinQueue};
+use g1_tokio::sync::metered::mpsc::{self, UnboundedSender};
+use g1_tokio::task::{Cancel, JoinGuard, JoinQueue, LoopExit};
 use g1_zmq::duplex::Duplex;
 use g1_zmq::envelope::{Envelope, Frame, Multipart};
+use g1_zmq::rpc;
 use g1_zmq::Socket;
 
 use ddcache_peer::Peer;
 use ddcache_rpc::envelope;
-use ddcache_rpc::{BlobEndpoint, Request, Timestamp, TimestampExt, Token};
-use ddcache_storage::{ReadGuard, Storage, WriteGuard};
+use ddcache_rpc::{
+    BlobEndpoint, ChangeCursor, DictionaryId, FsckRepair, Request, Temperature, Timestamp,
+    TimestampExt, Token,
+};
+use ddcache_storage::{Cursor, ReadGuard, Repair, Storage, WriteGuard};
 
+use crate::mode::ModeSwitch;
 use crate::rep;
+use crate::sketch::Sketches;
 use crate::state::State;
 use crate::Guard;
 
@@ -37,6 +44,9 @@ pub(crate) struct Actor {
     max_metadata_size: usize,
     max_blob_size: usize,
 
+    read_timeout: Duration,
+    write_timeout: Duration,
+
     tasks: JoinQueue<()>,
     concurrency: Arc<Semaphore>,
 
@@ -49,10 +59,13 @@ pub(crate) struct Actor {
 
     peer: Peer,
 
+    mode: ModeSwitch,
+
     evict_task: Option<Guard>,
     expire_task: Option<Guard>,
 
     stats: Arc<Stats>,
+    sketches: Sketches,
 }
 
 #[derive(Debug)]
@@ -87,6 +100,7 @@ impl Actor {
         state: Arc<State>,
         storage: Storage,
         peer: Peer,
+        mode: ModeSwitch,
     ) -> Guard {
         Guard::spawn(move |cancel| {
             Self::new(
@@ -96,6 +110,7 @@ impl Actor {
                 state,
                 storage,
                 peer,
+                mode,
             )
             .run()
         })
@@ -108,6 +123,7 @@ impl Actor {
         state: Arc<State>,
         storage: Storage,
         peer: Peer,
+        mode: ModeSwitch,
     ) -> Self {
         Self {
             cancel: cancel.clone(),
@@ -117,6 +133,9 @@ impl Actor {
             max_metadata_size: *crate::max_metadata_size(),
             max_blob_size: *crate::max_blob_size(),
 
+            read_timeout: *crate::read_timeout(),
+            write_timeout: *crate::write_timeout(),
+
             tasks: JoinQueue::with_cancel(cancel),
             concurrency: Arc::new(Semaphore::new(*crate::max_concurrency())),
 
@@ -129,37 +148,46 @@ impl Actor {
 
             peer,
 
+            mode,
+
             evict_task: None,
             expire_task: None,
 
             stats: Arc::new(Default::default()),
+            sketches: Sketches::new(),
         }
     }
 
     async fn run(mut self) -> Result<(), Error> {
-        let (response_send, mut response_recv) = mpsc::unbounded_channel();
+        let (response_send, mut response_recv) = mpsc::unbounded_channel("ddcache/server-response");
 
         let mut deadline = None;
         tokio::pin! { let timeout = OptionFuture::from(None); }
 
         let mut log_stats_interval = time::interval(Duration::from_secs(600));
 
-        loop {
-            let next_deadline = self.state.next_deadline();
-            if deadline != next_deadline {
-                deadline = next_deadline;
-                timeout.set(deadline.map(time::sleep_until).into());
+        let cancel = self.cancel.clone();
+        let result = g1_tokio::actor_loop! {
+            cancel: cancel,
+            error: Error,
+            before_select {
+                let next_deadline = self.state.next_deadline();
+                if deadline != next_deadline {
+                    deadline = next_deadline;
+                    timeout.set(deadline.map(time::sleep_until).into());
+                }
             }
-
-            tokio::select! {
-                () = self.cancel.wait() => break,
-
+            select {
                 request = self.duplex.try_next() => {
-                    let Some(request) = request? else { break };
+                    let Some(request) = request? else {
+                        break Ok(LoopExit::Closed("duplex"));
+                    };
                     self.handle_request(request, &response_send);
                 }
                 response = response_recv.recv() => {
-                    let Some(response) = response else { break };
+                    let Some(response) = response else {
+                        break Ok(LoopExit::Closed("response_recv"));
+                    };
                     // Block the actor loop on `duplex.send` because it is probably desirable to
                     // derive back pressure from this point.
                     self.duplex.send(response.into()).await?;
@@ -172,7 +200,9 @@ impl Actor {
                 }
 
                 guard = self.tasks.join_next() => {
-                    let Some(guard) = guard else { break };
+                    let Some(guard) = guard else {
+                        break Ok(LoopExit::Closed("tasks"));
+                    };
                     self.handle_task(guard);
                     // We check and spawn an evict task regardless of whether `guard` is write, and
                     // we do so even before the client completes writing to the blob.  While this
@@ -209,29 +239,34 @@ impl Actor {
 
                 _ = log_stats_interval.tick() => tracing::info!(stats = ?self.stats),
             }
-        }
-        tracing::info!(stats = ?self.stats);
-
-        self.tasks.cancel();
-        while let Some(guard) = self.tasks.join_next().await {
-            self.handle_task(guard);
-        }
+            cleanup {
+                tracing::info!(stats = ?self.stats);
 
-        for mut guard in self
-            .evict_task
-            .take()
-            .into_iter()
-            .chain(self.expire_task.take().into_iter())
-        {
-            guard.cancel();
-            guard.join().await;
-            self.handle_cleanup_task(guard)?;
-        }
+                self.tasks.cancel();
+                while let Some(guard) = self.tasks.join_next().await {
+                    self.handle_task(guard);
+                }
 
-        Ok(())
+                for mut guard in self
+                    .evict_task
+                    .take()
+                    .into_iter()
+                    .chain(self.expire_task.take().into_iter())
+                {
+                    guard.cancel();
+                    guard.join().await;
+                    self.handle_cleanup_task(guard)?;
+                }
+            }
+        };
+        result.map(|_| ())
     }
 
-    fn handle_request(&self, request: Multipart, response_send: &UnboundedSender<Envelope<Frame>>) {
+    fn handle_request(
+        &mut self,
+        request: Multipart,
+        response_send: &UnboundedSender<Envelope<Frame>>,
+    ) {
         let envelope = match envelope::decode_request(request) {
             Ok(envelope) => envelope,
             Err(error) => {
@@ -254,6 +289,7 @@ impl Actor {
         };
         tracing::debug!(request = ?&**envelope.data());
 
+        let stream = envelope.data().get_stream();
         let request = match Request::try_from(**envelope.data()) {
             Ok(request) => request,
             Err(error) => {
@@ -263,21 +299,32 @@ impl Actor {
             }
         };
 
+        let mode = self.mode.get();
+        if !mode.accepts(&request) {
+            tracing::debug!(?mode, ?request, "reject request");
+            let _ = response_send.send(envelope.map(|_| rep::unavailable_error()));
+            return;
+        }
+
         let Ok(permit) = self.concurrency.clone().try_acquire_owned() else {
             let _ = response_send.send(envelope.map(|_| rep::unavailable_error()));
             return;
         };
         let handler = Handler::new(self, envelope.map(|_| ()), response_send.clone(), permit);
 
+        self.sketches.record(&request);
+
         let max_key_size = self.max_key_size;
         let max_metadata_size = self.max_metadata_size;
         let max_blob_size = self.max_blob_size;
+        let read_timeout = self.read_timeout;
+        let write_timeout = self.write_timeout;
 
         macro_rules! check_key {
             ($key:ident $(,)?) => {
                 if $key.len() > max_key_size {
                     tracing::warn!(key = %$key.escape_ascii(), max_key_size, "max size exceeded");
-                    handler.send_response(rep::max_key_size_exceeded_error());
+                    handler.send_response(rep::max_key_size_exceeded_error($key.len()));
                     return;
                 }
             };
@@ -292,7 +339,7 @@ impl Actor {
                         max_metadata_size,
                         "max size exceeded",
                     );
-                    handler.send_response(rep::max_metadata_size_exceeded_error());
+                    handler.send_response(rep::max_metadata_size_exceeded_error(metadata.len()));
                     return;
                 }
             }};
@@ -302,7 +349,7 @@ impl Actor {
             ($size:ident $(,)?) => {
                 if $size > max_blob_size {
                     tracing::warn!($size, max_blob_size, "max size exceeded");
-                    handler.send_response(rep::max_blob_size_exceeded_error());
+                    handler.send_response(rep::max_blob_size_exceeded_error($size));
                     return;
                 }
             };
@@ -320,9 +367,11 @@ impl Actor {
                     .push(JoinGuard::spawn(move |cancel| {
                         async move {
                             check_key!(key);
+                            let handle =
+                                handler.run_with_timeout(read_timeout, |handler| handler.read(key));
                             tokio::select! {
                                 () = cancel.wait() => {}
-                                () = handler.read(key) => {}
+                                () = handle => {}
                             }
                         }
                         .instrument(tracing::info_span!("ddcache/read"))
@@ -335,9 +384,12 @@ impl Actor {
                     .push(JoinGuard::spawn(move |cancel| {
                         async move {
                             check_key!(key);
+                            let handle = handler.run_with_timeout(read_timeout, |handler| {
+                                handler.read_metadata(key)
+                            });
                             tokio::select! {
                                 () = cancel.wait() => {}
-                                () = handler.read_metadata(key) => {}
+                                () = handle => {}
                             }
                         }
                         .instrument(tracing::info_span!("ddcache/read-metadata"))
@@ -350,19 +402,31 @@ impl Actor {
                 metadata,
                 size,
                 expire_at,
+                stale_at,
+                temperature,
+                dictionary,
             } => {
                 let span = tracing::info_span!("ddcache/write");
                 let _enter = span.enter();
                 check_key!(key);
                 check_metadata!(metadata.as_deref().unwrap_or(&[]));
                 check_size!(size);
-                handler.write(key, metadata, size, expire_at);
+                handler.write(
+                    key,
+                    metadata,
+                    size,
+                    expire_at,
+                    stale_at,
+                    temperature,
+                    dictionary,
+                );
             }
 
             Request::WriteMetadata {
                 key,
                 metadata,
                 expire_at,
+                stale_at,
             } => {
                 let span = tracing::info_span!("ddcache/write-metadata");
                 let _enter = span.enter();
@@ -370,7 +434,7 @@ impl Actor {
                 check_metadata!(metadata
                     .as_ref()
                     .map_or(&[] as &[u8], |x| x.as_deref().unwrap_or(&[])));
-                handler.write_metadata(key, metadata, expire_at);
+                handler.write_metadata(key, metadata, expire_at, stale_at);
             }
 
             Request::Remove { key } => {
@@ -378,9 +442,11 @@ impl Actor {
                     .push(JoinGuard::spawn(move |cancel| {
                         async move {
                             check_key!(key);
+                            let handle = handler
+                                .run_with_timeout(write_timeout, |handler| handler.remove(key));
                             tokio::select! {
                                 () = cancel.wait() => {}
-                                () = handler.remove(key) => {}
+                                () = handle => {}
                             }
                         }
                         .instrument(tracing::info_span!("ddcache/remove"))
@@ -393,9 +459,11 @@ impl Actor {
                     .push(JoinGuard::spawn(move |cancel| {
                         async move {
                             check_key!(key);
+                            let handle =
+                                handler.run_with_timeout(read_timeout, |handler| handler.pull(key));
                             tokio::select! {
                                 () = cancel.wait() => {}
-                                () = handler.pull(key) => {}
+                                () = handle => {}
                             }
                         }
                         .instrument(tracing::info_span!("ddcache/pull"))
@@ -408,13 +476,84 @@ impl Actor {
                 metadata,
                 size,
                 expire_at,
+                stale_at,
+                temperature,
+                dictionary,
             } => {
                 let span = tracing::info_span!("ddcache/push");
                 let _enter = span.enter();
                 check_key!(key);
                 check_metadata!(metadata.as_deref().unwrap_or(&[]));
                 check_size!(size);
-                handler.push(key, metadata, size, expire_at);
+                handler.push(
+                    key,
+                    metadata,
+                    size,
+                    expire_at,
+                    stale_at,
+                    temperature,
+                    dictionary,
+                );
+            }
+
+            Request::Changes { cursor, limit } => {
+                let span = tracing::info_span!("ddcache/changes");
+                let _enter = span.enter();
+                handler.changes(cursor, limit, stream);
+            }
+
+            Request::Ping => handler.send_response(rep::ping_response()),
+
+            Request::Stats => {
+                let stats = self.sketches.report();
+                if stream {
+                    handler
+                        .send_responses(rep::stats_responses(stats, *crate::stream_chunk_size()));
+                } else {
+                    handler.send_response(rep::stats_response(stats));
+                }
+            }
+
+            Request::InstallDictionary { id, dictionary } => {
+                let span = tracing::info_span!("ddcache/install-dictionary");
+                let _enter = span.enter();
+                let size = dictionary.len();
+                check_size!(size);
+                handler.install_dictionary(id, dictionary);
+            }
+
+            Request::ReadDictionary { id } => {
+                let span = tracing::info_span!("ddcache/read-dictionary");
+                let _enter = span.enter();
+                handler.read_dictionary(id);
+            }
+
+            Request::Find {
+                namespace,
+                field,
+                value,
+                limit,
+            } => {
+                let span = tracing::info_span!("ddcache/find");
+                let _enter = span.enter();
+                check_key!(namespace);
+                check_metadata!(&field);
+                check_metadata!(&value);
+                handler.find(namespace, field, value, limit);
+            }
+
+            Request::Fsck { repair } => {
+                self.tasks
+                    .push(JoinGuard::spawn(move |cancel| {
+                        async move {
+                            tokio::select! {
+                                () = cancel.wait() => {}
+                                () = handler.fsck(repair) => {}
+                            }
+                        }
+                        .instrument(tracing::info_span!("ddcache/fsck"))
+                    }))
+                    .unwrap();
             }
         }
     }
@@ -482,11 +621,40 @@ impl Handler {
             .response_send
             .send(self.response_envelope.map(|()| response));
     }
+
+    /// Sends the frames of a streaming response.
+    fn send_responses(self, responses: Vec<Frame>) {
+        for response in responses {
+            let _ = self
+                .response_send
+                .send(rpc::copy_envelope(&self.response_envelope).map(|()| response));
+        }
+    }
+}
+
+impl Handler {
+    /// Runs the handler, aborting it and responding with a timeout error after `timeout`.
+    ///
+    /// Aborting drops the handler, which releases its resources, including the concurrency permit.
+    async fn run_with_timeout<F, Fut>(self, timeout: Duration, run: F)
+    where
+        F: FnOnce(Self) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let response_envelope = rpc::copy_envelope(&self.response_envelope);
+        let response_send = self.response_send.clone();
+        let start = Instant::now();
+        if time::timeout(timeout, run(self)).await.is_err() {
+            let elapsed = start.elapsed();
+            tracing::warn!(?elapsed, "request timeout");
+            let _ = response_send.send(response_envelope.map(|()| rep::timeout_error(elapsed)));
+        }
+    }
 }
 
 impl Handler {
     fn cancel(self, token: Token) {
-        if self.state.remove(token).is_some() {
+        if self.state.cancel(token) {
             tracing::debug!(token, "cancel");
         }
         self.send_response(rep::cancel_response());
@@ -510,6 +678,8 @@ impl Handler {
         let metadata = reader.metadata();
         let size = reader.size();
         let expire_at = reader.expire_at();
+        let stale_at = reader.stale_at();
+        let dictionary = reader.dictionary();
 
         // No errors after this point.
 
@@ -520,6 +690,8 @@ impl Handler {
             metadata,
             size.try_into().unwrap(),
             expire_at,
+            stale_at,
+            dictionary,
             endpoint,
             token,
         ));
@@ -536,6 +708,8 @@ impl Handler {
             reader.metadata(),
             reader.size().try_into().unwrap(),
             reader.expire_at(),
+            reader.stale_at(),
+            reader.dictionary(),
         ));
     }
 
@@ -550,12 +724,16 @@ impl Handler {
 }
 
 impl Handler {
+    #[allow(clippy::too_many_arguments)]
     fn write(
         mut self,
         key: Bytes,
         metadata: Option<Bytes>,
         size: usize,
         expire_at: Option<Timestamp>,
+        stale_at: Option<Timestamp>,
+        temperature: Temperature,
+        dictionary: Option<DictionaryId>,
     ) {
         // TODO: Pick a blob endpoint matching the client endpoint.
         let Some(endpoint) = self.blob_endpoints.first().copied() else {
@@ -570,6 +748,9 @@ impl Handler {
 
         writer.set_metadata(metadata);
         writer.set_expire_at(expire_at);
+        writer.set_stale_at(stale_at);
+        writer.set_temperature(to_temperature(temperature));
+        writer.set_dictionary(dictionary);
 
         // No errors after this point.
 
@@ -584,6 +765,7 @@ impl Handler {
         key: Bytes,
         new_metadata: Option<Option<Bytes>>,
         new_expire_at: Option<Option<Timestamp>>,
+        new_stale_at: Option<Option<Timestamp>>,
     ) {
         let Some(mut writer) = self.try_write_lock(key.clone(), false) else {
             self.send_response(rep::ok_none_response());
@@ -600,6 +782,8 @@ impl Handler {
         let metadata = writer.metadata();
         let size = writer.size();
         let expire_at = writer.expire_at();
+        let stale_at = writer.stale_at();
+        let dictionary = writer.dictionary();
 
         if let Some(new_metadata) = new_metadata {
             writer.set_metadata(new_metadata);
@@ -607,9 +791,18 @@ impl Handler {
         if let Some(new_expire_at) = new_expire_at {
             writer.set_expire_at(new_expire_at);
         }
+        if let Some(new_stale_at) = new_stale_at {
+            writer.set_stale_at(new_stale_at);
+        }
 
         self.send_response(match writer.commit() {
-            Ok(()) => rep::write_metadata_response(metadata, size.try_into().unwrap(), expire_at),
+            Ok(()) => rep::write_metadata_response(
+                metadata,
+                size.try_into().unwrap(),
+                expire_at,
+                stale_at,
+                dictionary,
+            ),
             Err(error) => {
                 tracing::warn!(key = %key.escape_ascii(), %error, "writer commit error");
                 rep::server_error()
@@ -633,9 +826,13 @@ impl Handler {
 impl Handler {
     async fn remove(self, key: Bytes) {
         let response = match self.storage.remove(key.clone()).await {
-            Ok(Some((metadata, size, expire_at))) => {
-                rep::remove_response(metadata, size.try_into().unwrap(), expire_at)
-            }
+            Ok(Some((metadata, size, expire_at, stale_at, dictionary))) => rep::remove_response(
+                metadata,
+                size.try_into().unwrap(),
+                expire_at,
+                stale_at,
+                dictionary,
+            ),
             Ok(None) => rep::ok_none_response(),
             Err(error) => {
                 tracing::warn!(key = %key.escape_ascii(), %error, "remove error");
@@ -663,6 +860,8 @@ impl Handler {
         let metadata = reader.metadata();
         let size = reader.size();
         let expire_at = reader.expire_at();
+        let stale_at = reader.stale_at();
+        let dictionary = reader.dictionary();
 
         // No errors after this point.
 
@@ -673,17 +872,23 @@ impl Handler {
             metadata,
             size.try_into().unwrap(),
             expire_at,
+            stale_at,
+            dictionary,
             endpoint,
             token,
         ));
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn push(
         mut self,
         key: Bytes,
         metadata: Option<Bytes>,
         size: usize,
         expire_at: Option<Timestamp>,
+        stale_at: Option<Timestamp>,
+        temperature: Temperature,
+        dictionary: Option<DictionaryId>,
     ) {
         // TODO: Pick a blob endpoint matching the peer endpoint.
         let Some(endpoint) = self.blob_endpoints.first().copied() else {
@@ -699,6 +904,9 @@ impl Handler {
 
         writer.set_metadata(metadata);
         writer.set_expire_at(expire_at);
+        writer.set_stale_at(stale_at);
+        writer.set_temperature(to_temperature(temperature));
+        writer.set_dictionary(dictionary);
 
         // No errors after this point.
 
@@ -707,6 +915,92 @@ impl Handler {
         tracing::debug!(token);
         self.send_response(rep::push_response(endpoint, token));
     }
+
+    fn changes(self, cursor: Option<ChangeCursor>, limit: usize, stream: bool) {
+        let cursor = cursor.map(|cursor| Cursor {
+            epoch: cursor.epoch,
+            sequence: cursor.sequence,
+        });
+        let changes = self.storage.changes(cursor, limit);
+        tracing::debug!(
+            reset = changes.reset,
+            num_keys = changes.keys.len(),
+            lag = changes.lag
+        );
+        if stream {
+            self.send_responses(rep::changes_responses(changes, *crate::stream_chunk_size()));
+        } else {
+            self.send_response(rep::changes_response(changes));
+        }
+    }
+
+    fn find(self, namespace: Bytes, field: Bytes, value: Bytes, limit: usize) {
+        let response = match self.storage.find(&namespace, &field, &value, limit) {
+            Some(keys) => {
+                tracing::debug!(num_keys = keys.len());
+                rep::find_response(keys)
+            }
+            None => {
+                tracing::warn!(
+                    namespace = %namespace.escape_ascii(),
+                    field = %field.escape_ascii(),
+                    "field is not indexed",
+                );
+                rep::invalid_request_error()
+            }
+        };
+        self.send_response(response);
+    }
+}
+
+impl Handler {
+    fn install_dictionary(self, id: DictionaryId, dictionary: Bytes) {
+        let response = match self.storage.install_dictionary(id, &dictionary) {
+            Ok(true) => {
+                tracing::info!(id, size = dictionary.len(), "install dictionary");
+                rep::install_dictionary_response()
+            }
+            Ok(false) => {
+                tracing::warn!(id, "dictionary id conflict");
+                rep::invalid_request_error()
+            }
+            Err(error) => {
+                tracing::warn!(id, %error, "install dictionary error");
+                rep::server_error()
+            }
+        };
+        self.send_response(response);
+    }
+
+    fn read_dictionary(self, id: DictionaryId) {
+        let response = match self.storage.read_dictionary(id) {
+            Ok(Some(dictionary)) => rep::read_dictionary_response(dictionary),
+            Ok(None) => rep::ok_none_response(),
+            Err(error) => {
+                tracing::warn!(id, %error, "read dictionary error");
+                rep::server_error()
+            }
+        };
+        self.send_response(response);
+    }
+}
+
+impl Handler {
+    async fn fsck(self, repair: FsckRepair) {
+        let repair = Repair {
+            drop_entry: repair.drop_entry,
+            adopt_orphan: repair.adopt_orphan,
+            recompute_size: repair.recompute_size,
+        };
+        let response = match self.storage.fsck(repair).await {
+            Ok(report) => rep::fsck_response(report),
+            Err(error) => {
+                tracing::warn!(%error, "fsck error");
+                rep::server_error()
+            }
+        };
+        self.send_response(response);
+    }
 }
 
 async fn evict(cancel: Cancel, storage: Storage, target_size: u64) -> Result<(), Error> {
@@ -733,3 +1027,11 @@ async fn expire(cancel: Cancel, storage: Storage) -> Result<(), Error> {
     tracing::info!(old_size, new_size, ?duration, "expire");
     Ok(())
 }
+
+fn to_temperature(temperature: Temperature) -> ddcache_storage::Temperature {
+    match temperature {
+        Temperature::Cold => ddcache_storage::Temperature::Cold,
+        Temperature::Normal => ddcache_storage::Temperature::Normal,
+        Temperature::Hot => ddcache_storage::Temperature::Hot,
+    }
+}
@@ -0,0 +1,318 @@
+//! Workload Sketches
+//!
+//! Sketches give operators an approximate view of the workload shape at a small, constant memory
+//! cost, without scanning the storage.  They are reset at the start of each window.
+
+use std::collections::HashMap;
+use std::hash::{BuildHasher, RandomState};
+use std::time::Duration;
+
+use bytes::Bytes;
+use tokio::time::Instant;
+
+use ddcache_rpc::{Request, WorkloadStats};
+
+#[derive(Debug)]
+pub(crate) struct Sketches {
+    window: Duration,
+    window_start: Instant,
+    distinct_keys: HyperLogLog,
+    write_sizes: SizeHistogram,
+    top_keys: TopKeys,
+}
+
+impl Sketches {
+    pub(crate) fn new() -> Self {
+        Self::with_param(*crate::stats_window(), *crate::stats_top_keys())
+    }
+
+    fn with_param(window: Duration, num_top_keys: usize) -> Self {
+        Self {
+            window,
+            window_start: Instant::now(),
+            distinct_keys: HyperLogLog::new(),
+            write_sizes: SizeHistogram::new(),
+            top_keys: TopKeys::new(num_top_keys),
+        }
+    }
+
+    /// Records a client request.
+    pub(crate) fn record(&mut self, request: &Request) {
+        let key = match request {
+            Request::Read { key }
+            | Request::ReadMetadata { key }
+            | Request::WriteMetadata { key, .. }
+            | Request::Remove { key } => key,
+            Request::Write { key, size, .. } => {
+                self.check_window();
+                self.write_sizes.add(*size);
+                key
+            }
+            _ => return,
+        };
+        self.check_window();
+        self.distinct_keys.add(key);
+        self.top_keys.add(key);
+    }
+
+    pub(crate) fn report(&mut self) -> WorkloadStats {
+        self.check_window();
+        WorkloadStats {
+            window_elapsed: self.window_start.elapsed(),
+            num_distinct_keys: self.distinct_keys.estimate(),
+            write_sizes: self.write_sizes.buckets().to_vec(),
+            top_keys: self.top_keys.top(),
+        }
+    }
+
+    fn check_window(&mut self) {
+        let now = Instant::now();
+        if now < self.window_start + self.window {
+            return;
+        }
+        self.window_start = now;
+        self.distinct_keys.clear();
+        self.write_sizes.clear();
+        self.top_keys.clear();
+    }
+}
+
+/// Estimates the number of distinct keys.
+#[derive(Debug)]
+struct HyperLogLog {
+    hasher: RandomState,
+    registers: Box<[u8; HLL_NUM_REGISTERS]>,
+}
+
+// The standard error is about 1.04 / sqrt(4096) = 1.6%.
+const HLL_PRECISION: u32 = 12;
+const HLL_NUM_REGISTERS: usize = 1 << HLL_PRECISION;
+
+impl HyperLogLog {
+    fn new() -> Self {
+        Self {
+            hasher: RandomState::new(),
+            registers: Box::new([0; HLL_NUM_REGISTERS]),
+        }
+    }
+
+    fn add(&mut self, key: &[u8]) {
+        let hash = self.hasher.hash_one(key);
+        let index = usize::try_from(hash >> (u64::BITS - HLL_PRECISION)).unwrap();
+        // Set a sentinel bit to cap the rank at `64 - HLL_PRECISION + 1`.
+        let rank = (hash << HLL_PRECISION) | (1 << (HLL_PRECISION - 1));
+        let rank = u8::try_from(rank.leading_zeros() + 1).unwrap();
+        self.registers[index] = self.registers[index].max(rank);
+    }
+
+    fn estimate(&self) -> u64 {
+        let m = HLL_NUM_REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|rank| 2.0f64.powi(-i32::from(*rank)))
+            .sum();
+        let mut estimate = alpha * m * m / sum;
+        // Small range correction.
+        let num_zeros = self.registers.iter().filter(|rank| **rank == 0).count();
+        if estimate <= 2.5 * m && num_zeros > 0 {
+            estimate = m * (m / num_zeros as f64).ln();
+        }
+        estimate.round() as u64
+    }
+
+    fn clear(&mut self) {
+        self.registers.fill(0);
+    }
+}
+
+/// Counts sizes in power-of-two buckets.
+#[derive(Debug)]
+struct SizeHistogram([u64; SIZE_NUM_BUCKETS]);
+
+const SIZE_NUM_BUCKETS: usize = usize::BITS as usize + 1;
+
+impl SizeHistogram {
+    fn new() -> Self {
+        Self([0; SIZE_NUM_BUCKETS])
+    }
+
+    fn add(&mut self, size: usize) {
+        self.0[usize::try_from(usize::BITS - size.leading_zeros()).unwrap()] += 1;
+    }
+
+    /// Returns the buckets, excluding trailing empty ones.
+    fn buckets(&self) -> &[u64] {
+        let len = self
+            .0
+            .iter()
+            .rposition(|count| *count != 0)
+            .map_or(0, |i| i + 1);
+        &self.0[..len]
+    }
+
+    fn clear(&mut self) {
+        self.0.fill(0);
+    }
+}
+
+/// Tracks the most frequently touched keys with the Space-Saving algorithm.
+///
+/// A count may overestimate the true count by up to the smallest count being tracked.
+#[derive(Debug)]
+struct TopKeys {
+    capacity: usize,
+    counts: HashMap<Bytes, u64>,
+}
+
+impl TopKeys {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            counts: HashMap::with_capacity(capacity),
+        }
+    }
+
+    fn add(&mut self, key: &Bytes) {
+        if let Some(count) = self.counts.get_mut(key) {
+            *count += 1;
+            return;
+        }
+        if self.capacity == 0 {
+            return;
+        }
+        let count = if self.counts.len() < self.capacity {
+            1
+        } else {
+            // The capacity is small; a linear scan should be fast enough.
+            let (min_key, min_count) = self
+                .counts
+                .iter()
+                .min_by_key(|(_, count)| **count)
+                .map(|(key, count)| (key.clone(), *count))
+                .unwrap();
+            self.counts.remove(&min_key);
+            min_count + 1
+        };
+        self.counts.insert(key.clone(), count);
+    }
+
+    fn top(&self) -> Vec<(Bytes, u64)> {
+        let mut top: Vec<_> = self
+            .counts
+            .iter()
+            .map(|(key, count)| (key.clone(), *count))
+            .collect();
+        top.sort_by(|(key_a, count_a), (key_b, count_b)| {
+            count_b.cmp(count_a).then_with(|| key_a.cmp(key_b))
+        });
+        top
+    }
+
+    fn clear(&mut self) {
+        self.counts.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time;
+
+    use ddcache_rpc::Temperature;
+
+    use super::*;
+
+    #[test]
+    fn hyper_log_log() {
+        let mut hll = HyperLogLog::new();
+        assert_eq!(hll.estimate(), 0);
+
+        for n in [1, 10, 100, 1000, 10000, 100000] {
+            hll.clear();
+            for i in 0..n {
+                hll.add(format!("key-{i}").as_bytes());
+                // Duplicates do not count.
+                hll.add(format!("key-{i}").as_bytes());
+            }
+            let estimate = hll.estimate() as f64;
+            let error = (estimate - n as f64).abs() / n as f64;
+            assert!(error < 0.1, "n={n} estimate={estimate}");
+        }
+    }
+
+    #[test]
+    fn size_histogram() {
+        let mut histogram = SizeHistogram::new();
+        assert_eq!(histogram.buckets(), &[] as &[u64]);
+
+        for size in [0, 1, 2, 3, 4, 7, 8, 1000] {
+            histogram.add(size);
+        }
+        assert_eq!(histogram.buckets(), &[1, 1, 2, 2, 1, 0, 0, 0, 0, 0, 1]);
+
+        histogram.add(usize::MAX);
+        assert_eq!(histogram.buckets().len(), SIZE_NUM_BUCKETS);
+
+        histogram.clear();
+        assert_eq!(histogram.buckets(), &[] as &[u64]);
+    }
+
+    #[test]
+    fn top_keys() {
+        let a = Bytes::from_static(b"a");
+        let b = Bytes::from_static(b"b");
+        let c = Bytes::from_static(b"c");
+
+        let mut top_keys = TopKeys::new(2);
+        assert_eq!(top_keys.top(), vec![]);
+
+        for key in [&a, &a, &a, &b, &b] {
+            top_keys.add(key);
+        }
+        assert_eq!(top_keys.top(), vec![(a.clone(), 3), (b.clone(), 2)]);
+
+        // `c` replaces `b` and inherits its count.
+        top_keys.add(&c);
+        assert_eq!(top_keys.top(), vec![(a.clone(), 3), (c.clone(), 3)]);
+
+        let mut top_keys = TopKeys::new(0);
+        top_keys.add(&a);
+        assert_eq!(top_keys.top(), vec![]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sketches() {
+        let window = Duration::from_secs(60);
+        let mut sketches = Sketches::with_param(window, 2);
+        assert_eq!(sketches.report(), WorkloadStats::default());
+
+        let key = Bytes::from_static(b"k");
+        sketches.record(&Request::Write {
+            key: key.clone(),
+            metadata: None,
+            size: 4,
+            expire_at: None,
+            stale_at: None,
+            temperature: Temperature::Normal,
+            dictionary: None,
+        });
+        sketches.record(&Request::Read { key: key.clone() });
+        sketches.record(&Request::Pull { key: key.clone() });
+        sketches.record(&Request::Ping);
+
+        time::advance(Duration::from_secs(1)).await;
+        assert_eq!(
+            sketches.report(),
+            WorkloadStats {
+                window_elapsed: Duration::from_secs(1),
+                num_distinct_keys: 1,
+                write_sizes: vec![0, 0, 0, 1],
+                top_keys: vec![(key.clone(), 2)],
+            },
+        );
+
+        time::advance(window).await;
+        assert_eq!(sketches.report(), WorkloadStats::default());
+    }
+}
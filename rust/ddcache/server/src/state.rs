@@ -6,6 +6,7 @@ use tokio::sync::OwnedSemaphorePermit;
 use tokio::time::Instant;
 
 use g1_base::sync::MutexExt;
+use g1_tokio::task::Cancel;
 
 use ddcache_rpc::Token;
 use ddcache_storage::{ReadGuard, WriteGuard};
@@ -16,6 +17,8 @@ pub(crate) struct State(Mutex<Inner>);
 #[derive(Debug)]
 struct Inner {
     map: HashMap<Token, Io>,
+    // Blob transfers in progress, which are no longer in `map`.
+    transfers: HashMap<Token, Cancel>,
 
     // For now, we can use `VecDeque` because `timeout` is fixed.
     deadlines: VecDeque<(Instant, Token)>,
@@ -75,8 +78,33 @@ impl State {
         token
     }
 
-    pub(crate) fn remove(&self, token: Token) -> Option<Io> {
-        self.0.must_lock().map.remove(&token)
+    /// Removes the blob I/O of `token` and registers the transfer so that `cancel` can abort it.
+    ///
+    /// The caller must call `finish_transfer` when the transfer completes or is aborted.
+    pub(crate) fn start_transfer(&self, token: Token, cancel: Cancel) -> Option<Io> {
+        let mut inner = self.0.must_lock();
+        let io = inner.map.remove(&token)?;
+        assert!(inner.transfers.insert(token, cancel).is_none());
+        Some(io)
+    }
+
+    pub(crate) fn finish_transfer(&self, token: Token) {
+        self.0.must_lock().transfers.remove(&token);
+    }
+
+    /// Releases the blob I/O of `token`, or aborts its transfer if it is in progress.
+    pub(crate) fn cancel(&self, token: Token) -> bool {
+        let mut inner = self.0.must_lock();
+        if inner.map.remove(&token).is_some() {
+            return true;
+        }
+        match inner.transfers.get(&token) {
+            Some(cancel) => {
+                cancel.set();
+                true
+            }
+            None => false,
+        }
     }
 }
 
@@ -84,6 +112,7 @@ impl Inner {
     fn new() -> Self {
         Self {
             map: HashMap::new(),
+            transfers: HashMap::new(),
             deadlines: VecDeque::new(),
             timeout: *crate::blob_lease_timeout(),
         }
@@ -93,7 +122,8 @@ impl Inner {
         for _ in 0..4 {
             let token = rand::random();
             // It is a small detail, but we do not generate 0.
-            if token != 0 && !self.map.contains_key(&token) {
+            if token != 0 && !self.map.contains_key(&token) && !self.transfers.contains_key(&token)
+            {
                 return token;
             }
         }
@@ -21,6 +21,8 @@ struct Program {
 
     #[arg(long, global = true, default_value = ".")]
     storage_dir: PathBuf,
+    #[arg(long, global = true)]
+    dedup: bool,
     #[command(subcommand)]
     command: Command,
 }
@@ -59,6 +61,8 @@ struct Write {
     file: PathBuf,
     #[arg(long)]
     expire_at: Option<Timestamp>,
+    #[arg(long)]
+    stale_at: Option<Timestamp>,
 }
 
 #[derive(Args, Debug)]
@@ -68,7 +72,11 @@ struct Remove {
 
 impl Program {
     async fn execute(&self) -> Result<(), Error> {
-        let storage = Storage::open(&self.storage_dir).await?;
+        let storage = if self.dedup {
+            Storage::open_dedup(&self.storage_dir).await?
+        } else {
+            Storage::open(&self.storage_dir).await?
+        };
         match &self.command {
             Command::Size => {
                 eprintln!("size={}", storage.size());
@@ -106,6 +114,7 @@ impl Program {
                 };
                 eprintln!("read: metadata={:?}", reader.metadata());
                 eprintln!("read: expire_at={:?}", reader.expire_at());
+                eprintln!("read: stale_at={:?}", reader.stale_at());
                 let size = usize::try_from(reader.size()).unwrap();
                 reader.open()?.splice(&mut file, size).await?;
             }
@@ -114,12 +123,14 @@ impl Program {
                 metadata,
                 file,
                 expire_at,
+                stale_at,
             }) => {
                 let mut file = OpenOptions::new().read(true).open(file)?;
                 let size = usize::try_from(file.metadata()?.len()).unwrap();
                 let mut writer = storage.write(key.clone(), /* truncate */ true).await?;
                 writer.set_metadata(metadata.clone());
                 writer.set_expire_at(*expire_at);
+                writer.set_stale_at(*stale_at);
                 file.splice(writer.open()?, size).await?;
                 writer.commit()?;
             }
@@ -8,7 +8,8 @@ use xattr::{self, SUPPORTED_PLATFORM};
 
 use g1_chrono::{Timestamp, TimestampExt};
 
-use crate::storage_capnp::blob_metadata;
+use crate::hash::ContentHash;
+use crate::storage_capnp::{self, blob_metadata};
 
 // Given our use case, it seems more efficient to use a shareable type `Bytes` than a `capnp`
 // reader.
@@ -18,6 +19,27 @@ pub(crate) struct BlobMetadata {
     pub(crate) metadata: Option<Bytes>,
     pub(crate) size: u64,
     pub(crate) expire_at: Option<Timestamp>,
+    pub(crate) stale_at: Option<Timestamp>,
+    pub(crate) content_hash: Option<ContentHash>,
+    pub(crate) temperature: Temperature,
+    pub(crate) dictionary: Option<DictionaryId>,
+}
+
+/// Id of the zstd dictionary that a blob's content is compressed with.
+///
+/// The storage does not interpret it; compression is done by the clients.
+pub type DictionaryId = u32;
+
+/// A writer's hint of how expensive an entry is to recompute.
+///
+/// Eviction prefers colder entries over warmer ones, and only falls back to the LRU order among
+/// entries of the same temperature.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Temperature {
+    Cold,
+    #[default]
+    Normal,
+    Hot,
 }
 
 // We store blob metadata in an extended attribute.
@@ -56,12 +78,38 @@ impl BlobMetadata {
                 .map_err(|expire_at| {
                     Error::other(std::format!("invalid timestamp: {expire_at}"))
                 })?;
+            let stale_at = <Option<Timestamp>>::from_timestamp_secs(blob_metadata.get_stale_at())
+                .map_err(|stale_at| {
+                Error::other(std::format!("invalid timestamp: {stale_at}"))
+            })?;
+
+            let content_hash = blob_metadata.get_content_hash()?;
+            let content_hash = if content_hash.is_empty() {
+                None
+            } else {
+                let Some(content_hash) = ContentHash::from_slice(content_hash) else {
+                    return Err(Error::other(format!(
+                        "invalid ddcache content hash: {:?}",
+                        blob_metadata,
+                    )));
+                };
+                Some(content_hash)
+            };
+
+            let temperature = blob_metadata.get_temperature()?.into();
+
+            let dictionary = blob_metadata.get_dictionary();
+            let dictionary = (dictionary != 0).then_some(dictionary);
 
             Self {
                 key,
                 metadata,
                 size,
                 expire_at,
+                stale_at,
+                content_hash,
+                temperature,
+                dictionary,
             }
         };
         blob_metadata.map_err(Error::other)
@@ -74,6 +122,10 @@ impl BlobMetadata {
             metadata: None,
             size: 0,
             expire_at: None,
+            stale_at: None,
+            content_hash: None,
+            temperature: Temperature::Normal,
+            dictionary: None,
         }
     }
 
@@ -89,6 +141,12 @@ impl BlobMetadata {
             blob_metadata.set_metadata(metadata);
         }
         blob_metadata.set_expire_at(self.expire_at.timestamp_u64());
+        blob_metadata.set_stale_at(self.stale_at.timestamp_u64());
+        if let Some(content_hash) = self.content_hash.as_ref() {
+            blob_metadata.set_content_hash(content_hash.as_slice());
+        }
+        blob_metadata.set_temperature(self.temperature.into());
+        blob_metadata.set_dictionary(self.dictionary.unwrap_or(0));
         serialize::write_message_to_words(&builder).into()
     }
 
@@ -97,6 +155,26 @@ impl BlobMetadata {
     }
 }
 
+impl From<storage_capnp::Temperature> for Temperature {
+    fn from(temperature: storage_capnp::Temperature) -> Self {
+        match temperature {
+            storage_capnp::Temperature::Cold => Self::Cold,
+            storage_capnp::Temperature::Normal => Self::Normal,
+            storage_capnp::Temperature::Hot => Self::Hot,
+        }
+    }
+}
+
+impl From<Temperature> for storage_capnp::Temperature {
+    fn from(temperature: Temperature) -> Self {
+        match temperature {
+            Temperature::Cold => Self::Cold,
+            Temperature::Normal => Self::Normal,
+            Temperature::Hot => Self::Hot,
+        }
+    }
+}
+
 #[cfg(test)]
 mod test_harness {
     use super::*;
@@ -116,6 +194,10 @@ mod test_harness {
                 },
                 size,
                 expire_at: None,
+                stale_at: None,
+                content_hash: None,
+                temperature: Temperature::Normal,
+                dictionary: None,
             }
         }
     }
@@ -152,6 +234,31 @@ mod tests {
         assert_eq!(blob_metadata.key, b"hello".as_slice());
         assert_eq!(blob_metadata.metadata, None);
         assert_eq!(blob_metadata.expire_at, None);
+        assert_eq!(blob_metadata.content_hash, None);
+
+        let mut expect = BlobMetadata::new(Bytes::from_static(b"hello"));
+        expect.content_hash = Some(ContentHash::new(b"foo bar"));
+        expect.write(&path)?;
+        let blob_metadata = BlobMetadata::read(&path)?;
+        assert_eq!(blob_metadata.content_hash, expect.content_hash);
+        assert_eq!(blob_metadata.temperature, Temperature::Normal);
+
+        expect.temperature = Temperature::Hot;
+        expect.write(&path)?;
+        let blob_metadata = BlobMetadata::read(&path)?;
+        assert_eq!(blob_metadata.temperature, Temperature::Hot);
+        assert_eq!(blob_metadata.stale_at, None);
+
+        expect.stale_at = Some(Timestamp::from_timestamp_secs(1).unwrap());
+        expect.write(&path)?;
+        let blob_metadata = BlobMetadata::read(&path)?;
+        assert_eq!(blob_metadata.stale_at, expect.stale_at);
+        assert_eq!(blob_metadata.dictionary, None);
+
+        expect.dictionary = Some(42);
+        expect.write(&path)?;
+        let blob_metadata = BlobMetadata::read(&path)?;
+        assert_eq!(blob_metadata.dictionary, Some(42));
 
         Ok(())
     }
@@ -0,0 +1,206 @@
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+
+use g1_base::sync::MutexExt;
+
+/// Position in the change feed.
+///
+/// The change feed is not persisted, and `epoch` changes whenever the storage is opened.  A cursor
+/// of a different epoch is invalid, and so is one that has fallen off the change log.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Cursor {
+    pub epoch: u64,
+    pub sequence: u64,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct Changes {
+    pub cursor: Cursor,
+    /// If true, `keys` is a snapshot of all keys rather than the changes since the given cursor.
+    pub reset: bool,
+    pub keys: Vec<Bytes>,
+    /// Number of changes after `cursor`.
+    pub lag: u64,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct ChangeLog(Arc<Mutex<RawChangeLog>>);
+
+#[derive(Debug)]
+struct RawChangeLog {
+    epoch: u64,
+    // Sequence number of the next change.
+    head: u64,
+    log: VecDeque<Bytes>,
+    capacity: usize,
+}
+
+impl ChangeLog {
+    pub(crate) fn new(capacity: usize) -> Self {
+        // Nanoseconds since the Unix epoch should be unique enough, and never 0.
+        let epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos()
+            .try_into()
+            .unwrap();
+        Self::with_epoch(epoch, capacity)
+    }
+
+    fn with_epoch(epoch: u64, capacity: usize) -> Self {
+        assert!(capacity > 0);
+        Self(Arc::new(Mutex::new(RawChangeLog {
+            epoch,
+            head: 0,
+            log: VecDeque::with_capacity(capacity),
+            capacity,
+        })))
+    }
+
+    pub(crate) fn push(&self, key: Bytes) {
+        let mut log = self.0.must_lock();
+        if log.log.len() == log.capacity {
+            log.log.pop_front();
+        }
+        log.log.push_back(key);
+        log.head += 1;
+    }
+
+    pub(crate) fn changes(
+        &self,
+        cursor: Option<Cursor>,
+        limit: usize,
+        snapshot: impl FnOnce() -> Vec<Bytes>,
+    ) -> Changes {
+        let head = {
+            let log = self.0.must_lock();
+            let tail = log.head - u64::try_from(log.log.len()).unwrap();
+            match cursor {
+                Some(cursor)
+                    if cursor.epoch == log.epoch
+                        && (tail..=log.head).contains(&cursor.sequence) =>
+                {
+                    let start = usize::try_from(cursor.sequence - tail).unwrap();
+                    let end = log.log.len().min(start.saturating_add(limit));
+                    let mut seen = HashSet::new();
+                    let keys = log
+                        .log
+                        .range(start..end)
+                        .filter(|key| seen.insert(*key))
+                        .cloned()
+                        .collect();
+                    let sequence = tail + u64::try_from(end).unwrap();
+                    return Changes {
+                        cursor: Cursor {
+                            epoch: log.epoch,
+                            sequence,
+                        },
+                        reset: false,
+                        keys,
+                        lag: log.head - sequence,
+                    };
+                }
+                _ => Cursor {
+                    epoch: log.epoch,
+                    sequence: log.head,
+                },
+            }
+        };
+        // Take the snapshot without holding the lock.  Changes made in the meantime will be
+        // returned again after `head`, which is harmless.
+        Changes {
+            cursor: head,
+            reset: true,
+            keys: snapshot(),
+            lag: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn b(key: &'static str) -> Bytes {
+        Bytes::from_static(key.as_bytes())
+    }
+
+    #[test]
+    fn changes() {
+        let log = ChangeLog::with_epoch(1, 4);
+        let cursor = |sequence| Cursor { epoch: 1, sequence };
+        let snapshot = || vec![b("snapshot")];
+
+        assert_eq!(
+            log.changes(None, 10, snapshot),
+            Changes {
+                cursor: cursor(0),
+                reset: true,
+                keys: vec![b("snapshot")],
+                lag: 0,
+            },
+        );
+        assert_eq!(
+            log.changes(Some(cursor(0)), 10, snapshot),
+            Changes {
+                cursor: cursor(0),
+                reset: false,
+                keys: vec![],
+                lag: 0,
+            },
+        );
+
+        log.push(b("a"));
+        log.push(b("b"));
+        log.push(b("a"));
+        assert_eq!(
+            log.changes(Some(cursor(0)), 10, snapshot),
+            Changes {
+                cursor: cursor(3),
+                reset: false,
+                keys: vec![b("a"), b("b")],
+                lag: 0,
+            },
+        );
+        assert_eq!(
+            log.changes(Some(cursor(1)), 1, snapshot),
+            Changes {
+                cursor: cursor(2),
+                reset: false,
+                keys: vec![b("b")],
+                lag: 1,
+            },
+        );
+
+        // `cursor(0)` falls off the change log.
+        log.push(b("c"));
+        log.push(b("d"));
+        assert_eq!(
+            log.changes(Some(cursor(1)), 10, snapshot),
+            Changes {
+                cursor: cursor(5),
+                reset: false,
+                keys: vec![b("b"), b("a"), b("c"), b("d")],
+                lag: 0,
+            },
+        );
+        assert!(log.changes(Some(cursor(0)), 10, snapshot).reset);
+
+        // Invalid cursors.
+        assert!(log.changes(Some(cursor(6)), 10, snapshot).reset);
+        assert!(
+            log.changes(
+                Some(Cursor {
+                    epoch: 2,
+                    sequence: 5,
+                }),
+                10,
+                snapshot,
+            )
+            .reset
+        );
+    }
+}
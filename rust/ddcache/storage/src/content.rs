@@ -0,0 +1,309 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+};
+
+use g1_base::sync::MutexExt;
+
+use crate::hash::{self, ContentHash};
+
+//
+// Implementer's Notes: Content files are shared by blobs whose content is identical.  We do not
+// persist the reference counts; instead, we recount them from blob metadata in `Storage::open`,
+// where unreferenced content files are also removed.
+//
+
+/// Content-addressed store that backs deduplicated blobs.
+#[derive(Clone, Debug)]
+pub(crate) struct ContentStore(Arc<Inner>);
+
+#[derive(Debug)]
+struct Inner {
+    dir: PathBuf,
+    refs: Mutex<HashMap<ContentHash, usize>>,
+}
+
+#[derive(Debug)]
+pub(crate) struct ContentStoreBuilder {
+    dir: PathBuf,
+    refs: HashMap<ContentHash, usize>,
+}
+
+const CONTENT_DIR: &str = "content";
+
+// Suffixes of temporary files, which keep concurrent `insert` calls of the same content apart.
+static NEXT_TMP_ID: AtomicU64 = AtomicU64::new(0);
+
+impl ContentStoreBuilder {
+    pub(crate) fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.join(CONTENT_DIR),
+            refs: HashMap::new(),
+        }
+    }
+
+    /// Returns the size of the content and increments its reference count.
+    pub(crate) fn acquire(&mut self, content_hash: ContentHash) -> Result<u64, Error> {
+        let size = content_hash.to_path(&self.dir).metadata()?.len();
+        *self.refs.entry(content_hash).or_default() += 1;
+        Ok(size)
+    }
+
+    /// Reverts `acquire`, in case the blob turns out to be invalid.
+    pub(crate) fn unacquire(&mut self, content_hash: ContentHash) {
+        let count = self.refs.get_mut(&content_hash).unwrap();
+        *count -= 1;
+        if *count == 0 {
+            self.refs.remove(&content_hash);
+        }
+    }
+
+    pub(crate) fn build(self) -> Result<ContentStore, Error> {
+        let content_dirs = match self.dir.read_dir() {
+            Ok(content_dirs) => content_dirs,
+            Err(error) if error.kind() == ErrorKind::NotFound => {
+                return Ok(ContentStore::new(self.dir, self.refs));
+            }
+            Err(error) => return Err(error),
+        };
+        for content_dir in content_dirs {
+            let content_dir = content_dir?;
+            let Some(content_dir) = hash::match_blob_dir(&content_dir)? else {
+                tracing::debug!(
+                    content_dir = %content_dir.path().display(),
+                    "skip unrecognizable content dir",
+                );
+                continue;
+            };
+            let mut n = 0;
+            for content in content_dir.read_dir()? {
+                n += 1;
+                let content = content?;
+                // Remove leftovers of interrupted `ContentStore::insert` calls.
+                let Some(content) = hash::match_blob(&content)? else {
+                    tracing::debug!(
+                        content = %content.path().display(),
+                        "remove unrecognizable content",
+                    );
+                    fs::remove_file(content.path())?;
+                    n -= 1;
+                    continue;
+                };
+                if !self.refs.contains_key(&ContentHash::from_path(&content)) {
+                    tracing::debug!(content = %content.display(), "remove unreferenced content");
+                    fs::remove_file(&content)?;
+                    n -= 1;
+                }
+            }
+            if n == 0 {
+                tracing::debug!(content_dir = %content_dir.display(), "remove empty content dir");
+                fs::remove_dir(content_dir)?;
+            }
+        }
+        Ok(ContentStore::new(self.dir, self.refs))
+    }
+}
+
+impl ContentStore {
+    fn new(dir: PathBuf, refs: HashMap<ContentHash, usize>) -> Self {
+        Self(Arc::new(Inner {
+            dir,
+            refs: Mutex::new(refs),
+        }))
+    }
+
+    pub(crate) fn path(&self, content_hash: ContentHash) -> PathBuf {
+        content_hash.to_path(&self.0.dir)
+    }
+
+    /// Copies the content of a blob into the store, or references the existing content if it is
+    /// identical.  On success, the caller may truncate the blob.
+    ///
+    /// We trust the content hash and do not compare the content with the existing content.
+    pub(crate) fn insert(&self, blob: &Path) -> Result<ContentHash, Error> {
+        // Hash and copy the content without holding the lock, as both could take a while for
+        // large blobs.
+        let content_hash = ContentHash::from_file(blob)?;
+        if self.add_ref(content_hash) {
+            return Ok(content_hash);
+        }
+
+        let path = self.path(content_hash);
+        fs::create_dir_all(path.parent().unwrap())?;
+        // Copy to a temporary file first so that a content file is always complete.
+        let tmp_path = path.with_extension(format!(
+            "tmp{}",
+            NEXT_TMP_ID.fetch_add(1, Ordering::Relaxed),
+        ));
+        fs::copy(blob, &tmp_path)?;
+
+        // We hold the lock while renaming the temporary file so that `release` cannot remove the
+        // content from under us.
+        let mut refs = self.0.refs.must_lock();
+        if let Some(count) = refs.get_mut(&content_hash) {
+            // Another `insert` call added the content while we were copying it.
+            *count += 1;
+            drop(refs);
+            if let Err(error) = fs::remove_file(&tmp_path) {
+                tracing::warn!(content = %tmp_path.display(), %error, "remove temporary content");
+            }
+            return Ok(content_hash);
+        }
+        // We will remove the temporary file in `open` if we fail to rename it here.
+        fs::rename(&tmp_path, &path)?;
+        refs.insert(content_hash, 1);
+        Ok(content_hash)
+    }
+
+    /// Increments the reference count of the content if it exists.
+    fn add_ref(&self, content_hash: ContentHash) -> bool {
+        match self.0.refs.must_lock().get_mut(&content_hash) {
+            Some(count) => {
+                *count += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub(crate) fn release(&self, content_hash: ContentHash) {
+        let mut refs = self.0.refs.must_lock();
+        let count = refs.get_mut(&content_hash).unwrap();
+        *count -= 1;
+        if *count > 0 {
+            return;
+        }
+        refs.remove(&content_hash);
+        // We will remove the unreferenced content in `open` if we fail to remove it here.
+        let path = self.path(content_hash);
+        if let Err(error) = fs::remove_file(&path) {
+            tracing::warn!(content = %path.display(), %error, "remove content");
+        }
+    }
+
+    /// Returns the size of the content and increments its reference count, or returns `None` if
+    /// the content does not exist.
+    pub(crate) fn acquire(&self, content_hash: ContentHash) -> Result<Option<u64>, Error> {
+        let mut refs = self.0.refs.must_lock();
+        let size = match self.path(content_hash).metadata() {
+            Ok(metadata) => metadata.len(),
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error),
+        };
+        *refs.entry(content_hash).or_default() += 1;
+        Ok(Some(size))
+    }
+
+    /// Counts the unreferenced content files and optionally removes them.
+    ///
+    /// It returns the number of unreferenced and removed content files.
+    pub(crate) fn check_orphans(&self, remove: bool) -> Result<(usize, usize), Error> {
+        let content_dirs = match self.0.dir.read_dir() {
+            Ok(content_dirs) => content_dirs,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok((0, 0)),
+            Err(error) => return Err(error),
+        };
+        // Hold the lock so that `insert` cannot add a reference while we are scanning.
+        let refs = self.0.refs.must_lock();
+        let mut num_orphan = 0;
+        let mut num_removed = 0;
+        for content_dir in content_dirs {
+            let Some(content_dir) = hash::match_blob_dir(&content_dir?)? else {
+                continue;
+            };
+            for content in content_dir.read_dir()? {
+                let Some(content) = hash::match_blob(&content?)? else {
+                    continue;
+                };
+                if refs.contains_key(&ContentHash::from_path(&content)) {
+                    continue;
+                }
+                tracing::warn!(content = %content.display(), "unreferenced content");
+                num_orphan += 1;
+                if remove {
+                    fs::remove_file(&content)?;
+                    num_removed += 1;
+                }
+            }
+        }
+        Ok((num_orphan, num_removed))
+    }
+}
+
+#[cfg(test)]
+mod test_harness {
+    use super::*;
+
+    impl ContentStore {
+        pub(crate) fn refs(&self) -> HashMap<ContentHash, usize> {
+            self.0.refs.must_lock().clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_and_release() -> Result<(), Error> {
+        let tempdir = tempfile::tempdir()?;
+        let store = ContentStoreBuilder::new(tempdir.path()).build()?;
+
+        let foo = tempdir.path().join("foo");
+        let bar = tempdir.path().join("bar");
+        let baz = tempdir.path().join("baz");
+        fs::write(&foo, b"Hello, World!")?;
+        fs::write(&bar, b"Hello, World!")?;
+        fs::write(&baz, b"spam eggs")?;
+
+        let h1 = store.insert(&foo)?;
+        let h2 = store.insert(&bar)?;
+        let h3 = store.insert(&baz)?;
+        assert_eq!(h1, h2);
+        assert_eq!(h1, ContentHash::new(b"Hello, World!"));
+        assert_ne!(h1, h3);
+        assert_eq!(store.refs(), HashMap::from([(h1, 2), (h3, 1)]));
+        assert_eq!(fs::read(store.path(h1))?, b"Hello, World!");
+        assert_eq!(fs::read(store.path(h3))?, b"spam eggs");
+
+        store.release(h1);
+        assert_eq!(store.refs(), HashMap::from([(h1, 1), (h3, 1)]));
+        assert_eq!(store.path(h1).try_exists()?, true);
+
+        store.release(h1);
+        store.release(h3);
+        assert_eq!(store.refs(), HashMap::new());
+        assert_eq!(store.path(h1).try_exists()?, false);
+        assert_eq!(store.path(h3).try_exists()?, false);
+
+        Ok(())
+    }
+
+    #[test]
+    fn build() -> Result<(), Error> {
+        let tempdir = tempfile::tempdir()?;
+        let store = ContentStoreBuilder::new(tempdir.path()).build()?;
+
+        let foo = tempdir.path().join("foo");
+        let bar = tempdir.path().join("bar");
+        fs::write(&foo, b"Hello, World!")?;
+        fs::write(&bar, b"spam eggs")?;
+        let h1 = store.insert(&foo)?;
+        let h2 = store.insert(&bar)?;
+        drop(store);
+
+        let mut builder = ContentStoreBuilder::new(tempdir.path());
+        assert_eq!(builder.acquire(h1)?, 13);
+        let store = builder.build()?;
+        assert_eq!(store.refs(), HashMap::from([(h1, 1)]));
+        assert_eq!(store.path(h1).try_exists()?, true);
+        assert_eq!(store.path(h2).try_exists()?, false);
+
+        Ok(())
+    }
+}
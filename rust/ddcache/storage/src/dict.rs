@@ -0,0 +1,84 @@
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use bytes::Bytes;
+
+use crate::blob::DictionaryId;
+
+//
+// Implementer's Notes: Dictionaries are small and rarely installed, and thus we neither index
+// them in memory nor evict them.  A dictionary is immutable once installed, since the blobs that
+// are compressed with it would otherwise become unreadable.
+//
+
+/// Stores the zstd dictionaries that clients compress blobs with.
+#[derive(Clone, Debug)]
+pub(crate) struct DictionaryStore {
+    dir: PathBuf,
+}
+
+const DICTIONARY_DIR: &str = "dictionary";
+
+impl DictionaryStore {
+    pub(crate) fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.join(DICTIONARY_DIR),
+        }
+    }
+
+    /// Installs a dictionary and returns false if a different dictionary has been installed under
+    /// the same id.
+    pub(crate) fn install(&self, id: DictionaryId, dictionary: &[u8]) -> Result<bool, Error> {
+        assert_ne!(id, 0);
+        if let Some(installed) = self.read(id)? {
+            return Ok(installed == dictionary);
+        }
+        if let Err(error) = fs::create_dir(&self.dir) {
+            if error.kind() != ErrorKind::AlreadyExists {
+                return Err(error);
+            }
+        }
+        // Write to a temporary file first so that a reader never observes a partial dictionary.
+        let path = self.path(id);
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, dictionary)?;
+        fs::rename(tmp, path)?;
+        Ok(true)
+    }
+
+    pub(crate) fn read(&self, id: DictionaryId) -> Result<Option<Bytes>, Error> {
+        match fs::read(self.path(id)) {
+            Ok(dictionary) => Ok(Some(dictionary.into())),
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn path(&self, id: DictionaryId) -> PathBuf {
+        self.dir.join(format!("{id:08x}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn install() -> Result<(), Error> {
+        let tempdir = tempfile::tempdir()?;
+        let dictionaries = DictionaryStore::new(tempdir.path());
+        assert_eq!(dictionaries.read(1)?, None);
+
+        assert_eq!(dictionaries.install(1, b"foo")?, true);
+        assert_eq!(dictionaries.read(1)?, Some(Bytes::from_static(b"foo")));
+        assert_eq!(dictionaries.install(1, b"foo")?, true);
+        assert_eq!(dictionaries.install(1, b"bar")?, false);
+        assert_eq!(dictionaries.read(1)?, Some(Bytes::from_static(b"foo")));
+
+        assert_eq!(dictionaries.install(2, b"bar")?, true);
+        assert_eq!(dictionaries.read(2)?, Some(Bytes::from_static(b"bar")));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,323 @@
+//! Storage Integrity Check
+
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+use tokio::task;
+
+use crate::blob::BlobMetadata;
+use crate::hash::{self, KeyHash};
+use crate::{map, Storage};
+
+/// Repair actions of `Storage::fsck`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Repair {
+    /// Removes the index entries whose blob is missing.
+    pub drop_entry: bool,
+    /// Adds valid orphan blobs to the index, and removes the orphans that cannot be adopted.
+    pub adopt_orphan: bool,
+    /// Updates the index entries whose size does not match the blob.
+    pub recompute_size: bool,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct FsckReport {
+    pub num_entries: usize,
+    /// Number of entries that were locked by other operations and were not checked.
+    pub num_busy: usize,
+    pub num_missing: usize,
+    pub num_size_mismatch: usize,
+    /// Number of blob and content files that no index entry refers to.
+    pub num_orphan: usize,
+    pub num_repaired: usize,
+}
+
+impl Storage {
+    /// Cross-checks the index against the blob files.
+    ///
+    /// NOTE: `open` already removes invalid blobs and unreferenced content files and derives the
+    /// blob sizes from the files; therefore, this is mostly useful on a running server whose
+    /// storage directory might have been tampered with.
+    pub async fn fsck(&self, repair: Repair) -> Result<FsckReport, Error> {
+        // Scanning directories seems to warrant using `spawn_blocking`.
+        let this = self.clone();
+        task::spawn_blocking(move || this.fsck_blocking(repair))
+            .await
+            .unwrap()
+    }
+
+    fn fsck_blocking(&self, repair: Repair) -> Result<FsckReport, Error> {
+        let mut report = FsckReport::default();
+        self.check_entries(repair, &mut report)?;
+        self.check_blobs(repair, &mut report)?;
+        let (num_orphan, num_removed) = self.content.check_orphans(repair.adopt_orphan)?;
+        report.num_orphan += num_orphan;
+        report.num_repaired += num_removed;
+        tracing::info!(?repair, ?report, "fsck");
+        Ok(report)
+    }
+
+    fn check_entries(&self, repair: Repair, report: &mut FsckReport) -> Result<(), Error> {
+        for key in self.map.keys() {
+            report.num_entries += 1;
+            // Skip the entries that are being written to or removed.
+            let Some((hash, guard)) = self.map.try_write_peek(key) else {
+                report.num_busy += 1;
+                continue;
+            };
+            let blob_metadata = guard.blob_metadata();
+
+            let blob = hash.to_path(&self.dir);
+            let size: Result<_, Error> = try {
+                let blob_size = blob.metadata()?.len();
+                match blob_metadata.content_hash {
+                    Some(content_hash) => self.content.path(content_hash).metadata()?.len(),
+                    None => blob_size,
+                }
+            };
+            let size = match size {
+                Ok(size) => size,
+                Err(error) if error.kind() == ErrorKind::NotFound => {
+                    tracing::warn!(key = ?blob_metadata.key, blob = %blob.display(), "missing blob");
+                    report.num_missing += 1;
+                    if repair.drop_entry {
+                        self.drop_entry(&blob, guard)?;
+                        report.num_repaired += 1;
+                    }
+                    continue;
+                }
+                Err(error) => return Err(error),
+            };
+
+            if size != blob_metadata.size {
+                tracing::warn!(
+                    key = ?blob_metadata.key,
+                    size,
+                    expect = blob_metadata.size,
+                    "blob size mismatch",
+                );
+                report.num_size_mismatch += 1;
+                if repair.recompute_size {
+                    let mut new_metadata = blob_metadata.clone();
+                    new_metadata.size = size;
+                    guard.commit(new_metadata);
+                    report.num_repaired += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn drop_entry(&self, blob: &Path, guard: map::WriteGuard) -> Result<(), Error> {
+        // In the dedup mode, the blob file may exist while its content is missing.
+        if let Err(error) = fs::remove_file(blob) {
+            if error.kind() != ErrorKind::NotFound {
+                return Err(error);
+            }
+        }
+        let blob_metadata = guard.blob_metadata();
+        if let Some(content_hash) = blob_metadata.content_hash {
+            self.content.release(content_hash);
+        }
+        let key = blob_metadata.key.clone();
+        guard.commit_remove();
+        self.index.remove(&key);
+        self.changes.push(key);
+        Ok(())
+    }
+
+    fn check_blobs(&self, repair: Repair, report: &mut FsckReport) -> Result<(), Error> {
+        for blob_dir in self.dir.read_dir()? {
+            let Some(blob_dir) = hash::match_blob_dir(&blob_dir?)? else {
+                continue;
+            };
+            for blob in blob_dir.read_dir()? {
+                let Some(blob) = hash::match_blob(&blob?)? else {
+                    continue;
+                };
+                if self.map.contains(KeyHash::from_path(&blob)) {
+                    continue;
+                }
+                self.check_orphan(&blob, repair, report)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn check_orphan(
+        &self,
+        blob: &Path,
+        repair: Repair,
+        report: &mut FsckReport,
+    ) -> Result<(), Error> {
+        let blob_metadata = match BlobMetadata::read(blob) {
+            // The blob was removed after we listed the directory.
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(()),
+            blob_metadata => blob_metadata,
+        };
+        tracing::warn!(blob = %blob.display(), "orphan blob");
+        report.num_orphan += 1;
+        if !repair.adopt_orphan {
+            return Ok(());
+        }
+
+        let is_adopted = match blob_metadata {
+            Ok(blob_metadata) => self.adopt(blob, blob_metadata),
+            Err(error) => {
+                tracing::warn!(blob = %blob.display(), %error, "invalid blob");
+                false
+            }
+        };
+        if !is_adopted {
+            tracing::warn!(blob = %blob.display(), "remove orphan blob");
+            fs::remove_file(blob)?;
+        }
+        report.num_repaired += 1;
+        Ok(())
+    }
+
+    fn adopt(&self, blob: &Path, mut blob_metadata: BlobMetadata) -> bool {
+        if KeyHash::new(&blob_metadata.key) != KeyHash::from_path(blob) {
+            tracing::warn!(blob = %blob.display(), ?blob_metadata, "expect hash(key) == blob");
+            return false;
+        }
+
+        let content_hash = blob_metadata.content_hash;
+        if let Some(content_hash) = content_hash {
+            match self.content.acquire(content_hash) {
+                Ok(Some(size)) => blob_metadata.size = size,
+                Ok(None) => {
+                    tracing::warn!(blob = %blob.display(), ?content_hash, "missing content");
+                    return false;
+                }
+                Err(error) => {
+                    tracing::warn!(blob = %blob.display(), ?content_hash, %error, "content");
+                    return false;
+                }
+            }
+        } else {
+            match blob.metadata() {
+                Ok(metadata) => blob_metadata.size = metadata.len(),
+                Err(error) => {
+                    tracing::warn!(blob = %blob.display(), %error, "blob");
+                    return false;
+                }
+            }
+        }
+
+        let key = blob_metadata.key.clone();
+        let Some((_, guard)) = self.map.write_new(key.clone()) else {
+            // Someone else has written the blob after we checked the index.
+            if let Some(content_hash) = content_hash {
+                self.content.release(content_hash);
+            }
+            return true;
+        };
+        tracing::info!(?key, "adopt orphan blob");
+        if let Some(expire_at) = blob_metadata.expire_at {
+            self.expire_queue.push(expire_at, key.clone());
+        }
+        guard.commit(blob_metadata);
+        self.changes.push(key);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::hash::ContentHash;
+
+    use super::*;
+
+    fn b(bytes: &'static str) -> Bytes {
+        Bytes::from_static(bytes.as_bytes())
+    }
+
+    async fn write(storage: &Storage, key: &'static str, content: &[u8]) -> Result<(), Error> {
+        let mut guard = storage.write(b(key), true).await?;
+        guard.open()?;
+        guard.write(content)?;
+        guard.commit()
+    }
+
+    #[tokio::test]
+    async fn fsck() -> Result<(), Error> {
+        let tempdir = tempfile::tempdir()?;
+        let storage = Storage::open(tempdir.path()).await?;
+        write(&storage, "foo", b"Hello, World!").await?;
+        write(&storage, "bar", b"spam eggs").await?;
+        write(&storage, "baz", b"xyz").await?;
+        assert_eq!(storage.size(), 25);
+
+        let report = storage.fsck(Repair::default()).await?;
+        assert_eq!(
+            report,
+            FsckReport {
+                num_entries: 3,
+                ..Default::default()
+            },
+        );
+
+        // Missing blob.
+        fs::remove_file(KeyHash::new(b"foo").to_path(tempdir.path()))?;
+        // Size mismatch.
+        fs::write(KeyHash::new(b"bar").to_path(tempdir.path()), b"spam")?;
+        // Orphan blob.
+        let orphan = KeyHash::new(b"qux").to_path(tempdir.path());
+        fs::create_dir_all(orphan.parent().unwrap())?;
+        fs::write(&orphan, b"quux")?;
+        BlobMetadata::new(b("qux")).write(&orphan)?;
+        // Invalid orphan blob.
+        let invalid = KeyHash::new(b"invalid").to_path(tempdir.path());
+        fs::create_dir_all(invalid.parent().unwrap())?;
+        fs::write(&invalid, b"")?;
+        // Orphan content.
+        let content = ContentHash::new(b"orphan").to_path(&tempdir.path().join("content"));
+        fs::create_dir_all(content.parent().unwrap())?;
+        fs::write(&content, b"orphan")?;
+
+        let expect = FsckReport {
+            num_entries: 3,
+            num_busy: 0,
+            num_missing: 1,
+            num_size_mismatch: 1,
+            num_orphan: 3,
+            num_repaired: 0,
+        };
+        assert_eq!(storage.fsck(Repair::default()).await?, expect);
+        assert_eq!(storage.size(), 25);
+
+        let repair = Repair {
+            drop_entry: true,
+            adopt_orphan: true,
+            recompute_size: true,
+        };
+        assert_eq!(
+            storage.fsck(repair).await?,
+            FsckReport {
+                num_repaired: 5,
+                ..expect
+            },
+        );
+        assert_eq!(storage.size(), 11);
+        assert!(storage.read(b("foo")).await.is_none());
+        assert_eq!(storage.read(b("bar")).await.unwrap().size(), 4);
+        assert_eq!(storage.read(b("qux")).await.unwrap().size(), 4);
+        assert_eq!(invalid.try_exists()?, false);
+        assert_eq!(content.try_exists()?, false);
+
+        let report = storage.fsck(repair).await?;
+        assert_eq!(
+            report,
+            FsckReport {
+                num_entries: 3,
+                ..Default::default()
+            },
+        );
+
+        Ok(())
+    }
+}
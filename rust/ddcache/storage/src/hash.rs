@@ -1,10 +1,11 @@
-use std::fs::DirEntry;
-use std::io::Error;
+use std::fs::{DirEntry, File};
+use std::io::{self, Error};
 use std::path::{Path, PathBuf};
 use std::str;
 
 use fasthash::city;
 use lazy_regex::regex;
+use sha2::{Digest, Sha256};
 
 use g1_base::fmt::{DebugExt, Hex};
 use g1_base::str::StrExt;
@@ -19,6 +20,14 @@ pub(crate) struct KeyHash(
     #[debug(with = Hex)] [u8; KEY_HASH_SIZE],
 );
 
+/// Hash of blob content
+///
+/// It is 1:1 mapped to a content path, which uses the same layout as a blob path.  Unlike
+/// `KeyHash`, it is a (truncated) cryptographic hash because we identify content by its hash
+/// without comparing the content itself.
+#[derive(Clone, Copy, DebugExt, Eq, Hash, PartialEq)]
+pub(crate) struct ContentHash(#[debug(with = Hex)] [u8; KEY_HASH_SIZE]);
+
 // 128 bits appear to be large enough to have a negligible collision rate and are supported by many
 // popular non-cryptographic hash functions.
 const KEY_HASH_SIZE: usize = 16;
@@ -68,15 +77,48 @@ impl KeyHash {
     }
 
     pub(crate) fn to_path(self, dir: &Path) -> PathBuf {
-        let mut path = dir.to_path_buf();
-        let mut buf = [0; KEY_HASH_SIZE * 2];
-        let hex = to_hex(self.0.as_slice(), buf.as_mut_slice());
-        path.push(Path::new(&hex[..2]));
-        path.push(Path::new(&hex[2..]));
-        path
+        to_path(&self.0, dir)
     }
 }
 
+impl ContentHash {
+    pub(crate) fn new<T: AsRef<[u8]>>(content: T) -> Self {
+        Self(Sha256::digest(content)[..KEY_HASH_SIZE].try_into().unwrap())
+    }
+
+    /// Hashes the content of a file without reading it into memory as a whole.
+    pub(crate) fn from_file(path: &Path) -> Result<Self, Error> {
+        let mut hasher = Sha256::new();
+        io::copy(&mut File::open(path)?, &mut hasher)?;
+        Ok(Self(hasher.finalize()[..KEY_HASH_SIZE].try_into().unwrap()))
+    }
+
+    pub(crate) fn from_slice(hash: &[u8]) -> Option<Self> {
+        Some(Self(hash.try_into().ok()?))
+    }
+
+    pub(crate) fn from_path(content: &Path) -> Self {
+        Self(KeyHash::from_path(content).0)
+    }
+
+    pub(crate) fn as_slice(&self) -> &[u8] {
+        self.0.as_slice()
+    }
+
+    pub(crate) fn to_path(self, dir: &Path) -> PathBuf {
+        to_path(&self.0, dir)
+    }
+}
+
+fn to_path(hash: &[u8; KEY_HASH_SIZE], dir: &Path) -> PathBuf {
+    let mut path = dir.to_path_buf();
+    let mut buf = [0; KEY_HASH_SIZE * 2];
+    let hex = to_hex(hash.as_slice(), buf.as_mut_slice());
+    path.push(Path::new(&hex[..2]));
+    path.push(Path::new(&hex[2..]));
+    path
+}
+
 fn to_file_name(path: &Path) -> &str {
     path.file_name().unwrap().to_str().unwrap()
 }
@@ -206,6 +248,13 @@ mod tests {
             let hash = KeyHash::from_path(blob);
             assert_eq!(hash, KeyHash(expect));
             assert_eq!(hash.to_path(dir), blob.to_path_buf());
+
+            let hash = ContentHash::from_path(blob);
+            assert_eq!(hash, ContentHash(expect));
+            assert_eq!(hash.to_path(dir), blob.to_path_buf());
+            assert_eq!(ContentHash::from_slice(hash.as_slice()), Some(hash));
         }
+        assert_eq!(ContentHash::from_slice(&[0; 15]), None);
+        assert_eq!(ContentHash::from_slice(&[0; 17]), None);
     }
 }
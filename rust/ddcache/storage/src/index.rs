@@ -0,0 +1,238 @@
+//! Secondary Index of Metadata Fields
+//!
+//! The storage does not interpret entry metadata in general, but it may index a few fields that
+//! are declared per namespace (key prefix), so that callers may find entries by field value.  For
+//! indexing, metadata is parsed as `field=value` lines, and lines of other forms are ignored.
+//!
+//! Like the change feed, the index is kept in memory only and is rebuilt in `Storage::open`.
+
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+
+use g1_base::sync::MutexExt;
+
+/// Declares the metadata fields to be indexed for the entries under a namespace.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct IndexSpec {
+    pub namespace: Bytes,
+    pub fields: Vec<Bytes>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct MetadataIndex(Arc<Mutex<Inner>>);
+
+// (namespace, field, value)
+type Term = (Bytes, Bytes, Bytes);
+
+#[derive(Debug)]
+struct Inner {
+    specs: Vec<IndexSpec>,
+    index: HashMap<Term, BTreeSet<Bytes>>,
+    // Terms of each indexed key, which we need for removing the key from the index.
+    terms: HashMap<Bytes, Vec<Term>>,
+}
+
+impl MetadataIndex {
+    pub(crate) fn new(specs: Vec<IndexSpec>) -> Self {
+        Self(Arc::new(Mutex::new(Inner {
+            specs,
+            index: HashMap::new(),
+            terms: HashMap::new(),
+        })))
+    }
+
+    /// Indexes the entry, replacing its previously indexed fields.
+    pub(crate) fn insert(&self, key: &Bytes, metadata: Option<&[u8]>) {
+        let mut inner = self.0.must_lock();
+        inner.remove(key);
+        if inner.specs.is_empty() {
+            return;
+        }
+
+        let mut terms = Vec::new();
+        let fields: Vec<_> = parse_fields(metadata.unwrap_or(&[])).collect();
+        for spec in inner
+            .specs
+            .iter()
+            .filter(|spec| key.starts_with(&spec.namespace))
+        {
+            for (field, value) in &fields {
+                if spec.fields.iter().any(|f| f == field) {
+                    terms.push((
+                        spec.namespace.clone(),
+                        Bytes::copy_from_slice(field),
+                        Bytes::copy_from_slice(value),
+                    ));
+                }
+            }
+        }
+        if terms.is_empty() {
+            return;
+        }
+
+        for term in &terms {
+            inner
+                .index
+                .entry(term.clone())
+                .or_default()
+                .insert(key.clone());
+        }
+        inner.terms.insert(key.clone(), terms);
+    }
+
+    pub(crate) fn remove(&self, key: &[u8]) {
+        self.0.must_lock().remove(key);
+    }
+
+    /// Returns at most `limit` keys, in sorted order, whose metadata field matches the value.
+    ///
+    /// It returns `None` if the field is not declared for the namespace.
+    pub(crate) fn find(
+        &self,
+        namespace: &[u8],
+        field: &[u8],
+        value: &[u8],
+        limit: usize,
+    ) -> Option<Vec<Bytes>> {
+        let inner = self.0.must_lock();
+        inner
+            .specs
+            .iter()
+            .any(|spec| spec.namespace == namespace && spec.fields.iter().any(|f| f == field))
+            .then(|| {
+                let term = (
+                    Bytes::copy_from_slice(namespace),
+                    Bytes::copy_from_slice(field),
+                    Bytes::copy_from_slice(value),
+                );
+                inner
+                    .index
+                    .get(&term)
+                    .map(|keys| keys.iter().take(limit).cloned().collect())
+                    .unwrap_or_default()
+            })
+    }
+}
+
+impl Inner {
+    fn remove(&mut self, key: &[u8]) {
+        let Some(terms) = self.terms.remove(key) else {
+            return;
+        };
+        for term in terms {
+            if let Some(keys) = self.index.get_mut(&term) {
+                keys.remove(key);
+                if keys.is_empty() {
+                    self.index.remove(&term);
+                }
+            }
+        }
+    }
+}
+
+fn parse_fields(metadata: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    metadata.split(|&x| x == b'\n').filter_map(|line| {
+        let i = line.iter().position(|&x| x == b'=')?;
+        (i > 0).then(|| (&line[..i], &line[i + 1..]))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn b(bytes: &'static str) -> Bytes {
+        Bytes::from_static(bytes.as_bytes())
+    }
+
+    fn new_index() -> MetadataIndex {
+        MetadataIndex::new(vec![
+            IndexSpec {
+                namespace: b("build/"),
+                fields: vec![b("build-id"), b("content-type")],
+            },
+            IndexSpec {
+                namespace: b("image/"),
+                fields: vec![b("content-type")],
+            },
+        ])
+    }
+
+    #[test]
+    fn index() {
+        let index = new_index();
+        assert_eq!(index.find(b"build/", b"build-id", b"1", 10), Some(vec![]));
+        assert_eq!(index.find(b"image/", b"build-id", b"1", 10), None);
+        assert_eq!(index.find(b"other/", b"content-type", b"x", 10), None);
+
+        index.insert(
+            &b("build/a"),
+            Some(b"build-id=1\ncontent-type=text\nowner=x".as_slice()),
+        );
+        index.insert(
+            &b("build/b"),
+            Some(b"build-id=1\ncontent-type=json".as_slice()),
+        );
+        index.insert(&b("build/c"), Some(b"build-id=2".as_slice()));
+        index.insert(
+            &b("image/a"),
+            Some(b"content-type=text\nbuild-id=1".as_slice()),
+        );
+        index.insert(&b("other/a"), Some(b"build-id=1".as_slice()));
+        index.insert(&b("build/d"), None);
+
+        assert_eq!(
+            index.find(b"build/", b"build-id", b"1", 10),
+            Some(vec![b("build/a"), b("build/b")]),
+        );
+        assert_eq!(
+            index.find(b"build/", b"build-id", b"1", 1),
+            Some(vec![b("build/a")]),
+        );
+        assert_eq!(
+            index.find(b"build/", b"content-type", b"text", 10),
+            Some(vec![b("build/a")]),
+        );
+        assert_eq!(
+            index.find(b"image/", b"content-type", b"text", 10),
+            Some(vec![b("image/a")]),
+        );
+        assert_eq!(index.find(b"build/", b"owner", b"x", 10), None);
+
+        index.insert(&b("build/a"), Some(b"build-id=2".as_slice()));
+        assert_eq!(
+            index.find(b"build/", b"build-id", b"1", 10),
+            Some(vec![b("build/b")]),
+        );
+        assert_eq!(
+            index.find(b"build/", b"build-id", b"2", 10),
+            Some(vec![b("build/a"), b("build/c")]),
+        );
+        assert_eq!(
+            index.find(b"build/", b"content-type", b"text", 10),
+            Some(vec![])
+        );
+
+        index.remove(b"build/a");
+        index.remove(b"build/c");
+        index.remove(b"no-such-key");
+        assert_eq!(index.find(b"build/", b"build-id", b"2", 10), Some(vec![]));
+        assert_eq!(index.0.must_lock().terms.len(), 2);
+    }
+
+    #[test]
+    fn parse_fields() {
+        fn test(metadata: &[u8], expect: &[(&[u8], &[u8])]) {
+            assert_eq!(super::parse_fields(metadata).collect::<Vec<_>>(), expect);
+        }
+
+        test(b"", &[]);
+        test(b"a=1", &[(b"a", b"1")]);
+        test(
+            b"a=1\nb=\nc\n=2\nd=x=y",
+            &[(b"a", b"1"), (b"b", b""), (b"d", b"x=y")],
+        );
+    }
+}
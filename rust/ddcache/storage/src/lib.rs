@@ -2,7 +2,12 @@
 #![feature(try_blocks)]
 
 mod blob;
+mod change;
+mod content;
+mod dict;
+mod fsck;
 mod hash;
+mod index;
 mod map;
 
 mod storage_capnp {
@@ -15,7 +20,7 @@ mod storage_capnp {
 use std::cmp::Reverse;
 use std::collections::BinaryHeap;
 use std::fs::{self, File, OpenOptions};
-use std::io::{Error, ErrorKind};
+use std::io::{self, Error, ErrorKind, Seek};
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
@@ -25,7 +30,11 @@ use tokio::task;
 use g1_base::sync::MutexExt;
 
 use crate::blob::BlobMetadata;
+use crate::change::ChangeLog;
+use crate::content::{ContentStore, ContentStoreBuilder};
+use crate::dict::DictionaryStore;
 use crate::hash::KeyHash;
+use crate::index::MetadataIndex;
 use crate::map::{BlobMap, BlobMapBuilder};
 
 //
@@ -38,12 +47,30 @@ use crate::map::{BlobMap, BlobMapBuilder};
 //   that we do not have to worry that `tokio::fs::*` is completed but the calling task is
 //   cancelled (and thus `guard.commit` is not called).
 //
+// * In the content-addressed (dedup) mode, a committed blob's content is moved to the content
+//   store, and the blob file is truncated, keeping only the blob metadata.  Blobs with identical
+//   content share one content file.  Deduplicated blobs are readable regardless of the mode.
+//
+// * `size` is the sum of blob sizes, not the disk usage, which is smaller in the dedup mode.
+//
+// * The change feed records the keys of committed writes and removals in memory only.  It is
+//   bounded, and a reader that falls behind gets a snapshot of all keys instead.
+//
+// * The metadata index is likewise in memory only, and is rebuilt from blob metadata in `open`.
+//
+
+const CHANGE_LOG_CAPACITY: usize = 65536;
 
 #[derive(Clone, Debug)]
 pub struct Storage {
     dir: Arc<Path>,
     map: BlobMap,
+    content: ContentStore,
+    dedup: bool,
     expire_queue: ExpireQueue,
+    changes: ChangeLog,
+    dictionaries: DictionaryStore,
+    index: MetadataIndex,
 }
 
 #[derive(Clone, Debug)]
@@ -65,6 +92,10 @@ pub struct WriteGuard {
     new_metadata: Option<BlobMetadata>,
     file: Option<File>, // Use the blocking version of `File` in `Drop::drop`.
     expire_queue: ExpireQueue,
+    content: ContentStore,
+    dedup: bool,
+    changes: ChangeLog,
+    index: MetadataIndex,
 }
 
 #[derive(Debug)]
@@ -73,15 +104,46 @@ struct ExpireGuard {
     rollback: Option<(Timestamp, Bytes)>,
 }
 
-pub type RemovedBlobMetadata = (Option<Bytes>, u64, Option<Timestamp>);
+// (metadata, size, expire_at, stale_at, dictionary)
+pub type RemovedBlobMetadata = (
+    Option<Bytes>,
+    u64,
+    Option<Timestamp>,
+    Option<Timestamp>,
+    Option<DictionaryId>,
+);
+
+pub use crate::blob::{DictionaryId, Temperature};
+pub use crate::change::{Changes, Cursor};
+pub use crate::fsck::{FsckReport, Repair};
+pub use crate::index::IndexSpec;
 
 pub use g1_chrono::{Timestamp, TimestampExt};
 
 impl Storage {
     pub async fn open(dir: &Path) -> Result<Self, Error> {
+        Self::open_impl(dir, false, Vec::new()).await
+    }
+
+    /// Opens the storage in the content-addressed mode, where blobs with identical content share
+    /// one content file.
+    pub async fn open_dedup(dir: &Path) -> Result<Self, Error> {
+        Self::open_impl(dir, true, Vec::new()).await
+    }
+
+    /// Opens the storage with the metadata fields to be indexed for `find`.
+    pub async fn open_indexed(
+        dir: &Path,
+        dedup: bool,
+        indexes: Vec<IndexSpec>,
+    ) -> Result<Self, Error> {
+        Self::open_impl(dir, dedup, indexes).await
+    }
+
+    async fn open_impl(dir: &Path, dedup: bool, indexes: Vec<IndexSpec>) -> Result<Self, Error> {
         let dir = dir.canonicalize()?;
         // Scanning directories seems to warrant using `spawn_blocking`.
-        task::spawn_blocking(move || Self::open_blocking(dir.into()))
+        task::spawn_blocking(move || Self::open_blocking(dir.into(), dedup, indexes))
             .await
             .unwrap()
     }
@@ -89,8 +151,10 @@ impl Storage {
     // TODO: We scan the directory and store metadata in memory.  Essentially, we are trading a
     // smaller memory footprint for the ease of implementation and efficiency of `evict`.  We
     // should revisit this tradeoff under production load.
-    fn open_blocking(dir: Arc<Path>) -> Result<Self, Error> {
+    fn open_blocking(dir: Arc<Path>, dedup: bool, indexes: Vec<IndexSpec>) -> Result<Self, Error> {
         let mut map = BlobMapBuilder::new();
+        let mut content = ContentStoreBuilder::new(&dir);
+        let index = MetadataIndex::new(indexes);
         for blob_dir in dir.read_dir()? {
             let blob_dir = blob_dir?;
             let Some(blob_dir) = hash::match_blob_dir(&blob_dir)? else {
@@ -108,7 +172,22 @@ impl Storage {
                     tracing::debug!(blob = %blob.path().display(), "skip unrecognizable blob");
                     continue;
                 };
-                if let Err(error) = map.insert(&blob) {
+                let result: Result<(), Error> = try {
+                    let mut blob_metadata = BlobMetadata::read(&blob)?;
+                    let content_hash = blob_metadata.content_hash;
+                    if let Some(content_hash) = content_hash {
+                        blob_metadata.size = content.acquire(content_hash)?;
+                    }
+                    let key = blob_metadata.key.clone();
+                    let metadata = blob_metadata.metadata.clone();
+                    let result = map.insert(&blob, blob_metadata);
+                    if let (Err(_), Some(content_hash)) = (&result, content_hash) {
+                        content.unacquire(content_hash);
+                    }
+                    result?;
+                    index.insert(&key, metadata.as_deref());
+                };
+                if let Err(error) = result {
                     tracing::warn!(blob = %blob.display(), %error, "invalid blob");
                     fs::remove_file(&blob)?;
                     n -= 1;
@@ -121,9 +200,14 @@ impl Storage {
         }
         let (map, expire_queue) = map.build();
         Ok(Self {
-            dir,
             map,
+            content: content.build()?,
+            dedup,
             expire_queue: expire_queue.into(),
+            changes: ChangeLog::new(CHANGE_LOG_CAPACITY),
+            dictionaries: DictionaryStore::new(&dir),
+            index,
+            dir,
         })
     }
 
@@ -135,6 +219,34 @@ impl Storage {
         self.map.size()
     }
 
+    /// Returns the keys changed since `cursor`, or a snapshot of all keys if `cursor` is `None`
+    /// or no longer valid.
+    pub fn changes(&self, cursor: Option<Cursor>, limit: usize) -> Changes {
+        self.changes.changes(cursor, limit, || self.keys())
+    }
+
+    /// Installs a zstd dictionary and returns false if a different dictionary has been installed
+    /// under the same id.
+    pub fn install_dictionary(&self, id: DictionaryId, dictionary: &[u8]) -> Result<bool, Error> {
+        self.dictionaries.install(id, dictionary)
+    }
+
+    pub fn read_dictionary(&self, id: DictionaryId) -> Result<Option<Bytes>, Error> {
+        self.dictionaries.read(id)
+    }
+
+    /// Returns at most `limit` keys under the namespace whose metadata field matches the value,
+    /// or `None` if the field is not indexed for the namespace.
+    pub fn find(
+        &self,
+        namespace: &[u8],
+        field: &[u8],
+        value: &[u8],
+        limit: usize,
+    ) -> Option<Vec<Bytes>> {
+        self.index.find(namespace, field, value, limit)
+    }
+
     pub async fn evict(&self, target_size: u64) -> Result<u64, Error> {
         // Evicting cache entries seems to warrant using `spawn_blocking`.
         let this = self.clone();
@@ -168,18 +280,26 @@ impl Storage {
     }
 
     pub async fn read(&self, key: Bytes) -> Option<ReadGuard> {
-        self.map.read(key).await.map(|(hash, guard)| ReadGuard {
-            guard,
-            path: hash.to_path(&self.dir),
-        })
+        self.map
+            .read(key)
+            .await
+            .map(|(hash, guard)| self.new_read_guard(hash, guard))
     }
 
     /// Similar to `read`, except that it does not update a cache entry's recency.
     pub async fn peek(&self, key: Bytes) -> Option<ReadGuard> {
-        self.map.peek(key).await.map(|(hash, guard)| ReadGuard {
-            guard,
-            path: hash.to_path(&self.dir),
-        })
+        self.map
+            .peek(key)
+            .await
+            .map(|(hash, guard)| self.new_read_guard(hash, guard))
+    }
+
+    fn new_read_guard(&self, hash: KeyHash, guard: map::ReadGuard) -> ReadGuard {
+        let path = match guard.blob_metadata().content_hash {
+            Some(content_hash) => self.content.path(content_hash),
+            None => hash.to_path(&self.dir),
+        };
+        ReadGuard { guard, path }
     }
 
     pub async fn write(&self, key: Bytes, truncate: bool) -> Result<WriteGuard, Error> {
@@ -221,6 +341,10 @@ impl Storage {
             hash.to_path(&self.dir),
             truncate,
             self.expire_queue.clone(),
+            self.content.clone(),
+            self.dedup,
+            self.changes.clone(),
+            self.index.clone(),
         )
     }
 
@@ -229,7 +353,7 @@ impl Storage {
             return Ok(None);
         };
         let path = hash.to_path(&self.dir);
-        Self::do_remove(path, guard)
+        self.do_remove(path, guard)
     }
 
     pub async fn remove_expire(
@@ -244,7 +368,7 @@ impl Storage {
             return Ok(None);
         }
         let path = hash.to_path(&self.dir);
-        Self::do_remove(path, guard)
+        self.do_remove(path, guard)
     }
 
     pub fn try_remove_front(&self) -> Result<Option<RemovedBlobMetadata>, Error> {
@@ -252,23 +376,32 @@ impl Storage {
             return Ok(None);
         };
         let path = hash.to_path(&self.dir);
-        Self::do_remove(path, guard)
+        self.do_remove(path, guard)
     }
 
     // We will remove empty directories in `open`.
     fn do_remove(
+        &self,
         path: PathBuf,
         guard: map::RemoveGuard,
     ) -> Result<Option<RemovedBlobMetadata>, Error> {
         // We assume that the file is unchanged on error and does not update the map.
         fs::remove_file(path)?;
         let blob_metadata = guard.blob_metadata();
+        if let Some(content_hash) = blob_metadata.content_hash {
+            self.content.release(content_hash);
+        }
+        let key = blob_metadata.key.clone();
         let blob_metadata = (
             blob_metadata.metadata.clone(),
             blob_metadata.size,
             blob_metadata.expire_at,
+            blob_metadata.stale_at,
+            blob_metadata.dictionary,
         );
         guard.commit();
+        self.index.remove(&key);
+        self.changes.push(key);
         Ok(Some(blob_metadata))
     }
 }
@@ -312,17 +445,34 @@ impl ReadGuard {
         self.guard.blob_metadata().expire_at
     }
 
+    pub fn stale_at(&self) -> Option<Timestamp> {
+        self.guard.blob_metadata().stale_at
+    }
+
+    pub fn temperature(&self) -> Temperature {
+        self.guard.blob_metadata().temperature
+    }
+
+    pub fn dictionary(&self) -> Option<DictionaryId> {
+        self.guard.blob_metadata().dictionary
+    }
+
     pub fn open(&self) -> Result<File, Error> {
         OpenOptions::new().read(true).open(&self.path)
     }
 }
 
 impl WriteGuard {
+    #[allow(clippy::too_many_arguments)]
     fn new(
         guard: map::WriteGuard,
         path: PathBuf,
         truncate: bool,
         expire_queue: ExpireQueue,
+        content: ContentStore,
+        dedup: bool,
+        changes: ChangeLog,
+        index: MetadataIndex,
     ) -> Self {
         Self {
             guard: Some(guard),
@@ -331,6 +481,10 @@ impl WriteGuard {
             new_metadata: None,
             file: None,
             expire_queue,
+            content,
+            dedup,
+            changes,
+            index,
         }
     }
 
@@ -361,6 +515,18 @@ impl WriteGuard {
         self.new_metadata().expire_at
     }
 
+    pub fn stale_at(&self) -> Option<Timestamp> {
+        self.new_metadata().stale_at
+    }
+
+    pub fn temperature(&self) -> Temperature {
+        self.new_metadata().temperature
+    }
+
+    pub fn dictionary(&self) -> Option<DictionaryId> {
+        self.new_metadata().dictionary
+    }
+
     pub fn set_metadata(&mut self, metadata: Option<Bytes>) {
         self.new_metadata_mut().metadata = metadata;
     }
@@ -369,6 +535,18 @@ impl WriteGuard {
         self.new_metadata_mut().expire_at = expire_at;
     }
 
+    pub fn set_stale_at(&mut self, stale_at: Option<Timestamp>) {
+        self.new_metadata_mut().stale_at = stale_at;
+    }
+
+    pub fn set_temperature(&mut self, temperature: Temperature) {
+        self.new_metadata_mut().temperature = temperature;
+    }
+
+    pub fn set_dictionary(&mut self, dictionary: Option<DictionaryId>) {
+        self.new_metadata_mut().dictionary = dictionary;
+    }
+
     // TODO: Should we convert `open` to async with `spawn_blocking`?
     pub fn open(&mut self) -> Result<&mut File, Error> {
         self.ensure_file(self.truncate)?;
@@ -388,32 +566,65 @@ impl WriteGuard {
                 }
             }
         }
-        self.file = Some(
-            OpenOptions::new()
-                .create_new(is_new)
-                .write(true)
-                .truncate(truncate)
-                .open(&self.path)?,
-        );
+        let mut file = OpenOptions::new()
+            .create_new(is_new)
+            .write(true)
+            .truncate(truncate)
+            .open(&self.path)?;
+        // Copy the shared content back to the blob so that the caller may modify it in place.
+        let content_hash = self.guard.as_ref().unwrap().blob_metadata().content_hash;
+        if let Some(content_hash) = content_hash.filter(|_| !truncate) {
+            io::copy(&mut File::open(self.content.path(content_hash))?, &mut file)?;
+            file.rewind()?;
+        }
+        self.file = Some(file);
         Ok(())
     }
 
     // On commit error, the blob will be removed by `drop` below.
     pub fn commit(mut self) -> Result<(), Error> {
         self.new_metadata_mut();
-        self.ensure_file(false)?;
+        // If the caller did not open the blob, its content is unchanged.
+        let is_written = self.file.is_some() || self.is_new();
+        if is_written {
+            self.ensure_file(false)?;
+        }
 
         let mut new_metadata = self.new_metadata.take().unwrap();
-        new_metadata.size = self.file.as_ref().unwrap().metadata()?.len();
+        let mut old_content_hash = None;
+        if is_written {
+            new_metadata.size = self.file.as_ref().unwrap().metadata()?.len();
+            old_content_hash = new_metadata.content_hash.take();
+            if self.dedup {
+                new_metadata.content_hash = Some(self.content.insert(&self.path)?);
+            }
+        }
 
-        new_metadata.write(&self.path)?;
+        let result: Result<(), Error> = try {
+            if new_metadata.content_hash.is_some() && is_written {
+                self.file.as_ref().unwrap().set_len(0)?;
+            }
+            new_metadata.write(&self.path)?;
+        };
+        if let Err(error) = result {
+            if let Some(content_hash) = new_metadata.content_hash.filter(|_| is_written) {
+                self.content.release(content_hash);
+            }
+            return Err(error);
+        }
 
         // No errors after this point.
 
+        if let Some(content_hash) = old_content_hash {
+            self.content.release(content_hash);
+        }
         if let Some(expire_at) = new_metadata.expire_at {
             self.expire_queue.push(expire_at, new_metadata.key.clone());
         }
+        let key = new_metadata.key.clone();
+        self.index.insert(&key, new_metadata.metadata.as_deref());
         self.guard.take().unwrap().commit(new_metadata);
+        self.changes.push(key);
 
         self.file = None;
         Ok(())
@@ -429,7 +640,18 @@ impl Drop for WriteGuard {
     fn drop(&mut self) {
         if self.file.is_some() {
             fs::remove_file(&self.path).unwrap();
-            self.guard.take().unwrap().commit_remove();
+            let guard = self.guard.take().unwrap();
+            if let Some(content_hash) = guard.blob_metadata().content_hash {
+                self.content.release(content_hash);
+            }
+            let key = guard.blob_metadata().key.clone();
+            let is_new = guard.is_new();
+            guard.commit_remove();
+            self.index.remove(&key);
+            // Readers have not seen a new blob yet.
+            if !is_new {
+                self.changes.push(key);
+            }
         }
     }
 }
@@ -484,7 +706,7 @@ mod tests {
 
     use tempfile;
 
-    use crate::hash::KeyHash;
+    use crate::hash::{ContentHash, KeyHash};
 
     use super::*;
 
@@ -618,6 +840,27 @@ mod tests {
         assert_eq!(storage.evict(0).await?, 0);
         assert_dir(tempdir.path(), []);
 
+        for (key, temperature) in [
+            (b("k1"), Temperature::Hot),
+            (b("k2"), Temperature::Normal),
+            (b("k3"), Temperature::Cold),
+        ] {
+            let mut guard = storage.write(key, true).await?;
+            guard.set_temperature(temperature);
+            guard.open()?;
+            guard.write(b"x")?;
+            guard.commit()?;
+        }
+        assert_eq!(
+            storage.read(b("k1")).await.unwrap().temperature(),
+            Temperature::Hot
+        );
+
+        assert_eq!(storage.evict(2).await?, 2);
+        assert_dir(tempdir.path(), [(b"k1", b"x"), (b"k2", b"x")]);
+        assert_eq!(storage.evict(1).await?, 1);
+        assert_dir(tempdir.path(), [(b"k1", b"x")]);
+
         Ok(())
     }
 
@@ -807,6 +1050,109 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn dedup() -> Result<(), Error> {
+        let h1 = ContentHash::new(b"Hello, World!");
+        let h2 = ContentHash::new(b"spam eggs");
+        let h3 = ContentHash::new(b"Jello, World!");
+
+        let tempdir = tempfile::tempdir()?;
+        let storage = Storage::open_dedup(tempdir.path()).await?;
+
+        for (key, content) in [
+            ("foo", "Hello, World!"),
+            ("bar", "Hello, World!"),
+            ("baz", "spam eggs"),
+        ] {
+            let mut guard = storage.write(b(key), true).await?;
+            guard.open()?;
+            guard.write(content)?;
+            guard.commit()?;
+        }
+        assert_eq!(storage.size(), 35);
+        assert_eq!(storage.content.refs(), HashMap::from([(h1, 2), (h2, 1)]));
+        for (key, content) in [
+            ("foo", "Hello, World!"),
+            ("bar", "Hello, World!"),
+            ("baz", "spam eggs"),
+        ] {
+            assert_eq!(fs::read(KeyHash::new(key).to_path(&storage.dir))?, b"");
+            let mut guard = storage.read(b(key)).await.unwrap();
+            assert_eq!(guard.size(), u64::try_from(content.len()).unwrap());
+            assert_eq!(guard.read()?, b(content));
+        }
+
+        // Modify the shared content in place.
+        {
+            let mut guard = storage.write(b("bar"), false).await?;
+            guard.open()?;
+            guard.write(b"J")?;
+            guard.commit()?;
+        }
+        assert_eq!(storage.size(), 35);
+        assert_eq!(
+            storage.content.refs(),
+            HashMap::from([(h1, 1), (h2, 1), (h3, 1)]),
+        );
+        assert_eq!(
+            storage.read(b("foo")).await.unwrap().read()?,
+            b("Hello, World!")
+        );
+        assert_eq!(
+            storage.read(b("bar")).await.unwrap().read()?,
+            b("Jello, World!")
+        );
+
+        // Set blob metadata only.
+        {
+            let mut guard = storage.write(b("bar"), false).await?;
+            guard.set_metadata(Some(b("x")));
+            guard.commit()?;
+        }
+        assert_eq!(storage.size(), 35);
+        assert_eq!(
+            storage.content.refs(),
+            HashMap::from([(h1, 1), (h2, 1), (h3, 1)]),
+        );
+        {
+            let mut guard = storage.read(b("bar")).await.unwrap();
+            assert_eq!(guard.metadata(), Some(b("x")));
+            assert_eq!(guard.read()?, b("Jello, World!"));
+        }
+
+        assert_matches!(
+            storage.remove(b("foo")).await?,
+            Some((None, 13, None, None))
+        );
+        assert_eq!(storage.size(), 22);
+        assert_eq!(storage.content.refs(), HashMap::from([(h2, 1), (h3, 1)]));
+        assert_eq!(storage.content.path(h1).try_exists()?, false);
+
+        // Deduplicated blobs remain readable without the dedup mode.
+        drop(storage);
+        let storage = Storage::open(tempdir.path()).await?;
+        assert_eq!(storage.size(), 22);
+        assert_eq!(storage.content.refs(), HashMap::from([(h2, 1), (h3, 1)]));
+        assert_eq!(
+            storage.read(b("baz")).await.unwrap().read()?,
+            b("spam eggs")
+        );
+
+        {
+            let mut guard = storage.write(b("baz"), true).await?;
+            guard.open()?;
+            guard.write(b"x")?;
+            guard.commit()?;
+        }
+        assert_eq!(storage.size(), 14);
+        assert_eq!(storage.content.refs(), HashMap::from([(h3, 1)]));
+        assert_eq!(storage.content.path(h2).try_exists()?, false);
+        assert_eq!(fs::read(KeyHash::new("baz").to_path(&storage.dir))?, b"x");
+        assert_eq!(storage.read(b("baz")).await.unwrap().read()?, b("x"));
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn try_write() -> Result<(), Error> {
         let tempdir = tempfile::tempdir()?;
@@ -914,4 +1260,55 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn find() -> Result<(), Error> {
+        let tempdir = tempfile::tempdir()?;
+        let indexes = vec![IndexSpec {
+            namespace: b("build/"),
+            fields: vec![b("build-id")],
+        }];
+        let storage = Storage::open_indexed(tempdir.path(), false, indexes.clone()).await?;
+        assert_eq!(storage.find(b"build/", b"build-id", b"1", 10), Some(vec![]));
+        assert_eq!(storage.find(b"build/", b"owner", b"x", 10), None);
+
+        for (key, metadata) in [("build/foo", "build-id=1"), ("build/bar", "build-id=1")] {
+            let mut guard = storage.write(b(key), true).await?;
+            guard.set_metadata(Some(b(metadata)));
+            guard.open()?;
+            guard.write(b"x")?;
+            guard.commit()?;
+        }
+        assert_eq!(
+            storage.find(b"build/", b"build-id", b"1", 10),
+            Some(vec![b("build/bar"), b("build/foo")]),
+        );
+
+        {
+            let mut guard = storage.write(b("build/bar"), false).await?;
+            guard.set_metadata(Some(b("build-id=2")));
+            guard.commit()?;
+        }
+        assert_eq!(
+            storage.find(b"build/", b"build-id", b"1", 10),
+            Some(vec![b("build/foo")]),
+        );
+        assert_eq!(
+            storage.find(b"build/", b"build-id", b"2", 10),
+            Some(vec![b("build/bar")]),
+        );
+
+        assert_matches!(storage.remove(b("build/foo")).await?, Some(_));
+        assert_eq!(storage.find(b"build/", b"build-id", b"1", 10), Some(vec![]));
+        drop(storage);
+
+        // The index is rebuilt on open.
+        let storage = Storage::open_indexed(tempdir.path(), false, indexes).await?;
+        assert_eq!(
+            storage.find(b"build/", b"build-id", b"2", 10),
+            Some(vec![b("build/bar")]),
+        );
+
+        Ok(())
+    }
 }
@@ -14,7 +14,7 @@ use tokio::task;
 use g1_base::collections::HashOrderedMap;
 use g1_base::sync::MutexExt;
 
-use crate::blob::BlobMetadata;
+use crate::blob::{BlobMetadata, Temperature};
 use crate::hash::KeyHash;
 use crate::RawExpireQueue;
 
@@ -23,6 +23,10 @@ use crate::RawExpireQueue;
 // the map's invariants, instead of delegating this responsibility to the caller.
 //
 
+// `try_remove_front` picks the coldest entry among this many removable entries at the front.  We
+// bound the scan so that a map full of hot entries does not make eviction quadratic.
+const EVICTION_WINDOW: usize = 64;
+
 #[derive(Clone, Debug)]
 pub(crate) struct BlobMap(Arc<Inner>);
 
@@ -81,8 +85,7 @@ impl BlobMapBuilder {
         }
     }
 
-    pub(crate) fn insert(&mut self, blob: &Path) -> Result<(), Error> {
-        let blob_metadata = BlobMetadata::read(blob)?;
+    pub(crate) fn insert(&mut self, blob: &Path, blob_metadata: BlobMetadata) -> Result<(), Error> {
         let hash = KeyHash::from_path(blob);
         if KeyHash::new(&blob_metadata.key) != hash {
             return Err(Error::other(format!(
@@ -129,6 +132,10 @@ impl BlobMap {
         self.0.size.load(Ordering::SeqCst)
     }
 
+    pub(crate) fn contains(&self, hash: KeyHash) -> bool {
+        self.0.map.must_lock().contains_key(&hash)
+    }
+
     fn get(&self, key: &Bytes, hash: KeyHash) -> Option<Arc<RwLock<State>>> {
         self.0
             .map
@@ -216,6 +223,16 @@ impl BlobMap {
         }
     }
 
+    /// Similar to `try_write`, except that it neither moves the entry to the back nor inserts a
+    /// new entry.
+    pub(crate) fn try_write_peek(&self, key: Bytes) -> Option<(KeyHash, WriteGuard)> {
+        let hash = KeyHash::new(&key);
+        let guard = self.get(&key, hash)?.try_write_owned().ok()?;
+        guard
+            .ensure_present()
+            .then(|| self.new_write_guard(hash, guard))
+    }
+
     #[allow(clippy::type_complexity)]
     fn write_lock(
         &self,
@@ -259,12 +276,27 @@ impl BlobMap {
     }
 
     pub(crate) fn try_remove_front(&self) -> Option<(KeyHash, RemoveGuard)> {
-        self.0.map.must_lock().iter().find_map(|(hash, entry)| {
-            let guard = entry.state.clone().try_write_owned().ok()?;
-            guard
-                .ensure_present()
-                .then(|| self.new_remove_guard(*hash, guard))
-        })
+        let map = self.0.map.must_lock();
+        let candidates = map
+            .iter()
+            .filter_map(|(hash, entry)| {
+                let guard = entry.state.clone().try_write_owned().ok()?;
+                guard.ensure_present().then_some((*hash, guard))
+            })
+            .take(EVICTION_WINDOW);
+        let mut coldest: Option<(KeyHash, OwnedRwLockWriteGuard<State>)> = None;
+        for (hash, guard) in candidates {
+            let temperature = guard.blob_metadata().temperature;
+            if coldest.as_ref().map_or(true, |(_, coldest)| {
+                temperature < coldest.blob_metadata().temperature
+            }) {
+                coldest = Some((hash, guard));
+                if temperature == Temperature::Cold {
+                    break;
+                }
+            }
+        }
+        coldest.map(|(hash, guard)| self.new_remove_guard(hash, guard))
     }
 
     fn new_remove_guard(
@@ -506,6 +538,16 @@ mod test_harness {
                 Self::Present(blob_metadata)
             }
         }
+
+        pub(super) fn new_mock_with(
+            size: u64,
+            key: &'static [u8],
+            temperature: Temperature,
+        ) -> Self {
+            let mut blob_metadata = BlobMetadata::new_mock(key, None, size);
+            blob_metadata.temperature = temperature;
+            Self::Present(blob_metadata)
+        }
     }
 }
 
@@ -762,6 +804,33 @@ mod tests {
         map.assert_eq([], 20);
     }
 
+    #[test]
+    fn try_remove_front_temperature() {
+        fn t(key: &'static [u8], temperature: Temperature) -> (&'static [u8], State) {
+            (key, State::new_mock_with(1, key, temperature))
+        }
+
+        let entries = [
+            t(b"hot", Temperature::Hot),
+            t(b"normal-1", Temperature::Normal),
+            R,
+            t(b"cold", Temperature::Cold),
+            t(b"normal-2", Temperature::Normal),
+        ];
+        let map = BlobMap::new_mock(entries.clone(), 20);
+
+        let e0 = map.try_remove_front();
+        assert_matches!(e0, Some((hash, _)) if hash == h("cold"));
+        let e1 = map.try_remove_front();
+        assert_matches!(e1, Some((hash, _)) if hash == h("normal-1"));
+        let e2 = map.try_remove_front();
+        assert_matches!(e2, Some((hash, _)) if hash == h("normal-2"));
+        let e3 = map.try_remove_front();
+        assert_matches!(e3, Some((hash, _)) if hash == h("hot"));
+        assert_matches!(map.try_remove_front(), None);
+        map.assert_eq(entries.clone(), 20);
+    }
+
     #[test]
     #[should_panic(expected = "expect State::Present of State::Removing")]
     fn try_remove_front_panic() {
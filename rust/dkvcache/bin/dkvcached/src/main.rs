@@ -5,7 +5,9 @@ use clap::Parser;
 use futures::future::FutureExt;
 use tokio::signal;
 
-use g1_cli::{param::ParametersConfig, tracing::TracingConfig};
+use g1_cli::{
+    metrics::MetricsConfig, otlp::OtlpConfig, param::ParametersConfig, tracing::TracingConfig,
+};
 
 use dkvcache_server::Server;
 
@@ -15,6 +17,10 @@ struct Dkvcached {
     #[command(flatten)]
     tracing: TracingConfig,
     #[command(flatten)]
+    otlp: OtlpConfig,
+    #[command(flatten)]
+    metrics: MetricsConfig,
+    #[command(flatten)]
     parameters: ParametersConfig,
 
     storage_path: PathBuf,
@@ -34,7 +40,10 @@ impl Dkvcached {
 #[tokio::main]
 async fn main() -> Result<(), Error> {
     let dkvcached = Dkvcached::parse();
-    dkvcached.tracing.init();
+    dkvcached.tracing.init_with_otlp(&dkvcached.otlp);
+    dkvcached.metrics.init();
     dkvcached.parameters.init();
-    dkvcached.execute().await
+    let result = dkvcached.execute().await;
+    dkvcached.otlp.shutdown();
+    result
 }
@@ -31,7 +31,10 @@ g1_param::define!(storage_len_hwm: usize = 1024 * 1024);
 g1_param::define!(max_concurrency: usize = 512);
 
 g1_param::define!(max_key_size: usize = 128);
-g1_param::define!(max_value_size: usize = 1024);
+g1_param::define!(
+    max_value_size: usize = 1024;
+    parse = g1_param::parse::byte_size;
+);
 
 #[derive(Clone, Debug)]
 pub struct Server {
@@ -0,0 +1,333 @@
+//! Bump Arena Allocator
+//!
+//! An arena hands out memory by bumping a pointer and frees everything at once when it is reset
+//! or dropped.  It is intended for short-lived allocations, such as those made while decoding a
+//! message, where the bookkeeping of the global allocator dominates.
+//!
+//! NOTE: The arena does not run the destructors of the values allocated in it.
+
+use std::alloc::{self, AllocError, Allocator, Layout};
+use std::cell::{Cell, RefCell};
+use std::ptr::{self, NonNull};
+use std::slice;
+use std::str;
+
+const DEFAULT_CHUNK_SIZE: usize = 4096;
+const CHUNK_ALIGN: usize = 16;
+
+#[derive(Debug)]
+pub struct Arena {
+    // `[ptr, end)` is the free space of the last chunk.
+    ptr: Cell<*mut u8>,
+    end: Cell<*mut u8>,
+    chunks: RefCell<Vec<Chunk>>,
+    chunk_size: usize,
+}
+
+#[derive(Debug)]
+struct Chunk {
+    ptr: NonNull<u8>,
+    layout: Layout,
+}
+
+// SAFETY: The arena owns its chunks, and the values allocated in it borrow the arena.
+unsafe impl Send for Arena {}
+
+impl Default for Arena {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Arena {
+    pub fn new() -> Self {
+        Self::with_chunk_size(DEFAULT_CHUNK_SIZE)
+    }
+
+    /// Creates an arena that allocates memory from the global allocator in chunks of at least
+    /// `chunk_size` bytes.
+    pub fn with_chunk_size(chunk_size: usize) -> Self {
+        assert!(chunk_size > 0);
+        Self {
+            ptr: Cell::new(ptr::null_mut()),
+            end: Cell::new(ptr::null_mut()),
+            chunks: RefCell::new(Vec::new()),
+            chunk_size,
+        }
+    }
+
+    /// Returns the number of bytes that the arena has allocated from the global allocator.
+    pub fn allocated_bytes(&self) -> usize {
+        self.chunks
+            .borrow()
+            .iter()
+            .map(|chunk| chunk.layout.size())
+            .sum()
+    }
+
+    pub fn num_chunks(&self) -> usize {
+        self.chunks.borrow().len()
+    }
+
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc<T>(&self, value: T) -> &mut T {
+        let ptr = self.alloc_layout(Layout::new::<T>()).cast::<T>();
+        // SAFETY: `ptr` is valid for writes and properly aligned.
+        unsafe {
+            ptr.write(value);
+            &mut *ptr.as_ptr()
+        }
+    }
+
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_slice_copy<T>(&self, src: &[T]) -> &mut [T]
+    where
+        T: Copy,
+    {
+        let ptr = self.alloc_layout(Layout::for_value(src)).cast::<T>();
+        // SAFETY: `ptr` is valid for `src.len()` writes and properly aligned.
+        unsafe {
+            ptr::copy_nonoverlapping(src.as_ptr(), ptr.as_ptr(), src.len());
+            slice::from_raw_parts_mut(ptr.as_ptr(), src.len())
+        }
+    }
+
+    /// Allocates a slice and fills it with the items of `iter`.
+    ///
+    /// It panics if `iter` yields fewer items than its `len`.
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_slice_fill_iter<T, I>(&self, iter: I) -> &mut [T]
+    where
+        I: IntoIterator<Item = T>,
+        I::IntoIter: ExactSizeIterator,
+    {
+        let iter = iter.into_iter();
+        let len = iter.len();
+        let ptr = self
+            .alloc_layout(Layout::array::<T>(len).unwrap())
+            .cast::<T>();
+        let mut n = 0;
+        for item in iter.take(len) {
+            // SAFETY: `ptr` is valid for `len` writes and properly aligned.
+            unsafe { ptr.add(n).write(item) };
+            n += 1;
+        }
+        assert_eq!(n, len, "expect iterator to yield {len} items");
+        // SAFETY: All `len` items are initialized.
+        unsafe { slice::from_raw_parts_mut(ptr.as_ptr(), len) }
+    }
+
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_str(&self, src: &str) -> &mut str {
+        let bytes = self.alloc_slice_copy(src.as_bytes());
+        // SAFETY: `bytes` is a copy of a valid UTF-8 string.
+        unsafe { str::from_utf8_unchecked_mut(bytes) }
+    }
+
+    /// Calls `f` and then resets the arena.
+    ///
+    /// Values allocated in `f` cannot escape it, as they borrow the arena.
+    pub fn scope<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&Self) -> R,
+    {
+        let result = f(self);
+        self.reset();
+        result
+    }
+
+    /// Frees all allocations but keeps the largest chunk for reuse.
+    pub fn reset(&mut self) {
+        let chunks = self.chunks.get_mut();
+        match chunks.pop() {
+            Some(last) => {
+                chunks.clear();
+                let start = last.ptr.as_ptr();
+                self.ptr.set(start);
+                self.end.set(start.wrapping_add(last.layout.size()));
+                chunks.push(last);
+            }
+            None => {
+                self.ptr.set(ptr::null_mut());
+                self.end.set(ptr::null_mut());
+            }
+        }
+    }
+
+    fn alloc_layout(&self, layout: Layout) -> NonNull<u8> {
+        self.try_bump(layout)
+            .unwrap_or_else(|| self.alloc_chunk_then_bump(layout))
+    }
+
+    fn try_bump(&self, layout: Layout) -> Option<NonNull<u8>> {
+        if layout.size() == 0 {
+            return NonNull::new(ptr::without_provenance_mut(layout.align()));
+        }
+        let ptr = self.ptr.get();
+        if ptr.is_null() {
+            return None;
+        }
+        let start = ptr.addr().checked_add(layout.align() - 1)? & !(layout.align() - 1);
+        let new_ptr = start.checked_add(layout.size())?;
+        if new_ptr > self.end.get().addr() {
+            return None;
+        }
+        self.ptr.set(ptr.with_addr(new_ptr));
+        NonNull::new(ptr.with_addr(start))
+    }
+
+    #[cold]
+    fn alloc_chunk_then_bump(&self, layout: Layout) -> NonNull<u8> {
+        let mut chunks = self.chunks.borrow_mut();
+        // Grow the chunk size geometrically to keep the number of chunks small.
+        let size = chunks
+            .last()
+            .map_or(self.chunk_size, |chunk| {
+                chunk.layout.size().saturating_mul(2)
+            })
+            .max(layout.size().saturating_add(layout.align()));
+        let chunk_layout = Layout::from_size_align(size, CHUNK_ALIGN).unwrap();
+        // SAFETY: `chunk_layout` has a non-zero size.
+        let Some(ptr) = NonNull::new(unsafe { alloc::alloc(chunk_layout) }) else {
+            alloc::handle_alloc_error(chunk_layout);
+        };
+        chunks.push(Chunk {
+            ptr,
+            layout: chunk_layout,
+        });
+        drop(chunks);
+
+        self.ptr.set(ptr.as_ptr());
+        self.end.set(ptr.as_ptr().wrapping_add(size));
+        self.try_bump(layout).unwrap()
+    }
+
+    fn is_last_allocation(&self, ptr: NonNull<u8>, size: usize) -> bool {
+        ptr.as_ptr().wrapping_add(size) == self.ptr.get()
+    }
+}
+
+impl Drop for Chunk {
+    fn drop(&mut self) {
+        // SAFETY: `ptr` was allocated with `layout`.
+        unsafe { alloc::dealloc(self.ptr.as_ptr(), self.layout) };
+    }
+}
+
+// It is `&Arena` rather than `Arena` that implements `Allocator`; this way, collections like
+// `Vec<T, &Arena>` borrow the arena.
+unsafe impl Allocator for &Arena {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        Ok(NonNull::slice_from_raw_parts(
+            self.alloc_layout(layout),
+            layout.size(),
+        ))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        // Reclaim only the most recent allocation.
+        if layout.size() != 0 && self.is_last_allocation(ptr, layout.size()) {
+            self.ptr.set(ptr.as_ptr());
+        }
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // Grow the most recent allocation in place if it fits.
+        if old_layout.size() != 0
+            && ptr.as_ptr().addr().is_multiple_of(new_layout.align())
+            && self.is_last_allocation(ptr, old_layout.size())
+            && new_layout.size() - old_layout.size()
+                <= self.end.get().addr() - self.ptr.get().addr()
+        {
+            self.ptr.set(ptr.as_ptr().wrapping_add(new_layout.size()));
+            return Ok(NonNull::slice_from_raw_parts(ptr, new_layout.size()));
+        }
+        let new_ptr = self.allocate(new_layout)?;
+        // SAFETY: The new allocation does not overlap with the old one.
+        unsafe {
+            ptr::copy_nonoverlapping(
+                ptr.as_ptr(),
+                new_ptr.as_ptr().cast::<u8>(),
+                old_layout.size(),
+            )
+        };
+        Ok(new_ptr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alloc() {
+        let arena = Arena::with_chunk_size(64);
+        assert_eq!(arena.num_chunks(), 0);
+        assert_eq!(arena.allocated_bytes(), 0);
+
+        let x = arena.alloc(1u8);
+        let y = arena.alloc(2u64);
+        assert_eq!(*x, 1);
+        assert_eq!(*y, 2);
+        assert_eq!((y as *mut u64).addr() % align_of::<u64>(), 0);
+        *x = 3;
+        *y = 4;
+        assert_eq!(*x, 3);
+        assert_eq!(*y, 4);
+        assert_eq!(arena.num_chunks(), 1);
+        assert_eq!(arena.allocated_bytes(), 64);
+
+        assert_eq!(arena.alloc_slice_copy(&[1, 2, 3]), &[1, 2, 3]);
+        assert_eq!(arena.alloc_slice_fill_iter(0..4), &[0, 1, 2, 3]);
+        assert_eq!(arena.alloc_str("hello world"), "hello world");
+
+        // Larger than the chunk size.
+        assert_eq!(arena.alloc_slice_copy(&[0u8; 100]), &[0u8; 100]);
+        assert_eq!(arena.num_chunks(), 2);
+        assert_eq!(arena.allocated_bytes(), 64 + 128);
+    }
+
+    #[test]
+    fn reset() {
+        let mut arena = Arena::with_chunk_size(16);
+        arena.reset();
+        assert_eq!(arena.num_chunks(), 0);
+
+        for _ in 0..10 {
+            arena.alloc(0u64);
+        }
+        assert_eq!(arena.num_chunks(), 3);
+        assert_eq!(arena.allocated_bytes(), 16 + 32 + 64);
+
+        arena.reset();
+        assert_eq!(arena.num_chunks(), 1);
+        assert_eq!(arena.allocated_bytes(), 64);
+
+        assert_eq!(arena.scope(|arena| *arena.alloc(42)), 42);
+        for _ in 0..8 {
+            arena.alloc(0u64);
+        }
+        assert_eq!(arena.num_chunks(), 1);
+    }
+
+    #[test]
+    fn allocator() {
+        let arena = Arena::with_chunk_size(1024);
+        let mut vec = Vec::new_in(&arena);
+        for i in 0..100u32 {
+            vec.push(i);
+        }
+        assert_eq!(vec, (0..100).collect::<Vec<_>>());
+        // `vec` grows in place.
+        assert_eq!(arena.num_chunks(), 1);
+        assert_eq!(arena.ptr.get().addr() - vec.as_ptr().addr(), 128 * 4);
+
+        drop(vec);
+        assert_eq!(arena.ptr.get().addr(), arena.end.get().addr() - 1024);
+    }
+}
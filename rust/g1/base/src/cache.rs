@@ -1,11 +1,14 @@
 use std::borrow::Borrow;
 use std::cmp::{Ord, Reverse};
+use std::collections::hash_map::RandomState;
 use std::collections::BinaryHeap;
-use std::hash::Hash;
+use std::hash::{BuildHasher, Hash};
 use std::mem;
+use std::sync::{Mutex, MutexGuard};
 use std::time::{Duration, Instant};
 
 use crate::collections::HashOrderedMap;
+use crate::sync::MutexExt;
 
 #[derive(Clone, Debug, Default, Eq, PartialEq)]
 pub struct Stat {
@@ -36,6 +39,17 @@ pub struct LruCache<K, V> {
     stat: Stat,
 }
 
+/// Thread-safe LRU cache for non-async hot paths.
+///
+/// Entries are partitioned into shards by the key hash, and each shard is guarded by its own
+/// mutex, so that concurrent lookups rarely contend.  Eviction is per shard, and thus only
+/// approximates a global LRU order.
+#[derive(Debug)]
+pub struct ShardedLruCache<K, V, S = RandomState> {
+    shards: Box<[Mutex<LruCache<K, V>>]>,
+    hash_builder: S,
+}
+
 #[derive(Debug)]
 struct Entry<V> {
     value: V,
@@ -143,6 +157,110 @@ where
     }
 }
 
+impl<K, V> ShardedLruCache<K, V>
+where
+    K: Ord,
+{
+    pub fn new(num_shards: usize, max_size: usize, timeout: Option<Duration>) -> Self {
+        Self::with_hasher(num_shards, max_size, timeout, RandomState::new())
+    }
+}
+
+impl<K, V, S> ShardedLruCache<K, V, S>
+where
+    K: Ord,
+{
+    /// Creates a cache of `num_shards` shards, each of which holds up to
+    /// `max_size / num_shards` (rounded up) entries.
+    pub fn with_hasher(
+        num_shards: usize,
+        max_size: usize,
+        timeout: Option<Duration>,
+        hash_builder: S,
+    ) -> Self {
+        assert!(num_shards > 0);
+        let shard_size = max_size.div_ceil(num_shards);
+        Self {
+            shards: (0..num_shards)
+                .map(|_| Mutex::new(LruCache::new(shard_size, timeout)))
+                .collect(),
+            hash_builder,
+        }
+    }
+}
+
+impl<K, V, S> ShardedLruCache<K, V, S> {
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|shard| shard.must_lock().is_empty())
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.must_lock().len())
+            .sum()
+    }
+
+    pub fn clear(&self) {
+        for shard in self.shards.iter() {
+            shard.must_lock().clear();
+        }
+    }
+
+    pub fn take_stat(&self) -> Stat {
+        let mut stat = Stat::default();
+        for shard in self.shards.iter() {
+            let Stat {
+                num_hits,
+                num_misses,
+                num_expires,
+            } = shard.must_lock().take_stat();
+            stat.num_hits += num_hits;
+            stat.num_misses += num_misses;
+            stat.num_expires += num_expires;
+        }
+        stat
+    }
+}
+
+impl<K, V, S> ShardedLruCache<K, V, S>
+where
+    K: Eq + Hash,
+    K: Ord,
+    K: Clone, // `insert` needs this.
+    S: BuildHasher,
+{
+    /// Returns a clone of the value, as we cannot hold the shard lock beyond this call.
+    pub fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+        V: Clone,
+    {
+        self.shard(key).get(key).cloned()
+    }
+
+    pub fn insert(&self, key: K, value: V) {
+        self.shard(&key).insert(key, value)
+    }
+
+    pub fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.shard(key).remove(key)
+    }
+
+    fn shard<Q>(&self, key: &Q) -> MutexGuard<'_, LruCache<K, V>>
+    where
+        Q: Hash + ?Sized,
+    {
+        let hash = usize::try_from(self.hash_builder.hash_one(key)).unwrap_or(usize::MAX);
+        self.shards[hash % self.shards.len()].must_lock()
+    }
+}
+
 impl<V> Entry<V> {
     fn is_expired(&self, now: Instant) -> bool {
         self.deadline.map_or(false, |deadline| deadline < now)
@@ -296,6 +414,63 @@ mod tests {
         assert_cache(&cache, &[(1, 11)], &[1], 0, 0, 0);
     }
 
+    #[test]
+    fn sharded() {
+        let cache = ShardedLruCache::new(4, 8, None);
+        assert_eq!(cache.shards.len(), 4);
+        assert_eq!(cache.is_empty(), true);
+
+        for i in 0..4u8 {
+            cache.insert(i, i * 10);
+        }
+        assert_eq!(cache.is_empty(), false);
+        assert_eq!(cache.len(), 4);
+
+        assert_eq!(cache.get(&1), Some(10));
+        assert_eq!(cache.get(&3), Some(30));
+        assert_eq!(cache.get(&4), None);
+        assert_eq!(cache.take_stat(), Stat::new(2, 1, 0));
+        assert_eq!(cache.take_stat(), Stat::new(0, 0, 0));
+
+        assert_eq!(cache.remove(&1), Some(10));
+        assert_eq!(cache.remove(&1), None);
+        assert_eq!(cache.len(), 3);
+
+        cache.clear();
+        assert_eq!(cache.is_empty(), true);
+    }
+
+    #[test]
+    fn sharded_evict() {
+        let cache = ShardedLruCache::new(1, 2, None);
+        cache.insert(1, 10);
+        cache.insert(2, 20);
+        assert_eq!(cache.get(&1), Some(10));
+        cache.insert(3, 30);
+        assert_eq!(cache.get(&1), Some(10));
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&3), Some(30));
+    }
+
+    #[test]
+    fn sharded_concurrent() {
+        let cache = ShardedLruCache::new(8, 4096, None);
+        thread::scope(|scope| {
+            for t in 0..4u16 {
+                let cache = &cache;
+                scope.spawn(move || {
+                    for i in 0..100u16 {
+                        let key = t * 100 + i;
+                        cache.insert(key, key);
+                        assert_eq!(cache.get(&key), Some(key));
+                    }
+                });
+            }
+        });
+        assert_eq!(cache.len(), 400);
+        assert_eq!(cache.take_stat(), Stat::new(400, 0, 0));
+    }
+
     #[test]
     fn is_expired() {
         fn test(deadline: Option<Instant>, now: Instant, expect: bool) {
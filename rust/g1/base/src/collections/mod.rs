@@ -11,6 +11,8 @@ mod bimap;
 mod bitable;
 #[cfg(feature = "collections_ext")]
 mod ordered;
+#[cfg(feature = "serde")]
+mod serde;
 mod table;
 
 use std::iter::FusedIterator;
@@ -0,0 +1,217 @@
+//! Serde Support
+//!
+//! Map-like collections with unique keys are serialized as maps, and the rest are serialized as
+//! sequences of their elements (e.g., `(row, column, value)` tuples for tables).
+//!
+//! NOTE: Cursors are not preserved; deserializing a `VecList` or a `HashCursorSet` assigns new
+//! cursors to its elements.
+
+use std::fmt;
+use std::marker::PhantomData;
+
+use serde::de::{Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{Serialize, Serializer};
+
+#[cfg(feature = "collections_ext")]
+use super::{HashBasedBiTable, HashBiGraph, HashBiMap, HashCursorSet, HashOrderedMap};
+use super::{HashBasedTable, NaiveBTreeBiGraph, NaiveHashBiGraph, VecList};
+
+macro_rules! impl_serde_seq {
+    (
+        $(#[$attr:meta])*
+        [$($param:ident),*] $type:ty => $item:ty, ($($element:ident),*) $(where $($bound:tt)*)?
+    ) => {
+        $(#[$attr])*
+        impl<$($param),*> Serialize for $type
+        where
+            $($element: Serialize,)*
+            $($($bound)*)?
+        {
+            fn serialize<Ser>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error>
+            where
+                Ser: Serializer,
+            {
+                serializer.collect_seq(self.iter())
+            }
+        }
+
+        $(#[$attr])*
+        impl<'de, $($param),*> Deserialize<'de> for $type
+        where
+            $($element: Deserialize<'de>,)*
+            Self: Default + Extend<$item>,
+        {
+            fn deserialize<De>(deserializer: De) -> Result<Self, De::Error>
+            where
+                De: Deserializer<'de>,
+            {
+                deserializer.deserialize_seq(SeqVisitor(PhantomData))
+            }
+        }
+    };
+}
+
+macro_rules! impl_serde_map {
+    ($(#[$attr:meta])* [$($param:ident),*] $type:ty) => {
+        $(#[$attr])*
+        impl<K, V, $($param),*> Serialize for $type
+        where
+            K: Serialize,
+            V: Serialize,
+        {
+            fn serialize<Ser>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error>
+            where
+                Ser: Serializer,
+            {
+                serializer.collect_map(self.iter())
+            }
+        }
+
+        $(#[$attr])*
+        impl<'de, K, V, $($param),*> Deserialize<'de> for $type
+        where
+            K: Deserialize<'de>,
+            V: Deserialize<'de>,
+            Self: Default + Extend<(K, V)>,
+        {
+            fn deserialize<De>(deserializer: De) -> Result<Self, De::Error>
+            where
+                De: Deserializer<'de>,
+            {
+                deserializer.deserialize_map(MapVisitor(PhantomData))
+            }
+        }
+    };
+}
+
+impl_serde_seq!([T] VecList<T> => T, (T));
+impl_serde_seq!(
+    #[cfg(feature = "collections_ext")]
+    [T, S] HashCursorSet<T, S> => T, (T)
+);
+
+impl_serde_seq!([K, V] NaiveBTreeBiGraph<K, V> => (K, V), (K, V) where K: Copy, V: Copy);
+impl_serde_seq!([K, V] NaiveHashBiGraph<K, V> => (K, V), (K, V) where K: Copy, V: Copy);
+impl_serde_seq!(
+    #[cfg(feature = "collections_ext")]
+    [K, V, KS, VS] HashBiGraph<K, V, KS, VS> => (K, V), (K, V)
+);
+
+impl_serde_seq!([R, C, V] HashBasedTable<R, C, V> => (R, C, V), (R, C, V));
+impl_serde_seq!(
+    #[cfg(feature = "collections_ext")]
+    [R, C, V, RS, CS] HashBasedBiTable<R, C, V, RS, CS> => (R, C, V), (R, C, V)
+);
+
+impl_serde_map!(
+    #[cfg(feature = "collections_ext")]
+    [KS, VS] HashBiMap<K, V, KS, VS>
+);
+impl_serde_map!(
+    #[cfg(feature = "collections_ext")]
+    [S] HashOrderedMap<K, V, S>
+);
+
+struct SeqVisitor<C, T>(PhantomData<fn() -> (C, T)>);
+
+impl<'de, C, T> Visitor<'de> for SeqVisitor<C, T>
+where
+    C: Default + Extend<T>,
+    T: Deserialize<'de>,
+{
+    type Value = C;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a sequence")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut collection = C::default();
+        while let Some(element) = seq.next_element()? {
+            collection.extend([element]);
+        }
+        Ok(collection)
+    }
+}
+
+struct MapVisitor<C, K, V>(PhantomData<fn() -> (C, K, V)>);
+
+impl<'de, C, K, V> Visitor<'de> for MapVisitor<C, K, V>
+where
+    C: Default + Extend<(K, V)>,
+    K: Deserialize<'de>,
+    V: Deserialize<'de>,
+{
+    type Value = C;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a map")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut collection = C::default();
+        while let Some(entry) = map.next_entry()? {
+            collection.extend([entry]);
+        }
+        Ok(collection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Debug;
+
+    use serde::de::DeserializeOwned;
+
+    use super::*;
+
+    fn test<T>(collection: T, json: &str)
+    where
+        T: Debug + PartialEq + Serialize + DeserializeOwned,
+    {
+        assert_eq!(serde_json::to_string(&collection).unwrap(), json);
+        assert_eq!(serde_json::from_str::<T>(json).unwrap(), collection);
+    }
+
+    #[test]
+    fn vec_list() {
+        test(VecList::<u8>::new(), "[]");
+        test(VecList::from([3, 1, 2]), "[3,1,2]");
+    }
+
+    #[test]
+    fn bigraph() {
+        test(
+            NaiveBTreeBiGraph::from([(1, 'a'), (1, 'b'), (2, 'a')]),
+            r#"[[1,"a"],[1,"b"],[2,"a"]]"#,
+        );
+        test(NaiveHashBiGraph::from([(1, 'a')]), r#"[[1,"a"]]"#);
+    }
+
+    #[test]
+    fn table() {
+        test(HashBasedTable::from([(1, 'a', 10)]), r#"[[1,"a",10]]"#);
+    }
+
+    #[cfg(feature = "collections_ext")]
+    #[test]
+    fn collections_ext() {
+        test(HashCursorSet::<u8>::from([3, 1, 2]), "[3,1,2]");
+        test(HashBiGraph::<_, _>::from([(1, 'a')]), r#"[[1,"a"]]"#);
+        test(
+            HashBasedBiTable::<_, _, _>::from([(1, 'a', 10)]),
+            r#"[[1,"a",10]]"#,
+        );
+        test(HashBiMap::<_, _>::from([(1, 'a')]), r#"{"1":"a"}"#);
+        test(
+            HashOrderedMap::<_, _>::from([(3, 'c'), (1, 'a'), (2, 'b')]),
+            r#"{"3":"c","1":"a","2":"b"}"#,
+        );
+    }
+}
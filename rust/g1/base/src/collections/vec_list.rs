@@ -47,6 +47,17 @@ pub struct IterMut<'a, T> {
     len: usize,
 }
 
+/// Draining iterator of `VecList`.
+///
+/// The nodes are removed as they are yielded, and the nodes not yet yielded are removed when the
+/// iterator is dropped.
+#[derive(Debug)]
+pub struct Drain<'a, T> {
+    list: &'a mut VecList<T>,
+    start: Option<Cursor>,
+    end: Option<Cursor>,
+}
+
 #[derive(Clone, Debug)]
 struct Node<T> {
     value: Option<T>,
@@ -303,6 +314,41 @@ impl<T> VecList<T> {
         self.free = None;
     }
 
+    /// Appends clones of `values`, reusing free nodes before allocating new ones.
+    pub fn extend_from_slice(&mut self, values: &[T])
+    where
+        T: Clone,
+    {
+        let num_frees = self.nodes.len() - self.len;
+        self.nodes.reserve(values.len().saturating_sub(num_frees));
+        for value in values {
+            self.push_back(value.clone());
+        }
+    }
+
+    pub fn drain(&mut self) -> Drain<'_, T> {
+        let start = self.cursor_front();
+        let end = self.cursor_back();
+        Drain::new(self, start, end)
+    }
+
+    /// Removes the nodes from `start` to `end`, inclusively.
+    ///
+    /// NOTE: `end` must not precede `start`; otherwise, the iterator runs to the back of the list.
+    pub fn drain_range(&mut self, start: Cursor, end: Cursor) -> Drain<'_, T> {
+        assert!(!self.is_null(start));
+        assert!(!self.is_null(end));
+        Drain::new(self, Some(start), Some(end))
+    }
+
+    /// Moves the nodes from `at` to the back into a new list.
+    ///
+    /// NOTE: The cursors to the moved nodes are invalidated.
+    pub fn split_off(&mut self, at: Cursor) -> Self {
+        let end = self.cursor_back().unwrap();
+        self.drain_range(at, end).collect()
+    }
+
     //
     // Cursor Methods
     //
@@ -518,6 +564,63 @@ impl<T> ExactSizeIterator for IterMut<'_, T> {}
 impl<T> FusedIterator for Iter<'_, T> {}
 impl<T> FusedIterator for IterMut<'_, T> {}
 
+impl<'a, T> Drain<'a, T> {
+    fn new(list: &'a mut VecList<T>, start: Option<Cursor>, end: Option<Cursor>) -> Self {
+        Self { list, start, end }
+    }
+}
+
+impl<T> Iterator for Drain<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let this = self.start?;
+        if self.start == self.end {
+            self.start = None;
+            self.end = None;
+        } else {
+            // Compute the next cursor before `this` is removed.
+            self.start = self.list.next(this);
+            if self.start.is_none() {
+                self.end = None;
+            }
+        }
+        Some(self.list.remove(this))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        if self.start.is_none() {
+            (0, Some(0))
+        } else {
+            (1, Some(self.list.len()))
+        }
+    }
+}
+
+impl<T> DoubleEndedIterator for Drain<'_, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let this = self.end?;
+        if self.start == self.end {
+            self.start = None;
+            self.end = None;
+        } else {
+            self.end = self.list.prev(this);
+            if self.end.is_none() {
+                self.start = None;
+            }
+        }
+        Some(self.list.remove(this))
+    }
+}
+
+impl<T> FusedIterator for Drain<'_, T> {}
+
+impl<T> Drop for Drain<'_, T> {
+    fn drop(&mut self) {
+        self.for_each(drop);
+    }
+}
+
 impl<T> Node<T> {
     fn new(value: T) -> Self {
         Self {
@@ -942,6 +1045,88 @@ mod tests {
         list.assert_list(&[], 0);
     }
 
+    #[test]
+    fn extend_from_slice() {
+        let mut list = VecList::from([100, 101, 102]);
+        list.pop_front();
+        list.pop_front();
+        list.assert_list(&[102], 2);
+
+        list.extend_from_slice(&[103, 104, 105]);
+        list.assert_list(&[102, 103, 104, 105], 0);
+        assert_eq!(list.nodes.len(), 4);
+
+        list.extend_from_slice(&[]);
+        list.assert_list(&[102, 103, 104, 105], 0);
+    }
+
+    #[test]
+    fn drain() {
+        let mut list = VecList::from([100, 101, 102]);
+        assert_eq!(list.drain().collect::<Vec<_>>(), [100, 101, 102]);
+        list.assert_list(&[], 0);
+
+        let mut list = VecList::from([100, 101, 102]);
+        assert_eq!(list.drain().rev().collect::<Vec<_>>(), [102, 101, 100]);
+        list.assert_list(&[], 0);
+
+        let mut list = VecList::<usize>::new();
+        assert_eq!(list.drain().next(), None);
+        list.assert_list(&[], 0);
+    }
+
+    #[test]
+    fn drain_range() {
+        fn test(start: usize, end: usize, expect: &[usize], remain: &[usize], num_frees: usize) {
+            let mut list = VecList::from([100, 101, 102, 103]);
+            assert_eq!(
+                list.drain_range(Cursor(start), Cursor(end))
+                    .collect::<Vec<_>>(),
+                expect,
+            );
+            list.assert_list(remain, num_frees);
+        }
+
+        test(0, 0, &[100], &[101, 102, 103], 1);
+        test(0, 3, &[100, 101, 102, 103], &[], 0);
+        test(1, 2, &[101, 102], &[100, 103], 2);
+        test(2, 3, &[102, 103], &[100, 101], 0);
+        test(3, 3, &[103], &[100, 101, 102], 0);
+
+        // Dropping the iterator removes the rest.
+        let mut list = VecList::from([100, 101, 102, 103]);
+        let mut drain = list.drain_range(Cursor(1), Cursor(3));
+        assert_eq!(drain.next(), Some(101));
+        assert_eq!(drain.next_back(), Some(103));
+        drop(drain);
+        list.assert_list(&[100], 0);
+
+        // Free nodes are reused.
+        let mut list = VecList::from([100, 101, 102, 103]);
+        list.drain_range(Cursor(0), Cursor(1));
+        list.extend_from_slice(&[104, 105]);
+        list.assert_list(&[102, 103, 104, 105], 0);
+        assert_eq!(list.nodes.len(), 4);
+    }
+
+    #[test]
+    fn split_off() {
+        let mut list = VecList::from([100, 101, 102, 103]);
+        let back = list.split_off(Cursor(2));
+        list.assert_list(&[100, 101], 0);
+        back.assert_list(&[102, 103], 0);
+
+        let back = list.split_off(Cursor(0));
+        list.assert_list(&[], 0);
+        back.assert_list(&[100, 101], 0);
+
+        let mut list = VecList::from([100, 101, 102]);
+        list.pop_back();
+        let back = list.split_off(Cursor(1));
+        list.assert_list(&[100], 0);
+        back.assert_list(&[101], 0);
+    }
+
     #[test]
     fn is_null() {
         let mut list = VecList::from([100, 101]);
@@ -10,6 +10,7 @@
 // sufficient for our use case in the `fmt` module.
 #![allow(incomplete_features)]
 #![allow(internal_features)]
+#![feature(allocator_api)]
 #![feature(iterator_try_collect)]
 #![feature(lazy_type_alias)]
 #![feature(rustc_attrs)]
@@ -20,6 +21,7 @@
 #![cfg_attr(test, feature(assert_matches))]
 #![cfg_attr(test, feature(noop_waker))]
 
+pub mod arena;
 #[cfg(feature = "collections_ext")]
 pub mod cache;
 pub mod collections;
@@ -30,6 +32,8 @@ pub mod io;
 pub mod iter;
 pub mod ops;
 pub mod owner;
+#[cfg(feature = "rand")]
+pub mod rand;
 pub mod slice;
 pub mod str;
 pub mod sync;
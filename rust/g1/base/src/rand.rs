@@ -0,0 +1,64 @@
+//! Seedable Thread-Local RNG
+//!
+//! Randomized logic that is not security-sensitive, such as tie-breaking and transaction ids,
+//! should draw from this RNG instead of `rand::thread_rng`, so that a test may make it
+//! reproducible by fixing the seed.  Do NOT use it for cryptographic purposes.
+//!
+//! The RNG is per thread.  A test that fixes the seed must run the randomized logic on the same
+//! thread, e.g., in a current-thread Tokio runtime.
+
+use std::cell::RefCell;
+
+use ::rand::distributions::{Distribution, Standard};
+use ::rand::rngs::StdRng;
+use ::rand::{Rng, SeedableRng};
+
+thread_local! {
+    static RNG: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
+}
+
+/// Calls `f` with the RNG of the current thread.
+///
+/// `f` must not call into this module, or it will panic.
+pub fn with_rng<F, T>(f: F) -> T
+where
+    F: FnOnce(&mut StdRng) -> T,
+{
+    RNG.with_borrow_mut(f)
+}
+
+pub fn random<T>() -> T
+where
+    Standard: Distribution<T>,
+{
+    with_rng(|rng| rng.gen())
+}
+
+/// Reseeds the RNG of the current thread.
+pub fn seed(seed: u64) {
+    RNG.set(StdRng::seed_from_u64(seed));
+}
+
+#[cfg(test)]
+mod tests {
+    use ::rand::seq::SliceRandom;
+
+    use super::*;
+
+    #[test]
+    fn seed() {
+        super::seed(42);
+        let x = random::<u64>();
+        let mut xs = [1, 2, 3, 4, 5, 6, 7, 8];
+        with_rng(|rng| xs.shuffle(rng));
+
+        super::seed(42);
+        assert_eq!(random::<u64>(), x);
+        let mut ys = [1, 2, 3, 4, 5, 6, 7, 8];
+        with_rng(|rng| ys.shuffle(rng));
+        assert_eq!(ys, xs);
+
+        // The RNG is per thread.
+        assert_ne!(std::thread::spawn(random::<u64>).join().unwrap(), x);
+    }
+}
@@ -0,0 +1,77 @@
+/// Generates the conversions between a domain struct and a struct of the schema.
+///
+/// It implements `TryFrom<Reader>` for the domain struct and a `set` method for the `Builder`.  A
+/// field is copied as is, unless a pair of conversion functions is given: `to` takes the return
+/// value of the getter (which, for a pointer field, is a `Result`) and returns
+/// `Result<T, capnp::Error>`, and `from` takes `&T` and returns the argument of the setter.
+///
+/// ```ignore
+/// g1_capnp::impl_conversion!(change_cursor => ChangeCursor { epoch, sequence });
+///
+/// g1_capnp::impl_conversion!(response::metadata => BlobMetadata {
+///     metadata: (
+///         |metadata: capnp::Result<&[u8]>| metadata.map(to_metadata),
+///         from_metadata,
+///     ),
+///     size: (
+///         |size| Ok(to_size(size)),
+///         |size: &usize| u32::try_from(*size).unwrap(),
+///     ),
+///     expire_at: (
+///         to_expire_at,
+///         |expire_at: &Option<Timestamp>| expire_at.timestamp_u64(),
+///     ),
+/// });
+/// ```
+///
+/// NOTE: The `paste::paste!` dependency is exposed to callers.
+#[macro_export]
+macro_rules! impl_conversion {
+    (
+        $($schema:ident)::+ => $type:ident {
+            $($field:ident $(: ($to:expr, $from:expr $(,)?))?),* $(,)?
+        }
+    ) => {
+        impl<'a> ::std::convert::TryFrom<$($schema)::+::Reader<'a>> for $type {
+            type Error = ::capnp::Error;
+
+            fn try_from(
+                reader: $($schema)::+::Reader<'a>,
+            ) -> ::std::result::Result<Self, Self::Error> {
+                Ok(Self {
+                    $($field: $crate::__get!(reader, $field $(, $to)?),)*
+                })
+            }
+        }
+
+        impl $($schema)::+::Builder<'_> {
+            pub fn set(&mut self, value: &$type) {
+                $($crate::__set!(self, value, $field $(, $from)?);)*
+            }
+        }
+    };
+}
+
+#[macro_export]
+macro_rules! __get {
+    ($reader:ident, $field:ident $(,)?) => {
+        ::paste::paste! { $reader.[<get_ $field>]() }
+    };
+    ($reader:ident, $field:ident, $to:expr $(,)?) => {
+        ::paste::paste! {{
+            // Annotate the error type so that `to` may simply return `Ok(...)`.
+            let value: ::std::result::Result<_, ::capnp::Error> = ($to)($reader.[<get_ $field>]());
+            value?
+        }}
+    };
+}
+
+#[macro_export]
+macro_rules! __set {
+    ($builder:ident, $value:ident, $field:ident $(,)?) => {
+        ::paste::paste! { $builder.[<set_ $field>]($value.$field) }
+    };
+    ($builder:ident, $value:ident, $field:ident, $from:expr $(,)?) => {
+        ::paste::paste! { $builder.[<set_ $field>](($from)(&$value.$field)) }
+    };
+}
@@ -1,3 +1,7 @@
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(feature = "otlp")]
+pub mod otlp;
 #[cfg(feature = "param")]
 pub mod param;
 #[cfg(feature = "tracing")]
@@ -0,0 +1,32 @@
+use std::net::SocketAddr;
+
+use clap::Args;
+use metrics_exporter_prometheus::{BuildError, PrometheusBuilder};
+
+#[derive(Args, Clone, Debug)]
+pub struct MetricsConfig {
+    #[arg(
+        long,
+        global = true,
+        value_name = "ADDRESS:PORT",
+        help = "Serve Prometheus metrics over HTTP at this endpoint"
+    )]
+    metrics_endpoint: Option<SocketAddr>,
+}
+
+impl MetricsConfig {
+    /// Installs the global metrics recorder and spawns the HTTP listener onto the current Tokio
+    /// runtime.  It does nothing if no endpoint is specified.
+    pub fn init(&self) {
+        self.try_init().expect("metrics exporter init error");
+    }
+
+    pub fn try_init(&self) -> Result<(), BuildError> {
+        let Some(endpoint) = self.metrics_endpoint else {
+            return Ok(());
+        };
+        PrometheusBuilder::new()
+            .with_http_listener(endpoint)
+            .install()
+    }
+}
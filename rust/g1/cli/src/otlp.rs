@@ -0,0 +1,88 @@
+use std::env;
+use std::path::Path;
+
+use clap::Args;
+use opentelemetry::{trace::TracerProvider as _, KeyValue};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{
+    runtime,
+    trace::{Sampler, Tracer, TracerProvider},
+    Resource,
+};
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::Registry;
+
+#[derive(Args, Clone, Debug)]
+pub struct OtlpConfig {
+    #[arg(
+        long,
+        global = true,
+        value_name = "URL",
+        help = "Export traces to this OTLP (gRPC) collector endpoint"
+    )]
+    otlp_endpoint: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        default_value_t = 1.0,
+        value_name = "RATE",
+        value_parser = parse_sample_rate,
+        help = "Export this fraction (0.0 to 1.0) of traces"
+    )]
+    trace_sample_rate: f64,
+}
+
+fn parse_sample_rate(rate: &str) -> Result<f64, String> {
+    let rate: f64 = rate.parse().map_err(|error| format!("{error}"))?;
+    if (0.0..=1.0).contains(&rate) {
+        Ok(rate)
+    } else {
+        Err(format!("expect sample rate within 0.0 and 1.0: {rate}"))
+    }
+}
+
+impl OtlpConfig {
+    /// Creates the trace export layer, which requires the current Tokio runtime to run the batch
+    /// exporter.
+    pub(crate) fn layer(&self) -> Option<OpenTelemetryLayer<Registry, Tracer>> {
+        let endpoint = self.otlp_endpoint.as_ref()?;
+        let exporter = SpanExporter::builder()
+            .with_tonic()
+            .with_endpoint(endpoint)
+            .build()
+            .expect("otlp exporter init error");
+        let service_name = service_name();
+        let provider = TracerProvider::builder()
+            .with_batch_exporter(exporter, runtime::Tokio)
+            // Respect the sampling decision of the caller, if any.
+            .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+                self.trace_sample_rate,
+            ))))
+            .with_resource(Resource::new([KeyValue::new(
+                "service.name",
+                service_name.clone(),
+            )]))
+            .build();
+        let tracer = provider.tracer(service_name);
+        opentelemetry::global::set_tracer_provider(provider);
+        Some(tracing_opentelemetry::layer().with_tracer(tracer))
+    }
+
+    /// Flushes the traces that are pending export; call it before the process exits.
+    pub fn shutdown(&self) {
+        if self.otlp_endpoint.is_some() {
+            opentelemetry::global::shutdown_tracer_provider();
+        }
+    }
+}
+
+/// Returns the program name, which serves as the service name.
+fn service_name() -> String {
+    env::args_os()
+        .next()
+        .as_deref()
+        .and_then(|program| Path::new(program).file_name())
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "unknown".to_string())
+}
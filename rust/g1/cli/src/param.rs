@@ -35,6 +35,11 @@ impl ParametersConfig {
     }
 
     pub fn try_init(&self) -> Result<(), Error> {
+        self.parse()?.commit()
+    }
+
+    /// Parses the parameter values without storing them statically, e.g., to reload them.
+    pub fn parse(&self) -> Result<Parameters<'static>, Error> {
         let mut parameters = Parameters::load();
         for path_or_value in &self.parameter {
             match path_or_value.strip_prefix('@') {
@@ -48,6 +53,6 @@ impl ParametersConfig {
                 }
             }
         }
-        parameters.commit()
+        Ok(parameters)
     }
 }
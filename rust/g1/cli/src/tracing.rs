@@ -1,12 +1,23 @@
-use std::io::{self, Stderr};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Error};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
 
 use clap::{ArgAction, Args};
 use tracing_subscriber::{
     filter::{EnvFilter, LevelFilter},
-    fmt::{self, format::FmtSpan},
+    fmt::{
+        self,
+        format::FmtSpan,
+        writer::{BoxMakeWriter, MakeWriter, MutexGuardWriter},
+    },
     prelude::*,
+    Layer, Registry,
 };
 
+#[cfg(feature = "otlp")]
+use crate::otlp::OtlpConfig;
+
 #[derive(Args, Clone, Debug)]
 pub struct TracingConfig {
     #[arg(
@@ -30,6 +41,14 @@ pub struct TracingConfig {
 
     #[arg(long, global = true, help = "Enable tokio console")]
     console: bool,
+
+    #[arg(
+        long,
+        global = true,
+        value_name = "PATH",
+        help = "Append tracing output to a file instead of stderr"
+    )]
+    log_file: Option<PathBuf>,
 }
 
 const OFF: i16 = -3;
@@ -42,10 +61,40 @@ const TRACE: i16 = 2;
 const LINE_NUMBER: bool = true;
 const TARGET: bool = true;
 const THREAD_IDS: bool = true;
-const WRITER: fn() -> Stderr = io::stderr;
+
+// `reopen` replaces the file in place because the subscriber owns the writer once initialized.
+static LOG_FILE: OnceLock<Mutex<File>> = OnceLock::new();
+
+/// Writes to `LOG_FILE`.
+#[derive(Clone, Copy, Debug)]
+struct LogFile;
+
+impl<'a> MakeWriter<'a> for LogFile {
+    type Writer = MutexGuardWriter<'a, File>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        LOG_FILE.get().expect("log file").make_writer()
+    }
+}
 
 impl TracingConfig {
     pub fn init(&self) {
+        self.init_with(None::<Box<dyn Layer<Registry> + Send + Sync>>);
+    }
+
+    /// Initializes tracing with trace export, which must be called within a Tokio runtime.
+    #[cfg(feature = "otlp")]
+    pub fn init_with_otlp(&self, otlp: &OtlpConfig) {
+        self.init_with(
+            otlp.layer()
+                .map(|layer| layer.with_filter(self.env_filter())),
+        );
+    }
+
+    fn init_with<L>(&self, export_layer: Option<L>)
+    where
+        L: Layer<Registry> + Send + Sync + 'static,
+    {
         let layer = fmt::layer()
             .compact()
             .with_ansi(self.ansi())
@@ -54,9 +103,11 @@ impl TracingConfig {
             .with_span_events(self.span_events())
             .with_target(TARGET)
             .with_thread_ids(THREAD_IDS)
-            .with_writer(WRITER)
+            .with_writer(self.writer())
             .with_filter(self.env_filter());
-        let registry = tracing_subscriber::registry().with(layer);
+        let registry = tracing_subscriber::registry()
+            .with(export_layer)
+            .with(layer);
         if self.console {
             registry.with(console_subscriber::spawn()).init();
         } else {
@@ -64,12 +115,36 @@ impl TracingConfig {
         }
     }
 
+    /// Reopens the log file (e.g., after logrotate renames it), which is a no-op when tracing
+    /// output goes to stderr.
+    pub fn reopen(&self) -> Result<(), Error> {
+        let (Some(path), Some(log_file)) = (&self.log_file, LOG_FILE.get()) else {
+            return Ok(());
+        };
+        let file = open(path)?;
+        *log_file.lock().unwrap() = file;
+        Ok(())
+    }
+
+    fn writer(&self) -> BoxMakeWriter {
+        match &self.log_file {
+            Some(path) => {
+                let file = open(path)
+                    .unwrap_or_else(|error| std::panic!("open log file: {path:?}: {error}"));
+                LOG_FILE.set(Mutex::new(file)).expect("log file");
+                BoxMakeWriter::new(LogFile)
+            }
+            None => BoxMakeWriter::new(io::stderr),
+        }
+    }
+
     fn level(&self) -> i16 {
         i16::from(self.verbose).saturating_sub(i16::from(self.silent))
     }
 
     fn ansi(&self) -> bool {
-        self.level() >= DEBUG || self.color
+        // Do not write escape codes to a log file unless asked to.
+        (self.log_file.is_none() && self.level() >= DEBUG) || self.color
     }
 
     fn file(&self) -> bool {
@@ -103,3 +178,7 @@ impl TracingConfig {
         }
     }
 }
+
+fn open(path: &Path) -> Result<File, Error> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
@@ -1,12 +1,14 @@
 pub mod sockopt;
 
+use std::mem;
+use std::net::{SocketAddr, SocketAddrV4, SocketAddrV6};
 use std::os::fd::RawFd;
 
 use bytes::BufMut;
-use libc::{c_int, socket};
+use libc::{c_int, c_void, socket};
 use nix::{
     errno::Errno,
-    sys::socket::{AddressFamily, SockFlag},
+    sys::socket::{AddressFamily, SockFlag, SockaddrLike, SockaddrStorage},
 };
 
 /// Calls `socket(domain, SOCK_DGRAM | flags, IPPROTO_ICMP)`.
@@ -24,6 +26,104 @@ pub fn icmp_socket(domain: AddressFamily, flags: SockFlag) -> Result<RawFd, Errn
     })
 }
 
+/// Receives a datagram along with its IP TOS byte (or IPv6 traffic class).
+///
+/// The TOS byte is `None` unless `IpRecvTos` (or `Ipv6RecvTclass`) is enabled on the socket.
+pub fn recvmsg_tos(fd: RawFd, buffer: &mut [u8]) -> Result<(usize, SocketAddr, Option<u8>), Errno> {
+    let mut iov = libc::iovec {
+        iov_base: buffer.as_mut_ptr().cast(),
+        iov_len: buffer.len(),
+    };
+    let mut address: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    // It is of type `u64` for the alignment of `cmsghdr`, and is large enough for either an
+    // `IP_TOS` or an `IPV6_TCLASS` message.
+    let mut cmsg_buffer = [0u64; 8];
+    let mut message: libc::msghdr = unsafe { mem::zeroed() };
+    message.msg_name = (&mut address as *mut libc::sockaddr_storage).cast();
+    message.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    message.msg_iov = &mut iov;
+    message.msg_iovlen = 1;
+    message.msg_control = cmsg_buffer.as_mut_ptr().cast();
+    message.msg_controllen = mem::size_of_val(&cmsg_buffer) as _;
+
+    let size = Errno::result(unsafe { libc::recvmsg(fd, &mut message, 0) })?;
+
+    let address = unsafe {
+        SockaddrStorage::from_raw(
+            (&address as *const libc::sockaddr_storage).cast(),
+            Some(message.msg_namelen),
+        )
+    }
+    .and_then(|address| to_socket_addr(&address))
+    .ok_or(Errno::EAFNOSUPPORT)?;
+
+    let mut tos = None;
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&message);
+        while !cmsg.is_null() {
+            let data = libc::CMSG_DATA(cmsg);
+            match ((*cmsg).cmsg_level, (*cmsg).cmsg_type) {
+                (libc::IPPROTO_IP, libc::IP_TOS) => tos = Some(*data),
+                (libc::IPPROTO_IPV6, libc::IPV6_TCLASS) => {
+                    tos = u8::try_from(data.cast::<c_int>().read_unaligned()).ok();
+                }
+                _ => {}
+            }
+            cmsg = libc::CMSG_NXTHDR(&message, cmsg);
+        }
+    }
+
+    Ok((size.try_into().unwrap(), address, tos))
+}
+
+/// Sends a datagram with the given IP TOS byte (or IPv6 traffic class).
+pub fn sendmsg_tos(fd: RawFd, buffer: &[u8], peer: SocketAddr, tos: u8) -> Result<usize, Errno> {
+    let address = SockaddrStorage::from(peer);
+    let mut iov = libc::iovec {
+        iov_base: buffer.as_ptr() as *mut c_void,
+        iov_len: buffer.len(),
+    };
+    let mut cmsg_buffer = [0u64; 4];
+    let mut message: libc::msghdr = unsafe { mem::zeroed() };
+    message.msg_name = address.as_ptr() as *mut c_void;
+    message.msg_namelen = address.len();
+    message.msg_iov = &mut iov;
+    message.msg_iovlen = 1;
+    message.msg_control = cmsg_buffer.as_mut_ptr().cast();
+
+    // Datagrams to IPv4-mapped addresses are sent over IPv4, where the kernel looks for `IP_TOS`
+    // but not for `IPV6_TCLASS`.
+    let (level, name) = match peer {
+        SocketAddr::V6(peer) if peer.ip().to_ipv4_mapped().is_none() => {
+            (libc::IPPROTO_IPV6, libc::IPV6_TCLASS)
+        }
+        _ => (libc::IPPROTO_IP, libc::IP_TOS),
+    };
+    let size = mem::size_of::<c_int>() as u32;
+    unsafe {
+        message.msg_controllen = libc::CMSG_SPACE(size) as _;
+        let cmsg = libc::CMSG_FIRSTHDR(&message);
+        (*cmsg).cmsg_level = level;
+        (*cmsg).cmsg_type = name;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(size) as _;
+        libc::CMSG_DATA(cmsg)
+            .cast::<c_int>()
+            .write_unaligned(tos.into());
+    }
+
+    Errno::result(unsafe { libc::sendmsg(fd, &message, 0) }).map(|size| size.try_into().unwrap())
+}
+
+fn to_socket_addr(address: &SockaddrStorage) -> Option<SocketAddr> {
+    if let Some(address) = address.as_sockaddr_in() {
+        Some(SocketAddrV4::from(*address).into())
+    } else {
+        address
+            .as_sockaddr_in6()
+            .map(|address| SocketAddrV6::from(*address).into())
+    }
+}
+
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 #[repr(i32)]
 pub enum IpPmtudisc {
@@ -98,9 +198,30 @@ where
 
 #[cfg(test)]
 mod tests {
+    use std::net::UdpSocket;
+    use std::os::fd::AsRawFd;
+
     use hex_literal::hex;
+    use nix::sys::socket::setsockopt;
+
+    use super::{sockopt::IpRecvTos, *};
+
+    #[test]
+    fn tos() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let endpoint = socket.local_addr().unwrap();
+        let fd = socket.as_raw_fd();
+        let mut buffer = [0u8; 16];
+
+        assert_eq!(sendmsg_tos(fd, b"spam", endpoint, 0x02), Ok(4));
+        assert_eq!(recvmsg_tos(fd, &mut buffer), Ok((4, endpoint, None)));
+        assert_eq!(&buffer[..4], b"spam");
 
-    use super::*;
+        setsockopt(&socket, IpRecvTos, &true).unwrap();
+        assert_eq!(sendmsg_tos(fd, b"egg", endpoint, 0x03), Ok(3));
+        assert_eq!(recvmsg_tos(fd, &mut buffer), Ok((3, endpoint, Some(0x03))));
+        assert_eq!(&buffer[..3], b"egg");
+    }
 
     #[test]
     fn encode() {
@@ -9,20 +9,47 @@ use super::IpPmtudisc;
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub struct IpMtuDiscover;
 
+/// Enables the `IP_TOS` control message on received datagrams.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct IpRecvTos;
+
+/// Enables the `IPV6_TCLASS` control message on received datagrams.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Ipv6RecvTclass;
+
 impl SetSockOpt for IpMtuDiscover {
     type Val = IpPmtudisc;
 
     fn set<F: AsFd>(&self, fd: &F, val: &Self::Val) -> Result<(), Errno> {
-        let val = *val as c_int;
-        Errno::result(unsafe {
-            setsockopt(
-                fd.as_fd().as_raw_fd(),
-                libc::SOL_IP,
-                libc::IP_MTU_DISCOVER,
-                &val as *const c_int as *const c_void,
-                mem::size_of::<c_int>() as socklen_t,
-            )
-        })
-        .map(drop)
+        set_int(fd, libc::SOL_IP, libc::IP_MTU_DISCOVER, *val as c_int)
+    }
+}
+
+impl SetSockOpt for IpRecvTos {
+    type Val = bool;
+
+    fn set<F: AsFd>(&self, fd: &F, val: &Self::Val) -> Result<(), Errno> {
+        set_int(fd, libc::SOL_IP, libc::IP_RECVTOS, (*val).into())
+    }
+}
+
+impl SetSockOpt for Ipv6RecvTclass {
+    type Val = bool;
+
+    fn set<F: AsFd>(&self, fd: &F, val: &Self::Val) -> Result<(), Errno> {
+        set_int(fd, libc::SOL_IPV6, libc::IPV6_RECVTCLASS, (*val).into())
     }
 }
+
+fn set_int<F: AsFd>(fd: &F, level: c_int, name: c_int, val: c_int) -> Result<(), Errno> {
+    Errno::result(unsafe {
+        setsockopt(
+            fd.as_fd().as_raw_fd(),
+            level,
+            name,
+            &val as *const c_int as *const c_void,
+            mem::size_of::<c_int>() as socklen_t,
+        )
+    })
+    .map(drop)
+}
@@ -14,6 +14,7 @@ g1_param::define!(
 g1_param::define!(x: u32 = 42; validate = |x: &u32| *x > 0; validate = is_even);
 g1_param::define!(d: Option<Duration> = None; parse = g1_param::parse::opt_duration);
 g1_param::define!(n: Option<SocketAddr> = None);
+g1_param::define!(s: usize = 4096; parse = g1_param::parse::byte_size);
 
 fn is_even(x: &u32) -> bool {
     *x % 2 == 0
@@ -49,6 +50,7 @@ fn main() -> Result<(), Error> {
     println!("x == {}", x());
     println!("d == {:?}", d());
     println!("n == {:?}", n());
+    println!("s == {}", s());
 
     Ok(())
 }
@@ -339,13 +339,39 @@ impl<'a> Parameters<'a> {
             .parameters
             .get(&(module_path, name))
             .ok_or_else(|| format!("parameter was not defined: {}::{}", module_path, name))?;
-        let value = get_value(parameter)?;
+        let value = get_value(parameter).map_err(|error| {
+            format!(
+                "invalid parameter value: {}::{}: {}",
+                parameter.module_path, parameter.name, error,
+            )
+        })?;
         parameter.validate(&value)?;
         self.values
             .insert((parameter.module_path, parameter.name), value);
         Ok(())
     }
 
+    /// Returns the parameters that have temporary values.
+    pub fn iter_values(&self) -> impl Iterator<Item = &Parameter> {
+        self.parameters
+            .iter()
+            .filter(|(key, _)| self.values.contains_key(key))
+            .map(|(_, parameter)| *parameter)
+    }
+
+    /// Removes the temporary value of a parameter, e.g., to apply it at runtime instead of
+    /// committing it.
+    pub fn take<T: 'static>(&mut self, module_path: &str, name: &str) -> Result<Option<T>, Error> {
+        let parameter = *self
+            .parameters
+            .get(&(module_path, name))
+            .ok_or_else(|| format!("parameter was not defined: {}::{}", module_path, name))?;
+        self.values
+            .remove(&(parameter.module_path, parameter.name))
+            .map(|value| parameter.downcast(value))
+            .transpose()
+    }
+
     /// Commits all temporary values, storing them statically.
     ///
     /// It is an error to call this method multiple times.
@@ -1,41 +1,101 @@
 use std::time::Duration;
 
 use lazy_regex::regex;
+use serde::Deserialize;
 
 use crate::Error;
 
+/// Either an integer or a string.
+///
+/// Byte sizes accept raw integers (in bytes) so that existing config files remain valid.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(untagged)]
+pub enum IntOrString {
+    Int(u64),
+    String(String),
+}
+
 pub fn opt_duration(d: Option<String>) -> Result<Option<Duration>, Error> {
     d.map(duration).transpose()
 }
 
 pub fn duration(d: String) -> Result<Duration, Error> {
-    parse_duration(&d).ok_or_else(|| format!("invalid duration: {d:?}").into())
+    parse_duration(&d).ok_or_else(|| {
+        format!("invalid duration: {d:?} (expect, e.g., \"500ms\", \"2s\", or \"1m 30s\")").into()
+    })
+}
+
+pub fn opt_byte_size<T>(size: Option<IntOrString>) -> Result<Option<T>, Error>
+where
+    T: TryFrom<u64>,
+{
+    size.map(byte_size).transpose()
+}
+
+pub fn byte_size<T>(size: IntOrString) -> Result<T, Error>
+where
+    T: TryFrom<u64>,
+{
+    let (bytes, size) = match size {
+        IntOrString::Int(bytes) => (Some(bytes), bytes.to_string()),
+        IntOrString::String(size) => (parse_byte_size(&size), size),
+    };
+    let bytes = bytes.ok_or_else(|| {
+        format!("invalid byte size: {size:?} (expect, e.g., \"4096\", \"64KiB\", or \"2GB\")")
+    })?;
+    T::try_from(bytes).map_err(|_| format!("byte size out of range: {size:?}").into())
 }
 
 fn parse_duration(duration: &str) -> Option<Duration> {
-    if !regex!(r"(?i-u)^\s*(?:\d+\s*(?:s|ms|us|ns)\s*)+$").is_match(duration) {
+    if !regex!(r"(?i-u)^\s*(?:\d+\s*(?:h|ms|us|ns|m|s)\s*)+$").is_match(duration) {
         return None;
     }
     let mut acc = Duration::ZERO;
-    for (_, [amount, unit]) in regex!(r"(?i-u)\s*(\d+)\s*(s|ms|us|ns)\s*")
+    for (_, [amount, unit]) in regex!(r"(?i-u)\s*(\d+)\s*(h|ms|us|ns|m|s)\s*")
         .captures_iter(duration)
         .map(|c| c.extract())
     {
-        acc += if unit.eq_ignore_ascii_case("s") {
-            Duration::from_secs
+        let amount = amount.parse::<u64>().ok()?;
+        acc += if unit.eq_ignore_ascii_case("h") {
+            Duration::from_secs(amount.checked_mul(3600)?)
+        } else if unit.eq_ignore_ascii_case("m") {
+            Duration::from_secs(amount.checked_mul(60)?)
+        } else if unit.eq_ignore_ascii_case("s") {
+            Duration::from_secs(amount)
         } else if unit.eq_ignore_ascii_case("ms") {
-            Duration::from_millis
+            Duration::from_millis(amount)
         } else if unit.eq_ignore_ascii_case("us") {
-            Duration::from_micros
+            Duration::from_micros(amount)
         } else if unit.eq_ignore_ascii_case("ns") {
-            Duration::from_nanos
+            Duration::from_nanos(amount)
         } else {
             std::unreachable!()
-        }(amount.parse::<u64>().ok()?);
+        };
     }
     Some(acc)
 }
 
+fn parse_byte_size(size: &str) -> Option<u64> {
+    let (_, [amount, unit]) = regex!(r"(?i-u)^\s*(\d+)\s*([kmgt]i?b|b|)\s*$")
+        .captures(size)?
+        .extract();
+    let amount = amount.parse::<u64>().ok()?;
+    let unit = unit.to_ascii_lowercase();
+    let scale: u64 = match unit.as_str() {
+        "" | "b" => 1,
+        "kb" => 1000,
+        "mb" => 1_000_000,
+        "gb" => 1_000_000_000,
+        "tb" => 1_000_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        "tib" => 1 << 40,
+        _ => std::unreachable!(),
+    };
+    amount.checked_mul(scale)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -70,5 +130,60 @@ mod tests {
         assert_eq!(parse_duration("1ps"), None);
 
         assert_eq!(parse_duration("𝟙𝟘s"), None);
+
+        assert_eq!(parse_duration("2m"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_duration("1h 1M 1s"), Some(Duration::from_secs(3661)));
+        assert_eq!(parse_duration("1ms1m"), Some(Duration::from_millis(60001)));
+        assert_eq!(parse_duration("18446744073709551615h"), None);
+    }
+
+    #[test]
+    fn test_byte_size() {
+        assert_eq!(byte_size::<u64>(IntOrString::Int(42)).unwrap(), 42);
+        assert_eq!(
+            byte_size::<usize>(IntOrString::String("64MiB".to_string())).unwrap(),
+            64 * 1024 * 1024,
+        );
+        assert_eq!(
+            byte_size::<u8>(IntOrString::Int(256))
+                .unwrap_err()
+                .to_string(),
+            "byte size out of range: \"256\"",
+        );
+        assert_eq!(
+            byte_size::<u64>(IntOrString::String("1PiB".to_string()))
+                .unwrap_err()
+                .to_string(),
+            "invalid byte size: \"1PiB\" (expect, e.g., \"4096\", \"64KiB\", or \"2GB\")",
+        );
+
+        assert_eq!(
+            opt_byte_size::<u64>(Some(IntOrString::String("1kb".to_string()))).unwrap(),
+            Some(1000),
+        );
+        assert_eq!(opt_byte_size::<u64>(None).unwrap(), None);
+    }
+
+    #[test]
+    fn test_parse_byte_size() {
+        assert_eq!(parse_byte_size("0"), Some(0));
+        assert_eq!(parse_byte_size("42"), Some(42));
+        assert_eq!(parse_byte_size(" 42 b "), Some(42));
+        assert_eq!(parse_byte_size("1KB"), Some(1000));
+        assert_eq!(parse_byte_size("1MB"), Some(1_000_000));
+        assert_eq!(parse_byte_size("1GB"), Some(1_000_000_000));
+        assert_eq!(parse_byte_size("1TB"), Some(1_000_000_000_000));
+        assert_eq!(parse_byte_size("1KiB"), Some(1 << 10));
+        assert_eq!(parse_byte_size("1mib"), Some(1 << 20));
+        assert_eq!(parse_byte_size("1GiB"), Some(1 << 30));
+        assert_eq!(parse_byte_size("1TiB"), Some(1 << 40));
+
+        assert_eq!(parse_byte_size(""), None);
+        assert_eq!(parse_byte_size("MiB"), None);
+        assert_eq!(parse_byte_size("1.5MiB"), None);
+        assert_eq!(parse_byte_size("-1"), None);
+        assert_eq!(parse_byte_size("1 MiB 1 KiB"), None);
+        assert_eq!(parse_byte_size("1KiBx"), None);
+        assert_eq!(parse_byte_size("18446744073709551615KiB"), None);
     }
 }
@@ -0,0 +1,396 @@
+//! Fault Injection
+//!
+//! `FaultyIo` wraps a byte stream (the sub-stream of a `g1_tokio::io::Stream`), and `FaultyStream`
+//! wraps a datagram stream (the stream half of a `g1_tokio::net::udp::UdpSocket`).  They inject
+//! faults into what is received through them.  The faults are controlled by a `Faults` knob that
+//! is shared between a test and the wrappers, and so the test may change them at runtime (e.g., to
+//! heal a lossy network midway).
+//!
+//! NOTE: Drops and reorders are applied to datagrams only, as byte streams are reliable.
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io::Error;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use bytes::{Bytes, BytesMut};
+use futures::stream::{Stream, StreamExt};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{self, Sleep};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FaultConfig {
+    /// Delay before each read or datagram is delivered.
+    pub latency: Duration,
+    /// Probability that a datagram is dropped.
+    pub drop_rate: f64,
+    /// Probability that a datagram is held back and delivered after the next one.
+    pub reorder_rate: f64,
+    /// Probability that a byte of a read or a datagram is flipped.
+    pub corrupt_rate: f64,
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct FaultStats {
+    pub num_dropped: usize,
+    pub num_reordered: usize,
+    pub num_corrupted: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct Faults(Arc<Mutex<Inner>>);
+
+#[derive(Debug)]
+struct Inner {
+    config: FaultConfig,
+    stats: FaultStats,
+    rng: StdRng,
+}
+
+#[derive(Debug)]
+pub struct FaultyIo<Io> {
+    io: Io,
+    faults: Faults,
+    delay: Option<Pin<Box<Sleep>>>,
+}
+
+#[derive(Debug)]
+pub struct FaultyStream<UdpStream> {
+    stream: UdpStream,
+    faults: Faults,
+    delay: Option<Pin<Box<Sleep>>>,
+    held: Option<(SocketAddr, Bytes)>,
+    queue: VecDeque<(SocketAddr, Bytes)>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Fault {
+    Drop,
+    Reorder,
+}
+
+impl Faults {
+    /// Creates a knob that injects no faults until it is set.
+    ///
+    /// Faults are injected pseudo-randomly from `seed`, making a failing test easier to
+    /// reproduce.
+    pub fn new(seed: u64) -> Self {
+        Self(Arc::new(Mutex::new(Inner {
+            config: Default::default(),
+            stats: Default::default(),
+            rng: StdRng::seed_from_u64(seed),
+        })))
+    }
+
+    pub fn config(&self) -> FaultConfig {
+        self.lock().config
+    }
+
+    pub fn set(&self, config: FaultConfig) {
+        self.lock().config = config;
+    }
+
+    /// Stops injecting faults.
+    pub fn heal(&self) {
+        self.set(Default::default());
+    }
+
+    pub fn stats(&self) -> FaultStats {
+        self.lock().stats
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.0.lock().unwrap()
+    }
+
+    fn new_delay(&self) -> Option<Pin<Box<Sleep>>> {
+        let latency = self.lock().config.latency;
+        (!latency.is_zero()).then(|| Box::pin(time::sleep(latency)))
+    }
+
+    fn pick_fault(&self) -> Option<Fault> {
+        let mut inner = self.lock();
+        let FaultConfig {
+            drop_rate,
+            reorder_rate,
+            ..
+        } = inner.config;
+        if inner.roll(drop_rate) {
+            inner.stats.num_dropped += 1;
+            Some(Fault::Drop)
+        } else if inner.roll(reorder_rate) {
+            Some(Fault::Reorder)
+        } else {
+            None
+        }
+    }
+
+    fn corrupt(&self, data: &mut [u8]) {
+        let mut inner = self.lock();
+        let corrupt_rate = inner.config.corrupt_rate;
+        if data.is_empty() || !inner.roll(corrupt_rate) {
+            return;
+        }
+        let i = inner.rng.gen_range(0..data.len());
+        data[i] ^= inner.rng.gen_range(1..=u8::MAX);
+        inner.stats.num_corrupted += 1;
+    }
+
+    fn corrupt_bytes(&self, data: Bytes) -> Bytes {
+        let mut data = BytesMut::from(data.as_ref());
+        self.corrupt(&mut data);
+        data.freeze()
+    }
+}
+
+impl Inner {
+    fn roll(&mut self, probability: f64) -> bool {
+        probability > 0.0 && self.rng.gen_bool(probability.min(1.0))
+    }
+}
+
+impl<Io> FaultyIo<Io> {
+    pub fn new(io: Io, faults: Faults) -> Self {
+        Self {
+            io,
+            faults,
+            delay: None,
+        }
+    }
+
+    pub fn into_inner(self) -> Io {
+        self.io
+    }
+}
+
+impl<Io> AsyncRead for FaultyIo<Io>
+where
+    Io: AsyncRead + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        context: &mut Context<'_>,
+        buffer: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(), Error>> {
+        let this = self.get_mut();
+        if this.delay.is_none() {
+            this.delay = this.faults.new_delay();
+        }
+        // `Sleep` remains ready after it elapses, and so we keep it until the read completes.
+        if let Some(delay) = this.delay.as_mut() {
+            ready!(delay.as_mut().poll(context));
+        }
+        let start = buffer.filled().len();
+        ready!(Pin::new(&mut this.io).poll_read(context, buffer))?;
+        this.delay = None;
+        this.faults.corrupt(&mut buffer.filled_mut()[start..]);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<Io> AsyncWrite for FaultyIo<Io>
+where
+    Io: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        context: &mut Context<'_>,
+        buffer: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        Pin::new(&mut self.get_mut().io).poll_write(context, buffer)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.get_mut().io).poll_flush(context)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.get_mut().io).poll_shutdown(context)
+    }
+}
+
+impl<UdpStream> FaultyStream<UdpStream> {
+    pub fn new(stream: UdpStream, faults: Faults) -> Self {
+        Self {
+            stream,
+            faults,
+            delay: None,
+            held: None,
+            queue: VecDeque::new(),
+        }
+    }
+}
+
+impl<UdpStream> Stream for FaultyStream<UdpStream>
+where
+    UdpStream: Stream<Item = Result<(SocketAddr, Bytes), Error>> + Unpin,
+{
+    type Item = Result<(SocketAddr, Bytes), Error>;
+
+    fn poll_next(self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if !this.queue.is_empty() {
+                if this.delay.is_none() {
+                    this.delay = this.faults.new_delay();
+                }
+                if let Some(delay) = this.delay.as_mut() {
+                    ready!(delay.as_mut().poll(context));
+                    this.delay = None;
+                }
+                return Poll::Ready(this.queue.pop_front().map(Ok));
+            }
+
+            match ready!(this.stream.poll_next_unpin(context)) {
+                Some(Ok((endpoint, payload))) => match this.faults.pick_fault() {
+                    Some(Fault::Drop) => {}
+                    Some(Fault::Reorder) if this.held.is_none() => {
+                        this.faults.lock().stats.num_reordered += 1;
+                        this.held = Some((endpoint, this.faults.corrupt_bytes(payload)));
+                    }
+                    _ => {
+                        this.queue
+                            .push_back((endpoint, this.faults.corrupt_bytes(payload)));
+                        this.queue.extend(this.held.take());
+                    }
+                },
+                Some(Err(error)) => return Poll::Ready(Some(Err(error))),
+                None => match this.held.take() {
+                    Some(item) => this.queue.push_back(item),
+                    None => return Poll::Ready(None),
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::stream;
+    use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
+    use tokio::time::Instant;
+
+    use super::*;
+
+    fn new_datagrams(n: u8) -> Vec<Result<(SocketAddr, Bytes), Error>> {
+        let endpoint = "127.0.0.1:8000".parse().unwrap();
+        (0..n)
+            .map(|i| Ok((endpoint, Bytes::copy_from_slice(&[i]))))
+            .collect()
+    }
+
+    async fn collect<S>(stream: S) -> Vec<u8>
+    where
+        S: Stream<Item = Result<(SocketAddr, Bytes), Error>> + Unpin,
+    {
+        stream
+            .map(|item| item.unwrap().1[0])
+            .collect::<Vec<_>>()
+            .await
+    }
+
+    #[tokio::test]
+    async fn faulty_stream() {
+        let faults = Faults::new(0);
+        assert_eq!(
+            collect(FaultyStream::new(
+                stream::iter(new_datagrams(3)),
+                faults.clone(),
+            ))
+            .await,
+            [0, 1, 2],
+        );
+        assert_eq!(faults.stats(), FaultStats::default());
+
+        faults.set(FaultConfig {
+            drop_rate: 1.0,
+            ..Default::default()
+        });
+        assert_eq!(
+            collect(FaultyStream::new(
+                stream::iter(new_datagrams(3)),
+                faults.clone(),
+            ))
+            .await,
+            [0u8; 0],
+        );
+        assert_eq!(faults.stats().num_dropped, 3);
+
+        faults.set(FaultConfig {
+            reorder_rate: 1.0,
+            ..Default::default()
+        });
+        assert_eq!(
+            collect(FaultyStream::new(
+                stream::iter(new_datagrams(5)),
+                faults.clone(),
+            ))
+            .await,
+            [1, 0, 3, 2, 4],
+        );
+        assert_eq!(faults.stats().num_reordered, 3);
+
+        faults.set(FaultConfig {
+            corrupt_rate: 1.0,
+            ..Default::default()
+        });
+        let output = collect(FaultyStream::new(
+            stream::iter(new_datagrams(3)),
+            faults.clone(),
+        ))
+        .await;
+        assert_eq!(output.len(), 3);
+        assert_ne!(output, [0, 1, 2]);
+        assert_eq!(faults.stats().num_corrupted, 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn faulty_stream_latency() {
+        let faults = Faults::new(0);
+        faults.set(FaultConfig {
+            latency: Duration::from_secs(1),
+            ..Default::default()
+        });
+        let start = Instant::now();
+        assert_eq!(
+            collect(FaultyStream::new(stream::iter(new_datagrams(3)), faults)).await,
+            [0, 1, 2],
+        );
+        assert_eq!(start.elapsed(), Duration::from_secs(3));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn faulty_io() {
+        let faults = Faults::new(0);
+        let (io, mut mock) = io::duplex(4096);
+        let mut io = FaultyIo::new(io, faults.clone());
+
+        let mut buffer = [0u8; 5];
+        mock.write_all(b"hello").await.unwrap();
+        io.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(&buffer, b"hello");
+
+        faults.set(FaultConfig {
+            latency: Duration::from_secs(1),
+            corrupt_rate: 1.0,
+            ..Default::default()
+        });
+        let start = Instant::now();
+        mock.write_all(b"world").await.unwrap();
+        io.read_exact(&mut buffer).await.unwrap();
+        assert_ne!(&buffer, b"world");
+        assert!(start.elapsed() >= Duration::from_secs(1));
+        assert!(faults.stats().num_corrupted >= 1);
+
+        faults.heal();
+        io.write_all(b"spam").await.unwrap();
+        mock.read_exact(&mut buffer[..4]).await.unwrap();
+        assert_eq!(&buffer[..4], b"spam");
+    }
+}
@@ -0,0 +1,4 @@
+//! Test Utilities
+
+#[cfg(feature = "faults")]
+pub mod faults;
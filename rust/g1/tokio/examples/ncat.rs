@@ -2,13 +2,15 @@ use std::io::{Error, ErrorKind};
 use std::marker::Unpin;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 
-use bytes::{Buf, Bytes};
+use bytes::{Buf, Bytes, BytesMut};
 use clap::{Parser, ValueEnum};
 use futures::{sink::SinkExt, stream::StreamExt};
 use tokio::{
     io::{self, AsyncReadExt, AsyncWriteExt},
     net::{self, TcpListener, TcpSocket},
+    time,
 };
 
 use g1_base::str::Hex;
@@ -21,10 +23,11 @@ use g1_tokio::{
 };
 
 use bittorrent_base::{Features, InfoHash};
+use bittorrent_extension::{Handshake, HandshakeOwner};
 use bittorrent_mse::MseStream;
 use bittorrent_peer::Peer;
 use bittorrent_socket::{Message, Socket};
-use bittorrent_utp::UtpSocket;
+use bittorrent_utp::{UtpConfig, UtpSocket};
 
 #[derive(Debug, Parser)]
 #[command(after_help = ParametersConfig::render())]
@@ -43,6 +46,10 @@ struct NetCat {
     #[arg(long)]
     info_hash: Option<String>,
 
+    /// Performs the peer handshake and prints what the peer advertises.
+    #[arg(long, value_name = "INFO_HASH", conflicts_with("info_hash"))]
+    bt_handshake: Option<String>,
+
     #[arg(long, short)]
     listen: bool,
     #[arg(default_value = "127.0.0.1:8000")]
@@ -70,6 +77,9 @@ enum Protocol {
 
 impl NetCat {
     async fn execute(&self) -> Result<(), Error> {
+        if self.bt_handshake.is_some() {
+            return self.execute_bt_handshake().await;
+        }
         match self.protocol {
             Protocol::Bt => return self.execute_bt().await,
             Protocol::BtPeer => return self.execute_bt_peer().await,
@@ -162,6 +172,85 @@ impl NetCat {
         }
     }
 
+    /// Performs the peer handshake (and the extension handshake if supported) with a peer.
+    async fn execute_bt_handshake(&self) -> Result<(), Error> {
+        const EXTENSION_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(8);
+
+        if self.protocol != Protocol::Tcp {
+            return Err(Error::other("bt-handshake mode only supports tcp"));
+        }
+        if self.listen {
+            return Err(Error::other(
+                "bt-handshake mode does not support `--listen`",
+            ));
+        }
+        if self.recv || self.no_recv {
+            return Err(Error::other(
+                "bt-handshake mode does not support `--recv` nor `--no-recv`",
+            ));
+        }
+        if self.send || self.no_send {
+            return Err(Error::other(
+                "bt-handshake mode does not support `--send` nor `--no-send`",
+            ));
+        }
+
+        let info_hash = parse_info_hash(self.bt_handshake.as_ref().unwrap())?;
+        let stream = self.connect().await?;
+        let stream = self.mse_handshake(stream).await?;
+        println!("mse: {}", matches!(stream, MseStream::Rc4(_)));
+        let stream: DynStream<'static> = stream.into();
+
+        let self_features = Features::load();
+        let mut socket = Socket::connect(
+            stream,
+            info_hash,
+            bittorrent_base::self_id().clone(),
+            self_features,
+            None,
+        )
+        .await?;
+        let peer_id = socket.peer_id();
+        println!("peer id: {:?}", peer_id);
+        match peer_id.client() {
+            Some(client) => println!("peer client: {} {}", client.name, client.version),
+            None => println!(
+                "peer client: unknown ({})",
+                peer_id.as_ref()[..8].escape_ascii(),
+            ),
+        }
+        println!(
+            "peer features: {:?} (reserved: {:?})",
+            socket.peer_features(),
+            g1_base::fmt::Hex(&socket.peer_reserved()),
+        );
+
+        if self_features.extension && socket.peer_features().extension {
+            let mut buffer = BytesMut::new();
+            Handshake::new(None).encode_into(&mut buffer);
+            socket
+                .send(Message::Extended(Handshake::ID, buffer.freeze()))
+                .await?;
+
+            let handshake = time::timeout(EXTENSION_HANDSHAKE_TIMEOUT, async {
+                loop {
+                    match socket.recv().await? {
+                        Message::Extended(Handshake::ID, payload) => {
+                            break HandshakeOwner::try_from(payload).map_err(Error::other);
+                        }
+                        // Peers may send, say, a bitfield before the extension handshake.
+                        message => tracing::debug!(?message, "ignore message"),
+                    }
+                }
+            })
+            .await
+            .map_err(|_| Error::other("extension handshake timeout"))??;
+            println!("peer extension handshake: {:?}", handshake.deref());
+        }
+
+        socket.shutdown().await
+    }
+
     async fn execute_tcp(&self) -> Result<(), Error> {
         let stream = if self.listen {
             let (stream, _) = self.bind()?.accept().await?;
@@ -242,7 +331,7 @@ impl NetCat {
     fn new_utp_socket(&self, socket: net::UdpSocket) -> UtpSocket {
         let socket = Arc::new(socket);
         let (stream, sink) = UdpSocket::new(socket.clone()).into_split();
-        UtpSocket::new(socket, stream, sink)
+        UtpSocket::new(socket, stream, sink, UtpConfig::default())
     }
 
     async fn mse_handshake<Stream>(&self, stream: Stream) -> Result<MseStream<Stream>, Error>
@@ -303,13 +392,7 @@ impl NetCat {
     }
 
     fn parse_info_hash(&self) -> Result<Option<InfoHash>, Error> {
-        self.info_hash
-            .as_ref()
-            .map(|info_hash| match info_hash.as_str().try_into() {
-                Ok(Hex(hex)) => Ok(InfoHash::new(hex)),
-                Err(error) => Err(Error::other(error)),
-            })
-            .transpose()
+        self.info_hash.as_deref().map(parse_info_hash).transpose()
     }
 
     async fn copy_bidirectional<Stream, Source, Sink>(
@@ -375,6 +458,13 @@ impl NetCat {
     }
 }
 
+fn parse_info_hash(info_hash: &str) -> Result<InfoHash, Error> {
+    match info_hash.try_into() {
+        Ok(Hex(hex)) => Ok(InfoHash::new(hex)),
+        Err(error) => Err(Error::other(error)),
+    }
+}
+
 async fn recv<Source, Sink>(mut source: Source, mut sink: Sink) -> Result<(), Error>
 where
     Source: StreamRecv<Error = Error>,
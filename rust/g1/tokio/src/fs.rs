@@ -0,0 +1,196 @@
+//! Cancellation-aware File I/O
+//!
+//! `tokio::fs::File` dispatches each operation to the blocking thread pool, and when the calling
+//! task is cancelled, the dispatched operation still runs to completion in the background.  `File`
+//! splits a large read or write into chunks and checks for cancellation between the chunks, so
+//! that a cancelled (or timed out) operation stops consuming disk bandwidth promptly.
+
+use std::cmp;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::os::unix::fs::FileExt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::{Bytes, BytesMut};
+
+use crate::task::{Cancel, JoinGuard};
+
+#[derive(Clone, Debug)]
+pub struct File {
+    file: Arc<fs::File>,
+    timeout: Option<Duration>,
+}
+
+// TODO: What chunk size should we use?
+const CHUNK_SIZE: usize = 65536;
+
+impl From<fs::File> for File {
+    fn from(file: fs::File) -> Self {
+        Self::new(file)
+    }
+}
+
+impl File {
+    pub fn new(file: fs::File) -> Self {
+        Self {
+            file: Arc::new(file),
+            timeout: None,
+        }
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Sets the timeout of each read or write operation.
+    ///
+    /// An operation that times out returns `ErrorKind::TimedOut`, and the blocking task stops
+    /// after it completes the current chunk.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    pub async fn read_exact_at(&self, offset: u64, size: usize) -> Result<Bytes, Error> {
+        self.run(move |file, cancel| {
+            let mut buffer = BytesMut::zeroed(size);
+            read_exact_at(file, &cancel, &mut buffer, offset)?;
+            Ok(buffer.freeze())
+        })
+        .await
+    }
+
+    pub async fn write_all_at(&self, offset: u64, buffer: Bytes) -> Result<(), Error> {
+        self.run(move |file, cancel| write_all_at(file, &cancel, &buffer, offset))
+            .await
+    }
+
+    pub async fn sync_data(&self) -> Result<(), Error> {
+        self.run(|file, _| file.sync_data()).await
+    }
+
+    /// Runs `f` on the blocking thread pool.
+    ///
+    /// If the returned future is dropped, the blocking task is cancelled (but not aborted).
+    async fn run<F, T>(&self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&fs::File, Cancel) -> Result<T, Error> + Send + 'static,
+        T: Send + 'static,
+    {
+        let file = self.file.clone();
+        let mut guard = JoinGuard::spawn_blocking(move |cancel| f(&file, cancel));
+        if let Some(timeout) = self.timeout {
+            guard.add_timeout(timeout);
+        }
+        guard.join().await;
+        guard.take_result()?
+    }
+}
+
+fn read_exact_at(
+    file: &fs::File,
+    cancel: &Cancel,
+    mut buffer: &mut [u8],
+    mut offset: u64,
+) -> Result<(), Error> {
+    while !buffer.is_empty() {
+        check_cancel(cancel)?;
+        let size = cmp::min(buffer.len(), CHUNK_SIZE);
+        match file.read_at(&mut buffer[..size], offset) {
+            Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+            Ok(n) => {
+                buffer = &mut buffer[n..];
+                offset += u64::try_from(n).unwrap();
+            }
+            Err(error) if error.kind() == ErrorKind::Interrupted => {}
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(())
+}
+
+fn write_all_at(
+    file: &fs::File,
+    cancel: &Cancel,
+    mut buffer: &[u8],
+    mut offset: u64,
+) -> Result<(), Error> {
+    while !buffer.is_empty() {
+        check_cancel(cancel)?;
+        let size = cmp::min(buffer.len(), CHUNK_SIZE);
+        match file.write_at(&buffer[..size], offset) {
+            Ok(0) => return Err(ErrorKind::WriteZero.into()),
+            Ok(n) => {
+                buffer = &buffer[n..];
+                offset += u64::try_from(n).unwrap();
+            }
+            Err(error) if error.kind() == ErrorKind::Interrupted => {}
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(())
+}
+
+// `cancel` is set either by the timeout or by dropping the guard, and in the latter case, no one
+// observes the error.
+fn check_cancel(cancel: &Cancel) -> Result<(), Error> {
+    if cancel.is_set() {
+        Err(Error::new(ErrorKind::TimedOut, "file i/o timeout"))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_file() -> File {
+        File::new(tempfile::tempfile().unwrap())
+    }
+
+    #[tokio::test]
+    async fn read_write() {
+        let file = new_file();
+        let data = Bytes::from((0..CHUNK_SIZE * 2 + 1).map(|i| i as u8).collect::<Vec<_>>());
+        file.write_all_at(1, data.clone()).await.unwrap();
+        file.sync_data().await.unwrap();
+
+        assert_eq!(file.read_exact_at(1, data.len()).await.unwrap(), data);
+        assert_eq!(
+            file.read_exact_at(0, 1).await.unwrap(),
+            Bytes::from_static(&[0])
+        );
+        assert_eq!(
+            file.read_exact_at(1, data.len() + 1)
+                .await
+                .unwrap_err()
+                .kind(),
+            ErrorKind::UnexpectedEof,
+        );
+    }
+
+    #[test]
+    fn cancel() {
+        let file = tempfile::tempfile().unwrap();
+        let cancel = Cancel::new();
+        cancel.set();
+
+        assert_eq!(
+            write_all_at(&file, &cancel, b"foo", 0).unwrap_err().kind(),
+            ErrorKind::TimedOut,
+        );
+        assert_eq!(file.metadata().unwrap().len(), 0);
+
+        let mut buffer = [0u8; 1];
+        assert_eq!(
+            read_exact_at(&file, &cancel, &mut buffer, 0)
+                .unwrap_err()
+                .kind(),
+            ErrorKind::TimedOut,
+        );
+
+        // An empty operation completes regardless of cancellation.
+        write_all_at(&file, &cancel, b"", 0).unwrap();
+    }
+}
@@ -3,10 +3,12 @@
 use std::borrow::BorrowMut;
 use std::io::{Error, ErrorKind};
 use std::marker::Unpin;
+use std::time::Duration;
 
 use async_trait::async_trait;
 use bytes::BytesMut;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::time;
 
 use crate::bstream::{SendBuffer, StreamRecv, StreamSend};
 
@@ -15,34 +17,127 @@ pub struct Stream<SubStream> {
     pub(crate) stream: SubStream,
     pub(crate) recv_buffer: BytesMut,
     pub(crate) send_buffer: BytesMut,
+    pub(crate) config: BufferConfig,
 }
 
 #[derive(Debug)]
 pub struct RecvStream<SubStream, Buffer> {
     pub(crate) stream: SubStream,
     pub(crate) buffer: Buffer,
+    pub(crate) config: BufferConfig,
 }
 
 #[derive(Debug)]
 pub struct SendStream<SubStream, Buffer> {
     pub(crate) stream: SubStream,
     pub(crate) buffer: Buffer,
+    pub(crate) config: BufferConfig,
 }
 
-impl<SubStream> Stream<SubStream> {
-    pub fn new(stream: SubStream) -> Self {
+/// Buffer Sizing Policy
+///
+/// When the recv buffer is full, it grows geometrically from `initial_capacity` up to
+/// `max_capacity`, and beyond that, it grows linearly by `initial_capacity`.  When the send
+/// buffer is emptied and its capacity exceeds `max_capacity`, it is released.
+///
+/// When no data is received for `idle_timeout`, empty buffers are released, reducing the memory
+/// held by idle connections.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct BufferConfig {
+    pub initial_capacity: usize,
+    pub max_capacity: usize,
+    pub idle_timeout: Option<Duration>,
+}
+
+impl Default for BufferConfig {
+    fn default() -> Self {
         Self {
-            stream,
-            recv_buffer: BytesMut::new(),
-            send_buffer: BytesMut::new(),
+            initial_capacity: 4096,
+            max_capacity: 256 * 1024,
+            idle_timeout: Some(Duration::from_secs(60)),
         }
     }
+}
+
+impl BufferConfig {
+    fn grow(&self, buffer: &mut BytesMut) {
+        let len = buffer.len();
+        if len < buffer.capacity() {
+            return;
+        }
+        let new_capacity = if len < self.max_capacity {
+            len.saturating_mul(2).clamp(
+                self.initial_capacity.min(self.max_capacity),
+                self.max_capacity,
+            )
+        } else {
+            len.saturating_add(self.initial_capacity)
+        };
+        if new_capacity <= len {
+            return;
+        }
+        // `BytesMut::reserve` doubles the capacity, and so we allocate a new buffer instead.
+        let mut new_buffer = BytesMut::with_capacity(new_capacity);
+        new_buffer.extend_from_slice(buffer);
+        *buffer = new_buffer;
+    }
+}
+
+/// Releases the buffer if it is empty and its capacity exceeds `max_capacity`.
+fn shrink(buffer: &mut BytesMut, max_capacity: usize) {
+    if buffer.is_empty() && buffer.capacity() > max_capacity {
+        *buffer = BytesMut::new();
+    }
+}
+
+async fn read_buf<SubStream, F>(
+    stream: &mut SubStream,
+    buffer: &mut BytesMut,
+    config: &BufferConfig,
+    mut on_idle: F,
+) -> Result<usize, Error>
+where
+    SubStream: AsyncReadExt + Unpin,
+    F: FnMut(),
+{
+    config.grow(buffer);
+    let Some(idle_timeout) = config.idle_timeout else {
+        return stream.read_buf(buffer).await;
+    };
+    loop {
+        // `read_buf` is cancel-safe.
+        tokio::select! {
+            size = stream.read_buf(&mut *buffer) => return size,
+            () = time::sleep(idle_timeout) => {
+                // After the buffer is released, `read_buf` reserves only a small chunk for the
+                // next read.
+                shrink(buffer, 0);
+                on_idle();
+            }
+        }
+    }
+}
+
+impl<SubStream> Stream<SubStream> {
+    pub fn new(stream: SubStream) -> Self {
+        Self::with_buffer_config(stream, Default::default())
+    }
 
     pub fn with_capacity(stream: SubStream, recv_capacity: usize, send_capacity: usize) -> Self {
         Self {
             stream,
             recv_buffer: BytesMut::with_capacity(recv_capacity),
             send_buffer: BytesMut::with_capacity(send_capacity),
+            config: Default::default(),
+        }
+    }
+
+    pub fn with_buffer_config(stream: SubStream, config: BufferConfig) -> Self {
+        Self {
+            stream,
+            recv_buffer: BytesMut::new(),
+            send_buffer: BytesMut::new(),
+            config,
         }
     }
 
@@ -50,24 +145,34 @@ impl<SubStream> Stream<SubStream> {
         stream: SubStream,
         recv_buffer: BytesMut,
         send_buffer: BytesMut,
+        config: BufferConfig,
     ) -> Self {
         Self {
             stream,
             recv_buffer,
             send_buffer,
+            config,
         }
     }
 }
 
 impl<SubStream, Buffer> RecvStream<SubStream, Buffer> {
-    pub(crate) fn new(stream: SubStream, buffer: Buffer) -> Self {
-        Self { stream, buffer }
+    pub(crate) fn new(stream: SubStream, buffer: Buffer, config: BufferConfig) -> Self {
+        Self {
+            stream,
+            buffer,
+            config,
+        }
     }
 }
 
 impl<SubStream, Buffer> SendStream<SubStream, Buffer> {
-    pub(crate) fn new(stream: SubStream, buffer: Buffer) -> Self {
-        Self { stream, buffer }
+    pub(crate) fn new(stream: SubStream, buffer: Buffer, config: BufferConfig) -> Self {
+        Self {
+            stream,
+            buffer,
+            config,
+        }
     }
 }
 
@@ -85,7 +190,14 @@ where
     }
 
     async fn recv_or_eof(&mut self) -> Result<Option<usize>, Self::Error> {
-        let size = self.stream.read_buf(&mut self.recv_buffer).await?;
+        let send_buffer = &mut self.send_buffer;
+        let size = read_buf(
+            &mut self.stream,
+            &mut self.recv_buffer,
+            &self.config,
+            || shrink(send_buffer, 0),
+        )
+        .await?;
         if size == 0 {
             Ok(None)
         } else {
@@ -112,6 +224,7 @@ where
     async fn send_all(&mut self) -> Result<(), Self::Error> {
         self.stream.write_all_buf(&mut self.send_buffer).await?;
         self.stream.flush().await?;
+        shrink(&mut self.send_buffer, self.config.max_capacity);
         Ok(())
     }
 
@@ -137,7 +250,13 @@ where
     }
 
     async fn recv_or_eof(&mut self) -> Result<Option<usize>, Self::Error> {
-        let size = self.stream.read_buf(self.buffer.borrow_mut()).await?;
+        let size = read_buf(
+            &mut self.stream,
+            self.buffer.borrow_mut(),
+            &self.config,
+            || {},
+        )
+        .await?;
         if size == 0 {
             Ok(None)
         } else {
@@ -165,6 +284,7 @@ where
     async fn send_all(&mut self) -> Result<(), Self::Error> {
         self.stream.write_all_buf(self.buffer.borrow_mut()).await?;
         self.stream.flush().await?;
+        shrink(self.buffer.borrow_mut(), self.config.max_capacity);
         Ok(())
     }
 
@@ -192,14 +312,14 @@ mod mock {
     impl RecvStream<DuplexStream, BytesMut> {
         pub fn new_mock(max_buf_size: usize) -> (Self, DuplexStream) {
             let (stream, mock) = io::duplex(max_buf_size);
-            (Self::new(stream, BytesMut::new()), mock)
+            (Self::new(stream, BytesMut::new(), Default::default()), mock)
         }
     }
 
     impl SendStream<DuplexStream, BytesMut> {
         pub fn new_mock(max_buf_size: usize) -> (Self, DuplexStream) {
             let (stream, mock) = io::duplex(max_buf_size);
-            (Self::new(stream, BytesMut::new()), mock)
+            (Self::new(stream, BytesMut::new(), Default::default()), mock)
         }
     }
 }
@@ -213,6 +333,60 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn buffer_config_grow() {
+        let config = BufferConfig {
+            initial_capacity: 4,
+            max_capacity: 16,
+            idle_timeout: None,
+        };
+        let mut buffer = BytesMut::new();
+        for (len, capacity) in [(0, 4), (4, 8), (8, 16), (16, 20), (20, 24)] {
+            buffer.resize(len, 0);
+            config.grow(&mut buffer);
+            assert_eq!(buffer.len(), len);
+            assert_eq!(buffer.capacity(), capacity);
+            // Not full yet.
+            config.grow(&mut buffer);
+            assert_eq!(buffer.capacity(), capacity);
+        }
+    }
+
+    #[test]
+    fn test_shrink() {
+        let mut buffer = BytesMut::with_capacity(16);
+        shrink(&mut buffer, 16);
+        assert_eq!(buffer.capacity(), 16);
+        shrink(&mut buffer, 8);
+        assert_eq!(buffer.capacity(), 0);
+
+        let mut buffer = BytesMut::from(b"x".as_slice());
+        shrink(&mut buffer, 0);
+        assert_eq!(buffer.as_ref(), b"x");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stream_recv_idle() {
+        let (mut stream, mut mock) = Stream::new_mock(4096);
+
+        mock.write_all(b"hello").await.unwrap();
+        assert_matches!(stream.recv().await, Ok(5));
+        assert_eq!(stream.recv_buffer.capacity(), 4096);
+        stream.recv_buffer.clear();
+        stream.send_buffer.reserve(4096);
+
+        assert_matches!(
+            time::timeout(Duration::from_secs(61), stream.recv()).await,
+            Err(_),
+        );
+        assert!(stream.recv_buffer.capacity() < 4096);
+        assert_eq!(stream.send_buffer.capacity(), 0);
+
+        mock.write_all(b"world").await.unwrap();
+        assert_matches!(stream.recv().await, Ok(5));
+        assert_eq!(stream.recv_buffer.as_ref(), b"world");
+    }
+
     #[tokio::test]
     async fn stream_recv() {
         test_stream_recv(Stream::new_mock(4096)).await;
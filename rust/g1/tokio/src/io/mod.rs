@@ -7,7 +7,7 @@ use async_trait::async_trait;
 use bytes::BufMut;
 use tokio::io::AsyncReadExt;
 
-pub use self::bstream::{RecvStream, SendStream, Stream};
+pub use self::bstream::{BufferConfig, RecvStream, SendStream, Stream};
 pub use self::traitobj::{DynStream, DynStreamRecv, DynStreamSend};
 
 #[async_trait]
@@ -5,6 +5,7 @@
 #![cfg_attr(test, feature(binary_heap_into_iter_sorted))]
 
 pub mod bstream;
+pub mod fs;
 pub mod io;
 pub mod net;
 pub mod os;
@@ -0,0 +1,297 @@
+//! DNS Resolution with Caching
+//!
+//! Unlike `tokio::net::lookup_host`, which calls `getaddrinfo` on every invocation, `Resolver`
+//! caches the results for as long as their TTL permits (capped at `MAX_TTL`), and supports SRV and
+//! TXT lookups.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+
+use bytes::Bytes;
+use hickory_resolver::config::{ResolverConfig, ResolverOpts};
+use hickory_resolver::error::{ResolveError, ResolveErrorKind};
+use hickory_resolver::TokioAsyncResolver;
+use tokio::time::Instant;
+
+use g1_base::sync::MutexExt;
+
+const CACHE_SIZE: usize = 256;
+const MAX_TTL: Duration = Duration::from_secs(300);
+
+#[derive(Clone, Debug)]
+pub struct Resolver(Arc<Inner>);
+
+#[derive(Debug)]
+struct Inner {
+    resolver: TokioAsyncResolver,
+    ip: Cache<Arc<[IpAddr]>>,
+    srv: Cache<Arc<[Srv]>>,
+    txt: Cache<Arc<[Bytes]>>,
+}
+
+/// SRV record, which is sorted by priority (ascending) and then by weight (descending).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Srv {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    pub target: String,
+}
+
+#[derive(Debug)]
+struct Cache<T>(Mutex<HashMap<String, (Instant, T)>>);
+
+/// Returns the process-wide resolver, which is configured from `/etc/resolv.conf`.
+pub fn resolver() -> &'static Resolver {
+    static RESOLVER: LazyLock<Resolver> = LazyLock::new(|| {
+        let resolver = TokioAsyncResolver::tokio_from_system_conf().unwrap_or_else(|error| {
+            tracing::warn!(%error, "use default resolver config");
+            TokioAsyncResolver::tokio(ResolverConfig::default(), ResolverOpts::default())
+        });
+        Resolver::new(resolver)
+    });
+    &RESOLVER
+}
+
+impl Resolver {
+    pub fn new(resolver: TokioAsyncResolver) -> Self {
+        Self(Arc::new(Inner {
+            resolver,
+            ip: Cache::new(),
+            srv: Cache::new(),
+            txt: Cache::new(),
+        }))
+    }
+
+    pub async fn lookup_ip(&self, host: &str) -> Result<Arc<[IpAddr]>, Error> {
+        if let Some(ips) = self.0.ip.get(host) {
+            return Ok(ips);
+        }
+        let lookup = self.0.resolver.lookup_ip(host).await.map_err(to_io_error)?;
+        let ips: Arc<[IpAddr]> = lookup.iter().collect();
+        self.0
+            .ip
+            .insert(host, ttl(lookup.valid_until()), ips.clone());
+        Ok(ips)
+    }
+
+    pub async fn lookup_host(&self, host: &str, port: u16) -> Result<Vec<SocketAddr>, Error> {
+        Ok(self
+            .lookup_ip(host)
+            .await?
+            .iter()
+            .map(|ip| SocketAddr::new(*ip, port))
+            .collect())
+    }
+
+    /// Similar to `g1_tokio::net::lookup_host_first`, except that it caches the result.
+    pub async fn lookup_host_first(&self, host: &str, port: u16) -> Result<SocketAddr, Error> {
+        self.lookup_host(host, port)
+            .await?
+            .first()
+            .copied()
+            .ok_or_else(|| Error::other("cannot be resolved to any addresses"))
+    }
+
+    /// Resolves an endpoint of the form `host:port`.
+    pub async fn lookup_endpoint(&self, endpoint: &str) -> Result<SocketAddr, Error> {
+        if let Ok(endpoint) = endpoint.parse() {
+            return Ok(endpoint);
+        }
+        let (host, port) = split_host_port(endpoint)?;
+        self.lookup_host_first(host, port).await
+    }
+
+    pub async fn lookup_srv(&self, name: &str) -> Result<Arc<[Srv]>, Error> {
+        if let Some(srvs) = self.0.srv.get(name) {
+            return Ok(srvs);
+        }
+        let lookup = self
+            .0
+            .resolver
+            .srv_lookup(name)
+            .await
+            .map_err(to_io_error)?;
+        let mut srvs: Vec<_> = lookup
+            .iter()
+            .map(|srv| Srv {
+                priority: srv.priority(),
+                weight: srv.weight(),
+                port: srv.port(),
+                target: srv.target().to_utf8(),
+            })
+            .collect();
+        srvs.sort_by_key(|srv| (srv.priority, Reverse(srv.weight)));
+        let srvs: Arc<[Srv]> = srvs.into();
+        self.0
+            .srv
+            .insert(name, ttl(lookup.as_lookup().valid_until()), srvs.clone());
+        Ok(srvs)
+    }
+
+    /// Resolves the SRV records and then their targets, in the order of the SRV records.
+    pub async fn lookup_srv_endpoints(&self, name: &str) -> Result<Vec<SocketAddr>, Error> {
+        let mut endpoints = Vec::new();
+        for srv in self.lookup_srv(name).await?.iter() {
+            match self.lookup_host(&srv.target, srv.port).await {
+                Ok(addrs) => endpoints.extend(addrs),
+                Err(error) => tracing::warn!(name, ?srv, %error, "srv target resolution error"),
+            }
+        }
+        Ok(endpoints)
+    }
+
+    /// Returns the TXT records, where the character strings of each record are concatenated.
+    pub async fn lookup_txt(&self, name: &str) -> Result<Arc<[Bytes]>, Error> {
+        if let Some(txts) = self.0.txt.get(name) {
+            return Ok(txts);
+        }
+        let lookup = self
+            .0
+            .resolver
+            .txt_lookup(name)
+            .await
+            .map_err(to_io_error)?;
+        let txts: Arc<[Bytes]> = lookup
+            .iter()
+            .map(|txt| Bytes::from(txt.txt_data().concat()))
+            .collect();
+        self.0
+            .txt
+            .insert(name, ttl(lookup.as_lookup().valid_until()), txts.clone());
+        Ok(txts)
+    }
+}
+
+impl<T> Cache<T>
+where
+    T: Clone,
+{
+    fn new() -> Self {
+        Self(Mutex::new(HashMap::new()))
+    }
+
+    fn get(&self, name: &str) -> Option<T> {
+        let mut map = self.0.must_lock();
+        let (expire_at, value) = map.get(name)?;
+        if *expire_at > Instant::now() {
+            return Some(value.clone());
+        }
+        map.remove(name);
+        None
+    }
+
+    fn insert(&self, name: &str, ttl: Duration, value: T) {
+        let now = Instant::now();
+        let mut map = self.0.must_lock();
+        if map.len() >= CACHE_SIZE && !map.contains_key(name) {
+            map.retain(|_, (expire_at, _)| *expire_at > now);
+            if map.len() >= CACHE_SIZE {
+                // Evict the entry that expires the soonest.
+                let name = map
+                    .iter()
+                    .min_by_key(|(_, (expire_at, _))| *expire_at)
+                    .map(|(name, _)| name.clone())
+                    .unwrap();
+                map.remove(&name);
+            }
+        }
+        map.insert(name.to_string(), (now + ttl.min(MAX_TTL), value));
+    }
+}
+
+fn ttl(valid_until: std::time::Instant) -> Duration {
+    valid_until.saturating_duration_since(std::time::Instant::now())
+}
+
+fn split_host_port(endpoint: &str) -> Result<(&str, u16), Error> {
+    let invalid = || {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid endpoint: {endpoint}"),
+        )
+    };
+    let (host, port) = endpoint.rsplit_once(':').ok_or_else(invalid)?;
+    let host = host
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host);
+    if host.is_empty() {
+        return Err(invalid());
+    }
+    Ok((host, port.parse().map_err(|_| invalid())?))
+}
+
+fn to_io_error(error: ResolveError) -> Error {
+    let kind = match error.kind() {
+        ResolveErrorKind::NoRecordsFound { .. } => ErrorKind::NotFound,
+        ResolveErrorKind::Timeout => ErrorKind::TimedOut,
+        _ => ErrorKind::Other,
+    };
+    Error::new(kind, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn cache() {
+        let cache = Cache::new();
+        assert_eq!(cache.get("foo"), None);
+
+        cache.insert("foo", Duration::from_secs(10), 1);
+        cache.insert("bar", Duration::from_secs(3600), 2);
+        assert_eq!(cache.get("foo"), Some(1));
+        assert_eq!(cache.get("bar"), Some(2));
+
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert_eq!(cache.get("foo"), None);
+        assert_eq!(cache.get("bar"), Some(2));
+        assert_eq!(cache.0.must_lock().len(), 1);
+
+        // TTL is capped at `MAX_TTL`.
+        tokio::time::advance(MAX_TTL).await;
+        assert_eq!(cache.get("bar"), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cache_evict() {
+        let cache = Cache::new();
+        for i in 0..CACHE_SIZE {
+            cache.insert(
+                &i.to_string(),
+                Duration::from_secs(10 + u64::try_from(i).unwrap()),
+                i,
+            );
+        }
+        cache.insert("x", Duration::from_secs(1), CACHE_SIZE);
+        assert_eq!(cache.0.must_lock().len(), CACHE_SIZE);
+        assert_eq!(cache.get("0"), None);
+        assert_eq!(cache.get("1"), Some(1));
+        assert_eq!(cache.get("x"), Some(CACHE_SIZE));
+
+        // Expired entries are removed before evicting unexpired ones.
+        tokio::time::advance(Duration::from_secs(10)).await;
+        cache.insert("y", Duration::from_secs(1), 0);
+        assert_eq!(cache.0.must_lock().len(), CACHE_SIZE);
+        assert_eq!(cache.get("x"), None);
+        assert_eq!(cache.get("1"), Some(1));
+    }
+
+    #[test]
+    fn test_split_host_port() {
+        assert_eq!(split_host_port("foo:80").unwrap(), ("foo", 80));
+        assert_eq!(split_host_port("[::1]:80").unwrap(), ("::1", 80));
+        for endpoint in ["", "foo", ":80", "foo:", "foo:bar", "foo:65536"] {
+            assert_eq!(
+                split_host_port(endpoint).unwrap_err().kind(),
+                ErrorKind::InvalidInput,
+            );
+        }
+    }
+}
@@ -1,10 +1,18 @@
+#[cfg(feature = "dns")]
+pub mod dns;
 #[cfg(feature = "icmp")]
 pub mod icmp;
+#[cfg(feature = "socks5")]
+pub mod socks5;
 pub mod tcp;
 pub mod udp;
 
+#[cfg(target_os = "linux")]
+use std::ffi::OsString;
 use std::io::Error;
 use std::net::SocketAddr;
+#[cfg(target_os = "linux")]
+use std::os::fd::AsFd;
 
 use tokio::net::{self, ToSocketAddrs};
 
@@ -17,3 +25,18 @@ where
         .next()
         .ok_or_else(|| Error::other("cannot be resolved to any addresses"))
 }
+
+/// Binds a socket to a network interface (`SO_BINDTODEVICE`).
+///
+/// Packets are then sent and received only through that interface regardless of the routing
+/// table, which is what VPN split tunneling setups usually require.
+#[cfg(target_os = "linux")]
+pub fn bind_device<F>(socket: &F, interface: &str) -> Result<(), Error>
+where
+    F: AsFd,
+{
+    use nix::sys::socket::{setsockopt, sockopt};
+
+    setsockopt(socket, sockopt::BindToDevice, &OsString::from(interface))?;
+    Ok(())
+}
@@ -0,0 +1,502 @@
+//! SOCKS5 Client (RFC 1928)
+//!
+//! It supports the CONNECT and UDP ASSOCIATE commands, but not authentication.
+
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+
+use bytes::{Buf, BufMut, BytesMut};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+
+const VERSION: u8 = 0x05;
+
+const METHOD_NO_AUTH: u8 = 0x00;
+const METHOD_NO_ACCEPTABLE: u8 = 0xff;
+
+const COMMAND_CONNECT: u8 = 0x01;
+const COMMAND_UDP_ASSOCIATE: u8 = 0x03;
+
+const ADDRESS_TYPE_IPV4: u8 = 0x01;
+const ADDRESS_TYPE_DOMAIN_NAME: u8 = 0x03;
+const ADDRESS_TYPE_IPV6: u8 = 0x04;
+
+const REPLY_SUCCEEDED: u8 = 0x00;
+
+// IPv4 UDP datagrams are smaller than 64 KB.
+const BUFFER_CAPACITY: usize = 65536;
+
+/// Destination address, which may be a domain name to be resolved by the proxy.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Address {
+    SocketAddr(SocketAddr),
+    DomainName(String, u16),
+}
+
+/// UDP association with a SOCKS5 proxy.
+///
+/// The association lasts as long as the control connection, which is closed when this is dropped.
+#[derive(Debug)]
+pub struct UdpAssociation {
+    // We never use the control connection, but the proxy terminates the association when it is
+    // closed.
+    _control: TcpStream,
+    socket: UdpSocket,
+    relay: SocketAddr,
+}
+
+impl From<SocketAddr> for Address {
+    fn from(endpoint: SocketAddr) -> Self {
+        Self::SocketAddr(endpoint)
+    }
+}
+
+impl From<SocketAddrV4> for Address {
+    fn from(endpoint: SocketAddrV4) -> Self {
+        Self::SocketAddr(endpoint.into())
+    }
+}
+
+impl From<SocketAddrV6> for Address {
+    fn from(endpoint: SocketAddrV6) -> Self {
+        Self::SocketAddr(endpoint.into())
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SocketAddr(endpoint) => std::write!(f, "{endpoint}"),
+            Self::DomainName(domain_name, port) => std::write!(f, "{domain_name}:{port}"),
+        }
+    }
+}
+
+impl Address {
+    fn encode(&self, buffer: &mut BytesMut) -> Result<(), Error> {
+        match self {
+            Self::SocketAddr(SocketAddr::V4(endpoint)) => {
+                buffer.put_u8(ADDRESS_TYPE_IPV4);
+                buffer.put_slice(&endpoint.ip().octets());
+                buffer.put_u16(endpoint.port());
+            }
+            Self::SocketAddr(SocketAddr::V6(endpoint)) => {
+                buffer.put_u8(ADDRESS_TYPE_IPV6);
+                buffer.put_slice(&endpoint.ip().octets());
+                buffer.put_u16(endpoint.port());
+            }
+            Self::DomainName(domain_name, port) => {
+                let size = u8::try_from(domain_name.len()).map_err(|_| {
+                    Error::new(
+                        ErrorKind::InvalidInput,
+                        std::format!("socks5 domain name is too long: {domain_name}"),
+                    )
+                })?;
+                buffer.put_u8(ADDRESS_TYPE_DOMAIN_NAME);
+                buffer.put_u8(size);
+                buffer.put_slice(domain_name.as_bytes());
+                buffer.put_u16(*port);
+            }
+        }
+        Ok(())
+    }
+
+    /// Decodes an address from the front of `buffer`.
+    fn decode(buffer: &mut &[u8]) -> Result<Self, Error> {
+        fn ensure_remaining(buffer: &[u8], size: usize) -> Result<(), Error> {
+            if buffer.remaining() < size {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "socks5 address is truncated",
+                ));
+            }
+            Ok(())
+        }
+
+        ensure_remaining(buffer, 1)?;
+        match buffer.get_u8() {
+            ADDRESS_TYPE_IPV4 => {
+                ensure_remaining(buffer, 4 + 2)?;
+                let ip = Ipv4Addr::from(buffer.get_u32());
+                Ok(SocketAddrV4::new(ip, buffer.get_u16()).into())
+            }
+            ADDRESS_TYPE_IPV6 => {
+                ensure_remaining(buffer, 16 + 2)?;
+                let ip = Ipv6Addr::from(buffer.get_u128());
+                Ok(SocketAddrV6::new(ip, buffer.get_u16(), 0, 0).into())
+            }
+            ADDRESS_TYPE_DOMAIN_NAME => {
+                ensure_remaining(buffer, 1)?;
+                let size = usize::from(buffer.get_u8());
+                ensure_remaining(buffer, size + 2)?;
+                let domain_name = String::from_utf8(buffer[..size].to_vec())
+                    .map_err(|error| Error::new(ErrorKind::InvalidData, error))?;
+                buffer.advance(size);
+                Ok(Self::DomainName(domain_name, buffer.get_u16()))
+            }
+            address_type => Err(Error::new(
+                ErrorKind::InvalidData,
+                std::format!("unknown socks5 address type: {address_type}"),
+            )),
+        }
+    }
+}
+
+/// Asks the proxy, to which `stream` is connected, to connect to `target`.
+///
+/// On success, `stream` is relayed to `target`.
+pub async fn connect<Stream>(stream: &mut Stream, target: &Address) -> Result<(), Error>
+where
+    Stream: AsyncRead + AsyncWrite + Unpin,
+{
+    request(stream, COMMAND_CONNECT, target).await?;
+    Ok(())
+}
+
+impl UdpAssociation {
+    /// Associates `socket` with the proxy, to which `control` is connected.
+    pub async fn new(mut control: TcpStream, socket: UdpSocket) -> Result<Self, Error> {
+        // We do not know the address from which the proxy will see our datagrams (we might be
+        // behind a NAT), and so we send the unspecified address as RFC 1928 suggests.
+        let unspecified = match socket.local_addr()? {
+            SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+            SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+        };
+        let relay = request(&mut control, COMMAND_UDP_ASSOCIATE, &unspecified.into()).await?;
+        let Address::SocketAddr(mut relay) = relay else {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                std::format!("expect socks5 relay socket address: {relay}"),
+            ));
+        };
+        // Some proxies reply with the unspecified address, meaning the proxy address itself.
+        if relay.ip().is_unspecified() {
+            relay.set_ip(control.peer_addr()?.ip());
+        }
+        Ok(Self {
+            _control: control,
+            socket,
+            relay,
+        })
+    }
+
+    pub fn relay(&self) -> SocketAddr {
+        self.relay
+    }
+
+    pub async fn send_to(&self, payload: &[u8], target: &Address) -> Result<(), Error> {
+        let mut buffer = BytesMut::with_capacity(payload.len() + 32);
+        buffer.put_u16(0); // RSV
+        buffer.put_u8(0); // FRAG
+        target.encode(&mut buffer)?;
+        buffer.put_slice(payload);
+        self.socket.send_to(&buffer, self.relay).await?;
+        Ok(())
+    }
+
+    /// Receives a datagram and returns the payload and the address from which it was sent.
+    ///
+    /// It drops datagrams that are not from the relay or are fragmented.
+    pub async fn recv_from(&self) -> Result<(BytesMut, Address), Error> {
+        let mut buffer = vec![0u8; BUFFER_CAPACITY];
+        loop {
+            let (size, endpoint) = self.socket.recv_from(&mut buffer).await?;
+            if endpoint != self.relay {
+                tracing::debug!(%endpoint, "drop socks5 datagram from non-relay");
+                continue;
+            }
+            match decode_datagram(&buffer[..size]) {
+                Ok(Some((payload, source))) => return Ok((BytesMut::from(payload), source)),
+                Ok(None) => tracing::debug!("drop fragmented socks5 datagram"),
+                Err(error) => tracing::debug!(%error, "drop invalid socks5 datagram"),
+            }
+        }
+    }
+}
+
+/// Sends a request and returns the address in the reply.
+async fn request<Stream>(
+    stream: &mut Stream,
+    command: u8,
+    target: &Address,
+) -> Result<Address, Error>
+where
+    Stream: AsyncRead + AsyncWrite + Unpin,
+{
+    stream.write_all(&[VERSION, 1, METHOD_NO_AUTH]).await?;
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await?;
+    ensure_version(reply[0])?;
+    match reply[1] {
+        METHOD_NO_AUTH => {}
+        METHOD_NO_ACCEPTABLE => {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                "socks5 proxy requires authentication",
+            ));
+        }
+        method => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                std::format!("unexpected socks5 method: {method}"),
+            ));
+        }
+    }
+
+    let mut buffer = BytesMut::new();
+    buffer.put_slice(&[VERSION, command, 0]);
+    target.encode(&mut buffer)?;
+    stream.write_all(&buffer).await?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await?;
+    ensure_version(reply[0])?;
+    if reply[1] != REPLY_SUCCEEDED {
+        return Err(reply_error(reply[1]));
+    }
+    // Read the rest of the address, including the port, and then decode it.
+    let mut address = BytesMut::new();
+    address.put_u8(reply[3]);
+    let size = match reply[3] {
+        ADDRESS_TYPE_IPV4 => 4 + 2,
+        ADDRESS_TYPE_IPV6 => 16 + 2,
+        ADDRESS_TYPE_DOMAIN_NAME => {
+            let size = stream.read_u8().await?;
+            address.put_u8(size);
+            usize::from(size) + 2
+        }
+        address_type => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                std::format!("unknown socks5 address type: {address_type}"),
+            ));
+        }
+    };
+    let offset = address.len();
+    address.resize(offset + size, 0);
+    stream.read_exact(&mut address[offset..]).await?;
+    Address::decode(&mut address.as_ref())
+}
+
+fn ensure_version(version: u8) -> Result<(), Error> {
+    if version != VERSION {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            std::format!("unexpected socks version: {version}"),
+        ));
+    }
+    Ok(())
+}
+
+fn reply_error(reply: u8) -> Error {
+    let (kind, message) = match reply {
+        0x01 => (ErrorKind::Other, "general socks server failure"),
+        0x02 => (
+            ErrorKind::PermissionDenied,
+            "connection not allowed by ruleset",
+        ),
+        0x03 => (ErrorKind::NetworkUnreachable, "network unreachable"),
+        0x04 => (ErrorKind::HostUnreachable, "host unreachable"),
+        0x05 => (ErrorKind::ConnectionRefused, "connection refused"),
+        0x06 => (ErrorKind::TimedOut, "ttl expired"),
+        0x07 => (ErrorKind::Unsupported, "command not supported"),
+        0x08 => (ErrorKind::Unsupported, "address type not supported"),
+        _ => (ErrorKind::Other, "unknown socks5 reply"),
+    };
+    Error::new(kind, std::format!("{message}: {reply}"))
+}
+
+/// Decodes a UDP request header and returns the payload, or `None` if it is a fragment.
+fn decode_datagram(mut datagram: &[u8]) -> Result<Option<(&[u8], Address)>, Error> {
+    if datagram.remaining() < 3 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "socks5 datagram is truncated",
+        ));
+    }
+    datagram.advance(2); // RSV
+    if datagram.get_u8() != 0 {
+        return Ok(None);
+    }
+    let source = Address::decode(&mut datagram)?;
+    Ok(Some((datagram, source)))
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn address() {
+        fn test(address: Address, expect: &[u8]) {
+            let mut buffer = BytesMut::new();
+            address.encode(&mut buffer).unwrap();
+            assert_eq!(buffer, expect);
+
+            let mut buffer = expect;
+            assert_eq!(Address::decode(&mut buffer).unwrap(), address);
+            assert_eq!(buffer, b"");
+        }
+
+        test(
+            "127.0.0.1:8000".parse::<SocketAddr>().unwrap().into(),
+            b"\x01\x7f\x00\x00\x01\x1f\x40",
+        );
+        test(
+            "[::1]:8000".parse::<SocketAddr>().unwrap().into(),
+            b"\x04\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x01\x1f\x40",
+        );
+        test(
+            Address::DomainName("x.org".to_string(), 8000),
+            b"\x03\x05x.org\x1f\x40",
+        );
+
+        assert!(Address::decode(&mut b"\x01\x7f\x00".as_slice()).is_err());
+        assert!(Address::decode(&mut b"\x03\x05x.o".as_slice()).is_err());
+        assert!(Address::decode(&mut b"\x02".as_slice()).is_err());
+
+        let mut buffer = BytesMut::new();
+        assert!(Address::DomainName("x".repeat(256), 80)
+            .encode(&mut buffer)
+            .is_err());
+    }
+
+    #[test]
+    fn test_decode_datagram() {
+        assert_eq!(
+            decode_datagram(b"\x00\x00\x00\x01\x7f\x00\x00\x01\x1f\x40spam").unwrap(),
+            Some((
+                b"spam".as_slice(),
+                "127.0.0.1:8000".parse::<SocketAddr>().unwrap().into(),
+            )),
+        );
+        assert_eq!(
+            decode_datagram(b"\x00\x00\x01\x01\x7f\x00\x00\x01\x1f\x40spam").unwrap(),
+            None,
+        );
+        assert!(decode_datagram(b"\x00\x00").is_err());
+    }
+
+    /// Serves one request and returns the command and the target.
+    async fn serve_request(stream: &mut TcpStream, bound: &Address) -> (u8, Address) {
+        let mut greeting = [0u8; 3];
+        stream.read_exact(&mut greeting).await.unwrap();
+        assert_eq!(greeting, [VERSION, 1, METHOD_NO_AUTH]);
+        stream.write_all(&[VERSION, METHOD_NO_AUTH]).await.unwrap();
+
+        let mut header = [0u8; 3];
+        stream.read_exact(&mut header).await.unwrap();
+        assert_eq!(header[0], VERSION);
+        // Our tests only send IPv4 addresses to the proxy.
+        let mut address = [0u8; 1 + 4 + 2];
+        stream.read_exact(&mut address).await.unwrap();
+        let target = Address::decode(&mut address.as_slice()).unwrap();
+
+        let mut reply = BytesMut::new();
+        reply.put_slice(&[VERSION, REPLY_SUCCEEDED, 0]);
+        bound.encode(&mut reply).unwrap();
+        stream.write_all(&reply).await.unwrap();
+
+        (header[1], target)
+    }
+
+    #[tokio::test]
+    async fn test_connect() {
+        let target: SocketAddr = "127.0.0.1:8000".parse().unwrap();
+        let proxy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_endpoint = proxy.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = proxy.accept().await.unwrap();
+            let request = serve_request(&mut stream, &target.into()).await;
+            stream.write_all(b"hello").await.unwrap();
+            request
+        });
+
+        let mut stream = TcpStream::connect(proxy_endpoint).await.unwrap();
+        connect(&mut stream, &target.into()).await.unwrap();
+        let mut buffer = [0u8; 5];
+        stream.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(&buffer, b"hello");
+
+        assert_eq!(server.await.unwrap(), (COMMAND_CONNECT, target.into()));
+    }
+
+    #[tokio::test]
+    async fn refuse() {
+        let proxy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_endpoint = proxy.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = proxy.accept().await.unwrap();
+            let mut greeting = [0u8; 3];
+            stream.read_exact(&mut greeting).await.unwrap();
+            stream
+                .write_all(&[VERSION, METHOD_NO_AUTH, VERSION, 0x05, 0, ADDRESS_TYPE_IPV4])
+                .await
+                .unwrap();
+        });
+
+        let mut stream = TcpStream::connect(proxy_endpoint).await.unwrap();
+        let target = "127.0.0.1:8000".parse::<SocketAddr>().unwrap().into();
+        let error = connect(&mut stream, &target).await.unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ConnectionRefused);
+    }
+
+    #[tokio::test]
+    async fn udp_associate() {
+        let target: SocketAddr = "127.0.0.1:8000".parse().unwrap();
+        let proxy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_endpoint = proxy.local_addr().unwrap();
+        let relay = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let relay_port = relay.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = proxy.accept().await.unwrap();
+            // Reply with the unspecified address, meaning the proxy address.
+            let bound = SocketAddr::from((Ipv4Addr::UNSPECIFIED, relay_port)).into();
+            let request = serve_request(&mut stream, &bound).await;
+
+            // Echo the payload in reverse from the target.
+            let mut buffer = [0u8; 256];
+            let (size, endpoint) = relay.recv_from(&mut buffer).await.unwrap();
+            let (payload, destination) = decode_datagram(&buffer[..size]).unwrap().unwrap();
+            assert_eq!(destination, target.into());
+            let mut datagram = BytesMut::new();
+            datagram.put_slice(&[0, 0, 0]);
+            destination.encode(&mut datagram).unwrap();
+            datagram.extend(payload.iter().rev());
+            // A datagram from elsewhere is dropped.
+            let other = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            other.send_to(&datagram, endpoint).await.unwrap();
+            relay.send_to(&datagram, endpoint).await.unwrap();
+
+            // Keep the control connection open until the client closes it.
+            let mut buffer = [0u8; 1];
+            assert_eq!(stream.read(&mut buffer).await.unwrap(), 0);
+            request
+        });
+
+        let control = TcpStream::connect(proxy_endpoint).await.unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let association = UdpAssociation::new(control, socket).await.unwrap();
+        assert_eq!(
+            association.relay(),
+            SocketAddr::from((Ipv4Addr::LOCALHOST, relay_port)),
+        );
+
+        association.send_to(b"spam", &target.into()).await.unwrap();
+        let (payload, source) = association.recv_from().await.unwrap();
+        assert_eq!(payload, b"maps".as_slice());
+        assert_eq!(source, target.into());
+
+        drop(association);
+        assert_eq!(
+            server.await.unwrap(),
+            (
+                COMMAND_UDP_ASSOCIATE,
+                SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)).into(),
+            ),
+        );
+    }
+}
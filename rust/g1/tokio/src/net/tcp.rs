@@ -1,3 +1,8 @@
+#[cfg(target_os = "linux")]
+use std::io::Error;
+#[cfg(target_os = "linux")]
+use std::os::fd::AsFd;
+
 use bytes::BytesMut;
 use tokio::net;
 
@@ -15,6 +20,28 @@ pub type SendHalf<'a> = SendStream<net::tcp::WriteHalf<'a>, &'a mut BytesMut>;
 pub type OwnedRecvHalf = RecvStream<net::tcp::OwnedReadHalf, BytesMut>;
 pub type OwnedSendHalf = SendStream<net::tcp::OwnedWriteHalf, BytesMut>;
 
+/// TCP Socket Options
+///
+/// Options that are not set are left at the system defaults.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "param",
+    derive(serde::Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct TcpConfig {
+    pub nodelay: Option<bool>,
+    pub keepalive: Option<bool>,
+    /// Idle time in seconds before the first keepalive probe (`TCP_KEEPIDLE`).
+    pub keepalive_time: Option<u32>,
+    /// Interval in seconds between keepalive probes (`TCP_KEEPINTVL`).
+    pub keepalive_interval: Option<u32>,
+    /// Number of unacknowledged probes before the connection is dropped (`TCP_KEEPCNT`).
+    pub keepalive_probes: Option<u32>,
+    pub recv_buffer_size: Option<usize>,
+    pub send_buffer_size: Option<usize>,
+}
+
 impl From<net::TcpStream> for TcpStream {
     fn from(stream: net::TcpStream) -> Self {
         Self::new(stream)
@@ -27,6 +54,36 @@ impl TcpStream {
     }
 }
 
+#[cfg(target_os = "linux")]
+impl TcpConfig {
+    /// Applies the options to a socket.
+    ///
+    /// On Linux, sockets accepted from a listening socket inherit these options from it.
+    pub fn apply<F>(&self, socket: &F) -> Result<(), Error>
+    where
+        F: AsFd,
+    {
+        use nix::sys::socket::{setsockopt, sockopt};
+
+        macro_rules! set {
+            ($opt:expr, $value:expr $(,)?) => {
+                if let Some(value) = $value {
+                    setsockopt(socket, $opt, &value)?;
+                }
+            };
+        }
+
+        set!(sockopt::TcpNoDelay, self.nodelay);
+        set!(sockopt::KeepAlive, self.keepalive);
+        set!(sockopt::TcpKeepIdle, self.keepalive_time);
+        set!(sockopt::TcpKeepInterval, self.keepalive_interval);
+        set!(sockopt::TcpKeepCount, self.keepalive_probes);
+        set!(sockopt::RcvBuf, self.recv_buffer_size);
+        set!(sockopt::SndBuf, self.send_buffer_size);
+        Ok(())
+    }
+}
+
 impl StreamSplit for TcpStream {
     type RecvHalf<'a> = RecvHalf<'a>;
     type SendHalf<'a> = SendHalf<'a>;
@@ -34,8 +91,8 @@ impl StreamSplit for TcpStream {
     fn split(&mut self) -> (Self::RecvHalf<'_>, Self::SendHalf<'_>) {
         let (read_half, write_half) = self.stream.split();
         (
-            Self::RecvHalf::new(read_half, &mut self.recv_buffer),
-            Self::SendHalf::new(write_half, &mut self.send_buffer),
+            Self::RecvHalf::new(read_half, &mut self.recv_buffer, self.config),
+            Self::SendHalf::new(write_half, &mut self.send_buffer, self.config),
         )
     }
 }
@@ -47,8 +104,8 @@ impl StreamIntoSplit for TcpStream {
     fn into_split(self) -> (Self::OwnedRecvHalf, Self::OwnedSendHalf) {
         let (read_half, write_half) = self.stream.into_split();
         (
-            Self::OwnedRecvHalf::new(read_half, self.recv_buffer),
-            Self::OwnedSendHalf::new(write_half, self.send_buffer),
+            Self::OwnedRecvHalf::new(read_half, self.recv_buffer, self.config),
+            Self::OwnedSendHalf::new(write_half, self.send_buffer, self.config),
         )
     }
 
@@ -57,10 +114,15 @@ impl StreamIntoSplit for TcpStream {
         send: Self::OwnedSendHalf,
     ) -> Result<Self, (Self::OwnedRecvHalf, Self::OwnedSendHalf)> {
         match recv.stream.reunite(send.stream) {
-            Ok(stream) => Ok(Self::from_parts(stream, recv.buffer, send.buffer)),
+            Ok(stream) => Ok(Self::from_parts(
+                stream,
+                recv.buffer,
+                send.buffer,
+                recv.config,
+            )),
             Err(net::tcp::ReuniteError(read_half, write_half)) => Err((
-                Self::OwnedRecvHalf::new(read_half, recv.buffer),
-                Self::OwnedSendHalf::new(write_half, send.buffer),
+                Self::OwnedRecvHalf::new(read_half, recv.buffer, recv.config),
+                Self::OwnedSendHalf::new(write_half, send.buffer, send.config),
             )),
         }
     }
@@ -74,6 +136,8 @@ mod param {
     use serde::Deserialize;
     use tokio::net::{TcpListener, TcpSocket};
 
+    use super::TcpConfig;
+
     #[derive(Clone, Debug, Deserialize)]
     #[serde(default, deny_unknown_fields)]
     pub struct TcpListenerBuilder {
@@ -81,6 +145,8 @@ mod param {
         pub reuseaddr: Option<bool>,
         pub reuseport: Option<bool>,
         pub backlog: u32,
+        /// Options inherited by the accepted sockets.
+        pub config: TcpConfig,
     }
 
     impl Default for TcpListenerBuilder {
@@ -90,6 +156,7 @@ mod param {
                 reuseaddr: None,
                 reuseport: Some(true),
                 backlog: 1024,
+                config: TcpConfig::default(),
             }
         }
     }
@@ -109,6 +176,8 @@ mod param {
             if let Some(reuseport) = self.reuseport {
                 socket.set_reuseport(reuseport)?;
             }
+            #[cfg(target_os = "linux")]
+            self.config.apply(&socket)?;
 
             socket.bind(self.endpoint)?;
 
@@ -1,6 +1,9 @@
 use std::borrow::Borrow;
 use std::io::{Error, ErrorKind};
+use std::marker::PhantomData;
 use std::net::SocketAddr;
+#[cfg(target_os = "linux")]
+use std::os::fd::AsRawFd;
 use std::pin::Pin;
 use std::sync::{
     atomic::{AtomicBool, Ordering},
@@ -10,6 +13,8 @@ use std::task::{Context, Poll};
 
 use bytes::Bytes;
 use futures::{sink::Sink, stream::Stream};
+#[cfg(target_os = "linux")]
+use tokio::io::Interest;
 use tokio::{io::ReadBuf, net};
 
 use g1_base::task::WakerCell;
@@ -18,50 +23,185 @@ use g1_base::task::WakerCell;
 // jumbograms are used, but such datagrams are probably very rare in practice.
 const BUFFER_CAPACITY: usize = 65536;
 
+/// Item of the streams and sinks below.
+///
+/// It is either `(SocketAddr, Bytes)` or, for sockets created by `with_ecn`,
+/// `(SocketAddr, Bytes, Ecn)`.
+pub trait Datagram: Sized {
+    fn peer(&self) -> SocketAddr;
+
+    fn payload(&self) -> &Bytes;
+
+    fn poll_recv_from(
+        socket: &net::UdpSocket,
+        context: &mut Context<'_>,
+        buffer: &mut [u8],
+    ) -> Poll<Result<Self, Error>>;
+
+    fn poll_send_to(
+        &self,
+        socket: &net::UdpSocket,
+        context: &mut Context<'_>,
+    ) -> Poll<Result<usize, Error>>;
+}
+
+/// Explicit Congestion Notification (RFC 3168) codepoint of a datagram.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+#[repr(u8)]
+pub enum Ecn {
+    #[default]
+    NotEct = 0b00,
+    Ect1 = 0b01,
+    Ect0 = 0b10,
+    Ce = 0b11,
+}
+
 #[derive(Debug)]
-pub struct UdpSocket<Socket = net::UdpSocket>
+pub struct UdpSocket<Socket = net::UdpSocket, D = (SocketAddr, Bytes)>
 where
     Socket: Borrow<net::UdpSocket>,
 {
     socket: Socket,
     eof: AtomicBool,
     recv_buffer: Box<[u8]>,
-    send_item: Option<(SocketAddr, Bytes)>,
+    send_item: Option<D>,
     poll_next_waker: WakerCell,
 }
 
 #[derive(Debug)]
-pub struct UdpStream<'a> {
+pub struct UdpStream<'a, D = (SocketAddr, Bytes)> {
     socket: &'a net::UdpSocket,
     eof: &'a AtomicBool,
     recv_buffer: &'a mut [u8],
     poll_next_waker: &'a WakerCell,
+    _datagram: PhantomData<fn() -> D>,
 }
 
 #[derive(Debug)]
-pub struct UdpSink<'a> {
+pub struct UdpSink<'a, D = (SocketAddr, Bytes)> {
     socket: &'a net::UdpSocket,
     eof: &'a AtomicBool,
-    send_item: &'a mut Option<(SocketAddr, Bytes)>,
+    send_item: &'a mut Option<D>,
     poll_next_waker: &'a WakerCell,
 }
 
 #[derive(Debug)]
-pub struct OwnedUdpStream {
+pub struct OwnedUdpStream<D = (SocketAddr, Bytes)> {
     socket: Arc<net::UdpSocket>,
     eof: Arc<AtomicBool>,
     recv_buffer: Box<[u8]>,
     poll_next_waker: Arc<WakerCell>,
+    _datagram: PhantomData<fn() -> D>,
 }
 
 #[derive(Debug)]
-pub struct OwnedUdpSink {
+pub struct OwnedUdpSink<D = (SocketAddr, Bytes)> {
     socket: Arc<net::UdpSocket>,
     eof: Arc<AtomicBool>,
-    send_item: Option<(SocketAddr, Bytes)>,
+    send_item: Option<D>,
     poll_next_waker: Arc<WakerCell>,
 }
 
+impl Datagram for (SocketAddr, Bytes) {
+    fn peer(&self) -> SocketAddr {
+        self.0
+    }
+
+    fn payload(&self) -> &Bytes {
+        &self.1
+    }
+
+    fn poll_recv_from(
+        socket: &net::UdpSocket,
+        context: &mut Context<'_>,
+        buffer: &mut [u8],
+    ) -> Poll<Result<Self, Error>> {
+        let mut buffer = ReadBuf::new(buffer);
+        socket
+            .poll_recv_from(context, &mut buffer)
+            .map(|result| result.map(|peer| (peer, Bytes::copy_from_slice(buffer.filled()))))
+    }
+
+    fn poll_send_to(
+        &self,
+        socket: &net::UdpSocket,
+        context: &mut Context<'_>,
+    ) -> Poll<Result<usize, Error>> {
+        socket.poll_send_to(context, &self.1, self.0)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Datagram for (SocketAddr, Bytes, Ecn) {
+    fn peer(&self) -> SocketAddr {
+        self.0
+    }
+
+    fn payload(&self) -> &Bytes {
+        &self.1
+    }
+
+    fn poll_recv_from(
+        socket: &net::UdpSocket,
+        context: &mut Context<'_>,
+        buffer: &mut [u8],
+    ) -> Poll<Result<Self, Error>> {
+        loop {
+            if let Err(error) = std::task::ready!(socket.poll_recv_ready(context)) {
+                return Poll::Ready(Err(error));
+            }
+            match socket.try_io(Interest::READABLE, || {
+                g1_nix::sys::socket::recvmsg_tos(socket.as_raw_fd(), buffer).map_err(Error::from)
+            }) {
+                Ok((size, peer, tos)) => {
+                    return Poll::Ready(Ok((
+                        peer,
+                        Bytes::copy_from_slice(&buffer[..size]),
+                        tos.map_or(Ecn::NotEct, Ecn::from_tos),
+                    )));
+                }
+                Err(error) if error.kind() == ErrorKind::WouldBlock => continue,
+                Err(error) => return Poll::Ready(Err(error)),
+            }
+        }
+    }
+
+    fn poll_send_to(
+        &self,
+        socket: &net::UdpSocket,
+        context: &mut Context<'_>,
+    ) -> Poll<Result<usize, Error>> {
+        let (peer, payload, ecn) = self;
+        if *ecn == Ecn::NotEct {
+            return socket.poll_send_to(context, payload, *peer);
+        }
+        loop {
+            if let Err(error) = std::task::ready!(socket.poll_send_ready(context)) {
+                return Poll::Ready(Err(error));
+            }
+            match socket.try_io(Interest::WRITABLE, || {
+                g1_nix::sys::socket::sendmsg_tos(socket.as_raw_fd(), payload, *peer, *ecn as u8)
+                    .map_err(Error::from)
+            }) {
+                Err(error) if error.kind() == ErrorKind::WouldBlock => continue,
+                result => return Poll::Ready(result),
+            }
+        }
+    }
+}
+
+impl Ecn {
+    /// Extracts the codepoint from the IP TOS byte (or IPv6 traffic class).
+    pub fn from_tos(tos: u8) -> Self {
+        match tos & 0b11 {
+            0b00 => Self::NotEct,
+            0b01 => Self::Ect1,
+            0b10 => Self::Ect0,
+            _ => Self::Ce,
+        }
+    }
+}
+
 impl From<net::UdpSocket> for UdpSocket {
     fn from(socket: net::UdpSocket) -> Self {
         Self::new(socket)
@@ -79,6 +219,37 @@ where
     Socket: Borrow<net::UdpSocket>,
 {
     pub fn new(socket: Socket) -> Self {
+        Self::new_impl(socket)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl<Socket> UdpSocket<Socket, (SocketAddr, Bytes, Ecn)>
+where
+    Socket: Borrow<net::UdpSocket>,
+{
+    /// Creates a socket whose datagrams carry their ECN codepoints.
+    ///
+    /// It enables receiving the TOS byte (and the IPv6 traffic class) on `socket`.
+    pub fn with_ecn(socket: Socket) -> Result<Self, Error> {
+        use g1_nix::sys::socket::sockopt::{IpRecvTos, Ipv6RecvTclass};
+        use nix::sys::socket::setsockopt;
+
+        let udp_socket = socket.borrow();
+        // Datagrams from IPv4-mapped addresses carry `IP_TOS` even on IPv6 sockets.
+        setsockopt(udp_socket, IpRecvTos, &true)?;
+        if udp_socket.local_addr()?.is_ipv6() {
+            setsockopt(udp_socket, Ipv6RecvTclass, &true)?;
+        }
+        Ok(Self::new_impl(socket))
+    }
+}
+
+impl<Socket, D> UdpSocket<Socket, D>
+where
+    Socket: Borrow<net::UdpSocket>,
+{
+    fn new_impl(socket: Socket) -> Self {
         Self {
             socket,
             eof: AtomicBool::new(false),
@@ -94,13 +265,14 @@ where
 
     // NOTE: This method name conflicts with `StreamExt::split` (though I think `StreamExt::split`
     // should actually be named `into_split`).
-    pub fn split(&mut self) -> (UdpStream, UdpSink) {
+    pub fn split(&mut self) -> (UdpStream<'_, D>, UdpSink<'_, D>) {
         (
             UdpStream {
                 socket: self.socket.borrow(),
                 eof: &self.eof,
                 recv_buffer: &mut self.recv_buffer,
                 poll_next_waker: &self.poll_next_waker,
+                _datagram: PhantomData,
             },
             UdpSink {
                 socket: self.socket.borrow(),
@@ -112,8 +284,8 @@ where
     }
 }
 
-impl UdpSocket<net::UdpSocket> {
-    pub fn into_split(self) -> (OwnedUdpStream, OwnedUdpSink) {
+impl<D> UdpSocket<net::UdpSocket, D> {
+    pub fn into_split(self) -> (OwnedUdpStream<D>, OwnedUdpSink<D>) {
         UdpSocket {
             socket: Arc::new(self.socket),
             eof: self.eof,
@@ -125,8 +297,8 @@ impl UdpSocket<net::UdpSocket> {
     }
 }
 
-impl UdpSocket<Arc<net::UdpSocket>> {
-    pub fn into_split(self) -> (OwnedUdpStream, OwnedUdpSink) {
+impl<D> UdpSocket<Arc<net::UdpSocket>, D> {
+    pub fn into_split(self) -> (OwnedUdpStream<D>, OwnedUdpSink<D>) {
         let UdpSocket {
             socket,
             eof,
@@ -142,6 +314,7 @@ impl UdpSocket<Arc<net::UdpSocket>> {
                 eof: eof.clone(),
                 recv_buffer,
                 poll_next_waker: poll_next_waker.clone(),
+                _datagram: PhantomData,
             },
             OwnedUdpSink {
                 socket,
@@ -153,25 +326,25 @@ impl UdpSocket<Arc<net::UdpSocket>> {
     }
 }
 
-impl UdpStream<'_> {
+impl<D> UdpStream<'_, D> {
     pub fn socket(&self) -> &net::UdpSocket {
         self.socket
     }
 }
 
-impl UdpSink<'_> {
+impl<D> UdpSink<'_, D> {
     pub fn socket(&self) -> &net::UdpSocket {
         self.socket
     }
 }
 
-impl OwnedUdpStream {
+impl<D> OwnedUdpStream<D> {
     pub fn socket(&self) -> &net::UdpSocket {
         &self.socket
     }
 }
 
-impl OwnedUdpSink {
+impl<D> OwnedUdpSink<D> {
     pub fn socket(&self) -> &net::UdpSocket {
         &self.socket
     }
@@ -179,7 +352,7 @@ impl OwnedUdpSink {
 
 macro_rules! gen_stream_impl {
     () => {
-        type Item = Result<(SocketAddr, Bytes), Error>;
+        type Item = Result<D, Error>;
 
         fn poll_next(self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Option<Self::Item>> {
             let this = self.get_mut();
@@ -189,13 +362,7 @@ macro_rules! gen_stream_impl {
                 return Poll::Ready(None);
             }
 
-            let mut buffer = ReadBuf::new(&mut this.recv_buffer);
-            let poll = this
-                .socket
-                .poll_recv_from(context, &mut buffer)
-                .map(|result| {
-                    Some(result.map(|peer| (peer, Bytes::copy_from_slice(buffer.filled()))))
-                });
+            let poll = D::poll_recv_from(&this.socket, context, &mut this.recv_buffer).map(Some);
 
             if poll.is_pending() {
                 this.poll_next_waker.update(context);
@@ -208,15 +375,15 @@ macro_rules! gen_stream_impl {
     };
 }
 
-impl Stream for UdpSocket {
+impl<D: Datagram + Unpin> Stream for UdpSocket<net::UdpSocket, D> {
     gen_stream_impl!();
 }
 
-impl Stream for UdpStream<'_> {
+impl<D: Datagram> Stream for UdpStream<'_, D> {
     gen_stream_impl!();
 }
 
-impl Stream for OwnedUdpStream {
+impl<D: Datagram> Stream for OwnedUdpStream<D> {
     gen_stream_impl!();
 }
 
@@ -235,7 +402,7 @@ macro_rules! gen_sink_impl {
             poll_send_to(&this.socket, context, &mut this.send_item)
         }
 
-        fn start_send(self: Pin<&mut Self>, item: (SocketAddr, Bytes)) -> Result<(), Self::Error> {
+        fn start_send(self: Pin<&mut Self>, item: D) -> Result<(), Self::Error> {
             let _ = self.get_mut().send_item.insert(item);
             Ok(())
         }
@@ -274,22 +441,26 @@ macro_rules! gen_sink_impl {
     };
 }
 
-fn poll_send_to(
+fn poll_send_to<D>(
     socket: &net::UdpSocket,
     context: &mut Context<'_>,
-    send_item: &mut Option<(SocketAddr, Bytes)>,
-) -> Poll<Result<(), Error>> {
-    let Some((peer, payload)) = send_item else {
+    send_item: &mut Option<D>,
+) -> Poll<Result<(), Error>>
+where
+    D: Datagram,
+{
+    let Some(item) = send_item else {
         return Poll::Ready(Ok(()));
     };
-    let poll = socket.poll_send_to(context, payload, *peer).map(|result| {
+    let poll = item.poll_send_to(socket, context).map(|result| {
         result.and_then(|size| {
+            let payload = item.payload();
             if size == payload.len() {
                 Ok(())
             } else {
                 Err(Error::other(format!(
                     "only a partial payload is sent: {} actual={} expect={}",
-                    peer,
+                    item.peer(),
                     size,
                     payload.len(),
                 )))
@@ -302,15 +473,15 @@ fn poll_send_to(
     poll
 }
 
-impl Sink<(SocketAddr, Bytes)> for UdpSocket {
+impl<D: Datagram + Unpin> Sink<D> for UdpSocket<net::UdpSocket, D> {
     gen_sink_impl!();
 }
 
-impl Sink<(SocketAddr, Bytes)> for UdpSink<'_> {
+impl<D: Datagram> Sink<D> for UdpSink<'_, D> {
     gen_sink_impl!();
 }
 
-impl Sink<(SocketAddr, Bytes)> for OwnedUdpSink {
+impl<D: Datagram + Unpin> Sink<D> for OwnedUdpSink<D> {
     gen_sink_impl!();
 }
 
@@ -429,6 +600,29 @@ mod tests {
         test(sink, addr).await;
     }
 
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn ecn() {
+        assert_eq!(Ecn::from_tos(0xb8), Ecn::NotEct);
+        assert_eq!(Ecn::from_tos(0xb8 | 0b10), Ecn::Ect0);
+
+        let socket =
+            UdpSocket::with_ecn(net::UdpSocket::bind("127.0.0.1:0").await.unwrap()).unwrap();
+        let addr = socket.socket().local_addr().unwrap();
+        let (mut stream, mut sink) = socket.into_split();
+        for ecn in [Ecn::NotEct, Ecn::Ect1, Ecn::Ect0, Ecn::Ce] {
+            assert_matches!(
+                sink.send((addr, Bytes::from_static(b"spam"), ecn)).await,
+                Ok(()),
+            );
+            assert_matches!(
+                stream.next().await,
+                Some(Ok((peer, payload, this)))
+                if peer == addr && payload.as_ref() == b"spam" && this == ecn,
+            );
+        }
+    }
+
     #[tokio::test]
     async fn close_unblock_stream() {
         let mut socket = UdpSocket::new(net::UdpSocket::bind("127.0.0.1:0").await.unwrap());
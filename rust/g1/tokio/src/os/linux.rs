@@ -6,7 +6,6 @@ use nix::fcntl::{splice, OFlag, SpliceFFlags};
 use nix::sys::sendfile::sendfile;
 use nix::unistd::pipe2;
 use tokio::io::unix::AsyncFd;
-use tokio::task;
 
 use g1_nix::fcntl::StatusGuard;
 
@@ -85,23 +84,10 @@ where
         // stop promptly.  (There are race conditions if `splice_all` does not stop.)
         //
 
-        let cancel = Cancel::new();
-        let mut i_guard = JoinGuard::new(
-            {
-                let cancel = cancel.clone();
-                task::spawn_blocking(move || splice_all(cancel, &i, &w, count))
-            },
-            cancel,
-        );
-
-        let cancel = Cancel::new();
-        let mut o_guard = JoinGuard::new(
-            {
-                let cancel = cancel.clone();
-                task::spawn_blocking(move || splice_all(cancel, &r, &o, count))
-            },
-            cancel,
-        );
+        let mut i_guard =
+            JoinGuard::spawn_blocking(move |cancel| splice_all(cancel, &i, &w, count));
+        let mut o_guard =
+            JoinGuard::spawn_blocking(move |cancel| splice_all(cancel, &r, &o, count));
 
         tokio::try_join!(
             async {
@@ -0,0 +1,118 @@
+//! Channels that record their queue depth, send-blocked time, and receiver lag.
+//!
+//! NOTE: We do not have a metrics subsystem yet.  For now, the stats of the live channels are
+//! collected in a process-wide registry, and it is up to the caller to export them.
+
+pub mod mpsc;
+pub mod watch;
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use g1_base::sync::MutexExt;
+
+/// Snapshot of the stats of a channel.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ChannelStats {
+    /// Number of messages in the queue (`mpsc` only).
+    pub depth: usize,
+    pub max_depth: usize,
+    /// Number of messages (or `watch` updates) sent.
+    pub num_sent: u64,
+    /// Number of `watch` updates that are overwritten before a receiver sees them.
+    pub num_skipped: u64,
+    /// Total time that senders are blocked waiting for queue capacity.
+    pub send_blocked: Duration,
+    /// Time between sending and receiving the last message.
+    pub lag: Duration,
+    pub max_lag: Duration,
+}
+
+#[derive(Debug)]
+struct Counters {
+    depth: AtomicUsize,
+    max_depth: AtomicUsize,
+    num_sent: AtomicU64,
+    num_skipped: AtomicU64,
+    // Durations are stored in nanoseconds.
+    send_blocked: AtomicU64,
+    lag: AtomicU64,
+    max_lag: AtomicU64,
+}
+
+static REGISTRY: Mutex<Vec<(&'static str, Weak<Counters>)>> = Mutex::new(Vec::new());
+
+/// Returns the stats of the live channels.
+pub fn stats() -> Vec<(&'static str, ChannelStats)> {
+    let mut registry = REGISTRY.must_lock();
+    registry.retain(|(_, counters)| counters.strong_count() > 0);
+    registry
+        .iter()
+        .filter_map(|(name, counters)| Some((*name, counters.upgrade()?.snapshot())))
+        .collect()
+}
+
+impl Counters {
+    fn register(name: &'static str) -> Arc<Self> {
+        let this = Arc::new(Self {
+            depth: AtomicUsize::new(0),
+            max_depth: AtomicUsize::new(0),
+            num_sent: AtomicU64::new(0),
+            num_skipped: AtomicU64::new(0),
+            send_blocked: AtomicU64::new(0),
+            lag: AtomicU64::new(0),
+            max_lag: AtomicU64::new(0),
+        });
+        let mut registry = REGISTRY.must_lock();
+        registry.retain(|(_, counters)| counters.strong_count() > 0);
+        registry.push((name, Arc::downgrade(&this)));
+        this
+    }
+
+    fn snapshot(&self) -> ChannelStats {
+        ChannelStats {
+            depth: self.depth.load(Ordering::Relaxed),
+            max_depth: self.max_depth.load(Ordering::Relaxed),
+            num_sent: self.num_sent.load(Ordering::Relaxed),
+            num_skipped: self.num_skipped.load(Ordering::Relaxed),
+            send_blocked: Duration::from_nanos(self.send_blocked.load(Ordering::Relaxed)),
+            lag: Duration::from_nanos(self.lag.load(Ordering::Relaxed)),
+            max_lag: Duration::from_nanos(self.max_lag.load(Ordering::Relaxed)),
+        }
+    }
+
+    fn enqueue(&self) {
+        let depth = self.depth.fetch_add(1, Ordering::Relaxed) + 1;
+        self.max_depth.fetch_max(depth, Ordering::Relaxed);
+        self.num_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Reverts `enqueue` when the send fails.
+    fn unenqueue(&self) {
+        self.depth.fetch_sub(1, Ordering::Relaxed);
+        self.num_sent.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    fn dequeue(&self, send_at: Instant) {
+        self.depth.fetch_sub(1, Ordering::Relaxed);
+        self.record_lag(send_at);
+    }
+
+    fn record_send_blocked(&self, start: Instant) {
+        self.send_blocked
+            .fetch_add(to_nanos(start.elapsed()), Ordering::Relaxed);
+    }
+
+    fn record_lag(&self, send_at: Instant) {
+        let lag = to_nanos(send_at.elapsed());
+        self.lag.store(lag, Ordering::Relaxed);
+        self.max_lag.fetch_max(lag, Ordering::Relaxed);
+    }
+}
+
+fn to_nanos(duration: Duration) -> u64 {
+    u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX)
+}
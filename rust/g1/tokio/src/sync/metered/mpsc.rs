@@ -0,0 +1,261 @@
+use std::fmt;
+use std::sync::Arc;
+
+use tokio::sync::mpsc::{
+    self,
+    error::{SendError, TryRecvError, TrySendError},
+};
+use tokio::time::Instant;
+
+use super::{ChannelStats, Counters};
+
+pub struct Sender<T> {
+    send: mpsc::Sender<(T, Instant)>,
+    counters: Arc<Counters>,
+}
+
+pub struct Receiver<T> {
+    recv: mpsc::Receiver<(T, Instant)>,
+    counters: Arc<Counters>,
+}
+
+pub struct UnboundedSender<T> {
+    send: mpsc::UnboundedSender<(T, Instant)>,
+    counters: Arc<Counters>,
+}
+
+pub struct UnboundedReceiver<T> {
+    recv: mpsc::UnboundedReceiver<(T, Instant)>,
+    counters: Arc<Counters>,
+}
+
+// Implement `Debug` manually because deriving it would require `T: Debug`.
+macro_rules! impl_debug {
+    ($type:ident, $field:ident) => {
+        impl<T> fmt::Debug for $type<T> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.debug_struct(stringify!($type))
+                    .field(stringify!($field), &self.$field)
+                    .field("counters", &self.counters)
+                    .finish()
+            }
+        }
+    };
+}
+
+impl_debug!(Sender, send);
+impl_debug!(Receiver, recv);
+impl_debug!(UnboundedSender, send);
+impl_debug!(UnboundedReceiver, recv);
+
+pub fn channel<T>(name: &'static str, buffer: usize) -> (Sender<T>, Receiver<T>) {
+    let (send, recv) = mpsc::channel(buffer);
+    let counters = Counters::register(name);
+    (
+        Sender {
+            send,
+            counters: counters.clone(),
+        },
+        Receiver { recv, counters },
+    )
+}
+
+pub fn unbounded_channel<T>(name: &'static str) -> (UnboundedSender<T>, UnboundedReceiver<T>) {
+    let (send, recv) = mpsc::unbounded_channel();
+    let counters = Counters::register(name);
+    (
+        UnboundedSender {
+            send,
+            counters: counters.clone(),
+        },
+        UnboundedReceiver { recv, counters },
+    )
+}
+
+// Do not derive `Clone` because it would require `T: Clone`.
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            send: self.send.clone(),
+            counters: self.counters.clone(),
+        }
+    }
+}
+
+impl<T> Sender<T> {
+    pub async fn send(&self, value: T) -> Result<(), SendError<T>> {
+        let start = Instant::now();
+        let Ok(permit) = self.send.reserve().await else {
+            return Err(SendError(value));
+        };
+        self.counters.record_send_blocked(start);
+        // Update the counters before the receiver may observe the message.
+        self.counters.enqueue();
+        permit.send((value, Instant::now()));
+        Ok(())
+    }
+
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        match self.send.try_reserve() {
+            Ok(permit) => {
+                self.counters.enqueue();
+                permit.send((value, Instant::now()));
+                Ok(())
+            }
+            Err(TrySendError::Full(())) => Err(TrySendError::Full(value)),
+            Err(TrySendError::Closed(())) => Err(TrySendError::Closed(value)),
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.send.is_closed()
+    }
+
+    pub fn stats(&self) -> ChannelStats {
+        self.counters.snapshot()
+    }
+}
+
+impl<T> Receiver<T> {
+    pub async fn recv(&mut self) -> Option<T> {
+        let (value, send_at) = self.recv.recv().await?;
+        self.counters.dequeue(send_at);
+        Some(value)
+    }
+
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let (value, send_at) = self.recv.try_recv()?;
+        self.counters.dequeue(send_at);
+        Ok(value)
+    }
+
+    pub fn close(&mut self) {
+        self.recv.close();
+    }
+
+    pub fn stats(&self) -> ChannelStats {
+        self.counters.snapshot()
+    }
+}
+
+// Ditto.
+impl<T> Clone for UnboundedSender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            send: self.send.clone(),
+            counters: self.counters.clone(),
+        }
+    }
+}
+
+impl<T> UnboundedSender<T> {
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        // Ditto.
+        self.counters.enqueue();
+        self.send
+            .send((value, Instant::now()))
+            .map_err(|SendError((value, _))| {
+                self.counters.unenqueue();
+                SendError(value)
+            })
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.send.is_closed()
+    }
+
+    pub fn stats(&self) -> ChannelStats {
+        self.counters.snapshot()
+    }
+}
+
+impl<T> UnboundedReceiver<T> {
+    pub async fn recv(&mut self) -> Option<T> {
+        let (value, send_at) = self.recv.recv().await?;
+        self.counters.dequeue(send_at);
+        Some(value)
+    }
+
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let (value, send_at) = self.recv.try_recv()?;
+        self.counters.dequeue(send_at);
+        Ok(value)
+    }
+
+    pub fn close(&mut self) {
+        self.recv.close();
+    }
+
+    pub fn stats(&self) -> ChannelStats {
+        self.counters.snapshot()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time;
+
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn channel() {
+        let (send, mut recv) = super::channel("test_mpsc_channel", 2);
+        assert_eq!(send.stats(), ChannelStats::default());
+
+        send.send(1).await.unwrap();
+        send.try_send(2).unwrap();
+        assert_eq!(send.try_send(3), Err(TrySendError::Full(3)));
+        let stats = recv.stats();
+        assert_eq!(stats.depth, 2);
+        assert_eq!(stats.max_depth, 2);
+        assert_eq!(stats.num_sent, 2);
+
+        let blocked = tokio::spawn({
+            let send = send.clone();
+            async move { send.send(3).await }
+        });
+        time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(recv.recv().await, Some(1));
+        blocked.await.unwrap().unwrap();
+        time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(recv.try_recv(), Ok(2));
+        assert_eq!(recv.recv().await, Some(3));
+
+        let stats = send.stats();
+        assert_eq!(stats.depth, 0);
+        assert_eq!(stats.max_depth, 2);
+        assert_eq!(stats.num_sent, 3);
+        assert_eq!(stats.send_blocked, Duration::from_secs(1));
+        assert_eq!(stats.lag, Duration::from_secs(1));
+        assert_eq!(stats.max_lag, Duration::from_secs(2));
+
+        recv.close();
+        assert_eq!(send.send(4).await, Err(SendError(4)));
+        assert_eq!(send.stats().num_sent, 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn unbounded_channel() {
+        let (send, mut recv) = super::unbounded_channel("test_mpsc_unbounded_channel");
+        send.send(1).unwrap();
+        send.send(2).unwrap();
+        time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(recv.recv().await, Some(1));
+
+        let stats = send.stats();
+        assert_eq!(stats.depth, 1);
+        assert_eq!(stats.max_depth, 2);
+        assert_eq!(stats.num_sent, 2);
+        assert_eq!(stats.lag, Duration::from_secs(1));
+
+        assert!(super::super::stats()
+            .iter()
+            .any(|(name, stats)| *name == "test_mpsc_unbounded_channel" && stats.depth == 1));
+
+        drop(recv);
+        assert_eq!(send.send(3), Err(SendError(3)));
+        assert_eq!(send.stats().depth, 1);
+    }
+}
@@ -0,0 +1,215 @@
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::watch::{
+    self,
+    error::{RecvError, SendError},
+    Ref,
+};
+use tokio::time::Instant;
+
+use g1_base::sync::MutexExt;
+
+use crate::sync::watch::Update;
+
+use super::{ChannelStats, Counters};
+
+#[derive(Debug)]
+pub struct Sender<T> {
+    send: watch::Sender<T>,
+    state: Arc<State>,
+}
+
+#[derive(Debug)]
+pub struct Receiver<T> {
+    recv: watch::Receiver<T>,
+    state: Arc<State>,
+    // The value of `num_sent` when the receiver last marked the value as seen.
+    seen: u64,
+}
+
+#[derive(Debug)]
+struct State {
+    counters: Arc<Counters>,
+    send_at: Mutex<Instant>,
+}
+
+pub fn channel<T>(name: &'static str, init: T) -> (Sender<T>, Receiver<T>) {
+    let (send, recv) = watch::channel(init);
+    let state = Arc::new(State {
+        counters: Counters::register(name),
+        send_at: Mutex::new(Instant::now()),
+    });
+    (
+        Sender {
+            send,
+            state: state.clone(),
+        },
+        Receiver {
+            recv,
+            state,
+            seen: 0,
+        },
+    )
+}
+
+impl<T> Sender<T> {
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        if self.send.is_closed() {
+            return Err(SendError(value));
+        }
+        self.send_modify(|current| *current = value);
+        Ok(())
+    }
+
+    pub fn send_modify<F>(&self, modify: F)
+    where
+        F: FnOnce(&mut T),
+    {
+        self.send_if_modified(|current| {
+            modify(current);
+            true
+        });
+    }
+
+    pub fn send_if_modified<F>(&self, modify: F) -> bool
+    where
+        F: FnOnce(&mut T) -> bool,
+    {
+        self.send.send_if_modified(|current| {
+            let modified = modify(current);
+            // Update the counters while we are still holding the write lock so that receivers
+            // observe them together with the value.
+            if modified {
+                self.state.counters.num_sent.fetch_add(1, Ordering::Relaxed);
+                *self.state.send_at.must_lock() = Instant::now();
+            }
+            modified
+        })
+    }
+
+    pub fn borrow(&self) -> Ref<'_, T> {
+        self.send.borrow()
+    }
+
+    pub fn subscribe(&self) -> Receiver<T> {
+        let recv = self.send.subscribe();
+        // `subscribe` marks the current value as seen.
+        let seen = self.state.counters.num_sent.load(Ordering::Relaxed);
+        Receiver {
+            recv,
+            state: self.state.clone(),
+            seen,
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.send.is_closed()
+    }
+
+    pub fn stats(&self) -> ChannelStats {
+        self.state.counters.snapshot()
+    }
+}
+
+impl<T> Update<T> for Sender<T>
+where
+    T: PartialEq,
+{
+    fn update(&self, value: T) -> bool {
+        self.send_if_modified(move |current| {
+            if &value == current {
+                false
+            } else {
+                *current = value;
+                true
+            }
+        })
+    }
+}
+
+// Do not derive `Clone` because it would require `T: Clone`.
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        Self {
+            recv: self.recv.clone(),
+            state: self.state.clone(),
+            seen: self.seen,
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    pub async fn changed(&mut self) -> Result<(), RecvError> {
+        self.recv.changed().await
+    }
+
+    pub fn has_changed(&self) -> Result<bool, RecvError> {
+        self.recv.has_changed()
+    }
+
+    pub fn borrow(&self) -> Ref<'_, T> {
+        self.recv.borrow()
+    }
+
+    pub fn borrow_and_update(&mut self) -> Ref<'_, T> {
+        let value = self.recv.borrow_and_update();
+        // Holding `value` blocks the sender, and thus `num_sent` cannot change here.
+        let num_sent = self.state.counters.num_sent.load(Ordering::Relaxed);
+        if num_sent > self.seen {
+            let counters = &self.state.counters;
+            counters
+                .num_skipped
+                .fetch_add(num_sent - self.seen - 1, Ordering::Relaxed);
+            counters.record_lag(*self.state.send_at.must_lock());
+            self.seen = num_sent;
+        }
+        value
+    }
+
+    pub fn stats(&self) -> ChannelStats {
+        self.state.counters.snapshot()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time;
+
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn channel() {
+        let (send, mut recv) = super::channel("test_watch_channel", 0);
+        assert_eq!(*recv.borrow_and_update(), 0);
+        assert_eq!(send.stats(), ChannelStats::default());
+
+        send.send(1).unwrap();
+        assert_eq!(send.update(2), true);
+        assert_eq!(send.update(2), false);
+        time::sleep(Duration::from_secs(1)).await;
+        recv.changed().await.unwrap();
+        assert_eq!(*recv.borrow_and_update(), 2);
+
+        let stats = recv.stats();
+        assert_eq!(stats.num_sent, 2);
+        assert_eq!(stats.num_skipped, 1);
+        assert_eq!(stats.lag, Duration::from_secs(1));
+
+        let mut other = send.subscribe();
+        send.send_modify(|value| *value += 1);
+        assert_eq!(*other.borrow_and_update(), 3);
+        assert_eq!(*recv.borrow_and_update(), 3);
+        let stats = send.stats();
+        assert_eq!(stats.num_sent, 3);
+        assert_eq!(stats.num_skipped, 1);
+        assert_eq!(stats.lag, Duration::ZERO);
+        assert_eq!(stats.max_lag, Duration::from_secs(1));
+
+        drop(recv);
+        drop(other);
+        assert_eq!(send.send(4), Err(SendError(4)));
+    }
+}
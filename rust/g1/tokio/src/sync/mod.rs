@@ -1,4 +1,5 @@
 pub mod bucket;
+pub mod metered;
 pub mod mpmc;
 pub mod oneway;
 pub mod watch;
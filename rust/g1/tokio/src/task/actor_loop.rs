@@ -0,0 +1,105 @@
+/// Runs the common actor loop pattern.
+///
+/// In each iteration, it runs `before_select` (if given) and then selects over `cancel` and the
+/// given branches.  Once a branch breaks out of the loop or returns an error (via `?` or
+/// `return Err(...)`), it logs the exit reason and then runs `cleanup` regardless of how the loop
+/// exited.  It evaluates to `Result<LoopExit, $error>`.
+///
+/// A branch breaks out of the loop with `break Ok(LoopExit::Closed("name"))`.
+///
+/// NOTE: The expanded code refers to the `tokio` and `tracing` crates, which the caller must
+/// depend on.
+#[macro_export]
+macro_rules! actor_loop {
+    (
+        cancel: $cancel:expr,
+        error: $error:ty,
+        $(before_select { $($before_select:tt)* })?
+        select { $($branches:tt)* }
+        cleanup { $($cleanup:tt)* } $(,)?
+    ) => {{
+        let result: ::std::result::Result<$crate::task::LoopExit, $error> = async {
+            loop {
+                $({ $($before_select)* })?
+                ::tokio::select! {
+                    () = $cancel.wait() => break Ok($crate::task::LoopExit::Cancel),
+                    $($branches)*
+                }
+            }
+        }
+        .await;
+        match &result {
+            Ok(exit) => ::tracing::debug!(?exit, "actor loop exit"),
+            Err(error) => ::tracing::warn!(%error, "actor loop exit"),
+        }
+        { $($cleanup)* }
+        result
+    }};
+}
+
+/// Why an actor loop exited, excluding errors.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LoopExit {
+    Cancel,
+    /// The named input was closed.
+    Closed(&'static str),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Error, ErrorKind};
+
+    use tokio::sync::mpsc;
+
+    use crate::task::Cancel;
+
+    use super::*;
+
+    async fn run(
+        cancel: Cancel,
+        mut recv: mpsc::Receiver<Result<(), Error>>,
+    ) -> (Result<LoopExit, Error>, Vec<&'static str>) {
+        let mut log = Vec::new();
+        let result = crate::actor_loop! {
+            cancel: cancel,
+            error: Error,
+            select {
+                message = recv.recv() => {
+                    let Some(message) = message else { break Ok(LoopExit::Closed("recv")) };
+                    message?;
+                    log.push("message");
+                }
+            }
+            cleanup {
+                log.push("cleanup");
+            }
+        };
+        (result, log)
+    }
+
+    #[tokio::test]
+    async fn actor_loop() {
+        let cancel = Cancel::new();
+        let (send, recv) = mpsc::channel(4);
+        send.send(Ok(())).await.unwrap();
+        let task = tokio::spawn(run(cancel.clone(), recv));
+        tokio::task::yield_now().await;
+        cancel.set();
+        let (result, log) = task.await.unwrap();
+        assert_eq!(result.unwrap(), LoopExit::Cancel);
+        assert_eq!(log, ["message", "cleanup"]);
+
+        let (send, recv) = mpsc::channel(4);
+        send.send(Ok(())).await.unwrap();
+        drop(send);
+        let (result, log) = run(Cancel::new(), recv).await;
+        assert_eq!(result.unwrap(), LoopExit::Closed("recv"));
+        assert_eq!(log, ["message", "cleanup"]);
+
+        let (send, recv) = mpsc::channel(4);
+        send.send(Err(Error::other("test"))).await.unwrap();
+        let (result, log) = run(Cancel::new(), recv).await;
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::Other);
+        assert_eq!(log, ["cleanup"]);
+    }
+}
@@ -11,7 +11,7 @@ use std::time::Duration;
 #[cfg(tokio_unstable)]
 use tokio::task::Id;
 use tokio::{
-    task::{JoinError, JoinHandle},
+    task::{self, JoinError, JoinHandle},
     time::{self, Instant},
 };
 
@@ -78,6 +78,23 @@ impl<T> JoinGuard<T> {
         Self::new(handle, cancel)
     }
 
+    /// Spawns a blocking task on the blocking thread pool.
+    ///
+    /// NOTE: A blocking task cannot be aborted once it has started.  Dropping the guard only sets
+    /// `cancel`, and it is the responsibility of `f` to check `cancel` periodically.
+    pub fn spawn_blocking<F>(f: F) -> Self
+    where
+        F: FnOnce(Cancel) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let cancel = Cancel::new();
+        let handle = task::spawn_blocking({
+            let cancel = cancel.clone();
+            move || f(cancel)
+        });
+        Self::new(handle, cancel)
+    }
+
     pub fn new(handle: JoinHandle<T>, cancel: Cancel) -> Self {
         Self {
             handle,
@@ -356,6 +373,24 @@ mod tests {
         assert!(matches!(guard.result, Some(Ok(42))));
     }
 
+    #[tokio::test]
+    async fn spawn_blocking() {
+        let mut guard = JoinGuard::spawn_blocking(|cancel| {
+            let mut n = 0;
+            while !cancel.is_set() {
+                std::thread::sleep(Duration::from_millis(1));
+                n += 1;
+            }
+            n
+        });
+        guard.add_timeout(Duration::from_millis(10));
+        guard.join().await;
+        assert_ne!(guard.take_result().unwrap(), 0);
+
+        let mut guard = JoinGuard::spawn_blocking(|_| 42);
+        assert_eq!(guard.shutdown().await, Ok(42));
+    }
+
     #[tokio::test]
     async fn shutdown() {
         let (mut parent, mock) = spawn_tasks();
@@ -1,8 +1,10 @@
+mod actor_loop;
 mod join_array;
 mod join_guard;
 mod join_queue;
 mod joiner;
 
+pub use self::actor_loop::LoopExit;
 pub use self::join_array::JoinArray;
 pub use self::join_guard::{Cancel, JoinGuard, ShutdownError};
 pub use self::join_queue::JoinQueue;
@@ -3,7 +3,8 @@
 //!
 //! [client]: https://zeromq.org/socket-api/#client-server-pattern
 
-use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fmt;
 use std::io::{Error, ErrorKind};
 use std::sync::Arc;
 use std::time::Duration;
@@ -14,20 +15,23 @@ use futures::stream::StreamExt;
 use tokio::sync::mpsc;
 use tokio::sync::oneshot;
 
+use g1_base::fmt::{DebugExt, InsertPlaceholder};
 use g1_tokio::task::{Cancel, JoinGuard};
-use g1_tokio::time::queue::naive::FixedDelayQueue;
 
 use crate::duplex::Duplex;
-use crate::envelope::{Envelope, Frame, Multipart};
+use crate::envelope::{Frame, Multipart};
+use crate::rpc::{self, Codec, ResponseError, ResponseSends};
 use crate::Socket;
 
 #[cfg(feature = "param")]
 mod param {
+    use std::fmt;
     use std::io::Error;
     use std::time::Duration;
 
     use serde::Deserialize;
 
+    use crate::rpc::Codec;
     use crate::{Dealer, SocketBuilder};
 
     use super::{Client, ClientGuard};
@@ -59,7 +63,13 @@ mod param {
             self
         }
 
-        pub fn build(&self, context: &zmq::Context) -> Result<(Client, ClientGuard), Error> {
+        pub fn build<C>(&self, context: &zmq::Context) -> Result<(Client<C>, ClientGuard), Error>
+        where
+            C: Codec + 'static,
+            C::Request: fmt::Debug + Send + 'static,
+            C::Response: fmt::Debug + Send + 'static,
+            C::Error: std::error::Error + Send + Sync + 'static,
+        {
             if self.socket.bind.is_empty() && self.socket.connect.is_empty() {
                 // When a `DEALER` socket has no peers, `send` will block.  This is probably not
                 // what you want, so we return an error for now.
@@ -95,35 +105,77 @@ mod param {
 #[cfg(feature = "param")]
 pub use self::param::ClientBuilder;
 
-#[derive(Clone, Debug)]
-pub struct Client {
+#[derive(Debug)]
+pub struct Client<C = Raw>
+where
+    C: Codec,
+{
     bind_endpoints: Arc<[String]>,
-    request_send: RequestSend,
+    request_send: RequestSend<C>,
 }
 
 // For convenience, we make `Actor::run` return `Result<(), Error>`.
 pub type ClientGuard = JoinGuard<Result<(), Error>>;
 
+/// Passes the data frames of requests and responses through as is.
 #[derive(Debug)]
-struct Actor {
+pub enum Raw {}
+
+#[derive(DebugExt)]
+struct Actor<C>
+where
+    C: Codec,
+{
     cancel: Cancel,
     duplex: Duplex,
-    request_recv: RequestRecv,
-    response_sends: HashMap<RoutingId, ResponseSend>,
-    deadlines: FixedDelayQueue<RoutingId>,
+    #[debug(with = InsertPlaceholder)]
+    request_recv: RequestRecv<C>,
+    #[debug(with = InsertPlaceholder)]
+    response_sends: ResponseSends<Response<C>>,
 }
 
-type RoutingId = u64;
+type Request<C> = (<C as Codec>::Request, ResponseSend<C>);
+type Response<C> = Result<<C as Codec>::Response, Error>;
 
-type Request = (Bytes, ResponseSend);
-type Response = Result<Bytes, Error>;
+type RequestRecv<C> = mpsc::Receiver<Request<C>>;
+type RequestSend<C> = mpsc::Sender<Request<C>>;
+
+type ResponseSend<C> = oneshot::Sender<Response<C>>;
+
+impl Codec for Raw {
+    type Request = Bytes;
+    type Response = Bytes;
+    type Error = Infallible;
+
+    fn encode_request(request: Self::Request) -> Frame {
+        Frame::from(<Vec<u8>>::from(request))
+    }
 
-type RequestRecv = mpsc::Receiver<Request>;
-type RequestSend = mpsc::Sender<Request>;
+    fn decode_response(response: Frame) -> Result<Self::Response, Self::Error> {
+        Ok(response.to_vec().into())
+    }
+}
 
-type ResponseSend = oneshot::Sender<Response>;
+// We implement `Clone` by hand because `derive` would require `C: Clone`.
+impl<C> Clone for Client<C>
+where
+    C: Codec,
+{
+    fn clone(&self) -> Self {
+        Self {
+            bind_endpoints: self.bind_endpoints.clone(),
+            request_send: self.request_send.clone(),
+        }
+    }
+}
 
-impl Client {
+impl<C> Client<C>
+where
+    C: Codec + 'static,
+    C::Request: fmt::Debug + Send + 'static,
+    C::Response: fmt::Debug + Send + 'static,
+    C::Error: std::error::Error + Send + Sync + 'static,
+{
     fn spawn(
         socket: Socket,
         bind_endpoints: Vec<String>,
@@ -131,7 +183,7 @@ impl Client {
     ) -> (Self, ClientGuard) {
         let (request_send, request_recv) = mpsc::channel(32);
         let guard = ClientGuard::spawn(move |cancel| {
-            Actor::new(cancel, socket, request_recv, timeout).run()
+            Actor::<C>::new(cancel, socket, request_recv, timeout).run()
         });
         (
             Self {
@@ -146,7 +198,7 @@ impl Client {
         &self.bind_endpoints
     }
 
-    pub async fn request(&self, request: Bytes) -> Result<Bytes, Error> {
+    pub async fn request(&self, request: C::Request) -> Result<C::Response, Error> {
         fn stopped() -> Error {
             Error::other("client task stopped")
         }
@@ -160,38 +212,27 @@ impl Client {
     }
 }
 
-impl Actor {
-    fn new(cancel: Cancel, socket: Socket, request_recv: RequestRecv, timeout: Duration) -> Self {
+impl<C> Actor<C>
+where
+    C: Codec + 'static,
+    C::Request: fmt::Debug + Send + 'static,
+    C::Response: fmt::Debug + Send + 'static,
+    C::Error: std::error::Error + Send + Sync + 'static,
+{
+    fn new(
+        cancel: Cancel,
+        socket: Socket,
+        request_recv: RequestRecv<C>,
+        timeout: Duration,
+    ) -> Self {
         Self {
             cancel,
             duplex: socket.into(),
             request_recv,
-            response_sends: HashMap::new(),
-            deadlines: FixedDelayQueue::new(timeout),
+            response_sends: ResponseSends::new(timeout),
         }
     }
 
-    fn next_routing_id(&self) -> RoutingId {
-        for _ in 0..4 {
-            let routing_id = rand::random();
-            // It is a small detail, but we do not generate 0.
-            if routing_id != 0 && !self.response_sends.contains_key(&routing_id) {
-                return routing_id;
-            }
-        }
-        std::panic!("cannot generate random routing id")
-    }
-
-    fn insert(&mut self, response_send: ResponseSend) -> RoutingId {
-        let routing_id = self.next_routing_id();
-        assert!(self
-            .response_sends
-            .insert(routing_id, response_send)
-            .is_none());
-        self.deadlines.push(routing_id);
-        routing_id
-    }
-
     async fn run(mut self) -> Result<(), Error> {
         loop {
             tokio::select! {
@@ -211,62 +252,54 @@ impl Actor {
                     }
                 }
 
-                Some(routing_id) = self.deadlines.pop() => {
-                    self.handle_timeout(routing_id);
+                Some((routing_id, response_send)) = self.response_sends.expire() => {
+                    if response_send.is_closed() {
+                        tracing::trace!(routing_id, "cancel");
+                        continue;
+                    }
+                    let _ = response_send.send(Err(Error::new(
+                        ErrorKind::TimedOut,
+                        "request timeout",
+                    )));
                 }
             }
         }
         Ok(())
     }
 
-    async fn handle_request(&mut self, (request, response_send): Request) {
-        let routing_id = self.insert(response_send);
+    async fn handle_request(&mut self, (request, response_send): Request<C>) {
+        let routing_id = self.response_sends.insert(response_send);
         tracing::trace!(routing_id, ?request);
-        let request = Envelope::new(
-            vec![Frame::from(routing_id.to_be_bytes().as_slice())],
-            Frame::from(<Vec<u8>>::from(request)),
-        );
+        let request = rpc::encode_request::<C>(routing_id, request);
         // We assume that this error is transient and do not exit.
         // TODO: Should we re-send the request?
-        if let Err(error) = self.duplex.send(request.into()).await {
+        if let Err(error) = self.duplex.send(request).await {
             let _ = self
                 .response_sends
-                .remove(&routing_id)
+                .remove(routing_id)
                 .expect("response_send")
                 .send(Err(error));
         }
     }
 
     fn handle_response(&mut self, frames: Multipart) {
-        let envelope = match <Envelope<Frame>>::try_from(frames) {
-            Ok(envelope) => envelope,
-            Err(frames) => {
-                tracing::warn!(?frames, "invalid frames");
+        let (routing_id, response) = match rpc::decode_response::<C>(frames) {
+            Ok((routing_id, response)) => (routing_id, Ok(response)),
+            Err(ResponseError::Decode { routing_id, source }) => {
+                (routing_id, Err(Error::new(ErrorKind::InvalidData, source)))
+            }
+            Err(error) => {
+                tracing::warn!(?error, "invalid response");
                 return;
             }
         };
-
-        let routing_id = envelope.routing_id();
-        if !(routing_id.len() == 1 && routing_id[0].len() == 8) {
-            tracing::warn!(?envelope, "invalid routing id");
-            return;
-        }
-        let routing_id = RoutingId::from_be_bytes((*routing_id[0]).try_into().expect("routing_id"));
-
-        let (_, response) = envelope.unwrap();
         tracing::trace!(routing_id, ?response);
 
-        let Some(response_send) = self.response_sends.remove(&routing_id) else {
+        let Some(response_send) = self.response_sends.remove(routing_id) else {
             tracing::debug!(routing_id, "response_send not found");
             return;
         };
 
-        let _ = response_send.send(Ok(response.to_vec().into()));
-    }
-
-    fn handle_timeout(&mut self, routing_id: RoutingId) {
-        if let Some(response_send) = self.response_sends.remove(&routing_id) {
-            let _ = response_send.send(Err(Error::new(ErrorKind::TimedOut, "request timeout")));
-        }
+        let _ = response_send.send(response);
     }
 }
@@ -38,6 +38,10 @@ impl Duplex {
         }
     }
 
+    pub fn get_mut(&mut self) -> &mut Socket {
+        &mut self.socket
+    }
+
     pub fn into_socket(self) -> Socket {
         self.socket
     }
@@ -0,0 +1,300 @@
+//! Endpoint Failover
+//!
+//! ZeroMQ reconnects a socket to its endpoints transparently, which is usually what we want, but
+//! it does not help when an endpoint is unreachable from our end while the same peer is reachable
+//! via another endpoint.  `Failover` connects a socket to one endpoint at a time, in priority
+//! order, and moves on to the next endpoint when the current one fails to connect too many times
+//! in a row.  It does not fail back to a higher-priority endpoint on its own.
+
+use std::io::Error;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use futures::stream::StreamExt;
+use zmq::{Message, SocketEvent, PAIR};
+
+use crate::duplex::Duplex;
+use crate::Socket;
+
+#[derive(Debug)]
+pub struct Failover {
+    monitor: Duplex,
+    endpoints: Arc<[String]>,
+    current: usize,
+    num_retries: usize,
+    max_retries: usize,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Event {
+    Connected(String),
+    Disconnected(String),
+    Retried {
+        endpoint: String,
+        num_retries: usize,
+    },
+    Failover {
+        from: String,
+        to: String,
+    },
+}
+
+/// Event that is read from the socket monitor.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MonitorEvent {
+    pub event: SocketEvent,
+    pub value: u32,
+    pub endpoint: String,
+}
+
+const MONITOR_EVENTS: [SocketEvent; 3] = [
+    SocketEvent::CONNECTED,
+    SocketEvent::CONNECT_RETRIED,
+    SocketEvent::DISCONNECTED,
+];
+
+impl Failover {
+    /// Connects the socket to the first endpoint.
+    ///
+    /// The socket must not be monitored by anyone else, as ZeroMQ supports only one monitor per
+    /// socket.
+    pub fn connect(
+        context: &zmq::Context,
+        socket: &mut Socket,
+        endpoints: Vec<String>,
+        max_retries: usize,
+    ) -> Result<Self, Error> {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+        if endpoints.is_empty() {
+            return Err(Error::other("no endpoints to connect to"));
+        }
+
+        let monitor_endpoint = format!(
+            "inproc://g1_zmq.failover.{}",
+            NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        );
+        let events = MONITOR_EVENTS
+            .iter()
+            .fold(0, |events, event| events | i32::from(event.to_raw()));
+        socket.monitor(&monitor_endpoint, events)?;
+        let mut monitor = Socket::try_from(context.socket(PAIR)?)?;
+        monitor.set_linger(0)?;
+        monitor.connect(&monitor_endpoint)?;
+
+        socket.connect(&endpoints[0])?;
+
+        Ok(Self {
+            monitor: monitor.into(),
+            endpoints: endpoints.into(),
+            current: 0,
+            num_retries: 0,
+            max_retries,
+        })
+    }
+
+    pub fn endpoints(&self) -> &[String] {
+        &self.endpoints
+    }
+
+    pub fn current(&self) -> &str {
+        &self.endpoints[self.current]
+    }
+
+    /// Receives the next event from the socket monitor.
+    ///
+    /// It is cancel safe.
+    pub async fn recv(&mut self) -> Result<MonitorEvent, Error> {
+        let frames = self
+            .monitor
+            .next()
+            .await
+            .ok_or_else(|| Error::other("socket monitor closed"))??;
+        MonitorEvent::decode(&frames).ok_or_else(|| Error::other("invalid socket monitor event"))
+    }
+
+    /// Handles a monitor event, and fails over to the next endpoint when necessary.
+    ///
+    /// Note that after a failover, the monitor may still report a few stale events of the previous
+    /// endpoint, which are counted against the current endpoint.
+    pub fn handle(
+        &mut self,
+        socket: &mut Socket,
+        event: MonitorEvent,
+    ) -> Result<Option<Event>, Error> {
+        Ok(Some(match event.event {
+            SocketEvent::CONNECTED => {
+                self.num_retries = 0;
+                Event::Connected(event.endpoint)
+            }
+            SocketEvent::DISCONNECTED => Event::Disconnected(event.endpoint),
+            SocketEvent::CONNECT_RETRIED => {
+                self.num_retries += 1;
+                if self.num_retries <= self.max_retries || self.endpoints.len() == 1 {
+                    Event::Retried {
+                        endpoint: event.endpoint,
+                        num_retries: self.num_retries,
+                    }
+                } else {
+                    self.failover(socket)?
+                }
+            }
+            _ => return Ok(None),
+        }))
+    }
+
+    fn failover(&mut self, socket: &mut Socket) -> Result<Event, Error> {
+        let from = self.current().to_string();
+        match socket.disconnect(&from) {
+            // The endpoint might have been disconnected already.
+            Ok(()) | Err(zmq::Error::ENOENT) => {}
+            Err(error) => return Err(error.into()),
+        }
+
+        self.current = (self.current + 1) % self.endpoints.len();
+        self.num_retries = 0;
+        let to = self.current().to_string();
+        socket.connect(&to)?;
+
+        Ok(Event::Failover { from, to })
+    }
+}
+
+impl MonitorEvent {
+    /// Decodes a monitor event, which is made up of two frames: the event id (`u16`) and value
+    /// (`u32`) in native byte order, and the endpoint.
+    pub fn decode(frames: &[Message]) -> Option<Self> {
+        let [event, endpoint] = frames else {
+            return None;
+        };
+        let (event, value) = event.split_first_chunk::<2>()?;
+        let value = <[u8; 4]>::try_from(value).ok()?;
+        Some(Self {
+            event: SocketEvent::from_raw(u16::from_ne_bytes(*event)),
+            value: u32::from_ne_bytes(value),
+            endpoint: endpoint.as_str()?.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zmq::{Context, DEALER};
+
+    use super::*;
+
+    fn e(event: SocketEvent, endpoint: &str) -> MonitorEvent {
+        MonitorEvent {
+            event,
+            value: 0,
+            endpoint: endpoint.to_string(),
+        }
+    }
+
+    fn m(event: SocketEvent, value: u32, endpoint: &str) -> Vec<Message> {
+        let mut frame = event.to_raw().to_ne_bytes().to_vec();
+        frame.extend(value.to_ne_bytes());
+        vec![frame.into(), endpoint.into()]
+    }
+
+    #[tokio::test]
+    async fn failover() -> Result<(), Error> {
+        let context = Context::new();
+        let mut socket = Socket::try_from(context.socket(DEALER)?)?;
+        socket.set_linger(0)?;
+
+        let a = format!("inproc://{}.a", std::module_path!());
+        let b = format!("inproc://{}.b", std::module_path!());
+        let mut failover = Failover::connect(&context, &mut socket, vec![a.clone(), b.clone()], 1)?;
+        assert_eq!(failover.endpoints(), [a.clone(), b.clone()]);
+        assert_eq!(failover.current(), a);
+
+        assert_eq!(
+            failover.handle(&mut socket, e(SocketEvent::CONNECT_RETRIED, &a))?,
+            Some(Event::Retried {
+                endpoint: a.clone(),
+                num_retries: 1,
+            }),
+        );
+        assert_eq!(
+            failover.handle(&mut socket, e(SocketEvent::CONNECT_RETRIED, &a))?,
+            Some(Event::Failover {
+                from: a.clone(),
+                to: b.clone(),
+            }),
+        );
+        assert_eq!(failover.current(), b);
+
+        assert_eq!(
+            failover.handle(&mut socket, e(SocketEvent::CONNECTED, &b))?,
+            Some(Event::Connected(b.clone())),
+        );
+        assert_eq!(
+            failover.handle(&mut socket, e(SocketEvent::DISCONNECTED, &b))?,
+            Some(Event::Disconnected(b.clone())),
+        );
+        assert_eq!(
+            failover.handle(&mut socket, e(SocketEvent::CONNECT_RETRIED, &b))?,
+            Some(Event::Retried {
+                endpoint: b.clone(),
+                num_retries: 1,
+            }),
+        );
+        assert_eq!(
+            failover.handle(&mut socket, e(SocketEvent::CONNECT_RETRIED, &b))?,
+            Some(Event::Failover {
+                from: b.clone(),
+                to: a.clone(),
+            }),
+        );
+        assert_eq!(failover.current(), a);
+
+        assert_eq!(
+            failover.handle(&mut socket, e(SocketEvent::LISTENING, &a))?,
+            None,
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn single_endpoint() -> Result<(), Error> {
+        let context = Context::new();
+        let mut socket = Socket::try_from(context.socket(DEALER)?)?;
+        socket.set_linger(0)?;
+
+        let a = format!("inproc://{}", std::module_path!());
+        let mut failover = Failover::connect(&context, &mut socket, vec![a.clone()], 0)?;
+        for num_retries in 1..=3 {
+            assert_eq!(
+                failover.handle(&mut socket, e(SocketEvent::CONNECT_RETRIED, &a))?,
+                Some(Event::Retried {
+                    endpoint: a.clone(),
+                    num_retries,
+                }),
+            );
+        }
+
+        assert!(Failover::connect(&context, &mut socket, Vec::new(), 0).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn decode() {
+        assert_eq!(
+            MonitorEvent::decode(&m(SocketEvent::CONNECTED, 42, "tcp://127.0.0.1:8000")),
+            Some(MonitorEvent {
+                event: SocketEvent::CONNECTED,
+                value: 42,
+                endpoint: "tcp://127.0.0.1:8000".to_string(),
+            }),
+        );
+
+        assert_eq!(MonitorEvent::decode(&[]), None);
+        assert_eq!(
+            MonitorEvent::decode(&[b"\x01\x00".as_slice().into(), "x".into()]),
+            None,
+        );
+    }
+}
@@ -2,6 +2,9 @@
 pub mod client;
 pub mod duplex;
 pub mod envelope;
+pub mod failover;
+#[cfg(feature = "rpc")]
+pub mod rpc;
 
 use std::io::Error;
 use std::os::fd::{AsRawFd, RawFd};
@@ -0,0 +1,289 @@
+//! Building blocks of the request-response pattern over `DEALER` and `ROUTER` sockets.
+//!
+//! A client tags each request with a random routing id, which doubles as the correlation id, and
+//! a server echoes the routing id back in the response envelope.  Requests and responses consist
+//! of exactly one data frame, which a protocol encodes and decodes through its `Codec` (e.g., with
+//! Cap'n Proto or serde), as the framing here is agnostic to the payload.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use tokio::sync::oneshot;
+
+use g1_tokio::time::queue::naive::FixedDelayQueue;
+
+use crate::envelope::{Envelope, Frame};
+use crate::Multipart;
+
+pub type RoutingId = u64;
+
+#[derive(Debug)]
+pub enum DecodeError {
+    ExpectOneDataFrame { envelope: Envelope },
+    InvalidFrameSequence { frames: Multipart },
+}
+
+/// Converts between the requests and responses of a protocol and their data frames.
+pub trait Codec {
+    type Request;
+    type Response;
+    type Error;
+
+    fn encode_request(request: Self::Request) -> Frame;
+
+    fn decode_response(response: Frame) -> Result<Self::Response, Self::Error>;
+}
+
+#[derive(Debug)]
+pub enum ResponseError<E> {
+    Envelope(DecodeError),
+    InvalidRoutingId { envelope: Envelope<Frame> },
+    Decode { routing_id: RoutingId, source: E },
+}
+
+/// Tracks in-flight requests and matches responses to them.
+///
+/// A caller cancels a request by dropping the receiving end of its response channel.  We do not
+/// scan for cancelled requests; their entries are reclaimed when either the response arrives or
+/// the request expires, whichever comes first.
+#[derive(Debug)]
+pub struct ResponseSends<T> {
+    map: HashMap<RoutingId, oneshot::Sender<T>>,
+    deadlines: FixedDelayQueue<RoutingId>,
+}
+
+impl<T> ResponseSends<T> {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            map: HashMap::new(),
+            deadlines: FixedDelayQueue::new(timeout),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    fn next_routing_id(&self) -> RoutingId {
+        for _ in 0..4 {
+            let routing_id = rand::random();
+            // It is a small detail, but we do not generate 0.
+            if routing_id != 0 && !self.map.contains_key(&routing_id) {
+                return routing_id;
+            }
+        }
+        std::panic!("cannot generate random routing id")
+    }
+
+    pub fn insert(&mut self, response_send: oneshot::Sender<T>) -> RoutingId {
+        let routing_id = self.next_routing_id();
+        assert!(self.map.insert(routing_id, response_send).is_none());
+        self.deadlines.push(routing_id);
+        routing_id
+    }
+
+    pub fn contains(&self, routing_id: RoutingId) -> bool {
+        self.map.contains_key(&routing_id)
+    }
+
+    pub fn remove(&mut self, routing_id: RoutingId) -> Option<oneshot::Sender<T>> {
+        self.map.remove(&routing_id)
+    }
+
+    /// Waits for the next request to expire and returns it.
+    ///
+    /// The caller may have cancelled the request already, in which case `response_send` is closed.
+    /// It returns `None` when there are no requests, and it is cancel safe.
+    pub async fn expire(&mut self) -> Option<(RoutingId, oneshot::Sender<T>)> {
+        loop {
+            let routing_id = self.deadlines.pop().await?;
+            if let Some(response_send) = self.map.remove(&routing_id) {
+                return Some((routing_id, response_send));
+            }
+        }
+    }
+}
+
+pub fn encode<T>(routing_id: RoutingId, data: T) -> Envelope<T> {
+    Envelope::new(vec![Frame::from(routing_id.to_be_bytes().as_slice())], data)
+}
+
+pub fn encode_request<C>(routing_id: RoutingId, request: C::Request) -> Multipart
+where
+    C: Codec,
+{
+    encode(routing_id, C::encode_request(request)).into()
+}
+
+pub fn decode_routing_id(routing_id: &[Frame]) -> Option<RoutingId> {
+    match routing_id {
+        [routing_id] => Some(RoutingId::from_be_bytes((**routing_id).try_into().ok()?)),
+        _ => None,
+    }
+}
+
+/// Decodes a request or response envelope.
+pub fn decode(frames: Multipart) -> Result<Envelope<Frame>, DecodeError> {
+    Envelope::try_from(frames).map_err(|frames| match Envelope::try_from(frames) {
+        Ok(envelope) => DecodeError::ExpectOneDataFrame { envelope },
+        Err(frames) => DecodeError::InvalidFrameSequence { frames },
+    })
+}
+
+/// Decodes a response envelope and returns the routing id and the response.
+pub fn decode_response<C>(
+    frames: Multipart,
+) -> Result<(RoutingId, C::Response), ResponseError<C::Error>>
+where
+    C: Codec,
+{
+    let envelope = decode(frames).map_err(ResponseError::Envelope)?;
+    let Some(routing_id) = decode_routing_id(envelope.routing_id()) else {
+        return Err(ResponseError::InvalidRoutingId { envelope });
+    };
+    let (_, response) = envelope.unwrap();
+    C::decode_response(response)
+        .map(|response| (routing_id, response))
+        .map_err(|source| ResponseError::Decode { routing_id, source })
+}
+
+/// Copies the routing id of a request envelope, which a server needs when it sends more than one
+/// response (e.g., a streaming response or a timeout error) to a request.
+pub fn copy_envelope(envelope: &Envelope<()>) -> Envelope<()> {
+    Envelope::new(
+        envelope
+            .routing_id()
+            .iter()
+            .map(|frame| Frame::from(&**frame))
+            .collect(),
+        (),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routing_id() {
+        for routing_id in [1, 0x0102030405060708, RoutingId::MAX] {
+            let envelope = encode(routing_id, ());
+            assert_eq!(decode_routing_id(envelope.routing_id()), Some(routing_id));
+        }
+
+        assert_eq!(decode_routing_id(&[]), None);
+        assert_eq!(decode_routing_id(&[Frame::from(b"x".as_slice())]), None);
+        assert_eq!(
+            decode_routing_id(&[Frame::from([0u8; 8].as_slice()), Frame::new()]),
+            None,
+        );
+    }
+
+    #[test]
+    fn test_decode() {
+        let frames = |frames: &[&[u8]]| -> Multipart {
+            frames.iter().map(|frame| Frame::from(*frame)).collect()
+        };
+
+        let envelope = decode(frames(&[b"x", b"", b"y"])).unwrap();
+        assert_eq!(envelope.routing_id(), frames(&[b"x"]));
+        assert_eq!(**envelope.data(), *b"y");
+
+        let copy = copy_envelope(&envelope.map(|_| ()));
+        assert_eq!(copy.routing_id(), frames(&[b"x"]));
+
+        assert!(matches!(
+            decode(frames(&[b"x", b"", b"y", b"z"])),
+            Err(DecodeError::ExpectOneDataFrame { .. }),
+        ));
+        assert!(matches!(
+            decode(frames(&[b"x", b"y"])),
+            Err(DecodeError::InvalidFrameSequence { .. }),
+        ));
+    }
+
+    struct TestCodec;
+
+    impl Codec for TestCodec {
+        type Request = u8;
+        type Response = u8;
+        type Error = usize;
+
+        fn encode_request(request: Self::Request) -> Frame {
+            Frame::from([request].as_slice())
+        }
+
+        fn decode_response(response: Frame) -> Result<Self::Response, Self::Error> {
+            match *response {
+                [response] => Ok(response),
+                _ => Err(response.len()),
+            }
+        }
+    }
+
+    #[test]
+    fn codec() {
+        let frames = |frames: &[&[u8]]| -> Multipart {
+            frames.iter().map(|frame| Frame::from(*frame)).collect()
+        };
+
+        let request = encode_request::<TestCodec>(42, 7);
+        assert!(matches!(decode_response::<TestCodec>(request), Ok((42, 7)),));
+
+        assert!(matches!(
+            decode_response::<TestCodec>(frames(&[b"x", b"y"])),
+            Err(ResponseError::Envelope(
+                DecodeError::InvalidFrameSequence { .. }
+            )),
+        ));
+        assert!(matches!(
+            decode_response::<TestCodec>(frames(&[b"x", b"", b"y"])),
+            Err(ResponseError::InvalidRoutingId { .. }),
+        ));
+        assert!(matches!(
+            decode_response::<TestCodec>(frames(&[&42u64.to_be_bytes(), b"", b"yz"])),
+            Err(ResponseError::Decode {
+                routing_id: 42,
+                source: 2,
+            }),
+        ));
+    }
+
+    #[tokio::test]
+    async fn response_sends() {
+        let mut response_sends = ResponseSends::new(Duration::from_millis(10));
+        assert_eq!(response_sends.expire().await.is_none(), true);
+
+        let (send_1, recv_1) = oneshot::channel::<u8>();
+        let (send_2, recv_2) = oneshot::channel::<u8>();
+        let (send_3, recv_3) = oneshot::channel::<u8>();
+        let id_1 = response_sends.insert(send_1);
+        let id_2 = response_sends.insert(send_2);
+        let id_3 = response_sends.insert(send_3);
+        assert_eq!(response_sends.len(), 3);
+        assert_eq!(response_sends.contains(id_1), true);
+
+        response_sends.remove(id_1).unwrap().send(1).unwrap();
+        assert_eq!(recv_1.await, Ok(1));
+        assert_eq!(response_sends.remove(id_1).is_none(), true);
+        assert_eq!(response_sends.contains(id_1), false);
+
+        drop(recv_2);
+        assert_eq!(response_sends.len(), 2);
+        let (routing_id, send_2) = response_sends.expire().await.unwrap();
+        assert_eq!(routing_id, id_2);
+        assert_eq!(send_2.is_closed(), true);
+        assert_eq!(response_sends.len(), 1);
+
+        let (routing_id, send_3) = response_sends.expire().await.unwrap();
+        assert_eq!(routing_id, id_3);
+        send_3.send(3).unwrap();
+        assert_eq!(recv_3.await, Ok(3));
+        assert_eq!(response_sends.is_empty(), true);
+        assert_eq!(response_sends.expire().await.is_none(), true);
+    }
+}